@@ -0,0 +1,387 @@
+//! Caching of cgroup statistics for high frequency pollers
+//!
+//! Container runtime interfaces poll the stats of every container every
+//! second, and each poll opens, reads and closes about 20 cgroup files. The
+//! [`CachedManager`] wraps any [`CgroupManager`] and serves the cgroup files
+//! read while collecting stats from a cache. The contents of a file are reused
+//! for the time to live of its stat class, which is the controller prefix of
+//! the file name, e.g. `memory` for `memory.current`. Files stay open between
+//! polls and are read again with `pread`. Applying resources or removing the
+//! cgroup invalidates the cache, so a poll never returns outdated limits.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use nix::unistd::Pid;
+
+use crate::common::{CgroupManager, ControllerOpt, FreezerState, WrappedIoError};
+use crate::stats::Stats;
+
+/// Time to live of the stat classes without an explicit one
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1);
+/// Time to live of the memory stats, which change all the time
+pub const MEMORY_TTL: Duration = Duration::from_secs(1);
+/// Time to live of the hugetlb stats, which rarely change
+pub const HUGETLB_TTL: Duration = Duration::from_secs(10);
+
+/// Number of cgroup file reads served from the cache and from the file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct CachedFile {
+    file: File,
+    contents: String,
+    read_at: Instant,
+}
+
+struct FileCache {
+    files: HashMap<PathBuf, CachedFile>,
+    ttls: HashMap<String, Duration>,
+    default_ttl: Duration,
+    metrics: CacheMetrics,
+}
+
+impl Default for FileCache {
+    fn default() -> Self {
+        Self {
+            files: HashMap::new(),
+            ttls: HashMap::from([
+                ("memory".to_owned(), MEMORY_TTL),
+                ("hugetlb".to_owned(), HUGETLB_TTL),
+            ]),
+            default_ttl: DEFAULT_TTL,
+            metrics: CacheMetrics::default(),
+        }
+    }
+}
+
+impl FileCache {
+    fn ttl(&self, path: &Path) -> Duration {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split('.').next())
+            .and_then(|class| self.ttls.get(class))
+            .copied()
+            .unwrap_or(self.default_ttl)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<String, WrappedIoError> {
+        let ttl = self.ttl(path);
+        if let Some(cached) = self.files.get(path) {
+            if cached.read_at.elapsed() < ttl {
+                self.metrics.hits += 1;
+                return Ok(cached.contents.clone());
+            }
+        }
+
+        self.metrics.misses += 1;
+        let file = match self.files.remove(path) {
+            Some(cached) => cached.file,
+            None => File::open(path).map_err(|err| WrappedIoError::Open {
+                err,
+                path: path.to_path_buf(),
+            })?,
+        };
+        // A file that fails to read is dropped, the next read opens it again.
+        let contents = pread_to_string(&file).map_err(|err| WrappedIoError::Read {
+            err,
+            path: path.to_path_buf(),
+        })?;
+        self.files.insert(
+            path.to_path_buf(),
+            CachedFile {
+                file,
+                contents: contents.clone(),
+                read_at: Instant::now(),
+            },
+        );
+
+        Ok(contents)
+    }
+}
+
+/// Reads the whole file from the start, regardless of the file offset.
+fn pread_to_string(file: &File) -> std::io::Result<String> {
+    let mut contents = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match file.read_at(&mut buf, contents.len() as u64) {
+            Ok(0) => break,
+            Ok(n) => contents.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    String::from_utf8(contents)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+thread_local! {
+    // The cache of the manager collecting stats on this thread. It is only
+    // set for the duration of `CachedManager::stats`.
+    static ACTIVE_CACHE: RefCell<Option<FileCache>> = RefCell::new(None);
+}
+
+/// Reads a cgroup file through the cache of the manager collecting stats on
+/// this thread. Returns None if no stats are being collected.
+pub(crate) fn read_cached(path: &Path) -> Option<Result<String, WrappedIoError>> {
+    ACTIVE_CACHE.with(|active| active.borrow_mut().as_mut().map(|cache| cache.read(path)))
+}
+
+/// Cgroup manager caching the stats of the wrapped manager
+pub struct CachedManager<M: CgroupManager> {
+    inner: M,
+    cache: Mutex<FileCache>,
+}
+
+impl<M: CgroupManager> CachedManager<M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(FileCache::default()),
+        }
+    }
+
+    /// Sets the time to live of a stat class, e.g. `memory` or `hugetlb`.
+    /// A zero time to live disables the caching of the contents, but the files
+    /// are still kept open.
+    pub fn with_ttl(self, class: &str, ttl: Duration) -> Self {
+        self.lock().ttls.insert(class.to_owned(), ttl);
+        self
+    }
+
+    /// Sets the time to live of the stat classes without an explicit one.
+    pub fn with_default_ttl(self, ttl: Duration) -> Self {
+        self.lock().default_ttl = ttl;
+        self
+    }
+
+    /// Returns the number of cache hits and misses so far.
+    pub fn metrics(&self) -> CacheMetrics {
+        self.lock().metrics
+    }
+
+    /// Drops all cached contents and closes the cached files.
+    pub fn invalidate(&self) {
+        self.lock().files.clear();
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FileCache> {
+        // The cache is consistent at any point, a panic while holding the
+        // lock can't leave it broken.
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<M: CgroupManager> CgroupManager for CachedManager<M> {
+    type Error = M::Error;
+
+    fn add_task(&self, pid: Pid) -> Result<(), Self::Error> {
+        self.inner.add_task(pid)
+    }
+
+    fn apply(&self, controller_opt: &ControllerOpt) -> Result<(), Self::Error> {
+        // Even a failed apply may have changed some of the files.
+        let result = self.inner.apply(controller_opt);
+        self.invalidate();
+        result
+    }
+
+    fn remove(&self) -> Result<(), Self::Error> {
+        let result = self.inner.remove();
+        self.invalidate();
+        result
+    }
+
+    fn freeze(&self, state: FreezerState) -> Result<(), Self::Error> {
+        self.inner.freeze(state)
+    }
+
+    fn stats(&self) -> Result<Stats, Self::Error> {
+        // The lock is held while collecting, so concurrent polls of the same
+        // manager are serialized and share the cache.
+        let mut cache = self.lock();
+        let previous =
+            ACTIVE_CACHE.with(|active| active.borrow_mut().replace(std::mem::take(&mut *cache)));
+        let result = self.inner.stats();
+        ACTIVE_CACHE.with(|active| {
+            let mut active = active.borrow_mut();
+            if let Some(used) = active.take() {
+                *cache = used;
+            }
+            *active = previous;
+        });
+
+        result
+    }
+
+    fn get_all_pids(&self) -> Result<Vec<Pid>, Self::Error> {
+        self.inner.get_all_pids()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResources, LinuxResourcesBuilder};
+
+    use super::*;
+    use crate::stats::parse_single_value;
+    use crate::test::set_fixture;
+
+    /// Manager keeping the memory limit and the hugetlb usage in plain files
+    struct FileManager {
+        dir: PathBuf,
+    }
+
+    impl CgroupManager for FileManager {
+        type Error = WrappedIoError;
+
+        fn add_task(&self, _pid: Pid) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn apply(&self, controller_opt: &ControllerOpt) -> Result<(), Self::Error> {
+            if let Some(limit) = controller_opt
+                .resources
+                .memory()
+                .as_ref()
+                .and_then(|memory| memory.limit())
+            {
+                crate::common::write_cgroup_file(self.dir.join("memory.max"), limit)?;
+            }
+            Ok(())
+        }
+
+        fn remove(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn freeze(&self, _state: FreezerState) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn stats(&self) -> Result<Stats, Self::Error> {
+            let mut stats = Stats::default();
+            stats.memory.memory.limit = parse_single_value(&self.dir.join("memory.max"))?;
+            let usage = parse_single_value(&self.dir.join("hugetlb.2MB.current"))?;
+            stats.hugetlb.insert(
+                "2MB".to_owned(),
+                crate::stats::HugeTlbStats {
+                    usage,
+                    ..Default::default()
+                },
+            );
+            Ok(stats)
+        }
+
+        fn get_all_pids(&self) -> Result<Vec<Pid>, Self::Error> {
+            Ok(vec![])
+        }
+    }
+
+    fn setup() -> Result<(tempfile::TempDir, CachedManager<FileManager>)> {
+        let tmp = tempfile::tempdir()?;
+        set_fixture(tmp.path(), "memory.max", "1024\n")?;
+        set_fixture(tmp.path(), "hugetlb.2MB.current", "1\n")?;
+        let manager = CachedManager::new(FileManager {
+            dir: tmp.path().to_path_buf(),
+        })
+        .with_ttl("memory", Duration::from_secs(3600))
+        .with_ttl("hugetlb", Duration::from_secs(3600));
+
+        Ok((tmp, manager))
+    }
+
+    fn resources(limit: i64) -> Result<LinuxResources> {
+        Ok(LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(limit).build()?)
+            .build()?)
+    }
+
+    #[test]
+    fn test_stats_are_cached() -> Result<()> {
+        let (tmp, manager) = setup()?;
+
+        assert_eq!(manager.stats()?.memory.memory.limit, 1024);
+        assert_eq!(manager.metrics(), CacheMetrics { hits: 0, misses: 2 });
+
+        // A change behind the back of the manager is only seen once the
+        // cached contents expire.
+        set_fixture(tmp.path(), "hugetlb.2MB.current", "2\n")?;
+        assert_eq!(manager.stats()?.hugetlb["2MB"].usage, 1);
+        assert_eq!(manager.metrics(), CacheMetrics { hits: 2, misses: 2 });
+
+        manager.invalidate();
+        assert_eq!(manager.stats()?.hugetlb["2MB"].usage, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_poll_after_apply_returns_new_limits() -> Result<()> {
+        let (_tmp, manager) = setup()?;
+        assert_eq!(manager.stats()?.memory.memory.limit, 1024);
+
+        for limit in [2048, 4096, 1024] {
+            let resources = resources(limit)?;
+            manager.apply(&ControllerOpt {
+                resources: &resources,
+                disable_oom_killer: false,
+                oom_score_adj: None,
+                freezer_state: None,
+                cpuset_partition: None,
+            })?;
+            assert_eq!(manager.stats()?.memory.memory.limit, limit as u64);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_contents_are_read_again() -> Result<()> {
+        let (tmp, manager) = setup()?;
+        let manager = manager.with_ttl("hugetlb", Duration::ZERO);
+
+        assert_eq!(manager.stats()?.hugetlb["2MB"].usage, 1);
+        // The file is rewritten in place, so the open file sees the change.
+        fs::write(tmp.path().join("hugetlb.2MB.current"), "3\n")?;
+        assert_eq!(manager.stats()?.hugetlb["2MB"].usage, 3);
+        assert_eq!(manager.metrics(), CacheMetrics { hits: 1, misses: 3 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_outside_stats_are_not_cached() -> Result<()> {
+        let (tmp, manager) = setup()?;
+        manager.stats()?;
+
+        let path = tmp.path().join("memory.max");
+        fs::write(&path, "8192\n")?;
+        assert_eq!(parse_single_value(&path)?, 8192);
+        assert_eq!(manager.metrics().misses, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl_per_class() {
+        let cache = FileCache::default();
+        assert_eq!(cache.ttl(Path::new("/cg/memory.current")), MEMORY_TTL);
+        assert_eq!(cache.ttl(Path::new("/cg/hugetlb.2MB.current")), HUGETLB_TTL);
+        assert_eq!(cache.ttl(Path::new("/cg/cpu.stat")), DEFAULT_TTL);
+    }
+}
@@ -1,3 +1,4 @@
+use std::convert::Infallible;
 use std::fmt::{Debug, Display};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
@@ -11,9 +12,11 @@ use oci_spec::runtime::LinuxResources;
 use oci_spec::runtime::{
     LinuxDevice, LinuxDeviceBuilder, LinuxDeviceCgroup, LinuxDeviceCgroupBuilder, LinuxDeviceType,
 };
+use serde::{Deserialize, Serialize};
 
 use super::stats::Stats;
-use super::{systemd, v1, v2};
+use super::unmanaged::UnmanagedManager;
+use super::{cached, systemd, v1, v2};
 
 pub const CGROUP_PROCS: &str = "cgroup.procs";
 pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
@@ -63,11 +66,51 @@ pub enum AnyManagerError {
     V2(#[from] v2::manager::V2ManagerError),
 }
 
+impl From<Infallible> for AnyManagerError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
 // systemd is boxed due to size lint https://rust-lang.github.io/rust-clippy/master/index.html#/large_enum_variant
+#[non_exhaustive]
 pub enum AnyCgroupManager {
     Systemd(Box<systemd::manager::Manager>),
     V1(v1::manager::Manager),
     V2(v2::manager::Manager),
+    Unmanaged(UnmanagedManager),
+}
+
+impl AnyCgroupManager {
+    /// Returns the absolute path of the cgroup. A v1 cgroup has a path in
+    /// each hierarchy, so there is no single one.
+    pub fn cgroup_path(&self) -> Option<&Path> {
+        match self {
+            AnyCgroupManager::Systemd(m) => Some(m.full_path()),
+            AnyCgroupManager::V1(_) => None,
+            AnyCgroupManager::V2(m) => Some(m.full_path()),
+            AnyCgroupManager::Unmanaged(_) => None,
+        }
+    }
+
+    /// Returns the name of the systemd unit of the cgroup, if systemd
+    /// manages it
+    pub fn systemd_unit(&self) -> Option<&str> {
+        match self {
+            AnyCgroupManager::Systemd(m) => Some(m.unit_name()),
+            _ => None,
+        }
+    }
+
+    /// Splits the cgroup for nested delegation, once the processes of the
+    /// container are in their namespaces. Only a v2 cgroup can be split, the
+    /// other managers are never created for nested delegation.
+    pub fn delegate_nested(&self) -> Result<(), AnyManagerError> {
+        match self {
+            AnyCgroupManager::V2(m) => Ok(m.delegate_nested()?),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl CgroupManager for AnyCgroupManager {
@@ -78,6 +121,7 @@ impl CgroupManager for AnyCgroupManager {
             AnyCgroupManager::Systemd(m) => Ok(m.add_task(pid)?),
             AnyCgroupManager::V1(m) => Ok(m.add_task(pid)?),
             AnyCgroupManager::V2(m) => Ok(m.add_task(pid)?),
+            AnyCgroupManager::Unmanaged(m) => Ok(m.add_task(pid)?),
         }
     }
 
@@ -86,6 +130,7 @@ impl CgroupManager for AnyCgroupManager {
             AnyCgroupManager::Systemd(m) => Ok(m.apply(controller_opt)?),
             AnyCgroupManager::V1(m) => Ok(m.apply(controller_opt)?),
             AnyCgroupManager::V2(m) => Ok(m.apply(controller_opt)?),
+            AnyCgroupManager::Unmanaged(m) => Ok(m.apply(controller_opt)?),
         }
     }
 
@@ -94,6 +139,7 @@ impl CgroupManager for AnyCgroupManager {
             AnyCgroupManager::Systemd(m) => Ok(m.remove()?),
             AnyCgroupManager::V1(m) => Ok(m.remove()?),
             AnyCgroupManager::V2(m) => Ok(m.remove()?),
+            AnyCgroupManager::Unmanaged(m) => Ok(m.remove()?),
         }
     }
 
@@ -102,6 +148,7 @@ impl CgroupManager for AnyCgroupManager {
             AnyCgroupManager::Systemd(m) => Ok(m.freeze(state)?),
             AnyCgroupManager::V1(m) => Ok(m.freeze(state)?),
             AnyCgroupManager::V2(m) => Ok(m.freeze(state)?),
+            AnyCgroupManager::Unmanaged(m) => Ok(m.freeze(state)?),
         }
     }
 
@@ -110,6 +157,7 @@ impl CgroupManager for AnyCgroupManager {
             AnyCgroupManager::Systemd(m) => Ok(m.stats()?),
             AnyCgroupManager::V1(m) => Ok(m.stats()?),
             AnyCgroupManager::V2(m) => Ok(m.stats()?),
+            AnyCgroupManager::Unmanaged(m) => Ok(m.stats()?),
         }
     }
 
@@ -118,15 +166,22 @@ impl CgroupManager for AnyCgroupManager {
             AnyCgroupManager::Systemd(m) => Ok(m.get_all_pids()?),
             AnyCgroupManager::V1(m) => Ok(m.get_all_pids()?),
             AnyCgroupManager::V2(m) => Ok(m.get_all_pids()?),
+            AnyCgroupManager::Unmanaged(m) => Ok(m.get_all_pids()?),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
 pub enum CgroupSetup {
     Hybrid,
     Legacy,
     Unified,
+    /// The cgroups are left to the caller, no cgroup is created, joined or
+    /// removed. Never detected on a host, only requested with
+    /// [`CgroupConfig::with_setup`].
+    Unmanaged,
 }
 
 impl Display for CgroupSetup {
@@ -135,6 +190,7 @@ impl Display for CgroupSetup {
             CgroupSetup::Hybrid => "hybrid",
             CgroupSetup::Legacy => "legacy",
             CgroupSetup::Unified => "unified",
+            CgroupSetup::Unmanaged => "unmanaged",
         };
 
         write!(f, "{print}")
@@ -152,8 +208,109 @@ pub enum FreezerState {
     Thawed,
 }
 
+/// Partition mode of a cgroup v2 cpuset, see the `cpuset.cpus.partition`
+/// section of the cgroup v2 kernel documentation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpusetPartition {
+    /// The cpuset shares the cpus of its parent partition
+    Member,
+    /// The cpus of the cpuset are exclusive to it and its descendants
+    Root,
+    /// Like root, but the cpus are also isolated from the scheduler's load
+    /// balancing
+    Isolated,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid cpuset partition {0:?}, expected one of member, root or isolated")]
+pub struct InvalidCpusetPartition(String);
+
+impl CpusetPartition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Root => "root",
+            Self::Isolated => "isolated",
+        }
+    }
+}
+
+impl Display for CpusetPartition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for CpusetPartition {
+    type Err = InvalidCpusetPartition;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(Self::Member),
+            "root" => Ok(Self::Root),
+            "isolated" => Ok(Self::Isolated),
+            _ => Err(InvalidCpusetPartition(s.to_owned())),
+        }
+    }
+}
+
+/// Swap limit of a cgroup as the `memory.swap.max` of cgroup v2: the swap
+/// alone, unlike the `swap` of the spec, which is the memory plus the swap.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SwapLimit {
+    /// No swap limit of its own, only the limits of the parents apply
+    Max,
+    /// At most this many bytes of swap, zero disables the swap
+    Bytes(u64),
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid swap limit {0:?}, expected max or a number of bytes")]
+pub struct InvalidSwapLimit(String);
+
+impl SwapLimit {
+    /// Returns the `swap` of the spec, memory plus swap, for the memory limit
+    /// `limit` of the spec. A limited swap needs a memory limit, `None` if
+    /// there is none or the sum overflows.
+    pub fn to_spec_swap(&self, limit: Option<i64>) -> Option<i64> {
+        match (self, limit) {
+            (Self::Max, _) => Some(-1),
+            (Self::Bytes(bytes), Some(limit)) if limit >= 0 => {
+                i64::try_from(*bytes).ok()?.checked_add(limit)
+            }
+            (Self::Bytes(_), _) => None,
+        }
+    }
+}
+
+impl Display for SwapLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Max => f.write_str("max"),
+            Self::Bytes(bytes) => write!(f, "{bytes}"),
+        }
+    }
+}
+
+impl std::str::FromStr for SwapLimit {
+    type Err = InvalidSwapLimit;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(Self::Max),
+            _ => s
+                .parse()
+                .map(Self::Bytes)
+                .map_err(|_| InvalidSwapLimit(s.to_owned())),
+        }
+    }
+}
+
 /// ControllerOpt is given all cgroup controller for applying cgroup configuration.
+/// Outside of libcgroups it is created with [`ControllerOpt::new`].
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub struct ControllerOpt<'a> {
     /// Resources contain cgroup information for handling resource constraints for the container.
     pub resources: &'a LinuxResources,
@@ -163,6 +320,46 @@ pub struct ControllerOpt<'a> {
     pub oom_score_adj: Option<i32>,
     /// FreezerState is given to freezer controller for suspending process.
     pub freezer_state: Option<FreezerState>,
+    /// Partition mode of the cpuset, only supported on cgroup v2.
+    pub cpuset_partition: Option<CpusetPartition>,
+}
+
+impl<'a> ControllerOpt<'a> {
+    /// Creates the options applying `resources`, with the OOM killer enabled
+    /// and everything else left as it is
+    pub fn new(resources: &'a LinuxResources) -> Self {
+        Self {
+            resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpuset_partition: None,
+        }
+    }
+
+    /// Disables the OOM killer for out of memory conditions
+    pub fn with_disable_oom_killer(mut self, disable_oom_killer: bool) -> Self {
+        self.disable_oom_killer = disable_oom_killer;
+        self
+    }
+
+    /// Sets the oom_score_adj of the container
+    pub fn with_oom_score_adj(mut self, oom_score_adj: Option<i32>) -> Self {
+        self.oom_score_adj = oom_score_adj;
+        self
+    }
+
+    /// Sets the state the freezer controller puts the processes in
+    pub fn with_freezer_state(mut self, freezer_state: Option<FreezerState>) -> Self {
+        self.freezer_state = freezer_state;
+        self
+    }
+
+    /// Sets the partition mode of the cpuset, only supported on cgroup v2
+    pub fn with_cpuset_partition(mut self, cpuset_partition: Option<CpusetPartition>) -> Self {
+        self.cpuset_partition = cpuset_partition;
+        self
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -248,6 +445,11 @@ pub fn write_cgroup_file<P: AsRef<Path>, T: ToString>(
 #[inline]
 pub fn read_cgroup_file<P: AsRef<Path>>(path: P) -> Result<String, WrappedIoError> {
     let path = path.as_ref();
+    // Stats collected by a CachedManager are served from its cache.
+    if let Some(result) = cached::read_cached(path) {
+        return result;
+    }
+
     fs::read_to_string(path).map_err(|err| WrappedIoError::Read {
         err,
         path: path.to_path_buf(),
@@ -324,13 +526,67 @@ pub enum CreateCgroupSetupError {
     V2(#[from] v2::manager::V2ManagerError),
     #[error("systemd error: {0}")]
     Systemd(#[from] systemd::manager::SystemdManagerError),
+    #[error("named cgroup hierarchies {0:?} require cgroup v1, but the host is cgroup v2 only")]
+    ExtraHierarchiesUnsupported(Vec<String>),
+    #[error("nested cgroup delegation requires cgroup v2 without systemd")]
+    NestedDelegationUnsupported,
 }
 
+/// What the cgroup manager is created for, see [`CgroupConfig::new`]
 #[derive(Clone)]
+#[non_exhaustive]
 pub struct CgroupConfig {
     pub cgroup_path: PathBuf,
     pub systemd_cgroup: bool,
     pub container_name: String,
+    /// Cgroups in named v1 hierarchies the container is attached to besides
+    /// its managed cgroups, given as `name=<name>:<path>`, e.g.
+    /// `name=ops:/teams/web`
+    pub extra_hierarchies: Vec<String>,
+    /// If the processes live in a leaf below the cgroup, so the cgroup can
+    /// delegate its controllers to cgroups created in the container, see
+    /// [`v2::manager::Manager::with_nested_delegation`]
+    pub nested_delegation: bool,
+    /// Setup the cgroups are managed for, detected on the host if not set.
+    /// With [`CgroupSetup::Unmanaged`], the cgroups are not managed at all,
+    /// see [`UnmanagedManager`].
+    pub setup: Option<CgroupSetup>,
+}
+
+impl CgroupConfig {
+    /// Creates the config of the cgroup at `cgroup_path`, without extra
+    /// hierarchies or nested delegation, for the setup of the host
+    pub fn new(cgroup_path: PathBuf, systemd_cgroup: bool, container_name: String) -> Self {
+        Self {
+            cgroup_path,
+            systemd_cgroup,
+            container_name,
+            extra_hierarchies: Vec::new(),
+            nested_delegation: false,
+            setup: None,
+        }
+    }
+
+    /// Sets the cgroups in named v1 hierarchies the container is attached
+    /// to, see [`CgroupConfig::extra_hierarchies`]
+    pub fn with_extra_hierarchies(mut self, extra_hierarchies: Vec<String>) -> Self {
+        self.extra_hierarchies = extra_hierarchies;
+        self
+    }
+
+    /// Sets if the processes live in a leaf below the cgroup, see
+    /// [`CgroupConfig::nested_delegation`]
+    pub fn with_nested_delegation(mut self, nested_delegation: bool) -> Self {
+        self.nested_delegation = nested_delegation;
+        self
+    }
+
+    /// Sets the setup the cgroups are managed for, `None` to detect it on
+    /// the host
+    pub fn with_setup(mut self, setup: Option<CgroupSetup>) -> Self {
+        self.setup = setup;
+        self
+    }
 }
 
 // Create any cgroup manager with customize root path. If root_path provided
@@ -344,21 +600,41 @@ pub fn create_cgroup_manager_with_root(
         None => Path::new(DEFAULT_CGROUP_ROOT),
     };
 
-    let cgroup_setup = get_cgroup_setup_with_root(root).map_err(|err| match err {
-        GetCgroupSetupError::WrappedIo(err) => CreateCgroupSetupError::WrappedIo(err),
-        GetCgroupSetupError::NonDefault => CreateCgroupSetupError::NonDefault,
-        GetCgroupSetupError::FailedToDetect => CreateCgroupSetupError::FailedToDetect,
-    })?;
+    let cgroup_setup = match config.setup {
+        Some(setup) => setup,
+        None => get_cgroup_setup_with_root(root).map_err(|err| match err {
+            GetCgroupSetupError::WrappedIo(err) => CreateCgroupSetupError::WrappedIo(err),
+            GetCgroupSetupError::NonDefault => CreateCgroupSetupError::NonDefault,
+            GetCgroupSetupError::FailedToDetect => CreateCgroupSetupError::FailedToDetect,
+        })?,
+    };
     let cgroup_path = config.cgroup_path.as_path();
 
     match cgroup_setup {
+        CgroupSetup::Unmanaged => {
+            tracing::info!("cgroups are not managed");
+            Ok(UnmanagedManager.any())
+        }
+        CgroupSetup::Legacy | CgroupSetup::Hybrid if config.nested_delegation => {
+            Err(CreateCgroupSetupError::NestedDelegationUnsupported)
+        }
         CgroupSetup::Legacy | CgroupSetup::Hybrid => {
-            Ok(create_v1_cgroup_manager(cgroup_path)?.any())
+            Ok(create_v1_cgroup_manager(cgroup_path, &config.extra_hierarchies)?.any())
         }
         CgroupSetup::Unified => {
+            if !config.extra_hierarchies.is_empty() {
+                return Err(CreateCgroupSetupError::ExtraHierarchiesUnsupported(
+                    config.extra_hierarchies,
+                ));
+            }
             // ref https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#cgroups-path
             if cgroup_path.is_absolute() || !config.systemd_cgroup {
-                return Ok(create_v2_cgroup_manager(root, cgroup_path)?.any());
+                return Ok(create_v2_cgroup_manager(root, cgroup_path)?
+                    .with_nested_delegation(config.nested_delegation)
+                    .any());
+            }
+            if config.nested_delegation {
+                return Err(CreateCgroupSetupError::NestedDelegationUnsupported);
             }
             Ok(
                 create_systemd_cgroup_manager(root, cgroup_path, config.container_name.as_str())?
@@ -377,14 +653,16 @@ pub fn create_cgroup_manager(
 #[cfg(feature = "v1")]
 fn create_v1_cgroup_manager(
     cgroup_path: &Path,
+    extra_hierarchies: &[String],
 ) -> Result<v1::manager::Manager, v1::manager::V1ManagerError> {
     tracing::info!("cgroup manager V1 will be used");
-    v1::manager::Manager::new(cgroup_path)
+    v1::manager::Manager::new(cgroup_path)?.with_named_hierarchies(extra_hierarchies)
 }
 
 #[cfg(not(feature = "v1"))]
 fn create_v1_cgroup_manager(
     _cgroup_path: &Path,
+    _extra_hierarchies: &[String],
 ) -> Result<v1::manager::Manager, v1::manager::V1ManagerError> {
     Err(v1::manager::V1ManagerError::NotEnabled)
 }
@@ -12,14 +12,17 @@ extern crate mockall;
 
 mod test;
 
+pub mod cached;
 pub mod common;
 pub mod stats;
+pub mod stats_stream;
 #[cfg(feature = "systemd")]
 pub mod systemd;
 #[cfg(not(feature = "systemd"))]
 #[path = "stub/systemd/mod.rs"]
 pub mod systemd;
 pub mod test_manager;
+pub mod unmanaged;
 #[cfg(feature = "v1")]
 pub mod v1;
 #[cfg(not(feature = "v1"))]
@@ -18,6 +18,7 @@ pub(crate) trait StatsProvider {
 
 /// Reports the statistics for a cgroup
 #[derive(Debug, Serialize, Default)]
+#[non_exhaustive]
 pub struct Stats {
     /// Cpu statistics for the cgroup
     pub cpu: CpuStats,
@@ -29,6 +30,10 @@ pub struct Stats {
     pub blkio: BlkioStats,
     /// Memory statistics for the cgroup
     pub memory: MemoryStats,
+    /// Cpus and memory nodes of the cgroup
+    pub cpuset: CpusetStats,
+    /// Set if the cgroups are not managed, the stats are then all empty
+    pub unmanaged: bool,
 }
 
 /// Reports the cpu statistics for a cgroup
@@ -104,6 +109,15 @@ pub struct MemoryData {
     pub limit: u64,
 }
 
+/// Reports the cpus and memory nodes the tasks of a cgroup may use
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CpusetStats {
+    /// Effective cpus of the cgroup, as a cpu list, e.g. `0-3,8`
+    pub cpus: String,
+    /// Effective memory nodes of the cgroup, as a node list
+    pub mems: String,
+}
+
 /// Reports pid stats for a cgroup
 #[derive(Debug, Default, PartialEq, Eq, Serialize)]
 pub struct PidStats {
@@ -423,6 +437,24 @@ pub fn pid_stats(cgroup_path: &Path) -> Result<PidStats, PidStatsError> {
     Ok(stats)
 }
 
+/// Returns the cpus and memory nodes of a cpuset. A cgroup without the
+/// cpuset controller has empty lists.
+pub fn cpuset_stats(cpus_file: &Path, mems_file: &Path) -> Result<CpusetStats, WrappedIoError> {
+    let read = |path: &Path| match fs::read_to_string(path) {
+        Ok(content) => Ok(content.trim().to_owned()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(WrappedIoError::Read {
+            err,
+            path: path.to_path_buf(),
+        }),
+    };
+
+    Ok(CpusetStats {
+        cpus: read(cpus_file)?,
+        mems: read(mems_file)?,
+    })
+}
+
 pub fn psi_stats(psi_file: &Path) -> Result<PSIStats, WrappedIoError> {
     let mut stats = PSIStats::default();
 
@@ -0,0 +1,147 @@
+//! Periodic samples of the statistics of a cgroup, for monitoring agents
+//! that want to pull the stats of a container during its lifetime.
+use std::thread;
+use std::time::Duration;
+
+use crate::common::CgroupManager;
+use crate::stats::Stats;
+
+/// Iterator over samples of the stats of a cgroup, taken every `interval`.
+/// The first sample is taken right away. The stream ends once the cgroup has
+/// no processes left or is gone, i.e. the container exited, and after the
+/// first sample that fails.
+pub struct StatsStream<M> {
+    manager: M,
+    interval: Duration,
+    started: bool,
+    ended: bool,
+}
+
+impl<M: CgroupManager> StatsStream<M> {
+    pub fn new(manager: M, interval: Duration) -> Self {
+        Self {
+            manager,
+            interval,
+            started: false,
+            ended: false,
+        }
+    }
+
+    /// The cgroup still has processes. A cgroup that was removed has none.
+    fn is_alive(&self) -> bool {
+        self.manager
+            .get_all_pids()
+            .map_or(false, |pids| !pids.is_empty())
+    }
+}
+
+impl<M: CgroupManager> Iterator for StatsStream<M> {
+    type Item = Result<Stats, M::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        if self.started {
+            thread::sleep(self.interval);
+        }
+        self.started = true;
+
+        if !self.is_alive() {
+            tracing::debug!("cgroup has no processes left, ending the stats stream");
+            self.ended = true;
+            return None;
+        }
+        let stats = self.manager.stats();
+        if stats.is_err() {
+            self.ended = true;
+        }
+        Some(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use nix::unistd::Pid;
+
+    use super::*;
+    use crate::common::{ControllerOpt, FreezerState};
+
+    /// Manager of a cgroup whose processes exit after `lifetime` samples
+    struct ExitingManager {
+        lifetime: usize,
+        samples: Cell<usize>,
+        fail_at: Option<usize>,
+    }
+
+    impl ExitingManager {
+        fn new(lifetime: usize) -> Self {
+            Self {
+                lifetime,
+                samples: Cell::new(0),
+                fail_at: None,
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct StatsFailed;
+
+    impl CgroupManager for ExitingManager {
+        type Error = StatsFailed;
+
+        fn add_task(&self, _pid: Pid) -> Result<(), StatsFailed> {
+            unimplemented!()
+        }
+
+        fn apply(&self, _controller_opt: &ControllerOpt) -> Result<(), StatsFailed> {
+            unimplemented!()
+        }
+
+        fn remove(&self) -> Result<(), StatsFailed> {
+            unimplemented!()
+        }
+
+        fn freeze(&self, _state: FreezerState) -> Result<(), StatsFailed> {
+            unimplemented!()
+        }
+
+        fn stats(&self) -> Result<Stats, StatsFailed> {
+            let sample = self.samples.get();
+            self.samples.set(sample + 1);
+            if self.fail_at == Some(sample) {
+                return Err(StatsFailed);
+            }
+            let mut stats = Stats::default();
+            stats.pids.current = sample as u64;
+            Ok(stats)
+        }
+
+        fn get_all_pids(&self) -> Result<Vec<Pid>, StatsFailed> {
+            if self.samples.get() < self.lifetime {
+                Ok(vec![Pid::from_raw(42)])
+            } else {
+                Ok(vec![])
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_ends_when_the_cgroup_is_empty() {
+        let stream = StatsStream::new(ExitingManager::new(3), Duration::from_millis(1));
+        let samples: Vec<u64> = stream.map(|stats| stats.unwrap().pids.current).collect();
+        assert_eq!(samples, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_stream_ends_after_a_failed_sample() {
+        let mut manager = ExitingManager::new(10);
+        manager.fail_at = Some(1);
+        let mut stream = StatsStream::new(manager, Duration::from_millis(1));
+        assert!(stream.next().unwrap().is_ok());
+        assert_eq!(stream.next().unwrap().unwrap_err(), StatsFailed);
+        assert!(stream.next().is_none());
+    }
+}
@@ -24,6 +24,7 @@ use crate::common::{
 use crate::stats::Stats;
 use crate::systemd::dbus_native::serialize::Variant;
 use crate::systemd::unified::Unified;
+use crate::v2::cpuset::CpuSet as FsCpuSet;
 use crate::v2::manager::{Manager as FsManager, V2ManagerError};
 
 const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
@@ -206,7 +207,11 @@ impl Manager {
     fn get_unit_name(cgroups_path: &CgroupsPath) -> String {
         // By default we create a scope unless specified explicitly.
         if !cgroups_path.name.ends_with(".slice") {
-            return format!("{}-{}.scope", cgroups_path.prefix, cgroups_path.name);
+            return format!(
+                "{}-{}.scope",
+                cgroups_path.prefix,
+                super::sanitize_for_systemd(&cgroups_path.name)
+            );
         }
         cgroups_path.name.clone()
     }
@@ -338,6 +343,16 @@ impl Manager {
         Ok(())
     }
 
+    /// Returns the absolute path of the cgroup of the unit
+    pub fn full_path(&self) -> &Path {
+        &self.full_path
+    }
+
+    /// Returns the name of the unit, e.g. youki-569d5ce3afe1074769f67.scope
+    pub fn unit_name(&self) -> &str {
+        &self.unit_name
+    }
+
     pub fn any(self) -> AnyCgroupManager {
         AnyCgroupManager::Systemd(Box::new(self))
     }
@@ -404,6 +419,12 @@ impl CgroupManager for Manager {
                 .set_unit_properties(&self.unit_name, &properties)?;
         }
 
+        // systemd has no property for the cpuset partition, so it is written
+        // directly to the cgroup of the unit once the cpus are set.
+        if let Some(partition) = controller_opt.cpuset_partition {
+            FsCpuSet::apply_partition(&self.full_path, partition)?;
+        }
+
         Ok(())
     }
 
@@ -542,6 +563,24 @@ mod tests {
 
         Ok(())
     }
+    #[test]
+    fn get_unit_name_escapes_the_name() -> Result<()> {
+        let cgroups_path = Path::new("machine.slice:libpod:web@1")
+            .try_into()
+            .context("construct path")?;
+
+        assert_eq!(
+            Manager::get_unit_name(&cgroups_path),
+            "libpod-web\\x401.scope"
+        );
+        assert_eq!(
+            Manager::construct_cgroups_path(&cgroups_path, &TestSystemdClient {})?.0,
+            PathBuf::from("/machine.slice/libpod-web\\x401.scope"),
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_task_addition() {
         let manager = Manager::new(
@@ -17,6 +17,49 @@ pub fn booted() -> bool {
         .unwrap_or_default()
 }
 
+/// Maximum length of a sanitized id. Unit names are limited to 255
+/// characters by systemd, this leaves room for the prefix and the unit type
+/// suffix around the id.
+const MAX_SANITIZED_ID_LEN: usize = 200;
+
+/// Makes a container id usable as a component of a systemd unit name.
+///
+/// Characters systemd doesn't accept in unit names and a leading `.` are
+/// escaped as `\xNN`, like `systemd-escape` does. Valid container ids don't
+/// contain a backslash, so different ids result in different names. Ids
+/// that are too long after escaping are truncated and suffixed with a hash of
+/// the full id, only these use the maximum length.
+pub fn sanitize_for_systemd(id: &str) -> String {
+    let mut sanitized = String::with_capacity(id.len());
+    for (i, byte) in id.bytes().enumerate() {
+        match byte {
+            b'.' if i == 0 => sanitized.push_str("\\x2e"),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' | b'.' | b'-' | b'\\' => {
+                sanitized.push(byte as char)
+            }
+            _ => sanitized.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+
+    if sanitized.len() >= MAX_SANITIZED_ID_LEN {
+        let hash = format!("{:016x}", fnv1a(id.as_bytes()));
+        sanitized.truncate(MAX_SANITIZED_ID_LEN - hash.len() - 1);
+        sanitized.push('-');
+        sanitized.push_str(&hash);
+    }
+
+    sanitized
+}
+
+// The unit name of a container has to be the same for every youki version
+// that may manage it, so a hash with a fixed definition is used instead of the
+// std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
 #[macro_export]
 macro_rules! recast {
     ($v:ident, $t:ty) => {{
@@ -27,3 +70,67 @@ macro_rules! recast {
         ret
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn is_valid_unit_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '.' | '-' | '\\')
+    }
+
+    #[test]
+    fn test_sanitize_for_systemd() {
+        assert_eq!(sanitize_for_systemd("74f1a4cb3801"), "74f1a4cb3801");
+        assert_eq!(sanitize_for_systemd("a.b_c-d"), "a.b_c-d");
+        assert_eq!(sanitize_for_systemd(".hidden"), "\\x2ehidden");
+        assert_eq!(sanitize_for_systemd("a/b"), "a\\x2fb");
+        assert_eq!(sanitize_for_systemd("a+b"), "a\\x2bb");
+        assert_eq!(sanitize_for_systemd("new\nline"), "new\\x0aline");
+        assert_eq!(sanitize_for_systemd("ü"), "\\xc3\\xbc");
+    }
+
+    #[test]
+    fn test_sanitize_for_systemd_long_ids() {
+        let long = "a".repeat(300);
+        let sanitized = sanitize_for_systemd(&long);
+        assert_eq!(sanitized.len(), MAX_SANITIZED_ID_LEN);
+        assert_eq!(sanitized, sanitize_for_systemd(&long));
+        assert_ne!(sanitized, sanitize_for_systemd(&"a".repeat(301)));
+    }
+
+    #[test]
+    fn test_sanitize_for_systemd_pathological_ids() {
+        // Deterministic xorshift, so failures can be reproduced
+        let mut state: u64 = 0x9e3779b97f4a7c15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let alphabet: Vec<char> = "aZ09._-:/\\\n\t \0+@%*?ü\u{202e}".chars().collect();
+
+        let mut seen = HashMap::new();
+        for _ in 0..20_000 {
+            let len = (next() % 260) as usize;
+            let id: String = (0..len)
+                .map(|_| alphabet[(next() % alphabet.len() as u64) as usize])
+                .collect();
+            let sanitized = sanitize_for_systemd(&id);
+
+            assert!(sanitized.len() <= MAX_SANITIZED_ID_LEN, "{id:?}");
+            assert!(sanitized.chars().all(is_valid_unit_char), "{id:?}");
+            assert!(!sanitized.starts_with('.'), "{id:?}");
+            // Only an id with a backslash can look like an escape.
+            if id.contains('\\') {
+                continue;
+            }
+            if let Some(other) = seen.insert(sanitized.clone(), id.clone()) {
+                assert_eq!(other, id, "{sanitized:?} is not unique");
+            }
+        }
+    }
+}
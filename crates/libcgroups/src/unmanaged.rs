@@ -0,0 +1,104 @@
+//! Manager for cgroups left to the caller
+//!
+//! Embedders managing the cgroups of their containers themselves create the
+//! manager with [`CgroupSetup::Unmanaged`](crate::common::CgroupSetup::Unmanaged). It
+//! creates, joins and removes no cgroup, every operation succeeds without
+//! touching the cgroup filesystem, and the processes of the container stay in
+//! the cgroup they were started in.
+use std::convert::Infallible;
+
+use nix::unistd::Pid;
+
+use crate::common::{AnyCgroupManager, CgroupManager, ControllerOpt, FreezerState};
+use crate::stats::Stats;
+
+#[derive(Debug, Default)]
+pub struct UnmanagedManager;
+
+impl UnmanagedManager {
+    pub fn any(self) -> AnyCgroupManager {
+        AnyCgroupManager::Unmanaged(self)
+    }
+}
+
+impl CgroupManager for UnmanagedManager {
+    type Error = Infallible;
+
+    fn add_task(&self, _pid: Pid) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn apply(&self, _controller_opt: &ControllerOpt) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn freeze(&self, _state: FreezerState) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    /// Returns empty stats, flagged as unmanaged
+    fn stats(&self) -> Result<Stats, Infallible> {
+        Ok(Stats {
+            unmanaged: true,
+            ..Default::default()
+        })
+    }
+
+    /// Returns no pids, the processes of the container aren't tracked in a
+    /// cgroup
+    fn get_all_pids(&self) -> Result<Vec<Pid>, Infallible> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use oci_spec::runtime::LinuxResources;
+
+    use super::*;
+    use crate::common::{create_cgroup_manager_with_root, CgroupConfig, CgroupSetup};
+
+    #[test]
+    fn test_unmanaged_lifecycle() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let manager = create_cgroup_manager_with_root(
+            Some(root.path()),
+            CgroupConfig::new(
+                PathBuf::from("/youki/unmanaged"),
+                false,
+                "unmanaged".to_owned(),
+            )
+            .with_setup(Some(CgroupSetup::Unmanaged)),
+        )?;
+        assert!(matches!(manager, AnyCgroupManager::Unmanaged(_)));
+        assert_eq!(manager.cgroup_path(), None);
+
+        let resources = LinuxResources::default();
+        manager.apply(&ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpuset_partition: None,
+        })?;
+        manager.add_task(Pid::this())?;
+        manager.freeze(FreezerState::Frozen)?;
+        manager.freeze(FreezerState::Thawed)?;
+        assert!(manager.get_all_pids()?.is_empty());
+        let stats = manager.stats()?;
+        assert!(stats.unmanaged);
+        assert_eq!(stats.pids.current, 0);
+        manager.remove()?;
+
+        // nothing was created in the cgroup root
+        assert_eq!(std::fs::read_dir(root.path())?.count(), 0);
+        Ok(())
+    }
+}
@@ -9,9 +9,12 @@ use super::controller::Controller;
 use super::util::{self, V1MountPointError};
 use super::ControllerType;
 use crate::common::{self, ControllerOpt, WrapIoResult, WrappedIoError, CGROUP_PROCS};
+use crate::stats::{self, CpusetStats, StatsProvider};
 
 const CGROUP_CPUSET_CPUS: &str = "cpuset.cpus";
 const CGROUP_CPUSET_MEMS: &str = "cpuset.mems";
+const CGROUP_CPUSET_EFFECTIVE_CPUS: &str = "cpuset.effective_cpus";
+const CGROUP_CPUSET_EFFECTIVE_MEMS: &str = "cpuset.effective_mems";
 
 #[derive(thiserror::Error, Debug)]
 pub enum V1CpuSetControllerError {
@@ -65,6 +68,18 @@ impl Controller for CpuSet {
     }
 }
 
+impl StatsProvider for CpuSet {
+    type Error = V1CpuSetControllerError;
+    type Stats = CpusetStats;
+
+    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
+        Ok(stats::cpuset_stats(
+            &cgroup_path.join(CGROUP_CPUSET_EFFECTIVE_CPUS),
+            &cgroup_path.join(CGROUP_CPUSET_EFFECTIVE_MEMS),
+        )?)
+    }
+}
+
 impl CpuSet {
     fn apply(cgroup_path: &Path, cpuset: &LinuxCpu) -> Result<(), V1CpuSetControllerError> {
         if let Some(cpus) = &cpuset.cpus() {
@@ -157,4 +172,20 @@ mod tests {
             .unwrap_or_else(|_| panic!("read {CGROUP_CPUSET_MEMS} file content"));
         assert_eq!(content, "1-3");
     }
+
+    #[test]
+    fn test_stat_cpuset() {
+        let tmp = tempfile::tempdir().unwrap();
+        crate::test::set_fixture(tmp.path(), CGROUP_CPUSET_EFFECTIVE_CPUS, "0-1,4\n").unwrap();
+        crate::test::set_fixture(tmp.path(), CGROUP_CPUSET_EFFECTIVE_MEMS, "0-1\n").unwrap();
+
+        let stats = CpuSet::stats(tmp.path()).expect("get cpuset stats");
+        assert_eq!(
+            stats,
+            CpusetStats {
+                cpus: "0-1,4".to_owned(),
+                mems: "0-1".to_owned(),
+            }
+        );
+    }
 }
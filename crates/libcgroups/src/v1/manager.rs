@@ -17,6 +17,7 @@ use super::devices::Devices;
 use super::freezer::{Freezer, V1FreezerControllerError};
 use super::hugetlb::{HugeTlb, V1HugeTlbControllerError, V1HugeTlbStatsError};
 use super::memory::{Memory, V1MemoryControllerError, V1MemoryStatsError};
+use super::named::{self, NamedHierarchyError};
 use super::network_classifier::NetworkClassifier;
 use super::network_priority::NetworkPriority;
 use super::perf_event::PerfEvent;
@@ -31,6 +32,8 @@ use crate::stats::{PidStatsError, Stats, StatsProvider};
 
 pub struct Manager {
     subsystems: HashMap<CtrlType, PathBuf>,
+    /// Cgroups in named hierarchies the tasks are also attached to
+    named_cgroups: Vec<PathBuf>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -47,6 +50,10 @@ pub enum V1ManagerError {
     CGroupRequired(CtrlType),
     #[error("subsystem does not exist")]
     SubsystemDoesNotExist,
+    #[error("cpuset partitions are only supported on cgroup v2")]
+    CpusetPartitionNotSupported,
+    #[error(transparent)]
+    NamedHierarchy(#[from] NamedHierarchyError),
 
     #[error(transparent)]
     BlkioController(WrappedIoError),
@@ -91,7 +98,20 @@ impl Manager {
             }
         }
 
-        Ok(Manager { subsystems })
+        Ok(Manager {
+            subsystems,
+            named_cgroups: Vec::new(),
+        })
+    }
+
+    /// Also attaches the tasks to cgroups in named hierarchies, given as
+    /// `name=<name>:<path>`, see [`named`]
+    pub fn with_named_hierarchies(
+        mut self,
+        hierarchies: &[String],
+    ) -> Result<Self, V1ManagerError> {
+        self.named_cgroups = named::resolve_mounted_cgroups(hierarchies)?;
+        Ok(self)
     }
 
     fn get_subsystem_path(
@@ -188,11 +208,16 @@ impl CgroupManager for Manager {
                 CtrlType::Freezer => Freezer::add_task(pid, cgroup_path)?,
             }
         }
+        named::add_task(&self.named_cgroups, pid)?;
 
         Ok(())
     }
 
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<(), Self::Error> {
+        if controller_opt.cpuset_partition.is_some() {
+            return Err(V1ManagerError::CpusetPartitionNotSupported);
+        }
+
         for (ctrl_type, cgroup_path) in self.get_required_controllers(controller_opt)? {
             match ctrl_type {
                 CtrlType::Cpu => Cpu::apply(controller_opt, cgroup_path)?,
@@ -233,6 +258,8 @@ impl CgroupManager for Manager {
                 common::delete_with_retry(cgroup_path, 4, Duration::from_millis(100))?;
             }
         }
+        // The tasks were killed with the managed cgroups.
+        named::remove(&self.named_cgroups)?;
 
         Ok(())
     }
@@ -243,6 +270,7 @@ impl CgroupManager for Manager {
             freezer_state: Some(state),
             oom_score_adj: None,
             disable_oom_killer: false,
+            cpuset_partition: None,
         };
         Ok(Freezer::apply(
             &controller_opt,
@@ -263,6 +291,7 @@ impl CgroupManager for Manager {
                 CtrlType::HugeTlb => stats.hugetlb = HugeTlb::stats(cgroup_path)?,
                 CtrlType::Blkio => stats.blkio = Blkio::stats(cgroup_path)?,
                 CtrlType::Memory => stats.memory = Memory::stats(cgroup_path)?,
+                CtrlType::CpuSet => stats.cpuset = CpuSet::stats(cgroup_path)?,
                 _ => continue,
             }
         }
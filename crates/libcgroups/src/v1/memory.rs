@@ -543,6 +543,7 @@ mod tests {
                     disable_oom_killer,
                     oom_score_adj: None,
                     freezer_state: None,
+                    cpuset_partition: None,
                 };
 
                 let result = <Memory as Controller>::apply(&controller_opt, tmp.path());
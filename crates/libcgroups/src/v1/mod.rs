@@ -9,6 +9,7 @@ mod freezer;
 mod hugetlb;
 pub mod manager;
 mod memory;
+pub mod named;
 mod network_classifier;
 mod network_priority;
 pub mod perf_event;
@@ -0,0 +1,244 @@
+//! Named v1 hierarchies, e.g. `name=ops`, have no controllers attached and
+//! are used by external agents for accounting. Besides its managed cgroups,
+//! a container can be attached to cgroups in named hierarchies, given as
+//! `name=<name>:<path>`, with the path relative to the root of the hierarchy.
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use nix::unistd::Pid;
+
+use crate::common::{
+    self, JoinSafelyError, PathBufExt, WrapIoResult, WrappedIoError, CGROUP_PROCS,
+};
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+#[derive(thiserror::Error, Debug)]
+pub enum NamedHierarchyError {
+    #[error("invalid named hierarchy {0:?}, expected name=<name>:<path>")]
+    Invalid(String),
+    #[error("named hierarchy {0} is not mounted")]
+    NotMounted(String),
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("while joining paths: {0}")]
+    JoinSafely(#[from] JoinSafelyError),
+}
+
+/// A cgroup in a named hierarchy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedCgroup {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for NamedCgroup {
+    type Err = NamedHierarchyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NamedHierarchyError::Invalid(s.to_owned());
+        let (name, path) = s
+            .strip_prefix("name=")
+            .and_then(|named| named.split_once(':'))
+            .ok_or_else(invalid)?;
+        if name.is_empty() || name.contains(',') || !path.starts_with('/') {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// Returns the mount point of the named hierarchy in the mountinfo
+pub fn find_mount_point(mountinfo: &str, name: &str) -> Option<PathBuf> {
+    let name_option = format!("name={name}");
+    mountinfo.lines().find_map(|line| {
+        // The optional fields end with a single hyphen, followed by the
+        // filesystem type, the source and the super options.
+        let (mount, fs) = line.split_once(" - ")?;
+        let mut fs = fs.split_whitespace();
+        if fs.next()? != "cgroup" {
+            return None;
+        }
+        let super_options = fs.nth(1)?;
+        if !super_options.split(',').any(|option| option == name_option) {
+            return None;
+        }
+        mount
+            .split_whitespace()
+            .nth(4)
+            .map(|mount_point| PathBuf::from(unescape(mount_point)))
+    })
+}
+
+/// Undoes the octal escapes of spaces, tabs, newlines and backslashes in the
+/// paths of the mountinfo
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(pos) = rest.find('\\') {
+        unescaped.push_str(&rest[..pos]);
+        let code = rest
+            .get(pos + 1..pos + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(char::from(code));
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Resolves the directories of the named cgroups against the mountinfo
+pub fn resolve_cgroups(
+    hierarchies: &[String],
+    mountinfo: &str,
+) -> Result<Vec<PathBuf>, NamedHierarchyError> {
+    hierarchies
+        .iter()
+        .map(|hierarchy| {
+            let cgroup: NamedCgroup = hierarchy.parse()?;
+            let mount_point = find_mount_point(mountinfo, &cgroup.name)
+                .ok_or_else(|| NamedHierarchyError::NotMounted(cgroup.name.clone()))?;
+            Ok(mount_point.join_safely(&cgroup.path)?)
+        })
+        .collect()
+}
+
+/// Resolves the directories of the named cgroups against the mounts of the
+/// current process
+pub fn resolve_mounted_cgroups(
+    hierarchies: &[String],
+) -> Result<Vec<PathBuf>, NamedHierarchyError> {
+    if hierarchies.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mountinfo = fs::read_to_string(MOUNTINFO_PATH).wrap_read(MOUNTINFO_PATH)?;
+    resolve_cgroups(hierarchies, &mountinfo)
+}
+
+/// Creates the cgroups and attaches the process to them
+pub fn add_task(cgroups: &[PathBuf], pid: Pid) -> Result<(), WrappedIoError> {
+    for cgroup in cgroups {
+        tracing::debug!(?cgroup, ?pid, "attach to named cgroup");
+        fs::create_dir_all(cgroup).wrap_create_dir(cgroup)?;
+        common::write_cgroup_file(cgroup.join(CGROUP_PROCS), pid)?;
+    }
+
+    Ok(())
+}
+
+/// Removes the cgroups that exist. The tasks of the container are gone by
+/// then, as the cgroups can only be removed without tasks.
+pub fn remove(cgroups: &[PathBuf]) -> Result<(), WrappedIoError> {
+    for cgroup in cgroups.iter().filter(|cgroup| cgroup.exists()) {
+        tracing::debug!(?cgroup, "remove named cgroup");
+        common::delete_with_retry(cgroup, 4, Duration::from_millis(100))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// Mounts with the named hierarchy `ops` at the mount point
+    fn fixture_mountinfo(mount_point: &Path) -> String {
+        format!(
+            "25 1 0:23 / /sys/fs/cgroup rw,nosuid,nodev,noexec shared:9 - tmpfs tmpfs ro,mode=755\n\
+             32 25 0:28 / /sys/fs/cgroup/memory rw,nosuid shared:13 - cgroup cgroup rw,memory\n\
+             36 25 0:31 / {} rw,nosuid,nodev,noexec,relatime shared:15 - cgroup ops rw,name=ops\n\
+             37 25 0:32 / /sys/fs/cgroup/systemd rw,relatime shared:16 - cgroup cgroup rw,xattr,name=systemd\n",
+            mount_point.display()
+        )
+    }
+
+    #[test]
+    fn test_parse_named_cgroup() {
+        assert_eq!(
+            "name=ops:/teams/web".parse::<NamedCgroup>().unwrap(),
+            NamedCgroup {
+                name: "ops".to_owned(),
+                path: PathBuf::from("/teams/web"),
+            }
+        );
+        for invalid in [
+            "ops:/teams/web",
+            "name=:/web",
+            "name=ops",
+            "name=ops:web",
+            "name=a,b:/",
+        ] {
+            assert!(
+                matches!(
+                    invalid.parse::<NamedCgroup>(),
+                    Err(NamedHierarchyError::Invalid(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_mount_point() {
+        let mountinfo = fixture_mountinfo(Path::new("/sys/fs/cgroup/ops\\040agent"));
+        assert_eq!(
+            find_mount_point(&mountinfo, "ops"),
+            Some(PathBuf::from("/sys/fs/cgroup/ops agent"))
+        );
+        assert_eq!(
+            find_mount_point(&mountinfo, "systemd"),
+            Some(PathBuf::from("/sys/fs/cgroup/systemd"))
+        );
+        // a controller or an unknown name is no named hierarchy
+        assert_eq!(find_mount_point(&mountinfo, "memory"), None);
+        assert_eq!(find_mount_point(&mountinfo, "op"), None);
+    }
+
+    #[test]
+    fn test_named_cgroup_lifecycle() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mountinfo = fixture_mountinfo(tmp.path());
+
+        let cgroups = resolve_cgroups(&["name=ops:/teams/web".to_owned()], &mountinfo).unwrap();
+        let cgroup = tmp.path().join("teams/web");
+        assert_eq!(cgroups, vec![cgroup.clone()]);
+        assert!(matches!(
+            resolve_cgroups(&["name=billing:/web".to_owned()], &mountinfo),
+            Err(NamedHierarchyError::NotMounted(name)) if name == "billing"
+        ));
+
+        // On a real hierarchy the kernel creates cgroup.procs with the cgroup
+        // and removes it with the cgroup, a fixture needs it to be created
+        // ahead and removed.
+        fs::create_dir_all(&cgroup).unwrap();
+        crate::test::set_fixture(&cgroup, CGROUP_PROCS, "").unwrap();
+        add_task(&cgroups, Pid::from_raw(1000)).unwrap();
+        assert_eq!(
+            fs::read_to_string(cgroup.join(CGROUP_PROCS)).unwrap(),
+            "1000"
+        );
+
+        fs::remove_file(cgroup.join(CGROUP_PROCS)).unwrap();
+        remove(&cgroups).unwrap();
+        assert!(!cgroup.exists());
+        assert!(tmp.path().join("teams").exists());
+        // the cgroups are already gone
+        remove(&cgroups).unwrap();
+    }
+}
@@ -3,10 +3,14 @@ use std::path::Path;
 use oci_spec::runtime::LinuxCpu;
 
 use super::controller::Controller;
-use crate::common::{self, ControllerOpt, WrappedIoError};
+use crate::common::{self, ControllerOpt, CpusetPartition, WrappedIoError};
+use crate::stats::{self, CpusetStats, StatsProvider};
 
 const CGROUP_CPUSET_CPUS: &str = "cpuset.cpus";
 const CGROUP_CPUSET_MEMS: &str = "cpuset.mems";
+const CGROUP_CPUSET_PARTITION: &str = "cpuset.cpus.partition";
+const CGROUP_CPUSET_CPUS_EFFECTIVE: &str = "cpuset.cpus.effective";
+const CGROUP_CPUSET_MEMS_EFFECTIVE: &str = "cpuset.mems.effective";
 
 pub struct CpuSet {}
 
@@ -18,10 +22,28 @@ impl Controller for CpuSet {
             Self::apply(cgroup_path, cpuset)?;
         }
 
+        // The partition has to be written after the cpus, as the kernel
+        // validates the partition against the cpus of the cpuset.
+        if let Some(partition) = controller_opt.cpuset_partition {
+            Self::apply_partition(cgroup_path, partition)?;
+        }
+
         Ok(())
     }
 }
 
+impl StatsProvider for CpuSet {
+    type Error = WrappedIoError;
+    type Stats = CpusetStats;
+
+    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
+        stats::cpuset_stats(
+            &cgroup_path.join(CGROUP_CPUSET_CPUS_EFFECTIVE),
+            &cgroup_path.join(CGROUP_CPUSET_MEMS_EFFECTIVE),
+        )
+    }
+}
+
 impl CpuSet {
     fn apply(path: &Path, cpuset: &LinuxCpu) -> Result<(), WrappedIoError> {
         if let Some(cpus) = &cpuset.cpus() {
@@ -34,13 +56,20 @@ impl CpuSet {
 
         Ok(())
     }
+
+    pub(crate) fn apply_partition(
+        path: &Path,
+        partition: CpusetPartition,
+    ) -> Result<(), WrappedIoError> {
+        common::write_cgroup_file_str(path.join(CGROUP_CPUSET_PARTITION), partition.as_str())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs;
 
-    use oci_spec::runtime::LinuxCpuBuilder;
+    use oci_spec::runtime::{LinuxCpuBuilder, LinuxResourcesBuilder};
 
     use super::*;
     use crate::test::setup;
@@ -80,4 +109,67 @@ mod tests {
             .unwrap_or_else(|_| panic!("read {CGROUP_CPUSET_MEMS} file content"));
         assert_eq!(content, "1-3");
     }
+
+    #[test]
+    fn test_set_partition() {
+        // arrange
+        let (tmp, partition) = setup(CGROUP_CPUSET_PARTITION);
+        crate::test::set_fixture(tmp.path(), CGROUP_CPUSET_CPUS, "").unwrap();
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().cpus("2-3").build().unwrap())
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpuset_partition: Some(CpusetPartition::Root),
+        };
+
+        // act
+        <CpuSet as Controller>::apply(&controller_opt, tmp.path()).expect("apply cpuset");
+
+        // assert
+        let content = fs::read_to_string(partition)
+            .unwrap_or_else(|_| panic!("read {CGROUP_CPUSET_PARTITION} file content"));
+        assert_eq!(content, "root");
+    }
+
+    #[test]
+    fn test_stat_cpuset() {
+        let tmp = tempfile::tempdir().unwrap();
+        crate::test::set_fixture(tmp.path(), CGROUP_CPUSET_CPUS_EFFECTIVE, "0-3\n").unwrap();
+        crate::test::set_fixture(tmp.path(), CGROUP_CPUSET_MEMS_EFFECTIVE, "0\n").unwrap();
+
+        let stats = CpuSet::stats(tmp.path()).expect("get cpuset stats");
+        assert_eq!(
+            stats,
+            CpusetStats {
+                cpus: "0-3".to_owned(),
+                mems: "0".to_owned(),
+            }
+        );
+
+        // without the cpuset controller
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(CpuSet::stats(tmp.path()).unwrap(), CpusetStats::default());
+    }
+
+    #[test]
+    fn test_parse_partition() {
+        assert_eq!(
+            "member".parse::<CpusetPartition>().unwrap(),
+            CpusetPartition::Member
+        );
+        assert_eq!(
+            "root".parse::<CpusetPartition>().unwrap(),
+            CpusetPartition::Root
+        );
+        assert_eq!(
+            "isolated".parse::<CpusetPartition>().unwrap(),
+            CpusetPartition::Isolated
+        );
+        assert!("exclusive".parse::<CpusetPartition>().is_err());
+    }
 }
@@ -20,7 +20,7 @@ use super::io::{Io, V2IoControllerError, V2IoStatsError};
 use super::memory::{Memory, V2MemoryControllerError, V2MemoryStatsError};
 use super::pids::Pids;
 use super::unified::{Unified, V2UnifiedError};
-use super::util::{self, V2UtilError, CGROUP_SUBTREE_CONTROL};
+use super::util::{self, V2UtilError, CGROUP_CONTROLLERS, CGROUP_SUBTREE_CONTROL};
 use crate::common::{
     self, AnyCgroupManager, CgroupManager, ControllerOpt, FreezerState, JoinSafelyError,
     PathBufExt, WrapIoResult, WrappedIoError, CGROUP_PROCS,
@@ -28,6 +28,9 @@ use crate::common::{
 use crate::stats::{PidStatsError, Stats, StatsProvider};
 
 pub const CGROUP_KILL: &str = "cgroup.kill";
+/// Leaf cgroup the processes of the container are moved to with nested
+/// delegation, see [`Manager::with_nested_delegation`]
+pub const NESTED_INIT_CGROUP: &str = "init";
 
 #[derive(thiserror::Error, Debug)]
 pub enum V2ManagerError {
@@ -77,6 +80,7 @@ pub struct Manager {
     root_path: PathBuf,
     cgroup_path: PathBuf,
     full_path: PathBuf,
+    nested_delegation: bool,
 }
 
 impl Manager {
@@ -89,9 +93,74 @@ impl Manager {
             root_path,
             cgroup_path,
             full_path,
+            nested_delegation: false,
         })
     }
 
+    /// Sets if the cgroup is split for nested delegation. The processes of
+    /// the container live in the [`NESTED_INIT_CGROUP`] leaf below the cgroup
+    /// and the controllers of the cgroup are enabled for its children, so a
+    /// runtime in the container can create cgroups with controllers next to
+    /// the leaf without breaking the no internal processes rule. The limits
+    /// apply to the cgroup, so they cover the leaf and the cgroups of the
+    /// workload, and as cgroup v2 accounts hierarchically the stats of the
+    /// cgroup aggregate all of them.
+    pub fn with_nested_delegation(mut self, nested: bool) -> Self {
+        self.nested_delegation = nested;
+        self
+    }
+
+    /// Returns the path of the leaf the processes of the container live in
+    /// with nested delegation
+    pub fn init_path(&self) -> PathBuf {
+        self.full_path.join(NESTED_INIT_CGROUP)
+    }
+
+    /// Splits the cgroup for nested delegation: creates the init leaf, moves
+    /// the processes of the cgroup into it and enables the controllers of the
+    /// cgroup for its children. The processes are only moved once the cgroup
+    /// namespace of the container was created, so the cgroup stays the root
+    /// of the namespace. Does nothing without nested delegation.
+    pub fn delegate_nested(&self) -> Result<(), V2ManagerError> {
+        if !self.nested_delegation {
+            return Ok(());
+        }
+
+        let init_path = self.init_path();
+        if !init_path.exists() {
+            fs::create_dir(&init_path).wrap_create_dir(&init_path)?;
+        }
+        let procs_path = self.full_path.join(CGROUP_PROCS);
+        let procs = fs::read_to_string(&procs_path).wrap_read(&procs_path)?;
+        for pid in procs.lines() {
+            common::write_cgroup_file_str(init_path.join(CGROUP_PROCS), pid)?;
+        }
+
+        let controllers_path = self.full_path.join(CGROUP_CONTROLLERS);
+        let controllers: Vec<String> = fs::read_to_string(&controllers_path)
+            .wrap_read(&controllers_path)?
+            .split_whitespace()
+            .map(|c| format!("+{c}"))
+            .collect();
+        tracing::debug!(cgroup = ?self.full_path, ?controllers, "delegate nested cgroup");
+        Self::write_controllers(&self.full_path, &controllers)?;
+
+        Ok(())
+    }
+
+    /// Removes the cgroups below the cgroup, deepest first
+    fn remove_children(path: &Path) -> Result<(), WrappedIoError> {
+        for entry in fs::read_dir(path).wrap_read(path)? {
+            let child = entry.wrap_read(path)?.path();
+            if child.is_dir() {
+                Self::remove_children(&child)?;
+                common::delete_with_retry(&child, 4, Duration::from_millis(100))?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Creates a unified cgroup at `self.full_path` and attaches a process to it
     fn create_unified_cgroup(&self, pid: Pid) -> Result<(), V2ManagerError> {
         let controllers: Vec<String> = util::get_available_controllers(&self.root_path)?
@@ -137,6 +206,11 @@ impl Manager {
         Ok(())
     }
 
+    /// Returns the absolute path of the cgroup, `{root_path}/{cgroup_path}`
+    pub fn full_path(&self) -> &Path {
+        &self.full_path
+    }
+
     pub fn any(self) -> AnyCgroupManager {
         AnyCgroupManager::V2(self)
     }
@@ -147,7 +221,14 @@ impl CgroupManager for Manager {
 
     fn add_task(&self, pid: Pid) -> Result<(), Self::Error> {
         if self.full_path.exists() {
-            common::write_cgroup_file(self.full_path.join(CGROUP_PROCS), pid)?;
+            // Once split, the cgroup itself can't take processes anymore.
+            let init_path = self.init_path();
+            let path = if self.nested_delegation && init_path.exists() {
+                &init_path
+            } else {
+                &self.full_path
+            };
+            common::write_cgroup_file(path.join(CGROUP_PROCS), pid)?;
             return Ok(());
         }
         self.create_unified_cgroup(pid)?;
@@ -188,6 +269,12 @@ impl CgroupManager for Manager {
             let kill_file = self.full_path.join(CGROUP_KILL);
             if kill_file.exists() {
                 fs::write(&kill_file, "1").wrap_write(&kill_file, "1")?;
+            } else if self.nested_delegation {
+                // The processes are spread over the leaf and the cgroups of
+                // the workload.
+                for pid in common::get_all_pids(&self.full_path)? {
+                    let _ = nix::sys::signal::kill(pid, nix::sys::signal::SIGKILL);
+                }
             } else {
                 let procs_path = self.full_path.join(CGROUP_PROCS);
                 let procs = fs::read_to_string(&procs_path).wrap_read(&procs_path)?;
@@ -201,6 +288,9 @@ impl CgroupManager for Manager {
                 }
             }
 
+            if self.nested_delegation {
+                Self::remove_children(&self.full_path)?;
+            }
             common::delete_with_retry(&self.full_path, 4, Duration::from_millis(100))?;
         }
 
@@ -213,6 +303,7 @@ impl CgroupManager for Manager {
             freezer_state: Some(state),
             oom_score_adj: None,
             disable_oom_killer: false,
+            cpuset_partition: None,
         };
         Ok(Freezer::apply(&controller_opt, &self.full_path)?)
     }
@@ -229,6 +320,10 @@ impl CgroupManager for Manager {
                 }
                 ControllerType::Memory => stats.memory = Memory::stats(&self.full_path)?,
                 ControllerType::Io => stats.blkio = Io::stats(&self.full_path)?,
+                ControllerType::CpuSet => {
+                    stats.cpuset =
+                        CpuSet::stats(&self.full_path).map_err(V2ManagerError::CpuSetController)?
+                }
                 _ => continue,
             }
         }
@@ -240,3 +335,130 @@ impl CgroupManager for Manager {
         Ok(common::get_all_pids(&self.full_path)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::set_fixture;
+
+    #[test]
+    fn test_full_path() -> Result<(), V2ManagerError> {
+        let manager = Manager::new(
+            PathBuf::from("/sys/fs/cgroup"),
+            PathBuf::from("/youki/569d5ce3afe1074769f67"),
+        )?;
+        assert_eq!(
+            manager.full_path(),
+            Path::new("/sys/fs/cgroup/youki/569d5ce3afe1074769f67")
+        );
+        let manager = manager.any();
+        assert_eq!(
+            manager.cgroup_path(),
+            Some(Path::new("/sys/fs/cgroup/youki/569d5ce3afe1074769f67"))
+        );
+        assert_eq!(manager.systemd_unit(), None);
+
+        Ok(())
+    }
+
+    fn nested_fixture() -> anyhow::Result<(tempfile::TempDir, Manager)> {
+        let tmp = tempfile::tempdir()?;
+        let full_path = tmp.path().join("youki/c1");
+        fs::create_dir_all(full_path.join(NESTED_INIT_CGROUP))?;
+        // The kernel creates the interface files of a cgroup.
+        set_fixture(&full_path, CGROUP_PROCS, "4711\n")?;
+        set_fixture(&full_path, CGROUP_CONTROLLERS, "pids\n")?;
+        set_fixture(&full_path, CGROUP_SUBTREE_CONTROL, "")?;
+        set_fixture(&full_path.join(NESTED_INIT_CGROUP), CGROUP_PROCS, "")?;
+        let manager = Manager::new(tmp.path().to_owned(), PathBuf::from("/youki/c1"))?
+            .with_nested_delegation(true);
+
+        Ok((tmp, manager))
+    }
+
+    #[test]
+    fn test_delegate_nested() -> anyhow::Result<()> {
+        let (_tmp, manager) = nested_fixture()?;
+        assert_eq!(manager.init_path(), manager.full_path().join("init"));
+
+        manager.delegate_nested()?;
+        assert_eq!(
+            fs::read_to_string(manager.init_path().join(CGROUP_PROCS))?,
+            "4711"
+        );
+        assert_eq!(
+            fs::read_to_string(manager.full_path().join(CGROUP_SUBTREE_CONTROL))?,
+            "+pids"
+        );
+
+        // processes joining later land in the leaf as well
+        set_fixture(&manager.init_path(), CGROUP_PROCS, "")?;
+        manager.add_task(Pid::from_raw(42))?;
+        assert_eq!(
+            fs::read_to_string(manager.init_path().join(CGROUP_PROCS))?,
+            "42"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delegate_nested_disabled() -> anyhow::Result<()> {
+        let (_tmp, manager) = nested_fixture()?;
+        let manager = manager.with_nested_delegation(false);
+
+        manager.delegate_nested()?;
+        assert_eq!(
+            fs::read_to_string(manager.init_path().join(CGROUP_PROCS))?,
+            ""
+        );
+        assert_eq!(
+            fs::read_to_string(manager.full_path().join(CGROUP_SUBTREE_CONTROL))?,
+            ""
+        );
+
+        set_fixture(manager.full_path(), CGROUP_PROCS, "")?;
+        manager.add_task(Pid::from_raw(42))?;
+        assert_eq!(
+            fs::read_to_string(manager.full_path().join(CGROUP_PROCS))?,
+            "42"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_pids_aggregate() -> anyhow::Result<()> {
+        let (_tmp, manager) = nested_fixture()?;
+        let workload = manager.full_path().join("workload");
+        fs::create_dir(&workload)?;
+        set_fixture(manager.full_path(), CGROUP_PROCS, "")?;
+        set_fixture(&manager.init_path(), CGROUP_PROCS, "1\n")?;
+        set_fixture(&workload, CGROUP_PROCS, "7\n8\n")?;
+
+        let mut pids = manager.get_all_pids()?;
+        pids.sort();
+        assert_eq!(
+            pids,
+            vec![Pid::from_raw(1), Pid::from_raw(7), Pid::from_raw(8)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_children() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        fs::create_dir_all(tmp.path().join("init"))?;
+        fs::create_dir_all(tmp.path().join("workload/nested"))?;
+        set_fixture(tmp.path(), CGROUP_PROCS, "")?;
+
+        Manager::remove_children(tmp.path())?;
+        let left: Vec<_> = fs::read_dir(tmp.path())?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(left, vec![std::ffi::OsString::from(CGROUP_PROCS)]);
+
+        Ok(())
+    }
+}
@@ -134,8 +134,9 @@ impl Memory {
                         // In cgroup v1 swap is memory+swap, but in cgroup v2 swap is
                         // a separate value, so the swap value in the runtime spec needs
                         // to be converted from the cgroup v1 value to the cgroup v2 value
-                        // by subtracting limit from swap
-                        Memory::set(path.join(CGROUP_MEMORY_SWAP), swap - limit)?;
+                        // by subtracting limit from swap. A swap equal to the limit
+                        // disables the swap, so unlike other zeros it is written.
+                        common::write_cgroup_file(path.join(CGROUP_MEMORY_SWAP), swap - limit)?;
                     }
                     Memory::set(path.join(CGROUP_MEMORY_MAX), limit)?;
                 }
@@ -207,6 +208,25 @@ mod tests {
         assert_eq!(reservation_content, reservation.to_string());
     }
 
+    #[test]
+    fn test_set_memory_swap_disabled() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_MEMORY_MAX, "").expect("set fixture for memory limit");
+        set_fixture(tmp.path(), CGROUP_MEMORY_SWAP, "").expect("set fixture for swap limit");
+
+        let memory_limits = LinuxMemoryBuilder::default()
+            .limit(1024)
+            .swap(1024)
+            .build()
+            .unwrap();
+
+        Memory::apply(tmp.path(), &memory_limits).expect("apply memory limits");
+
+        let swap_content =
+            read_to_string(tmp.path().join(CGROUP_MEMORY_SWAP)).expect("read swap limit");
+        assert_eq!(swap_content, "0");
+    }
+
     #[test]
     fn test_set_memory_unlimited() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1,7 +1,7 @@
 mod controller;
 pub mod controller_type;
 mod cpu;
-mod cpuset;
+pub(crate) mod cpuset;
 #[cfg(feature = "cgroupsv2_devices")]
 pub mod devices;
 mod freezer;
@@ -0,0 +1,147 @@
+//! Environment variables of the container process set by annotations
+//!
+//! Higher level runtimes can pass annotations to a container but usually
+//! can't change the env of its process. With an annotation env prefix set with
+//! [`InitContainerBuilder::with_annotation_env_prefix`](crate::container::init_builder::InitContainerBuilder::with_annotation_env_prefix),
+//! each annotation named `<prefix>NAME` sets the env var `NAME` of the
+//! container process to the value of the annotation, e.g. with the
+//! [`DEFAULT_ANNOTATION_ENV_PREFIX`] the annotation `env.FOO=bar` sets
+//! `FOO=bar`.
+//!
+//! The env of the spec takes precedence, an annotation only adds a variable
+//! the spec doesn't set.
+use oci_spec::runtime::Spec;
+
+/// Prefix of the annotations env vars are commonly taken from
+pub const DEFAULT_ANNOTATION_ENV_PREFIX: &str = "env.";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AnnotationEnvError {
+    #[error("annotation {annotation:?} doesn't name a valid env var")]
+    InvalidName { annotation: String },
+}
+
+type Result<T> = std::result::Result<T, AnnotationEnvError>;
+
+/// Adds the env vars of the annotations with `prefix` to the process of the
+/// spec, in the order of their names. Returns the names of the env vars that
+/// were added.
+pub fn apply(spec: &mut Spec, prefix: &str) -> Result<Vec<String>> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    for (annotation, value) in spec.annotations().iter().flatten() {
+        let name = match annotation.strip_prefix(prefix) {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.is_empty() || name.contains('=') || name.contains('\0') {
+            return Err(AnnotationEnvError::InvalidName {
+                annotation: annotation.clone(),
+            });
+        }
+        vars.push((name.to_owned(), value.clone()));
+    }
+    if vars.is_empty() {
+        return Ok(Vec::new());
+    }
+    vars.sort_unstable();
+
+    let process = match spec.process_mut() {
+        Some(process) => process,
+        None => return Ok(Vec::new()),
+    };
+    let mut env = process.env().clone().unwrap_or_default();
+    let mut added = Vec::new();
+    for (name, value) in vars {
+        let in_spec = env.iter().any(|var| {
+            var.split_once('=')
+                .map_or(var.as_str(), |(var_name, _)| var_name)
+                == name
+        });
+        if in_spec {
+            tracing::debug!(
+                ?name,
+                "env var of the spec takes precedence over annotation"
+            );
+            continue;
+        }
+        env.push(format!("{name}={value}"));
+        added.push(name);
+    }
+    process.set_env(Some(env));
+
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use oci_spec::runtime::ProcessBuilder;
+
+    use super::*;
+
+    fn spec_with(env: &[&str], annotations: &[(&str, &str)]) -> Result<Spec> {
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default()
+                .args(vec!["sh".to_owned()])
+                .env(env.iter().map(|var| var.to_string()).collect::<Vec<_>>())
+                .build()?,
+        ));
+        spec.set_annotations(Some(
+            annotations
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        ));
+        Ok(spec)
+    }
+
+    fn env(spec: &Spec) -> Vec<String> {
+        spec.process()
+            .as_ref()
+            .and_then(|process| process.env().clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let mut spec = spec_with(
+            &["PATH=/usr/bin", "FOO=from-spec"],
+            &[
+                ("env.FOO", "from-annotation"),
+                ("env.BAR", "bar"),
+                ("env.ADDR", "a=b"),
+                ("io.example.other", "other"),
+            ],
+        )?;
+
+        let added = apply(&mut spec, DEFAULT_ANNOTATION_ENV_PREFIX)?;
+        assert_eq!(added, vec!["ADDR", "BAR"]);
+        assert_eq!(
+            env(&spec),
+            vec!["PATH=/usr/bin", "FOO=from-spec", "ADDR=a=b", "BAR=bar"]
+        );
+
+        // a custom prefix only picks its own annotations
+        let mut spec = spec_with(&[], &[("env.FOO", "foo"), ("io.example.env/FOO", "bar")])?;
+        assert_eq!(apply(&mut spec, "io.example.env/")?, vec!["FOO"]);
+        assert_eq!(env(&spec), vec!["FOO=bar"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_invalid_name() -> Result<()> {
+        for annotation in ["env.", "env.A=B"] {
+            let mut spec = spec_with(&[], &[(annotation, "value")])?;
+            assert!(matches!(
+                apply(&mut spec, DEFAULT_ANNOTATION_ENV_PREFIX),
+                Err(AnnotationEnvError::InvalidName { annotation: invalid }) if invalid == annotation
+            ));
+        }
+
+        Ok(())
+    }
+}
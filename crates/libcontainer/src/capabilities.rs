@@ -1,9 +1,145 @@
 //! Handles Management of Capabilities
+//!
+//! Capability names are normalized before they are used, by both the init and
+//! the tenant path: the case and the `CAP_` prefix are ignored, duplicates
+//! are dropped and the sets are ordered by the number of the capability, so
+//! two specs with the same capabilities resolve to the same sets. A name
+//! youki doesn't know is refused, a known capability the running kernel
+//! doesn't support, i.e. above its `cap_last_cap`, is dropped with a warning.
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
 use caps::{Capability as CapsCapability, *};
 use oci_spec::runtime::{Capabilities, Capability as SpecCapability, LinuxCapabilities};
+use serde_json::Value;
 
 use crate::syscall::{Syscall, SyscallError};
 
+/// Holds the number of the last capability the running kernel supports
+pub const CAP_LAST_CAP_PATH: &str = "/proc/sys/kernel/cap_last_cap";
+
+/// Capability sets of the process in the spec
+const CAPABILITY_SETS: [&str; 5] = [
+    "bounding",
+    "effective",
+    "inheritable",
+    "permitted",
+    "ambient",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum CapabilityError {
+    #[error("unknown capability {name:?}, the kernel supports {}", valid.join(", "))]
+    Unknown { name: String, valid: Vec<String> },
+    #[error("ambient capability {0} isn't in the permitted set")]
+    AmbientNotPermitted(String),
+}
+
+/// Returns the number of the last capability the kernel supports, read from
+/// `path`. If it can't be read, all the capabilities youki knows are assumed
+/// to be supported.
+pub fn last_cap(path: &Path) -> u8 {
+    match fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+    {
+        Some(last_cap) => last_cap,
+        None => {
+            let last_known = known_capabilities().last().map_or(0, |cap| cap.index());
+            tracing::warn!(?path, last_known, "failed to read the last capability");
+            last_known
+        }
+    }
+}
+
+/// The capabilities youki knows, by number
+fn known_capabilities() -> Vec<CapsCapability> {
+    let mut known: Vec<_> = caps::all().into_iter().collect();
+    known.sort_unstable_by_key(|cap| cap.index());
+    known
+}
+
+/// Parses a capability name, ignoring its case and an optional `CAP_`
+/// prefix. Returns `None` for a capability above `last_cap`, which the kernel
+/// doesn't support.
+pub fn parse(name: &str, last_cap: u8) -> Result<Option<CapsCapability>, CapabilityError> {
+    let upper = name.trim().to_ascii_uppercase();
+    let canonical = if upper.starts_with("CAP_") {
+        upper
+    } else {
+        format!("CAP_{upper}")
+    };
+    let cap = CapsCapability::from_str(&canonical).map_err(|_| {
+        let valid = known_capabilities()
+            .into_iter()
+            .filter(|cap| cap.index() <= last_cap)
+            .map(|cap| cap.to_string())
+            .collect();
+        tracing::error!(name, "unknown capability");
+        CapabilityError::Unknown {
+            name: name.to_owned(),
+            valid,
+        }
+    })?;
+    if cap.index() > last_cap {
+        tracing::warn!(%cap, last_cap, "capability isn't supported by the kernel, dropping it");
+        return Ok(None);
+    }
+
+    Ok(Some(cap))
+}
+
+/// Parses the capability names into a set without duplicates, ordered by
+/// the number of the capability
+pub fn normalize<'a, I>(names: I, last_cap: u8) -> Result<Vec<CapsCapability>, CapabilityError>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut caps = Vec::new();
+    for name in names {
+        if let Some(cap) = parse(name, last_cap)? {
+            caps.push(cap);
+        }
+    }
+    caps.sort_unstable_by_key(|cap| cap.index());
+    caps.dedup();
+
+    Ok(caps)
+}
+
+/// Normalizes the capability sets of the process of a spec in its json form,
+/// before it is deserialized, so the names are parsed the same way
+/// everywhere. It also orders the sets of a serialized spec, whose sets are
+/// unordered otherwise. A set that isn't a list of names is left to fail the
+/// deserialization.
+pub fn normalize_spec_json(spec: &mut Value, last_cap: u8) -> Result<(), CapabilityError> {
+    let capabilities = match spec.pointer_mut("/process/capabilities") {
+        Some(Value::Object(capabilities)) => capabilities,
+        _ => return Ok(()),
+    };
+    for set in CAPABILITY_SETS {
+        let names = match capabilities.get(set) {
+            Some(Value::Array(names)) => names,
+            _ => continue,
+        };
+        let names: Option<Vec<&str>> = names.iter().map(Value::as_str).collect();
+        let names = match names {
+            Some(names) => normalize(names, last_cap)?,
+            None => continue,
+        };
+        capabilities.insert(
+            set.to_owned(),
+            names
+                .iter()
+                .map(|cap| Value::from(cap.to_string()))
+                .collect(),
+        );
+    }
+
+    Ok(())
+}
+
 /// Converts a list of capability types to capabilities has set
 fn to_set(caps: &Capabilities) -> CapsHashSet {
     let mut capabilities = CapsHashSet::new();
@@ -162,15 +298,210 @@ pub fn drop_privileges<S: Syscall + ?Sized>(
     Ok(())
 }
 
+/// How the ambient set of the container process is resolved. Ambient
+/// capabilities are kept across the execve of a program without file
+/// capabilities, so they are the only capabilities a process not running as
+/// root passes on to the processes it spawns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AmbientCapabilities {
+    /// The ambient set of the spec as is
+    #[default]
+    Spec,
+    /// The ambient set of the spec, raised with the capabilities that are
+    /// effective, inheritable and permitted in the spec
+    FromInheritable,
+    /// Exactly the capabilities, which are added to the inheritable set.
+    /// They must be permitted in the spec.
+    Set(Capabilities),
+}
+
+/// Resolves the ambient set of the capabilities with the policy. The kernel
+/// only raises an ambient capability that is both permitted and
+/// inheritable, so a derived set never asks for more.
+pub fn resolve_ambient(
+    caps: &mut LinuxCapabilities,
+    policy: &AmbientCapabilities,
+) -> Result<(), CapabilityError> {
+    let permitted = caps.permitted().clone().unwrap_or_default();
+    match policy {
+        AmbientCapabilities::Spec => {}
+        AmbientCapabilities::FromInheritable => {
+            let effective = caps.effective().clone().unwrap_or_default();
+            let mut ambient = caps.ambient().clone().unwrap_or_default();
+            ambient.extend(
+                caps.inheritable()
+                    .iter()
+                    .flatten()
+                    .filter(|cap| effective.contains(cap) && permitted.contains(cap))
+                    .copied(),
+            );
+            tracing::debug!(
+                ?ambient,
+                "raising ambient capabilities from the inheritable set"
+            );
+            caps.set_ambient(Some(ambient));
+        }
+        AmbientCapabilities::Set(ambient) => {
+            if let Some(cap) = ambient.iter().find(|cap| !permitted.contains(cap)) {
+                tracing::error!(?cap, "ambient capability isn't permitted");
+                return Err(CapabilityError::AmbientNotPermitted(
+                    cap.to_cap().to_string(),
+                ));
+            }
+            let mut inheritable = caps.inheritable().clone().unwrap_or_default();
+            inheritable.extend(ambient.iter().copied());
+            caps.set_inheritable(Some(inheritable));
+            caps.set_ambient(Some(ambient.clone()));
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
 
     use oci_spec::runtime::LinuxCapabilitiesBuilder;
+    use serde_json::json;
 
     use super::*;
     use crate::syscall::test::TestHelperSyscall;
 
+    #[test]
+    fn test_parse() {
+        // CAP_CHECKPOINT_RESTORE is the last capability youki knows, 40
+        let tests: &[(&str, u8, Option<CapsCapability>)] = &[
+            ("CAP_NET_ADMIN", 40, Some(CapsCapability::CAP_NET_ADMIN)),
+            ("net_admin", 40, Some(CapsCapability::CAP_NET_ADMIN)),
+            ("Cap_Net_Admin", 40, Some(CapsCapability::CAP_NET_ADMIN)),
+            ("NET_ADMIN", 40, Some(CapsCapability::CAP_NET_ADMIN)),
+            (" CAP_CHOWN ", 40, Some(CapsCapability::CAP_CHOWN)),
+            ("CAP_BPF", 39, Some(CapsCapability::CAP_BPF)),
+            // newer than the kernel
+            ("CAP_CHECKPOINT_RESTORE", 39, None),
+            ("perfmon", 37, None),
+            ("CAP_SETFCAP", 30, None),
+        ];
+        for (name, last_cap, expected) in tests {
+            assert_eq!(
+                parse(name, *last_cap).unwrap_or_else(|err| panic!("{name}: {err}")),
+                *expected,
+                "{name} with last cap {last_cap}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_unknown() {
+        for name in [
+            "CAP_FUTURE_ADMIN",
+            "future_admin",
+            "",
+            "CAP_",
+            "CAP_NET-ADMIN",
+        ] {
+            match parse(name, 2) {
+                Err(CapabilityError::Unknown { name: got, valid }) => {
+                    assert_eq!(got, name);
+                    assert_eq!(
+                        valid,
+                        ["CAP_CHOWN", "CAP_DAC_OVERRIDE", "CAP_DAC_READ_SEARCH"]
+                    );
+                }
+                other => panic!("{name}: expected unknown, got {other:?}"),
+            }
+        }
+        let err = parse("CAP_FUTURE_ADMIN", 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown capability \"CAP_FUTURE_ADMIN\", the kernel supports CAP_CHOWN, CAP_DAC_OVERRIDE"
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let tests: &[(&[&str], u8, &[CapsCapability])] = &[
+            (&[], 40, &[]),
+            (
+                &["CAP_NET_ADMIN", "net_admin", "CAP_CHOWN", "Net_Admin"],
+                40,
+                &[CapsCapability::CAP_CHOWN, CapsCapability::CAP_NET_ADMIN],
+            ),
+            (
+                &["sys_admin", "kill", "chown", "CAP_KILL"],
+                40,
+                &[
+                    CapsCapability::CAP_CHOWN,
+                    CapsCapability::CAP_KILL,
+                    CapsCapability::CAP_SYS_ADMIN,
+                ],
+            ),
+            (
+                &["CAP_CHECKPOINT_RESTORE", "CAP_BPF", "CAP_PERFMON"],
+                38,
+                &[CapsCapability::CAP_PERFMON],
+            ),
+        ];
+        for (names, last_cap, expected) in tests {
+            assert_eq!(
+                normalize(names.iter().copied(), *last_cap).unwrap(),
+                *expected,
+                "{names:?}"
+            );
+        }
+        assert!(normalize(["CAP_KILL", "CAP_TELEPORT"], 40).is_err());
+    }
+
+    #[test]
+    fn test_normalize_spec_json() {
+        let mut spec = json!({
+            "process": {
+                "capabilities": {
+                    "bounding": ["net_admin", "CAP_CHOWN", "CAP_NET_ADMIN", "CAP_BPF"],
+                    "effective": ["CAP_KILL", "kill"],
+                    "permitted": null,
+                    "ambient": [1],
+                }
+            }
+        });
+        normalize_spec_json(&mut spec, 38).unwrap();
+        assert_eq!(
+            spec,
+            json!({
+                "process": {
+                    "capabilities": {
+                        "bounding": ["CAP_CHOWN", "CAP_NET_ADMIN"],
+                        "effective": ["CAP_KILL"],
+                        "permitted": null,
+                        "ambient": [1],
+                    }
+                }
+            })
+        );
+
+        let mut spec = json!({"process": {"capabilities": {"inheritable": ["CAP_NOPE"]}}});
+        assert!(normalize_spec_json(&mut spec, 40).is_err());
+        // nothing to normalize
+        let mut spec = json!({"ociVersion": "1.0.2"});
+        normalize_spec_json(&mut spec, 40).unwrap();
+    }
+
+    #[test]
+    fn test_last_cap() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("cap_last_cap");
+        fs::write(&path, "40\n")?;
+        assert_eq!(last_cap(&path), 40);
+        fs::write(&path, "garbage")?;
+        assert_eq!(
+            last_cap(&path),
+            CapsCapability::CAP_CHECKPOINT_RESTORE.index()
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_reset_effective() {
         let test_command = TestHelperSyscall::default();
@@ -667,4 +998,63 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_resolve_ambient() {
+        let set = |caps: &[SpecCapability]| caps.iter().copied().collect::<Capabilities>();
+        let spec_caps = || {
+            LinuxCapabilitiesBuilder::default()
+                .effective(set(&[
+                    SpecCapability::NetBindService,
+                    SpecCapability::Chown,
+                ]))
+                .permitted(set(&[
+                    SpecCapability::NetBindService,
+                    SpecCapability::Chown,
+                    SpecCapability::Kill,
+                ]))
+                .inheritable(set(&[SpecCapability::NetBindService, SpecCapability::Kill]))
+                .ambient(set(&[SpecCapability::Kill]))
+                .build()
+                .unwrap()
+        };
+
+        let mut caps = spec_caps();
+        resolve_ambient(&mut caps, &AmbientCapabilities::Spec).unwrap();
+        assert_eq!(caps, spec_caps());
+
+        // only the capabilities that are effective too are raised
+        let mut caps = spec_caps();
+        resolve_ambient(&mut caps, &AmbientCapabilities::FromInheritable).unwrap();
+        assert_eq!(
+            caps.ambient(),
+            &Some(set(&[SpecCapability::Kill, SpecCapability::NetBindService]))
+        );
+
+        let mut caps = spec_caps();
+        resolve_ambient(
+            &mut caps,
+            &AmbientCapabilities::Set(set(&[SpecCapability::Chown])),
+        )
+        .unwrap();
+        assert_eq!(caps.ambient(), &Some(set(&[SpecCapability::Chown])));
+        assert_eq!(
+            caps.inheritable(),
+            &Some(set(&[
+                SpecCapability::NetBindService,
+                SpecCapability::Kill,
+                SpecCapability::Chown
+            ]))
+        );
+
+        let mut caps = spec_caps();
+        assert!(matches!(
+            resolve_ambient(
+                &mut caps,
+                &AmbientCapabilities::Set(set(&[SpecCapability::SysAdmin])),
+            ),
+            Err(CapabilityError::AmbientNotPermitted(_))
+        ));
+        assert_eq!(caps, spec_caps());
+    }
 }
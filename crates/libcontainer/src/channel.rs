@@ -2,11 +2,15 @@ use std::io::{IoSlice, IoSliceMut};
 use std::marker::PhantomData;
 use std::os::fd::AsRawFd;
 use std::os::unix::prelude::RawFd;
+use std::time::{Duration, Instant};
 
+use nix::errno::Errno;
 use nix::sys::socket::{self, UnixAddr};
 use nix::unistd::{self};
 use serde::{Deserialize, Serialize};
 
+use crate::create_signals;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ChannelError {
     #[error("failed unix syscalls")]
@@ -183,6 +187,34 @@ where
         Ok((serde_json::from_slice(&buf[..])?, fds))
     }
 
+    /// Waits up to `timeout` for a message, returns whether one arrived. A
+    /// closed sender counts as arrived, the recv reports it. poll isn't
+    /// restarted after a signal handler, even with `SA_RESTART`, so the wait
+    /// is resumed for the remaining time after any signal but a termination
+    /// signal caught for the create, which fails it with EINTR.
+    pub fn wait_readable(&self, timeout: Duration) -> Result<bool, ChannelError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut fds = [libc::pollfd {
+                fd: self.receiver,
+                events: libc::POLLIN,
+                revents: 0,
+            }];
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // Rounded up, so the wait doesn't spin on the last millisecond.
+            let timeout =
+                ((remaining.as_micros() + 999) / 1000).min(libc::c_int::MAX as u128) as libc::c_int;
+            // SAFETY: fds is valid for the duration of the call.
+            match Errno::result(unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) }) {
+                Ok(ready) => return Ok(ready > 0),
+                Err(Errno::EINTR) if !create_signals::termination_received() => {
+                    tracing::debug!("wait for a message interrupted by a signal, resuming");
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
     pub fn close(&self) -> Result<(), ChannelError> {
         Ok(unistd::close(self.receiver)?)
     }
@@ -5,6 +5,7 @@ use std::path::{Path, PathBuf};
 use oci_spec::runtime::{Hooks, Spec};
 use serde::{Deserialize, Serialize};
 
+use crate::readiness_probe::ReadinessProbe;
 use crate::utils;
 
 #[derive(Debug, thiserror::Error)]
@@ -36,6 +37,9 @@ pub enum ConfigError {
 type Result<T> = std::result::Result<T, ConfigError>;
 
 const YOUKI_CONFIG_NAME: &str = "youki_config.json";
+/// Annotation with the cgroups in named v1 hierarchies the container is
+/// attached to, e.g. `name=ops:/teams/web`, separated by `;`
+pub const EXTRA_CGROUP_ANNOTATION: &str = "io.youki.extra-cgroup";
 
 /// A configuration for passing information obtained during container creation to other commands.
 /// Keeping the information to a minimum improves performance.
@@ -44,6 +48,19 @@ const YOUKI_CONFIG_NAME: &str = "youki_config.json";
 pub struct YoukiConfig {
     pub hooks: Option<Hooks>,
     pub cgroup_path: PathBuf,
+    /// Cgroups in named v1 hierarchies the container is attached to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_cgroup_hierarchies: Vec<String>,
+    /// If the processes of the container live in a leaf below its cgroup
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nested_cgroup_delegation: bool,
+    /// If the workload reports its readiness over the readiness socket
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub readiness_notify: bool,
+    /// Probe [`Container::start`](crate::container::Container::start) waits
+    /// for before the container is running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readiness_probe: Option<ReadinessProbe>,
 }
 
 impl YoukiConfig {
@@ -57,6 +74,10 @@ impl YoukiConfig {
                     .cgroups_path(),
                 container_id,
             ),
+            extra_cgroup_hierarchies: extra_cgroup_hierarchies(spec),
+            nested_cgroup_delegation: false,
+            readiness_notify: false,
+            readiness_probe: None,
         })
     }
 
@@ -96,6 +117,22 @@ impl YoukiConfig {
     }
 }
 
+/// Returns the named cgroups of the [`EXTRA_CGROUP_ANNOTATION`] annotation
+pub fn extra_cgroup_hierarchies(spec: &Spec) -> Vec<String> {
+    spec.annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(EXTRA_CGROUP_ANNOTATION))
+        .map(|hierarchies| {
+            hierarchies
+                .split(';')
+                .map(str::trim)
+                .filter(|hierarchy| !hierarchy.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
@@ -113,6 +150,28 @@ mod tests {
             config.cgroup_path,
             PathBuf::from(format!(":youki:{container_id}"))
         );
+        assert!(config.extra_cgroup_hierarchies.is_empty());
+        assert!(!config.nested_cgroup_delegation);
+        assert!(!config.readiness_notify);
+        assert!(config.readiness_probe.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_extra_cgroup_hierarchies() -> Result<()> {
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(
+            [(
+                EXTRA_CGROUP_ANNOTATION.to_owned(),
+                "name=ops:/teams/web; name=billing:/web;".to_owned(),
+            )]
+            .into(),
+        ));
+        let config = YoukiConfig::from_spec(&spec, "sample")?;
+        assert_eq!(
+            config.extra_cgroup_hierarchies,
+            vec!["name=ops:/teams/web", "name=billing:/web"]
+        );
         Ok(())
     }
 
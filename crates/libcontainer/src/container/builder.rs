@@ -1,18 +1,38 @@
 use std::os::fd::OwnedFd;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use super::init_builder::InitContainerBuilder;
+use super::state_store::{default_state_store, StateStore};
 use super::tenant_builder::TenantContainerBuilder;
 use crate::error::{ErrInvalidID, LibcontainerError};
+use crate::fault_injection::FaultInjection;
+use crate::runtime_root;
+use crate::stdio_file::{RotationPolicy, StdioFile};
 use crate::syscall::syscall::SyscallType;
 use crate::utils::PathBufExt;
 use crate::workload::{self, Executor};
 
+/// Default maximum length of a container id.
+pub const DEFAULT_MAX_ID_LEN: usize = 128;
+/// Default time the seccomp listener has to take the seccomp notify fd.
+pub const DEFAULT_SECCOMP_NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default time a slow console socket receiver is retried for.
+pub const DEFAULT_CONSOLE_SOCKET_TIMEOUT: Duration = Duration::from_secs(1);
+/// Default time the intermediate process has to report to the main process.
+pub const DEFAULT_INTERMEDIATE_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub struct ContainerBuilder {
     /// Id of the container
     pub(super) container_id: String,
+    /// Maximum length of the container id
+    pub(super) max_id_len: usize,
     /// Root directory for container state
     pub(super) root_path: PathBuf,
+    /// If the root directory was set, otherwise the default runtime root of
+    /// the user is used
+    pub(super) root_path_set: bool,
     /// Interface to operating system primitives
     pub(super) syscall: SyscallType,
     /// File which will be used to communicate the pid of the
@@ -20,11 +40,39 @@ pub struct ContainerBuilder {
     pub(super) pid_file: Option<PathBuf>,
     /// Socket to communicate the file descriptor of the ptty
     pub(super) console_socket: Option<PathBuf>,
+    /// How long connecting to the console socket and sending the pty master
+    /// over it is retried
+    pub(super) console_socket_timeout: Duration,
+    /// How long the intermediate process may take to report before it is
+    /// considered stuck
+    pub(super) intermediate_timeout: Duration,
     /// File descriptors to be passed into the container process
     pub(super) preserve_fds: i32,
     /// The function that actually runs on the container init process. Default
     /// is to execute the specified command in the oci spec.
     pub(super) executor: Box<dyn Executor>,
+    /// If the executor was set, otherwise the default executor is used
+    pub(super) executor_set: bool,
+    /// Store the records of the container are persisted in
+    pub(super) state_store: Arc<dyn StateStore>,
+    /// If a host that doesn't permit making the runtime non-dumpable is
+    /// tolerated
+    pub(super) tolerate_dumpable_eperm: bool,
+    /// How long the seccomp listener has to take the seccomp notify fd
+    pub(super) seccomp_notify_timeout: Duration,
+    /// PATH the executable is resolved with if the env of the process has
+    /// none
+    pub(super) default_path: Option<String>,
+    /// If the default PATH is also added to the env of the process
+    pub(super) inject_default_path: bool,
+    /// argv[0] the executable of the process gets instead of the first arg
+    pub(super) argv0_override: Option<String>,
+    /// Env files the env of the process is layered on, in order
+    pub(super) env_files: Vec<PathBuf>,
+    /// Files the stdout and stderr are written to, rotated by the runtime
+    pub(super) stdio_files: Vec<StdioFile>,
+    /// Faults injected into the create, for testing
+    pub(super) fault_injection: FaultInjection,
     // RawFd set to stdin of the container init process.
     pub stdin: Option<OwnedFd>,
     // RawFd set to stdout of the container init process.
@@ -68,15 +116,29 @@ impl ContainerBuilder {
     /// );
     /// ```
     pub fn new(container_id: String, syscall: SyscallType) -> Self {
-        let root_path = PathBuf::from("/run/youki");
+        let root_path = PathBuf::from(runtime_root::ROOT_RUNTIME_ROOT);
         Self {
             container_id,
+            max_id_len: DEFAULT_MAX_ID_LEN,
             root_path,
+            root_path_set: false,
             syscall,
             pid_file: None,
             console_socket: None,
+            console_socket_timeout: DEFAULT_CONSOLE_SOCKET_TIMEOUT,
+            intermediate_timeout: DEFAULT_INTERMEDIATE_TIMEOUT,
             preserve_fds: 0,
             executor: workload::default::get_executor(),
+            executor_set: false,
+            state_store: default_state_store(),
+            tolerate_dumpable_eperm: false,
+            seccomp_notify_timeout: DEFAULT_SECCOMP_NOTIFY_TIMEOUT,
+            default_path: None,
+            inject_default_path: false,
+            argv0_override: None,
+            env_files: Vec::new(),
+            stdio_files: Vec::new(),
+            fault_injection: FaultInjection::default(),
             stdin: None,
             stdout: None,
             stderr: None,
@@ -84,13 +146,12 @@ impl ContainerBuilder {
     }
 
     /// validate_id checks if the supplied container ID is valid, returning
-    /// the ErrInvalidID in case it is not.
-    ///
-    /// The format of valid ID was never formally defined, instead the code
-    /// was modified to allow or disallow specific characters.
+    /// the ErrInvalidID in case it is not. The id is also validated when the
+    /// container is built, calling this only reports an invalid id earlier.
     ///
-    /// Currently, a valid ID is a non-empty string consisting only of
-    /// the following characters:
+    /// A valid ID is a string of 1 to [`DEFAULT_MAX_ID_LEN`] bytes, unless
+    /// configured otherwise with [`ContainerBuilder::with_max_id_len`],
+    /// consisting only of the following characters:
     /// - uppercase (A-Z) and lowercase (a-z) Latin letters;
     /// - digits (0-9);
     /// - underscore (_);
@@ -99,24 +160,54 @@ impl ContainerBuilder {
     /// - period (.).
     ///
     /// In addition, IDs that can't be used to represent a file name
-    /// (such as . or ..) are rejected.
+    /// (such as . or ..) are rejected, and the ID can't start with a minus
+    /// sign, so it can't be mistaken for a command line flag.
     pub fn validate_id(self) -> Result<Self, LibcontainerError> {
-        let container_id = self.container_id.clone();
-        if container_id.is_empty() {
-            Err(ErrInvalidID::Empty)?;
-        }
+        validate_container_id(&self.container_id, self.max_id_len)?;
+        Ok(self)
+    }
 
-        if container_id == "." || container_id == ".." {
-            Err(ErrInvalidID::FileName)?;
-        }
+    /// Sets the store the state and the exec sessions of the container are
+    /// persisted in, instead of the
+    /// [default store](crate::container::state_store::default_state_store)
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::InMemoryStateStore;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_state_store(Arc::new(InMemoryStateStore::new()));
+    /// ```
+    pub fn with_state_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.state_store = store;
+        self
+    }
 
-        for c in container_id.chars() {
-            match c {
-                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '+' | '-' | '.' => (),
-                _ => Err(ErrInvalidID::InvalidChars(c))?,
-            }
-        }
-        Ok(self)
+    /// Sets the maximum length of the container id accepted by
+    /// [`ContainerBuilder::validate_id`]
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_max_id_len(64)
+    /// .validate_id()
+    /// .expect("invalid container id");
+    /// ```
+    pub fn with_max_id_len(mut self, max_id_len: usize) -> Self {
+        self.max_id_len = max_id_len;
+        self
     }
 
     /// Transforms this builder into a tenant builder
@@ -159,7 +250,9 @@ impl ContainerBuilder {
         InitContainerBuilder::new(self, bundle.into())
     }
 
-    /// Sets the root path which will be used to store the container state
+    /// Sets the root path which will be used to store the container state.
+    /// Without, the default runtime root of the user is used, see
+    /// [`runtime_root`].
     /// # Example
     ///
     /// ```no_run
@@ -178,10 +271,23 @@ impl ContainerBuilder {
             tracing::error!(?path, ?err, "failed to canonicalize root path");
             LibcontainerError::InvalidInput(format!("invalid root path {path:?}: {err:?}"))
         })?;
+        self.root_path_set = true;
 
         Ok(self)
     }
 
+    /// Falls back to the default runtime root of the user, created and
+    /// checked by [`runtime_root::default_for_user`], if no root path was set
+    pub(super) fn resolve_root_path(&mut self) -> Result<(), LibcontainerError> {
+        if !self.root_path_set {
+            self.root_path =
+                runtime_root::default_for_user(self.syscall.create_syscall().as_ref())?;
+            self.root_path_set = true;
+        }
+
+        Ok(())
+    }
+
     /// Sets the pid file which will be used to write the pid of the container
     /// process
     /// # Example
@@ -230,6 +336,54 @@ impl ContainerBuilder {
         self
     }
 
+    /// Sets how long connecting to the console socket and sending the pty
+    /// master over it is retried, defaults to
+    /// [`DEFAULT_CONSOLE_SOCKET_TIMEOUT`]. A receiver that doesn't listen
+    /// yet or is slow to read is retried with a backoff instead of failing
+    /// the create, a zero timeout makes a single attempt.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_console_socket(Some("/var/run/docker/sock.tty"))
+    /// .with_console_socket_timeout(Duration::from_secs(5));
+    /// ```
+    pub fn with_console_socket_timeout(mut self, timeout: Duration) -> Self {
+        self.console_socket_timeout = timeout;
+        self
+    }
+
+    /// Sets how long the intermediate process may take to report to the
+    /// main process, defaults to [`DEFAULT_INTERMEDIATE_TIMEOUT`]. A process
+    /// stuck past it, e.g. on a frozen cgroup, is killed with its
+    /// descendants and the create fails with
+    /// [`ProcessError::CreateTimeout`](crate::process::container_main_process::ProcessError::CreateTimeout),
+    /// which carries where it was stuck.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_intermediate_timeout(Duration::from_secs(120));
+    /// ```
+    pub fn with_intermediate_timeout(mut self, timeout: Duration) -> Self {
+        self.intermediate_timeout = timeout;
+        self
+    }
+
     /// Sets the number of additional file descriptors which will be passed into
     /// the container process.
     /// # Example
@@ -249,7 +403,154 @@ impl ContainerBuilder {
         self
     }
 
+    /// Sets if the create continues when making the runtime non-dumpable
+    /// fails with EPERM, e.g. on a host whose seccomp policy blocks prctl.
+    /// Being non-dumpable keeps processes of the joined namespaces from
+    /// accessing the runtime, which is hardening only, so the runtime stays
+    /// dumpable with a warning instead. Defaults to false, the create fails
+    /// with [`LibcontainerError::DumpableNotPermitted`].
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_tolerate_dumpable_eperm(true);
+    /// ```
+    pub fn with_tolerate_dumpable_eperm(mut self, tolerate: bool) -> Self {
+        self.tolerate_dumpable_eperm = tolerate;
+        self
+    }
+
+    /// Sets how long the seccomp listener of a spec with notify rules has to
+    /// take the seccomp notify fd, defaults to
+    /// [`DEFAULT_SECCOMP_NOTIFY_TIMEOUT`]. If the listener doesn't take it in
+    /// time, e.g. because the agent behind it hangs, the create fails with
+    /// [`LibcontainerError::SeccompNotifyTimeout`] instead of blocking.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_seccomp_notify_timeout(Duration::from_secs(2));
+    /// ```
+    pub fn with_seccomp_notify_timeout(mut self, timeout: Duration) -> Self {
+        self.seccomp_notify_timeout = timeout;
+        self
+    }
+
+    /// Sets the PATH the executable of the process is resolved with inside
+    /// the rootfs when the env of the process has no PATH, as execvpe does.
+    /// The PATH is handed to the executor in the
+    /// [`DEFAULT_PATH_ANNOTATION`](crate::workload::DEFAULT_PATH_ANNOTATION)
+    /// annotation and isn't added to the env of the process, unless set with
+    /// [`ContainerBuilder::with_inject_default_path`].
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_default_path("/usr/local/bin:/usr/bin:/bin");
+    /// ```
+    pub fn with_default_path<S: Into<String>>(mut self, path: S) -> Self {
+        self.default_path = Some(path.into());
+        self
+    }
+
+    /// Sets if the default PATH, see [`ContainerBuilder::with_default_path`],
+    /// is also added to the env of a process that has no PATH. Defaults to
+    /// false.
+    pub fn with_inject_default_path(mut self, inject: bool) -> Self {
+        self.inject_default_path = inject;
+        self
+    }
+
+    /// Sets the argv[0] the executable of the process is run with. The
+    /// executable is still resolved from the first arg of the process, so a
+    /// multi-call binary like busybox can be run as one of its applets. The
+    /// argv[0] is handed to the executor in the
+    /// [`ARGV0_ANNOTATION`](crate::workload::ARGV0_ANNOTATION) annotation.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_argv0_override("ls");
+    /// ```
+    pub fn with_argv0_override<S: Into<String>>(mut self, argv0: S) -> Self {
+        self.argv0_override = Some(argv0.into());
+        self
+    }
+
+    /// Sets the delays and failures injected into the create, see
+    /// [`fault_injection`](crate::fault_injection). For testing only.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    /// use libcontainer::fault_injection::{Fault, FaultInjection, FaultPoint};
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_fault_injection(
+    ///     FaultInjection::default().with_fault(FaultPoint::PreCgroupApply, Fault::Fail),
+    /// );
+    /// ```
+    #[cfg(feature = "fault_injection")]
+    pub fn with_fault_injection(mut self, fault_injection: FaultInjection) -> Self {
+        self.fault_injection = fault_injection;
+        self
+    }
+
+    /// Adds an env file the env of the process is layered on, see
+    /// [`env_file`](crate::env_file) for its format. The vars of a later file
+    /// override the ones of earlier files, the env of the spec or process and
+    /// the env set with `with_env` override the vars of all files.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_env_file("/etc/ci/defaults.env")
+    /// .with_env_file("/etc/ci/job.env");
+    /// ```
+    pub fn with_env_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.env_files.push(path.into());
+        self
+    }
+
     /// Sets the function that actually runs on the container init process.
+    /// An [`ExecutorRegistry`](crate::workload::registry::ExecutorRegistry) can
+    /// be passed here to select the executor by name or spec annotation.
     /// # Example
     ///
     /// ```no_run
@@ -261,10 +562,11 @@ impl ContainerBuilder {
     ///     "74f1a4cb3801".to_owned(),
     ///     SyscallType::default(),
     /// )
-    /// .with_executor(DefaultExecutor{});
+    /// .with_executor(DefaultExecutor::default());
     /// ```
     pub fn with_executor(mut self, executor: impl Executor + 'static) -> Self {
         self.executor = Box::new(executor);
+        self.executor_set = true;
         self
     }
 
@@ -336,6 +638,119 @@ impl ContainerBuilder {
         self.stderr = Some(stderr.into());
         self
     }
+
+    /// Sets the stdout of the container to a file at `path`, which the
+    /// container appends to and the runtime rotates according to `rotation`.
+    /// The rotation runs in a thread of the current process for as long as
+    /// the container process exists, so it stops if the current process
+    /// exits before, while the container keeps writing to the file.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::stdio_file::RotationPolicy;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_stdout_file("/var/log/web.out", RotationPolicy::default())
+    /// .expect("invalid stdout file");
+    /// ```
+    pub fn with_stdout_file<P: Into<PathBuf>>(
+        mut self,
+        path: P,
+        rotation: RotationPolicy,
+    ) -> Result<Self, LibcontainerError> {
+        self.stdout = Some(self.open_stdio_file(path.into(), rotation)?);
+        Ok(self)
+    }
+
+    /// Sets the stderr of the container to a file at `path`, like
+    /// [`ContainerBuilder::with_stdout_file`] does for the stdout
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::stdio_file::RotationPolicy;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_stderr_file("/var/log/web.err", RotationPolicy::default())
+    /// .expect("invalid stderr file");
+    /// ```
+    pub fn with_stderr_file<P: Into<PathBuf>>(
+        mut self,
+        path: P,
+        rotation: RotationPolicy,
+    ) -> Result<Self, LibcontainerError> {
+        self.stderr = Some(self.open_stdio_file(path.into(), rotation)?);
+        Ok(self)
+    }
+
+    fn open_stdio_file(
+        &mut self,
+        path: PathBuf,
+        rotation: RotationPolicy,
+    ) -> Result<OwnedFd, LibcontainerError> {
+        let file = StdioFile::new(path, rotation);
+        let fd = file.open().map_err(|err| {
+            tracing::error!(path = ?file.path, ?err, "failed to open stdio file");
+            LibcontainerError::InvalidInput(format!("invalid stdio file {:?}: {err:?}", file.path))
+        })?;
+        // The same file may serve both stdout and stderr, it is rotated once.
+        if !self.stdio_files.iter().any(|known| known.path == file.path) {
+            self.stdio_files.push(file);
+        }
+
+        Ok(fd.into())
+    }
+}
+
+/// Checks the container id against the rules described in
+/// [`ContainerBuilder::validate_id`].
+pub(super) fn validate_container_id(
+    container_id: &str,
+    max_len: usize,
+) -> Result<(), ErrInvalidID> {
+    let result = check_container_id(container_id, max_len);
+    if let Err(err) = &result {
+        tracing::error!(?container_id, %err, "invalid container id");
+    }
+    result
+}
+
+fn check_container_id(container_id: &str, max_len: usize) -> Result<(), ErrInvalidID> {
+    if container_id.is_empty() {
+        return Err(ErrInvalidID::Empty);
+    }
+
+    if container_id.len() > max_len {
+        return Err(ErrInvalidID::TooLong {
+            len: container_id.len(),
+            max: max_len,
+        });
+    }
+
+    if container_id == "." || container_id == ".." {
+        return Err(ErrInvalidID::FileName);
+    }
+
+    for c in container_id.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '+' | '-' | '.' => (),
+            _ => return Err(ErrInvalidID::InvalidChars(c)),
+        }
+    }
+
+    match container_id.chars().next() {
+        Some(c @ '-') => Err(ErrInvalidID::InvalidLeadingChar(c)),
+        _ => Ok(()),
+    }
 }
 
 #[cfg(test)]
@@ -346,7 +761,9 @@ mod tests {
     use anyhow::{Context, Result};
     use nix::unistd::pipe;
 
-    use crate::container::builder::ContainerBuilder;
+    use crate::container::builder::{validate_container_id, ContainerBuilder, DEFAULT_MAX_ID_LEN};
+    use crate::error::ErrInvalidID;
+    use crate::stdio_file::{RotationPolicy, StdioFile};
     use crate::syscall::syscall::SyscallType;
 
     #[test]
@@ -416,6 +833,82 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_validate_id_max_len() {
+        let result = ContainerBuilder::new("a".repeat(65), SyscallType::default())
+            .with_max_id_len(64)
+            .validate_id();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_validate_container_id_reasons() {
+        let max = DEFAULT_MAX_ID_LEN;
+        let cases = [
+            ("", Some(ErrInvalidID::Empty)),
+            (".", Some(ErrInvalidID::FileName)),
+            ("..", Some(ErrInvalidID::FileName)),
+            ("../sibling", Some(ErrInvalidID::InvalidChars('/'))),
+            ("a/b", Some(ErrInvalidID::InvalidChars('/'))),
+            ("new\nline", Some(ErrInvalidID::InvalidChars('\n'))),
+            ("-rf", Some(ErrInvalidID::InvalidLeadingChar('-'))),
+            ("a+b", None),
+            (".hidden", None),
+            ("a-b.c_D9", None),
+        ];
+        for (id, expected) in cases {
+            let result = validate_container_id(id, max);
+            match expected {
+                None => assert!(result.is_ok(), "{id:?}"),
+                Some(expected) => assert_eq!(
+                    result.unwrap_err().to_string(),
+                    expected.to_string(),
+                    "{id:?}"
+                ),
+            }
+        }
+
+        assert!(validate_container_id(&"a".repeat(max), max).is_ok());
+        assert!(matches!(
+            validate_container_id(&"a".repeat(max + 1), max),
+            Err(ErrInvalidID::TooLong { len, max: m }) if len == max + 1 && m == max
+        ));
+    }
+
+    #[test]
+    fn test_validate_container_id_pathological() {
+        let alphabet: Vec<char> = "aZ09._-/\\\n\t \0+:@%ü\u{202e}".chars().collect();
+        let mut rng = fastrand::Rng::with_seed(0x5eed);
+        for _ in 0..20_000 {
+            let len = rng.usize(0..=DEFAULT_MAX_ID_LEN + 8);
+            let id: String = (0..len)
+                .map(|_| alphabet[rng.usize(..alphabet.len())])
+                .collect();
+
+            let expected = !id.is_empty()
+                && id.len() <= DEFAULT_MAX_ID_LEN
+                && id != "."
+                && id != ".."
+                && !id.starts_with('-')
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.'));
+            let valid = validate_container_id(&id, DEFAULT_MAX_ID_LEN).is_ok();
+            assert_eq!(valid, expected, "{id:?}");
+
+            if valid {
+                // A valid id is always a single plain component when joined
+                // to the root path, so it can't escape its state directory.
+                let mut components = std::path::Path::new(&id).components();
+                assert!(matches!(
+                    components.next(),
+                    Some(std::path::Component::Normal(_))
+                ));
+                assert!(components.next().is_none());
+            }
+        }
+    }
+
     #[test]
     fn test_stdios() -> Result<()> {
         let (r, _w) = pipe()?;
@@ -446,4 +939,26 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_stdio_files() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let rotation = RotationPolicy::default();
+        let builder = ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+            .with_stdout_file(tmp.path().join("out"), rotation)?
+            .with_stderr_file(tmp.path().join("out"), rotation)?;
+        assert!(builder.stdout.is_some());
+        assert!(builder.stderr.is_some());
+        assert_eq!(
+            builder.stdio_files,
+            vec![StdioFile::new(tmp.path().join("out"), rotation)]
+        );
+
+        assert!(
+            ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+                .with_stdout_file(tmp.path().join("missing/out"), rotation)
+                .is_err()
+        );
+        Ok(())
+    }
 }
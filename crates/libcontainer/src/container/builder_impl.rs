@@ -1,23 +1,38 @@
 use std::fs;
-use std::io::Write;
-use std::os::fd::{AsRawFd, OwnedFd};
-use std::path::PathBuf;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use libcgroups::common::CgroupManager;
+use libcgroups::common::{CgroupSetup, CpusetPartition};
+use nix::sched::CloneFlags;
 use nix::unistd::Pid;
-use oci_spec::runtime::Spec;
+use oci_spec::runtime::{Linux, Spec};
+use procfs::process::Process;
 
-use super::{Container, ContainerStatus};
-use crate::error::{CreateContainerError, LibcontainerError, MissingSpecError};
+use super::cleanup::{run_cleanup, CleanupError, ContainerCleanup};
+use super::init_builder::HostnamePolicy;
+use super::{Container, ContainerStatus, PhaseTimings, Rusage, State};
+use crate::core_sched::CoreSched;
+use crate::create_signals::{self, CreateSignalPolicy};
+use crate::error::{LibcontainerError, MissingSpecError};
+use crate::fault_injection::{FaultInjection, FaultPoint};
+use crate::hooks::HookStage;
+use crate::namespaces::Namespaces;
 use crate::notify_socket::NotifyListener;
 use crate::process::args::{ContainerArgs, ContainerType};
-use crate::process::intel_rdt::delete_resctrl_subdirectory;
+use crate::process::channel::ChannelError;
+use crate::process::container_main_process::{MainProcessResult, ProcessError};
+#[cfg(feature = "libseccomp")]
+use crate::process::seccomp_listener::SeccompListenerError;
 use crate::process::{self};
+use crate::rootfs::{FsType, MountOrder};
 use crate::syscall::syscall::SyscallType;
+use crate::syscall::{Syscall, SyscallError};
+use crate::user::ResolvedUser;
 use crate::user_ns::UserNamespaceConfig;
 use crate::workload::Executor;
-use crate::{hooks, utils};
+use crate::{create_limit, hooks, utils};
 
 pub(super) struct ContainerBuilderImpl {
     /// Flag indicating if an init or a tenant container should be created
@@ -37,14 +52,57 @@ pub(super) struct ContainerBuilderImpl {
     pub pid_file: Option<PathBuf>,
     /// Socket to communicate the file descriptor of the ptty
     pub console_socket: Option<OwnedFd>,
+    /// How long sending the pty master over the console socket is retried
+    pub console_socket_timeout: Duration,
+    /// How long the intermediate process may take to report
+    pub intermediate_timeout: Duration,
     /// Options for new user namespace
     pub user_ns_config: Option<UserNamespaceConfig>,
+    /// Partition mode of the container's cpuset
+    pub cpuset_partition: Option<CpusetPartition>,
+    /// If the cgroup filesystem is mounted read-only in the container
+    pub cgroup_mount_readonly: bool,
+    /// If the cgroup of the container is delegated to its root user
+    pub delegate_cgroup: bool,
+    /// Whether the hostname is set when joining an existing uts namespace
+    pub hostname_policy: HostnamePolicy,
+    /// If the hostname is set without a new uts namespace, i.e. on the host
+    pub hostname_without_uts: bool,
+    /// When the spec mounts are applied relative to pivot_root
+    pub mount_order: MountOrder,
+    /// If the loginuid of the container process is reset to unset
+    pub reset_loginuid: bool,
+    /// If /proc/sys is remounted read-only in the container
+    pub proc_sys_readonly: bool,
+    /// If an empty bind mount destination of the wrong type is replaced
+    pub fix_mount_target_type: bool,
+    /// If the /etc/mtab symlink is created when the image lacks it
+    pub mtab_symlink: bool,
+    /// If the default devices missing from the rootfs are created
+    pub ensure_default_devices: bool,
+    /// Bytes the rootfs setup may write into the rootfs
+    pub rootfs_write_limit: Option<u64>,
+    /// Size of the tmpfs the writable layer of the rootfs is backed with
+    pub rootfs_max_size: Option<u64>,
+    /// Uid and gid the container process runs as instead of the spec user
+    pub run_as_user: Option<(u32, u32)>,
+    /// If the username of the spec is resolved even if it gives ids
+    pub resolve_username: bool,
+    /// If the init process starts a new session
+    pub new_session: bool,
+    /// File the exit status of a detached init process is written to
+    pub exit_status_file: Option<PathBuf>,
     /// Path to the Unix Domain Socket to communicate container start
     pub notify_path: PathBuf,
     /// Container state
     pub container: Option<Container>,
+    /// State of the existing container a tenant process joins
+    pub joined_container_state: Option<State>,
     /// File descriptos preserved/passed to the container init process.
     pub preserve_fds: i32,
+    /// Listening sockets handed off to the container process, with the fd
+    /// each is passed as
+    pub socket_fds: Vec<(RawFd, RawFd)>,
     /// If the container is to be run in detached mode
     pub detached: bool,
     /// Default executes the specified execution of a generic command
@@ -59,38 +117,108 @@ pub(super) struct ContainerBuilderImpl {
     pub stderr: Option<OwnedFd>,
     // Indicate if the init process should be a sibling of the main process.
     pub as_sibling: bool,
+    /// If EPERM from making the processes non-dumpable is tolerated
+    pub tolerate_dumpable_eperm: bool,
+    /// How long the seccomp listener has to take the seccomp notify fd
+    pub seccomp_notify_timeout: Duration,
+    /// PATH the executable is resolved with if the env has none
+    pub default_path: Option<String>,
+    /// If the default PATH is added to the env of the process
+    pub inject_default_path: bool,
+    /// argv[0] the executable is run with instead of the first arg
+    pub argv0_override: Option<String>,
+    /// How long the create hooks may take from the start of the create
+    pub create_timeout: Option<Duration>,
+    /// Core scheduling cookie the init process gets
+    pub core_sched: Option<CoreSched>,
+    /// What a termination signal received during the create does
+    pub create_signal_policy: CreateSignalPolicy,
+    /// Cgroups in named v1 hierarchies the container is attached to
+    pub extra_cgroup_hierarchies: Vec<String>,
+    /// If the processes live in a leaf below the cgroup of the container
+    pub nested_cgroup_delegation: bool,
+    /// Cgroup setup the cgroup managers are created with, `None` to detect
+    /// the setup of the host
+    pub cgroup_setup: Option<CgroupSetup>,
+    /// Faults injected into the create, for testing
+    pub fault_injection: FaultInjection,
+}
+
+/// Outcome of a successful container creation
+#[derive(Debug)]
+pub(super) struct ContainerCreated {
+    /// Pid of the container init process
+    pub init_pid: Pid,
+    /// Exact contents written to the pid file, if one was requested
+    pub pid_file_contents: Option<String>,
+    /// Time spent in each phase of the create
+    pub timings: PhaseTimings,
+    /// Resource usage of the intermediate process
+    pub child_rusage: Option<Rusage>,
+    /// Clone flags of the namespaces created for the container
+    pub namespace_flags: CloneFlags,
+    /// Bytes the rootfs setup wrote into the rootfs
+    pub rootfs_written: Option<u64>,
+    /// Type of the filesystem backing the rootfs
+    pub rootfs_fs_type: Option<FsType>,
+    /// Ids the process of the container runs with
+    pub user: Option<ResolvedUser>,
+    /// pidfd of a sibling init process
+    pub init_pidfd: Option<OwnedFd>,
 }
 
 impl ContainerBuilderImpl {
-    pub(super) fn create(&mut self) -> Result<Pid, LibcontainerError> {
-        match self.run_container() {
-            Ok(pid) => Ok(pid),
-            Err(outer) => {
+    pub(super) fn create(&mut self) -> Result<ContainerCreated, LibcontainerError> {
+        create_signals::run_create(
+            self.create_signal_policy,
+            self,
+            Self::run_container,
+            |this| {
                 // Only the init container should be cleaned up in the case of
                 // an error.
-                let cleanup_err = if self.is_init_container() {
-                    self.cleanup_container().err()
+                if this.is_init_container() {
+                    this.cleanup_container().err()
                 } else {
                     None
-                };
-
-                Err(CreateContainerError::new(outer, cleanup_err).into())
-            }
-        }
+                }
+            },
+        )
     }
 
     fn is_init_container(&self) -> bool {
         matches!(self.container_type, ContainerType::InitContainer)
     }
 
-    fn run_container(&mut self) -> Result<Pid, LibcontainerError> {
+    fn creates_user_ns(&self) -> bool {
+        self.user_ns_config
+            .as_ref()
+            .map_or(false, |config| !config.joins_existing())
+    }
+
+    /// If the container is rootless, it has a user namespace and the runtime
+    /// isn't real root
+    fn is_rootless(&self) -> bool {
+        self.user_ns_config
+            .as_ref()
+            .map_or(false, |config| config.is_rootless())
+    }
+
+    fn run_container(&mut self) -> Result<ContainerCreated, LibcontainerError> {
+        let start = Instant::now();
+        let create_deadline = self.create_timeout.map(|timeout| start + timeout);
         let linux = self.spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
+        // The container processes unshare the namespaces from the same spec.
+        let namespace_flags =
+            Namespaces::try_from(linux.namespaces().as_ref())?.new_namespace_flags();
         let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.container_id);
-        let cgroup_config = libcgroups::common::CgroupConfig {
-            cgroup_path: cgroups_path,
-            systemd_cgroup: self.use_systemd || self.user_ns_config.is_some(),
-            container_name: self.container_id.to_owned(),
-        };
+        let cgroup_config = libcgroups::common::CgroupConfig::new(
+            cgroups_path,
+            self.use_systemd || self.creates_user_ns(),
+            self.container_id.to_owned(),
+        )
+        .with_extra_hierarchies(self.extra_cgroup_hierarchies.clone())
+        .with_nested_delegation(self.nested_cgroup_delegation)
+        .with_setup(self.cgroup_setup);
         let process = self
             .spec
             .process()
@@ -103,30 +231,110 @@ impl ContainerBuilderImpl {
         // namespace. We also need to create to socket before entering into the
         // user namespace in the case that the path is located in paths only
         // root can access.
+        let notify_socket_setup_start = Instant::now();
         let notify_listener = NotifyListener::new(&self.notify_path)?;
+        let notify_socket_setup = notify_socket_setup_start.elapsed();
 
-        // If Out-of-memory score adjustment is set in specification.  set the score
-        // value for the current process check
-        // https://dev.to/rrampage/surviving-the-linux-oom-killer-2ki9 for some more
-        // information.
-        //
-        // This has to be done before !dumpable because /proc/self/oom_score_adj
-        // is not writeable unless you're an privileged user (if !dumpable is
-        // set). All children inherit their parent's oom_score_adj value on
-        // fork(2) so this will always be propagated properly.
-        if let Some(oom_score_adj) = process.oom_score_adj() {
-            tracing::debug!("Set OOM score to {}", oom_score_adj);
-            let mut f = fs::File::create("/proc/self/oom_score_adj").map_err(|err| {
-                tracing::error!("failed to open /proc/self/oom_score_adj: {}", err);
-                LibcontainerError::OtherIO(err)
-            })?;
-            f.write_all(oom_score_adj.to_string().as_bytes())
-                .map_err(|err| {
-                    tracing::error!("failed to write to /proc/self/oom_score_adj: {}", err);
-                    LibcontainerError::OtherIO(err)
-                })?;
+        // If Out-of-memory score adjustment is set in specification, set the
+        // score for the current process, check
+        // https://dev.to/rrampage/surviving-the-linux-oom-killer-2ki9 for some
+        // more information. All children inherit their parent's oom_score_adj
+        // value on fork(2) so this will always be propagated properly.
+        let syscall = self.syscall.create_syscall();
+        let nested_cgroup_config = (self.nested_cgroup_delegation
+            && matches!(self.container_type, ContainerType::InitContainer))
+        .then(|| cgroup_config.clone());
+        let mut main_result = with_oom_score_adj(&*syscall, process.oom_score_adj(), || {
+            self.run_main_process(linux, notify_listener, cgroup_config, create_deadline)
+        })?;
+        let init_pid = main_result.init_pid;
+        self.fault_injection.inject(FaultPoint::PostClone)?;
+
+        // The init process is ready, so its cgroup namespace was created with
+        // the cgroup of the container as root. Splitting the cgroup only now
+        // keeps the leaf and the cgroups of the workload visible in it.
+        if let Some(cgroup_config) = nested_cgroup_config {
+            libcgroups::common::create_cgroup_manager(cgroup_config)?.delegate_nested()?;
         }
 
+        // if file to write the pid to is specified, write pid of the child
+        let pid_file_contents = match &self.pid_file {
+            Some(pid_file) => Some(write_pid_file(pid_file, init_pid)?),
+            None => None,
+        };
+
+        let rootless = self.is_rootless();
+        if let Some(container) = &mut self.container {
+            let init_start_time = Process::new(init_pid.as_raw())
+                .and_then(|process| process.stat())
+                .map(|stat| stat.starttime)
+                .map_err(|err| tracing::warn!(?err, "failed to read the start time of init"))
+                .ok();
+            // Recorded as the cgroup manager placed the cgroup, so the state
+            // stays right even if the detection changes later.
+            let (cgroup_path, systemd_unit) = match main_result.cgroup_location.take() {
+                Some(location) => (Some(location.path), location.systemd_unit),
+                None => (None, None),
+            };
+            // update status and pid of the container process
+            container
+                .set_status(ContainerStatus::Created)
+                .set_creator(nix::unistd::geteuid().as_raw())
+                .set_pid(init_pid.as_raw())
+                .set_clean_up_intel_rdt_directory(
+                    main_result.need_to_clean_up_intel_rdt_subdirectory,
+                )
+                .set_exit_waiter_pid(main_result.exit_waiter_pid.map(|pid| pid.as_raw()))
+                .set_init_start_time(init_start_time)
+                .set_cgroup_location(cgroup_path, systemd_unit)
+                .set_rootless(rootless)
+                .save()?;
+        }
+
+        self.fault_injection.inject(FaultPoint::PreHooks)?;
+        let hooks_start = Instant::now();
+        if matches!(self.container_type, ContainerType::InitContainer) {
+            if let Some(hooks) = self.spec.hooks() {
+                hooks::run_hooks_with_deadline(
+                    HookStage::CreateRuntime,
+                    hooks.create_runtime().as_ref(),
+                    self.container.as_ref(),
+                    None,
+                    create_deadline,
+                )?
+            }
+        }
+
+        let hooks = hooks_start.elapsed();
+
+        Ok(ContainerCreated {
+            init_pid,
+            pid_file_contents,
+            timings: PhaseTimings {
+                notify_socket_setup,
+                clone: main_result.clone,
+                cgroup_apply: main_result.cgroup_apply,
+                rootfs_prepare: main_result.rootfs_prepare,
+                hooks,
+                total: start.elapsed(),
+            },
+            child_rusage: main_result.intermediate_rusage,
+            namespace_flags,
+            rootfs_written: main_result.rootfs_written,
+            rootfs_fs_type: main_result.rootfs_fs_type,
+            user: main_result.user,
+            init_pidfd: main_result.init_pidfd,
+        })
+    }
+
+    /// Forks the intermediate and init process of the container
+    fn run_main_process(
+        &self,
+        linux: &Linux,
+        notify_listener: NotifyListener,
+        cgroup_config: libcgroups::common::CgroupConfig,
+        create_deadline: Option<Instant>,
+    ) -> Result<MainProcessResult, LibcontainerError> {
         // Make the process non-dumpable, to avoid various race conditions that
         // could cause processes in namespaces we're joining to access host
         // resources (or potentially execute code).
@@ -136,12 +344,8 @@ impl ContainerBuilderImpl {
         // ourselves to be non-dumpable only breaks things (like rootless
         // containers), which is the recommendation from the kernel folks.
         if linux.namespaces().is_some() {
-            prctl::set_dumpable(false).map_err(|e| {
-                LibcontainerError::Other(format!(
-                    "error in setting dumpable to false : {}",
-                    nix::errno::Errno::from_raw(e)
-                ))
-            })?;
+            let syscall = self.syscall.create_syscall();
+            set_non_dumpable(&*syscall, self.tolerate_dumpable_eperm)?;
         }
 
         // This container_args will be passed to the container processes,
@@ -153,98 +357,255 @@ impl ContainerBuilderImpl {
             spec: Rc::clone(&self.spec),
             rootfs: self.rootfs.to_owned(),
             console_socket: self.console_socket.as_ref().map(|c| c.as_raw_fd()),
+            console_socket_timeout: self.console_socket_timeout,
+            intermediate_timeout: self.intermediate_timeout,
             notify_listener,
             preserve_fds: self.preserve_fds,
+            socket_fds: self.socket_fds.clone(),
             container: self.container.to_owned(),
+            joined_container_state: self.joined_container_state.to_owned(),
             user_ns_config: self.user_ns_config.to_owned(),
             cgroup_config,
+            cpuset_partition: self.cpuset_partition,
+            cgroup_mount_readonly: self.cgroup_mount_readonly,
+            delegate_cgroup: self.delegate_cgroup,
+            hostname_policy: self.hostname_policy,
+            hostname_without_uts: self.hostname_without_uts,
+            mount_order: self.mount_order,
+            reset_loginuid: self.reset_loginuid,
+            proc_sys_readonly: self.proc_sys_readonly,
+            fix_mount_target_type: self.fix_mount_target_type,
+            mtab_symlink: self.mtab_symlink,
+            ensure_default_devices: self.ensure_default_devices,
+            rootfs_write_limit: self.rootfs_write_limit,
+            rootfs_max_size: self.rootfs_max_size,
+            run_as_user: self.run_as_user,
+            resolve_username: self.resolve_username,
+            new_session: self.new_session,
             detached: self.detached,
+            exit_status_file: self.exit_status_file.clone(),
             executor: self.executor.clone(),
             no_pivot: self.no_pivot,
             stdin: self.stdin.as_ref().map(|x| x.as_raw_fd()),
             stdout: self.stdout.as_ref().map(|x| x.as_raw_fd()),
             stderr: self.stderr.as_ref().map(|x| x.as_raw_fd()),
             as_sibling: self.as_sibling,
+            tolerate_dumpable_eperm: self.tolerate_dumpable_eperm,
+            seccomp_notify_timeout: self.seccomp_notify_timeout,
+            default_path: self.default_path.clone(),
+            inject_default_path: self.inject_default_path,
+            argv0_override: self.argv0_override.clone(),
+            create_timeout_left: create_deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now())),
+            core_sched: self.core_sched,
+            fault_injection: self.fault_injection.clone(),
         };
 
-        let (init_pid, need_to_clean_up_intel_rdt_dir) =
-            process::container_main_process::container_main_process(&container_args).map_err(
-                |err| {
-                    tracing::error!("failed to run container process {}", err);
-                    LibcontainerError::MainProcess(err)
-                },
-            )?;
-
-        // if file to write the pid to is specified, write pid of the child
-        if let Some(pid_file) = &self.pid_file {
-            fs::write(pid_file, format!("{init_pid}")).map_err(|err| {
-                tracing::error!("failed to write pid to file: {}", err);
-                LibcontainerError::OtherIO(err)
+        // The cgroup, namespace and mount setup of the container processes is
+        // the most contention-prone part of a create. If a create concurrency
+        // limit is configured, only a bounded number of creates in this process
+        // run it at the same time.
+        let create_permit = create_limit::acquire();
+        self.fault_injection.inject(FaultPoint::PreClone)?;
+        let main_result = process::container_main_process::container_main_process(&container_args)
+            .map_err(|err| {
+                tracing::error!("failed to run container process {}", err);
+                match err {
+                    ProcessError::Channel(ChannelError::NamespaceCreateFailed {
+                        namespace,
+                        errno,
+                    }) => LibcontainerError::NamespaceCreateFailed { namespace, errno },
+                    #[cfg(feature = "libseccomp")]
+                    ProcessError::SeccompListener(SeccompListenerError::Timeout {
+                        timeout,
+                        ..
+                    }) => LibcontainerError::SeccompNotifyTimeout { timeout },
+                    err => LibcontainerError::MainProcess(err),
+                }
             })?;
-        }
+        drop(create_permit);
 
-        if let Some(container) = &mut self.container {
-            // update status and pid of the container process
-            container
-                .set_status(ContainerStatus::Created)
-                .set_creator(nix::unistd::geteuid().as_raw())
-                .set_pid(init_pid.as_raw())
-                .set_clean_up_intel_rdt_directory(need_to_clean_up_intel_rdt_dir)
-                .save()?;
-        }
+        Ok(main_result)
+    }
 
-        if matches!(self.container_type, ContainerType::InitContainer) {
-            if let Some(hooks) = self.spec.hooks() {
-                hooks::run_hooks(
-                    hooks.create_runtime().as_ref(),
-                    self.container.as_ref(),
-                    None,
-                )?
-            }
+    /// Releases the resources of the failed create, see
+    /// [`cleanup`](super::cleanup) for the order of the steps
+    fn cleanup_container(&self) -> Result<(), LibcontainerError> {
+        let container = match &self.container {
+            Some(container) => container,
+            None => return Ok(()),
+        };
+        let linux = self.spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
+        let cgroup_config = libcgroups::common::CgroupConfig::new(
+            utils::get_cgroup_path(linux.cgroups_path(), &self.container_id),
+            self.use_systemd || self.creates_user_ns(),
+            self.container_id.to_string(),
+        )
+        .with_extra_hierarchies(self.extra_cgroup_hierarchies.clone())
+        .with_nested_delegation(self.nested_cgroup_delegation)
+        .with_setup(self.cgroup_setup);
+
+        let report = run_cleanup(&ContainerCleanup {
+            container,
+            cgroup_config: Some(cgroup_config),
+        });
+        if !report.is_clean() {
+            return Err(CleanupError(report).into());
         }
 
-        Ok(init_pid)
+        Ok(())
     }
+}
 
-    fn cleanup_container(&self) -> Result<(), LibcontainerError> {
-        let linux = self.spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
-        let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.container_id);
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: cgroups_path,
-                systemd_cgroup: self.use_systemd || self.user_ns_config.is_some(),
-                container_name: self.container_id.to_string(),
-            })?;
+/// Runs `f` with the oom_score_adj of the current process set to `score`, so
+/// the container processes forked by `f` inherit it. The previous score is
+/// restored afterwards, also if `f` fails, so a long-lived process embedding
+/// libcontainer doesn't keep the score of the last container it created.
+fn with_oom_score_adj<T>(
+    syscall: &dyn Syscall,
+    score: Option<i32>,
+    f: impl FnOnce() -> Result<T, LibcontainerError>,
+) -> Result<T, LibcontainerError> {
+    let score = match score {
+        Some(score) => score,
+        None => return f(),
+    };
 
-        let mut errors = Vec::new();
+    // This has to be done before !dumpable because /proc/self/oom_score_adj
+    // is not writeable unless you're an privileged user (if !dumpable is
+    // set).
+    let previous = syscall.get_oom_score_adj().map_err(|err| {
+        tracing::error!("failed to read /proc/self/oom_score_adj: {}", err);
+        LibcontainerError::Other(format!("failed to read oom_score_adj: {err}"))
+    })?;
+    tracing::debug!("Set OOM score to {}", score);
+    syscall.set_oom_score_adj(score).map_err(|err| {
+        tracing::error!("failed to write to /proc/self/oom_score_adj: {}", err);
+        LibcontainerError::Other(format!("failed to set oom_score_adj: {err}"))
+    })?;
 
-        if let Err(e) = cmanager.remove() {
-            tracing::error!(error = ?e, "failed to remove cgroup manager");
-            errors.push(e.to_string());
+    let result = f();
+    // Restoring never fails the create, the container doesn't depend on it.
+    match syscall.set_oom_score_adj(previous) {
+        Ok(()) => {}
+        // Without CAP_SYS_RESOURCE, e.g. rootless, the score can't be lowered
+        // below the lowest one a privileged process set. The runtime keeps
+        // the score of the container then, as before there was a restore.
+        Err(SyscallError::IO(err)) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+            tracing::debug!(?err, previous, "not permitted to restore oom_score_adj");
         }
+        Err(err) => tracing::warn!(?err, previous, "failed to restore oom_score_adj"),
+    }
 
-        if let Some(container) = &self.container {
-            if let Some(true) = container.clean_up_intel_rdt_subdirectory() {
-                if let Err(e) = delete_resctrl_subdirectory(container.id()) {
-                    tracing::error!(id = ?container.id(), error = ?e, "failed to delete resctrl subdirectory");
-                    errors.push(e.to_string());
-                }
-            }
+    result
+}
 
-            if container.root.exists() {
-                if let Err(e) = fs::remove_dir_all(&container.root) {
-                    tracing::error!(container_root = ?container.root, error = ?e, "failed to delete container root");
-                    errors.push(e.to_string());
-                }
-            }
+fn set_non_dumpable(syscall: &dyn Syscall, tolerate_eperm: bool) -> Result<(), LibcontainerError> {
+    utils::set_dumpable(syscall, false, tolerate_eperm).map_err(|err| {
+        tracing::error!(?err, "failed to set the process non-dumpable");
+        match err {
+            SyscallError::Nix(nix::errno::Errno::EPERM) => LibcontainerError::DumpableNotPermitted,
+            err => LibcontainerError::Other(format!("error in setting dumpable to false : {err}")),
         }
+    })
+}
 
-        if !errors.is_empty() {
-            return Err(LibcontainerError::Other(format!(
-                "failed to cleanup container: {}",
-                errors.join(";")
-            )));
-        }
+/// Writes the pid of the container init process to the pid file and returns
+/// the exact contents that were written.
+pub(super) fn write_pid_file(pid_file: &Path, init_pid: Pid) -> Result<String, LibcontainerError> {
+    let contents = format!("{init_pid}");
+    fs::write(pid_file, &contents).map_err(|err| {
+        tracing::error!("failed to write pid to file: {}", err);
+        LibcontainerError::OtherIO(err)
+    })?;
+
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::syscall::test::{ArgName, TestHelperSyscall};
+
+    #[test]
+    fn test_set_non_dumpable_eperm() -> Result<()> {
+        // EPERM fails the create unless it is tolerated
+        let syscall = TestHelperSyscall::default();
+        syscall.set_ret_err(ArgName::Dumpable, || {
+            Err(SyscallError::Nix(nix::errno::Errno::EPERM))
+        });
+        assert!(matches!(
+            set_non_dumpable(&syscall, false),
+            Err(LibcontainerError::DumpableNotPermitted)
+        ));
+
+        // tolerated, the process is left dumpable
+        let syscall = TestHelperSyscall::default();
+        syscall.set_ret_err(ArgName::Dumpable, || {
+            Err(SyscallError::Nix(nix::errno::Errno::EPERM))
+        });
+        set_non_dumpable(&syscall, true)?;
+        assert!(syscall.get_dumpable_args().is_empty());
+
+        // other errors are never tolerated
+        let syscall = TestHelperSyscall::default();
+        syscall.set_ret_err(ArgName::Dumpable, || {
+            Err(SyscallError::Nix(nix::errno::Errno::EINVAL))
+        });
+        assert!(matches!(
+            set_non_dumpable(&syscall, true),
+            Err(LibcontainerError::Other(_))
+        ));
+
+        let syscall = TestHelperSyscall::default();
+        set_non_dumpable(&syscall, false)?;
+        assert_eq!(syscall.get_dumpable_args(), vec![false]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oom_score_adj_restored() -> Result<()> {
+        let syscall = TestHelperSyscall::default();
+        syscall.set_initial_oom_score_adj(100);
+
+        let inherited = with_oom_score_adj(&syscall, Some(-500), || {
+            Ok(syscall.get_oom_score_adj().unwrap())
+        })?;
+        assert_eq!(inherited, -500);
+        assert_eq!(syscall.get_oom_score_adj_args(), vec![-500, 100]);
+        assert_eq!(syscall.get_oom_score_adj()?, 100);
+
+        // also when forking the container processes fails
+        let syscall = TestHelperSyscall::default();
+        syscall.set_initial_oom_score_adj(100);
+        let result: Result<(), _> = with_oom_score_adj(&syscall, Some(1000), || {
+            Err(LibcontainerError::Other("failed to fork".to_owned()))
+        });
+        assert!(result.is_err());
+        assert_eq!(syscall.get_oom_score_adj_args(), vec![1000, 100]);
+        assert_eq!(syscall.get_oom_score_adj()?, 100);
+
+        // a restore that isn't permitted, e.g. rootless, doesn't fail the
+        // create
+        let syscall = TestHelperSyscall::default();
+        syscall.set_initial_oom_score_adj(-100);
+        with_oom_score_adj(&syscall, Some(500), || {
+            syscall.set_ret_err(ArgName::OomScoreAdj, || {
+                Err(SyscallError::IO(
+                    std::io::ErrorKind::PermissionDenied.into(),
+                ))
+            });
+            Ok(())
+        })?;
+        assert_eq!(syscall.get_oom_score_adj()?, 500);
+
+        // and left alone without a score in the spec
+        let syscall = TestHelperSyscall::default();
+        with_oom_score_adj(&syscall, None, || Ok(()))?;
+        assert!(syscall.get_oom_score_adj_args().is_empty());
 
         Ok(())
     }
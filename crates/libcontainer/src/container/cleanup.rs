@@ -0,0 +1,464 @@
+//! Cleanup of the resources of a container that failed to be created
+//!
+//! The resources are released in [`CLEANUP_ORDER`]:
+//!
+//! 1. the processes of the container still known to the runtime are killed,
+//!    so nothing keeps the cgroup busy,
+//! 2. the cgroup is removed, which kills whatever is left in it,
+//! 3. the resctrl directory is removed, if the container created one,
+//! 4. sockets handed off to the container and the sockets and fifos in the
+//!    container directory are removed,
+//! 5. the container directory and the records of the container are removed.
+//!
+//! Every step treats a resource that is already gone as success, so a cleanup
+//! can be repeated after a partial failure. The container directory holds the
+//! state everything else is found with, so it is kept if an earlier step
+//! failed, and [`Container::cleanup`] can retry the cleanup later.
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+
+use libcgroups::common::{CgroupConfig, CgroupManager};
+use nix::sys::signal;
+use nix::unistd::Pid;
+
+use super::exec_session::EXECS_FILE;
+use super::status_probe::{ProbeCollector, ProcessProbe};
+use super::{Container, ContainerStatus, State};
+use crate::config::YoukiConfig;
+use crate::error::LibcontainerError;
+use crate::process::intel_rdt::{delete_resctrl_subdirectory, IntelRdtError};
+use crate::socket_handoff;
+
+/// A step of the cleanup of a container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CleanupStep {
+    /// Kill the init and exec'd processes
+    KillProcesses,
+    /// Remove the cgroup
+    Cgroup,
+    /// Remove the resctrl directory
+    Resctrl,
+    /// Remove handed off sockets and the sockets and fifos of the container
+    /// directory
+    Sockets,
+    /// Remove the container directory and the records of the container
+    StateDir,
+}
+
+/// The order the steps of a cleanup run in
+pub const CLEANUP_ORDER: [CleanupStep; 5] = [
+    CleanupStep::KillProcesses,
+    CleanupStep::Cgroup,
+    CleanupStep::Resctrl,
+    CleanupStep::Sockets,
+    CleanupStep::StateDir,
+];
+
+impl fmt::Display for CleanupStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::KillProcesses => "kill processes",
+            Self::Cgroup => "cgroup",
+            Self::Resctrl => "resctrl",
+            Self::Sockets => "sockets",
+            Self::StateDir => "state dir",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Outcome of a step of the cleanup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The resource was released
+    Done,
+    /// The resource was already released, e.g. by an earlier cleanup
+    AlreadyClean,
+    /// The container never had the resource
+    NotNeeded,
+    /// The container directory was kept because an earlier step failed
+    Kept,
+    Failed(String),
+}
+
+impl StepOutcome {
+    fn is_clean(&self) -> bool {
+        matches!(self, Self::Done | Self::AlreadyClean | Self::NotNeeded)
+    }
+}
+
+/// Outcome of each step of a cleanup, in the order the steps ran
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    steps: Vec<(CleanupStep, StepOutcome)>,
+}
+
+impl CleanupReport {
+    pub fn steps(&self) -> &[(CleanupStep, StepOutcome)] {
+        &self.steps
+    }
+
+    pub fn outcome(&self, step: CleanupStep) -> Option<&StepOutcome> {
+        self.steps
+            .iter()
+            .find(|(s, _)| *s == step)
+            .map(|(_, outcome)| outcome)
+    }
+
+    /// If every resource of the container is released
+    pub fn is_clean(&self) -> bool {
+        self.steps.iter().all(|(_, outcome)| outcome.is_clean())
+    }
+}
+
+impl fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (step, outcome) in &self.steps {
+            let message = match outcome {
+                StepOutcome::Failed(err) => err.as_str(),
+                StepOutcome::Kept => "kept for a later cleanup",
+                _ => continue,
+            };
+            if !first {
+                f.write_str("; ")?;
+            }
+            first = false;
+            write!(f, "{step}: {message}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("failed to clean up container: {0}")]
+pub struct CleanupError(pub CleanupReport);
+
+/// Runs the steps of a cleanup
+pub(crate) trait CleanupSteps {
+    fn run(&self, step: CleanupStep) -> Result<StepOutcome, String>;
+}
+
+/// Runs the steps in [`CLEANUP_ORDER`]. A failed step doesn't stop the
+/// cleanup, except that the container directory is kept.
+pub(crate) fn run_cleanup(steps: &dyn CleanupSteps) -> CleanupReport {
+    let mut report = CleanupReport::default();
+    for step in CLEANUP_ORDER {
+        let outcome = if step == CleanupStep::StateDir && !report.is_clean() {
+            StepOutcome::Kept
+        } else {
+            steps.run(step).unwrap_or_else(|err| {
+                tracing::error!(%step, %err, "failed to clean up container");
+                StepOutcome::Failed(err)
+            })
+        };
+        tracing::debug!(%step, ?outcome, "container cleanup step");
+        report.steps.push((step, outcome));
+    }
+
+    report
+}
+
+/// The cleanup of the resources of a container
+pub(crate) struct ContainerCleanup<'a> {
+    pub container: &'a Container,
+    /// Cgroup of the container, `None` if it never got one
+    pub cgroup_config: Option<CgroupConfig>,
+}
+
+impl CleanupSteps for ContainerCleanup<'_> {
+    fn run(&self, step: CleanupStep) -> Result<StepOutcome, String> {
+        match step {
+            CleanupStep::KillProcesses => self.kill_processes(),
+            CleanupStep::Cgroup => self.remove_cgroup(),
+            CleanupStep::Resctrl => self.remove_resctrl(),
+            CleanupStep::Sockets => self.remove_sockets(),
+            CleanupStep::StateDir => self.remove_state_dir(),
+        }
+    }
+}
+
+impl ContainerCleanup<'_> {
+    fn kill_processes(&self) -> Result<StepOutcome, String> {
+        let container = self.container;
+        let mut pids = Vec::new();
+        if let Some(pid) = container.pid() {
+            pids.push((pid, container.init_start_time()));
+        }
+        if container.root.exists() {
+            let execs = container.execs().map_err(|err| err.to_string())?;
+            pids.extend(
+                execs
+                    .into_iter()
+                    .map(|exec| (Pid::from_raw(exec.pid), Some(exec.start_time))),
+            );
+        }
+        if pids.is_empty() {
+            return Ok(StepOutcome::NotNeeded);
+        }
+
+        container.kill_exit_waiter();
+        let mut outcome = StepOutcome::AlreadyClean;
+        for (pid, start_time) in pids {
+            if ProbeCollector::default().probe_process(Some(pid), start_time) != ProcessProbe::Alive
+            {
+                continue;
+            }
+            match signal::kill(pid, signal::SIGKILL) {
+                Ok(()) => outcome = StepOutcome::Done,
+                Err(nix::errno::Errno::ESRCH) => {}
+                Err(err) => return Err(format!("failed to kill {pid}: {err}")),
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    fn remove_cgroup(&self) -> Result<StepOutcome, String> {
+        let cgroup_config = match &self.cgroup_config {
+            Some(cgroup_config) => cgroup_config.clone(),
+            None => return Ok(StepOutcome::NotNeeded),
+        };
+        // The managers only remove a cgroup that exists.
+        let cmanager = libcgroups::common::create_cgroup_manager(cgroup_config)
+            .map_err(|err| err.to_string())?;
+        cmanager.remove().map_err(|err| err.to_string())?;
+        Ok(StepOutcome::Done)
+    }
+
+    fn remove_resctrl(&self) -> Result<StepOutcome, String> {
+        if self.container.clean_up_intel_rdt_subdirectory() != Some(true) {
+            return Ok(StepOutcome::NotNeeded);
+        }
+        match delete_resctrl_subdirectory(self.container.id()) {
+            Ok(()) => Ok(StepOutcome::Done),
+            Err(IntelRdtError::Canonicalize(err)) if err.kind() == ErrorKind::NotFound => {
+                Ok(StepOutcome::AlreadyClean)
+            }
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn remove_sockets(&self) -> Result<StepOutcome, String> {
+        let handoffs = socket_handoff::recorded(self.container.state.annotations.as_ref());
+        let mut outcome = match socket_handoff::remove_sockets(&handoffs) {
+            Ok(true) => StepOutcome::Done,
+            Ok(false) => StepOutcome::AlreadyClean,
+            Err(err) => return Err(format!("failed to remove handed off sockets: {err}")),
+        };
+
+        let mut paths = Vec::new();
+        match fs::read_dir(&self.container.root) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    let is_socket_or_fifo = entry.file_type().map_or(false, |file_type| {
+                        file_type.is_socket() || file_type.is_fifo()
+                    });
+                    if is_socket_or_fifo {
+                        paths.push(entry.path());
+                    }
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(format!("failed to list {:?}: {err}", self.container.root)),
+        }
+
+        for path in paths {
+            match fs::remove_file(&path) {
+                Ok(()) => outcome = StepOutcome::Done,
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(format!("failed to remove {path:?}: {err}")),
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    fn remove_state_dir(&self) -> Result<StepOutcome, String> {
+        let container = self.container;
+        // The directory may be gone already, there is nothing to lock then.
+        let lock = if container.root.exists() {
+            let lock = container
+                .state_store()
+                .lock(&container.root)
+                .map_err(|err| err.to_string())?;
+            Some(lock)
+        } else {
+            None
+        };
+        let outcome = match fs::remove_dir_all(&container.root) {
+            Ok(()) => StepOutcome::Done,
+            Err(err) if err.kind() == ErrorKind::NotFound => StepOutcome::AlreadyClean,
+            Err(err) => return Err(format!("failed to remove {:?}: {err}", container.root)),
+        };
+        // A store other than the files of the container directory keeps the
+        // records elsewhere.
+        for record in [State::STATE_FILE_PATH, EXECS_FILE] {
+            container
+                .state_store()
+                .delete(&container.root, record)
+                .map_err(|err| err.to_string())?;
+        }
+        drop(lock);
+        container.release_shared_volumes();
+
+        Ok(outcome)
+    }
+}
+
+impl Container {
+    /// Cleans up a container that was left behind by a failed create, e.g.
+    /// one stuck in the creating status. The processes of the container are
+    /// killed and its cgroup, resctrl directory, sockets and directory are
+    /// removed, see [`cleanup`](self) for the order. Resources that are
+    /// already gone are skipped, so the cleanup can be repeated until the
+    /// report is clean.
+    pub fn cleanup(&mut self) -> Result<CleanupReport, LibcontainerError> {
+        let _span = self.span().entered();
+        self.refresh_status()?;
+        if matches!(
+            self.status(),
+            ContainerStatus::Running | ContainerStatus::Paused
+        ) {
+            tracing::error!(id = ?self.id(), status = ?self.status(), "cleanup of a running container");
+            return Err(LibcontainerError::IncorrectStatus);
+        }
+
+        // Without the config, the container never got as far as creating its
+        // cgroup.
+        let cgroup_config = YoukiConfig::load(&self.root).ok().map(|config| {
+            CgroupConfig::new(config.cgroup_path, self.systemd(), self.id().to_owned())
+                .with_extra_hierarchies(config.extra_cgroup_hierarchies)
+                .with_nested_delegation(config.nested_cgroup_delegation)
+                .with_setup(self.cgroup_setup())
+        });
+        let report = run_cleanup(&ContainerCleanup {
+            container: self,
+            cgroup_config,
+        });
+        if !report.is_clean() {
+            return Err(CleanupError(report).into());
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashSet;
+    use std::os::unix::net::UnixListener;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::container::InMemoryStateStore;
+
+    /// Steps of which one fails the first time it runs
+    struct FlakySteps {
+        failing: CleanupStep,
+        failed: Cell<bool>,
+        released: RefCell<HashSet<CleanupStep>>,
+    }
+
+    impl CleanupSteps for FlakySteps {
+        fn run(&self, step: CleanupStep) -> Result<StepOutcome, String> {
+            if step == self.failing && !self.failed.replace(true) {
+                return Err("injected failure".to_owned());
+            }
+            if self.released.borrow_mut().insert(step) {
+                Ok(StepOutcome::Done)
+            } else {
+                Ok(StepOutcome::AlreadyClean)
+            }
+        }
+    }
+
+    #[test]
+    fn test_cleanup_retry_after_failed_step() {
+        for failing in CLEANUP_ORDER {
+            let steps = FlakySteps {
+                failing,
+                failed: Cell::new(false),
+                released: RefCell::new(HashSet::new()),
+            };
+
+            let report = run_cleanup(&steps);
+            assert!(!report.is_clean(), "{failing}");
+            assert_eq!(
+                report.outcome(failing),
+                Some(&StepOutcome::Failed("injected failure".to_owned()))
+            );
+            for (step, outcome) in report.steps() {
+                if *step == failing {
+                    continue;
+                }
+                let expected = if *step == CleanupStep::StateDir {
+                    StepOutcome::Kept
+                } else {
+                    StepOutcome::Done
+                };
+                assert_eq!(outcome, &expected, "{step} after {failing} failed");
+            }
+            assert!(report
+                .to_string()
+                .contains(&format!("{failing}: injected failure")));
+
+            let report = run_cleanup(&steps);
+            assert!(report.is_clean(), "{failing}: {report}");
+            assert_eq!(report.outcome(failing), Some(&StepOutcome::Done));
+            assert_eq!(
+                report.steps().iter().map(|(s, _)| *s).collect::<Vec<_>>(),
+                CLEANUP_ORDER
+            );
+        }
+    }
+
+    #[test]
+    fn test_container_cleanup_is_idempotent() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("container");
+        fs::create_dir(&root)?;
+        let _notify = UnixListener::bind(root.join("notify.sock"))?;
+        let mut container = Container::default();
+        container.root = root.clone();
+        container.set_state_store(Arc::new(InMemoryStateStore::new()));
+        container.save()?;
+        let cleanup = ContainerCleanup {
+            container: &container,
+            cgroup_config: None,
+        };
+
+        let report = run_cleanup(&cleanup);
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(
+            report.outcome(CleanupStep::Sockets),
+            Some(&StepOutcome::Done)
+        );
+        assert_eq!(
+            report.outcome(CleanupStep::StateDir),
+            Some(&StepOutcome::Done)
+        );
+        assert!(!root.exists());
+        assert!(State::load_from(&**container.state_store(), &root).is_err());
+
+        let report = run_cleanup(&cleanup);
+        assert!(report.is_clean(), "{report}");
+        for step in [
+            CleanupStep::KillProcesses,
+            CleanupStep::Cgroup,
+            CleanupStep::Resctrl,
+        ] {
+            assert_eq!(report.outcome(step), Some(&StepOutcome::NotNeeded));
+        }
+        for step in [CleanupStep::Sockets, CleanupStep::StateDir] {
+            assert_eq!(report.outcome(step), Some(&StepOutcome::AlreadyClean));
+        }
+
+        Ok(())
+    }
+}
@@ -1,15 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
+use libcgroups::common::CgroupSetup;
 use nix::unistd::Pid;
-use procfs::process::Process;
 
+use super::exit_status::ExitStatus;
+use super::log_level::{self, ContainerLogLevel};
+use super::state_store::{default_state_store, StateStore};
+use super::status_probe::{compute_status, ProbeCollector};
 use crate::config::YoukiConfig;
-use crate::container::{ContainerStatus, State};
+use crate::container::{ContainerStatus, CreateDescriptor, State};
 use crate::error::LibcontainerError;
+use crate::hooks::{self, HookResult};
+use crate::shared_volume::SharedVolumeManager;
+use crate::spec_diff::{DiffEntry, SpecProvenance};
 use crate::syscall::syscall::create_syscall;
 
 /// Structure representing the container data
@@ -19,6 +27,8 @@ pub struct Container {
     pub state: State,
     // indicated the directory for the root path in the container
     pub root: PathBuf,
+    // Store the records of the container are persisted in
+    store: Arc<dyn StateStore>,
 }
 
 impl Default for Container {
@@ -26,6 +36,7 @@ impl Default for Container {
         Self {
             state: State::default(),
             root: PathBuf::from("/run/youki"),
+            store: default_state_store(),
         }
     }
 }
@@ -51,6 +62,7 @@ impl Container {
         Ok(Self {
             state,
             root: container_root,
+            store: default_state_store(),
         })
     }
 
@@ -139,6 +151,170 @@ impl Container {
         self.state.clean_up_intel_rdt_subdirectory
     }
 
+    pub fn set_log_level(&mut self, log_level: Option<ContainerLogLevel>) -> &mut Self {
+        self.state.log_level = log_level;
+        self
+    }
+
+    pub fn log_level(&self) -> Option<ContainerLogLevel> {
+        self.state.log_level
+    }
+
+    /// Span the operations on the container run in, carrying its log level.
+    pub fn span(&self) -> tracing::Span {
+        log_level::container_span(self.id(), self.log_level())
+    }
+
+    pub fn set_exit_waiter_pid(&mut self, pid: Option<i32>) -> &mut Self {
+        self.state.exit_waiter_pid = pid;
+        self
+    }
+
+    pub fn exit_waiter_pid(&self) -> Option<Pid> {
+        self.state.exit_waiter_pid.map(Pid::from_raw)
+    }
+
+    pub fn set_init_start_time(&mut self, start_time: Option<u64>) -> &mut Self {
+        self.state.init_start_time = start_time;
+        self
+    }
+
+    pub fn init_start_time(&self) -> Option<u64> {
+        self.state.init_start_time
+    }
+
+    /// Records where the cgroup of the container landed at create time
+    pub fn set_cgroup_location(
+        &mut self,
+        cgroup_path: Option<PathBuf>,
+        systemd_unit: Option<String>,
+    ) -> &mut Self {
+        self.state.cgroup_path = cgroup_path;
+        self.state.systemd_unit = systemd_unit;
+        self
+    }
+
+    /// Returns the absolute path of the cgroup of the container, if it was
+    /// recorded at create time
+    pub fn cgroup_path(&self) -> Option<&Path> {
+        self.state.cgroup_path.as_deref()
+    }
+
+    /// Returns the name of the systemd unit of the cgroup of the container,
+    /// if systemd manages it
+    pub fn systemd_unit(&self) -> Option<&str> {
+        self.state.systemd_unit.as_deref()
+    }
+
+    /// Marks the container to be kept for inspection after it stopped. A kept
+    /// container is deleted like any other, or by
+    /// [`ContainerRegistry::prune`](super::registry::ContainerRegistry::prune).
+    pub fn set_kept(&mut self, kept: bool) -> &mut Self {
+        self.state.kept = kept;
+        self
+    }
+
+    pub fn kept(&self) -> bool {
+        self.state.kept
+    }
+
+    pub fn set_exit_status(&mut self, exit_status: Option<ExitStatus>) -> &mut Self {
+        self.state.exit_status = exit_status;
+        self
+    }
+
+    pub fn exit_status(&self) -> Option<&ExitStatus> {
+        self.state.exit_status.as_ref()
+    }
+
+    pub fn set_hook_output_limit(&mut self, limit: Option<usize>) -> &mut Self {
+        self.state.hook_output_limit = limit;
+        self
+    }
+
+    pub fn hook_output_limit(&self) -> Option<usize> {
+        self.state.hook_output_limit
+    }
+
+    /// Returns the results of the hooks that ran for the container, in the
+    /// order they ran
+    pub fn hook_results(&self) -> Result<Vec<HookResult>, LibcontainerError> {
+        Ok(hooks::load_hook_results(&self.root)?)
+    }
+
+    pub fn set_ipc_sysctls(&mut self, sysctls: BTreeMap<String, String>) -> &mut Self {
+        self.state.ipc_sysctls = sysctls;
+        self
+    }
+
+    /// The IPC sysctls applied in the namespace of the container
+    pub fn ipc_sysctls(&self) -> &BTreeMap<String, String> {
+        &self.state.ipc_sysctls
+    }
+
+    pub fn set_unmanaged_cgroups(&mut self, unmanaged: bool) -> &mut Self {
+        self.state.unmanaged_cgroups = unmanaged;
+        self
+    }
+
+    /// If the cgroups of the container are left to the caller
+    pub fn unmanaged_cgroups(&self) -> bool {
+        self.state.unmanaged_cgroups
+    }
+
+    pub fn set_rootless(&mut self, rootless: bool) -> &mut Self {
+        self.state.rootless = rootless;
+        self
+    }
+
+    /// If the container is rootless, i.e. it has a user namespace and was
+    /// created by a runtime that isn't real root. Recorded at create time.
+    pub fn is_rootless(&self) -> bool {
+        self.state.rootless
+    }
+
+    pub fn set_core_sched(&mut self, core_sched: bool) -> &mut Self {
+        self.state.core_sched = core_sched;
+        self
+    }
+
+    /// If the init process of the container has a core scheduling cookie
+    pub fn core_sched(&self) -> bool {
+        self.state.core_sched
+    }
+
+    /// The cgroup setup the managers of the container are created with,
+    /// `None` to detect the setup of the host
+    pub fn cgroup_setup(&self) -> Option<CgroupSetup> {
+        self.unmanaged_cgroups().then_some(CgroupSetup::Unmanaged)
+    }
+
+    pub fn set_shared_volumes(&mut self, group_ids: Vec<String>) -> &mut Self {
+        self.state.shared_volumes = group_ids;
+        self
+    }
+
+    pub fn shared_volumes(&self) -> &[String] {
+        &self.state.shared_volumes
+    }
+
+    /// Drops the references of the container to its shared volumes, removing
+    /// the volumes no other container is attached to. Errors are logged, a
+    /// volume that can't be released must not prevent the cleanup of the
+    /// container.
+    pub(crate) fn release_shared_volumes(&self) {
+        let root_path = match self.root.parent() {
+            Some(root_path) => root_path,
+            None => return,
+        };
+        let manager = SharedVolumeManager::new(root_path).with_state_store(self.store.clone());
+        for group_id in self.shared_volumes() {
+            if let Err(err) = manager.release(self.id(), group_id) {
+                tracing::warn!(?err, ?group_id, "failed to release shared volume");
+            }
+        }
+    }
+
     pub fn status(&self) -> ContainerStatus {
         self.state.status
     }
@@ -155,55 +331,55 @@ impl Container {
         self
     }
 
+    /// Updates the status from probes of the init process, its freezer and
+    /// the start notification. The probes time out instead of blocking, see
+    /// [`status_probe`](super::status_probe) for the transitions.
     pub fn refresh_status(&mut self) -> Result<(), LibcontainerError> {
-        let new_status = match self.pid() {
-            Some(pid) => {
-                // Note that Process::new does not spawn a new process
-                // but instead creates a new Process structure, and fill
-                // it with information about the process with given pid
-                if let Ok(proc) = Process::new(pid.as_raw()) {
-                    use procfs::process::ProcState;
-
-                    match proc.stat()?.state()? {
-                        ProcState::Zombie | ProcState::Dead => ContainerStatus::Stopped,
-                        _ => match self.status() {
-                            ContainerStatus::Creating
-                            | ContainerStatus::Created
-                            | ContainerStatus::Paused => self.status(),
-                            _ => ContainerStatus::Running,
-                        },
-                    }
-                } else {
-                    ContainerStatus::Stopped
-                }
-            }
-            None => ContainerStatus::Stopped,
-        };
-
-        self.set_status(new_status);
+        let probes = ProbeCollector::default().collect(self);
+        self.set_status(compute_status(probes));
         Ok(())
     }
 
     pub fn refresh_state(&mut self) -> Result<&mut Self, LibcontainerError> {
-        let state = State::load(&self.root)?;
+        let state = State::load_from(&*self.store, &self.root)?;
         self.state = state;
 
         Ok(self)
     }
 
+    /// Loads the container from the [default store](super::state_store::default_state_store)
     pub fn load(container_root: PathBuf) -> Result<Self, LibcontainerError> {
-        let state = State::load(&container_root)?;
+        Self::load_with_store(container_root, default_state_store())
+    }
+
+    pub fn load_with_store(
+        container_root: PathBuf,
+        store: Arc<dyn StateStore>,
+    ) -> Result<Self, LibcontainerError> {
+        let state = State::load_from(&*store, &container_root)?;
         let mut container = Self {
             state,
             root: container_root,
+            store,
         };
         container.refresh_status()?;
         Ok(container)
     }
 
+    /// Sets the store the records of the container are persisted in
+    pub fn set_state_store(&mut self, store: Arc<dyn StateStore>) -> &mut Self {
+        self.store = store;
+        self
+    }
+
+    pub fn state_store(&self) -> &Arc<dyn StateStore> {
+        &self.store
+    }
+
     pub fn save(&self) -> Result<(), LibcontainerError> {
         tracing::debug!("Save container status: {:?} in {:?}", self, self.root);
-        self.state.save(&self.root)?;
+        let _lock = self.store.lock(&self.root)?;
+        self.state.save_to(&*self.store, &self.root)?;
 
         Ok(())
     }
@@ -212,6 +388,22 @@ impl Container {
         let spec = YoukiConfig::load(&self.root)?;
         Ok(spec)
     }
+
+    /// Descriptor of the create of the container, saved by the create of an
+    /// init container
+    pub fn create_descriptor(&self) -> Result<CreateDescriptor, LibcontainerError> {
+        Ok(CreateDescriptor::load(&self.root)?)
+    }
+
+    /// Differences between the spec of the bundle and the spec the container
+    /// was created with, with the subsystem that made each change, see
+    /// [`spec_diff`](crate::spec_diff). Fails for containers created without
+    /// [`with_spec_provenance`](crate::container::init_builder::InitContainerBuilder::with_spec_provenance).
+    pub fn spec_diff(&self) -> Result<Vec<DiffEntry>, LibcontainerError> {
+        let provenance = SpecProvenance::load(&self.root)?;
+        let resolved = self.create_descriptor()?.spec;
+        Ok(provenance.diff(&resolved))
+    }
 }
 
 /// Checkpoint parameter structure
@@ -227,10 +419,14 @@ pub struct CheckpointOptions {
 
 #[cfg(test)]
 mod tests {
+    use std::thread;
+    use std::time::Duration;
+
     use anyhow::{Context, Result};
     use serial_test::serial;
 
     use super::*;
+    use crate::container::InMemoryStateStore;
 
     #[test]
     fn test_get_set_pid() {
@@ -325,6 +521,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_save_waits_for_lock() -> Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let store: Arc<dyn StateStore> = Arc::new(InMemoryStateStore::new());
+        let mut container = Container::new(
+            "container_id",
+            ContainerStatus::Created,
+            None,
+            &PathBuf::from("."),
+            tmp_dir.path(),
+        )?;
+        container.set_state_store(Arc::clone(&store));
+        let container_root = container.root.clone();
+
+        let lock = store.lock(&container_root)?;
+        let saver = thread::spawn(move || container.save().unwrap());
+        thread::sleep(Duration::from_millis(50));
+        assert!(
+            store
+                .load(&container_root, State::STATE_FILE_PATH)?
+                .is_none(),
+            "the state was saved while the container was locked"
+        );
+        drop(lock);
+        saver.join().unwrap();
+        assert!(State::load_from(&*store, &container_root).is_ok());
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_get_spec() -> Result<()> {
@@ -22,6 +22,7 @@ pub enum CheckpointError {
 
 impl Container {
     pub fn checkpoint(&mut self, opts: &CheckpointOptions) -> Result<(), LibcontainerError> {
+        let _span = self.span().entered();
         self.refresh_status()?;
 
         // can_pause() checks if the container is running. That also works for
@@ -4,11 +4,14 @@ use libcgroups::common::CgroupManager;
 use libcgroups::{self};
 use nix::sys::signal;
 
-use super::{Container, ContainerStatus};
+use super::exec_session::EXECS_FILE;
+use super::{Container, ContainerStatus, State};
 use crate::config::YoukiConfig;
 use crate::error::LibcontainerError;
-use crate::hooks;
+use crate::hooks::{self, HookStage};
+use crate::process::exit_waiter::kill_exit_waiter;
 use crate::process::intel_rdt::delete_resctrl_subdirectory;
+use crate::socket_handoff;
 
 impl Container {
     /// Deletes the container
@@ -32,6 +35,7 @@ impl Container {
     /// # }
     /// ```
     pub fn delete(&mut self, force: bool) -> Result<(), LibcontainerError> {
+        let _span = self.span().entered();
         self.refresh_status()?;
 
         tracing::debug!("container status: {:?}", self.status());
@@ -45,6 +49,7 @@ impl Container {
                 // deletion of status `created` without `force` flag. But both
                 // `runc` and `crun` allows deleting `created`. Therefore we
                 // decided to follow `runc` and `crun`.
+                self.kill_exit_waiter();
                 self.do_kill(signal::Signal::SIGKILL, true)?;
                 self.set_status(ContainerStatus::Stopped).save()?;
             }
@@ -53,6 +58,7 @@ impl Container {
                 // force flag is set. In the force case, we need to clean up any
                 // processes associated with containers.
                 if force {
+                    self.kill_exit_waiter();
                     self.do_kill(signal::Signal::SIGKILL, true)?;
                     self.set_status(ContainerStatus::Stopped).save()?;
                 } else {
@@ -77,6 +83,11 @@ impl Container {
             }
         }
 
+        // The socket paths stay bound otherwise and a container created with
+        // the same sockets fails with EADDRINUSE.
+        let handoffs = socket_handoff::recorded(self.state.annotations.as_ref());
+        socket_handoff::remove_sockets(&handoffs).map_err(LibcontainerError::OtherIO)?;
+
         if self.root.exists() {
             match YoukiConfig::load(&self.root) {
                 Ok(config) => {
@@ -86,11 +97,14 @@ impl Container {
                     // check https://man7.org/linux/man-pages/man7/cgroups.7.html
                     // creating and removing cgroups section for more information on cgroups
                     let cmanager = libcgroups::common::create_cgroup_manager(
-                        libcgroups::common::CgroupConfig {
-                            cgroup_path: config.cgroup_path.to_owned(),
-                            systemd_cgroup: self.systemd(),
-                            container_name: self.id().to_string(),
-                        },
+                        libcgroups::common::CgroupConfig::new(
+                            config.cgroup_path.to_owned(),
+                            self.systemd(),
+                            self.id().to_string(),
+                        )
+                        .with_extra_hierarchies(config.extra_cgroup_hierarchies.clone())
+                        .with_nested_delegation(config.nested_cgroup_delegation)
+                        .with_setup(self.cgroup_setup()),
                     )?;
                     cmanager.remove().map_err(|err| {
                         tracing::error!(cgroup_path = ?config.cgroup_path, "failed to remove cgroup due to: {err:?}");
@@ -98,12 +112,16 @@ impl Container {
                     })?;
 
                     if let Some(hooks) = config.hooks.as_ref() {
-                        hooks::run_hooks(hooks.poststop().as_ref(), Some(self), None).map_err(
-                            |err| {
-                                tracing::error!(err = ?err, "failed to run post stop hooks");
-                                err
-                            },
-                        )?;
+                        hooks::run_hooks(
+                            HookStage::Poststop,
+                            hooks.poststop().as_ref(),
+                            Some(self),
+                            None,
+                        )
+                        .map_err(|err| {
+                            tracing::error!(err = ?err, "failed to run post stop hooks");
+                            err
+                        })?;
                     }
                 }
                 Err(err) => {
@@ -117,14 +135,37 @@ impl Container {
                 }
             }
 
+            // Another user of the store must not save the state while it is
+            // deleted, the lock is released once the records are gone.
+            let lock = self.state_store().lock(&self.root)?;
+
             // remove the directory storing container state
             tracing::debug!("remove dir {:?}", self.root);
             fs::remove_dir_all(&self.root).map_err(|err| {
                 tracing::error!(?err, path = ?self.root, "failed to remove container dir");
                 LibcontainerError::OtherIO(err)
             })?;
+
+            // A store other than the files of the container directory keeps
+            // the records elsewhere.
+            for record in [State::STATE_FILE_PATH, EXECS_FILE] {
+                self.state_store().delete(&self.root, record)?;
+            }
+            drop(lock);
+
+            // The container state is gone at this point, so the release also
+            // treats a reference of this container that is left behind as
+            // stale.
+            self.release_shared_volumes();
         }
 
         Ok(())
     }
+    /// Kills the exit waiter of a container that is removed before its init
+    /// process exited, so the waiter doesn't outlive the container.
+    pub(super) fn kill_exit_waiter(&self) {
+        if let (Some(waiter_pid), Some(init_pid)) = (self.exit_waiter_pid(), self.pid()) {
+            kill_exit_waiter(waiter_pid, init_pid);
+        }
+    }
 }
@@ -1,7 +1,8 @@
 use std::thread;
 use std::time::Duration;
 
-use libcgroups::common::CgroupManager;
+use libcgroups::common::{AnyCgroupManager, CgroupManager};
+use libcgroups::stats_stream::StatsStream;
 
 use super::{Container, ContainerStatus};
 use crate::error::LibcontainerError;
@@ -28,18 +29,23 @@ impl Container {
     /// # }
     /// ```
     pub fn events(&mut self, interval: u32, stats: bool) -> Result<(), LibcontainerError> {
+        let _span = self.span().entered();
         self.refresh_status()?;
         if !self.state.status.eq(&ContainerStatus::Running) {
             tracing::error!(id = ?self.id(), status = ?self.state.status, "container is not running");
             return Err(LibcontainerError::IncorrectStatus);
         }
 
-        let cgroup_manager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: self.spec()?.cgroup_path,
-                systemd_cgroup: self.systemd(),
-                container_name: self.id().to_string(),
-            })?;
+        let config = self.spec()?;
+        let cgroup_manager = libcgroups::common::create_cgroup_manager(
+            libcgroups::common::CgroupConfig::new(
+                config.cgroup_path,
+                self.systemd(),
+                self.id().to_string(),
+            )
+            .with_nested_delegation(config.nested_cgroup_delegation)
+            .with_setup(self.cgroup_setup()),
+        )?;
         match stats {
             true => {
                 let stats = cgroup_manager.stats()?;
@@ -62,4 +68,64 @@ impl Container {
 
         Ok(())
     }
+
+    /// Returns a stream of samples of the cgroup stats of the running
+    /// container, taken every `interval`, see [`StatsStream`]. The stream
+    /// ends once the processes of the container exited.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// use libcontainer::container::builder::ContainerBuilder;
+    /// use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut container = ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .as_init("/var/run/docker/bundle")
+    /// .build()?;
+    /// container.start()?;
+    ///
+    /// for stats in container.stats_stream(Duration::from_secs(5))? {
+    ///     println!("{}", stats?.memory.memory.usage);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stats_stream(
+        &mut self,
+        interval: Duration,
+    ) -> Result<StatsStream<AnyCgroupManager>, LibcontainerError> {
+        let _span = self.span().entered();
+        self.refresh_status()?;
+        // Without managed cgroups, there are no stats and no processes to
+        // end the stream with.
+        if self.unmanaged_cgroups() {
+            tracing::error!(id = ?self.id(), "cannot stream the stats of a container without managed cgroups");
+            return Err(LibcontainerError::UnsupportedWithoutCgroups {
+                operation: "stats stream",
+            });
+        }
+        if self.state.status != ContainerStatus::Running {
+            tracing::error!(id = ?self.id(), status = ?self.state.status, "container is not running");
+            return Err(LibcontainerError::IncorrectStatus);
+        }
+
+        let config = self.spec()?;
+        let cgroup_manager = libcgroups::common::create_cgroup_manager(
+            libcgroups::common::CgroupConfig::new(
+                config.cgroup_path,
+                self.systemd(),
+                self.id().to_string(),
+            )
+            .with_nested_delegation(config.nested_cgroup_delegation)
+            .with_setup(self.cgroup_setup()),
+        )?;
+
+        Ok(StatsStream::new(cgroup_manager, interval))
+    }
 }
@@ -4,6 +4,7 @@ use nix::sys::signal::{self};
 use super::{Container, ContainerStatus};
 use crate::error::LibcontainerError;
 use crate::signal::Signal;
+use crate::utils;
 
 impl Container {
     /// Sends the specified signal to the container init process
@@ -28,6 +29,7 @@ impl Container {
     /// # }
     /// ```
     pub fn kill<S: Into<Signal>>(&mut self, signal: S, all: bool) -> Result<(), LibcontainerError> {
+        let _span = self.span().entered();
         self.refresh_status()?;
         match self.can_kill() {
             true => {
@@ -82,16 +84,19 @@ impl Container {
             match get_cgroup_setup()? {
                 libcgroups::common::CgroupSetup::Legacy
                 | libcgroups::common::CgroupSetup::Hybrid => {
+                    let config = self.spec()?;
                     let cmanager = libcgroups::common::create_cgroup_manager(
-                        libcgroups::common::CgroupConfig {
-                            cgroup_path: self.spec()?.cgroup_path,
-                            systemd_cgroup: self.systemd(),
-                            container_name: self.id().to_string(),
-                        },
+                        libcgroups::common::CgroupConfig::new(
+                            config.cgroup_path,
+                            self.systemd(),
+                            self.id().to_string(),
+                        )
+                        .with_nested_delegation(config.nested_cgroup_delegation)
+                        .with_setup(self.cgroup_setup()),
                     )?;
                     cmanager.freeze(libcgroups::common::FreezerState::Thawed)?;
                 }
-                libcgroups::common::CgroupSetup::Unified => {}
+                _ => {}
             }
         }
         Ok(())
@@ -99,12 +104,20 @@ impl Container {
 
     fn kill_all_processes<S: Into<Signal>>(&self, signal: S) -> Result<(), LibcontainerError> {
         let signal = signal.into().into_raw();
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: self.spec()?.cgroup_path,
-                systemd_cgroup: self.systemd(),
-                container_name: self.id().to_string(),
-            })?;
+        if self.unmanaged_cgroups() {
+            return self.kill_process_tree(signal);
+        }
+
+        let config = self.spec()?;
+        let cmanager = libcgroups::common::create_cgroup_manager(
+            libcgroups::common::CgroupConfig::new(
+                config.cgroup_path,
+                self.systemd(),
+                self.id().to_string(),
+            )
+            .with_nested_delegation(config.nested_cgroup_delegation)
+            .with_setup(self.cgroup_setup()),
+        )?;
 
         if let Err(e) = cmanager.freeze(libcgroups::common::FreezerState::Frozen) {
             tracing::warn!(
@@ -138,4 +151,25 @@ impl Container {
 
         Ok(())
     }
+
+    /// Signals the init process and its descendants, for a container whose
+    /// processes aren't tracked in a cgroup. Processes that were reparented
+    /// away from the init process are missed.
+    fn kill_process_tree(&self, signal: signal::Signal) -> Result<(), LibcontainerError> {
+        let pid = match self.pid() {
+            Some(pid) => pid,
+            // the container never got a process to signal
+            None => return Ok(()),
+        };
+        utils::process_tree(pid)
+            .iter()
+            .try_for_each(|&pid| {
+                tracing::debug!("kill signal {} to {}", signal, pid);
+                match signal::kill(pid, signal) {
+                    Err(nix::errno::Errno::ESRCH) => Ok(()),
+                    res => res,
+                }
+            })
+            .map_err(LibcontainerError::OtherSyscall)
+    }
 }
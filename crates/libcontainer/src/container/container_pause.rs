@@ -25,19 +25,29 @@ impl Container {
     /// # }
     /// ```
     pub fn pause(&mut self) -> Result<(), LibcontainerError> {
+        let _span = self.span().entered();
         self.refresh_status()?;
+        // The freezer is part of the cgroups of the container.
+        if self.unmanaged_cgroups() {
+            tracing::error!(id = ?self.id(), "cannot pause a container without managed cgroups");
+            return Err(LibcontainerError::UnsupportedWithoutCgroups { operation: "pause" });
+        }
 
         if !self.can_pause() {
             tracing::error!(status = ?self.status(), id = ?self.id(), "cannot pause container");
             return Err(LibcontainerError::IncorrectStatus);
         }
 
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: self.spec()?.cgroup_path,
-                systemd_cgroup: self.systemd(),
-                container_name: self.id().to_string(),
-            })?;
+        let config = self.spec()?;
+        let cmanager = libcgroups::common::create_cgroup_manager(
+            libcgroups::common::CgroupConfig::new(
+                config.cgroup_path,
+                self.systemd(),
+                self.id().to_string(),
+            )
+            .with_nested_delegation(config.nested_cgroup_delegation)
+            .with_setup(self.cgroup_setup()),
+        )?;
         cmanager.freeze(FreezerState::Frozen)?;
 
         tracing::debug!("saving paused status");
@@ -25,7 +25,15 @@ impl Container {
     /// # }
     /// ```
     pub fn resume(&mut self) -> Result<(), LibcontainerError> {
+        let _span = self.span().entered();
         self.refresh_status()?;
+        // The freezer is part of the cgroups of the container.
+        if self.unmanaged_cgroups() {
+            tracing::error!(id = ?self.id(), "cannot resume a container without managed cgroups");
+            return Err(LibcontainerError::UnsupportedWithoutCgroups {
+                operation: "resume",
+            });
+        }
         // check if container can be resumed :
         // for example, a running process cannot be resumed
         if !self.can_resume() {
@@ -33,12 +41,16 @@ impl Container {
             return Err(LibcontainerError::IncorrectStatus);
         }
 
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: self.spec()?.cgroup_path,
-                systemd_cgroup: self.systemd(),
-                container_name: self.id().to_string(),
-            })?;
+        let config = self.spec()?;
+        let cmanager = libcgroups::common::create_cgroup_manager(
+            libcgroups::common::CgroupConfig::new(
+                config.cgroup_path,
+                self.systemd(),
+                self.id().to_string(),
+            )
+            .with_nested_delegation(config.nested_cgroup_delegation)
+            .with_setup(self.cgroup_setup()),
+        )?;
         // resume the frozen container
         cmanager.freeze(FreezerState::Thawed)?;
 
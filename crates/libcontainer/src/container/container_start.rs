@@ -1,10 +1,11 @@
 use nix::sys::signal;
 
+use super::status_probe::START_NOTIFIED_FILE;
 use super::{Container, ContainerStatus};
 use crate::config::YoukiConfig;
 use crate::error::LibcontainerError;
-use crate::hooks;
-use crate::notify_socket::{NotifySocket, NOTIFY_FILE};
+use crate::hooks::{self, HookStage};
+use crate::notify_socket::{NotifyListenerError, NotifySocket, ReadinessListener, NOTIFY_FILE};
 
 impl Container {
     /// Starts a previously created container
@@ -28,6 +29,7 @@ impl Container {
     /// # }
     /// ```
     pub fn start(&mut self) -> Result<(), LibcontainerError> {
+        let _span = self.span().entered();
         self.refresh_status()?;
 
         if !self.can_start() {
@@ -47,7 +49,13 @@ impl Container {
             // While prestart is marked as deprecated in the OCI spec, the docker and integration test still
             // uses it.
             #[allow(deprecated)]
-            hooks::run_hooks(hooks.prestart().as_ref(), Some(self), None).map_err(|err| {
+            hooks::run_hooks(
+                HookStage::Prestart,
+                hooks.prestart().as_ref(),
+                Some(self),
+                None,
+            )
+            .map_err(|err| {
                 tracing::error!("failed to run pre start hooks: {}", err);
                 // In the case where prestart hook fails, the runtime must
                 // stop the container before generating an error and exiting.
@@ -59,6 +67,24 @@ impl Container {
 
         let mut notify_socket = NotifySocket::new(self.root.join(NOTIFY_FILE));
         notify_socket.notify_container_start()?;
+        // Lets refresh_status notice the start if saving the state fails.
+        if let Err(err) = std::fs::write(self.root.join(START_NOTIFIED_FILE), "") {
+            tracing::warn!(id = ?self.id(), ?err, "failed to record the start notification");
+        }
+        if let (Some(probe), Some(pid)) = (config.readiness_probe.as_ref(), self.pid()) {
+            match probe.wait(pid) {
+                Ok(attempts) => {
+                    tracing::info!(id = ?self.id(), ?probe, attempts, "readiness probe succeeded");
+                }
+                Err(err) => {
+                    tracing::error!(id = ?self.id(), ?probe, %err, "readiness probe failed");
+                    // The workload already runs, so it's stopped rather than
+                    // left behind in a container that was never started.
+                    let _ = self.kill(signal::Signal::SIGKILL, true);
+                    return Err(err.into());
+                }
+            }
+        }
         self.set_status(ContainerStatus::Running)
             .save()
             .map_err(|err| {
@@ -69,14 +95,56 @@ impl Container {
         // Run post start hooks. It runs after the container process is started.
         // It is called in the runtime namespace.
         if let Some(hooks) = config.hooks.as_ref() {
-            hooks::run_hooks(hooks.poststart().as_ref(), Some(self), Some(&self.root)).map_err(
-                |err| {
-                    tracing::error!("failed to run post start hooks: {}", err);
-                    err
-                },
-            )?;
+            hooks::run_hooks(
+                HookStage::Poststart,
+                hooks.poststart().as_ref(),
+                Some(self),
+                Some(&self.root),
+            )
+            .map_err(|err| {
+                tracing::error!("failed to run post start hooks: {}", err);
+                err
+            })?;
         }
 
         Ok(())
     }
+
+    /// Binds the readiness socket of a container created with
+    /// [`with_readiness_notify`](crate::container::init_builder::InitContainerBuilder::with_readiness_notify).
+    /// It is bound before the container is started, so the readiness of the
+    /// workload isn't missed, and the caller waits on it after the start.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use libcontainer::container::builder::ContainerBuilder;
+    /// use libcontainer::notify_socket::DEFAULT_READY_TIMEOUT;
+    /// use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut container = ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .as_init("/var/run/docker/bundle")
+    /// .with_readiness_notify(true)
+    /// .build()?;
+    ///
+    /// let listener = container.readiness_listener()?;
+    /// container.start()?;
+    /// let readiness = listener.wait(DEFAULT_READY_TIMEOUT)?;
+    /// println!("ready: {:?}", readiness.status);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn readiness_listener(&self) -> Result<ReadinessListener, LibcontainerError> {
+        let config = YoukiConfig::load(&self.root)?;
+        if !config.readiness_notify {
+            tracing::error!(id = ?self.id(), "readiness notify is not enabled");
+            Err(NotifyListenerError::ReadinessDisabled)?;
+        }
+
+        Ok(ReadinessListener::bind(&self.root)?)
+    }
 }
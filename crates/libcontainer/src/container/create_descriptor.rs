@@ -0,0 +1,501 @@
+//! Serializable description of a container create
+//!
+//! A [`CreateDescriptor`] captures everything the create of a container is
+//! run with, once the builder has resolved it, except for the live parts: the
+//! file descriptors, the executor and the syscall interface. An orchestrator
+//! stores it to audit how a container was created, or to run the create
+//! again after a crash with [`CreateDescriptor::create`], supplying the live
+//! parts anew as [`CreateHandles`].
+//!
+//! The descriptor of each init container is saved in its container directory
+//! and loaded with
+//! [`Container::create_descriptor`](crate::container::Container::create_descriptor).
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::os::fd::{OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use libcgroups::common::{CgroupSetup, CpusetPartition};
+use nix::unistd::Pid;
+use oci_spec::runtime::Spec;
+use serde::{Deserialize, Serialize};
+
+use super::builder_impl::ContainerBuilderImpl;
+use super::init_builder::HostnamePolicy;
+use super::{Container, State};
+use crate::core_sched::CoreSched;
+use crate::create_signals::CreateSignalPolicy;
+use crate::error::LibcontainerError;
+use crate::fault_injection::FaultInjection;
+use crate::process::args::ContainerType;
+use crate::rootfs::MountOrder;
+use crate::syscall::syscall::SyscallType;
+use crate::user_ns::UserNamespaceConfig;
+use crate::workload::Executor;
+
+/// Name of the file the descriptor is saved as in the container directory
+const CREATE_DESCRIPTOR_NAME: &str = "create_descriptor.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CreateDescriptorError {
+    #[error("failed to save create descriptor")]
+    SaveIO {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("failed to save create descriptor")]
+    SaveEncode {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+    #[error("failed to load create descriptor")]
+    LoadIO {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("failed to parse create descriptor")]
+    LoadParse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+    #[error("the create of a tenant container needs the exec notify fd")]
+    MissingExecNotifyFd,
+    #[error("the create of an init container needs its container directory")]
+    MissingContainerRoot,
+}
+
+type Result<T> = std::result::Result<T, CreateDescriptorError>;
+
+/// Kind of container a create is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CreateKind {
+    /// A new container, with namespaces and cgroups of its own
+    Init,
+    /// A process joining an existing container, e.g. for exec
+    Tenant,
+}
+
+/// Everything the create of a container is run with, except for the live
+/// parts, see [`CreateHandles`]. Paths are absolute, the rootfs is
+/// canonicalized and the spec is the one the builder resolved, so a create
+/// run from the descriptor doesn't depend on the working directory or the
+/// bundle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateDescriptor {
+    pub kind: CreateKind,
+    pub container_id: String,
+    pub spec: Spec,
+    pub rootfs: PathBuf,
+    /// Directory of the container, for an init container
+    pub container_root: Option<PathBuf>,
+    /// State of the container a tenant process joins
+    pub joined_container_state: Option<State>,
+    pub notify_path: PathBuf,
+    pub pid_file: Option<PathBuf>,
+    pub exit_status_file: Option<PathBuf>,
+    pub use_systemd: bool,
+    pub cgroup_setup: Option<CgroupSetup>,
+    pub cpuset_partition: Option<CpusetPartition>,
+    pub cgroup_mount_readonly: bool,
+    pub delegate_cgroup: bool,
+    pub extra_cgroup_hierarchies: Vec<String>,
+    pub nested_cgroup_delegation: bool,
+    pub hostname_policy: HostnamePolicy,
+    pub hostname_without_uts: bool,
+    pub mount_order: MountOrder,
+    pub reset_loginuid: bool,
+    pub proc_sys_readonly: bool,
+    pub fix_mount_target_type: bool,
+    pub mtab_symlink: bool,
+    pub ensure_default_devices: bool,
+    pub rootfs_write_limit: Option<u64>,
+    pub rootfs_max_size: Option<u64>,
+    pub run_as_user: Option<(u32, u32)>,
+    pub resolve_username: bool,
+    pub new_session: bool,
+    pub preserve_fds: i32,
+    pub detached: bool,
+    pub no_pivot: bool,
+    pub as_sibling: bool,
+    pub tolerate_dumpable_eperm: bool,
+    pub default_path: Option<String>,
+    pub inject_default_path: bool,
+    pub argv0_override: Option<String>,
+    pub core_sched: Option<CoreSched>,
+    pub create_signal_policy: CreateSignalPolicy,
+    pub console_socket_timeout: Duration,
+    pub intermediate_timeout: Duration,
+    pub seccomp_notify_timeout: Duration,
+    pub create_timeout: Option<Duration>,
+}
+
+/// The live parts of a create a [`CreateDescriptor`] leaves out. Faults
+/// injected for testing aren't carried over either.
+pub struct CreateHandles {
+    pub executor: Box<dyn Executor>,
+    pub syscall: SyscallType,
+    /// Socket the pty master is sent over
+    pub console_socket: Option<OwnedFd>,
+    pub stdin: Option<OwnedFd>,
+    pub stdout: Option<OwnedFd>,
+    pub stderr: Option<OwnedFd>,
+    /// Listening sockets handed off to the container process, with the fd
+    /// each is passed as
+    pub socket_fds: Vec<(RawFd, RawFd)>,
+    /// Write end of the pipe a tenant process reports its exec on
+    pub exec_notify_fd: Option<RawFd>,
+}
+
+impl CreateHandles {
+    pub fn new(executor: Box<dyn Executor>, syscall: SyscallType) -> Self {
+        Self {
+            executor,
+            syscall,
+            console_socket: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            socket_fds: Vec::new(),
+            exec_notify_fd: None,
+        }
+    }
+}
+
+impl CreateDescriptor {
+    /// Path of the cgroup of the container in the spec
+    pub fn cgroup_path(&self) -> Option<&Path> {
+        self.spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.cgroups_path().as_deref())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = fs::File::create(path.join(CREATE_DESCRIPTOR_NAME)).map_err(|err| {
+            CreateDescriptorError::SaveIO {
+                source: err,
+                path: path.to_owned(),
+            }
+        })?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self).map_err(|err| {
+            CreateDescriptorError::SaveEncode {
+                source: err,
+                path: path.to_owned(),
+            }
+        })?;
+        writer
+            .flush()
+            .map_err(|err| CreateDescriptorError::SaveIO {
+                source: err,
+                path: path.to_owned(),
+            })?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = fs::File::open(path.join(CREATE_DESCRIPTOR_NAME)).map_err(|err| {
+            CreateDescriptorError::LoadIO {
+                source: err,
+                path: path.to_owned(),
+            }
+        })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+            CreateDescriptorError::LoadParse {
+                source: err,
+                path: path.to_owned(),
+            }
+        })
+    }
+
+    /// Runs the create of the descriptor again, e.g. after the runtime
+    /// crashed during the first one, and returns the pid of the init
+    /// process. The directory of an init container must still exist: the
+    /// caller kills the processes and removes the cgroup the failed create
+    /// left, but doesn't delete the container.
+    pub fn create(self, handles: CreateHandles) -> std::result::Result<Pid, LibcontainerError> {
+        if self.kind == CreateKind::Init {
+            // The socket of the failed create is still bound to the path.
+            let _ = fs::remove_file(&self.notify_path);
+        }
+        let mut builder_impl = ContainerBuilderImpl::from_descriptor(self, handles)?;
+        Ok(builder_impl.create()?.init_pid)
+    }
+}
+
+impl ContainerBuilderImpl {
+    pub(super) fn to_descriptor(&self) -> CreateDescriptor {
+        CreateDescriptor {
+            kind: match self.container_type {
+                ContainerType::InitContainer => CreateKind::Init,
+                ContainerType::TenantContainer { .. } => CreateKind::Tenant,
+            },
+            container_id: self.container_id.clone(),
+            spec: self.spec.as_ref().clone(),
+            rootfs: self.rootfs.clone(),
+            container_root: self
+                .container
+                .as_ref()
+                .map(|container| container.root.clone()),
+            joined_container_state: self.joined_container_state.clone(),
+            notify_path: self.notify_path.clone(),
+            pid_file: self.pid_file.clone(),
+            exit_status_file: self.exit_status_file.clone(),
+            use_systemd: self.use_systemd,
+            cgroup_setup: self.cgroup_setup,
+            cpuset_partition: self.cpuset_partition,
+            cgroup_mount_readonly: self.cgroup_mount_readonly,
+            delegate_cgroup: self.delegate_cgroup,
+            extra_cgroup_hierarchies: self.extra_cgroup_hierarchies.clone(),
+            nested_cgroup_delegation: self.nested_cgroup_delegation,
+            hostname_policy: self.hostname_policy,
+            hostname_without_uts: self.hostname_without_uts,
+            mount_order: self.mount_order,
+            reset_loginuid: self.reset_loginuid,
+            proc_sys_readonly: self.proc_sys_readonly,
+            fix_mount_target_type: self.fix_mount_target_type,
+            mtab_symlink: self.mtab_symlink,
+            ensure_default_devices: self.ensure_default_devices,
+            rootfs_write_limit: self.rootfs_write_limit,
+            rootfs_max_size: self.rootfs_max_size,
+            run_as_user: self.run_as_user,
+            resolve_username: self.resolve_username,
+            new_session: self.new_session,
+            preserve_fds: self.preserve_fds,
+            detached: self.detached,
+            no_pivot: self.no_pivot,
+            as_sibling: self.as_sibling,
+            tolerate_dumpable_eperm: self.tolerate_dumpable_eperm,
+            default_path: self.default_path.clone(),
+            inject_default_path: self.inject_default_path,
+            argv0_override: self.argv0_override.clone(),
+            core_sched: self.core_sched,
+            create_signal_policy: self.create_signal_policy,
+            console_socket_timeout: self.console_socket_timeout,
+            intermediate_timeout: self.intermediate_timeout,
+            seccomp_notify_timeout: self.seccomp_notify_timeout,
+            create_timeout: self.create_timeout,
+        }
+    }
+
+    /// Rebuilds the builder of a create from its descriptor. The user
+    /// namespace config is derived from the spec and the container of an
+    /// init container is loaded from its directory.
+    pub(super) fn from_descriptor(
+        descriptor: CreateDescriptor,
+        handles: CreateHandles,
+    ) -> std::result::Result<Self, LibcontainerError> {
+        let container_type = match descriptor.kind {
+            CreateKind::Init => ContainerType::InitContainer,
+            CreateKind::Tenant => ContainerType::TenantContainer {
+                exec_notify_fd: handles
+                    .exec_notify_fd
+                    .ok_or(CreateDescriptorError::MissingExecNotifyFd)?,
+            },
+        };
+        let container = match (descriptor.kind, descriptor.container_root) {
+            (CreateKind::Init, Some(root)) => Some(Container::load(root)?),
+            (CreateKind::Init, None) => {
+                return Err(CreateDescriptorError::MissingContainerRoot.into())
+            }
+            (CreateKind::Tenant, _) => None,
+        };
+        let user_ns_config = UserNamespaceConfig::new(&descriptor.spec)?;
+
+        Ok(Self {
+            container_type,
+            syscall: handles.syscall,
+            use_systemd: descriptor.use_systemd,
+            container_id: descriptor.container_id,
+            spec: Rc::new(descriptor.spec),
+            rootfs: descriptor.rootfs,
+            pid_file: descriptor.pid_file,
+            console_socket: handles.console_socket,
+            console_socket_timeout: descriptor.console_socket_timeout,
+            intermediate_timeout: descriptor.intermediate_timeout,
+            user_ns_config,
+            cpuset_partition: descriptor.cpuset_partition,
+            cgroup_mount_readonly: descriptor.cgroup_mount_readonly,
+            delegate_cgroup: descriptor.delegate_cgroup,
+            hostname_policy: descriptor.hostname_policy,
+            hostname_without_uts: descriptor.hostname_without_uts,
+            mount_order: descriptor.mount_order,
+            reset_loginuid: descriptor.reset_loginuid,
+            proc_sys_readonly: descriptor.proc_sys_readonly,
+            fix_mount_target_type: descriptor.fix_mount_target_type,
+            mtab_symlink: descriptor.mtab_symlink,
+            ensure_default_devices: descriptor.ensure_default_devices,
+            rootfs_write_limit: descriptor.rootfs_write_limit,
+            rootfs_max_size: descriptor.rootfs_max_size,
+            run_as_user: descriptor.run_as_user,
+            resolve_username: descriptor.resolve_username,
+            new_session: descriptor.new_session,
+            exit_status_file: descriptor.exit_status_file,
+            notify_path: descriptor.notify_path,
+            container,
+            joined_container_state: descriptor.joined_container_state,
+            preserve_fds: descriptor.preserve_fds,
+            socket_fds: handles.socket_fds,
+            detached: descriptor.detached,
+            executor: handles.executor,
+            no_pivot: descriptor.no_pivot,
+            stdin: handles.stdin,
+            stdout: handles.stdout,
+            stderr: handles.stderr,
+            as_sibling: descriptor.as_sibling,
+            tolerate_dumpable_eperm: descriptor.tolerate_dumpable_eperm,
+            seccomp_notify_timeout: descriptor.seccomp_notify_timeout,
+            default_path: descriptor.default_path,
+            inject_default_path: descriptor.inject_default_path,
+            argv0_override: descriptor.argv0_override,
+            create_timeout: descriptor.create_timeout,
+            core_sched: descriptor.core_sched,
+            create_signal_policy: descriptor.create_signal_policy,
+            extra_cgroup_hierarchies: descriptor.extra_cgroup_hierarchies,
+            nested_cgroup_delegation: descriptor.nested_cgroup_delegation,
+            cgroup_setup: descriptor.cgroup_setup,
+            fault_injection: FaultInjection::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serial_test::serial;
+
+    use super::*;
+    use crate::container::ContainerStatus;
+    use crate::workload::default::DefaultExecutor;
+
+    fn builder_impl(container: Option<Container>) -> Result<ContainerBuilderImpl> {
+        let spec = Spec::default();
+        Ok(ContainerBuilderImpl {
+            container_type: ContainerType::InitContainer,
+            syscall: SyscallType::Test,
+            use_systemd: true,
+            container_id: "descriptor".to_owned(),
+            user_ns_config: UserNamespaceConfig::new(&spec)?,
+            spec: Rc::new(spec),
+            rootfs: PathBuf::from("/run/rootfs"),
+            pid_file: Some(PathBuf::from("/run/descriptor.pid")),
+            console_socket: None,
+            console_socket_timeout: Duration::from_secs(3),
+            intermediate_timeout: Duration::from_secs(30),
+            cpuset_partition: Some(CpusetPartition::Isolated),
+            cgroup_mount_readonly: true,
+            delegate_cgroup: false,
+            hostname_policy: HostnamePolicy::Apply,
+            hostname_without_uts: false,
+            mount_order: MountOrder::AfterPivot,
+            reset_loginuid: true,
+            proc_sys_readonly: true,
+            fix_mount_target_type: false,
+            mtab_symlink: true,
+            ensure_default_devices: false,
+            rootfs_write_limit: Some(4096),
+            rootfs_max_size: None,
+            run_as_user: Some((1000, 1000)),
+            resolve_username: false,
+            new_session: false,
+            exit_status_file: None,
+            notify_path: PathBuf::from("/run/descriptor/notify.sock"),
+            container,
+            joined_container_state: None,
+            preserve_fds: 2,
+            socket_fds: vec![(7, 3)],
+            detached: true,
+            executor: Box::new(DefaultExecutor::default()),
+            no_pivot: false,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            as_sibling: false,
+            tolerate_dumpable_eperm: true,
+            seccomp_notify_timeout: Duration::from_millis(500),
+            default_path: Some("/usr/bin:/bin".to_owned()),
+            inject_default_path: true,
+            argv0_override: Some("init".to_owned()),
+            create_timeout: Some(Duration::from_secs(60)),
+            core_sched: Some(CoreSched::ShareFrom(Pid::from_raw(42))),
+            create_signal_policy: CreateSignalPolicy::Cleanup,
+            extra_cgroup_hierarchies: vec!["name=systemd".to_owned()],
+            nested_cgroup_delegation: true,
+            cgroup_setup: Some(CgroupSetup::Unified),
+            fault_injection: FaultInjection::default(),
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn test_descriptor_round_trip() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let container_root = tmp.path().join("descriptor");
+        fs::create_dir(&container_root)?;
+        let container = Container::new(
+            "descriptor",
+            ContainerStatus::Creating,
+            None,
+            tmp.path(),
+            &container_root,
+        )?;
+        container.save()?;
+
+        let descriptor = builder_impl(Some(container))?.to_descriptor();
+        descriptor.save(&container_root)?;
+        let loaded = CreateDescriptor::load(&container_root)?;
+        let mut handles =
+            CreateHandles::new(Box::new(DefaultExecutor::default()), SyscallType::Test);
+        handles.socket_fds = vec![(7, 3)];
+        let rebuilt = ContainerBuilderImpl::from_descriptor(loaded, handles)?;
+
+        assert!(matches!(
+            rebuilt.container_type,
+            ContainerType::InitContainer
+        ));
+        assert_eq!(rebuilt.socket_fds, vec![(7, 3)]);
+        assert_eq!(
+            rebuilt.container.as_ref().map(|container| container.id()),
+            Some("descriptor")
+        );
+        assert_eq!(
+            serde_json::to_value(rebuilt.to_descriptor())?,
+            serde_json::to_value(&descriptor)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tenant_needs_exec_notify_fd() -> Result<()> {
+        let mut descriptor = builder_impl(None)?.to_descriptor();
+        descriptor.kind = CreateKind::Tenant;
+
+        let handles = CreateHandles::new(Box::new(DefaultExecutor::default()), SyscallType::Test);
+        assert!(matches!(
+            ContainerBuilderImpl::from_descriptor(descriptor.clone(), handles),
+            Err(LibcontainerError::CreateDescriptor(
+                CreateDescriptorError::MissingExecNotifyFd
+            ))
+        ));
+
+        let mut handles =
+            CreateHandles::new(Box::new(DefaultExecutor::default()), SyscallType::Test);
+        handles.exec_notify_fd = Some(5);
+        let rebuilt = ContainerBuilderImpl::from_descriptor(descriptor, handles)?;
+        assert!(matches!(
+            rebuilt.container_type,
+            ContainerType::TenantContainer { exec_notify_fd: 5 }
+        ));
+        assert!(rebuilt.container.is_none());
+
+        Ok(())
+    }
+}
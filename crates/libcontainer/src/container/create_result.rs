@@ -0,0 +1,153 @@
+use std::os::fd::OwnedFd;
+use std::time::Duration;
+
+use nix::sched::CloneFlags;
+use nix::unistd::Pid;
+
+use crate::rootfs::FsType;
+use crate::user::ResolvedUser;
+
+/// Detailed outcome of a container creation, for callers that track the
+/// performance of the create path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateResult {
+    /// Pid of the container init process
+    pub pid: Pid,
+    /// Time spent in each phase of the create
+    pub timings: PhaseTimings,
+    /// Resource usage of the intermediate process, if it was reaped by the
+    /// create
+    pub child_rusage: Option<Rusage>,
+    /// Exact contents written to the pid file, so callers parsing it back
+    /// don't have to re-read it. `None` if no pid file was requested.
+    pub pid_file: Option<String>,
+    /// Whether the pid file was written. A failed write fails the create, so
+    /// this is only false if no pid file was requested.
+    pub pid_file_written: bool,
+    /// Clone flags of the namespaces created for the container. Youki clones
+    /// its processes without namespace flags and unshares the namespaces of
+    /// the spec afterwards, these are the flags it unshared. Namespaces
+    /// joined by path are not included.
+    pub namespace_flags: CloneFlags,
+    /// Bytes the rootfs setup wrote into the rootfs: the names of the device
+    /// nodes, symlinks and mount targets it created and the symlink targets.
+    /// `None` if the init process didn't report it.
+    pub rootfs_written: Option<u64>,
+    /// Type of the filesystem backing the rootfs, e.g. overlay, detected
+    /// once the rootfs is mounted. `None` if the init process couldn't
+    /// detect or report it.
+    pub rootfs_fs_type: Option<FsType>,
+    /// Ids of the user of the spec, resolved from its username as described
+    /// in [`user`](crate::user). `None` if the init process didn't report
+    /// them.
+    pub user: Option<ResolvedUser>,
+}
+
+/// Outcome of the creation of a container whose init process is a sibling of
+/// the caller, see
+/// [`InitContainerBuilder::build_sibling`](crate::container::init_builder::InitContainerBuilder::build_sibling).
+///
+/// The init process is a child of the parent of the caller, so only that
+/// parent can reap it, libcontainer never does. The pidfd lets the caller
+/// poll for the exit of the init process and signal it without racing the
+/// reuse of its pid, and lets the parent, if it gets the pidfd, wait for the
+/// exit status with `waitid(P_PIDFD)`.
+#[derive(Debug)]
+pub struct SiblingCreateResult {
+    pub result: CreateResult,
+    /// pidfd of the container init process
+    pub pidfd: OwnedFd,
+}
+
+/// Durations of the phases of a container creation. Phases that run inside
+/// the intermediate or init process are reported back over the channel and
+/// are `None` if they were skipped or not reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// Creating the notify socket used by `start`
+    pub notify_socket_setup: Duration,
+    /// Cloning the intermediate process
+    pub clone: Duration,
+    /// Applying the cgroup configuration, measured in the intermediate process
+    pub cgroup_apply: Option<Duration>,
+    /// Preparing the rootfs, measured in the init process
+    pub rootfs_prepare: Option<Duration>,
+    /// Running the `createRuntime` hooks
+    pub hooks: Duration,
+    /// The whole create, from the notify socket setup to the hooks
+    pub total: Duration,
+}
+
+/// Resource usage of a reaped child process, as reported by `wait4(2)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rusage {
+    /// Time spent in user mode
+    pub user_time: Duration,
+    /// Time spent in kernel mode
+    pub system_time: Duration,
+    /// Maximum resident set size in kilobytes
+    pub max_rss_kb: i64,
+    /// Page faults serviced without any I/O
+    pub minor_faults: i64,
+    /// Page faults that required I/O
+    pub major_faults: i64,
+    /// Context switches because the process waited for a resource
+    pub voluntary_context_switches: i64,
+    /// Context switches because the process was preempted
+    pub involuntary_context_switches: i64,
+}
+
+impl From<libc::rusage> for Rusage {
+    fn from(usage: libc::rusage) -> Self {
+        Self {
+            user_time: timeval_to_duration(usage.ru_utime),
+            system_time: timeval_to_duration(usage.ru_stime),
+            max_rss_kb: usage.ru_maxrss,
+            minor_faults: usage.ru_minflt,
+            major_faults: usage.ru_majflt,
+            voluntary_context_switches: usage.ru_nvcsw,
+            involuntary_context_switches: usage.ru_nivcsw,
+        }
+    }
+}
+
+fn timeval_to_duration(tv: libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec.max(0) as u64) + Duration::from_micros(tv.tv_usec.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rusage_from_libc() {
+        // SAFETY: rusage is a plain C struct, all zeroes is a valid value.
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        usage.ru_utime = libc::timeval {
+            tv_sec: 1,
+            tv_usec: 500_000,
+        };
+        usage.ru_stime = libc::timeval {
+            tv_sec: 0,
+            tv_usec: 250,
+        };
+        usage.ru_maxrss = 2048;
+        usage.ru_minflt = 10;
+        usage.ru_majflt = 1;
+        usage.ru_nvcsw = 3;
+        usage.ru_nivcsw = 4;
+
+        assert_eq!(
+            Rusage::from(usage),
+            Rusage {
+                user_time: Duration::from_millis(1500),
+                system_time: Duration::from_micros(250),
+                max_rss_kb: 2048,
+                minor_faults: 10,
+                major_faults: 1,
+                voluntary_context_switches: 3,
+                involuntary_context_switches: 4,
+            }
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Processes exec'd into a container under an id chosen by the caller
+//!
+//! Shims signal single exec'd processes. A pid alone is not enough for that,
+//! the process may have exited and its pid been reused by the time the signal
+//! is sent. A session records the pid together with the start time of the
+//! process, and a process whose start time doesn't match anymore is treated
+//! as exited. The sessions are stored in `execs.json` in the container
+//! directory.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use nix::sys::signal;
+use nix::unistd::Pid;
+use procfs::process::Process;
+use serde::{Deserialize, Serialize};
+
+use super::state::StateError;
+use super::state_store::{StateLock, StateStore};
+use super::status_probe::{ProbeCollector, ProcessProbe};
+use super::Container;
+use crate::error::LibcontainerError;
+use crate::signal::Signal;
+
+pub(super) const EXECS_FILE: &str = "execs.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExecSessionError {
+    #[error("invalid exec id {0:?}")]
+    InvalidId(String),
+    #[error("exec id {0:?} is already used by a running process")]
+    Duplicate(String),
+    #[error("no exec session with id {0:?}")]
+    NotFound(String),
+    #[error("state of the process of exec session {0:?} is unknown")]
+    UnknownProcess(String),
+    #[error(transparent)]
+    Store(#[from] StateError),
+    #[error("failed to parse exec sessions of {container_root:?}")]
+    Parse {
+        container_root: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, ExecSessionError>;
+
+/// A process exec'd into the container
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecSession {
+    pub exec_id: String,
+    pub pid: i32,
+    /// Start time of the process in clock ticks after boot
+    pub start_time: u64,
+}
+
+impl ExecSession {
+    /// Creates the session of a running process, reading its start time
+    pub(crate) fn new(exec_id: &str, pid: Pid) -> std::result::Result<Self, LibcontainerError> {
+        let start_time = Process::new(pid.as_raw())?.stat()?.starttime;
+        Ok(Self {
+            exec_id: exec_id.to_owned(),
+            pid: pid.as_raw(),
+            start_time,
+        })
+    }
+
+    fn probe(&self) -> ProcessProbe {
+        ProbeCollector::default()
+            .probe_process(Some(Pid::from_raw(self.pid)), Some(self.start_time))
+    }
+}
+
+/// The sessions of a container, locked while this is alive
+struct ExecSessions<'a> {
+    store: &'a dyn StateStore,
+    container_root: &'a Path,
+    sessions: BTreeMap<String, ExecSession>,
+    _lock: StateLock,
+}
+
+impl<'a> ExecSessions<'a> {
+    fn open(store: &'a dyn StateStore, container_root: &'a Path) -> Result<Self> {
+        let lock = store.lock(container_root)?;
+        let sessions = match store.load(container_root, EXECS_FILE)? {
+            Some(content) => {
+                let sessions: Vec<ExecSession> =
+                    serde_json::from_slice(&content).map_err(|err| ExecSessionError::Parse {
+                        container_root: container_root.to_owned(),
+                        source: err,
+                    })?;
+                sessions
+                    .into_iter()
+                    .map(|session| (session.exec_id.clone(), session))
+                    .collect()
+            }
+            None => BTreeMap::new(),
+        };
+
+        Ok(Self {
+            store,
+            container_root,
+            sessions,
+            _lock: lock,
+        })
+    }
+
+    /// Drops the sessions whose process is gone. A zombie is kept until it
+    /// is reaped, so the session can be listed until its exit is collected.
+    fn prune(&mut self) -> bool {
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|_, session| session.probe() != ProcessProbe::Gone);
+        self.sessions.len() != before
+    }
+
+    fn save(&self) -> Result<()> {
+        let sessions: Vec<&ExecSession> = self.sessions.values().collect();
+        let content = serde_json::to_vec(&sessions).map_err(|err| ExecSessionError::Parse {
+            container_root: self.container_root.to_owned(),
+            source: err,
+        })?;
+        self.store.save(self.container_root, EXECS_FILE, &content)?;
+        Ok(())
+    }
+}
+
+/// Checks that an exec id can be used, before the process is started
+pub(crate) fn check_exec_id(container: &Container, exec_id: &str) -> Result<()> {
+    validate_exec_id(exec_id)?;
+    let mut sessions = ExecSessions::open(&**container.state_store(), &container.root)?;
+    if sessions.prune() {
+        sessions.save()?;
+    }
+    if sessions.sessions.contains_key(exec_id) {
+        tracing::error!(?exec_id, "exec id is already in use");
+        return Err(ExecSessionError::Duplicate(exec_id.to_owned()));
+    }
+
+    Ok(())
+}
+
+/// Records the session of a process that started successfully
+pub(crate) fn record_exec_session(container: &Container, session: ExecSession) -> Result<()> {
+    let mut sessions = ExecSessions::open(&**container.state_store(), &container.root)?;
+    sessions.prune();
+    if sessions.sessions.contains_key(&session.exec_id) {
+        tracing::error!(exec_id = ?session.exec_id, "exec id is already in use");
+        return Err(ExecSessionError::Duplicate(session.exec_id));
+    }
+    sessions.sessions.insert(session.exec_id.clone(), session);
+    sessions.save()
+}
+
+fn validate_exec_id(exec_id: &str) -> Result<()> {
+    let valid = !exec_id.is_empty()
+        && exec_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'));
+    if !valid {
+        return Err(ExecSessionError::InvalidId(exec_id.to_owned()));
+    }
+
+    Ok(())
+}
+
+impl Container {
+    /// Lists the exec sessions of the container whose process wasn't reaped
+    /// yet
+    pub fn execs(&self) -> std::result::Result<Vec<ExecSession>, LibcontainerError> {
+        let mut sessions = ExecSessions::open(&**self.state_store(), &self.root)?;
+        if sessions.prune() {
+            sessions.save()?;
+        }
+        Ok(sessions.sessions.into_values().collect())
+    }
+
+    /// Removes the session of `exec_id`, after its process was reaped.
+    /// Removing a session that doesn't exist is not an error.
+    pub fn remove_exec(&self, exec_id: &str) -> std::result::Result<(), LibcontainerError> {
+        let mut sessions = ExecSessions::open(&**self.state_store(), &self.root)?;
+        sessions.sessions.remove(exec_id);
+        sessions.prune();
+        sessions.save()?;
+        Ok(())
+    }
+
+    /// Sends the signal to the process exec'd with `exec_id`. A process that
+    /// exited, or whose pid now belongs to another process, is not signaled.
+    pub fn kill_exec<S: Into<Signal>>(
+        &self,
+        exec_id: &str,
+        signal: S,
+    ) -> std::result::Result<(), LibcontainerError> {
+        let _span = self.span().entered();
+        let mut sessions = ExecSessions::open(&**self.state_store(), &self.root)?;
+        let session = sessions
+            .sessions
+            .get(exec_id)
+            .cloned()
+            .ok_or_else(|| ExecSessionError::NotFound(exec_id.to_owned()))?;
+
+        match session.probe() {
+            ProcessProbe::Alive => {}
+            ProcessProbe::Exited => {
+                tracing::debug!(?exec_id, "exec process already exited");
+                return Ok(());
+            }
+            ProcessProbe::Gone => {
+                tracing::debug!(?exec_id, "exec process is gone, removing its session");
+                sessions.sessions.remove(exec_id);
+                sessions.save()?;
+                return Ok(());
+            }
+            ProcessProbe::Unknown => {
+                tracing::error!(?exec_id, "failed to probe the exec process");
+                return Err(ExecSessionError::UnknownProcess(exec_id.to_owned()).into());
+            }
+        }
+
+        let signal = signal.into().into_raw();
+        let pid = Pid::from_raw(session.pid);
+        tracing::debug!("kill signal {} to exec {} ({})", signal, exec_id, pid);
+        match signal::kill(pid, signal) {
+            Ok(_) | Err(nix::errno::Errno::ESRCH) => Ok(()),
+            Err(err) => {
+                tracing::error!(id = ?self.id(), ?err, ?exec_id, ?signal, "failed to kill exec process");
+                Err(LibcontainerError::OtherSyscall(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Child, Command};
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::container::InMemoryStateStore;
+
+    fn container() -> Container {
+        let mut container = Container::default();
+        container.set_state_store(Arc::new(InMemoryStateStore::new()));
+        container
+    }
+
+    fn sleeper() -> Result<(Child, Pid)> {
+        let child = Command::new("sleep").arg("60").spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+        Ok((child, pid))
+    }
+
+    #[test]
+    fn test_record_and_remove_exec() -> Result<()> {
+        let container = container();
+        let (mut child, pid) = sleeper()?;
+
+        check_exec_id(&container, "exec-1")?;
+        record_exec_session(&container, ExecSession::new("exec-1", pid)?)?;
+        assert!(matches!(
+            check_exec_id(&container, "exec-1"),
+            Err(ExecSessionError::Duplicate(_))
+        ));
+        let execs = container.execs()?;
+        assert_eq!(execs.len(), 1);
+        assert_eq!(execs[0].exec_id, "exec-1");
+        assert_eq!(execs[0].pid, pid.as_raw());
+
+        child.kill()?;
+        child.wait()?;
+        // A reaped process is pruned.
+        assert!(container.execs()?.is_empty());
+
+        let (mut child, pid) = sleeper()?;
+        record_exec_session(&container, ExecSession::new("exec-1", pid)?)?;
+        container.remove_exec("exec-1")?;
+        assert!(container.execs()?.is_empty());
+        child.kill()?;
+        child.wait()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_exec_id() -> Result<()> {
+        let container = container();
+        for exec_id in ["", "../exec", "exec id"] {
+            assert!(matches!(
+                check_exec_id(&container, exec_id),
+                Err(ExecSessionError::InvalidId(_))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_kill_exec() -> Result<()> {
+        let container = container();
+        let (mut child, pid) = sleeper()?;
+        record_exec_session(&container, ExecSession::new("exec-1", pid)?)?;
+
+        container.kill_exec("exec-1", signal::Signal::SIGKILL)?;
+        let status = child.wait()?;
+        assert!(!status.success());
+        assert!(matches!(
+            container.kill_exec("unknown", signal::Signal::SIGKILL),
+            Err(LibcontainerError::ExecSession(ExecSessionError::NotFound(
+                _
+            )))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_kill_exec_stale_start_time() -> Result<()> {
+        let container = container();
+        let (mut child, pid) = sleeper()?;
+        let mut session = ExecSession::new("exec-1", pid)?;
+        // as if the pid was reused by another process
+        session.start_time += 1;
+        record_exec_session(&container, session)?;
+
+        container.kill_exec("exec-1", signal::Signal::SIGKILL)?;
+        assert!(child.try_wait()?.is_none(), "a stale session was signaled");
+        assert!(container.execs()?.is_empty());
+
+        child.kill()?;
+        child.wait()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Exit status of the container init process
+//!
+//! A shim running youki detached is not the parent of the container init
+//! process, so it can't wait for it. Instead, it can ask for an exit status
+//! file, which is written once the init process is reaped. The file is written
+//! atomically, a reader either sees no file or the complete exit status.
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use nix::sys::wait::WaitStatus;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExitStatusError {
+    #[error("failed to serialize the exit status")]
+    Serialize(#[source] serde_json::Error),
+    #[error("failed to write the exit status file {path:?}")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to read the exit status file {path:?}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse the exit status file {path:?}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, ExitStatusError>;
+
+/// Contents of the exit status file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExitStatus {
+    /// Exit code of the init process. If the process was killed by a signal,
+    /// this is 128 plus the signal number, like in a shell.
+    pub exit_code: i32,
+    /// Signal that killed the init process, 0 if it exited by itself
+    pub signal: i32,
+    /// Time the init process was reaped
+    pub finished_at: DateTime<Utc>,
+}
+
+impl ExitStatus {
+    /// Creates the exit status of a reaped process. Returns None if the wait
+    /// status doesn't say the process terminated, e.g. if it was stopped.
+    pub fn from_wait_status(status: WaitStatus) -> Option<Self> {
+        let (exit_code, signal) = match status {
+            WaitStatus::Exited(_, code) => (code, 0),
+            WaitStatus::Signaled(_, signal, _) => (128 + signal as i32, signal as i32),
+            _ => return None,
+        };
+
+        Some(Self {
+            exit_code,
+            signal,
+            finished_at: Utc::now(),
+        })
+    }
+
+    /// Writes the exit status to a temporary file next to the given path and
+    /// renames it over the path.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_vec(self).map_err(ExitStatusError::Serialize)?;
+        let mut temp_path = OsString::from(path);
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        fs::write(&temp_path, contents)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|err| {
+                tracing::error!(?path, ?err, "failed to write exit status file");
+                let _ = fs::remove_file(&temp_path);
+                ExitStatusError::Write {
+                    path: path.to_owned(),
+                    source: err,
+                }
+            })
+    }
+
+    /// Reads an exit status file written by [`ExitStatus::write`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read(path).map_err(|err| ExitStatusError::Read {
+            path: path.to_owned(),
+            source: err,
+        })?;
+
+        serde_json::from_slice(&contents).map_err(|err| ExitStatusError::Parse {
+            path: path.to_owned(),
+            source: err,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use nix::sys::signal::Signal;
+    use nix::unistd::Pid;
+
+    use super::*;
+
+    #[test]
+    fn test_from_wait_status() {
+        let pid = Pid::from_raw(1);
+
+        let exited = ExitStatus::from_wait_status(WaitStatus::Exited(pid, 3)).unwrap();
+        assert_eq!((exited.exit_code, exited.signal), (3, 0));
+
+        let killed =
+            ExitStatus::from_wait_status(WaitStatus::Signaled(pid, Signal::SIGKILL, false))
+                .unwrap();
+        assert_eq!((killed.exit_code, killed.signal), (137, 9));
+
+        assert!(ExitStatus::from_wait_status(WaitStatus::Stopped(pid, Signal::SIGSTOP)).is_none());
+    }
+
+    #[test]
+    fn test_write_and_load() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("exit-status");
+        let status = ExitStatus::from_wait_status(WaitStatus::Exited(Pid::from_raw(1), 0)).unwrap();
+
+        status.write(&path)?;
+        assert_eq!(ExitStatus::load(&path)?, status);
+        // Only the exit status file is left behind
+        assert_eq!(fs::read_dir(tmp.path())?.count(), 1);
+
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&path)?)?;
+        for key in ["exit_code", "signal", "finished_at"] {
+            assert!(json.get(key).is_some(), "missing {key}");
+        }
+
+        Ok(())
+    }
+}
@@ -1,27 +1,160 @@
+use std::collections::HashMap;
 use std::fs;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Duration;
 
-use oci_spec::runtime::Spec;
+use libcgroups::common::{
+    CgroupSetup, CpusetPartition, CreateCgroupSetupError, SwapLimit, DEFAULT_CGROUP_ROOT,
+};
+use oci_spec::runtime::{
+    Capability, Hook, LinuxNamespaceBuilder, LinuxNamespaceType, LinuxPidsBuilder, MountBuilder,
+    Spec,
+};
+use serde::{Deserialize, Serialize};
 use user_ns::UserNamespaceConfig;
 
-use super::builder::ContainerBuilder;
+use super::builder::{validate_container_id, ContainerBuilder};
 use super::builder_impl::ContainerBuilderImpl;
-use super::{Container, ContainerStatus};
-use crate::config::YoukiConfig;
+use super::log_level::{self, ContainerLogLevel};
+use super::{Container, ContainerStatus, CreateResult, SiblingCreateResult};
+use crate::capabilities::{self, AmbientCapabilities};
+use crate::config::{self, YoukiConfig};
+use crate::core_sched::{CoreSched, CoreSchedError, CoreSchedRequest};
+use crate::create_signals::CreateSignalPolicy;
+use crate::debug::ResolvedSpec;
 use crate::error::{ErrInvalidSpec, LibcontainerError, MissingSpecError};
-use crate::notify_socket::NOTIFY_FILE;
+use crate::notify_socket::{self, NOTIFY_FILE};
 use crate::process::args::ContainerType;
-use crate::{apparmor, tty, user_ns, utils};
+use crate::readiness_probe::ReadinessProbe;
+use crate::rootfs::{ipc_mounts, ownership_shift, prewarm, utils as rootfs_utils, MountOrder};
+use crate::shared_volume::{SharedVolume, SharedVolumeManager};
+use crate::spec_diff::{subsystem, SpecProvenance};
+use crate::spec_limits::Limits;
+use crate::syscall::syscall::create_syscall;
+use crate::utils::PathBufExt;
+use crate::workload::handshake::HandshakeOnlyExecutor;
+use crate::{
+    annotation_env, apparmor, core_sched, cpuset, env_file, hostname_file, numa, socket_handoff,
+    stdio_file, sysctl, tty, user_ns, utils,
+};
+
+/// Default delay after which the liveness of the init process is confirmed
+pub const DEFAULT_LIVENESS_DELAY: Duration = Duration::from_millis(100);
+
+/// What to do with the hostname and domainname of the spec when the container
+/// joins an existing UTS namespace by path. Setting them in a shared namespace
+/// changes them for every other process in it, e.g. all containers of a pod.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostnamePolicy {
+    /// Fail the creation of the container
+    Reject,
+    /// Leave the hostname and domainname of the joined namespace untouched
+    #[default]
+    Skip,
+    /// Overwrite the hostname and domainname of the joined namespace
+    Apply,
+}
+
+/// What happens to the `/proc` mount of the spec when the container doesn't
+/// create a pid namespace. A new procfs shows the pids of the pid namespace
+/// the container process is in, i.e. of a joined namespace, or of the host if
+/// the container shares the pid namespace of the runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProcMountPolicy {
+    /// Mount a new procfs as the spec says
+    #[default]
+    Mount,
+    /// Drop the `/proc` mount, the rootfs keeps whatever `/proc` it has
+    SkipShared,
+    /// Bind the `/proc` of the runtime, with its submounts, if the container
+    /// shares the pid namespace of the runtime. Only a new procfs shows the
+    /// pids of a joined namespace, so one is still mounted then.
+    BindShared,
+}
+
+/// What happens to the cgroup of the container when it joins an existing
+/// cgroup namespace by path. The joined namespace keeps the cgroup root it
+/// was created with, so a cgroup created for the container shows up
+/// relative to that root inside it, or outside of it as `/..`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CgroupNamespacePolicy {
+    /// Create the cgroup of the container as usual
+    #[default]
+    Create,
+    /// Fail the creation of the container if it would get a cgroup of its
+    /// own
+    Reject,
+    /// Don't manage a cgroup, as with
+    /// [`CgroupSetup::Unmanaged`](libcgroups::common::CgroupSetup::Unmanaged).
+    /// The container stays in the cgroup of the caller, which is expected to
+    /// be in the cgroup the joined namespace is rooted at, like a process
+    /// executed into a pod.
+    Inherit,
+}
 
 // Builder that can be used to configure the properties of a new container
 pub struct InitContainerBuilder {
     base: ContainerBuilder,
     bundle: PathBuf,
+    resolve_bundle: Option<PathBuf>,
     use_systemd: bool,
     detached: bool,
+    detached_null_stdin: bool,
     no_pivot: bool,
     as_sibling: bool,
+    cpuset_partition: Option<CpusetPartition>,
+    swap_limit: Option<SwapLimit>,
+    pids_max_override: Option<i64>,
+    handshake_only: bool,
+    cgroup_mount_readonly: Option<bool>,
+    mount_cgroup2_inside: bool,
+    nested_cgroup_delegation: bool,
+    hostname_policy: HostnamePolicy,
+    hostname_without_uts: bool,
+    hostname_file: bool,
+    mount_order: MountOrder,
+    reset_loginuid: bool,
+    proc_sys_readonly: bool,
+    fix_mount_target_type: bool,
+    mtab_symlink: bool,
+    ensure_default_devices: bool,
+    mqueue_mount: bool,
+    shm_size: Option<u64>,
+    rootfs_write_limit: Option<u64>,
+    rootfs_max_size: Option<u64>,
+    spec_limits: Limits,
+    prefix_relative_mount_targets: bool,
+    run_as_user: Option<(u32, u32)>,
+    resolve_username: bool,
+    new_session: bool,
+    auto_no_new_privs: bool,
+    prewarm_rootfs: bool,
+    numa_auto_mems: bool,
+    clamp_cpuset_to_online: bool,
+    annotation_env_prefix: Option<String>,
+    env: HashMap<String, String>,
+    exit_status_file: Option<PathBuf>,
+    confirm_liveness: bool,
+    liveness_delay: Duration,
+    create_timeout: Option<Duration>,
+    create_signal_policy: CreateSignalPolicy,
+    extra_cgroup_hierarchies: Vec<String>,
+    cgroup_setup: Option<CgroupSetup>,
+    hook_output_limit: Option<usize>,
+    shared_volumes: Vec<SharedVolume>,
+    readiness_notify: bool,
+    readiness_probe: Option<ReadinessProbe>,
+    core_sched: bool,
+    ambient_capabilities: AmbientCapabilities,
+    shift_rootfs_ownership: bool,
+    proc_mount_policy: ProcMountPolicy,
+    cgroup_namespace_policy: CgroupNamespacePolicy,
+    spec_provenance: bool,
+    resolved_spec: Option<ResolvedSpec>,
 }
 
 impl InitContainerBuilder {
@@ -31,13 +164,73 @@ impl InitContainerBuilder {
         Self {
             base: builder,
             bundle,
+            resolve_bundle: None,
             use_systemd: true,
             detached: true,
+            detached_null_stdin: true,
             no_pivot: false,
             as_sibling: false,
+            cpuset_partition: None,
+            swap_limit: None,
+            pids_max_override: None,
+            handshake_only: false,
+            cgroup_mount_readonly: None,
+            mount_cgroup2_inside: false,
+            nested_cgroup_delegation: false,
+            hostname_policy: HostnamePolicy::default(),
+            hostname_without_uts: false,
+            hostname_file: false,
+            mount_order: MountOrder::default(),
+            reset_loginuid: false,
+            proc_sys_readonly: false,
+            fix_mount_target_type: false,
+            mtab_symlink: true,
+            ensure_default_devices: true,
+            mqueue_mount: true,
+            shm_size: Some(ipc_mounts::DEFAULT_SHM_SIZE),
+            rootfs_write_limit: None,
+            rootfs_max_size: None,
+            spec_limits: Limits::default(),
+            prefix_relative_mount_targets: false,
+            run_as_user: None,
+            resolve_username: false,
+            new_session: true,
+            auto_no_new_privs: false,
+            prewarm_rootfs: false,
+            numa_auto_mems: false,
+            clamp_cpuset_to_online: false,
+            annotation_env_prefix: None,
+            env: HashMap::new(),
+            exit_status_file: None,
+            confirm_liveness: false,
+            liveness_delay: DEFAULT_LIVENESS_DELAY,
+            create_timeout: None,
+            create_signal_policy: CreateSignalPolicy::default(),
+            extra_cgroup_hierarchies: Vec::new(),
+            cgroup_setup: None,
+            hook_output_limit: None,
+            shared_volumes: Vec::new(),
+            readiness_notify: false,
+            readiness_probe: None,
+            core_sched: false,
+            ambient_capabilities: AmbientCapabilities::default(),
+            shift_rootfs_ownership: false,
+            proc_mount_policy: ProcMountPolicy::default(),
+            cgroup_namespace_policy: CgroupNamespacePolicy::default(),
+            spec_provenance: false,
+            resolved_spec: None,
         }
     }
 
+    /// Sets the directory relative rootfs and hook paths of the spec are
+    /// resolved against. Defaults to the bundle the spec is loaded from. The
+    /// directory is made absolute once, so the resolution doesn't depend on
+    /// the working directory of the caller.
+    pub fn with_bundle<P: Into<PathBuf>>(mut self, bundle: Option<P>) -> Self {
+        self.resolve_bundle = bundle.map(|b| b.into());
+        self
+    }
+
     /// Sets if systemd should be used for managing cgroups
     pub fn with_systemd(mut self, should_use: bool) -> Self {
         self.use_systemd = should_use;
@@ -45,7 +238,9 @@ impl InitContainerBuilder {
     }
 
     /// Sets if the init process should be run as a child or a sibling of
-    /// the calling process
+    /// the calling process. A sibling init process is a child of the parent
+    /// of the caller, which has to reap it, libcontainer never waits on it.
+    /// [`InitContainerBuilder::build_sibling`] returns a pidfd of it.
     pub fn as_sibling(mut self, as_sibling: bool) -> Self {
         self.as_sibling = as_sibling;
         self
@@ -56,73 +251,1106 @@ impl InitContainerBuilder {
         self
     }
 
+    /// Sets if the stdin of a detached container is `/dev/null` when none was
+    /// set with [`ContainerBuilder::with_stdin`], so a workload reading it
+    /// gets EOF instead of blocking on the stdin inherited from the caller,
+    /// e.g. a terminal. With a console socket or the pty master returned by
+    /// [`InitContainerBuilder::build_with_pty_master`], the terminal is the
+    /// stdin and this has no effect. Callers passing the stdin of the
+    /// container as their own, like a shim, turn it off. Defaults to true.
+    pub fn with_detached_null_stdin(mut self, null_stdin: bool) -> Self {
+        self.detached_null_stdin = null_stdin;
+        self
+    }
+
+    /// Sets the file the exit status of the init process is written to as
+    /// [`ExitStatus`](super::ExitStatus) JSON. A waiter process is left
+    /// behind to reap the init process and write the file, so this requires
+    /// a [detached](Self::with_detach) container. The init process of an
+    /// attached container is reaped by the caller, which has to write the
+    /// exit status itself.
+    ///
+    /// This only works if the init process is a child youki can reap. With
+    /// [`as_sibling`](Self::as_sibling), the init process is a child of the
+    /// parent of youki and no waiter can be started. The build fails with
+    /// [`LibcontainerError::InvalidInput`] in both cases instead of never
+    /// writing the file.
+    pub fn with_exit_status_file<P: Into<PathBuf>>(
+        mut self,
+        path: Option<P>,
+    ) -> Result<Self, LibcontainerError> {
+        self.exit_status_file = match path.map(|p| p.into()) {
+            Some(path) => Some(path.canonicalize_safely().map_err(|err| {
+                tracing::error!(?path, ?err, "failed to canonicalize exit status file");
+                LibcontainerError::InvalidInput(format!(
+                    "invalid exit status file path {path:?}: {err:?}"
+                ))
+            })?),
+            None => None,
+        };
+
+        Ok(self)
+    }
+
     pub fn with_no_pivot(mut self, no_pivot: bool) -> Self {
         self.no_pivot = no_pivot;
         self
     }
 
+    /// Sets the partition mode of the container's cpuset (cgroup v2 only),
+    /// which is applied together with the cpus and mems of the spec. Use
+    /// `CpusetPartition::Root` to make the cpus exclusive to the container.
+    pub fn with_cpuset_partition(mut self, partition: Option<CpusetPartition>) -> Self {
+        self.cpuset_partition = partition;
+        self
+    }
+
+    /// Sets the swap limit of the container, the `memory.swap.max` of cgroup
+    /// v2: the swap alone, not the memory plus the swap like the `swap` of
+    /// the spec. It replaces the swap of the spec, so a limited swap needs a
+    /// memory limit of the spec to be derived from. `SwapLimit::Bytes(0)`
+    /// disables the swap.
+    ///
+    /// By default the swap of the spec applies. If the spec has none, or
+    /// its memory limit is -1, the swap isn't limited by the container and
+    /// it may swap as much as its parent cgroup allows.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    /// use libcgroups::common::SwapLimit;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_swap_limit(Some(SwapLimit::Bytes(0)))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_swap_limit(mut self, swap_limit: Option<SwapLimit>) -> Self {
+        self.swap_limit = swap_limit;
+        self
+    }
+
+    /// Sets the `pids.max` of the cgroup of the container, replacing the
+    /// pids limit of the spec, so a fork limit can be enforced on specs
+    /// that omit it. `-1` lifts the limit, other values must be positive.
+    /// The limit is applied with the other resources, before the workload
+    /// runs. By default the pids limit of the spec applies.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_pids_max_override(Some(4096))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_pids_max_override(mut self, pids_max: Option<i64>) -> Self {
+        self.pids_max_override = pids_max;
+        self
+    }
+
+    /// Sets if the container should only complete the create and start
+    /// handshake. Instead of executing the workload, the init process exits
+    /// successfully once the container is started, so `create` and `start`
+    /// can be tested end to end without a real image. This replaces the
+    /// executor set on the builder.
+    pub fn with_handshake_only(mut self, handshake_only: bool) -> Self {
+        self.handshake_only = handshake_only;
+        self
+    }
+
+    /// Sets if the cgroup filesystem is mounted read-only inside the
+    /// container. Only the container's view is read-only, the runtime can
+    /// still update, pause and resume the container through the host mount.
+    /// Defaults to read-only unless the container has `CAP_SYS_ADMIN` in its
+    /// bounding set, like runc.
+    pub fn with_cgroup_mount_readonly(mut self, readonly: bool) -> Self {
+        self.cgroup_mount_readonly = Some(readonly);
+        self
+    }
+
+    /// Sets if cgroup2 is mounted at `/sys/fs/cgroup` inside the container,
+    /// rooted at the container's own cgroup, for nested container workloads
+    /// that manage cgroups themselves. The container gets a cgroup namespace
+    /// if the spec has none, the cgroup mount of the spec is replaced and the
+    /// mount is read-write unless set otherwise with
+    /// [`Self::with_cgroup_mount_readonly`]. With a user namespace, the cgroup
+    /// is delegated to the root user of the container, so it can create
+    /// sub-cgroups. Requires the unified hierarchy, defaults to false.
+    pub fn with_mount_cgroup2_inside(mut self, mount: bool) -> Self {
+        self.mount_cgroup2_inside = mount;
+        self
+    }
+
+    /// Sets if the cgroup of the container is split, so a runtime in the
+    /// container can create cgroups with controllers. The processes of the
+    /// container live in an `init` leaf below the cgroup, and the controllers
+    /// of the cgroup are enabled for its children, which would otherwise
+    /// break the no internal processes rule of cgroup v2. The limits of the
+    /// spec, updates and stats apply to the cgroup as a whole, and the
+    /// removal of the container removes every cgroup below it. Requires
+    /// cgroup v2 without systemd, defaults to false.
+    pub fn with_nested_cgroup_delegation(mut self, nested: bool) -> Self {
+        self.nested_cgroup_delegation = nested;
+        self
+    }
+
+    /// Sets what to do with the hostname and domainname of the spec when the
+    /// container joins an existing UTS namespace by path. Defaults to
+    /// [`HostnamePolicy::Skip`].
+    pub fn with_hostname_policy(mut self, policy: HostnamePolicy) -> Self {
+        self.hostname_policy = policy;
+        self
+    }
+
+    /// Sets if the hostname and domainname of the spec are set when the
+    /// container doesn't get a uts namespace of its own. They are then set in
+    /// the uts namespace of the runtime, usually the one of the host, so
+    /// without this the build fails with
+    /// [`LibcontainerError::HostnameWithoutUtsNamespace`]. Defaults to false.
+    pub fn with_hostname_without_uts(mut self, allow: bool) -> Self {
+        self.hostname_without_uts = allow;
+        self
+    }
+
+    /// Sets if the effective hostname of the container is written into
+    /// `/etc/hostname` of the rootfs, unless the spec mounts it, see
+    /// [`hostname_file`]. It can also be enabled with the
+    /// [`HOSTNAME_FILE_ANNOTATION`](hostname_file::HOSTNAME_FILE_ANNOTATION)
+    /// annotation. Defaults to false.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_hostname_file(true);
+    /// ```
+    pub fn with_hostname_file(mut self, hostname_file: bool) -> Self {
+        self.hostname_file = hostname_file;
+        self
+    }
+
+    /// Sets if the mounts of the spec are applied before (the default) or
+    /// after pivot_root. See [`MountOrder`] for the differences.
+    pub fn with_mount_order(mut self, mount_order: MountOrder) -> Self {
+        self.mount_order = mount_order;
+        self
+    }
+
+    /// Sets if the audit login uid of the container process is reset to the
+    /// unset value, so the actions of the container aren't attributed to the
+    /// login session that created it in the host audit trail. Failing to
+    /// reset it is only a warning for containers in a user namespace, which
+    /// lack the required `CAP_AUDIT_CONTROL` in the host.
+    pub fn with_reset_loginuid(mut self, reset_loginuid: bool) -> Self {
+        self.reset_loginuid = reset_loginuid;
+        self
+    }
+
+    /// Sets if `/proc/sys` is remounted read-only in the container, even if
+    /// the spec doesn't list it in the readonly paths, e.g. because the
+    /// container has its own network namespace. The sysctls of the spec are
+    /// still applied, they are written before the remount.
+    pub fn with_proc_sys_readonly(mut self, readonly: bool) -> Self {
+        self.proc_sys_readonly = readonly;
+        self
+    }
+
+    /// Sets if a bind mount destination of the wrong type, e.g. a directory in
+    /// the image where the spec binds a file, is replaced by one of the right
+    /// type. Only empty destinations are replaced. Without it, such a mount
+    /// fails with an error naming both paths.
+    pub fn with_fix_mount_target_type(mut self, fix: bool) -> Self {
+        self.fix_mount_target_type = fix;
+        self
+    }
+
+    /// Sets if `/etc/mtab` is created as a symlink to `/proc/self/mounts`
+    /// when the image lacks it, which tools like `df` rely on. An existing
+    /// `/etc/mtab` is never replaced. Defaults to true.
+    pub fn with_mtab_symlink(mut self, mtab_symlink: bool) -> Self {
+        self.mtab_symlink = mtab_symlink;
+        self
+    }
+
+    /// Sets if the minimal set of devices programs expect, `/dev/null`,
+    /// `/dev/zero`, `/dev/full`, `/dev/random`, `/dev/urandom` and `/dev/tty`,
+    /// is created besides the devices of the spec. A device the rootfs already
+    /// has, or that the device cgroup rules of the spec explicitly deny, is
+    /// left out. Defaults to true. Without it, the spec has to list at least
+    /// `/dev/null`, which the runtime itself relies on in the container.
+    pub fn with_ensure_default_devices(mut self, ensure: bool) -> Self {
+        self.ensure_default_devices = ensure;
+        self
+    }
+
+    /// Sets if a container with a new IPC namespace gets an `mqueue` mounted
+    /// at `/dev/mqueue` when the spec doesn't mount one, so the POSIX message
+    /// queues of the namespace are visible. Defaults to true.
+    pub fn with_mqueue_mount(mut self, mount: bool) -> Self {
+        self.mqueue_mount = mount;
+        self
+    }
+
+    /// Sets the size in bytes of `/dev/shm` for a container with a new IPC
+    /// namespace. It is mounted when the spec doesn't mount it, and a `tmpfs`
+    /// of the spec without a size gets it. Defaults to
+    /// [`DEFAULT_SHM_SIZE`](crate::rootfs::ipc_mounts::DEFAULT_SHM_SIZE),
+    /// `None` leaves `/dev/shm` to the spec.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_shm_size(Some(256 * 1024 * 1024))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_shm_size(mut self, size: Option<u64>) -> Self {
+        self.shm_size = size;
+        self
+    }
+
+    /// Sets how many bytes the rootfs setup may write into the rootfs, which
+    /// is often a scratch layer charged against a quota. The create fails if
+    /// the setup writes more. The bytes written are reported in
+    /// [`CreateResult::rootfs_written`]. By default, there is no limit.
+    pub fn with_rootfs_write_limit(mut self, limit: Option<u64>) -> Self {
+        self.rootfs_write_limit = limit;
+        self
+    }
+
+    /// Sets the maximum size of the rootfs for ephemeral RAM-backed roots.
+    /// The init process overlays the rootfs with a writable layer on a tmpfs
+    /// of this size, so the container can't consume more host memory through
+    /// writes to its root and the image itself is left untouched. Writes
+    /// beyond the size fail with `ENOSPC`. The overlay is discarded with the
+    /// mount namespace of the container. Requires overlayfs, which a rootless
+    /// container only gets from Linux 5.11 on. By default, the rootfs is used
+    /// as is.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_rootfs_max_size(Some(256 * 1024 * 1024))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_rootfs_max_size(mut self, size: Option<u64>) -> Self {
+        self.rootfs_max_size = size;
+        self
+    }
+
+    /// Sets the limits the spec of the bundle is loaded with, see
+    /// [`spec_limits`](crate::spec_limits). The defaults are generous, only
+    /// specs of legitimately giant bundles need them raised.
+    pub fn with_spec_limits(mut self, limits: Limits) -> Self {
+        self.spec_limits = limits;
+        self
+    }
+
+    /// Sets if relative mount targets of the spec are taken relative to the
+    /// root of the container, by prefixing them with `/`. Otherwise, a spec
+    /// with a relative mount target is rejected, as its meaning is ambiguous.
+    pub fn with_prefix_relative_mount_targets(mut self, prefix: bool) -> Self {
+        self.prefix_relative_mount_targets = prefix;
+        self
+    }
+
+    /// Sets the uid and gid the container process runs as, taking precedence
+    /// over the user of the spec, e.g. to run a debug shell as root. The
+    /// supplementary groups of the spec user are not given to the forced user.
+    /// With a new user namespace, the ids must be mapped in it.
+    pub fn with_run_as_user(mut self, run_as_user: Option<(u32, u32)>) -> Self {
+        self.run_as_user = run_as_user;
+        self
+    }
+
+    /// Sets if the username of the spec is looked up in the container even
+    /// if the spec gives a uid or gid, see [`user`](crate::user). The spec
+    /// defines the username for Windows only, so by default it is only
+    /// looked up if both ids are 0.
+    pub fn with_resolve_username(mut self, resolve_username: bool) -> Self {
+        self.resolve_username = resolve_username;
+        self
+    }
+
+    /// Sets if the init process starts a new session. With
+    /// `kernel.sched_autogroup_enabled`, a new session is also a new
+    /// scheduler autogroup, so the CPU time of the host is shared fairly
+    /// between the container as a whole and the other sessions instead of
+    /// between their processes, e.g. a parallel build in an interactive
+    /// container doesn't starve the desktop. Without a new session, the
+    /// container stays in the session and autogroup of the caller.
+    ///
+    /// Autogroups only apply to processes in the root cgroup of the cpu
+    /// controller. Once the container has a cgroup with the cpu controller,
+    /// like with the cpu limits of the spec, the shares and quota of the
+    /// cgroup decide instead and the autogroup has no effect.
+    ///
+    /// A terminal needs a new session to become the controlling terminal of
+    /// the container, so it can't be combined with a console socket.
+    /// Defaults to true, like runc.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_new_session(false)
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_new_session(mut self, new_session: bool) -> Self {
+        self.new_session = new_session;
+        self
+    }
+
+    /// Sets what happens when the spec has a seccomp profile but disables
+    /// `noNewPrivileges` and the container process lacks `CAP_SYS_ADMIN`.
+    /// Such a filter can't be installed. If set, `no_new_privs` is enabled
+    /// with a warning, otherwise the build fails with
+    /// [`LibcontainerError::SeccompRequiresNoNewPrivs`]. Defaults to false.
+    pub fn with_auto_no_new_privs(mut self, auto_enable: bool) -> Self {
+        self.auto_no_new_privs = auto_enable;
+        self
+    }
+
+    /// Sets if the page cache is warmed with the entrypoint of the container
+    /// process and the shared libraries it loads before the process is
+    /// created, which saves page faults on a cold first start. This is best
+    /// effort and never fails the build. Defaults to false.
+    pub fn with_prewarm_rootfs(mut self, prewarm: bool) -> Self {
+        self.prewarm_rootfs = prewarm;
+        self
+    }
+
+    /// Sets if the memory nodes of the cpuset are set to the NUMA nodes of its
+    /// cpus when the spec pins cpus but no memory nodes, see [`numa`]. It
+    /// can also be enabled per container with the
+    /// [`NUMA_AUTO_MEMS_ANNOTATION`](numa::NUMA_AUTO_MEMS_ANNOTATION)
+    /// annotation. Defaults to false.
+    pub fn with_numa_auto_mems(mut self, auto_mems: bool) -> Self {
+        self.numa_auto_mems = auto_mems;
+        self
+    }
+
+    /// Sets if cpus and memory nodes of the cpuset that are not online are
+    /// dropped with a warning instead of failing the build, see [`cpuset`].
+    /// The warning is recorded in the
+    /// [`CPUSET_CLAMPED_ANNOTATION`](cpuset::CPUSET_CLAMPED_ANNOTATION)
+    /// annotation. Defaults to false.
+    pub fn with_clamp_cpuset_to_online(mut self, clamp: bool) -> Self {
+        self.clamp_cpuset_to_online = clamp;
+        self
+    }
+
+    /// Sets the prefix of the annotations that set env vars of the container
+    /// process, e.g. [`DEFAULT_ANNOTATION_ENV_PREFIX`](annotation_env::DEFAULT_ANNOTATION_ENV_PREFIX).
+    /// The env of the spec takes precedence, see [`annotation_env`]. By
+    /// default, annotations don't set env vars.
+    pub fn with_annotation_env_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.annotation_env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Sets env vars of the container process that override the env of the
+    /// spec and of the env files, see
+    /// [`ContainerBuilder::with_env_file`](crate::container::builder::ContainerBuilder::with_env_file)
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Sets if the build checks that the init process is still alive a short
+    /// while after it signaled readiness. If the init process exited in the
+    /// meantime, the container is saved as stopped and the build fails, so
+    /// callers don't report a container as created that is already gone.
+    pub fn with_confirm_liveness(mut self, confirm_liveness: bool) -> Self {
+        self.confirm_liveness = confirm_liveness;
+        self
+    }
+
+    /// Sets how long to wait before confirming the liveness of the init
+    /// process, defaults to [`DEFAULT_LIVENESS_DELAY`]
+    pub fn with_liveness_delay(mut self, delay: Duration) -> Self {
+        self.liveness_delay = delay;
+        self
+    }
+
+    /// Sets how long the create hooks of the container may take, counted from
+    /// the start of the container processes. The time left is passed to the
+    /// hooks in the [`CREATE_DEADLINE_ENV`](crate::hooks::CREATE_DEADLINE_ENV)
+    /// env var, and a hook still running when it is up is killed. By default,
+    /// the hooks have no timeout.
+    ///
+    /// Only the `createRuntime` and `createContainer` hooks are bounded, the
+    /// rest of the create isn't interrupted when the time is up. The wait for
+    /// the container processes is bounded by
+    /// [`with_intermediate_timeout`](super::builder::ContainerBuilder::with_intermediate_timeout).
+    pub fn with_create_timeout(mut self, timeout: Duration) -> Self {
+        self.create_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets what a termination signal, e.g. the SIGTERM of a supervisor,
+    /// does while the container is created. By default the process keeps its
+    /// handlers, so youki is terminated and may leave a partial container.
+    /// With [`CreateSignalPolicy::Cleanup`], the create fails with
+    /// [`LibcontainerError::CreateInterrupted`](crate::error::LibcontainerError::CreateInterrupted)
+    /// and the partial container is cleaned up.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    /// use libcontainer::create_signals::CreateSignalPolicy;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_create_signal_policy(CreateSignalPolicy::Cleanup)
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_create_signal_policy(mut self, policy: CreateSignalPolicy) -> Self {
+        self.create_signal_policy = policy;
+        self
+    }
+
+    /// Also attaches the container to a cgroup in a named cgroup v1
+    /// hierarchy, given as `name=<name>:<path>`, e.g. `name=ops:/teams/web`.
+    /// The cgroup is created if needed and removed with the container. Named
+    /// cgroups can also be set with the
+    /// [`EXTRA_CGROUP_ANNOTATION`](crate::config::EXTRA_CGROUP_ANNOTATION)
+    /// annotation. On a cgroup v2 only host, the build fails.
+    pub fn with_extra_cgroup_hierarchy<S: Into<String>>(mut self, hierarchy: S) -> Self {
+        self.extra_cgroup_hierarchies.push(hierarchy.into());
+        self
+    }
+
+    /// Sets the cgroup setup the cgroups of the container are managed with,
+    /// instead of detecting the setup of the host. With
+    /// [`CgroupSetup::Unmanaged`], no cgroup is created, joined or removed for
+    /// the container and the resources of the spec are ignored, for embedders
+    /// managing the cgroups themselves. Killing all the processes of such a
+    /// container signals the init process and its descendants, pausing and
+    /// resuming it fail with
+    /// [`LibcontainerError::UnsupportedWithoutCgroups`](crate::error::LibcontainerError::UnsupportedWithoutCgroups).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    /// use libcgroups::common::CgroupSetup;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_cgroup_setup(CgroupSetup::Unmanaged)
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_cgroup_setup(mut self, setup: CgroupSetup) -> Self {
+        self.cgroup_setup = Some(setup);
+        self
+    }
+
+    /// Sets how many bytes of each of stdout and stderr of a hook are kept in
+    /// its result, defaults to
+    /// [`DEFAULT_CAPTURE_LIMIT`](crate::output_capture::DEFAULT_CAPTURE_LIMIT).
+    /// The results are read with [`Container::hook_results`].
+    pub fn with_hook_output_limit(mut self, limit: usize) -> Self {
+        self.hook_output_limit = Some(limit);
+        self
+    }
+
+    /// Attaches the container to the shared volume of a group, creating the
+    /// volume if this is the first container of the group. The volume is
+    /// removed when the last container attached to it is deleted.
+    pub fn with_shared_volume(mut self, volume: SharedVolume) -> Self {
+        self.shared_volumes.push(volume);
+        self
+    }
+
+    /// Sets if the workload can report that it is ready over the readiness
+    /// socket, see [`notify_socket`](crate::notify_socket) for the protocol.
+    /// `NOTIFY_SOCKET` of the process points at the socket, and the caller
+    /// waits for the readiness with [`Container::readiness_listener`].
+    /// Defaults to false.
+    pub fn with_readiness_notify(mut self, readiness_notify: bool) -> Self {
+        self.readiness_notify = readiness_notify;
+        self
+    }
+
+    /// Sets the probe [`Container::start`] waits for before the container is
+    /// running, see [`readiness_probe`](crate::readiness_probe). If the probe
+    /// doesn't succeed, the start fails and the init process is killed. By
+    /// default, the container is running once the workload was told to start.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::readiness_probe::{ReadinessCheck, ReadinessProbe};
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// let probe = ReadinessProbe::new(ReadinessCheck::SocketConnect {
+    ///     path: "/run/app.sock".into(),
+    /// });
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_readiness_probe(Some(probe))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_readiness_probe(mut self, probe: Option<ReadinessProbe>) -> Self {
+        self.readiness_probe = probe;
+        self
+    }
+
+    /// Sets if the container gets a core scheduling cookie of its own, like
+    /// the [`CORE_SCHED_ANNOTATION`](crate::core_sched::CORE_SCHED_ANNOTATION)
+    /// does, see [`core_sched`](crate::core_sched). A container sharing the
+    /// cookie of a group by annotation shares it regardless. Defaults to
+    /// false.
+    pub fn with_core_sched(mut self, core_sched: bool) -> Self {
+        self.core_sched = core_sched;
+        self
+    }
+
+    /// Sets how the ambient capabilities of the container process are
+    /// resolved, see [`AmbientCapabilities`]. They are resolved after
+    /// `noNewPrivileges` is, which doesn't clear them, so a process without
+    /// file capabilities can still pass them on. Defaults to the ambient set
+    /// of the spec.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    /// use libcontainer::capabilities::AmbientCapabilities;
+    /// use oci_spec::runtime::Capability;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_ambient_capabilities(AmbientCapabilities::Set(
+    ///         [Capability::NetBindService].into_iter().collect(),
+    ///     ))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_ambient_capabilities(mut self, ambient: AmbientCapabilities) -> Self {
+        self.ambient_capabilities = ambient;
+        self
+    }
+
+    /// Sets if the ownership of the rootfs is shifted to the host ids the
+    /// user namespace of the spec maps its ids to, for kernels without
+    /// idmapped mounts, see [`ownership_shift`]. The shift is recorded next
+    /// to the rootfs, later creates with the same mappings skip it and an
+    /// interrupted one is resumed. A spec with idmapped mounts fails the
+    /// build. Defaults to false.
+    pub fn with_shift_rootfs_ownership(mut self, shift: bool) -> Self {
+        self.shift_rootfs_ownership = shift;
+        self
+    }
+
+    /// Sets what happens to the `/proc` mount of the spec when the container
+    /// joins a pid namespace or shares the one of the runtime, see
+    /// [`ProcMountPolicy`]. Defaults to mounting a new procfs.
+    pub fn with_proc_mount_policy(mut self, policy: ProcMountPolicy) -> Self {
+        self.proc_mount_policy = policy;
+        self
+    }
+
+    /// Sets what happens to the cgroup of the container when it joins an
+    /// existing cgroup namespace by path, see [`CgroupNamespacePolicy`].
+    /// Defaults to [`CgroupNamespacePolicy::Create`]. Mounting cgroup2 inside
+    /// the container contradicts joining a cgroup namespace and fails the
+    /// build with any policy.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::init_builder::CgroupNamespacePolicy;
+    /// # use libcontainer::error::LibcontainerError;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// # fn main() -> Result<(), LibcontainerError> {
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+    ///     .as_init("/var/run/docker/bundle")
+    ///     .with_cgroup_namespace_policy(CgroupNamespacePolicy::Inherit)
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_cgroup_namespace_policy(mut self, policy: CgroupNamespacePolicy) -> Self {
+        self.cgroup_namespace_policy = policy;
+        self
+    }
+
+    /// Sets if the subsystems that adjust the spec of the bundle are recorded
+    /// with it in the container directory, which
+    /// [`Container::spec_diff`] needs. Recording diffs the spec after every
+    /// adjustment, so it is off by default.
+    pub fn with_spec_provenance(mut self, record: bool) -> Self {
+        self.spec_provenance = record;
+        self
+    }
+
+    /// Records the spec in `resolved_spec` once every adjustment of the
+    /// builder is applied to it, for a
+    /// [`DebugContext`](crate::debug::DebugContext) of a failed create.
+    pub fn with_resolved_spec(mut self, resolved_spec: Option<ResolvedSpec>) -> Self {
+        self.resolved_spec = resolved_spec;
+        self
+    }
+
+    /// Returns if the container will be rootless, i.e. the spec of the bundle
+    /// has a user namespace and the runtime isn't real root. This is the
+    /// decision [`build`](Self::build) makes, the created container reports it
+    /// with [`Container::is_rootless`].
+    pub fn is_rootless(&self) -> Result<bool, LibcontainerError> {
+        let spec = self.load_spec()?;
+        Ok(UserNamespaceConfig::new(&spec)?.map_or(false, |config| config.is_rootless()))
+    }
+
     /// Creates a new container
     pub fn build(self) -> Result<Container, LibcontainerError> {
-        let spec = self.load_spec()?;
+        self.build_with_result().map(|(container, _)| container)
+    }
+
+    /// Creates a new container like [`InitContainerBuilder::build`], and
+    /// additionally returns the timings of the create phases, the resource
+    /// usage of the intermediate process and the namespaces that were
+    /// created.
+    pub fn build_with_result(self) -> Result<(Container, CreateResult), LibcontainerError> {
+        self.create(false)
+            .map(|created| (created.container, created.result))
+    }
+
+    /// Creates a new container whose init process is a sibling of the
+    /// caller, see [`InitContainerBuilder::as_sibling`], which must be set.
+    /// Like [`InitContainerBuilder::build_with_result`], and additionally
+    /// returns a pidfd of the init process.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use libcontainer::container::builder::ContainerBuilder;
+    /// use libcontainer::syscall::syscall::SyscallType;
+    /// use nix::sys::wait::{waitid, Id, WaitPidFlag};
+    /// use std::os::fd::AsFd;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let (mut container, sibling) = ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .as_init("/var/run/docker/bundle")
+    /// .as_sibling(true)
+    /// .build_sibling()?;
+    ///
+    /// container.start()?;
+    /// // Only the parent of the caller gets the exit status, anyone holding
+    /// // the pidfd can poll it for the exit.
+    /// let status = waitid(Id::PIDFd(sibling.pidfd.as_fd()), WaitPidFlag::WEXITED)?;
+    /// println!("init exited: {status:?}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn build_sibling(self) -> Result<(Container, SiblingCreateResult), LibcontainerError> {
+        if !self.as_sibling {
+            tracing::error!("the init process is not created as a sibling");
+            return Err(LibcontainerError::InvalidInput(
+                "build_sibling requires as_sibling".into(),
+            ));
+        }
+
+        let created = self.create(false)?;
+        let pidfd = created.init_pidfd.ok_or_else(|| {
+            LibcontainerError::Other("no pidfd of the sibling init process".into())
+        })?;
+        Ok((
+            created.container,
+            SiblingCreateResult {
+                result: created.result,
+                pidfd,
+            },
+        ))
+    }
+
+    /// Creates a new container like [`InitContainerBuilder::build_with_result`],
+    /// and returns the master of the pty allocated for the container process
+    /// in-process, instead of sending it over a console socket. The two are
+    /// mutually exclusive, so no console socket may be set. The caller owns the
+    /// returned fd, closing it hangs up the terminal of the container.
+    pub fn build_with_pty_master(
+        self,
+    ) -> Result<(Container, CreateResult, OwnedFd), LibcontainerError> {
+        if let Some(console_socket) = &self.base.console_socket {
+            tracing::error!(
+                ?console_socket,
+                "the pty master can't be returned when a console socket is set"
+            );
+            return Err(LibcontainerError::InvalidInput(format!(
+                "the pty master is sent to the console socket {console_socket:?}"
+            )));
+        }
+
+        let created = self.create(true)?;
+        let pty_master = created.pty_master.ok_or(tty::TTYError::MissingPtyMaster)?;
+        Ok((created.container, created.result, pty_master))
+    }
+
+    fn create(mut self, return_pty_master: bool) -> Result<Created, LibcontainerError> {
+        // The id ends up in paths and unit names, so it's checked before
+        // anything is derived from it.
+        validate_container_id(&self.base.container_id, self.base.max_id_len)?;
+        self.base.resolve_root_path()?;
+        let mut spec = self.read_spec()?;
+        let mut provenance = self.spec_provenance.then(|| SpecProvenance::new(&spec));
+        self.resolve_bundle_paths(&mut spec)?;
+        tag_provenance(&mut provenance, subsystem::BUNDLE, &spec);
+        let log_level = ContainerLogLevel::from_annotations(spec.annotations())?;
+        let _span = log_level::container_span(&self.base.container_id, log_level).entered();
+        self.validate_cpuset_partition(&spec)?;
+        if let Some(probe) = &self.readiness_probe {
+            probe.validate()?;
+        }
+        // A tmpfs of size 0 is unlimited.
+        if self.rootfs_max_size == Some(0) {
+            tracing::error!("rootfs max size must be positive");
+            return Err(LibcontainerError::InvalidInput(
+                "rootfs max size must be positive".to_owned(),
+            ));
+        }
+        if self.exit_status_file.is_some() && (!self.detached || self.as_sibling) {
+            tracing::error!(
+                detached = self.detached,
+                as_sibling = self.as_sibling,
+                "an exit status file requires a detached container that isn't a sibling"
+            );
+            return Err(LibcontainerError::InvalidInput(
+                "an exit status file requires a detached container that isn't a sibling".to_owned(),
+            ));
+        }
+        if !self.new_session && (self.base.console_socket.is_some() || return_pty_master) {
+            tracing::error!("a terminal requires the init process to start a new session");
+            return Err(LibcontainerError::InvalidInput(
+                "a terminal requires the init process to start a new session".to_owned(),
+            ));
+        }
+        Self::apply_swap_limit(&mut spec, self.swap_limit)?;
+        Self::apply_pids_max_override(&mut spec, self.pids_max_override)?;
+        tag_provenance(&mut provenance, subsystem::RESOURCES, &spec);
+        // The mems derived from the cpus must only see online cpus.
+        if cpuset::requested(&spec) {
+            let online = cpuset::OnlineIds::from_sysfs(
+                Path::new(cpuset::SYSFS_ONLINE_CPUS_PATH),
+                Path::new(cpuset::SYSFS_ONLINE_NODES_PATH),
+            )?;
+            cpuset::resolve_online(&mut spec, &online, self.clamp_cpuset_to_online)?;
+        }
+        if numa::auto_mems_enabled(&spec, self.numa_auto_mems) {
+            let topology = numa::NumaTopology::from_sysfs(Path::new(numa::SYSFS_CPU_PATH))?;
+            numa::apply_auto_mems(&mut spec, &topology)?;
+        }
+        tag_provenance(&mut provenance, subsystem::CPUSET, &spec);
+        self.apply_env(&mut spec)?;
+        if let Some(prefix) = &self.annotation_env_prefix {
+            annotation_env::apply(&mut spec, prefix)?;
+        }
+        tag_provenance(&mut provenance, subsystem::ENV, &spec);
+        Self::validate_hostname_policy(&spec, self.hostname_policy)?;
+        Self::validate_hostname_without_uts(&spec, self.hostname_without_uts)?;
+        Self::resolve_hostname(&mut spec, self.hostname_policy);
+        tag_provenance(&mut provenance, subsystem::HOSTNAME, &spec);
+        Self::validate_run_as_user(&spec, self.run_as_user)?;
+        Self::resolve_seccomp_no_new_privs(&mut spec, self.auto_no_new_privs)?;
+        Self::resolve_ambient_capabilities(&mut spec, &self.ambient_capabilities)?;
+        tag_provenance(&mut provenance, subsystem::POLICY, &spec);
+        let core_sched = self.resolve_core_sched(&spec)?;
+        self.cgroup_setup = Self::resolve_cgroup_namespace_policy(
+            &spec,
+            self.cgroup_namespace_policy,
+            self.mount_cgroup2_inside,
+            self.cgroup_setup,
+        )?;
+        let unmanaged_cgroups = self.cgroup_setup == Some(CgroupSetup::Unmanaged);
+        if unmanaged_cgroups {
+            self.validate_unmanaged_cgroups(&spec)?;
+        } else {
+            Self::validate_cgroup_delegation(&spec)?;
+        }
+        tag_provenance(&mut provenance, subsystem::ROOTLESS, &spec);
+        Self::prepare_cgroup2_mount(&mut spec, self.mount_cgroup2_inside)?;
+        Self::apply_proc_mount_policy(&mut spec, self.proc_mount_policy)?;
+        ipc_mounts::apply(&mut spec, self.mqueue_mount, self.shm_size)?;
+        Self::validate_extra_cgroup_hierarchies(&spec, &self.extra_cgroup_hierarchies)?;
+        Self::resolve_relative_mount_targets(&mut spec, self.prefix_relative_mount_targets)?;
+        Self::validate_mount_options(&spec)?;
+        tag_provenance(&mut provenance, subsystem::MOUNTS, &spec);
+        if hostname_file::enabled(&spec, self.hostname_file) {
+            hostname_file::resolve(&mut spec);
+        }
+        tag_provenance(&mut provenance, subsystem::HOSTNAME_FILE, &spec);
+        if self.shift_rootfs_ownership {
+            let rootfs = spec.root().as_ref().ok_or(MissingSpecError::Root)?.path();
+            ownership_shift::apply(&spec, rootfs, ownership_shift::Budget::default())?;
+        }
+        // The sockets are removed again if the create fails from here on.
+        let listening_sockets = socket_handoff::prepare(&mut spec)?;
+        tag_provenance(&mut provenance, subsystem::SOCKET_HANDOFF, &spec);
         let container_dir = self.create_container_dir()?;
 
         let mut container = self.create_container_state(&container_dir)?;
         container
             .set_systemd(self.use_systemd)
-            .set_annotations(spec.annotations().clone());
+            .set_annotations(spec.annotations().clone())
+            .set_log_level(log_level)
+            .set_ipc_sysctls(sysctl::ipc_sysctls(&spec))
+            .set_unmanaged_cgroups(unmanaged_cgroups)
+            .set_core_sched(core_sched.is_some());
+        self.attach_shared_volumes(&mut container, &mut spec)?;
+        tag_provenance(&mut provenance, subsystem::SHARED_VOLUMES, &spec);
+        if self.readiness_notify {
+            notify_socket::prepare_readiness(&mut spec, &container_dir)?;
+        }
+        tag_provenance(&mut provenance, subsystem::READINESS, &spec);
+        if let Some(resolved_spec) = &self.resolved_spec {
+            resolved_spec.set(&spec);
+        }
 
         let notify_path = container_dir.join(NOTIFY_FILE);
         // convert path of root file system of the container to absolute path
         let rootfs = fs::canonicalize(spec.root().as_ref().ok_or(MissingSpecError::Root)?.path())
             .map_err(LibcontainerError::OtherIO)?;
+        if self.prewarm_rootfs {
+            if let Some(process) = spec.process() {
+                prewarm::prewarm_rootfs(&rootfs, process);
+            }
+        }
 
         // if socket file path is given in commandline options,
         // get file descriptors of console socket
+        let mut pty_master_socket = None;
         let csocketfd = if let Some(console_socket) = &self.base.console_socket {
             Some(tty::setup_console_socket(
                 &container_dir,
                 console_socket,
                 "console-socket",
+                self.base.console_socket_timeout,
             )?)
+        } else if return_pty_master {
+            let (init_socket, socket) = tty::create_pty_master_socket()?;
+            pty_master_socket = Some(socket);
+            Some(init_socket)
         } else {
             None
         };
 
         let user_ns_config = UserNamespaceConfig::new(&spec)?;
 
-        let config = YoukiConfig::from_spec(&spec, container.id())?;
+        let mut config = YoukiConfig::from_spec(&spec, container.id())?;
+        config
+            .extra_cgroup_hierarchies
+            .extend(self.extra_cgroup_hierarchies.iter().cloned());
+        config.nested_cgroup_delegation = self.nested_cgroup_delegation;
+        config.readiness_notify = self.readiness_notify;
+        config.readiness_probe = self.readiness_probe.clone();
         config.save(&container_dir).map_err(|err| {
             tracing::error!(?container_dir, "failed to save config: {}", err);
             err
         })?;
 
+        let cgroup_mount_readonly = self
+            .cgroup_mount_readonly
+            .unwrap_or_else(|| !self.mount_cgroup2_inside && !is_privileged(&spec));
+
+        let stdin = match self.base.stdin {
+            Some(stdin) => Some(stdin),
+            None if self.detached && self.detached_null_stdin && csocketfd.is_none() => {
+                Some(open_null_stdin()?)
+            }
+            None => None,
+        };
+
         let mut builder_impl = ContainerBuilderImpl {
             container_type: ContainerType::InitContainer,
             syscall: self.base.syscall,
             container_id: self.base.container_id,
             pid_file: self.base.pid_file,
             console_socket: csocketfd,
+            console_socket_timeout: self.base.console_socket_timeout,
+            intermediate_timeout: self.base.intermediate_timeout,
             use_systemd: self.use_systemd,
             spec: Rc::new(spec),
             rootfs,
             user_ns_config,
+            cpuset_partition: self.cpuset_partition,
+            cgroup_mount_readonly,
+            delegate_cgroup: self.mount_cgroup2_inside,
+            hostname_policy: self.hostname_policy,
+            hostname_without_uts: self.hostname_without_uts,
+            mount_order: self.mount_order,
+            reset_loginuid: self.reset_loginuid,
+            proc_sys_readonly: self.proc_sys_readonly,
+            fix_mount_target_type: self.fix_mount_target_type,
+            mtab_symlink: self.mtab_symlink,
+            ensure_default_devices: self.ensure_default_devices,
+            rootfs_write_limit: self.rootfs_write_limit,
+            rootfs_max_size: self.rootfs_max_size,
+            run_as_user: self.run_as_user,
+            resolve_username: self.resolve_username,
+            new_session: self.new_session,
+            exit_status_file: self.exit_status_file,
             notify_path,
             container: Some(container.clone()),
+            joined_container_state: None,
             preserve_fds: self.base.preserve_fds,
+            socket_fds: listening_sockets.fds(),
             detached: self.detached,
-            executor: self.base.executor,
+            executor: if self.handshake_only {
+                Box::new(HandshakeOnlyExecutor {})
+            } else {
+                self.base.executor
+            },
             no_pivot: self.no_pivot,
-            stdin: self.base.stdin,
+            stdin,
             stdout: self.base.stdout,
             stderr: self.base.stderr,
             as_sibling: self.as_sibling,
+            tolerate_dumpable_eperm: self.base.tolerate_dumpable_eperm,
+            seccomp_notify_timeout: self.base.seccomp_notify_timeout,
+            default_path: self.base.default_path,
+            inject_default_path: self.base.inject_default_path,
+            argv0_override: self.base.argv0_override,
+            create_timeout: self.create_timeout,
+            core_sched,
+            create_signal_policy: self.create_signal_policy,
+            extra_cgroup_hierarchies: config.extra_cgroup_hierarchies.clone(),
+            nested_cgroup_delegation: config.nested_cgroup_delegation,
+            cgroup_setup: self.cgroup_setup,
+            fault_injection: self.base.fault_injection,
         };
 
-        builder_impl.create()?;
+        builder_impl.to_descriptor().save(&container_dir)?;
+        if let Some(provenance) = &provenance {
+            provenance.save(&container_dir)?;
+        }
+        let created = builder_impl.create()?;
+        listening_sockets.keep();
+        stdio_file::watch(self.base.stdio_files, created.init_pid);
+        // The init process sends the pty master before it reports to be ready,
+        // so it is already waiting in the socket.
+        let pty_master = pty_master_socket
+            .as_ref()
+            .map(tty::receive_pty_master)
+            .transpose()?;
 
         container.refresh_state()?;
+        if self.confirm_liveness {
+            confirm_liveness(&mut container, self.liveness_delay)?;
+        }
 
-        Ok(container)
+        Ok(Created {
+            container,
+            result: CreateResult {
+                pid: created.init_pid,
+                timings: created.timings,
+                child_rusage: created.child_rusage,
+                pid_file_written: created.pid_file_contents.is_some(),
+                pid_file: created.pid_file_contents,
+                namespace_flags: created.namespace_flags,
+                rootfs_written: created.rootfs_written,
+                rootfs_fs_type: created.rootfs_fs_type,
+                user: created.user,
+            },
+            pty_master,
+            init_pidfd: created.init_pidfd,
+        })
+    }
+
+    fn attach_shared_volumes(
+        &self,
+        container: &mut Container,
+        spec: &mut Spec,
+    ) -> Result<(), LibcontainerError> {
+        if self.shared_volumes.is_empty() {
+            return Ok(());
+        }
+
+        // The groups are recorded in the state before attaching, so a
+        // concurrent delete of another container of the group doesn't take
+        // the new references for stale ones.
+        container
+            .set_shared_volumes(
+                self.shared_volumes
+                    .iter()
+                    .map(|volume| volume.group_id.clone())
+                    .collect(),
+            )
+            .save()?;
+
+        SharedVolumeManager::new(&self.base.root_path)
+            .with_state_store(self.base.state_store.clone())
+            .attach_all(spec, container.id(), &self.shared_volumes)?;
+
+        Ok(())
     }
 
     fn create_container_dir(&self) -> Result<PathBuf, LibcontainerError> {
@@ -146,17 +1374,99 @@ impl InitContainerBuilder {
         Ok(container_dir)
     }
 
+    /// Reads the spec of the bundle with the paths resolved against it
     fn load_spec(&self) -> Result<Spec, LibcontainerError> {
+        let mut spec = self.read_spec()?;
+        self.resolve_bundle_paths(&mut spec)?;
+
+        Ok(spec)
+    }
+
+    /// Reads and validates the spec of the bundle as it is. It is read only
+    /// once per create, the container processes inherit the resolved spec in
+    /// memory, so rewriting the config.json during the create changes nothing
+    /// in the container. The spec is deliberately not handed to them through
+    /// a sealed memfd, the clone already gives every stage the identical spec
+    /// without the serialization and the parses a memfd would add.
+    fn read_spec(&self) -> Result<Spec, LibcontainerError> {
         let source_spec_path = self.bundle.join("config.json");
-        let mut spec = Spec::load(source_spec_path)?;
+        let spec = self.spec_limits.load_spec(source_spec_path)?;
         Self::validate_spec(&spec)?;
 
-        spec.canonicalize_rootfs(&self.bundle).map_err(|err| {
-            tracing::error!(bundle = ?self.bundle, "failed to canonicalize rootfs: {}", err);
+        Ok(spec)
+    }
+
+    /// Resolves the rootfs and the relative hook paths against the bundle
+    fn resolve_bundle_paths(&self, spec: &mut Spec) -> Result<(), LibcontainerError> {
+        let bundle = self.resolve_bundle.as_ref().unwrap_or(&self.bundle);
+        let bundle = fs::canonicalize(bundle).map_err(|err| {
+            tracing::error!(?bundle, ?err, "failed to canonicalize bundle");
+            LibcontainerError::InvalidInput(format!("invalid bundle {bundle:?}: {err:?}"))
+        })?;
+        spec.canonicalize_rootfs(&bundle).map_err(|err| {
+            tracing::error!(?bundle, "failed to canonicalize rootfs: {}", err);
             err
         })?;
+        Self::resolve_relative_hook_paths(spec, &bundle);
 
-        Ok(spec)
+        Ok(())
+    }
+
+    /// Layers the env of the process on the env files and the explicit env
+    fn apply_env(&self, spec: &mut Spec) -> Result<(), LibcontainerError> {
+        if self.base.env_files.is_empty() && self.env.is_empty() {
+            return Ok(());
+        }
+        let files = env_file::load(&self.base.env_files)?;
+        let process = match spec.process_mut() {
+            Some(process) => process,
+            None => return Ok(()),
+        };
+        let env = env_file::merge(
+            &files,
+            process.env().as_deref().unwrap_or_default(),
+            &self.env,
+        );
+        tracing::debug!(
+            files = ?self.base.env_files,
+            count = env.len(),
+            "layered env of the process"
+        );
+        process.set_env(Some(env));
+        // The files may add more vars than the spec was allowed to have.
+        self.spec_limits.check_counts(spec)?;
+
+        Ok(())
+    }
+
+    fn resolve_relative_hook_paths(spec: &mut Spec, bundle: &Path) {
+        let mut hooks = match spec.hooks() {
+            Some(hooks) => hooks.clone(),
+            None => return,
+        };
+
+        let resolve = |hooks: &Option<Vec<Hook>>| {
+            hooks.clone().map(|hooks| {
+                hooks
+                    .into_iter()
+                    .map(|mut hook| {
+                        if hook.path().is_relative() {
+                            let path = bundle.join(hook.path());
+                            tracing::debug!(?path, "resolved relative hook path");
+                            hook.set_path(path);
+                        }
+                        hook
+                    })
+                    .collect()
+            })
+        };
+        hooks.set_prestart(resolve(hooks.prestart()));
+        hooks.set_create_runtime(resolve(hooks.create_runtime()));
+        hooks.set_create_container(resolve(hooks.create_container()));
+        hooks.set_start_container(resolve(hooks.start_container()));
+        hooks.set_poststart(resolve(hooks.poststart()));
+        hooks.set_poststop(resolve(hooks.poststop()));
+        spec.set_hooks(Some(hooks));
     }
 
     fn validate_spec(spec: &Spec) -> Result<(), LibcontainerError> {
@@ -201,19 +1511,1507 @@ impl InitContainerBuilder {
         }
 
         utils::validate_spec_for_new_user_ns(spec)?;
+        sysctl::validate(spec)?;
 
         Ok(())
     }
 
-    fn create_container_state(&self, container_dir: &Path) -> Result<Container, LibcontainerError> {
-        let container = Container::new(
-            &self.base.container_id,
-            ContainerStatus::Creating,
-            None,
-            &self.bundle,
-            container_dir,
-        )?;
-        container.save()?;
-        Ok(container)
+    /// A root or isolated partition needs an explicit set of cpus, otherwise
+    /// the kernel rejects the partition as invalid.
+    fn validate_cpuset_partition(&self, spec: &Spec) -> Result<(), LibcontainerError> {
+        let partition = match self.cpuset_partition {
+            None | Some(CpusetPartition::Member) => return Ok(()),
+            Some(partition) => partition,
+        };
+
+        let has_cpus = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref())
+            .and_then(|resources| resources.cpu().as_ref())
+            .and_then(|cpu| cpu.cpus().as_ref())
+            .map_or(false, |cpus| !cpus.trim().is_empty());
+        if !has_cpus {
+            tracing::error!(
+                %partition,
+                "cpuset partition requires linux.resources.cpu.cpus to be set"
+            );
+            Err(ErrInvalidSpec::CpusetPartition)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the swap of the spec with the one of the swap limit, which is
+    /// on top of the memory limit of the spec.
+    fn apply_swap_limit(
+        spec: &mut Spec,
+        swap_limit: Option<SwapLimit>,
+    ) -> Result<(), LibcontainerError> {
+        let swap_limit = match swap_limit {
+            Some(swap_limit) => swap_limit,
+            None => return Ok(()),
+        };
+
+        let linux = spec.linux_mut().as_mut().ok_or(MissingSpecError::Linux)?;
+        let mut resources = linux.resources().clone().unwrap_or_default();
+        let mut memory = resources.memory().clone().unwrap_or_default();
+        let limit = memory.limit();
+        let swap = match swap_limit.to_spec_swap(limit) {
+            Some(swap) => swap,
+            None => {
+                tracing::error!(
+                    %swap_limit,
+                    ?limit,
+                    "swap limit requires linux.resources.memory.limit to be set"
+                );
+                Err(ErrInvalidSpec::SwapLimit)?
+            }
+        };
+        memory.set_swap(Some(swap));
+        resources.set_memory(Some(memory));
+        linux.set_resources(Some(resources));
+
+        Ok(())
+    }
+
+    /// The cgroup managers write a limit below 1 as `max`, so 0 would lift
+    /// the limit instead of forbidding any process.
+    fn apply_pids_max_override(
+        spec: &mut Spec,
+        pids_max: Option<i64>,
+    ) -> Result<(), LibcontainerError> {
+        let pids_max = match pids_max {
+            Some(pids_max) => pids_max,
+            None => return Ok(()),
+        };
+        if pids_max < 1 && pids_max != -1 {
+            tracing::error!(pids_max, "pids.max override must be positive or -1");
+            Err(ErrInvalidSpec::PidsMaxOverride(pids_max))?
+        }
+
+        let linux = spec.linux_mut().as_mut().ok_or(MissingSpecError::Linux)?;
+        let mut resources = linux.resources().clone().unwrap_or_default();
+        resources.set_pids(Some(LinuxPidsBuilder::default().limit(pids_max).build()?));
+        linux.set_resources(Some(resources));
+
+        Ok(())
+    }
+
+    fn validate_run_as_user(
+        spec: &Spec,
+        run_as_user: Option<(u32, u32)>,
+    ) -> Result<(), LibcontainerError> {
+        let (uid, gid) = match run_as_user {
+            Some(ids) => ids,
+            None => return Ok(()),
+        };
+        let linux = match spec.linux() {
+            Some(linux) => linux,
+            None => return Ok(()),
+        };
+
+        // Without mappings, the ids are not translated and any id is valid.
+        if let Some(uid_mappings) = linux.uid_mappings().as_ref().filter(|m| !m.is_empty()) {
+            if !user_ns::is_id_mapped(uid, uid_mappings) {
+                tracing::error!(?uid, "uid to run the container as is not mapped");
+                Err(ErrInvalidSpec::RunAsUidNotMapped(uid))?;
+            }
+        }
+        if let Some(gid_mappings) = linux.gid_mappings().as_ref().filter(|m| !m.is_empty()) {
+            if !user_ns::is_id_mapped(gid, gid_mappings) {
+                tracing::error!(?gid, "gid to run the container as is not mapped");
+                Err(ErrInvalidSpec::RunAsGidNotMapped(gid))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Without `CAP_SYS_ADMIN`, the kernel only accepts a seccomp filter from
+    /// a thread with `no_new_privs` set. A spec that disables it for an
+    /// unprivileged process would fail late in the init process with an
+    /// opaque EACCES, so it is resolved up front.
+    fn resolve_seccomp_no_new_privs(
+        spec: &mut Spec,
+        auto_enable: bool,
+    ) -> Result<(), LibcontainerError> {
+        let has_seccomp = spec
+            .linux()
+            .as_ref()
+            .map_or(false, |linux| linux.seccomp().is_some());
+        if !has_seccomp || is_privileged(spec) {
+            return Ok(());
+        }
+        let process = match spec.process_mut() {
+            Some(process) if process.no_new_privileges() == &Some(false) => process,
+            _ => return Ok(()),
+        };
+
+        if !auto_enable {
+            tracing::error!("seccomp requires noNewPrivileges for a process without CAP_SYS_ADMIN");
+            return Err(LibcontainerError::SeccompRequiresNoNewPrivs);
+        }
+        tracing::warn!(
+            "enabling noNewPrivileges, seccomp can't be set up without it for a process without CAP_SYS_ADMIN"
+        );
+        process.set_no_new_privileges(Some(true));
+
+        Ok(())
+    }
+
+    fn resolve_ambient_capabilities(
+        spec: &mut Spec,
+        policy: &AmbientCapabilities,
+    ) -> Result<(), LibcontainerError> {
+        let process = match spec.process_mut() {
+            Some(process) => process,
+            None => return Ok(()),
+        };
+        let mut caps = match process.capabilities() {
+            Some(caps) => caps.clone(),
+            None if *policy == AmbientCapabilities::Spec => return Ok(()),
+            None => Default::default(),
+        };
+        capabilities::resolve_ambient(&mut caps, policy)?;
+        process.set_capabilities(Some(caps));
+
+        Ok(())
+    }
+
+    /// A rootless container can only create cgroups in the subtree delegated
+    /// to the user, an absolute cgroups path elsewhere would only fail later
+    /// with a permission error while the cgroup is created.
+    fn validate_cgroup_delegation(spec: &Spec) -> Result<(), LibcontainerError> {
+        let cgroups_path = match spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.cgroups_path().as_ref())
+            .filter(|path| path.is_absolute())
+        {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let syscall = create_syscall();
+        if !utils::rootless_required(&*syscall).map_err(LibcontainerError::OtherIO)? {
+            return Ok(());
+        }
+
+        // Delegation is a cgroup v2 concept, the unified hierarchy has id 0.
+        let own_cgroup = procfs::process::Process::myself()?
+            .cgroups()?
+            .0
+            .into_iter()
+            .find(|cgroup| cgroup.hierarchy == 0)
+            .map(|cgroup| PathBuf::from(cgroup.pathname));
+        let own_cgroup = match own_cgroup {
+            Some(cgroup) => cgroup,
+            None => return Ok(()),
+        };
+        let delegated_root = delegated_cgroup_root(
+            Path::new(DEFAULT_CGROUP_ROOT),
+            &own_cgroup,
+            syscall.get_euid().as_raw(),
+        );
+
+        Self::check_cgroup_path_delegation(cgroups_path, &delegated_root)
+    }
+
+    /// Resolves the core scheduling the spec asks for. The cookie of a group
+    /// is shared from the init process of the group container, which must be
+    /// alive and have a cookie itself.
+    fn resolve_core_sched(&self, spec: &Spec) -> Result<Option<CoreSched>, LibcontainerError> {
+        let group = match core_sched::requested(spec, self.core_sched)? {
+            None => return Ok(None),
+            Some(CoreSchedRequest::Create) => return Ok(Some(CoreSched::Create)),
+            Some(CoreSchedRequest::Group(group)) => group,
+        };
+        validate_container_id(&group, self.base.max_id_len)?;
+        let group_dir = self.base.root_path.join(&group);
+        if !group_dir.exists() {
+            Err(CoreSchedError::GroupNotFound(group.clone()))?;
+        }
+        let container = Container::load_with_store(group_dir, self.base.state_store.clone())?;
+        if !container.core_sched() {
+            Err(CoreSchedError::GroupWithoutCookie(group))?;
+        }
+        match container.pid() {
+            Some(pid)
+                if matches!(
+                    container.status(),
+                    ContainerStatus::Created | ContainerStatus::Running | ContainerStatus::Paused
+                ) =>
+            {
+                tracing::debug!(%group, ?pid, "sharing the core scheduling cookie of the group");
+                Ok(Some(CoreSched::ShareFrom(pid)))
+            }
+            _ => Err(CoreSchedError::GroupNotRunning(group).into()),
+        }
+    }
+
+    /// Without cgroup management, the options working on the cgroup of the
+    /// container have no cgroup to act on.
+    fn validate_unmanaged_cgroups(&self, spec: &Spec) -> Result<(), LibcontainerError> {
+        let option = if self.mount_cgroup2_inside {
+            Some("mounting cgroup2 inside")
+        } else if self.nested_cgroup_delegation {
+            Some("nested cgroup delegation")
+        } else if !self.extra_cgroup_hierarchies.is_empty()
+            || !config::extra_cgroup_hierarchies(spec).is_empty()
+        {
+            Some("named cgroup hierarchies")
+        } else {
+            None
+        };
+        if let Some(option) = option {
+            tracing::error!(option, "option requires managed cgroups");
+            return Err(LibcontainerError::InvalidInput(format!(
+                "{option} requires managed cgroups"
+            )));
+        }
+        if spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref())
+            .is_some()
+        {
+            tracing::warn!("the resources of the spec are ignored without managed cgroups");
+        }
+
+        Ok(())
+    }
+
+    /// Named hierarchies only exist in cgroup v1, so a cgroup v2 only host
+    /// fails before anything is created for the container.
+    fn validate_extra_cgroup_hierarchies(
+        spec: &Spec,
+        hierarchies: &[String],
+    ) -> Result<(), LibcontainerError> {
+        let mut hierarchies = hierarchies.to_vec();
+        hierarchies.extend(config::extra_cgroup_hierarchies(spec));
+        if hierarchies.is_empty() {
+            return Ok(());
+        }
+        if matches!(
+            libcgroups::common::get_cgroup_setup()?,
+            CgroupSetup::Unified
+        ) {
+            tracing::error!(?hierarchies, "named cgroup hierarchies require cgroup v1");
+            return Err(CreateCgroupSetupError::ExtraHierarchiesUnsupported(hierarchies).into());
+        }
+
+        Ok(())
+    }
+
+    fn apply_proc_mount_policy(
+        spec: &mut Spec,
+        policy: ProcMountPolicy,
+    ) -> Result<(), LibcontainerError> {
+        let pid_namespace = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.namespaces().as_ref())
+            .and_then(|namespaces| {
+                namespaces
+                    .iter()
+                    .find(|ns| ns.typ() == LinuxNamespaceType::Pid)
+            });
+        let shares_runtime_pids = match pid_namespace {
+            Some(ns) if ns.path().is_none() => return Ok(()),
+            Some(_) => false,
+            None => true,
+        };
+        let mut mounts = match spec.mounts() {
+            Some(mounts) => mounts.clone(),
+            None => return Ok(()),
+        };
+        let index = match mounts.iter().position(|mount| {
+            mount.destination() == Path::new("/proc") && mount.typ().as_deref() == Some("proc")
+        }) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        match policy {
+            ProcMountPolicy::Mount => return Ok(()),
+            ProcMountPolicy::SkipShared => {
+                tracing::debug!("no new pid namespace, not mounting /proc");
+                mounts.remove(index);
+            }
+            ProcMountPolicy::BindShared if shares_runtime_pids => {
+                tracing::debug!("pid namespace of the runtime is shared, binding its /proc");
+                mounts[index] = MountBuilder::default()
+                    .destination("/proc")
+                    .typ("bind")
+                    .source("/proc")
+                    .options(
+                        ["rbind", "nosuid", "noexec", "nodev"]
+                            .map(String::from)
+                            .to_vec(),
+                    )
+                    .build()?;
+            }
+            ProcMountPolicy::BindShared => return Ok(()),
+        }
+        spec.set_mounts(Some(mounts));
+
+        Ok(())
+    }
+
+    /// Mounting cgroup2 at `/sys/fs/cgroup` only shows the container's own
+    /// cgroup as the root in a cgroup namespace, which is added if the spec
+    /// doesn't have one.
+    fn prepare_cgroup2_mount(spec: &mut Spec, enabled: bool) -> Result<(), LibcontainerError> {
+        if !enabled {
+            return Ok(());
+        }
+        let setup = libcgroups::common::get_cgroup_setup()?;
+        if !matches!(setup, CgroupSetup::Unified) {
+            tracing::error!(
+                ?setup,
+                "cgroup2 can only be mounted inside on the unified hierarchy"
+            );
+            return Err(LibcontainerError::Cgroup2MountRequiresUnified);
+        }
+
+        let linux = spec.linux_mut().as_mut().ok_or(MissingSpecError::Linux)?;
+        let mut namespaces = linux.namespaces().clone().unwrap_or_default();
+        if !namespaces
+            .iter()
+            .any(|ns| ns.typ() == LinuxNamespaceType::Cgroup)
+        {
+            tracing::debug!("adding a cgroup namespace to mount cgroup2 inside");
+            namespaces.push(
+                LinuxNamespaceBuilder::default()
+                    .typ(LinuxNamespaceType::Cgroup)
+                    .build()?,
+            );
+            linux.set_namespaces(Some(namespaces));
+        }
+
+        let cgroup_mount = MountBuilder::default()
+            .destination(DEFAULT_CGROUP_ROOT)
+            .typ("cgroup")
+            .source("cgroup")
+            .options(
+                ["nosuid", "noexec", "nodev", "relatime", "rw"]
+                    .map(String::from)
+                    .to_vec(),
+            )
+            .build()?;
+        let mut mounts = spec.mounts().clone().unwrap_or_default();
+        // The cgroup mount has to stay behind the mount of /sys, so one of the
+        // spec is replaced in place.
+        match mounts
+            .iter()
+            .position(|mount| mount.destination() == Path::new(DEFAULT_CGROUP_ROOT))
+        {
+            Some(index) => mounts[index] = cgroup_mount,
+            None => mounts.push(cgroup_mount),
+        }
+        spec.set_mounts(Some(mounts));
+
+        Ok(())
+    }
+
+    /// Returns the cgroup setup of a container that joins an existing cgroup
+    /// namespace by path, or fails if the configuration contradicts the view
+    /// of the joined namespace.
+    fn resolve_cgroup_namespace_policy(
+        spec: &Spec,
+        policy: CgroupNamespacePolicy,
+        mount_cgroup2_inside: bool,
+        cgroup_setup: Option<CgroupSetup>,
+    ) -> Result<Option<CgroupSetup>, LibcontainerError> {
+        let joined = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.namespaces().as_ref())
+            .and_then(|namespaces| {
+                namespaces
+                    .iter()
+                    .find(|ns| ns.typ() == LinuxNamespaceType::Cgroup)
+            })
+            .and_then(|ns| ns.path().as_ref());
+        let joined = match joined {
+            Some(joined) => joined,
+            None => return Ok(cgroup_setup),
+        };
+        // The mount would show the root of the joined namespace, not the
+        // cgroup of the container.
+        if mount_cgroup2_inside {
+            tracing::error!(
+                ?joined,
+                "cgroup2 can't be mounted inside when joining an existing cgroup namespace"
+            );
+            return Err(LibcontainerError::Cgroup2MountWithJoinedCgroupNamespace);
+        }
+
+        let managed = cgroup_setup != Some(CgroupSetup::Unmanaged);
+        match policy {
+            CgroupNamespacePolicy::Create => {
+                if managed {
+                    tracing::debug!(
+                        ?joined,
+                        "creating the cgroup of the container in the joined cgroup namespace"
+                    );
+                }
+                Ok(cgroup_setup)
+            }
+            CgroupNamespacePolicy::Reject if managed => {
+                tracing::error!(
+                    ?joined,
+                    "the container can't get a cgroup of its own when joining an existing cgroup namespace"
+                );
+                Err(ErrInvalidSpec::CgroupWithJoinedCgroupNamespace.into())
+            }
+            CgroupNamespacePolicy::Reject => Ok(cgroup_setup),
+            CgroupNamespacePolicy::Inherit => match cgroup_setup {
+                None | Some(CgroupSetup::Unmanaged) => {
+                    tracing::debug!(
+                        ?joined,
+                        "not managing a cgroup, the container inherits the cgroup of the caller"
+                    );
+                    Ok(Some(CgroupSetup::Unmanaged))
+                }
+                Some(setup) => {
+                    tracing::error!(
+                        ?joined,
+                        ?setup,
+                        "cgroup setup contradicts inheriting the cgroup of the caller"
+                    );
+                    Err(LibcontainerError::InvalidInput(format!(
+                        "cgroup setup {setup:?} contradicts inheriting the cgroup of a joined cgroup namespace"
+                    )))
+                }
+            },
+        }
+    }
+
+    fn check_cgroup_path_delegation(
+        cgroups_path: &Path,
+        delegated_root: &Path,
+    ) -> Result<(), LibcontainerError> {
+        // The path is normalized, so `..` can't be used to get out of the
+        // delegated subtree.
+        if !cgroups_path.normalize().starts_with(delegated_root) {
+            tracing::error!(
+                ?cgroups_path,
+                ?delegated_root,
+                "cgroup path is outside of the cgroup subtree delegated to the user"
+            );
+            return Err(LibcontainerError::CgroupPathEscapesDelegation {
+                path: cgroups_path.to_path_buf(),
+                delegated_root: delegated_root.to_path_buf(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn resolve_relative_mount_targets(
+        spec: &mut Spec,
+        prefix: bool,
+    ) -> Result<(), LibcontainerError> {
+        let mut mounts = match spec.mounts() {
+            Some(mounts) if mounts.iter().any(|m| !m.destination().is_absolute()) => mounts.clone(),
+            _ => return Ok(()),
+        };
+
+        for mount in mounts.iter_mut() {
+            let destination = mount.destination().clone();
+            if destination.is_absolute() {
+                continue;
+            }
+            if !prefix {
+                tracing::error!(?destination, "mount target is not an absolute path");
+                return Err(LibcontainerError::RelativeMountTarget(destination));
+            }
+
+            let absolute = Path::new("/").join(&destination);
+            tracing::warn!(?destination, ?absolute, "prefixing relative mount target");
+            mount.set_destination(absolute);
+        }
+        spec.set_mounts(Some(mounts));
+
+        Ok(())
+    }
+
+    /// Options of a mount that contradict each other would otherwise only fail
+    /// at `mount(2)` with an errno that doesn't tell which option is wrong.
+    fn validate_mount_options(spec: &Spec) -> Result<(), LibcontainerError> {
+        for mount in spec.mounts().iter().flatten() {
+            let options = match mount.options() {
+                Some(options) => options,
+                None => continue,
+            };
+            if let Some(options) = rootfs_utils::find_conflicting_options(options) {
+                let target = mount.destination();
+                tracing::error!(?target, ?options, "mount has conflicting options");
+                return Err(LibcontainerError::ConflictingMountOptions {
+                    target: target.clone(),
+                    options,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Without a uts namespace of its own, the hostname of a container would
+    /// be the one of the host.
+    fn validate_hostname_without_uts(spec: &Spec, allow: bool) -> Result<(), LibcontainerError> {
+        if spec.hostname().is_none() && spec.domainname().is_none() {
+            return Ok(());
+        }
+        let has_uts = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.namespaces().as_ref())
+            .map_or(false, |namespaces| {
+                namespaces
+                    .iter()
+                    .any(|ns| ns.typ() == LinuxNamespaceType::Uts)
+            });
+        if has_uts {
+            return Ok(());
+        }
+
+        let hostname = spec.hostname();
+        let domainname = spec.domainname();
+        if !allow {
+            tracing::error!(
+                ?hostname,
+                ?domainname,
+                "hostname or domainname can't be set without a uts namespace"
+            );
+            return Err(LibcontainerError::HostnameWithoutUtsNamespace);
+        }
+        tracing::warn!(
+            ?hostname,
+            ?domainname,
+            "setting the hostname and domainname of the runtime's uts namespace"
+        );
+
+        Ok(())
+    }
+
+    /// Drops the hostname and domainname of the spec that the container
+    /// doesn't set, i.e. when it joins a uts namespace it leaves untouched,
+    /// so the resolved spec has the names the container gets.
+    fn resolve_hostname(spec: &mut Spec, policy: HostnamePolicy) {
+        let joins_uts = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.namespaces().as_ref())
+            .map_or(false, |namespaces| {
+                namespaces
+                    .iter()
+                    .any(|ns| ns.typ() == LinuxNamespaceType::Uts && ns.path().is_some())
+            });
+        if joins_uts && policy == HostnamePolicy::Skip {
+            spec.set_hostname(None).set_domainname(None);
+        }
+    }
+
+    fn validate_hostname_policy(
+        spec: &Spec,
+        policy: HostnamePolicy,
+    ) -> Result<(), LibcontainerError> {
+        let joins_uts = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.namespaces().as_ref())
+            .map_or(false, |namespaces| {
+                namespaces
+                    .iter()
+                    .any(|ns| ns.typ() == LinuxNamespaceType::Uts && ns.path().is_some())
+            });
+        if !joins_uts || (spec.hostname().is_none() && spec.domainname().is_none()) {
+            return Ok(());
+        }
+
+        let hostname = spec.hostname();
+        let domainname = spec.domainname();
+        match policy {
+            HostnamePolicy::Reject => {
+                tracing::error!(
+                    ?hostname,
+                    ?domainname,
+                    "hostname or domainname can't be set when joining an existing uts namespace"
+                );
+                Err(ErrInvalidSpec::HostnameWithJoinedUts)?;
+            }
+            HostnamePolicy::Skip => {
+                tracing::warn!(
+                    ?hostname,
+                    ?domainname,
+                    "ignoring hostname and domainname, the container joins an existing uts namespace"
+                );
+            }
+            HostnamePolicy::Apply => {
+                tracing::warn!(
+                    ?hostname,
+                    ?domainname,
+                    "overwriting hostname and domainname of the joined uts namespace"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_container_state(&self, container_dir: &Path) -> Result<Container, LibcontainerError> {
+        let mut container = Container::new(
+            &self.base.container_id,
+            ContainerStatus::Creating,
+            None,
+            &self.bundle,
+            container_dir,
+        )?;
+        container.set_state_store(self.base.state_store.clone());
+        container.set_hook_output_limit(self.hook_output_limit);
+        container.save()?;
+        Ok(container)
+    }
+}
+
+/// A created container with the fds only some of the builds return
+struct Created {
+    container: Container,
+    result: CreateResult,
+    pty_master: Option<OwnedFd>,
+    init_pidfd: Option<OwnedFd>,
+}
+
+/// Checks that the init process of the container is still alive after
+/// `delay`. If it exited, the container is saved as stopped, so it can be
+/// deleted.
+fn confirm_liveness(container: &mut Container, delay: Duration) -> Result<(), LibcontainerError> {
+    std::thread::sleep(delay);
+    container.refresh_status()?;
+    if container.status() != ContainerStatus::Stopped {
+        return Ok(());
+    }
+
+    let pid = container.pid().map(|pid| pid.as_raw()).unwrap_or_default();
+    tracing::error!(
+        id = container.id(),
+        pid,
+        "init process exited right after signaling readiness"
+    );
+    container.save()?;
+    Err(LibcontainerError::InitExitedEarly { pid })
+}
+
+/// Attributes the changes of `spec` since the last tag to `subsystem`, if
+/// the provenance is recorded
+fn tag_provenance(provenance: &mut Option<SpecProvenance>, subsystem: &str, spec: &Spec) {
+    if let Some(provenance) = provenance {
+        provenance.tag(subsystem, spec);
+    }
+}
+
+/// Opens `/dev/null` as the stdin of a detached container
+fn open_null_stdin() -> Result<OwnedFd, LibcontainerError> {
+    let null = fs::File::open("/dev/null").map_err(|err| {
+        tracing::error!(
+            ?err,
+            "failed to open /dev/null as the stdin of the container"
+        );
+        LibcontainerError::OtherIO(err)
+    })?;
+    Ok(null.into())
+}
+
+/// A container is considered privileged if it may use `CAP_SYS_ADMIN`.
+fn is_privileged(spec: &Spec) -> bool {
+    spec.process()
+        .as_ref()
+        .and_then(|process| process.capabilities().as_ref())
+        .and_then(|capabilities| capabilities.bounding().as_ref())
+        .map_or(false, |bounding| bounding.contains(&Capability::SysAdmin))
+}
+
+/// Returns the topmost cgroup above and including `own_cgroup` that is owned
+/// by `uid`, which is the root of the subtree delegated to the user. If not
+/// even the own cgroup is owned by the user, it is returned as is.
+fn delegated_cgroup_root(cgroup_root: &Path, own_cgroup: &Path, uid: u32) -> PathBuf {
+    let is_owned = |cgroup: &Path| {
+        let relative = cgroup.strip_prefix("/").unwrap_or(cgroup);
+        fs::metadata(cgroup_root.join(relative)).map_or(false, |metadata| metadata.uid() == uid)
+    };
+
+    own_cgroup
+        .ancestors()
+        .take_while(|cgroup| is_owned(cgroup))
+        .last()
+        .unwrap_or(own_cgroup)
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use anyhow::Result;
+    use oci_spec::runtime::{
+        HookBuilder, HooksBuilder, LinuxBuilder, LinuxCapabilitiesBuilder, LinuxIdMappingBuilder,
+        LinuxMemoryBuilder, LinuxResourcesBuilder, LinuxSeccompAction, LinuxSeccompBuilder,
+        ProcessBuilder, RootBuilder, SpecBuilder,
+    };
+
+    use super::*;
+    use crate::env_file::EnvFileError;
+    use crate::spec_limits::SpecValidationError;
+    use crate::syscall::syscall::SyscallType;
+
+    fn spec_with_uts(path: Option<&str>) -> Result<Spec> {
+        let mut uts = LinuxNamespaceBuilder::default().typ(LinuxNamespaceType::Uts);
+        if let Some(path) = path {
+            uts = uts.path(path);
+        }
+        Ok(SpecBuilder::default()
+            .hostname("youki")
+            .linux(
+                LinuxBuilder::default()
+                    .namespaces(vec![uts.build()?])
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    #[test]
+    fn test_validate_run_as_user() -> Result<()> {
+        let mapping = || {
+            LinuxIdMappingBuilder::default()
+                .host_id(100000_u32)
+                .container_id(0_u32)
+                .size(10_u32)
+                .build()
+        };
+        let mapped = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .uid_mappings(vec![mapping()?])
+                    .gid_mappings(vec![mapping()?])
+                    .build()?,
+            )
+            .build()?;
+
+        InitContainerBuilder::validate_run_as_user(&mapped, None)?;
+        InitContainerBuilder::validate_run_as_user(&mapped, Some((0, 5)))?;
+        assert!(matches!(
+            InitContainerBuilder::validate_run_as_user(&mapped, Some((1000, 0))),
+            Err(LibcontainerError::InvalidSpec(
+                ErrInvalidSpec::RunAsUidNotMapped(1000)
+            ))
+        ));
+        assert!(matches!(
+            InitContainerBuilder::validate_run_as_user(&mapped, Some((0, 1000))),
+            Err(LibcontainerError::InvalidSpec(
+                ErrInvalidSpec::RunAsGidNotMapped(1000)
+            ))
+        ));
+
+        // without mappings the ids are not translated
+        let unmapped = SpecBuilder::default()
+            .linux(LinuxBuilder::default().build()?)
+            .build()?;
+        InitContainerBuilder::validate_run_as_user(&unmapped, Some((1000, 1000)))?;
+        Ok(())
+    }
+
+    fn spec_with_seccomp(
+        no_new_privileges: Option<bool>,
+        bounding: Vec<Capability>,
+    ) -> Result<Spec> {
+        let mut process = ProcessBuilder::default()
+            .capabilities(
+                LinuxCapabilitiesBuilder::default()
+                    .bounding(bounding.into_iter().collect::<HashSet<_>>())
+                    .build()?,
+            )
+            .build()?;
+        process.set_no_new_privileges(no_new_privileges);
+        Ok(SpecBuilder::default()
+            .process(process)
+            .linux(
+                LinuxBuilder::default()
+                    .seccomp(
+                        LinuxSeccompBuilder::default()
+                            .default_action(LinuxSeccompAction::ScmpActAllow)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    #[test]
+    fn test_resolve_seccomp_no_new_privs() -> Result<()> {
+        let no_new_privileges = |spec: &Spec| *spec.process().as_ref().unwrap().no_new_privileges();
+
+        // strict
+        let mut spec = spec_with_seccomp(Some(false), vec![])?;
+        assert!(matches!(
+            InitContainerBuilder::resolve_seccomp_no_new_privs(&mut spec, false),
+            Err(LibcontainerError::SeccompRequiresNoNewPrivs)
+        ));
+        assert_eq!(no_new_privileges(&spec), Some(false));
+
+        // auto-enable
+        InitContainerBuilder::resolve_seccomp_no_new_privs(&mut spec, true)?;
+        assert_eq!(no_new_privileges(&spec), Some(true));
+
+        // a privileged process can install the filter itself
+        let mut spec = spec_with_seccomp(Some(false), vec![Capability::SysAdmin])?;
+        InitContainerBuilder::resolve_seccomp_no_new_privs(&mut spec, false)?;
+        assert_eq!(no_new_privileges(&spec), Some(false));
+
+        // the filter is installed before the capabilities are dropped
+        let mut spec = spec_with_seccomp(None, vec![])?;
+        InitContainerBuilder::resolve_seccomp_no_new_privs(&mut spec, false)?;
+        assert_eq!(no_new_privileges(&spec), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_swap_limit() -> Result<()> {
+        let spec_with_memory = |limit: Option<i64>, swap: Option<i64>| -> Result<Spec> {
+            let mut memory = LinuxMemoryBuilder::default();
+            if let Some(limit) = limit {
+                memory = memory.limit(limit);
+            }
+            if let Some(swap) = swap {
+                memory = memory.swap(swap);
+            }
+            let resources = LinuxResourcesBuilder::default()
+                .memory(memory.build()?)
+                .build()?;
+            let mut spec = Spec::default();
+            spec.set_linux(Some(LinuxBuilder::default().resources(resources).build()?));
+            Ok(spec)
+        };
+        let swap = |spec: &Spec| {
+            spec.linux()
+                .as_ref()
+                .and_then(|linux| linux.resources().as_ref())
+                .and_then(|resources| resources.memory().as_ref())
+                .and_then(|memory| memory.swap())
+        };
+
+        // the spec applies by default
+        let mut spec = spec_with_memory(Some(1024), Some(4096))?;
+        InitContainerBuilder::apply_swap_limit(&mut spec, None)?;
+        assert_eq!(swap(&spec), Some(4096));
+
+        // the swap limit is on top of the memory limit
+        InitContainerBuilder::apply_swap_limit(&mut spec, Some(SwapLimit::Bytes(512)))?;
+        assert_eq!(swap(&spec), Some(1536));
+        InitContainerBuilder::apply_swap_limit(&mut spec, Some(SwapLimit::Bytes(0)))?;
+        assert_eq!(swap(&spec), Some(1024));
+        InitContainerBuilder::apply_swap_limit(&mut spec, Some(SwapLimit::Max))?;
+        assert_eq!(swap(&spec), Some(-1));
+
+        // an unlimited swap needs no memory limit
+        let mut spec = spec_with_memory(None, None)?;
+        InitContainerBuilder::apply_swap_limit(&mut spec, Some(SwapLimit::Max))?;
+        assert_eq!(swap(&spec), Some(-1));
+
+        // a limited swap does
+        for limit in [None, Some(-1)] {
+            let mut spec = spec_with_memory(limit, None)?;
+            assert!(matches!(
+                InitContainerBuilder::apply_swap_limit(&mut spec, Some(SwapLimit::Bytes(512))),
+                Err(LibcontainerError::InvalidSpec(ErrInvalidSpec::SwapLimit))
+            ));
+            assert_eq!(swap(&spec), None);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_pids_max_override() -> Result<()> {
+        let pids_limit = |spec: &Spec| {
+            spec.linux()
+                .as_ref()
+                .and_then(|linux| linux.resources().as_ref())
+                .and_then(|resources| resources.pids().as_ref())
+                .map(|pids| pids.limit())
+        };
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(100).build()?)
+            .build()?;
+        let mut spec = Spec::default();
+        spec.set_linux(Some(LinuxBuilder::default().resources(resources).build()?));
+
+        // the spec applies by default
+        InitContainerBuilder::apply_pids_max_override(&mut spec, None)?;
+        assert_eq!(pids_limit(&spec), Some(100));
+
+        InitContainerBuilder::apply_pids_max_override(&mut spec, Some(4096))?;
+        assert_eq!(pids_limit(&spec), Some(4096));
+        InitContainerBuilder::apply_pids_max_override(&mut spec, Some(-1))?;
+        assert_eq!(pids_limit(&spec), Some(-1));
+
+        // a spec without resources gets the limit
+        let mut spec = Spec::default();
+        spec.set_linux(Some(LinuxBuilder::default().build()?));
+        InitContainerBuilder::apply_pids_max_override(&mut spec, Some(64))?;
+        assert_eq!(pids_limit(&spec), Some(64));
+
+        for pids_max in [0, -2] {
+            assert!(matches!(
+                InitContainerBuilder::apply_pids_max_override(&mut spec, Some(pids_max)),
+                Err(LibcontainerError::InvalidSpec(ErrInvalidSpec::PidsMaxOverride(limit)))
+                    if limit == pids_max
+            ));
+        }
+        assert_eq!(pids_limit(&spec), Some(64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_cgroup_path_delegation() {
+        let delegated_root = Path::new("/user.slice/user-1000.slice/user@1000.service");
+
+        for path in [
+            "/user.slice/user-1000.slice/user@1000.service/youki",
+            "/user.slice/user-1000.slice/user@1000.service/app.slice/../youki",
+        ] {
+            assert!(
+                InitContainerBuilder::check_cgroup_path_delegation(Path::new(path), delegated_root)
+                    .is_ok(),
+                "{path}"
+            );
+        }
+
+        for path in [
+            "/system.slice/youki",
+            "/user.slice/user-1000.slice/user@1000.service/../youki",
+            "/user.slice/user-1000.slice/user@1000.service-other",
+        ] {
+            let err =
+                InitContainerBuilder::check_cgroup_path_delegation(Path::new(path), delegated_root)
+                    .unwrap_err();
+            assert!(
+                matches!(
+                    &err,
+                    LibcontainerError::CgroupPathEscapesDelegation { path: p, .. } if p == Path::new(path)
+                ),
+                "{path}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_delegated_cgroup_root() -> Result<()> {
+        let cgroup_root = tempfile::tempdir()?;
+        let own_cgroup = Path::new("/user.slice/user@1000.service/app.slice");
+        fs::create_dir_all(cgroup_root.path().join(own_cgroup.strip_prefix("/")?))?;
+        let uid = fs::metadata(cgroup_root.path())?.uid();
+
+        // everything down from the temporary root is owned by the test user
+        assert_eq!(
+            delegated_cgroup_root(cgroup_root.path(), own_cgroup, uid),
+            Path::new("/")
+        );
+        // nothing is owned by another user
+        assert_eq!(
+            delegated_cgroup_root(cgroup_root.path(), own_cgroup, uid + 1),
+            own_cgroup
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_relative_mount_targets() -> Result<()> {
+        let spec_with_targets = |targets: &[&str]| -> Result<Spec> {
+            let mut spec = Spec::default();
+            spec.set_mounts(Some(
+                targets
+                    .iter()
+                    .map(|target| {
+                        MountBuilder::default()
+                            .destination(PathBuf::from(target))
+                            .typ("tmpfs")
+                            .source(PathBuf::from("tmpfs"))
+                            .build()
+                    })
+                    .collect::<Result<_, _>>()?,
+            ));
+            Ok(spec)
+        };
+        let targets = |spec: &Spec| -> Vec<PathBuf> {
+            spec.mounts()
+                .iter()
+                .flatten()
+                .map(|m| m.destination().clone())
+                .collect()
+        };
+
+        // absolute targets are kept in both modes
+        for prefix in [false, true] {
+            let mut spec = spec_with_targets(&["/tmp", "/run"])?;
+            InitContainerBuilder::resolve_relative_mount_targets(&mut spec, prefix)?;
+            assert_eq!(
+                targets(&spec),
+                vec![PathBuf::from("/tmp"), PathBuf::from("/run")]
+            );
+        }
+
+        // relative targets are rejected in strict mode
+        let mut spec = spec_with_targets(&["/tmp", "data/cache"])?;
+        let err =
+            InitContainerBuilder::resolve_relative_mount_targets(&mut spec, false).unwrap_err();
+        assert!(matches!(
+            err,
+            LibcontainerError::RelativeMountTarget(target) if target == Path::new("data/cache")
+        ));
+
+        // and prefixed otherwise
+        InitContainerBuilder::resolve_relative_mount_targets(&mut spec, true)?;
+        assert_eq!(
+            targets(&spec),
+            vec![PathBuf::from("/tmp"), PathBuf::from("/data/cache")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_cgroup2_mount() -> Result<()> {
+        let cgroup_mounts = |spec: &Spec| -> Vec<(usize, oci_spec::runtime::Mount)> {
+            spec.mounts()
+                .iter()
+                .flatten()
+                .cloned()
+                .enumerate()
+                .filter(|(_, m)| m.destination() == Path::new(DEFAULT_CGROUP_ROOT))
+                .collect()
+        };
+        let cgroup_namespaces = |spec: &Spec| {
+            spec.linux()
+                .as_ref()
+                .and_then(|linux| linux.namespaces().as_ref())
+                .iter()
+                .flat_map(|namespaces| namespaces.iter())
+                .filter(|ns| ns.typ() == LinuxNamespaceType::Cgroup)
+                .count()
+        };
+
+        // nothing changes unless enabled
+        let mut spec = Spec::default();
+        InitContainerBuilder::prepare_cgroup2_mount(&mut spec, false)?;
+        assert_eq!(spec.mounts(), Spec::default().mounts());
+
+        if !matches!(
+            libcgroups::common::get_cgroup_setup()?,
+            CgroupSetup::Unified
+        ) {
+            let err = InitContainerBuilder::prepare_cgroup2_mount(&mut spec, true).unwrap_err();
+            assert!(matches!(
+                err,
+                LibcontainerError::Cgroup2MountRequiresUnified
+            ));
+            return Ok(());
+        }
+
+        let position = cgroup_mounts(&spec)[0].0;
+        // preparing twice doesn't add a second mount or namespace
+        for _ in 0..2 {
+            InitContainerBuilder::prepare_cgroup2_mount(&mut spec, true)?;
+            let mounts = cgroup_mounts(&spec);
+            assert_eq!(mounts.len(), 1);
+            let (index, mount) = &mounts[0];
+            assert_eq!(*index, position);
+            assert_eq!(mount.typ().as_deref(), Some("cgroup"));
+            assert!(!mount
+                .options()
+                .iter()
+                .flatten()
+                .any(|option| option == "ro"));
+            assert_eq!(cgroup_namespaces(&spec), 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_extra_cgroup_hierarchies() -> Result<()> {
+        let spec = Spec::default();
+        InitContainerBuilder::validate_extra_cgroup_hierarchies(&spec, &[])?;
+
+        let hierarchies = vec!["name=ops:/teams/web".to_owned()];
+        let result = InitContainerBuilder::validate_extra_cgroup_hierarchies(&spec, &hierarchies);
+        if matches!(
+            libcgroups::common::get_cgroup_setup()?,
+            CgroupSetup::Unified
+        ) {
+            assert!(matches!(
+                result,
+                Err(LibcontainerError::CgroupCreate(
+                    CreateCgroupSetupError::ExtraHierarchiesUnsupported(unsupported)
+                )) if unsupported == hierarchies
+            ));
+        } else {
+            result?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_mount_options() -> Result<()> {
+        let spec_with_options = |options: &[&str]| -> Result<Spec> {
+            let mut spec = Spec::default();
+            spec.set_mounts(Some(vec![MountBuilder::default()
+                .destination(PathBuf::from("/data"))
+                .typ("bind")
+                .source(PathBuf::from("/srv/data"))
+                .options(options.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+                .build()?]));
+            Ok(spec)
+        };
+
+        let spec = spec_with_options(&["rbind", "ro", "rw"])?;
+        assert!(matches!(
+            InitContainerBuilder::validate_mount_options(&spec),
+            Err(LibcontainerError::ConflictingMountOptions { target, options })
+                if target == Path::new("/data") && options == ("ro".to_owned(), "rw".to_owned())
+        ));
+
+        let spec = spec_with_options(&["rbind", "ro", "nosuid", "nodev", "rprivate"])?;
+        InitContainerBuilder::validate_mount_options(&spec)?;
+        InitContainerBuilder::validate_mount_options(&Spec::default())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_hostname_policy() -> Result<()> {
+        let joined = spec_with_uts(Some("/proc/1/ns/uts"))?;
+        assert!(matches!(
+            InitContainerBuilder::validate_hostname_policy(&joined, HostnamePolicy::Reject),
+            Err(LibcontainerError::InvalidSpec(
+                ErrInvalidSpec::HostnameWithJoinedUts
+            ))
+        ));
+        InitContainerBuilder::validate_hostname_policy(&joined, HostnamePolicy::Skip)?;
+        InitContainerBuilder::validate_hostname_policy(&joined, HostnamePolicy::Apply)?;
+
+        // a new uts namespace is never shared, so the hostname is always fine
+        let new = spec_with_uts(None)?;
+        InitContainerBuilder::validate_hostname_policy(&new, HostnamePolicy::Reject)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_hostname_without_uts() -> Result<()> {
+        let mut no_uts = spec_with_uts(None)?;
+        no_uts.set_linux(Some(LinuxBuilder::default().namespaces(vec![]).build()?));
+        assert!(matches!(
+            InitContainerBuilder::validate_hostname_without_uts(&no_uts, false),
+            Err(LibcontainerError::HostnameWithoutUtsNamespace)
+        ));
+        InitContainerBuilder::validate_hostname_without_uts(&no_uts, true)?;
+
+        // fine with a uts namespace, new or joined
+        for path in [None, Some("/proc/1/ns/uts")] {
+            let spec = spec_with_uts(path)?;
+            InitContainerBuilder::validate_hostname_without_uts(&spec, false)?;
+        }
+
+        // and without a hostname to set
+        no_uts.set_hostname(None);
+        InitContainerBuilder::validate_hostname_without_uts(&no_uts, false)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_hostname() -> Result<()> {
+        let mut shared = spec_with_uts(None)?;
+        shared.set_linux(Some(LinuxBuilder::default().namespaces(vec![]).build()?));
+        let new = spec_with_uts(None)?;
+        let joined = spec_with_uts(Some("/proc/1/ns/uts"))?;
+
+        // the names of the spec are only dropped when the container leaves a
+        // joined uts namespace untouched
+        let cases = [
+            (&shared, HostnamePolicy::Skip, true),
+            (&new, HostnamePolicy::Skip, true),
+            (&joined, HostnamePolicy::Apply, true),
+            (&joined, HostnamePolicy::Skip, false),
+        ];
+        for (spec, policy, kept) in cases {
+            let mut resolved = spec.clone();
+            InitContainerBuilder::resolve_hostname(&mut resolved, policy);
+            assert_eq!(resolved.hostname().is_some(), kept, "{policy:?}");
+        }
+
+        // the hostname file is written in each case, as the init process
+        // writes the hostname of whichever uts namespace it is in
+        for spec in [&shared, &new, &joined] {
+            let mut resolved = spec.clone();
+            InitContainerBuilder::resolve_hostname(&mut resolved, HostnamePolicy::Skip);
+            hostname_file::resolve(&mut resolved);
+            assert!(hostname_file::should_write(&resolved));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_proc_mount_policy() -> Result<()> {
+        // pid namespace of the spec: None for new, Some(None) for the
+        // runtime's, Some(Some(path)) for a joined one
+        let spec_with_pid_ns = |pid_ns: Option<Option<&str>>| -> Result<Spec> {
+            let mut spec = Spec::default();
+            let mut namespaces: Vec<_> = spec
+                .linux()
+                .as_ref()
+                .and_then(|linux| linux.namespaces().clone())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|ns| ns.typ() != LinuxNamespaceType::Pid)
+                .collect();
+            match pid_ns {
+                None => namespaces.push(
+                    LinuxNamespaceBuilder::default()
+                        .typ(LinuxNamespaceType::Pid)
+                        .build()?,
+                ),
+                Some(Some(path)) => namespaces.push(
+                    LinuxNamespaceBuilder::default()
+                        .typ(LinuxNamespaceType::Pid)
+                        .path(path)
+                        .build()?,
+                ),
+                Some(None) => {}
+            }
+            spec.set_linux(Some(
+                LinuxBuilder::default().namespaces(namespaces).build()?,
+            ));
+            Ok(spec)
+        };
+        let proc_mount = |spec: &Spec| {
+            spec.mounts()
+                .iter()
+                .flatten()
+                .find(|mount| mount.destination() == Path::new("/proc"))
+                .cloned()
+        };
+        let apply = |pid_ns, policy| -> Result<Spec> {
+            let mut spec = spec_with_pid_ns(pid_ns)?;
+            InitContainerBuilder::apply_proc_mount_policy(&mut spec, policy)?;
+            Ok(spec)
+        };
+        let mounted = |spec: &Spec| {
+            proc_mount(spec).map_or(false, |mount| mount.typ().as_deref() == Some("proc"))
+        };
+
+        // a new pid namespace always gets its procfs
+        for policy in [ProcMountPolicy::SkipShared, ProcMountPolicy::BindShared] {
+            assert!(mounted(&apply(None, policy)?));
+        }
+
+        let host = Some(None);
+        let joined = Some(Some("/proc/42/ns/pid"));
+        for pid_ns in [host, joined] {
+            assert!(mounted(&apply(pid_ns, ProcMountPolicy::Mount)?));
+            assert!(proc_mount(&apply(pid_ns, ProcMountPolicy::SkipShared)?).is_none());
+        }
+
+        let bound = proc_mount(&apply(host, ProcMountPolicy::BindShared)?).unwrap();
+        assert_eq!(bound.typ().as_deref(), Some("bind"));
+        assert_eq!(bound.source().as_deref(), Some(Path::new("/proc")));
+        // only a new procfs shows the pids of the joined namespace
+        assert!(mounted(&apply(joined, ProcMountPolicy::BindShared)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_cgroup_namespace_policy() -> Result<()> {
+        let spec_with_cgroupns = |path: Option<&str>| -> Result<Spec> {
+            let mut cgroupns = LinuxNamespaceBuilder::default().typ(LinuxNamespaceType::Cgroup);
+            if let Some(path) = path {
+                cgroupns = cgroupns.path(path);
+            }
+            Ok(SpecBuilder::default()
+                .linux(
+                    LinuxBuilder::default()
+                        .namespaces(vec![cgroupns.build()?])
+                        .build()?,
+                )
+                .build()?)
+        };
+        let resolve = InitContainerBuilder::resolve_cgroup_namespace_policy;
+        let joined = spec_with_cgroupns(Some("/proc/1/ns/cgroup"))?;
+
+        assert_eq!(
+            resolve(&joined, CgroupNamespacePolicy::Create, false, None)?,
+            None
+        );
+        assert!(matches!(
+            resolve(&joined, CgroupNamespacePolicy::Reject, false, None),
+            Err(LibcontainerError::InvalidSpec(
+                ErrInvalidSpec::CgroupWithJoinedCgroupNamespace
+            ))
+        ));
+        // without managed cgroups, the container gets no cgroup to reject
+        assert_eq!(
+            resolve(
+                &joined,
+                CgroupNamespacePolicy::Reject,
+                false,
+                Some(CgroupSetup::Unmanaged)
+            )?,
+            Some(CgroupSetup::Unmanaged)
+        );
+        assert_eq!(
+            resolve(&joined, CgroupNamespacePolicy::Inherit, false, None)?,
+            Some(CgroupSetup::Unmanaged)
+        );
+        assert!(matches!(
+            resolve(
+                &joined,
+                CgroupNamespacePolicy::Inherit,
+                false,
+                Some(CgroupSetup::Unified)
+            ),
+            Err(LibcontainerError::InvalidInput(_))
+        ));
+        for policy in [
+            CgroupNamespacePolicy::Create,
+            CgroupNamespacePolicy::Reject,
+            CgroupNamespacePolicy::Inherit,
+        ] {
+            assert!(matches!(
+                resolve(&joined, policy, true, None),
+                Err(LibcontainerError::Cgroup2MountWithJoinedCgroupNamespace)
+            ));
+        }
+
+        // a new cgroup namespace is rooted at the cgroup of the container
+        let new = spec_with_cgroupns(None)?;
+        assert_eq!(
+            resolve(&new, CgroupNamespacePolicy::Reject, true, None)?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_spec_with_limits() -> Result<()> {
+        let bundle = tempfile::tempdir()?;
+        let spec = Spec::default();
+        spec.save(bundle.path().join("config.json"))?;
+
+        let builder = ContainerBuilder::new("test".to_owned(), SyscallType::default())
+            .as_init(bundle.path())
+            .with_spec_limits(Limits {
+                max_mounts: 1,
+                ..Default::default()
+            });
+        assert!(matches!(
+            builder.load_spec(),
+            Err(LibcontainerError::SpecValidation(
+                SpecValidationError::TooMany { what: "mounts", .. }
+            ))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_spec_resolves_against_bundle() -> Result<()> {
+        let config_dir = tempfile::tempdir()?;
+        let bundle = tempfile::tempdir()?;
+        fs::create_dir(bundle.path().join("rootfs"))?;
+        let mut spec = Spec::default();
+        spec.set_root(Some(RootBuilder::default().path("rootfs").build()?));
+        spec.set_hooks(Some(
+            HooksBuilder::default()
+                .create_runtime(vec![
+                    HookBuilder::default().path("hooks/setup").build()?,
+                    HookBuilder::default().path("/bin/true").build()?,
+                ])
+                .build()?,
+        ));
+        spec.save(config_dir.path().join("config.json"))?;
+
+        let builder = ContainerBuilder::new("test".to_owned(), SyscallType::default())
+            .as_init(config_dir.path())
+            .with_bundle(Some(bundle.path()));
+        // The rootfs is neither in the directory of the spec nor in the
+        // working directory of the tests.
+        let spec = builder.load_spec()?;
+
+        let bundle = fs::canonicalize(bundle.path())?;
+        assert_eq!(spec.root().as_ref().unwrap().path(), &bundle.join("rootfs"));
+        let hook_paths: Vec<PathBuf> = spec
+            .hooks()
+            .as_ref()
+            .and_then(|hooks| hooks.create_runtime().clone())
+            .unwrap()
+            .iter()
+            .map(|hook| hook.path().clone())
+            .collect();
+        assert_eq!(
+            hook_paths,
+            vec![bundle.join("hooks/setup"), PathBuf::from("/bin/true")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_env() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let defaults = tmp.path().join("defaults.env");
+        let job = tmp.path().join("job.env");
+        fs::write(&defaults, "A=defaults\nB=defaults\nC=defaults\n")?;
+        fs::write(&job, "B=job\r\nC=job\r\n")?;
+
+        let builder = ContainerBuilder::new("test".to_owned(), SyscallType::default())
+            .with_env_file(&defaults)
+            .with_env_file(&job)
+            .as_init(tmp.path())
+            .with_env(HashMap::from([("D".to_owned(), "explicit".to_owned())]));
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default()
+                .env(vec!["C=spec".to_owned(), "D=spec".to_owned()])
+                .build()?,
+        ));
+        builder.apply_env(&mut spec)?;
+        assert_eq!(
+            spec.process().as_ref().unwrap().env().as_ref().unwrap(),
+            &vec![
+                "A=defaults".to_owned(),
+                "B=job".to_owned(),
+                "C=spec".to_owned(),
+                "D=explicit".to_owned(),
+            ]
+        );
+
+        fs::write(&job, "B=job\nC\n")?;
+        assert!(matches!(
+            builder.apply_env(&mut spec),
+            Err(LibcontainerError::EnvFile(EnvFileError::Malformed {
+                line: 2,
+                ..
+            }))
+        ));
+
+        Ok(())
     }
 }
@@ -0,0 +1,139 @@
+//! Log verbosity of a single container
+//!
+//! Raising the global log level to debug a single container floods the logs on
+//! a busy node. The [`LOG_LEVEL_ANNOTATION`] annotation raises the verbosity
+//! for the operations on one container only. The level is persisted in the
+//! container state, and every operation on the container runs inside a span
+//! carrying it in the [`LOG_LEVEL_SPAN_FIELD`] field. It's up to the tracing
+//! subscriber of the caller to honor the field.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ErrInvalidSpec;
+
+/// Annotation setting the log level of the operations on the container
+pub const LOG_LEVEL_ANNOTATION: &str = "io.youki.log-level";
+
+/// Name of the field of the container span that holds the log level of the
+/// container.
+pub const LOG_LEVEL_SPAN_FIELD: &str = "container_log_level";
+
+/// Log levels that can be set for a single container. Only levels more verbose
+/// than the usual global level are supported, the per container level only
+/// ever raises the verbosity.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerLogLevel {
+    Debug,
+    Trace,
+}
+
+impl ContainerLogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+
+    /// Reads the log level from the [`LOG_LEVEL_ANNOTATION`] annotation.
+    pub fn from_annotations(
+        annotations: &Option<HashMap<String, String>>,
+    ) -> Result<Option<Self>, ErrInvalidSpec> {
+        let level = match annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(LOG_LEVEL_ANNOTATION))
+        {
+            Some(level) => level,
+            None => return Ok(None),
+        };
+
+        level.parse().map(Some).map_err(|_| {
+            tracing::error!(
+                ?level,
+                "invalid {LOG_LEVEL_ANNOTATION} annotation, expected debug or trace"
+            );
+            ErrInvalidSpec::LogLevelAnnotation(level.to_owned())
+        })
+    }
+}
+
+impl FromStr for ContainerLogLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            _ => Err(format!("unsupported container log level {level:?}")),
+        }
+    }
+}
+
+impl Display for ContainerLogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Creates the span the operations on the container run in. Containers without
+/// a log level get a disabled span, so their logs stay unchanged.
+pub fn container_span(id: &str, log_level: Option<ContainerLogLevel>) -> tracing::Span {
+    match log_level {
+        // The span is created at the error level, so it is never filtered out
+        // by the global level it is meant to override. The field name must
+        // match LOG_LEVEL_SPAN_FIELD.
+        Some(level) => {
+            tracing::error_span!("container", id, container_log_level = level.as_str())
+        }
+        None => tracing::Span::none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_annotations() {
+        let annotations = |level: &str| {
+            Some(HashMap::from([(
+                LOG_LEVEL_ANNOTATION.to_owned(),
+                level.to_owned(),
+            )]))
+        };
+
+        assert_eq!(ContainerLogLevel::from_annotations(&None).unwrap(), None);
+        assert_eq!(
+            ContainerLogLevel::from_annotations(&Some(HashMap::new())).unwrap(),
+            None
+        );
+        assert_eq!(
+            ContainerLogLevel::from_annotations(&annotations("debug")).unwrap(),
+            Some(ContainerLogLevel::Debug)
+        );
+        assert_eq!(
+            ContainerLogLevel::from_annotations(&annotations("trace")).unwrap(),
+            Some(ContainerLogLevel::Trace)
+        );
+        for invalid in ["info", "DEBUG", ""] {
+            assert!(matches!(
+                ContainerLogLevel::from_annotations(&annotations(invalid)),
+                Err(ErrInvalidSpec::LogLevelAnnotation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_container_span_field() {
+        // Disabled spans keep their metadata, so this works without a
+        // subscriber.
+        let span = container_span("c1", Some(ContainerLogLevel::Debug));
+        let metadata = span.metadata().unwrap();
+        assert!(metadata.fields().field(LOG_LEVEL_SPAN_FIELD).is_some());
+        assert!(container_span("c1", None).is_none());
+    }
+}
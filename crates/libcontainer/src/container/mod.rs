@@ -6,6 +6,7 @@
 /// the exec command).
 pub mod builder;
 mod builder_impl;
+pub mod cleanup;
 #[allow(clippy::module_inception)]
 mod container;
 mod container_checkpoint;
@@ -15,9 +16,31 @@ mod container_kill;
 mod container_pause;
 mod container_resume;
 mod container_start;
+pub mod create_descriptor;
+mod create_result;
+mod exec_session;
+pub mod exit_status;
 pub mod init_builder;
+pub mod log_level;
+pub mod registry;
 pub mod state;
+mod state_migration;
+pub mod state_store;
+pub mod status_probe;
 pub mod tenant_builder;
+pub use cleanup::{CleanupError, CleanupReport, CleanupStep, StepOutcome};
 pub use container::{CheckpointOptions, Container};
 pub use container_checkpoint::CheckpointError;
+pub use create_descriptor::{CreateDescriptor, CreateHandles, CreateKind};
+pub use create_result::{CreateResult, PhaseTimings, Rusage, SiblingCreateResult};
+pub use exec_session::{ExecSession, ExecSessionError};
+pub use exit_status::ExitStatus;
+pub use log_level::ContainerLogLevel;
+pub use registry::ContainerRegistry;
 pub use state::{ContainerProcessState, ContainerStatus, State};
+pub use state_migration::{MigrationError, CURRENT_SCHEMA_VERSION};
+pub use state_store::{
+    default_state_store, set_default_state_store, FileStateStore, InMemoryStateStore, StateLock,
+    StateStore,
+};
+pub use status_probe::{compute_status, ProbeCollector, StatusProbes};
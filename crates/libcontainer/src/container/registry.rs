@@ -0,0 +1,155 @@
+//! The containers under a root directory
+//!
+//! Containers kept for inspection after they stopped, e.g. by `youki run
+//! --keep`, are never deleted by the command that ran them. The registry
+//! garbage collects them once they are no longer of interest.
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use super::state_store::{default_state_store, StateStore};
+use super::{Container, ContainerStatus};
+use crate::error::LibcontainerError;
+
+pub struct ContainerRegistry {
+    root_path: PathBuf,
+    store: Arc<dyn StateStore>,
+}
+
+impl ContainerRegistry {
+    /// Creates the registry of the containers under `root_path`, with their
+    /// state in the [default store](super::state_store::default_state_store)
+    pub fn new<P: Into<PathBuf>>(root_path: P) -> Self {
+        Self {
+            root_path: root_path.into(),
+            store: default_state_store(),
+        }
+    }
+
+    /// Sets the store the state of the containers is read from
+    pub fn with_state_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Loads all containers under the root path
+    pub fn containers(&self) -> Result<Vec<Container>, LibcontainerError> {
+        self.store
+            .list(&self.root_path)?
+            .into_iter()
+            .map(|container_root| Container::load_with_store(container_root, self.store.clone()))
+            .collect()
+    }
+
+    /// Deletes the kept containers that stopped longer than `older_than` ago
+    /// and returns their ids. A container without a recorded exit status is
+    /// aged by its creation time. A container that fails to load or delete is
+    /// skipped, so one broken container doesn't block the others.
+    pub fn prune(&self, older_than: Duration) -> Result<Vec<String>, LibcontainerError> {
+        let deadline = match chrono::Duration::from_std(older_than)
+            .ok()
+            .and_then(|age| Utc::now().checked_sub_signed(age))
+        {
+            Some(deadline) => deadline,
+            // Nothing stopped that long ago.
+            None => return Ok(Vec::new()),
+        };
+
+        let mut pruned = Vec::new();
+        for container_root in self.store.list(&self.root_path)? {
+            let mut container =
+                match Container::load_with_store(container_root.clone(), self.store.clone()) {
+                    Ok(container) => container,
+                    Err(err) => {
+                        tracing::warn!(?err, ?container_root, "failed to load container to prune");
+                        continue;
+                    }
+                };
+            if !is_expired(&container, deadline) {
+                continue;
+            }
+
+            let id = container.id().to_owned();
+            match container.delete(false) {
+                Ok(()) => {
+                    tracing::debug!(?id, "pruned kept container");
+                    pruned.push(id);
+                }
+                Err(err) => tracing::warn!(?err, ?id, "failed to prune kept container"),
+            }
+        }
+
+        Ok(pruned)
+    }
+}
+
+fn is_expired(container: &Container, deadline: DateTime<Utc>) -> bool {
+    if !container.kept() || container.status() != ContainerStatus::Stopped {
+        return false;
+    }
+
+    container
+        .exit_status()
+        .map(|exit_status| exit_status.finished_at)
+        .or_else(|| container.created())
+        .map_or(false, |stopped_at| stopped_at <= deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::container::{ExitStatus, State};
+
+    fn save_container(
+        root_path: &std::path::Path,
+        id: &str,
+        kept: bool,
+        finished_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let container_root = root_path.join(id);
+        std::fs::create_dir(&container_root)?;
+        let mut state = State::new(id, ContainerStatus::Stopped, None, PathBuf::from("/bundle"));
+        state.created = Some(Utc::now() - chrono::Duration::hours(2));
+        state.kept = kept;
+        state.exit_status = finished_at.map(|finished_at| ExitStatus {
+            exit_code: 0,
+            signal: 0,
+            finished_at,
+        });
+        state.save(&container_root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+        save_container(root.path(), "kept-old", true, Some(an_hour_ago))?;
+        save_container(root.path(), "kept-recent", true, Some(Utc::now()))?;
+        // aged by its creation time two hours ago
+        save_container(root.path(), "kept-no-exit-status", true, None)?;
+        save_container(root.path(), "not-kept", false, Some(an_hour_ago))?;
+
+        let registry = ContainerRegistry::new(root.path());
+        let mut pruned = registry.prune(Duration::from_secs(30 * 60))?;
+        pruned.sort();
+        assert_eq!(pruned, vec!["kept-no-exit-status", "kept-old"]);
+
+        let mut remaining: Vec<String> = registry
+            .containers()?
+            .iter()
+            .map(|container| container.id().to_owned())
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, vec!["kept-recent", "not-kept"]);
+
+        // nothing stopped that long ago
+        assert!(registry.prune(Duration::MAX)?.is_empty());
+
+        Ok(())
+    }
+}
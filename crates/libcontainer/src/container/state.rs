@@ -1,15 +1,17 @@
 //! Information about status and state of the container
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
-use std::fs;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
+use super::exit_status::ExitStatus;
+use super::log_level::ContainerLogLevel;
+use super::state_migration::{self, MigrationError, CURRENT_SCHEMA_VERSION};
+use super::state_store::{FileStateStore, StateStore};
+
 /// Indicates status of the container
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
 #[serde(rename_all = "camelCase")]
@@ -84,6 +86,20 @@ pub enum StateError {
         state_file_path: PathBuf,
         source: std::io::Error,
     },
+    #[error("failed to migrate container state file {state_file_path:?}")]
+    MigrateStateFile {
+        state_file_path: PathBuf,
+        source: MigrationError,
+    },
+    #[error("failed to list the containers in {root_path:?}")]
+    ListStates {
+        root_path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to lock container records with {path:?}")]
+    Lock { path: PathBuf, source: nix::Error },
+    #[error("failed to access the state store")]
+    Store(#[source] Box<dyn std::error::Error + Send + Sync>),
 }
 
 type Result<T> = std::result::Result<T, StateError>;
@@ -92,6 +108,9 @@ type Result<T> = std::result::Result<T, StateError>;
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct State {
+    // Schema version of the persisted state, see state_migration.
+    #[serde(default)]
+    pub schema_version: u32,
     // Version is the version of the specification that is supported.
     pub oci_version: String,
     // ID is the container ID
@@ -116,10 +135,60 @@ pub struct State {
     pub use_systemd: bool,
     // Specifies if the Intel RDT subdirectory needs be cleaned up.
     pub clean_up_intel_rdt_subdirectory: Option<bool>,
+    // Groups of the shared volumes the container is attached to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shared_volumes: Vec<String>,
+    // Log level of the operations on the container, see log_level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<ContainerLogLevel>,
+    // Pid of the process writing the exit status file of a detached container.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_waiter_pid: Option<i32>,
+    // Start time of the init process in clock ticks after boot, to tell it
+    // apart from a later process reusing its pid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_start_time: Option<u64>,
+    // Specifies if the container is kept for inspection after it stopped,
+    // instead of being deleted by the command that ran it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub kept: bool,
+    // Exit status of the init process, if it was recorded when it was reaped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<ExitStatus>,
+    // Number of bytes of each of stdout and stderr of a hook kept in its
+    // result, see hooks::HookResult.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hook_output_limit: Option<usize>,
+    // Absolute path of the cgroup of the container, as the cgroup manager
+    // placed it at create time. None with cgroup v1, which has a path in
+    // each hierarchy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup_path: Option<PathBuf>,
+    // Name of the systemd unit of the cgroup, if systemd manages it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub systemd_unit: Option<String>,
+    // The sysctls of the spec scoped to the IPC namespace, applied in the
+    // namespace of the container, for debugging the SysV IPC limits.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ipc_sysctls: BTreeMap<String, String>,
+    // Specifies if the cgroups of the container are left to the caller, see
+    // libcgroups::common::CgroupSetup::Unmanaged.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unmanaged_cgroups: bool,
+    // Specifies if the container runs in a user namespace set up by a
+    // runtime that isn't real root.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rootless: bool,
+    // Specifies if the init process of the container has a core scheduling
+    // cookie, which the processes exec'd into it share.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub core_sched: bool,
 }
 
 impl State {
-    const STATE_FILE_PATH: &'static str = "state.json";
+    pub(crate) const STATE_FILE_PATH: &'static str = "state.json";
+    #[cfg(test)]
+    const TEMP_STATE_FILE_PATH: &'static str = "state.json.tmp";
 
     pub fn new(
         container_id: &str,
@@ -128,6 +197,7 @@ impl State {
         bundle: PathBuf,
     ) -> Self {
         Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
             oci_version: "v1.0.2".to_string(),
             id: container_id.to_string(),
             status,
@@ -138,82 +208,102 @@ impl State {
             creator: None,
             use_systemd: false,
             clean_up_intel_rdt_subdirectory: None,
+            shared_volumes: Vec::new(),
+            log_level: None,
+            exit_waiter_pid: None,
+            init_start_time: None,
+            kept: false,
+            exit_status: None,
+            hook_output_limit: None,
+            cgroup_path: None,
+            systemd_unit: None,
+            ipc_sysctls: BTreeMap::new(),
+            unmanaged_cgroups: false,
+            rootless: false,
+            core_sched: false,
         }
     }
 
+    /// Saves the state in the current schema version to the state file. The
+    /// state is written to a temporary file first and renamed over the state
+    /// file, so concurrent readers never observe a partially written state.
     #[instrument(level = "trace")]
     pub fn save(&self, container_root: &Path) -> Result<()> {
-        let state_file_path = Self::file_path(container_root);
-        let file = fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .append(false)
-            .create(true)
-            .truncate(true)
-            .open(&state_file_path)
-            .map_err(|err| {
-                tracing::error!(
-                    state_file_path = ?state_file_path,
-                    err = %err,
-                    "failed to open container state file",
-                );
-                StateError::OpenStateFile {
-                    state_file_path: state_file_path.to_owned(),
-                    source: err,
-                }
-            })?;
-        let mut writer = BufWriter::new(file);
-        serde_json::to_writer(&mut writer, self).map_err(|err| {
+        self.save_to(&FileStateStore, container_root)
+    }
+
+    /// Saves the state in the current schema version to the store
+    pub fn save_to(&self, store: &dyn StateStore, container_root: &Path) -> Result<()> {
+        let data = serde_json::to_vec(self).map_err(|err| {
+            let state_file_path = Self::file_path(container_root);
             tracing::error!(
                 ?state_file_path,
                 %err,
-                "failed to parse container state file",
+                "failed to serialize container state",
             );
             StateError::ParseStateFile {
-                state_file_path: state_file_path.to_owned(),
+                state_file_path,
                 source: err,
             }
         })?;
-        writer.flush().map_err(|err| {
-            tracing::error!(
-                ?state_file_path,
-                %err,
-                "failed to write container state file",
-            );
-            StateError::WriteStateFile {
-                state_file_path: state_file_path.to_owned(),
-                source: err,
-            }
-        })?;
-
-        Ok(())
+        store.save(container_root, Self::STATE_FILE_PATH, &data)
     }
 
+    /// Loads the state from the state file, migrating it to the current
+    /// schema version if it was written by an older version of youki. The
+    /// migrated state is not written back, this only happens on the next
+    /// `save`.
     pub fn load(container_root: &Path) -> Result<Self> {
+        Self::load_from(&FileStateStore, container_root)
+    }
+
+    /// Loads the state from the store, migrating it like [`State::load`]
+    pub fn load_from(store: &dyn StateStore, container_root: &Path) -> Result<Self> {
         let state_file_path = Self::file_path(container_root);
-        let state_file = File::open(&state_file_path).map_err(|err| {
+        let data = store
+            .load(container_root, Self::STATE_FILE_PATH)?
+            .ok_or_else(|| {
+                tracing::error!(?state_file_path, "container state does not exist");
+                StateError::OpenStateFile {
+                    state_file_path: state_file_path.to_owned(),
+                    source: std::io::ErrorKind::NotFound.into(),
+                }
+            })?;
+
+        let parse_err = |err: serde_json::Error| {
             tracing::error!(
                 ?state_file_path,
                 %err,
-                "failed to open container state file",
+                "failed to parse container state file",
             );
-            StateError::OpenStateFile {
+            StateError::ParseStateFile {
                 state_file_path: state_file_path.to_owned(),
                 source: err,
             }
-        })?;
+        };
 
-        let state: Self = serde_json::from_reader(BufReader::new(state_file)).map_err(|err| {
+        let mut raw_state: serde_json::Value = serde_json::from_slice(&data).map_err(parse_err)?;
+        let stored_version = state_migration::migrate(&mut raw_state).map_err(|err| {
             tracing::error!(
                 ?state_file_path,
                 %err,
-                "failed to parse container state file",
+                "failed to migrate container state file",
             );
-            StateError::ParseStateFile {
+            StateError::MigrateStateFile {
                 state_file_path: state_file_path.to_owned(),
                 source: err,
             }
         })?;
+        if stored_version != CURRENT_SCHEMA_VERSION {
+            tracing::debug!(
+                ?state_file_path,
+                stored_version,
+                "migrated container state to version {}",
+                CURRENT_SCHEMA_VERSION
+            );
+        }
+
+        let state: Self = serde_json::from_value(raw_state).map_err(parse_err)?;
 
         Ok(state)
     }
@@ -252,8 +342,144 @@ pub struct ContainerProcessState {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
+    use anyhow::Result;
+
     use super::*;
 
+    fn fixture_dir(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/container/fixture")
+            .join(name)
+    }
+
+    #[test]
+    fn test_load_unversioned_state() -> Result<()> {
+        let container_root = fixture_dir("unversioned");
+        let raw_before = fs::read_to_string(State::file_path(&container_root))?;
+
+        let state = State::load(&container_root)?;
+        assert_eq!(state.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(state.id, "unversioned");
+        assert_eq!(state.status, ContainerStatus::Running);
+        assert_eq!(state.pid, Some(4242));
+        assert_eq!(state.bundle, PathBuf::from("/run/bundle/unversioned"));
+        assert!(state.use_systemd);
+        assert_eq!(state.creator, Some(0));
+
+        // loading is read only
+        assert_eq!(
+            fs::read_to_string(State::file_path(&container_root))?,
+            raw_before
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_writes_current_version() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        fs::copy(
+            State::file_path(&fixture_dir("unversioned")),
+            State::file_path(tmp.path()),
+        )?;
+
+        let state = State::load(tmp.path())?;
+        state.save(tmp.path())?;
+
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert_eq!(raw["schemaVersion"], CURRENT_SCHEMA_VERSION);
+        assert!(!tmp.path().join(State::TEMP_STATE_FILE_PATH).exists());
+        assert_eq!(State::load(tmp.path())?.id, "unversioned");
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroup_location() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut state = State::new("web", ContainerStatus::Created, Some(42), PathBuf::new());
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert!(raw.get("cgroupPath").is_none());
+        assert!(raw.get("systemdUnit").is_none());
+
+        state.cgroup_path = Some(PathBuf::from("/sys/fs/cgroup/system.slice/youki-web.scope"));
+        state.systemd_unit = Some("youki-web.scope".to_owned());
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert_eq!(
+            raw["cgroupPath"],
+            "/sys/fs/cgroup/system.slice/youki-web.scope"
+        );
+        assert_eq!(raw["systemdUnit"], "youki-web.scope");
+
+        let loaded = State::load(tmp.path())?;
+        assert_eq!(loaded.cgroup_path, state.cgroup_path);
+        assert_eq!(loaded.systemd_unit, state.systemd_unit);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ipc_sysctls() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut state = State::new("web", ContainerStatus::Created, Some(42), PathBuf::new());
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert!(raw.get("ipcSysctls").is_none());
+
+        state
+            .ipc_sysctls
+            .insert("kernel.shmmax".to_owned(), "68719476736".to_owned());
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert_eq!(raw["ipcSysctls"]["kernel.shmmax"], "68719476736");
+        assert_eq!(State::load(tmp.path())?.ipc_sysctls, state.ipc_sysctls);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unmanaged_cgroups() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut state = State::new("web", ContainerStatus::Created, Some(42), PathBuf::new());
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert!(raw.get("unmanagedCgroups").is_none());
+        assert!(!State::load(tmp.path())?.unmanaged_cgroups);
+
+        state.unmanaged_cgroups = true;
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert_eq!(raw["unmanagedCgroups"], true);
+        assert!(State::load(tmp.path())?.unmanaged_cgroups);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootless() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut state = State::new("web", ContainerStatus::Created, Some(42), PathBuf::new());
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert!(raw.get("rootless").is_none());
+        assert!(!State::load(tmp.path())?.rootless);
+
+        state.rootless = true;
+        state.save(tmp.path())?;
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(State::file_path(tmp.path()))?)?;
+        assert_eq!(raw["rootless"], true);
+        assert!(State::load(tmp.path())?.rootless);
+        Ok(())
+    }
+
     #[test]
     fn test_creating_status() {
         let cstatus = ContainerStatus::default();
@@ -0,0 +1,145 @@
+//! Migrations of the persisted container state between schema versions
+//!
+//! Containers may outlive the youki binary that created them, e.g. when youki
+//! is upgraded on a node with running containers. To keep `kill`, `delete` and
+//! the other commands working, the state file carries a schema version and is
+//! migrated to the current version on load. Migrations operate on the raw json
+//! value, so they can rename, restructure or backfill fields freely.
+//!
+//! Loading never writes the migrated state back. The state is only persisted
+//! in the current format when the container is saved by a write operation.
+use serde_json::{Map, Value};
+
+/// Name of the field holding the schema version of the state file.
+pub(super) const SCHEMA_VERSION_FIELD: &str = "schemaVersion";
+
+/// Schema version of the state written by this version of youki. State files
+/// written before versioning was introduced have no version and are treated
+/// as version 0.
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("container state is not a json object")]
+    NotAnObject,
+    #[error("invalid schema version {0:?} in container state")]
+    InvalidVersion(Value),
+    #[error(
+        "container state schema version {0} is newer than the supported version {CURRENT_SCHEMA_VERSION}"
+    )]
+    UnsupportedVersion(u32),
+    #[error("failed to migrate container state from schema version {from}: {reason}")]
+    Failed { from: u32, reason: String },
+}
+
+type Result<T> = std::result::Result<T, MigrationError>;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// Ordered list of migrations. The migration at index `i` upgrades the state
+/// from schema version `i` to `i + 1`, so the length of this list must always
+/// be equal to `CURRENT_SCHEMA_VERSION`.
+const MIGRATIONS: &[Migration] = &[migrate_unversioned_to_v1];
+
+/// Migrates the raw container state to the current schema version. Returns the
+/// schema version the state was stored with.
+pub(super) fn migrate(state: &mut Value) -> Result<u32> {
+    let state = state.as_object_mut().ok_or(MigrationError::NotAnObject)?;
+    let stored_version = schema_version(state)?;
+    if stored_version > CURRENT_SCHEMA_VERSION {
+        return Err(MigrationError::UnsupportedVersion(stored_version));
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(stored_version as usize) {
+        tracing::debug!(from, to = from + 1, "migrating container state");
+        migration(state)?;
+        state.insert(SCHEMA_VERSION_FIELD.to_owned(), Value::from(from + 1));
+    }
+
+    Ok(stored_version)
+}
+
+fn schema_version(state: &Map<String, Value>) -> Result<u32> {
+    match state.get(SCHEMA_VERSION_FIELD) {
+        None => Ok(0),
+        Some(value) => value
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| MigrationError::InvalidVersion(value.clone())),
+    }
+}
+
+/// The unversioned format only differs from version 1 by the missing schema
+/// version, but the fields the later commands rely on are checked here so a
+/// corrupted state is reported as such instead of as a parse error.
+fn migrate_unversioned_to_v1(state: &mut Map<String, Value>) -> Result<()> {
+    for field in ["id", "status", "bundle"] {
+        if !state.contains_key(field) {
+            return Err(MigrationError::Failed {
+                from: 0,
+                reason: format!("missing field {field}"),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_migrations_cover_all_versions() {
+        assert_eq!(MIGRATIONS.len(), CURRENT_SCHEMA_VERSION as usize);
+    }
+
+    #[test]
+    fn test_migrate_unversioned() {
+        let mut state = json!({"id": "c1", "status": "running", "bundle": "/b"});
+        assert_eq!(migrate(&mut state).unwrap(), 0);
+        assert_eq!(state[SCHEMA_VERSION_FIELD], json!(CURRENT_SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn test_migrate_current_is_noop() {
+        let mut state = json!({
+            "id": "c1",
+            "status": "running",
+            "bundle": "/b",
+            SCHEMA_VERSION_FIELD: CURRENT_SCHEMA_VERSION,
+        });
+        let expected = state.clone();
+        assert_eq!(migrate(&mut state).unwrap(), CURRENT_SCHEMA_VERSION);
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn test_migrate_invalid() {
+        let mut state = json!({"id": "c1", "status": "running"});
+        assert!(matches!(
+            migrate(&mut state),
+            Err(MigrationError::Failed { from: 0, .. })
+        ));
+
+        let mut state = json!({ SCHEMA_VERSION_FIELD: CURRENT_SCHEMA_VERSION + 1 });
+        assert!(matches!(
+            migrate(&mut state),
+            Err(MigrationError::UnsupportedVersion(_))
+        ));
+
+        let mut state = json!({ SCHEMA_VERSION_FIELD: "1" });
+        assert!(matches!(
+            migrate(&mut state),
+            Err(MigrationError::InvalidVersion(_))
+        ));
+
+        let mut state = json!([]);
+        assert!(matches!(
+            migrate(&mut state),
+            Err(MigrationError::NotAnObject)
+        ));
+    }
+}
@@ -0,0 +1,355 @@
+//! Persistence of the records of a container
+//!
+//! The state of a container and its exec sessions are records stored by a
+//! [`StateStore`], addressed by the directory of the container and the name
+//! of the record. By default they are files in the container directory, see
+//! [`FileStateStore`]. Another store, e.g. backed by a database or a tmpfs,
+//! can be set per container with
+//! [`ContainerBuilder::with_state_store`](super::builder::ContainerBuilder::with_state_store)
+//! or for the whole library with [`set_default_state_store`]. The container
+//! directory is still created by the runtime either way, it holds the sockets
+//! and the config of the container.
+//!
+//! A store must replace a record atomically, so a reader sees either the old
+//! or the new record and never a partial one, and its locks must exclude each
+//! other across processes if containers are managed by several processes.
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+
+use nix::fcntl::{Flock, FlockArg};
+
+use super::state::StateError;
+use super::State;
+
+type Result<T> = std::result::Result<T, StateError>;
+
+const LOCK_FILE: &str = ".lock";
+
+/// Held lock of a container, the lock is released when this is dropped
+pub struct StateLock(#[allow(dead_code)] Box<dyn Send>);
+
+impl StateLock {
+    /// Wraps the guard of a lock of a store, which releases the lock when it
+    /// is dropped
+    pub fn new<G: Send + 'static>(guard: G) -> Self {
+        Self(Box::new(guard))
+    }
+}
+
+/// Storage of the records of containers
+pub trait StateStore: Debug + Send + Sync {
+    /// Loads the record `name` of the container, `None` if it doesn't exist
+    fn load(&self, container_root: &Path, name: &str) -> Result<Option<Vec<u8>>>;
+    /// Creates or atomically replaces the record `name` of the container
+    fn save(&self, container_root: &Path, name: &str, data: &[u8]) -> Result<()>;
+    /// Deletes the record `name` of the container, deleting a record that
+    /// doesn't exist is not an error
+    fn delete(&self, container_root: &Path, name: &str) -> Result<()>;
+    /// Lists the directories of the containers under `root_path` that have a
+    /// state
+    fn list(&self, root_path: &Path) -> Result<Vec<PathBuf>>;
+    /// Locks the records of the container against other users of the store,
+    /// waiting for the lock if it is held
+    fn lock(&self, container_root: &Path) -> Result<StateLock>;
+}
+
+static DEFAULT_STATE_STORE: RwLock<Option<Arc<dyn StateStore>>> = RwLock::new(None);
+
+/// Sets the store of the containers that aren't given one explicitly
+pub fn set_default_state_store(store: Arc<dyn StateStore>) {
+    *DEFAULT_STATE_STORE
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = Some(store);
+}
+
+/// Returns the store set with [`set_default_state_store`], a [`FileStateStore`]
+/// otherwise
+pub fn default_state_store() -> Arc<dyn StateStore> {
+    DEFAULT_STATE_STORE
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_else(|| Arc::new(FileStateStore))
+}
+
+/// Stores the records as files in the container directory, e.g. the state in
+/// `state.json`. A record is written to a temporary file that is renamed over
+/// the record, and the lock is a `flock` of a lock file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileStateStore;
+
+impl StateStore for FileStateStore {
+    fn load(&self, container_root: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = container_root.join(name);
+        match fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => {
+                tracing::error!(?path, %err, "failed to read container record");
+                Err(StateError::OpenStateFile {
+                    state_file_path: path,
+                    source: err,
+                })
+            }
+        }
+    }
+
+    fn save(&self, container_root: &Path, name: &str, data: &[u8]) -> Result<()> {
+        let path = container_root.join(name);
+        let tmp_path = container_root.join(format!("{name}.tmp"));
+        let write = || -> std::io::Result<()> {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_path)?;
+            file.write_all(data)?;
+            fs::rename(&tmp_path, &path)
+        };
+        write().map_err(|err| {
+            tracing::error!(?path, %err, "failed to write container record");
+            StateError::WriteStateFile {
+                state_file_path: path,
+                source: err,
+            }
+        })
+    }
+
+    fn delete(&self, container_root: &Path, name: &str) -> Result<()> {
+        let path = container_root.join(name);
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => {
+                tracing::error!(?path, %err, "failed to delete container record");
+                Err(StateError::WriteStateFile {
+                    state_file_path: path,
+                    source: err,
+                })
+            }
+        }
+    }
+
+    fn list(&self, root_path: &Path) -> Result<Vec<PathBuf>> {
+        let list_err = |err| StateError::ListStates {
+            root_path: root_path.to_owned(),
+            source: err,
+        };
+        let mut container_roots = Vec::new();
+        for entry in fs::read_dir(root_path).map_err(list_err)? {
+            let container_root = entry.map_err(list_err)?.path();
+            if State::file_path(&container_root).exists() {
+                container_roots.push(container_root);
+            }
+        }
+        container_roots.sort();
+
+        Ok(container_roots)
+    }
+
+    fn lock(&self, container_root: &Path) -> Result<StateLock> {
+        let path = container_root.join(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|err| StateError::OpenStateFile {
+                state_file_path: path.clone(),
+                source: err,
+            })?;
+        let lock = Flock::lock(file, FlockArg::LockExclusive).map_err(|(_, err)| {
+            tracing::error!(?path, %err, "failed to lock container records");
+            StateError::Lock { path, source: err }
+        })?;
+
+        Ok(StateLock::new(lock))
+    }
+}
+
+/// Keeps the records in memory. Only the containers of the process share the
+/// records and locks, which makes it useful for tests.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStateStore {
+    records: Arc<Mutex<HashMap<PathBuf, HashMap<String, Vec<u8>>>>>,
+    locks: Arc<(Mutex<HashSet<PathBuf>>, Condvar)>,
+}
+
+impl InMemoryStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn records(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, HashMap<String, Vec<u8>>>> {
+        // The records are replaced as a whole, a panic can't leave one half
+        // written.
+        self.records.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+struct InMemoryLock {
+    container_root: PathBuf,
+    locks: Arc<(Mutex<HashSet<PathBuf>>, Condvar)>,
+}
+
+impl Drop for InMemoryLock {
+    fn drop(&mut self) {
+        let (held, released) = &*self.locks;
+        held.lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&self.container_root);
+        released.notify_all();
+    }
+}
+
+impl StateStore for InMemoryStateStore {
+    fn load(&self, container_root: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .records()
+            .get(container_root)
+            .and_then(|records| records.get(name))
+            .cloned())
+    }
+
+    fn save(&self, container_root: &Path, name: &str, data: &[u8]) -> Result<()> {
+        self.records()
+            .entry(container_root.to_owned())
+            .or_default()
+            .insert(name.to_owned(), data.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, container_root: &Path, name: &str) -> Result<()> {
+        let mut records = self.records();
+        if let Some(container_records) = records.get_mut(container_root) {
+            container_records.remove(name);
+            if container_records.is_empty() {
+                records.remove(container_root);
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&self, root_path: &Path) -> Result<Vec<PathBuf>> {
+        let mut container_roots: Vec<PathBuf> = self
+            .records()
+            .iter()
+            .filter(|(container_root, records)| {
+                container_root.parent() == Some(root_path)
+                    && records.contains_key(State::STATE_FILE_PATH)
+            })
+            .map(|(container_root, _)| container_root.clone())
+            .collect();
+        container_roots.sort();
+
+        Ok(container_roots)
+    }
+
+    fn lock(&self, container_root: &Path) -> Result<StateLock> {
+        let (held, released) = &*self.locks;
+        let mut held = held.lock().unwrap_or_else(|err| err.into_inner());
+        while held.contains(container_root) {
+            held = released.wait(held).unwrap_or_else(|err| err.into_inner());
+        }
+        held.insert(container_root.to_owned());
+
+        Ok(StateLock::new(InMemoryLock {
+            container_root: container_root.to_owned(),
+            locks: Arc::clone(&self.locks),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::container::ContainerStatus;
+
+    fn check_records(store: &dyn StateStore, root_path: &Path) -> Result<()> {
+        let container_root = root_path.join("container");
+        fs::create_dir_all(&container_root)?;
+
+        assert_eq!(store.load(&container_root, "execs.json")?, None);
+        store.save(&container_root, "execs.json", b"[]")?;
+        store.save(&container_root, "execs.json", b"[1]")?;
+        assert_eq!(
+            store.load(&container_root, "execs.json")?,
+            Some(b"[1]".to_vec())
+        );
+        // only containers with a state are listed
+        assert!(store.list(root_path)?.is_empty());
+
+        let state = State::new(
+            "container",
+            ContainerStatus::Created,
+            None,
+            "/bundle".into(),
+        );
+        state.save_to(store, &container_root)?;
+        assert_eq!(store.list(root_path)?, vec![container_root.clone()]);
+        assert_eq!(State::load_from(store, &container_root)?.id, "container");
+
+        store.delete(&container_root, State::STATE_FILE_PATH)?;
+        store.delete(&container_root, State::STATE_FILE_PATH)?;
+        assert!(store.list(root_path)?.is_empty());
+        assert!(State::load_from(store, &container_root).is_err());
+
+        Ok(())
+    }
+
+    fn check_lock(store: Arc<dyn StateStore>, container_root: &Path) -> Result<()> {
+        let lock = store.lock(container_root)?;
+        let locked = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let store = Arc::clone(&store);
+            let container_root = container_root.to_owned();
+            let locked = Arc::clone(&locked);
+            thread::spawn(move || {
+                let _lock = store.lock(&container_root).unwrap();
+                locked.store(true, Ordering::SeqCst);
+            })
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!locked.load(Ordering::SeqCst), "the lock is not exclusive");
+        drop(lock);
+        waiter.join().unwrap();
+        assert!(locked.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_state_store() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        check_records(&FileStateStore, tmp.path())?;
+        assert!(!tmp.path().join("container/execs.json.tmp").exists());
+        check_lock(Arc::new(FileStateStore), tmp.path())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_state_store() -> Result<()> {
+        let root_path = Path::new("/nonexistent/youki");
+        let store = InMemoryStateStore::new();
+        // Nothing is written to the filesystem, only the directory of the
+        // container is created by the check.
+        let tmp = tempfile::tempdir()?;
+        check_records(&store, tmp.path())?;
+        assert!(fs::read_dir(tmp.path().join("container"))?.next().is_none());
+        check_lock(Arc::new(store), root_path)?;
+
+        Ok(())
+    }
+}
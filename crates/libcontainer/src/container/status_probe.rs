@@ -0,0 +1,443 @@
+//! Status of a container derived from probes of its init process
+//!
+//! The status recorded in the state only changes when youki acts on the
+//! container, so it goes stale when the init process exits or the container
+//! is changed from outside. [`ProbeCollector`] observes the init process, its
+//! freezer and the start notification, each with a timeout, so a probe that
+//! blocks, e.g. a cgroup read under memory pressure, can't block the caller.
+//! [`compute_status`] then decides the current status from the recorded one
+//! and the probes:
+//!
+//! | recorded | init process         | other probes      | status   |
+//! |----------|----------------------|-------------------|----------|
+//! | any      | gone or exited       |                   | Stopped  |
+//! | any      | unknown              |                   | recorded |
+//! | Creating | alive                |                   | Creating |
+//! | Created  | alive                | start not notified| Created  |
+//! | Created  | alive                | start notified    | Running  |
+//! | Running  | alive                | frozen            | Paused   |
+//! | Running  | alive                | thawed or unknown | Running  |
+//! | Paused   | alive                |                   | Paused   |
+//! | Stopped  | alive                | frozen            | Paused   |
+//! | Stopped  | alive                | thawed or unknown | Running  |
+//!
+//! A Paused container stays Paused while its process lives even if its cgroup
+//! is thawed, as a resume thaws the cgroup before it records the new status.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use nix::unistd::Pid;
+use procfs::process::{ProcState, Process};
+use procfs::ProcError;
+
+use super::{Container, ContainerStatus};
+
+/// File in the container directory created once the init process was told to
+/// start, so a start that failed to record the Running status is noticed.
+pub const START_NOTIFIED_FILE: &str = "start-notified";
+/// Time after which a probe is given up and reported as unknown
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Observed state of the init process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessProbe {
+    /// The process runs
+    Alive,
+    /// The process exited and is a zombie waiting to be reaped
+    Exited,
+    /// There is no process with the pid, or the pid was reused by a process
+    /// started at a different time
+    Gone,
+    /// The process couldn't be observed in time
+    Unknown,
+}
+
+/// Observed state of the freezer of the cgroup of the init process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreezerProbe {
+    Frozen,
+    Thawed,
+    /// The freezer couldn't be read in time, or the process has no cgroup
+    Unknown,
+}
+
+/// Inputs of [`compute_status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusProbes {
+    /// Status recorded in the state of the container
+    pub recorded: ContainerStatus,
+    pub process: ProcessProbe,
+    pub freezer: FreezerProbe,
+    /// If the init process was told to start
+    pub start_notified: bool,
+}
+
+/// Decides the status of a container, see the [module](self) documentation
+/// for the transitions.
+pub fn compute_status(probes: StatusProbes) -> ContainerStatus {
+    use ContainerStatus::*;
+
+    match probes.process {
+        ProcessProbe::Gone | ProcessProbe::Exited => return Stopped,
+        ProcessProbe::Unknown => return probes.recorded,
+        ProcessProbe::Alive => {}
+    }
+
+    let frozen = probes.freezer == FreezerProbe::Frozen;
+    match probes.recorded {
+        Creating => Creating,
+        Created if probes.start_notified => Running,
+        Created => Created,
+        Paused => Paused,
+        Running | Stopped if frozen => Paused,
+        Running | Stopped => Running,
+    }
+}
+
+/// Collects the [`StatusProbes`] of a container
+#[derive(Debug, Clone)]
+pub struct ProbeCollector {
+    proc_root: PathBuf,
+    cgroup_root: PathBuf,
+    timeout: Duration,
+}
+
+impl Default for ProbeCollector {
+    fn default() -> Self {
+        Self {
+            proc_root: PathBuf::from("/proc"),
+            cgroup_root: PathBuf::from(libcgroups::common::DEFAULT_CGROUP_ROOT),
+            timeout: DEFAULT_PROBE_TIMEOUT,
+        }
+    }
+}
+
+impl ProbeCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the mount point of procfs, `/proc` by default
+    pub fn with_proc_root<P: Into<PathBuf>>(mut self, proc_root: P) -> Self {
+        self.proc_root = proc_root.into();
+        self
+    }
+
+    /// Sets the mount point of the cgroup filesystems, `/sys/fs/cgroup` by
+    /// default
+    pub fn with_cgroup_root<P: Into<PathBuf>>(mut self, cgroup_root: P) -> Self {
+        self.cgroup_root = cgroup_root.into();
+        self
+    }
+
+    /// Sets the timeout of each probe, [`DEFAULT_PROBE_TIMEOUT`] by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn collect(&self, container: &Container) -> StatusProbes {
+        let pid = container.pid();
+        let process = self.probe_process(pid, container.init_start_time());
+        // The freezer only matters for a running process.
+        let freezer = match (process, pid) {
+            (ProcessProbe::Alive, Some(pid)) => self.probe_freezer(pid),
+            _ => FreezerProbe::Unknown,
+        };
+
+        StatusProbes {
+            recorded: container.status(),
+            process,
+            freezer,
+            start_notified: self.probe_start_notified(&container.root),
+        }
+    }
+
+    /// Checks if the process exists in procfs. If the start time of the
+    /// process is known, a process with the same pid but another start time
+    /// is a reuse of the pid and the process is gone.
+    pub fn probe_process(&self, pid: Option<Pid>, start_time: Option<u64>) -> ProcessProbe {
+        let pid = match pid {
+            Some(pid) => pid,
+            None => return ProcessProbe::Gone,
+        };
+        let proc_dir = self.proc_root.join(pid.to_string());
+
+        let probe = move || {
+            let stat = match Process::new_with_root(proc_dir).and_then(|p| p.stat()) {
+                Ok(stat) => stat,
+                Err(ProcError::NotFound(_)) => return ProcessProbe::Gone,
+                Err(err) => {
+                    tracing::warn!(?pid, ?err, "failed to probe the container process");
+                    return ProcessProbe::Unknown;
+                }
+            };
+            if start_time.map_or(false, |start_time| start_time != stat.starttime) {
+                return ProcessProbe::Gone;
+            }
+
+            match stat.state() {
+                Ok(ProcState::Zombie | ProcState::Dead) => ProcessProbe::Exited,
+                Ok(_) => ProcessProbe::Alive,
+                Err(err) => {
+                    tracing::warn!(?pid, ?err, "failed to parse the container process state");
+                    ProcessProbe::Unknown
+                }
+            }
+        };
+
+        with_timeout(self.timeout, probe).unwrap_or_else(|| {
+            tracing::warn!(?pid, "probing the container process timed out");
+            ProcessProbe::Unknown
+        })
+    }
+
+    /// Reads the freezer of the cgroup the process is in. The cgroup is taken
+    /// from procfs, so this works whatever manager created it.
+    pub fn probe_freezer(&self, pid: Pid) -> FreezerProbe {
+        let proc_dir = self.proc_root.join(pid.to_string());
+        let cgroup_root = self.cgroup_root.clone();
+
+        let probe = move || -> Option<FreezerProbe> {
+            let cgroups = Process::new_with_root(proc_dir).ok()?.cgroups().ok()?.0;
+            // The unified hierarchy reports the actual state in cgroup.events,
+            // cgroup v1 has a freezer hierarchy.
+            if let Some(cgroup) = cgroups.iter().find(|c| c.hierarchy == 0) {
+                let events = fs::read_to_string(
+                    join_cgroup(&cgroup_root, &cgroup.pathname).join("cgroup.events"),
+                )
+                .ok()?;
+                return events.lines().find_map(|line| match line {
+                    "frozen 1" => Some(FreezerProbe::Frozen),
+                    "frozen 0" => Some(FreezerProbe::Thawed),
+                    _ => None,
+                });
+            }
+
+            let cgroup = cgroups
+                .iter()
+                .find(|c| c.controllers.iter().any(|c| c == "freezer"))?;
+            let state = fs::read_to_string(
+                join_cgroup(&cgroup_root.join("freezer"), &cgroup.pathname).join("freezer.state"),
+            )
+            .ok()?;
+            match state.trim() {
+                "FROZEN" => Some(FreezerProbe::Frozen),
+                "THAWED" | "FREEZING" => Some(FreezerProbe::Thawed),
+                _ => None,
+            }
+        };
+
+        with_timeout(self.timeout, probe)
+            .flatten()
+            .unwrap_or(FreezerProbe::Unknown)
+    }
+
+    pub fn probe_start_notified(&self, container_root: &Path) -> bool {
+        container_root.join(START_NOTIFIED_FILE).exists()
+    }
+}
+
+fn join_cgroup(root: &Path, cgroup: &str) -> PathBuf {
+    root.join(cgroup.trim_start_matches('/'))
+}
+
+/// Runs the probe in a thread, and gives up on it after the timeout. A probe
+/// stuck in the kernel can't be cancelled, its thread is left behind.
+fn with_timeout<T, F>(timeout: Duration, probe: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("status-probe".to_owned())
+        .spawn(move || {
+            let _ = sender.send(probe());
+        })
+        .map_err(|err| tracing::warn!(?err, "failed to spawn a status probe"))
+        .ok()?;
+
+    receiver.recv_timeout(timeout).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use anyhow::Result;
+    use nix::sys::wait::{waitpid, WaitPidFlag};
+
+    use super::*;
+
+    fn probes(
+        recorded: ContainerStatus,
+        process: ProcessProbe,
+        freezer: FreezerProbe,
+        start_notified: bool,
+    ) -> StatusProbes {
+        StatusProbes {
+            recorded,
+            process,
+            freezer,
+            start_notified,
+        }
+    }
+
+    #[test]
+    fn test_compute_status_process_gone() {
+        use ContainerStatus::*;
+
+        // Covers Created with a dead init and Paused with a gone process.
+        for recorded in [Creating, Created, Running, Paused, Stopped] {
+            for process in [ProcessProbe::Gone, ProcessProbe::Exited] {
+                for freezer in [FreezerProbe::Frozen, FreezerProbe::Thawed] {
+                    assert_eq!(
+                        compute_status(probes(recorded, process, freezer, true)),
+                        Stopped,
+                        "{recorded:?} {process:?} {freezer:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compute_status_process_unknown() {
+        use ContainerStatus::*;
+
+        for recorded in [Creating, Created, Running, Paused, Stopped] {
+            assert_eq!(
+                compute_status(probes(
+                    recorded,
+                    ProcessProbe::Unknown,
+                    FreezerProbe::Frozen,
+                    true
+                )),
+                recorded
+            );
+        }
+    }
+
+    #[test]
+    fn test_compute_status_process_alive() {
+        use ContainerStatus::*;
+        use FreezerProbe::*;
+
+        let alive = ProcessProbe::Alive;
+        let cases = [
+            (Creating, Unknown, true, Creating),
+            (Created, Thawed, false, Created),
+            (Created, Frozen, false, Created),
+            (Created, Thawed, true, Running),
+            (Running, Thawed, true, Running),
+            (Running, Unknown, true, Running),
+            (Running, Frozen, true, Paused),
+            (Paused, Frozen, true, Paused),
+            (Paused, Thawed, true, Paused),
+            (Paused, Unknown, true, Paused),
+            (Stopped, Thawed, true, Running),
+            (Stopped, Frozen, true, Paused),
+        ];
+        for (recorded, freezer, start_notified, want) in cases {
+            assert_eq!(
+                compute_status(probes(recorded, alive, freezer, start_notified)),
+                want,
+                "{recorded:?} {freezer:?} {start_notified}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_probe_process() -> Result<()> {
+        let collector = ProbeCollector::new();
+        let own_pid = nix::unistd::getpid();
+        let start_time = Process::myself()?.stat()?.starttime;
+
+        assert_eq!(collector.probe_process(None, None), ProcessProbe::Gone);
+        assert_eq!(
+            collector.probe_process(Some(own_pid), None),
+            ProcessProbe::Alive
+        );
+        assert_eq!(
+            collector.probe_process(Some(own_pid), Some(start_time)),
+            ProcessProbe::Alive
+        );
+        // a reused pid
+        assert_eq!(
+            collector.probe_process(Some(own_pid), Some(start_time + 1)),
+            ProcessProbe::Gone
+        );
+
+        // an exited child is a zombie until it is reaped
+        let child = Command::new("true").spawn()?;
+        let child_pid = Pid::from_raw(child.id() as i32);
+        let mut probe = ProcessProbe::Alive;
+        for _ in 0..100 {
+            probe = collector.probe_process(Some(child_pid), None);
+            if probe != ProcessProbe::Alive {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(probe, ProcessProbe::Exited);
+        waitpid(child_pid, Some(WaitPidFlag::empty()))?;
+        assert_eq!(
+            collector.probe_process(Some(child_pid), None),
+            ProcessProbe::Gone
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_process_timeout() {
+        let collector = ProbeCollector::new().with_timeout(Duration::ZERO);
+        let probe = with_timeout(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_secs(1));
+        });
+        assert!(probe.is_none());
+        // a probe that didn't answer in time is unknown, not gone
+        let probe = collector.probe_process(Some(nix::unistd::getpid()), None);
+        assert!(matches!(probe, ProcessProbe::Alive | ProcessProbe::Unknown));
+    }
+
+    #[test]
+    fn test_probe_freezer() -> Result<()> {
+        let own_pid = nix::unistd::getpid();
+        let cgroups = Process::myself()?.cgroups()?.0;
+        let unified = match cgroups.iter().find(|c| c.hierarchy == 0) {
+            Some(cgroup) => cgroup.pathname.clone(),
+            // The fake freezer is only set up for the unified hierarchy.
+            None => return Ok(()),
+        };
+
+        let cgroup_root = tempfile::tempdir()?;
+        let cgroup_dir = join_cgroup(cgroup_root.path(), &unified);
+        fs::create_dir_all(&cgroup_dir)?;
+        let collector = ProbeCollector::new().with_cgroup_root(cgroup_root.path());
+
+        assert_eq!(collector.probe_freezer(own_pid), FreezerProbe::Unknown);
+        fs::write(cgroup_dir.join("cgroup.events"), "populated 1\nfrozen 1\n")?;
+        assert_eq!(collector.probe_freezer(own_pid), FreezerProbe::Frozen);
+        fs::write(cgroup_dir.join("cgroup.events"), "populated 1\nfrozen 0\n")?;
+        assert_eq!(collector.probe_freezer(own_pid), FreezerProbe::Thawed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_start_notified() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let collector = ProbeCollector::new();
+
+        assert!(!collector.probe_start_notified(root.path()));
+        fs::write(root.path().join(START_NOTIFIED_FILE), "")?;
+        assert!(collector.probe_start_notified(root.path()));
+
+        Ok(())
+    }
+}
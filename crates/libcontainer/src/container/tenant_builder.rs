@@ -6,32 +6,87 @@ use std::io::BufReader;
 use std::os::fd::{AsRawFd, OwnedFd};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::str::FromStr;
 
-use caps::Capability;
+use caps::{CapSet, Capability};
+use nix::errno::Errno;
 use nix::fcntl::OFlag;
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::waitpid;
 use nix::unistd::{pipe2, read, Pid};
 use oci_spec::runtime::{
     Capabilities as SpecCapabilities, Capability as SpecCapability, LinuxBuilder,
     LinuxCapabilities, LinuxCapabilitiesBuilder, LinuxNamespace, LinuxNamespaceBuilder,
-    LinuxNamespaceType, LinuxSchedulerPolicy, Process, ProcessBuilder, Spec, UserBuilder,
+    LinuxNamespaceType, LinuxSchedulerPolicy, LinuxSeccomp, Process, ProcessBuilder, Spec,
+    UserBuilder,
 };
 use procfs::process::Namespace;
 
-use super::builder::ContainerBuilder;
+use super::builder::{validate_container_id, ContainerBuilder};
+use super::exec_session::{check_exec_id, record_exec_session, ExecSession};
+use super::init_builder::HostnamePolicy;
 use super::Container;
-use crate::capabilities::CapabilityExt;
-use crate::container::builder_impl::ContainerBuilderImpl;
+use crate::capabilities::{self, CapabilityExt, CAP_LAST_CAP_PATH};
+use crate::config::YoukiConfig;
+use crate::container::builder_impl::{write_pid_file, ContainerBuilderImpl};
+use crate::core_sched::CoreSched;
+use crate::create_signals::CreateSignalPolicy;
 use crate::error::{ErrInvalidSpec, LibcontainerError, MissingSpecError};
 use crate::notify_socket::NotifySocket;
 use crate::process::args::ContainerType;
+use crate::process::fast_exec::{self, FastExec, FastExecError};
+use crate::process::message::Message;
+use crate::rootfs::MountOrder;
+use crate::spec_limits::Limits;
 use crate::user_ns::UserNamespaceConfig;
-use crate::{tty, utils};
+use crate::{env_file, stdio_file, tty, utils};
 
-const NAMESPACE_TYPES: &[&str] = &["ipc", "uts", "net", "pid", "mnt", "cgroup"];
+const NAMESPACE_TYPES: &[&str] = &["ipc", "uts", "net", "pid", "mnt", "cgroup", "time"];
 const TENANT_NOTIFY: &str = "tenant-notify-";
 const TENANT_TTY: &str = "tenant-tty-";
 
+/// Records the exec session of the started process. The process is already
+/// running, so it is killed and reaped when the session can't be recorded,
+/// otherwise it would run on without anyone being able to address it.
+fn record_session_or_kill(
+    container: &Container,
+    exec_id: &str,
+    pid: Pid,
+) -> Result<(), LibcontainerError> {
+    let err = match ExecSession::new(exec_id, pid)
+        .and_then(|session| Ok(record_exec_session(container, session)?))
+    {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+
+    tracing::error!(
+        ?err,
+        ?pid,
+        exec_id,
+        "failed to record exec session, killing the process"
+    );
+    if let Err(kill_err) = signal::kill(pid, Signal::SIGKILL) {
+        tracing::warn!(
+            ?kill_err,
+            ?pid,
+            "failed to kill the process of the exec session"
+        );
+    }
+    match waitpid(pid, None) {
+        // The process is not a child when it was started as a sibling.
+        Ok(_) | Err(Errno::ECHILD) => {}
+        Err(wait_err) => {
+            tracing::warn!(
+                ?wait_err,
+                ?pid,
+                "failed to reap the process of the exec session"
+            )
+        }
+    }
+
+    Err(err)
+}
+
 fn get_path_from_spec(spec: &Spec) -> Option<String> {
     let process = match spec.process() {
         Some(p) => p,
@@ -52,6 +107,33 @@ fn get_path_from_spec(spec: &Spec) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+/// Seccomp profile applied to a process joining an existing container
+#[derive(Debug, Clone, Default)]
+pub enum TenantSeccomp {
+    /// Apply no seccomp profile, as tenant processes did before they could
+    /// choose one. Unlike [`TenantSeccomp::Unconfined`], it doesn't require
+    /// CAP_SYS_ADMIN.
+    #[default]
+    Unset,
+    /// Apply the seccomp profile of the container's spec
+    Inherit,
+    /// Apply the given profile instead of the container's profile. A notify
+    /// profile forwards the notify fd to its own listener path.
+    Profile(LinuxSeccomp),
+    /// Do not apply any seccomp profile. This requires CAP_SYS_ADMIN in the
+    /// user namespace of the container init process.
+    Unconfined,
+}
+
+impl From<Option<LinuxSeccomp>> for TenantSeccomp {
+    fn from(seccomp: Option<LinuxSeccomp>) -> Self {
+        match seccomp {
+            Some(profile) => TenantSeccomp::Profile(profile),
+            None => TenantSeccomp::Inherit,
+        }
+    }
+}
+
 /// Builder that can be used to configure the properties of a process
 /// that will join an existing container sandbox
 pub struct TenantContainerBuilder {
@@ -67,6 +149,9 @@ pub struct TenantContainerBuilder {
     additional_gids: Vec<u32>,
     user: Option<u32>,
     group: Option<u32>,
+    seccomp: TenantSeccomp,
+    exec_id: Option<String>,
+    fast_exec: bool,
 }
 
 /// This is a helper function to get capabilities for tenant container, based on
@@ -76,11 +161,12 @@ fn get_capabilities(
     additional: &[String],
     spec: &Spec,
 ) -> Result<LinuxCapabilities, LibcontainerError> {
-    let mut caps: Vec<Capability> = Vec::with_capacity(additional.len());
-    for cap in additional {
-        caps.push(Capability::from_str(cap)?);
-    }
-    let caps: SpecCapabilities = caps.iter().map(|c| SpecCapability::from_cap(*c)).collect();
+    let last_cap = capabilities::last_cap(Path::new(CAP_LAST_CAP_PATH));
+    let caps: SpecCapabilities =
+        capabilities::normalize(additional.iter().map(String::as_str), last_cap)?
+            .into_iter()
+            .map(SpecCapability::from_cap)
+            .collect();
 
     if let Some(spec_caps) = spec
         .process()
@@ -162,10 +248,15 @@ impl TenantContainerBuilder {
             additional_gids: vec![],
             user: None,
             group: None,
+            seccomp: TenantSeccomp::Unset,
+            exec_id: None,
+            fast_exec: false,
         }
     }
 
-    /// Sets environment variables for the container
+    /// Sets environment variables for the container, they override the vars
+    /// of the env files, see
+    /// [`ContainerBuilder::with_env_file`](crate::container::builder::ContainerBuilder::with_env_file)
     pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
         self.env = env;
         self
@@ -225,15 +316,85 @@ impl TenantContainerBuilder {
         self
     }
 
+    /// Sets the seccomp profile of the process. `None` inherits the profile
+    /// of the container. Without it no profile is applied, see
+    /// [`TenantSeccomp::Unset`].
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::tenant_builder::TenantSeccomp;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .as_tenant()
+    /// .with_seccomp(TenantSeccomp::Unconfined);
+    /// ```
+    pub fn with_seccomp<S: Into<TenantSeccomp>>(mut self, seccomp: S) -> Self {
+        self.seccomp = seccomp.into();
+        self
+    }
+
+    /// Records the process as the exec session `exec_id` once it started, so
+    /// it can be signaled with [`Container::kill_exec`]. The id must not be
+    /// used by a running process of the container.
+    pub fn with_exec_id(mut self, exec_id: Option<String>) -> Self {
+        self.exec_id = exec_id;
+        self
+    }
+
+    /// Starts the process with the fast exec path if the container allows
+    /// it. The process is then cloned like with `posix_spawn` and only joins
+    /// the namespaces and the cgroup of the container before it is executed,
+    /// instead of being set up by an intermediate and an init process. The
+    /// full path is still used if the process needs more than that, e.g. a
+    /// terminal, redirected stdio, a seccomp override, a new namespace or an
+    /// executor other than the default one, which the fast path can't run.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .as_tenant()
+    /// .fast_exec(true);
+    /// ```
+    pub fn fast_exec(mut self, fast_exec: bool) -> Self {
+        self.fast_exec = fast_exec;
+        self
+    }
+
     /// Joins an existing container
-    pub fn build(self) -> Result<Pid, LibcontainerError> {
+    pub fn build(mut self) -> Result<Pid, LibcontainerError> {
+        validate_container_id(&self.base.container_id, self.base.max_id_len)?;
+        self.base.resolve_root_path()?;
         let container_dir = self.lookup_container_dir()?;
         let container = self.load_container_state(container_dir.clone())?;
+        let _span = container.span().entered();
+        if let Some(exec_id) = &self.exec_id {
+            check_exec_id(&container, exec_id)?;
+        }
         let mut spec = self.load_init_spec(&container)?;
         self.adapt_spec_for_tenant(&mut spec, &container)?;
 
         tracing::debug!("{:#?}", spec);
 
+        if self.fast_exec {
+            match self.fast_exec_unsupported(&spec, &container) {
+                None => return self.build_fast(&spec, &container),
+                Some(reason) => {
+                    tracing::debug!(reason, "fast exec is not supported, using the full path")
+                }
+            }
+        }
+
         let notify_path = Self::setup_notify_listener(&container_dir)?;
         // convert path of root file system of the container to absolute path
         let rootfs = fs::canonicalize(spec.root().as_ref().ok_or(MissingSpecError::Root)?.path())
@@ -245,10 +406,12 @@ impl TenantContainerBuilder {
 
         let use_systemd = self.should_use_systemd(&container);
         let user_ns_config = UserNamespaceConfig::new(&spec)?;
+        let config = Self::recorded_config(&container);
 
         let (read_end, write_end) =
             pipe2(OFlag::O_CLOEXEC).map_err(LibcontainerError::OtherSyscall)?;
 
+        let exec_id = self.exec_id;
         let mut builder_impl = ContainerBuilderImpl {
             container_type: ContainerType::TenantContainer {
                 exec_notify_fd: write_end.as_raw_fd(),
@@ -257,13 +420,34 @@ impl TenantContainerBuilder {
             container_id: self.base.container_id,
             pid_file: self.base.pid_file,
             console_socket: csocketfd,
+            console_socket_timeout: self.base.console_socket_timeout,
+            intermediate_timeout: self.base.intermediate_timeout,
             use_systemd,
             spec: Rc::new(spec),
             rootfs,
             user_ns_config,
+            cpuset_partition: None,
+            cgroup_mount_readonly: false,
+            delegate_cgroup: false,
+            hostname_policy: HostnamePolicy::Skip,
+            hostname_without_uts: false,
+            mount_order: MountOrder::default(),
+            reset_loginuid: false,
+            proc_sys_readonly: false,
+            fix_mount_target_type: false,
+            mtab_symlink: false,
+            ensure_default_devices: false,
+            rootfs_write_limit: None,
+            rootfs_max_size: None,
+            run_as_user: None,
+            resolve_username: false,
+            new_session: true,
+            exit_status_file: None,
             notify_path: notify_path.clone(),
             container: None,
+            joined_container_state: Some(container.state.clone()),
             preserve_fds: self.base.preserve_fds,
+            socket_fds: Vec::new(),
             detached: self.detached,
             executor: self.base.executor,
             no_pivot: false,
@@ -271,9 +455,31 @@ impl TenantContainerBuilder {
             stdout: self.base.stdout,
             stderr: self.base.stderr,
             as_sibling: self.as_sibling,
+            tolerate_dumpable_eperm: self.base.tolerate_dumpable_eperm,
+            seccomp_notify_timeout: self.base.seccomp_notify_timeout,
+            default_path: self.base.default_path,
+            inject_default_path: self.base.inject_default_path,
+            argv0_override: self.base.argv0_override,
+            create_timeout: None,
+            // The process joins the cookie of the init process.
+            core_sched: container
+                .core_sched()
+                .then(|| container.pid().map(CoreSched::ShareFrom))
+                .flatten(),
+            create_signal_policy: CreateSignalPolicy::Terminate,
+            extra_cgroup_hierarchies: config
+                .as_ref()
+                .map(|config| config.extra_cgroup_hierarchies.clone())
+                .unwrap_or_default(),
+            nested_cgroup_delegation: config
+                .as_ref()
+                .map_or(false, |config| config.nested_cgroup_delegation),
+            cgroup_setup: container.cgroup_setup(),
+            fault_injection: self.base.fault_injection,
         };
 
-        let pid = builder_impl.create()?;
+        let pid = builder_impl.create()?.init_pid;
+        stdio_file::watch(self.base.stdio_files, pid);
 
         let mut notify_socket = NotifySocket::new(notify_path);
         notify_socket.notify_container_start()?;
@@ -293,20 +499,79 @@ impl TenantContainerBuilder {
             match read(read_end.as_raw_fd(), &mut buf).map_err(LibcontainerError::OtherSyscall)? {
                 0 => {
                     if err_str_buf.is_empty() {
+                        if let Some(exec_id) = &exec_id {
+                            record_session_or_kill(&container, exec_id, pid)?;
+                        }
                         return Ok(pid);
                     } else {
-                        return Err(LibcontainerError::Other(
-                            String::from_utf8_lossy(&err_str_buf).to_string(),
-                        ));
+                        return Err(exec_notify_error(&err_str_buf));
                     }
                 }
-                _ => {
-                    err_str_buf.extend(buf);
+                n => {
+                    err_str_buf.extend(&buf[..n]);
                 }
             }
         }
     }
 
+    /// Returns why the process can't be started with the fast exec path, if
+    /// it can't
+    fn fast_exec_unsupported(&self, spec: &Spec, container: &Container) -> Option<&'static str> {
+        if self.base.console_socket.is_some() {
+            return Some("terminal");
+        }
+        if self.base.stdin.is_some() || self.base.stdout.is_some() || self.base.stderr.is_some() {
+            return Some("stdio redirection");
+        }
+        if !matches!(self.seccomp, TenantSeccomp::Unset | TenantSeccomp::Inherit) {
+            return Some("seccomp override");
+        }
+        if self.base.default_path.is_some() {
+            return Some("default PATH");
+        }
+        if self.base.argv0_override.is_some() {
+            return Some("argv0 override");
+        }
+        // The process is executed directly, as the default executor would
+        if self.base.executor_set {
+            return Some("custom executor");
+        }
+        // The fast path joins the cgroup of the init process, which must be
+        // left to the caller.
+        if container.unmanaged_cgroups() {
+            return Some("unmanaged cgroups");
+        }
+        if container.core_sched() {
+            return Some("core scheduling");
+        }
+
+        fast_exec::unsupported(spec)
+    }
+
+    fn build_fast(self, spec: &Spec, container: &Container) -> Result<Pid, LibcontainerError> {
+        let init_pid = container.pid().ok_or(LibcontainerError::Other(
+            "could not retrieve container init pid".into(),
+        ))?;
+        let pid = FastExec::prepare(spec, init_pid, self.base.preserve_fds)?
+            .spawn(self.as_sibling)
+            .map_err(|err| match err {
+                FastExecError::Exec { path, errno } => {
+                    LibcontainerError::ExecFailed { path, errno }
+                }
+                err => err.into(),
+            })?;
+        tracing::debug!(?pid, "started process with fast exec");
+
+        if let Some(pid_file) = &self.base.pid_file {
+            write_pid_file(pid_file, pid)?;
+        }
+        if let Some(exec_id) = &self.exec_id {
+            record_session_or_kill(container, exec_id, pid)?;
+        }
+
+        Ok(pid)
+    }
+
     fn lookup_container_dir(&self) -> Result<PathBuf, LibcontainerError> {
         let container_dir = self.base.root_path.join(&self.base.container_id);
         if !container_dir.exists() {
@@ -320,7 +585,9 @@ impl TenantContainerBuilder {
     fn load_init_spec(&self, container: &Container) -> Result<Spec, LibcontainerError> {
         let spec_path = container.bundle().join("config.json");
 
-        let mut spec = Spec::load(&spec_path).map_err(|err| {
+        // The spec was checked against the limits when the container was
+        // created, it's loaded the same way for its capability names.
+        let mut spec = Limits::unlimited().load_spec(&spec_path).map_err(|err| {
             tracing::error!(path = ?spec_path, ?err, "failed to load spec");
             err
         })?;
@@ -422,7 +689,7 @@ impl TenantContainerBuilder {
     }
 
     fn load_container_state(&self, container_dir: PathBuf) -> Result<Container, LibcontainerError> {
-        let container = Container::load(container_dir)?;
+        let container = Container::load_with_store(container_dir, self.base.state_store.clone())?;
         if !container.can_exec() {
             tracing::error!(status = ?container.status(), "cannot exec as container");
             return Err(LibcontainerError::IncorrectStatus);
@@ -437,12 +704,22 @@ impl TenantContainerBuilder {
         container: &Container,
     ) -> Result<(), LibcontainerError> {
         let process = if let Some(process) = &self.process {
-            self.get_process(process)?
+            let mut process = self.get_process(process)?;
+            if !self.base.env_files.is_empty() {
+                let files = env_file::load(&self.base.env_files)?;
+                let env = env_file::merge(
+                    &files,
+                    process.env().as_deref().unwrap_or_default(),
+                    &HashMap::new(),
+                );
+                process.set_env(Some(env));
+            }
+            process
         } else {
             let original_path_env = get_path_from_spec(spec);
             let mut process_builder = ProcessBuilder::default()
                 .args(self.get_args()?)
-                .env(self.get_environment(original_path_env));
+                .env(self.get_environment(original_path_env)?);
             if let Some(cwd) = self.get_working_dir()? {
                 process_builder = process_builder.cwd(cwd);
             }
@@ -487,12 +764,81 @@ impl TenantContainerBuilder {
         if let Some(ref cgroup_path) = spec_linux.cgroups_path() {
             linux_builder = linux_builder.cgroups_path(cgroup_path.clone());
         }
+        if let Some(seccomp) = self.get_seccomp(spec_linux.seccomp(), &init_process, container)? {
+            linux_builder = linux_builder.seccomp(seccomp);
+        }
         let linux = linux_builder.build()?;
         spec.set_process(Some(process)).set_linux(Some(linux));
 
         Ok(())
     }
 
+    fn get_seccomp(
+        &self,
+        init_seccomp: &Option<LinuxSeccomp>,
+        init_process: &procfs::process::Process,
+        container: &Container,
+    ) -> Result<Option<LinuxSeccomp>, LibcontainerError> {
+        let seccomp = match &self.seccomp {
+            TenantSeccomp::Unset => None,
+            TenantSeccomp::Inherit => init_seccomp.clone(),
+            TenantSeccomp::Profile(profile) => Some(profile.clone()),
+            TenantSeccomp::Unconfined => {
+                Self::check_can_run_unconfined(init_process)?;
+                None
+            }
+        };
+
+        tracing::info!(
+            id = container.id(),
+            mode = ?self.seccomp,
+            default_action = ?seccomp.as_ref().map(|s| s.default_action()),
+            listener_path = ?seccomp.as_ref().and_then(|s| s.listener_path().clone()),
+            "seccomp mode of tenant process",
+        );
+
+        Ok(seccomp)
+    }
+
+    /// Running unconfined is only allowed with CAP_SYS_ADMIN in the user
+    /// namespace of the container init process. Capabilities held in an
+    /// ancestor user namespace also apply in its descendants, and youki always
+    /// runs in the user namespace the container's user namespace was created
+    /// from, so it is enough to check the effective set of the caller as long
+    /// as the caller is not itself nested in an unrelated user namespace.
+    fn check_can_run_unconfined(
+        init_process: &procfs::process::Process,
+    ) -> Result<(), LibcontainerError> {
+        if !caps::has_cap(None, CapSet::Effective, Capability::CAP_SYS_ADMIN)? {
+            tracing::error!("running a tenant process unconfined requires CAP_SYS_ADMIN");
+            return Err(LibcontainerError::InvalidInput(
+                "running a tenant process unconfined requires CAP_SYS_ADMIN".into(),
+            ));
+        }
+
+        let user_ns = |process: &procfs::process::Process| {
+            process
+                .namespaces()
+                .map(|ns| ns.0.get(OsStr::new("user")).map(|ns| ns.identifier))
+        };
+        let own_user_ns = user_ns(&procfs::process::Process::myself()?)?;
+        let init_user_ns = user_ns(init_process)?;
+        if own_user_ns != init_user_ns
+            && utils::is_in_new_userns().map_err(LibcontainerError::OtherIO)?
+        {
+            tracing::error!(
+                ?own_user_ns,
+                ?init_user_ns,
+                "caller is not in an ancestor of the container user namespace"
+            );
+            return Err(LibcontainerError::InvalidInput(
+                "running a tenant process unconfined requires CAP_SYS_ADMIN in the container user namespace".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
     fn get_process(&self, process: &Path) -> Result<Process, LibcontainerError> {
         if !process.exists() {
             tracing::error!(?process, "process.json file does not exist");
@@ -529,28 +875,19 @@ impl TenantContainerBuilder {
         Ok(self.args.clone())
     }
 
-    fn get_environment(&self, path: Option<String>) -> Vec<String> {
-        let mut env_exists = false;
-        let mut env: Vec<String> = self
-            .env
-            .iter()
-            .map(|(k, v)| {
-                if k == "PATH" {
-                    env_exists = true;
-                }
-                format!("{k}={v}")
-            })
-            .collect();
+    fn get_environment(&self, path: Option<String>) -> Result<Vec<String>, LibcontainerError> {
+        let files = env_file::load(&self.base.env_files)?;
+        let mut env = env_file::merge(&files, &[], &self.env);
         // It is not possible in normal flow that path is None. The original container
         // creation would have failed if path was absent. However we use Option
         // just as a caution, and if neither exec cmd not original spec has PATH,
         // the container creation will fail later which is ok
         if let Some(p) = path {
-            if !env_exists {
+            if !env.iter().any(|var| var.starts_with("PATH=")) {
                 env.push(p);
             }
         }
-        env
+        Ok(env)
     }
 
     fn get_no_new_privileges(&self) -> Option<bool> {
@@ -582,6 +919,18 @@ impl TenantContainerBuilder {
         container.systemd()
     }
 
+    /// The tenant joins the named cgroups of the container and its layout of
+    /// the cgroup, which were recorded on create
+    fn recorded_config(container: &Container) -> Option<YoukiConfig> {
+        match YoukiConfig::load(&container.root) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                tracing::warn!(?err, "failed to load config, not joining named cgroups");
+                None
+            }
+        }
+    }
+
     fn setup_notify_listener(container_dir: &Path) -> Result<PathBuf, LibcontainerError> {
         let notify_name = Self::generate_name(container_dir, TENANT_NOTIFY);
         let socket_path = container_dir.join(notify_name);
@@ -596,6 +945,7 @@ impl TenantContainerBuilder {
                 container_dir,
                 console_socket,
                 &tty_name,
+                self.base.console_socket_timeout,
             )?)
         } else {
             None
@@ -615,16 +965,43 @@ impl TenantContainerBuilder {
     }
 }
 
+/// Converts the error sent by the init process over the exec notify pipe
+/// into the error returned to the caller of `build`.
+fn exec_notify_error(buf: &[u8]) -> LibcontainerError {
+    match serde_json::from_slice::<Message>(buf) {
+        Ok(Message::ExecErrno { path, errno }) => LibcontainerError::ExecFailed {
+            path,
+            errno: Errno::from_raw(errno),
+        },
+        Ok(Message::NamespaceCreateFailed { namespace, errno }) => {
+            LibcontainerError::NamespaceCreateFailed {
+                namespace,
+                errno: Errno::from_raw(errno),
+            }
+        }
+        Ok(Message::OtherError(err)) => LibcontainerError::Other(err),
+        Ok(msg) => LibcontainerError::Other(msg.to_string()),
+        Err(_) => LibcontainerError::Other(String::from_utf8_lossy(buf).to_string()),
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+    use std::fs;
 
     use caps::Capability as Cap;
     use oci_spec::runtime::{
-        Capabilities, Capability as SpecCap, LinuxCapabilities, ProcessBuilder, Spec, SpecBuilder,
+        Capabilities, Capability as SpecCap, LinuxCapabilities, LinuxSeccompAction,
+        LinuxSeccompBuilder, ProcessBuilder, Spec, SpecBuilder,
     };
 
-    use super::{get_capabilities, LibcontainerError};
+    use super::{get_capabilities, LibcontainerError, TenantSeccomp};
     use crate::capabilities::CapabilityExt;
+    use crate::container::builder::ContainerBuilder;
+    use crate::container::Container;
+    use crate::syscall::syscall::SyscallType;
+    use crate::workload::default::DefaultExecutor;
 
     fn get_spec(caps: LinuxCapabilities) -> Spec {
         SpecBuilder::default()
@@ -767,4 +1144,100 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_tenant_seccomp_from_option() -> Result<(), LibcontainerError> {
+        assert!(matches!(TenantSeccomp::default(), TenantSeccomp::Unset));
+        assert!(matches!(TenantSeccomp::from(None), TenantSeccomp::Inherit));
+
+        let profile = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActNotify)
+            .listener_path("/run/debug-agent.sock")
+            .build()?;
+        match TenantSeccomp::from(Some(profile.clone())) {
+            TenantSeccomp::Profile(p) => assert_eq!(p, profile),
+            other => panic!("unexpected tenant seccomp {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_exec_unsupported() -> Result<(), LibcontainerError> {
+        let builder = || ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default());
+        let container = Container::default();
+        let spec = SpecBuilder::default()
+            .process(ProcessBuilder::default().terminal(true).build()?)
+            .build()?;
+
+        let tenant = builder()
+            .with_console_socket(Some("/tmp/console.sock"))
+            .as_tenant();
+        assert_eq!(
+            tenant.fast_exec_unsupported(&spec, &container),
+            Some("terminal")
+        );
+
+        let tenant = builder()
+            .as_tenant()
+            .with_seccomp(TenantSeccomp::Unconfined);
+        assert_eq!(
+            tenant.fast_exec_unsupported(&spec, &container),
+            Some("seccomp override")
+        );
+
+        let tenant = builder()
+            .with_executor(DefaultExecutor::default())
+            .as_tenant();
+        assert_eq!(
+            tenant.fast_exec_unsupported(&spec, &container),
+            Some("custom executor")
+        );
+
+        // the process of the spec is checked last
+        let tenant = builder().as_tenant();
+        assert_eq!(
+            tenant.fast_exec_unsupported(&spec, &container),
+            Some("terminal")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_environment() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let defaults = tmp.path().join("defaults.env");
+        let job = tmp.path().join("job.env");
+        fs::write(&defaults, "A=defaults\nB=defaults\n")?;
+        fs::write(&job, "B=job\r\nURL=http://host/?a=b\r\n")?;
+
+        let tenant = ContainerBuilder::new("74f1a4cb3801".to_owned(), SyscallType::default())
+            .with_env_file(&defaults)
+            .with_env_file(&job)
+            .as_tenant()
+            .with_env(HashMap::from([("A".to_owned(), "explicit".to_owned())]));
+        assert_eq!(
+            tenant.get_environment(Some("PATH=/bin".to_owned()))?,
+            vec![
+                "A=explicit".to_owned(),
+                "B=job".to_owned(),
+                "URL=http://host/?a=b".to_owned(),
+                "PATH=/bin".to_owned(),
+            ]
+        );
+
+        // a PATH of the env files is kept
+        fs::write(&job, "PATH=/usr/bin\n")?;
+        assert_eq!(
+            tenant.get_environment(Some("PATH=/bin".to_owned()))?,
+            vec![
+                "A=explicit".to_owned(),
+                "B=defaults".to_owned(),
+                "PATH=/usr/bin".to_owned(),
+            ]
+        );
+
+        Ok(())
+    }
 }
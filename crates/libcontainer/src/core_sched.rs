@@ -0,0 +1,227 @@
+//! Core scheduling of the container processes
+//!
+//! The kernel only runs tasks with the same core scheduling cookie on the SMT
+//! siblings of a core, which protects them from side channels of the tasks of
+//! other tenants. With the [`CORE_SCHED_ANNOTATION`] set to `true`, or
+//! [`InitContainerBuilder::with_core_sched`](crate::container::init_builder::InitContainerBuilder::with_core_sched),
+//! the init process creates a cookie of its own before the container process
+//! is executed, and all processes of the container inherit it.
+//!
+//! The [`CORE_SCHED_GROUP_ANNOTATION`] names another container, e.g. the
+//! sandbox of a pod, whose init process has a cookie. The init process shares
+//! that cookie instead of creating one, so the containers of the pod share
+//! cores with each other but with no other task. Processes exec'd into a
+//! container share the cookie of its init process.
+//!
+//! The pid of the init process to share the cookie from is the one of the
+//! pid namespace of the runtime. The intermediate process shares it before
+//! it enters any namespace of the container, and the init process forked
+//! from it inherits the cookie, so the share works whether the container
+//! has a pid namespace of its own or not.
+//!
+//! Core scheduling needs a kernel built with `CONFIG_SCHED_CORE` and cpus
+//! with SMT. Without, a container asking for it fails to be created.
+use nix::errno::Errno;
+use nix::unistd::Pid;
+use oci_spec::runtime::Spec;
+use serde::{Deserialize, Serialize};
+
+use crate::syscall::linux::{
+    PIDTYPE_PID, PIDTYPE_TGID, PR_SCHED_CORE_CREATE, PR_SCHED_CORE_SHARE_FROM,
+};
+use crate::syscall::{Syscall, SyscallError};
+
+/// Annotation that gives the container a core scheduling cookie of its own
+/// when set to `true`
+pub const CORE_SCHED_ANNOTATION: &str = "io.youki.core-sched";
+/// Annotation with the id of the container whose core scheduling cookie the
+/// container shares
+pub const CORE_SCHED_GROUP_ANNOTATION: &str = "io.youki.core-sched-group";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CoreSchedError {
+    #[error("invalid value {0:?} of the {CORE_SCHED_ANNOTATION} annotation")]
+    InvalidAnnotation(String),
+    #[error("core scheduling group container {0:?} doesn't exist")]
+    GroupNotFound(String),
+    #[error("core scheduling group container {0:?} is not running")]
+    GroupNotRunning(String),
+    #[error("core scheduling group container {0:?} has no core scheduling cookie")]
+    GroupWithoutCookie(String),
+    #[error("core scheduling is not supported by the kernel or the cpus")]
+    Unsupported(#[source] SyscallError),
+    #[error("failed to set up core scheduling")]
+    Prctl(#[source] SyscallError),
+}
+
+type Result<T> = std::result::Result<T, CoreSchedError>;
+
+/// The core scheduling group the spec asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreSchedRequest {
+    /// A cookie of its own
+    Create,
+    /// The cookie of the container with the id
+    Group(String),
+}
+
+/// How the init process gets its cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CoreSched {
+    /// Creates a cookie of its own
+    Create,
+    /// Shares the cookie of the process
+    ShareFrom(#[serde(with = "raw_pid")] Pid),
+}
+
+/// (De)serializes a pid as its number
+mod raw_pid {
+    use nix::unistd::Pid;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(pid: &Pid, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(pid.as_raw())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pid, D::Error> {
+        i32::deserialize(deserializer).map(Pid::from_raw)
+    }
+}
+
+/// Returns the core scheduling the annotations of the spec ask for, or a
+/// cookie of its own if `enabled` by the builder
+pub fn requested(spec: &Spec, enabled: bool) -> Result<Option<CoreSchedRequest>> {
+    let annotations = spec.annotations().as_ref();
+    let annotation = |name: &str| annotations.and_then(|annotations| annotations.get(name));
+    if let Some(group) = annotation(CORE_SCHED_GROUP_ANNOTATION) {
+        return Ok(Some(CoreSchedRequest::Group(group.clone())));
+    }
+    let create = match annotation(CORE_SCHED_ANNOTATION).map(String::as_str) {
+        Some("true") => true,
+        Some("false") | None => enabled,
+        Some(value) => return Err(CoreSchedError::InvalidAnnotation(value.to_owned())),
+    };
+    Ok(create.then_some(CoreSchedRequest::Create))
+}
+
+/// Gives the calling process its cookie. A new cookie is created for all
+/// threads of the process, a shared one only for the calling thread, which
+/// is the only thread of the intermediate process. The pid to share from is
+/// looked up in the pid namespace of the caller.
+pub fn apply(syscall: &dyn Syscall, core_sched: CoreSched) -> Result<()> {
+    let result = match core_sched {
+        CoreSched::Create => syscall.sched_core(PR_SCHED_CORE_CREATE, 0, PIDTYPE_TGID),
+        CoreSched::ShareFrom(pid) => {
+            syscall.sched_core(PR_SCHED_CORE_SHARE_FROM, pid.as_raw(), PIDTYPE_PID)
+        }
+    };
+    result.map_err(|err| {
+        tracing::error!(?core_sched, ?err, "failed to set up core scheduling");
+        match err {
+            // EINVAL without CONFIG_SCHED_CORE, ENODEV without SMT
+            SyscallError::Nix(Errno::EINVAL | Errno::ENODEV) => CoreSchedError::Unsupported(err),
+            err => CoreSchedError::Prctl(err),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::syscall::syscall::create_syscall;
+    use crate::syscall::test::{ArgName, SchedCoreArgs, TestHelperSyscall};
+
+    fn spec(annotations: &[(&str, &str)]) -> Spec {
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(
+            annotations
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        ));
+        spec
+    }
+
+    #[test]
+    fn test_requested() -> Result<()> {
+        assert_eq!(requested(&spec(&[]), false)?, None);
+        assert_eq!(requested(&spec(&[]), true)?, Some(CoreSchedRequest::Create));
+        assert_eq!(
+            requested(&spec(&[(CORE_SCHED_ANNOTATION, "true")]), false)?,
+            Some(CoreSchedRequest::Create)
+        );
+        assert_eq!(
+            requested(&spec(&[(CORE_SCHED_ANNOTATION, "false")]), false)?,
+            None
+        );
+        assert_eq!(
+            requested(
+                &spec(&[
+                    (CORE_SCHED_ANNOTATION, "true"),
+                    (CORE_SCHED_GROUP_ANNOTATION, "sandbox")
+                ]),
+                false
+            )?,
+            Some(CoreSchedRequest::Group("sandbox".to_owned()))
+        );
+        assert!(matches!(
+            requested(&spec(&[(CORE_SCHED_ANNOTATION, "yes")]), false),
+            Err(CoreSchedError::InvalidAnnotation(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let syscall = create_syscall();
+        apply(syscall.as_ref(), CoreSched::Create)?;
+        apply(syscall.as_ref(), CoreSched::ShareFrom(Pid::from_raw(42)))?;
+
+        let got = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_sched_core_args();
+        assert_eq!(
+            got,
+            vec![
+                SchedCoreArgs {
+                    cmd: PR_SCHED_CORE_CREATE,
+                    pid: 0,
+                    pid_type: PIDTYPE_TGID,
+                },
+                SchedCoreArgs {
+                    cmd: PR_SCHED_CORE_SHARE_FROM,
+                    pid: 42,
+                    pid_type: PIDTYPE_PID,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_unsupported() {
+        let syscall = create_syscall();
+        let test_syscall = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        test_syscall.set_ret_err(ArgName::SchedCore, || Err(SyscallError::Nix(Errno::EINVAL)));
+        assert!(matches!(
+            apply(syscall.as_ref(), CoreSched::Create),
+            Err(CoreSchedError::Unsupported(_))
+        ));
+
+        test_syscall.set_ret_err(ArgName::SchedCore, || Err(SyscallError::Nix(Errno::EPERM)));
+        assert!(matches!(
+            apply(syscall.as_ref(), CoreSched::ShareFrom(Pid::from_raw(42))),
+            Err(CoreSchedError::Prctl(_))
+        ));
+    }
+}
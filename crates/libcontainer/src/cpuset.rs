@@ -0,0 +1,442 @@
+//! Cpu and memory node lists of cpusets
+//!
+//! The kernel rejects a cpuset with offline or nonexistent cpus or memory
+//! nodes with a bare EINVAL. The cpus and mems of the spec are therefore
+//! checked against the online cpus and nodes before they are applied. The
+//! create fails with the ids that are not online, or, with
+//! [`InitContainerBuilder::with_clamp_cpuset_to_online`](crate::container::init_builder::InitContainerBuilder::with_clamp_cpuset_to_online),
+//! the cpuset is clamped to its online ids and the clamping is recorded in
+//! the [`CPUSET_CLAMPED_ANNOTATION`] annotation.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::Spec;
+
+/// Annotation the clamping of the cpuset to the online ids is recorded in
+pub const CPUSET_CLAMPED_ANNOTATION: &str = "io.youki.cpuset.clamped";
+/// File the online cpus are read from
+pub const SYSFS_ONLINE_CPUS_PATH: &str = "/sys/devices/system/cpu/online";
+/// File the online memory nodes are read from
+pub const SYSFS_ONLINE_NODES_PATH: &str = "/sys/devices/system/node/online";
+/// Number of cpus, and of memory nodes, a kernel can have at most (the
+/// largest `NR_CPUS`). Ids of a list must be below it, larger ones can't
+/// exist on any host.
+pub const MAX_POSSIBLE_IDS: u32 = 8192;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CpusetError {
+    #[error("invalid cpu list {0:?}")]
+    InvalidList(String),
+    #[error("cpu list {0:?} has ids beyond the most a kernel can have")]
+    ImpossibleIds(String),
+    #[error("failed to read online ids from {path:?}")]
+    ReadOnline {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{kind} {offline} of the cpuset are offline or don't exist, online are {online}")]
+    Offline {
+        kind: CpusetKind,
+        offline: String,
+        online: String,
+    },
+    #[error("none of the {kind} {requested} of the cpuset is online, online are {online}")]
+    NoneOnline {
+        kind: CpusetKind,
+        requested: String,
+        online: String,
+    },
+    #[error(transparent)]
+    Spec(#[from] oci_spec::OciSpecError),
+}
+
+type Result<T> = std::result::Result<T, CpusetError>;
+
+/// The ids a cpuset is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpusetKind {
+    Cpus,
+    Mems,
+}
+
+impl std::fmt::Display for CpusetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cpus => write!(f, "cpus"),
+            Self::Mems => write!(f, "mems"),
+        }
+    }
+}
+
+/// Parses a cpu or node list as used by cpusets, e.g. `0-3,8,10-11`. The
+/// ids must be below [`MAX_POSSIBLE_IDS`], which also bounds the ranges that
+/// are expanded.
+pub fn parse_list(list: &str) -> Result<BTreeSet<u32>> {
+    let invalid = || CpusetError::InvalidList(list.to_owned());
+    let impossible = || CpusetError::ImpossibleIds(list.to_owned());
+    let mut ids = BTreeSet::new();
+    for part in list.trim().split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let start: u32 = start.trim().parse().map_err(|_| invalid())?;
+                let end: u32 = end.trim().parse().map_err(|_| invalid())?;
+                if start > end {
+                    return Err(invalid());
+                }
+                if end >= MAX_POSSIBLE_IDS {
+                    return Err(impossible());
+                }
+                ids.extend(start..=end);
+            }
+            None => {
+                let id: u32 = part.trim().parse().map_err(|_| invalid())?;
+                if id >= MAX_POSSIBLE_IDS {
+                    return Err(impossible());
+                }
+                ids.insert(id);
+            }
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Formats ids as a list with ranges, the inverse of [`parse_list`]
+pub fn format_list(ids: &BTreeSet<u32>) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for id in ids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == *id => *end = *id,
+            _ => ranges.push((*id, *id)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The online cpus and memory nodes of the host. `None` if they are unknown,
+/// e.g. the node list of a kernel without NUMA support, which is not checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OnlineIds {
+    pub cpus: Option<BTreeSet<u32>>,
+    pub mems: Option<BTreeSet<u32>>,
+}
+
+impl OnlineIds {
+    /// Reads the online cpus and nodes from sysfs, see
+    /// [`SYSFS_ONLINE_CPUS_PATH`] and [`SYSFS_ONLINE_NODES_PATH`]
+    pub fn from_sysfs(cpus_path: &Path, nodes_path: &Path) -> Result<Self> {
+        Ok(Self {
+            cpus: read_online(cpus_path)?,
+            mems: read_online(nodes_path)?,
+        })
+    }
+
+    fn of(&self, kind: CpusetKind) -> Option<&BTreeSet<u32>> {
+        match kind {
+            CpusetKind::Cpus => self.cpus.as_ref(),
+            CpusetKind::Mems => self.mems.as_ref(),
+        }
+    }
+}
+
+fn read_online(path: &Path) -> Result<Option<BTreeSet<u32>>> {
+    match fs::read_to_string(path) {
+        Ok(list) => Ok(Some(parse_list(&list)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(CpusetError::ReadOnline {
+            path: path.to_owned(),
+            source: err,
+        }),
+    }
+}
+
+/// If the spec has a cpuset, i.e. cpus or mems, to check against the online
+/// ids
+pub fn requested(spec: &Spec) -> bool {
+    spec.linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+        .and_then(|resources| resources.cpu().as_ref())
+        .map_or(false, |cpu| {
+            [cpu.cpus(), cpu.mems()]
+                .iter()
+                .any(|list| list.as_ref().map_or(false, |list| !list.trim().is_empty()))
+        })
+}
+
+/// Checks the cpus and mems of the cpuset of the spec against the online
+/// ids. With `clamp`, ids that are not online are dropped from the cpuset
+/// instead of failing, and a warning is recorded in the
+/// [`CPUSET_CLAMPED_ANNOTATION`] annotation. Returns the recorded warning.
+pub fn resolve_online(spec: &mut Spec, online: &OnlineIds, clamp: bool) -> Result<Option<String>> {
+    let mut cpu = match spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+        .and_then(|resources| resources.cpu().clone())
+    {
+        Some(cpu) => cpu,
+        None => return Ok(None),
+    };
+
+    let mut warnings = Vec::new();
+    for kind in [CpusetKind::Cpus, CpusetKind::Mems] {
+        let requested = match kind {
+            CpusetKind::Cpus => cpu.cpus(),
+            CpusetKind::Mems => cpu.mems(),
+        };
+        let (requested, online_ids) = match (
+            requested.as_ref().filter(|list| !list.trim().is_empty()),
+            online.of(kind),
+        ) {
+            (Some(requested), Some(online_ids)) => (requested.clone(), online_ids),
+            _ => continue,
+        };
+
+        let ids = parse_list(&requested)?;
+        let offline: BTreeSet<u32> = ids.difference(online_ids).copied().collect();
+        if offline.is_empty() {
+            continue;
+        }
+        if !clamp {
+            return Err(CpusetError::Offline {
+                kind,
+                offline: format_list(&offline),
+                online: format_list(online_ids),
+            });
+        }
+
+        let clamped: BTreeSet<u32> = ids.intersection(online_ids).copied().collect();
+        if clamped.is_empty() {
+            return Err(CpusetError::NoneOnline {
+                kind,
+                requested,
+                online: format_list(online_ids),
+            });
+        }
+        let clamped = format_list(&clamped);
+        let warning = format!(
+            "{kind} {} are not online, clamped {requested} to {clamped}",
+            format_list(&offline)
+        );
+        tracing::warn!("{warning}");
+        warnings.push(warning);
+        match kind {
+            CpusetKind::Cpus => cpu.set_cpus(Some(clamped)),
+            CpusetKind::Mems => cpu.set_mems(Some(clamped)),
+        };
+    }
+
+    if warnings.is_empty() {
+        return Ok(None);
+    }
+
+    // The cpu resources exist, checked above.
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    if let Some(mut resources) = linux.resources().clone() {
+        resources.set_cpu(Some(cpu));
+        linux.set_resources(Some(resources));
+    }
+    spec.set_linux(Some(linux));
+    let warning = warnings.join("; ");
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    annotations.insert(CPUSET_CLAMPED_ANNOTATION.to_owned(), warning.clone());
+    spec.set_annotations(Some(annotations));
+
+    Ok(Some(warning))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, LinuxCpuBuilder, LinuxResourcesBuilder};
+
+    use super::*;
+
+    fn ids(ids: &[u32]) -> BTreeSet<u32> {
+        ids.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_parse_list() -> Result<()> {
+        let cases: &[(&str, &[u32])] = &[
+            ("", &[]),
+            ("\n", &[]),
+            ("0", &[0]),
+            ("0-3,8, 10-11\n", &[0, 1, 2, 3, 8, 10, 11]),
+            ("5-5", &[5]),
+            ("3,1,2", &[1, 2, 3]),
+            ("0-2,1-3", &[0, 1, 2, 3]),
+            ("1,,2", &[1, 2]),
+            ("8191", &[MAX_POSSIBLE_IDS - 1]),
+        ];
+        for (list, expected) in cases {
+            assert_eq!(parse_list(list)?, ids(expected), "{list:?}");
+        }
+        for invalid in ["a", "3-1", "1-", "-1", "1-2-3", "0x1", "4294967296", "1;2"] {
+            assert!(
+                matches!(parse_list(invalid), Err(CpusetError::InvalidList(_))),
+                "{invalid}"
+            );
+        }
+        // ranges are bounded instead of expanded up to u32::MAX
+        for impossible in ["8192", "0-8192", "0-4294967295", "4294967295"] {
+            assert!(
+                matches!(parse_list(impossible), Err(CpusetError::ImpossibleIds(_))),
+                "{impossible}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_list() {
+        assert_eq!(format_list(&ids(&[])), "");
+        assert_eq!(format_list(&ids(&[7])), "7");
+        assert_eq!(format_list(&ids(&[0, 1])), "0-1");
+        assert_eq!(format_list(&ids(&[0, 2, 4])), "0,2,4");
+        assert_eq!(format_list(&ids(&[0, 1, 2, 3, 8, 10, 11])), "0-3,8,10-11");
+        assert_eq!(format_list(&ids(&[8190, 8191])), "8190-8191");
+    }
+
+    #[test]
+    fn test_list_round_trip() -> Result<()> {
+        // every subset of the ids 0 to 11
+        for mask in 0u32..(1 << 12) {
+            let set: BTreeSet<u32> = (0..12).filter(|id| mask & (1 << id) != 0).collect();
+            let list = format_list(&set);
+            assert_eq!(parse_list(&list)?, set, "{list:?}");
+            // the formatted list is canonical
+            assert_eq!(format_list(&parse_list(&list)?), list);
+        }
+        // lists in a non canonical form format to the canonical one
+        assert_eq!(format_list(&parse_list(" 3,0-1,2 ")?), "0-3");
+        Ok(())
+    }
+
+    fn spec_with(cpus: Option<&str>, mems: Option<&str>) -> Result<Spec> {
+        let mut cpu = LinuxCpuBuilder::default();
+        if let Some(cpus) = cpus {
+            cpu = cpu.cpus(cpus);
+        }
+        if let Some(mems) = mems {
+            cpu = cpu.mems(mems);
+        }
+        let mut spec = Spec::default();
+        spec.set_linux(Some(
+            LinuxBuilder::default()
+                .resources(LinuxResourcesBuilder::default().cpu(cpu.build()?).build()?)
+                .build()?,
+        ));
+        Ok(spec)
+    }
+
+    fn cpuset_of(spec: &Spec) -> (Option<String>, Option<String>) {
+        let cpu = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref())
+            .and_then(|resources| resources.cpu().as_ref())
+            .unwrap();
+        (cpu.cpus().clone(), cpu.mems().clone())
+    }
+
+    #[test]
+    fn test_requested() -> Result<()> {
+        assert!(!requested(&Spec::default()));
+        assert!(!requested(&spec_with(None, None)?));
+        assert!(!requested(&spec_with(Some(" "), Some(""))?));
+        assert!(requested(&spec_with(Some("0-3"), None)?));
+        assert!(requested(&spec_with(None, Some("0"))?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_online() -> Result<()> {
+        let online = OnlineIds {
+            cpus: Some(ids(&[0, 1, 2, 3, 6, 7])),
+            mems: Some(ids(&[0])),
+        };
+
+        // all online
+        let mut spec = spec_with(Some("0-3"), Some("0"))?;
+        assert_eq!(resolve_online(&mut spec, &online, false)?, None);
+        assert_eq!(cpuset_of(&spec), (Some("0-3".into()), Some("0".into())));
+
+        // offline and nonexistent ids are listed
+        let mut spec = spec_with(Some("2-9"), None)?;
+        let err = resolve_online(&mut spec, &online, false).unwrap_err();
+        assert!(matches!(
+            &err,
+            CpusetError::Offline { kind: CpusetKind::Cpus, offline, online }
+                if offline == "4-5,8-9" && online == "0-3,6-7"
+        ));
+        let mut spec = spec_with(None, Some("0-1"))?;
+        assert!(matches!(
+            resolve_online(&mut spec, &online, false),
+            Err(CpusetError::Offline { kind: CpusetKind::Mems, offline, .. }) if offline == "1"
+        ));
+
+        // clamped to the online ids
+        let mut spec = spec_with(Some("2-9"), Some("0-1"))?;
+        let warning = resolve_online(&mut spec, &online, true)?.unwrap();
+        assert_eq!(
+            warning,
+            "cpus 4-5,8-9 are not online, clamped 2-9 to 2-3,6-7; \
+             mems 1 are not online, clamped 0-1 to 0"
+        );
+        assert_eq!(cpuset_of(&spec), (Some("2-3,6-7".into()), Some("0".into())));
+        assert_eq!(
+            spec.annotations().as_ref().unwrap()[CPUSET_CLAMPED_ANNOTATION],
+            warning
+        );
+
+        // nothing left to clamp to
+        let mut spec = spec_with(Some("4-5"), None)?;
+        assert!(matches!(
+            resolve_online(&mut spec, &online, true),
+            Err(CpusetError::NoneOnline {
+                kind: CpusetKind::Cpus,
+                ..
+            })
+        ));
+
+        // unknown online ids are not checked
+        let mut spec = spec_with(Some("0-63"), Some("0-3"))?;
+        assert_eq!(
+            resolve_online(&mut spec, &OnlineIds::default(), false)?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_online_ids_from_sysfs() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let cpus_path = tmp.path().join("cpu_online");
+        fs::write(&cpus_path, "0-3,6\n")?;
+
+        let online = OnlineIds::from_sysfs(&cpus_path, &tmp.path().join("node_online"))?;
+        assert_eq!(
+            online,
+            OnlineIds {
+                cpus: Some(ids(&[0, 1, 2, 3, 6])),
+                mems: None,
+            }
+        );
+
+        Ok(())
+    }
+}
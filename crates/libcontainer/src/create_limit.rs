@@ -0,0 +1,151 @@
+//! Process global limit on concurrent container creation
+//!
+//! When many containers are created at once from the same process, the setup
+//! phases contend on shared kernel resources such as the mount table lock and
+//! the cgroup filesystem, which can make the aggregate throughput worse than
+//! creating them with less parallelism. This module provides an opt-in,
+//! process global semaphore that bounds the number of creates running their
+//! setup phase at the same time. No limit is applied unless
+//! [`set_create_concurrency_limit`] is called.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+static CREATE_LIMIT: Mutex<Option<Arc<Semaphore>>> = Mutex::new(None);
+
+/// Sets the maximum number of container creates that run their setup phase
+/// concurrently in this process. `None` or `Some(0)` removes the limit.
+///
+/// The limit is global to the process and applies to every container built
+/// afterwards, regardless of which builder is used. Creates that are already
+/// waiting for a permit keep waiting on the previous limit.
+pub fn set_create_concurrency_limit(permits: Option<usize>) {
+    let semaphore = permits
+        .filter(|permits| *permits > 0)
+        .map(|permits| Arc::new(Semaphore::new(permits)));
+    *lock(&CREATE_LIMIT) = semaphore;
+}
+
+/// Returns the currently configured limit, if any.
+pub fn create_concurrency_limit() -> Option<usize> {
+    lock(&CREATE_LIMIT)
+        .as_ref()
+        .map(|semaphore| semaphore.permits)
+}
+
+/// Waits for a permit to run the setup phase of a create. The permit is
+/// released when the returned guard is dropped. Returns `None` when no limit
+/// is configured.
+pub(crate) fn acquire() -> Option<CreatePermit> {
+    let semaphore = lock(&CREATE_LIMIT).clone()?;
+    semaphore.acquire();
+    Some(CreatePermit { semaphore })
+}
+
+/// Permit for running the setup phase of a create, released on drop.
+pub(crate) struct CreatePermit {
+    semaphore: Arc<Semaphore>,
+}
+
+impl Drop for CreatePermit {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+struct Semaphore {
+    permits: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self {
+            permits,
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) {
+        let mut available = lock(&self.available);
+        while *available == 0 {
+            available = self
+                .released
+                .wait(available)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *available -= 1;
+    }
+
+    fn release(&self) {
+        *lock(&self.available) += 1;
+        self.released.notify_one();
+    }
+}
+
+// A panic while holding one of the locks can't leave the counters in an
+// inconsistent state, so poisoning is ignored.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use serial_test::serial;
+
+    use super::*;
+
+    #[test]
+    #[serial]
+    fn test_no_limit_by_default() {
+        set_create_concurrency_limit(None);
+        assert_eq!(create_concurrency_limit(), None);
+        assert!(acquire().is_none());
+
+        set_create_concurrency_limit(Some(0));
+        assert_eq!(create_concurrency_limit(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_concurrent_creates_are_limited() {
+        const PERMITS: usize = 3;
+        const CREATES: usize = 32;
+
+        set_create_concurrency_limit(Some(PERMITS));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..CREATES)
+            .map(|_| {
+                let running = Arc::clone(&running);
+                let max_running = Arc::clone(&max_running);
+                let completed = Arc::clone(&completed);
+                thread::spawn(move || {
+                    let _permit = acquire().expect("limit is configured");
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    completed.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        set_create_concurrency_limit(None);
+
+        assert_eq!(completed.load(Ordering::SeqCst), CREATES);
+        assert!(max_running.load(Ordering::SeqCst) <= PERMITS);
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+    }
+}
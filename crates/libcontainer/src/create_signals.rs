@@ -0,0 +1,250 @@
+//! Handling of the termination signals received during a create
+//!
+//! A supervisor stopping youki while it creates a container, e.g. on a
+//! timeout of its own, sends it SIGTERM. With the default dispositions youki
+//! dies on the spot and leaves a partial container behind: the cgroup, the
+//! state dir and a half set up init process. With
+//! [`CreateSignalPolicy::Cleanup`], the termination signals are caught for
+//! the duration of the create instead. The handler only records the signal
+//! and interrupts the blocking syscall youki waits in, which fails the
+//! create, so it takes the same cleanup path as any failed create. The
+//! previous handlers are restored once the cleanup is done.
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CreateContainerError, LibcontainerError};
+
+/// The signals a supervisor stops youki with
+const TERMINATION_SIGNALS: &[Signal] = &[Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP];
+
+/// What youki does on a termination signal received during a create
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CreateSignalPolicy {
+    /// Keep the dispositions of the process, by default youki is terminated
+    #[default]
+    Terminate,
+    /// Fail the create and clean up the partial container
+    Cleanup,
+}
+
+/// Last termination signal received while the handlers are installed, 0 if
+/// none
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+static INSTALLED: Mutex<Option<Installed>> = Mutex::new(None);
+
+/// Handlers installed for the creates running in this process. They are
+/// installed by the first create and restored by the last one.
+struct Installed {
+    creates: usize,
+    previous: Vec<(Signal, SigAction)>,
+}
+
+extern "C" fn record(signo: libc::c_int) {
+    RECEIVED.store(signo, Ordering::SeqCst);
+}
+
+fn lock() -> MutexGuard<'static, Option<Installed>> {
+    INSTALLED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Termination signals caught for a create, the previous handlers are
+/// restored on drop
+pub(crate) struct CreateSignals {
+    installed: bool,
+}
+
+impl CreateSignals {
+    pub(crate) fn install(policy: CreateSignalPolicy) -> Self {
+        if policy == CreateSignalPolicy::Terminate {
+            return Self { installed: false };
+        }
+
+        let mut installed = lock();
+        match installed.as_mut() {
+            Some(installed) => installed.creates += 1,
+            None => {
+                RECEIVED.store(0, Ordering::SeqCst);
+                // Without SA_RESTART, so the blocking syscall the create
+                // waits in fails with EINTR.
+                let action = SigAction::new(
+                    SigHandler::Handler(record),
+                    SaFlags::empty(),
+                    SigSet::empty(),
+                );
+                let previous = TERMINATION_SIGNALS
+                    .iter()
+                    .filter_map(|signal| {
+                        // Safety: the handler only stores to an atomic
+                        match unsafe { signal::sigaction(*signal, &action) } {
+                            Ok(previous) => Some((*signal, previous)),
+                            Err(err) => {
+                                tracing::warn!(?signal, ?err, "failed to catch signal for create");
+                                None
+                            }
+                        }
+                    })
+                    .collect();
+                *installed = Some(Installed {
+                    creates: 1,
+                    previous,
+                });
+            }
+        }
+
+        Self { installed: true }
+    }
+
+    /// The termination signal received since the handlers were installed
+    pub(crate) fn received(&self) -> Option<Signal> {
+        if !self.installed {
+            return None;
+        }
+        Signal::try_from(RECEIVED.load(Ordering::SeqCst)).ok()
+    }
+}
+
+/// Whether a termination signal was received by the handlers of a create
+/// still running in this process
+pub(crate) fn termination_received() -> bool {
+    lock().is_some() && RECEIVED.load(Ordering::SeqCst) != 0
+}
+
+impl Drop for CreateSignals {
+    fn drop(&mut self) {
+        if !self.installed {
+            return;
+        }
+
+        let mut installed = lock();
+        let current = match installed.as_mut() {
+            Some(current) => current,
+            None => return,
+        };
+        current.creates -= 1;
+        if current.creates > 0 {
+            return;
+        }
+        for (signal, previous) in &current.previous {
+            // Safety: restores the handler that was installed before
+            if let Err(err) = unsafe { signal::sigaction(*signal, previous) } {
+                tracing::warn!(?signal, ?err, "failed to restore signal handler");
+            }
+        }
+        *installed = None;
+    }
+}
+
+/// Runs `create` with the termination signals handled as told by `policy`.
+/// If it fails, or a termination signal was received, `cleanup` is run with
+/// the signals still caught, so a second signal doesn't cut the cleanup
+/// short.
+pub(crate) fn run_create<S, T>(
+    policy: CreateSignalPolicy,
+    state: &mut S,
+    create: impl FnOnce(&mut S) -> Result<T, LibcontainerError>,
+    cleanup: impl FnOnce(&S) -> Option<LibcontainerError>,
+) -> Result<T, LibcontainerError> {
+    let signals = CreateSignals::install(policy);
+    let result = match (create(state), signals.received()) {
+        (result, None) => result,
+        (result, Some(signal)) => {
+            tracing::warn!(
+                ?signal,
+                ?result,
+                "create interrupted by a termination signal"
+            );
+            Err(LibcontainerError::CreateInterrupted { signal })
+        }
+    };
+
+    result.map_err(|err| {
+        let cleanup_err = cleanup(state);
+        CreateContainerError::new(err, cleanup_err).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::thread;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use serial_test::serial;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Partial {
+        slept: bool,
+        cleaned: Cell<bool>,
+    }
+
+    fn disposition(signal: Signal) -> Result<SigHandler> {
+        let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+        // Safety: the handler is set back right away
+        let current = unsafe { signal::sigaction(signal, &default) }?;
+        unsafe { signal::sigaction(signal, &current) }?;
+        Ok(current.handler())
+    }
+
+    #[test]
+    #[serial]
+    fn test_sigterm_during_slow_create_cleans_up() -> Result<()> {
+        // Safety: the thread is joined before the test returns
+        let main = unsafe { libc::pthread_self() };
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            unsafe { libc::pthread_kill(main, libc::SIGTERM) }
+        });
+
+        let mut partial = Partial::default();
+        let result = run_create(
+            CreateSignalPolicy::Cleanup,
+            &mut partial,
+            |partial| {
+                // returns early, with the seconds left, when interrupted
+                partial.slept = nix::unistd::sleep(5) == 0;
+                Ok(())
+            },
+            |partial| {
+                partial.cleaned.set(true);
+                None
+            },
+        );
+        assert_eq!(sender.join().unwrap(), 0);
+
+        let err = match result {
+            Err(LibcontainerError::CreateContainerError(err)) => err,
+            other => panic!("unexpected result {other:?}"),
+        };
+        assert!(matches!(
+            err.run_error(),
+            LibcontainerError::CreateInterrupted {
+                signal: Signal::SIGTERM
+            }
+        ));
+        assert!(!partial.slept);
+        assert!(partial.cleaned.get());
+        assert_eq!(disposition(Signal::SIGTERM)?, SigHandler::SigDfl);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_no_cleanup_without_signal() -> Result<()> {
+        for policy in [CreateSignalPolicy::Terminate, CreateSignalPolicy::Cleanup] {
+            let result = run_create(policy, &mut (), |_| Ok(1), |_| panic!("cleaned up"));
+            assert_eq!(result?, 1);
+        }
+        // the handlers of the process are left alone by default
+        let signals = CreateSignals::install(CreateSignalPolicy::Terminate);
+        assert_eq!(disposition(Signal::SIGTERM)?, SigHandler::SigDfl);
+        assert_eq!(signals.received(), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,520 @@
+//! Debug bundles of failed container creates
+//!
+//! A debug bundle is a single `tar.gz` with everything that is usually asked
+//! for when a create fails in the field. The entries have stable names, see
+//! the `*_ENTRY` constants, so tooling can pick them out:
+//!
+//! - the spec of the bundle and the resolved spec, if available, with the
+//!   values of secret looking env vars and annotations redacted
+//! - the error with its code, whether it is transient and the chain of its
+//!   sources
+//! - the kernel version, the mountinfo and the available cgroup controllers
+//! - the most recent tracing events, see [`RecentEvents`]
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use libcgroups::common::{CgroupSetup, DEFAULT_CGROUP_ROOT};
+use oci_spec::runtime::Spec;
+use serde::Serialize;
+
+use crate::capabilities;
+use crate::error::LibcontainerError;
+
+pub const SPEC_ENTRY: &str = "config.json";
+pub const RESOLVED_SPEC_ENTRY: &str = "resolved-config.json";
+pub const ERROR_ENTRY: &str = "error.json";
+pub const UNAME_ENTRY: &str = "uname.txt";
+pub const MOUNTINFO_ENTRY: &str = "mountinfo.txt";
+pub const CGROUPS_ENTRY: &str = "cgroups.txt";
+pub const EVENTS_ENTRY: &str = "events.log";
+
+/// Number of events kept by [`RecentEvents::default`]
+pub const DEFAULT_RECENT_EVENTS: usize = 256;
+
+/// Env vars and annotations whose name contains one of these, ignoring case,
+/// have their value redacted
+pub const REDACT_DENY_LIST: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "key",
+    "credential",
+    "auth",
+];
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DebugBundleError {
+    #[error("failed to write the debug bundle {path:?}")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to serialize {entry} for the debug bundle")]
+    Serialize {
+        entry: &'static str,
+        source: serde_json::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, DebugBundleError>;
+
+/// The most recent tracing events, oldest first. Clones share the same
+/// events, so a tracing layer can push to one clone while the bundle is
+/// collected from another.
+#[derive(Debug, Clone)]
+pub struct RecentEvents {
+    events: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl Default for RecentEvents {
+    fn default() -> Self {
+        Self::new(DEFAULT_RECENT_EVENTS)
+    }
+}
+
+impl RecentEvents {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Adds an event, dropping the oldest one once the capacity is reached
+    pub fn push(&self, event: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        // An event pushed while panicking is as good as any other.
+        let mut events = self.events.lock().unwrap_or_else(|err| err.into_inner());
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    pub fn events(&self) -> Vec<String> {
+        let events = self.events.lock().unwrap_or_else(|err| err.into_inner());
+        events.iter().cloned().collect()
+    }
+}
+
+/// The spec of a create once the builder resolved it, see
+/// [`InitContainerBuilder::with_resolved_spec`](crate::container::init_builder::InitContainerBuilder::with_resolved_spec).
+/// Clones share the same spec, so the builder can record it in one clone
+/// while the bundle is collected with another.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedSpec(Arc<Mutex<Option<Spec>>>);
+
+impl ResolvedSpec {
+    pub fn set(&self, spec: &Spec) {
+        let mut resolved = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        *resolved = Some(spec.clone());
+    }
+
+    /// The resolved spec, if the create got that far
+    pub fn get(&self) -> Option<Spec> {
+        let resolved = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        resolved.clone()
+    }
+}
+
+/// What is known about the failed create when the bundle is collected
+#[derive(Debug, Clone, Default)]
+pub struct DebugContext {
+    /// Id of the container that failed to be created
+    pub container_id: String,
+    /// Bundle directory the spec is read from
+    pub bundle: Option<PathBuf>,
+    /// Spec after it was resolved by the builder
+    pub resolved_spec: Option<Spec>,
+    /// Events that led to the failure
+    pub recent_events: Option<RecentEvents>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorReport {
+    code: &'static str,
+    /// If retrying the create may succeed
+    transient: bool,
+    message: String,
+    /// Messages of the sources of the error, outermost first
+    chain: Vec<String>,
+    cleanup: Option<Box<ErrorReport>>,
+}
+
+impl ErrorReport {
+    fn new(err: &LibcontainerError) -> Self {
+        // The run and cleanup errors of a create are reported on their own,
+        // the create error doesn't expose them as sources.
+        if let LibcontainerError::CreateContainerError(err) = err {
+            let mut report = Self::new(err.run_error());
+            report.cleanup = err.cleanup_error().map(|err| Box::new(Self::new(err)));
+            return report;
+        }
+
+        let mut chain = Vec::new();
+        let mut source = std::error::Error::source(err);
+        while let Some(err) = source {
+            chain.push(err.to_string());
+            source = err.source();
+        }
+
+        Self {
+            code: err.code(),
+            transient: err.is_transient(),
+            message: err.to_string(),
+            chain,
+            cleanup: None,
+        }
+    }
+}
+
+/// Writes a debug bundle of a failed create to `dir` and returns its path.
+/// Parts of the host that can't be read are noted in the bundle instead of
+/// failing the collection.
+pub fn collect_bundle(
+    dir: &Path,
+    ctx: &DebugContext,
+    error: &LibcontainerError,
+) -> Result<PathBuf> {
+    let path = dir.join(format!(
+        "youki-debug-{}-{}.tar.gz",
+        ctx.container_id,
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+    let write_err = |err| DebugBundleError::Write {
+        path: path.clone(),
+        source: err,
+    };
+
+    let mut entries: Vec<(&str, Vec<u8>)> = Vec::new();
+    if let Some(bundle) = &ctx.bundle {
+        let spec_path = bundle.join("config.json");
+        match Spec::load(&spec_path) {
+            Ok(spec) => entries.push((SPEC_ENTRY, spec_to_json(SPEC_ENTRY, redact_spec(spec))?)),
+            Err(err) => {
+                tracing::warn!(?spec_path, ?err, "failed to load spec for the debug bundle");
+                entries.push((SPEC_ENTRY, unavailable(&spec_path, &err)));
+            }
+        }
+    }
+    if let Some(spec) = &ctx.resolved_spec {
+        let spec = redact_spec(spec.clone());
+        entries.push((
+            RESOLVED_SPEC_ENTRY,
+            spec_to_json(RESOLVED_SPEC_ENTRY, spec)?,
+        ));
+    }
+    entries.push((ERROR_ENTRY, to_json(ERROR_ENTRY, &ErrorReport::new(error))?));
+    entries.push((UNAME_ENTRY, read_or_note(Path::new("/proc/version"))));
+    entries.push((
+        MOUNTINFO_ENTRY,
+        read_or_note(Path::new("/proc/self/mountinfo")),
+    ));
+    entries.push((CGROUPS_ENTRY, cgroups_report().into_bytes()));
+    let events = ctx
+        .recent_events
+        .as_ref()
+        .map(|events| events.events())
+        .unwrap_or_default();
+    entries.push((EVENTS_ENTRY, lines(&events)));
+
+    fs::create_dir_all(dir).map_err(write_err)?;
+    let file = fs::File::create(&path).map_err(write_err)?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mtime = chrono::Utc::now().timestamp().max(0) as u64;
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        archive
+            .append_data(&mut header, name, data.as_slice())
+            .map_err(write_err)?;
+    }
+    archive
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut file| file.flush())
+        .map_err(write_err)?;
+
+    tracing::info!(?path, "wrote debug bundle");
+    Ok(path)
+}
+
+/// Redacts the values of the env vars of the process and the hooks and the
+/// annotations whose name is on the [`REDACT_DENY_LIST`]
+pub fn redact_spec(mut spec: Spec) -> Spec {
+    if let Some(process) = spec.process_mut() {
+        let env = process.env().clone().map(redact_env);
+        process.set_env(env);
+    }
+
+    if let Some(mut hooks) = spec.hooks().clone() {
+        let redact_hooks = |hooks: &Option<Vec<oci_spec::runtime::Hook>>| {
+            hooks.clone().map(|hooks| {
+                hooks
+                    .into_iter()
+                    .map(|mut hook| {
+                        let env = hook.env().clone().map(redact_env);
+                        hook.set_env(env);
+                        hook
+                    })
+                    .collect()
+            })
+        };
+        hooks.set_prestart(redact_hooks(hooks.prestart()));
+        hooks.set_create_runtime(redact_hooks(hooks.create_runtime()));
+        hooks.set_create_container(redact_hooks(hooks.create_container()));
+        hooks.set_start_container(redact_hooks(hooks.start_container()));
+        hooks.set_poststart(redact_hooks(hooks.poststart()));
+        hooks.set_poststop(redact_hooks(hooks.poststop()));
+        spec.set_hooks(Some(hooks));
+    }
+
+    if let Some(annotations) = spec.annotations().clone() {
+        let annotations = annotations
+            .into_iter()
+            .map(|(name, value)| {
+                if is_denied(&name) {
+                    (name, REDACTED.to_owned())
+                } else {
+                    (name, value)
+                }
+            })
+            .collect();
+        spec.set_annotations(Some(annotations));
+    }
+
+    spec
+}
+
+fn redact_env(env: Vec<String>) -> Vec<String> {
+    env.into_iter()
+        .map(|var| match var.split_once('=') {
+            Some((name, _)) if is_denied(name) => format!("{name}={REDACTED}"),
+            _ => var,
+        })
+        .collect()
+}
+
+fn is_denied(name: &str) -> bool {
+    let name = name.to_lowercase();
+    REDACT_DENY_LIST.iter().any(|denied| name.contains(denied))
+}
+
+fn cgroups_report() -> String {
+    let setup = match libcgroups::common::get_cgroup_setup() {
+        Ok(setup) => setup,
+        Err(err) => return format!("cgroup setup unavailable: {err}\n"),
+    };
+    // The controllers of v1 hierarchies are listed in /proc/cgroups, the
+    // unified hierarchy lists its own.
+    let controllers_path = match setup {
+        CgroupSetup::Unified => Path::new(DEFAULT_CGROUP_ROOT).join("cgroup.controllers"),
+        CgroupSetup::Legacy | CgroupSetup::Hybrid => PathBuf::from("/proc/cgroups"),
+        _ => return format!("setup: {setup}\n"),
+    };
+    let controllers = String::from_utf8_lossy(&read_or_note(&controllers_path)).into_owned();
+
+    format!("setup: {setup}\n{controllers_path:?}:\n{controllers}")
+}
+
+fn read_or_note(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|err| unavailable(path, &err))
+}
+
+fn unavailable(path: &Path, err: &dyn std::fmt::Display) -> Vec<u8> {
+    format!("{path:?} unavailable: {err}\n").into_bytes()
+}
+
+fn lines(lines: &[String]) -> Vec<u8> {
+    lines
+        .iter()
+        .flat_map(|line| line.bytes().chain(std::iter::once(b'\n')))
+        .collect()
+}
+
+fn to_json<T: Serialize>(entry: &'static str, value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec_pretty(value)
+        .map_err(|err| DebugBundleError::Serialize { entry, source: err })
+}
+
+/// Serializes the spec with its capability sets in the canonical order, so
+/// the same spec always gives the same entry
+fn spec_to_json(entry: &'static str, spec: Spec) -> Result<Vec<u8>> {
+    let mut value = serde_json::to_value(spec)
+        .map_err(|err| DebugBundleError::Serialize { entry, source: err })?;
+    // The sets of a spec only hold known names, nothing is dropped.
+    if let Err(err) = capabilities::normalize_spec_json(&mut value, u8::MAX) {
+        tracing::warn!(?err, "failed to order the capabilities of the spec");
+    }
+    to_json(entry, &value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    use anyhow::Result;
+    use flate2::read::GzDecoder;
+    use oci_spec::runtime::{HookBuilder, HooksBuilder, ProcessBuilder};
+
+    use super::*;
+    use crate::error::{CreateContainerError, ErrInvalidID};
+
+    fn spec_with_secrets() -> Result<Spec> {
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default()
+                .args(vec!["sh".to_owned()])
+                .env(vec![
+                    "PATH=/usr/bin".to_owned(),
+                    "DB_PASSWORD=hunter2".to_owned(),
+                    "Api_Token=abc=def".to_owned(),
+                    "EMPTY".to_owned(),
+                ])
+                .build()?,
+        ));
+        spec.set_hooks(Some(
+            HooksBuilder::default()
+                .prestart(vec![HookBuilder::default()
+                    .path("/bin/hook")
+                    .env(vec!["AWS_SECRET_ACCESS_KEY=xyz".to_owned()])
+                    .build()?])
+                .build()?,
+        ));
+        spec.set_annotations(Some(HashMap::from([
+            ("io.example.auth-header".to_owned(), "Bearer xyz".to_owned()),
+            ("io.example.team".to_owned(), "storage".to_owned()),
+        ])));
+        Ok(spec)
+    }
+
+    #[test]
+    fn test_redact_spec() -> Result<()> {
+        let spec = redact_spec(spec_with_secrets()?);
+
+        assert_eq!(
+            spec.process().as_ref().unwrap().env().as_ref().unwrap(),
+            &vec![
+                "PATH=/usr/bin".to_owned(),
+                "DB_PASSWORD=<redacted>".to_owned(),
+                "Api_Token=<redacted>".to_owned(),
+                "EMPTY".to_owned(),
+            ]
+        );
+        let hook_env = spec.hooks().as_ref().unwrap().prestart().as_ref().unwrap()[0]
+            .env()
+            .clone();
+        assert_eq!(
+            hook_env,
+            Some(vec!["AWS_SECRET_ACCESS_KEY=<redacted>".to_owned()])
+        );
+        let annotations = spec.annotations().as_ref().unwrap();
+        assert_eq!(annotations["io.example.auth-header"], "<redacted>");
+        assert_eq!(annotations["io.example.team"], "storage");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_capabilities_ordered() -> Result<()> {
+        let spec = Spec::default();
+        let json: serde_json::Value =
+            serde_json::from_slice(&spec_to_json(RESOLVED_SPEC_ENTRY, spec)?)?;
+        // the default bounding set, in the order of the capability numbers
+        assert_eq!(
+            json["process"]["capabilities"]["bounding"],
+            serde_json::json!(["CAP_KILL", "CAP_NET_BIND_SERVICE", "CAP_AUDIT_WRITE"])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent_events() {
+        let events = RecentEvents::new(2);
+        let shared = events.clone();
+        for event in ["first", "second", "third"] {
+            shared.push(event.to_owned());
+        }
+        assert_eq!(events.events(), vec!["second", "third"]);
+
+        let events = RecentEvents::new(0);
+        events.push("dropped".to_owned());
+        assert!(events.events().is_empty());
+    }
+
+    #[test]
+    fn test_error_report() {
+        let err: LibcontainerError =
+            CreateContainerError::new(ErrInvalidID::Empty.into(), Some(LibcontainerError::Exist))
+                .into();
+        let report = ErrorReport::new(&err);
+        assert_eq!(report.code, "invalid_id");
+        assert!(!report.transient);
+        assert_eq!(report.message, "container id can't be empty");
+        let cleanup = report.cleanup.unwrap();
+        assert_eq!(cleanup.code, "exist");
+        assert!(cleanup.cleanup.is_none());
+    }
+
+    #[test]
+    fn test_collect_bundle() -> Result<()> {
+        let bundle = tempfile::tempdir()?;
+        spec_with_secrets()?.save(bundle.path().join("config.json"))?;
+        let out = tempfile::tempdir()?;
+        let events = RecentEvents::default();
+        events.push("ERROR failed to create container".to_owned());
+        let ctx = DebugContext {
+            container_id: "test".to_owned(),
+            bundle: Some(bundle.path().to_owned()),
+            resolved_spec: None,
+            recent_events: Some(events),
+        };
+
+        let path = collect_bundle(&out.path().join("bundles"), &ctx, &LibcontainerError::Exist)?;
+        assert!(path.starts_with(out.path().join("bundles")));
+
+        let mut archive = tar::Archive::new(GzDecoder::new(fs::File::open(&path)?));
+        let mut entries = HashMap::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let mut data = String::new();
+            entry.read_to_string(&mut data)?;
+            entries.insert(name, data);
+        }
+        let mut names: Vec<_> = entries.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            vec![
+                CGROUPS_ENTRY,
+                SPEC_ENTRY,
+                ERROR_ENTRY,
+                EVENTS_ENTRY,
+                MOUNTINFO_ENTRY,
+                UNAME_ENTRY
+            ]
+        );
+        assert!(!entries[SPEC_ENTRY].contains("hunter2"));
+        assert!(entries[SPEC_ENTRY].contains("DB_PASSWORD=<redacted>"));
+        assert!(entries[ERROR_ENTRY].contains("\"code\": \"exist\""));
+        assert_eq!(entries[EVENTS_ENTRY], "ERROR failed to create container\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+//! Environment variables of the container process read from env files
+//!
+//! Large env sets are easier to pass in files than as hundreds of entries of
+//! the spec. An env file, set with
+//! [`ContainerBuilder::with_env_file`](crate::container::builder::ContainerBuilder::with_env_file),
+//! is dotenv-style:
+//!
+//! - each line is a `NAME=VALUE`, the value is everything after the first `=`,
+//!   optionally preceded by `export `
+//! - blank lines and lines starting with `#` are ignored, an unquoted value
+//!   ends at a ` #` comment
+//! - a value in double quotes may contain the escapes `\"`, `\\`, `\n`, `\r`
+//!   and `\t`, a value in single quotes is taken as is
+//! - nothing is expanded, `$HOME` stays `$HOME`
+//! - lines may end with CRLF
+//!
+//! The env is layered, each layer overriding the vars of the layers before:
+//! the env files in the order they were added, then the env of the spec or
+//! process, then the env set explicitly with `with_env`.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum EnvFileError {
+    #[error("failed to read env file {path:?}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("env file {path:?} line {line}: {reason}")]
+    Malformed {
+        path: PathBuf,
+        line: usize,
+        reason: &'static str,
+    },
+}
+
+type Result<T> = std::result::Result<T, EnvFileError>;
+
+/// Reads the env files in order, a var of a later file overrides the same
+/// var of an earlier one. The vars are in the order they first appeared.
+pub fn load(paths: &[PathBuf]) -> Result<Vec<(String, String)>> {
+    let mut env = Vec::new();
+    for path in paths {
+        let content = fs::read_to_string(path).map_err(|source| EnvFileError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        for (name, value) in parse(path, &content)? {
+            set(&mut env, name, value);
+        }
+    }
+
+    Ok(env)
+}
+
+/// Parses the content of the env file at `path`, which is only used in the
+/// errors
+pub fn parse(path: &Path, content: &str) -> Result<Vec<(String, String)>> {
+    let mut env = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let malformed = |reason| EnvFileError::Malformed {
+            path: path.to_owned(),
+            line: idx + 1,
+            reason,
+        };
+
+        let line = line.strip_suffix('\r').unwrap_or(line).trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| malformed("missing '='"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(malformed("empty name"));
+        }
+        if name.contains(|c: char| c.is_whitespace() || c == '\0') {
+            return Err(malformed("invalid name"));
+        }
+        let value = parse_value(value.trim_start()).map_err(malformed)?;
+        if value.contains('\0') {
+            return Err(malformed("value contains a nul byte"));
+        }
+        env.push((name.to_owned(), value));
+    }
+
+    Ok(env)
+}
+
+fn parse_value(value: &str) -> std::result::Result<String, &'static str> {
+    let (parsed, rest) = match value.chars().next() {
+        Some('"') => {
+            let mut parsed = String::new();
+            let mut chars = value[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((idx, '"')) => break idx + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => parsed.push('\n'),
+                        Some((_, 'r')) => parsed.push('\r'),
+                        Some((_, 't')) => parsed.push('\t'),
+                        Some((_, c @ ('"' | '\\'))) => parsed.push(c),
+                        Some(_) => return Err("invalid escape in double quotes"),
+                        None => return Err("unterminated double quote"),
+                    },
+                    Some((_, c)) => parsed.push(c),
+                    None => return Err("unterminated double quote"),
+                }
+            };
+            (parsed, &value[end..])
+        }
+        Some('\'') => {
+            let end = value[1..].find('\'').ok_or("unterminated single quote")?;
+            (value[1..end + 1].to_owned(), &value[end + 2..])
+        }
+        _ => {
+            // A comment needs a blank before it, `a#b` is a value.
+            let end = value
+                .char_indices()
+                .find(|&(idx, c)| {
+                    c == '#' && value[..idx].ends_with(|c: char| c.is_ascii_whitespace())
+                })
+                .map_or(value.len(), |(idx, _)| idx);
+            return Ok(value[..end].trim_end().to_owned());
+        }
+    };
+
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err("unexpected characters after the quoted value");
+    }
+
+    Ok(parsed)
+}
+
+/// Layers the vars of the env files, the env of the spec or process, as
+/// `NAME=VALUE` entries, and the explicit vars, each overriding the ones
+/// before
+pub fn merge(
+    files: &[(String, String)],
+    env: &[String],
+    explicit: &HashMap<String, String>,
+) -> Vec<String> {
+    let mut merged: Vec<(String, String)> = files.to_vec();
+    for var in env {
+        let (name, value) = var.split_once('=').unwrap_or((var.as_str(), ""));
+        set(&mut merged, name.to_owned(), value.to_owned());
+    }
+    // The order of a HashMap isn't stable, the env of the process should be.
+    let mut explicit: Vec<_> = explicit.iter().collect();
+    explicit.sort_unstable();
+    for (name, value) in explicit {
+        set(&mut merged, name.clone(), value.clone());
+    }
+
+    merged
+        .into_iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect()
+}
+
+fn set(env: &mut Vec<(String, String)>, name: String, value: String) {
+    match env.iter_mut().find(|(var_name, _)| *var_name == name) {
+        Some((_, var_value)) => *var_value = value,
+        None => env.push((name, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{ProcessBuilder, Spec};
+
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let tests: &[(&str, &str, &[(&str, &str)])] = &[
+            ("empty", "", &[]),
+            ("plain", "A=1\nB=2\n", &[("A", "1"), ("B", "2")]),
+            ("crlf", "A=1\r\nB=2\r\n", &[("A", "1"), ("B", "2")]),
+            ("value with =", "URL=a=b=c", &[("URL", "a=b=c")]),
+            ("empty value", "A=", &[("A", "")]),
+            (
+                "comments and blanks",
+                "# comment\n\n  # indented\nA=1 # trailing\nB=a#b",
+                &[("A", "1"), ("B", "a#b")],
+            ),
+            ("export", "export A=1", &[("A", "1")]),
+            ("blanks around", "  A = 1  ", &[("A", "1")]),
+            (
+                "double quotes",
+                r#"A="a b # c" # comment"#,
+                &[("A", "a b # c")],
+            ),
+            (
+                "double quote escapes",
+                r#"A="say \"hi\"\n\\""#,
+                &[("A", "say \"hi\"\n\\")],
+            ),
+            ("single quotes", r"A='a \n $B'", &[("A", r"a \n $B")]),
+            ("no expansion", "A=$HOME/${B}", &[("A", "$HOME/${B}")]),
+            ("quoted crlf", "A=\"1\"\r\n", &[("A", "1")]),
+            ("repeated", "A=1\nA=2", &[("A", "1"), ("A", "2")]),
+        ];
+
+        for (name, content, expected) in tests {
+            let parsed =
+                parse(Path::new("test.env"), content).unwrap_or_else(|err| panic!("{name}: {err}"));
+            assert_eq!(parsed, vars(expected), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let tests: &[(&str, &str, usize, &str)] = &[
+            ("missing =", "A=1\nB", 2, "missing '='"),
+            ("empty name", "=1", 1, "empty name"),
+            ("blank in name", "A B=1", 1, "invalid name"),
+            (
+                "unterminated double",
+                "\r\nA=\"1\r\n",
+                2,
+                "unterminated double quote",
+            ),
+            (
+                "unterminated single",
+                "A='1",
+                1,
+                "unterminated single quote",
+            ),
+            (
+                "invalid escape",
+                r#"A="\x""#,
+                1,
+                "invalid escape in double quotes",
+            ),
+            (
+                "after quotes",
+                "A=\"1\"2",
+                1,
+                "unexpected characters after the quoted value",
+            ),
+        ];
+
+        for (name, content, line, reason) in tests {
+            match parse(Path::new("test.env"), content) {
+                Err(EnvFileError::Malformed {
+                    path,
+                    line: got_line,
+                    reason: got_reason,
+                }) => {
+                    assert_eq!(path, Path::new("test.env"), "{name}");
+                    assert_eq!((got_line, got_reason), (*line, *reason), "{name}");
+                }
+                other => panic!("{name}: expected malformed, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_layers_files() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let first = tmp.path().join("first.env");
+        let second = tmp.path().join("second.env");
+        fs::write(&first, "A=1\r\nB=1\r\n")?;
+        fs::write(&second, "B=2\nC=2\n")?;
+
+        let env = load(&[first, second])?;
+        assert_eq!(env, vars(&[("A", "1"), ("B", "2"), ("C", "2")]));
+
+        let missing = tmp.path().join("missing.env");
+        assert!(matches!(
+            load(&[missing.clone()]),
+            Err(EnvFileError::Read { path, .. }) if path == missing
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() {
+        let tests: &[(&str, &[(&str, &str)], &[&str], &[(&str, &str)], &[&str])] = &[
+            ("nothing", &[], &[], &[], &[]),
+            ("files only", &[("A", "1")], &[], &[], &["A=1"]),
+            (
+                "spec wins over files",
+                &[("A", "file"), ("B", "file")],
+                &["B=spec", "C=spec"],
+                &[],
+                &["A=file", "B=spec", "C=spec"],
+            ),
+            (
+                "explicit wins over all",
+                &[("A", "file")],
+                &["A=spec"],
+                &[("A", "explicit"), ("B", "explicit")],
+                &["A=explicit", "B=explicit"],
+            ),
+            (
+                "spec value with =",
+                &[("A", "file")],
+                &["A=x=y"],
+                &[],
+                &["A=x=y"],
+            ),
+        ];
+
+        for (name, files, env, explicit, expected) in tests {
+            let env: Vec<String> = env.iter().map(|var| var.to_string()).collect();
+            let explicit: HashMap<String, String> = vars(explicit).into_iter().collect();
+            assert_eq!(
+                merge(&vars(files), &env, &explicit),
+                expected
+                    .iter()
+                    .map(|var| var.to_string())
+                    .collect::<Vec<_>>(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_merged_env_redacted() -> Result<()> {
+        let env = merge(
+            &vars(&[("API_TOKEN", "s3cr3t"), ("MODE", "ci")]),
+            &[],
+            &HashMap::new(),
+        );
+        let mut spec = Spec::default();
+        spec.set_process(Some(ProcessBuilder::default().env(env).build()?));
+
+        let spec = crate::debug::redact_spec(spec);
+        assert_eq!(
+            spec.process().as_ref().unwrap().env().as_ref().unwrap(),
+            &vec!["API_TOKEN=<redacted>".to_owned(), "MODE=ci".to_owned()]
+        );
+
+        Ok(())
+    }
+}
@@ -1,3 +1,11 @@
+use nix::errno::Errno;
+
+use crate::channel::ChannelError as BaseChannelError;
+use crate::namespaces::NamespaceError;
+use crate::process::channel::ChannelError as ProcessChannelError;
+use crate::process::container_main_process::ProcessError;
+use crate::syscall::SyscallError;
+
 #[derive(Debug, thiserror::Error)]
 pub enum MissingSpecError {
     #[error("missing process in spec")]
@@ -24,6 +32,24 @@ pub enum LibcontainerError {
     NoExecutors,
     #[error("rootless container requires valid user namespace definition")]
     NoUserNamespace,
+    #[error("cgroup path {path:?} is outside of the delegated cgroup {delegated_root:?}")]
+    CgroupPathEscapesDelegation {
+        path: std::path::PathBuf,
+        delegated_root: std::path::PathBuf,
+    },
+    #[error("mounting cgroup2 inside the container requires the unified cgroup hierarchy")]
+    Cgroup2MountRequiresUnified,
+    #[error(
+        "cgroup2 can't be mounted inside the container when it joins an existing cgroup namespace"
+    )]
+    Cgroup2MountWithJoinedCgroupNamespace,
+    #[error("mount target {0:?} is not an absolute path")]
+    RelativeMountTarget(std::path::PathBuf),
+    #[error("mount {target:?} has conflicting options {options:?}")]
+    ConflictingMountOptions {
+        target: std::path::PathBuf,
+        options: (String, String),
+    },
 
     // Invalid inputs
     #[error(transparent)]
@@ -39,10 +65,20 @@ pub enum LibcontainerError {
     #[error(transparent)]
     UserNamespace(#[from] crate::user_ns::UserNamespaceError),
     #[error(transparent)]
+    Namespace(#[from] crate::namespaces::NamespaceError),
+    #[error(transparent)]
     NotifyListener(#[from] crate::notify_socket::NotifyListenerError),
     #[error(transparent)]
+    ReadinessProbe(#[from] crate::readiness_probe::ReadinessProbeError),
+    #[error(transparent)]
     Config(#[from] crate::config::ConfigError),
     #[error(transparent)]
+    RuntimeRoot(#[from] crate::runtime_root::RuntimeRootError),
+    #[error(transparent)]
+    CreateDescriptor(#[from] crate::container::create_descriptor::CreateDescriptorError),
+    #[error(transparent)]
+    SpecDiff(#[from] crate::spec_diff::SpecDiffError),
+    #[error(transparent)]
     Hook(#[from] crate::hooks::HookError),
     #[error(transparent)]
     State(#[from] crate::container::state::StateError),
@@ -55,6 +91,8 @@ pub enum LibcontainerError {
     #[error(transparent)]
     Capabilities(#[from] caps::errors::CapsError),
     #[error(transparent)]
+    CapabilityName(#[from] crate::capabilities::CapabilityError),
+    #[error(transparent)]
     CgroupManager(#[from] libcgroups::common::AnyManagerError),
     #[error(transparent)]
     CgroupCreate(#[from] libcgroups::common::CreateCgroupSetupError),
@@ -64,6 +102,58 @@ pub enum LibcontainerError {
     Checkpoint(#[from] crate::container::CheckpointError),
     #[error[transparent]]
     CreateContainerError(#[from] CreateContainerError),
+    #[error(transparent)]
+    SharedVolume(#[from] crate::shared_volume::SharedVolumeError),
+    #[error(transparent)]
+    ExecSession(#[from] crate::container::ExecSessionError),
+    #[error(transparent)]
+    SocketHandoff(#[from] crate::socket_handoff::SocketHandoffError),
+    #[error(transparent)]
+    Numa(#[from] crate::numa::NumaError),
+    #[error(transparent)]
+    Cpuset(#[from] crate::cpuset::CpusetError),
+    #[error(transparent)]
+    CoreSched(#[from] crate::core_sched::CoreSchedError),
+    #[error(transparent)]
+    AnnotationEnv(#[from] crate::annotation_env::AnnotationEnvError),
+    #[error(transparent)]
+    EnvFile(#[from] crate::env_file::EnvFileError),
+    #[error(transparent)]
+    FastExec(#[from] crate::process::fast_exec::FastExecError),
+    #[error(transparent)]
+    Cleanup(#[from] crate::container::CleanupError),
+    #[error(transparent)]
+    SpecValidation(#[from] crate::spec_limits::SpecValidationError),
+    #[error(transparent)]
+    Sysctl(#[from] crate::sysctl::SysctlError),
+    #[error(transparent)]
+    FaultInjection(#[from] crate::fault_injection::FaultInjectionError),
+    #[error(transparent)]
+    OwnershipShift(#[from] crate::rootfs::ownership_shift::OwnershipShiftError),
+    #[error("hostname or domainname is set without a uts namespace of the container")]
+    HostnameWithoutUtsNamespace,
+    #[error("setting the process non-dumpable is not permitted")]
+    DumpableNotPermitted,
+    #[error("seccomp requires noNewPrivileges for a process without CAP_SYS_ADMIN")]
+    SeccompRequiresNoNewPrivs,
+    #[error("container init process {pid} exited right after signaling readiness")]
+    InitExitedEarly { pid: i32 },
+    #[error("failed to execute {path:?}: {errno}")]
+    ExecFailed {
+        path: std::path::PathBuf,
+        errno: nix::errno::Errno,
+    },
+    #[error("failed to create {namespace:?} namespace: {errno}")]
+    NamespaceCreateFailed {
+        namespace: oci_spec::runtime::LinuxNamespaceType,
+        errno: nix::errno::Errno,
+    },
+    #[error("seccomp listener didn't take the seccomp notify fd within {timeout:?}")]
+    SeccompNotifyTimeout { timeout: std::time::Duration },
+    #[error("create was interrupted by {signal}")]
+    CreateInterrupted { signal: nix::sys::signal::Signal },
+    #[error("{operation} is not supported for a container whose cgroups are not managed")]
+    UnsupportedWithoutCgroups { operation: &'static str },
 
     // Catch all errors that are not covered by the above
     #[error("syscall error")]
@@ -78,6 +168,286 @@ pub enum LibcontainerError {
     Other(String),
 }
 
+impl LibcontainerError {
+    /// Stable identifier of the kind of error, for tooling that consumes
+    /// errors without parsing their messages
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::IncorrectStatus => "incorrect_status",
+            Self::Exist => "exist",
+            Self::NoDirectory => "no_directory",
+            Self::InvalidInput(_) => "invalid_input",
+            Self::NoExecutors => "no_executors",
+            Self::NoUserNamespace => "no_user_namespace",
+            Self::CgroupPathEscapesDelegation { .. } => "cgroup_path_escapes_delegation",
+            Self::Cgroup2MountRequiresUnified => "cgroup2_mount_requires_unified",
+            Self::Cgroup2MountWithJoinedCgroupNamespace => {
+                "cgroup2_mount_with_joined_cgroup_namespace"
+            }
+            Self::RelativeMountTarget(_) => "relative_mount_target",
+            Self::ConflictingMountOptions { .. } => "conflicting_mount_options",
+            Self::InvalidID(_) => "invalid_id",
+            Self::MissingSpec(_) => "missing_spec",
+            Self::InvalidSpec(_) => "invalid_spec",
+            Self::Tty(_) => "tty",
+            Self::UserNamespace(_) => "user_namespace",
+            Self::Namespace(_) => "namespace",
+            Self::NotifyListener(_) => "notify_listener",
+            Self::ReadinessProbe(_) => "readiness_probe",
+            Self::Config(_) => "config",
+            Self::RuntimeRoot(_) => "runtime_root",
+            Self::CreateDescriptor(_) => "create_descriptor",
+            Self::SpecDiff(_) => "spec_diff",
+            Self::Hook(_) => "hook",
+            Self::State(_) => "state",
+            Self::Spec(_) => "spec",
+            Self::MainProcess(_) => "main_process",
+            Self::Procfs(_) => "procfs",
+            Self::Capabilities(_) => "capabilities",
+            Self::CapabilityName(_) => "capability_name",
+            Self::CgroupManager(_) => "cgroup_manager",
+            Self::CgroupCreate(_) => "cgroup_create",
+            Self::CgroupGet(_) => "cgroup_get",
+            Self::Checkpoint(_) => "checkpoint",
+            // The create error only wraps the error the create failed with.
+            Self::CreateContainerError(err) => err.0.code(),
+            Self::SharedVolume(_) => "shared_volume",
+            Self::ExecSession(_) => "exec_session",
+            Self::SocketHandoff(_) => "socket_handoff",
+            Self::Numa(_) => "numa",
+            Self::Cpuset(_) => "cpuset",
+            Self::CoreSched(_) => "core_sched",
+            Self::AnnotationEnv(_) => "annotation_env",
+            Self::EnvFile(_) => "env_file",
+            Self::FastExec(_) => "fast_exec",
+            Self::Cleanup(_) => "cleanup",
+            Self::SpecValidation(_) => "spec_validation",
+            Self::Sysctl(_) => "sysctl",
+            Self::OwnershipShift(_) => "ownership_shift",
+            Self::FaultInjection(_) => "fault_injection",
+            Self::HostnameWithoutUtsNamespace => "hostname_without_uts_namespace",
+            Self::DumpableNotPermitted => "dumpable_not_permitted",
+            Self::SeccompRequiresNoNewPrivs => "seccomp_requires_no_new_privs",
+            Self::InitExitedEarly { .. } => "init_exited_early",
+            Self::ExecFailed { .. } => "exec_failed",
+            Self::NamespaceCreateFailed { .. } => "namespace_create_failed",
+            Self::SeccompNotifyTimeout { .. } => "seccomp_notify_timeout",
+            Self::CreateInterrupted { .. } => "create_interrupted",
+            Self::UnsupportedWithoutCgroups { .. } => "unsupported_without_cgroups",
+            Self::OtherSyscall(_) => "other_syscall",
+            Self::OtherIO(_) => "other_io",
+            Self::OtherSerialization(_) => "other_serialization",
+            Self::OtherCgroup(_) => "other_cgroup",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// Returns if retrying the operation that failed with the error may
+    /// succeed, because the failure was caused by a condition that goes away
+    /// on its own, e.g. a busy cgroup, a process limit that was hit for the
+    /// moment or a child that didn't respond in time. Invalid input, a
+    /// missing binary and failures whose cause is unknown are permanent. An
+    /// errno that an error only wraps transparently can't be seen and counts
+    /// as permanent, too.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // Invalid input and state don't get better by retrying.
+            Self::IncorrectStatus
+            | Self::Exist
+            | Self::NoDirectory
+            | Self::InvalidInput(_)
+            | Self::NoExecutors
+            | Self::NoUserNamespace
+            | Self::CgroupPathEscapesDelegation { .. }
+            | Self::Cgroup2MountRequiresUnified
+            | Self::Cgroup2MountWithJoinedCgroupNamespace
+            | Self::RelativeMountTarget(_)
+            | Self::ConflictingMountOptions { .. }
+            | Self::InvalidID(_)
+            | Self::MissingSpec(_)
+            | Self::InvalidSpec(_)
+            | Self::Spec(_)
+            | Self::CapabilityName(_)
+            | Self::SpecValidation(_)
+            | Self::HostnameWithoutUtsNamespace
+            | Self::DumpableNotPermitted
+            | Self::SeccompRequiresNoNewPrivs
+            | Self::UnsupportedWithoutCgroups { .. }
+            | Self::FaultInjection(_) => false,
+            // The workload failed, or the caller asked the create to stop.
+            Self::InitExitedEarly { .. } | Self::CreateInterrupted { .. } => false,
+            Self::SeccompNotifyTimeout { .. } => true,
+            Self::ExecFailed { errno, .. } | Self::NamespaceCreateFailed { errno, .. } => {
+                errno_is_transient(*errno)
+            }
+            Self::OtherSyscall(errno) => errno_is_transient(*errno),
+            Self::OtherIO(err) => io_is_transient(err),
+            Self::OtherSerialization(_) | Self::OtherCgroup(_) | Self::Other(_) => false,
+            // A failed create is retried as a whole, whatever its cleanup did.
+            Self::CreateContainerError(err) => err.run_error().is_transient(),
+            Self::MainProcess(err) => process_error_is_transient(err),
+            // The errors of the submodules carry the errno of the failure
+            // among their sources.
+            Self::Tty(err) => sources_are_transient(err),
+            Self::UserNamespace(err) => sources_are_transient(err),
+            Self::Namespace(err) => namespace_error_is_transient(err),
+            Self::NotifyListener(err) => sources_are_transient(err),
+            Self::ReadinessProbe(err) => sources_are_transient(err),
+            Self::Config(err) => sources_are_transient(err),
+            Self::RuntimeRoot(err) => sources_are_transient(err),
+            Self::CreateDescriptor(err) => sources_are_transient(err),
+            Self::SpecDiff(err) => sources_are_transient(err),
+            Self::Hook(err) => sources_are_transient(err),
+            Self::State(err) => sources_are_transient(err),
+            Self::Procfs(err) => sources_are_transient(err),
+            Self::Capabilities(err) => sources_are_transient(err),
+            Self::CgroupManager(err) => sources_are_transient(err),
+            Self::CgroupCreate(err) => sources_are_transient(err),
+            Self::CgroupGet(err) => sources_are_transient(err),
+            Self::Checkpoint(err) => sources_are_transient(err),
+            Self::SharedVolume(err) => sources_are_transient(err),
+            Self::ExecSession(err) => sources_are_transient(err),
+            Self::SocketHandoff(err) => sources_are_transient(err),
+            Self::Numa(err) => sources_are_transient(err),
+            Self::Cpuset(err) => sources_are_transient(err),
+            Self::CoreSched(err) => sources_are_transient(err),
+            Self::AnnotationEnv(err) => sources_are_transient(err),
+            Self::EnvFile(err) => sources_are_transient(err),
+            Self::FastExec(err) => sources_are_transient(err),
+            Self::Cleanup(err) => sources_are_transient(err),
+            Self::Sysctl(err) => sources_are_transient(err),
+            Self::OwnershipShift(err) => sources_are_transient(err),
+        }
+    }
+}
+
+/// Errnos of conditions that go away on their own: a resource that is busy
+/// or exhausted for the moment, an interrupted call or a timeout. ENOMEM
+/// isn't one of them, it mostly comes from a limit such as the memory limit
+/// of the cgroup or the number of namespaces, which a retry runs into again.
+fn errno_is_transient(errno: Errno) -> bool {
+    matches!(
+        errno,
+        Errno::EAGAIN
+            | Errno::EBUSY
+            | Errno::EINTR
+            | Errno::ETIMEDOUT
+            | Errno::ETXTBSY
+            | Errno::ENOBUFS
+            | Errno::EMFILE
+            | Errno::ENFILE
+    )
+}
+
+fn io_is_transient(err: &std::io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(errno) => errno_is_transient(Errno::from_raw(errno)),
+        None => matches!(
+            err.kind(),
+            std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::WouldBlock
+                | std::io::ErrorKind::Interrupted
+        ),
+    }
+}
+
+/// The main process reports the failures of the intermediate and init
+/// processes through the channel, with the errno if there is one.
+fn process_error_is_transient(err: &ProcessError) -> bool {
+    match err {
+        ProcessError::Channel(err) => channel_error_is_transient(err),
+        ProcessError::CreateTimeout { .. } => true,
+        ProcessError::ContainerStateRequired => false,
+        ProcessError::WaitIntermediateProcess(errno) | ProcessError::InitPidfd(_, errno) => {
+            errno_is_transient(*errno)
+        }
+        ProcessError::SetGroupsDeny(err) => io_is_transient(err),
+        ProcessError::SyscallOther(err) => syscall_error_is_transient(err),
+        ProcessError::UserNamespace(err) => sources_are_transient(err),
+        ProcessError::PartialIdMapping { source, .. } => sources_are_transient(source),
+        ProcessError::IntelRdt(err) => sources_are_transient(err),
+        ProcessError::IntermediateProcessFailed(err) => sources_are_transient(err),
+        #[cfg(feature = "libseccomp")]
+        ProcessError::SeccompListener(err) => sources_are_transient(err),
+        ProcessError::ExitWaiter(err) => sources_are_transient(err),
+    }
+}
+
+fn channel_error_is_transient(err: &ProcessChannelError) -> bool {
+    match err {
+        ProcessChannelError::Timeout(_) => true,
+        ProcessChannelError::NamespaceCreateFailed { errno, .. } => errno_is_transient(*errno),
+        ProcessChannelError::ReceiveError { source, .. } => base_channel_error_is_transient(source),
+        ProcessChannelError::BaseChannelError(err) => base_channel_error_is_transient(err),
+        // Only the message of these errors crosses the channel, their cause
+        // is unknown.
+        ProcessChannelError::ExecError(_) | ProcessChannelError::OtherError(_) => false,
+        ProcessChannelError::UnexpectedMessage { .. }
+        | ProcessChannelError::MissingSeccompFds
+        | ProcessChannelError::UnsupportedVersion(_) => false,
+    }
+}
+
+fn base_channel_error_is_transient(err: &BaseChannelError) -> bool {
+    match err {
+        BaseChannelError::Nix(errno) => errno_is_transient(*errno),
+        // A broken channel means the process on the other end died.
+        BaseChannelError::Serde(_) | BaseChannelError::BrokenChannel => false,
+    }
+}
+
+fn namespace_error_is_transient(err: &NamespaceError) -> bool {
+    match err {
+        NamespaceError::Nix(errno) => errno_is_transient(*errno),
+        NamespaceError::IO(err) => io_is_transient(err),
+        NamespaceError::Syscall(err) | NamespaceError::CreateFailed { source: err, .. } => {
+            syscall_error_is_transient(err)
+        }
+        NamespaceError::NotSupported(_) => false,
+    }
+}
+
+fn syscall_error_is_transient(err: &SyscallError) -> bool {
+    match err {
+        SyscallError::Nix(errno) => errno_is_transient(*errno),
+        SyscallError::IO(err) => io_is_transient(err),
+        SyscallError::UnexpectedMountRecursiveOption(_) | SyscallError::SetCaps(_) => false,
+    }
+}
+
+/// Classifies an error by the first errno among it and its sources. A
+/// transparent variant forwards the sources of the error it wraps, so the
+/// errors that are mostly wrapped that way are looked at directly.
+fn sources_are_transient(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(errno) = err.downcast_ref::<Errno>() {
+            return errno_is_transient(*errno);
+        }
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            return io_is_transient(err);
+        }
+        if let Some(err) = err.downcast_ref::<SyscallError>() {
+            return syscall_error_is_transient(err);
+        }
+        if let Some(err) = err.downcast_ref::<NamespaceError>() {
+            return namespace_error_is_transient(err);
+        }
+        if let Some(err) = err.downcast_ref::<ProcessChannelError>() {
+            return channel_error_is_transient(err);
+        }
+        if let Some(err) = err.downcast_ref::<BaseChannelError>() {
+            return base_channel_error_is_transient(err);
+        }
+        if let Some(err) = err.downcast_ref::<LibcontainerError>() {
+            return err.is_transient();
+        }
+        current = err.source();
+    }
+
+    false
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ErrInvalidID {
     #[error("container id can't be empty")]
@@ -86,6 +456,10 @@ pub enum ErrInvalidID {
     InvalidChars(char),
     #[error("container id can't be used to represent a file name (such as . or ..)")]
     FileName,
+    #[error("container id can't start with {0:?}")]
+    InvalidLeadingChar(char),
+    #[error("container id is {len} bytes long, the maximum is {max}")]
+    TooLong { len: usize, max: usize },
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -98,6 +472,22 @@ pub enum ErrInvalidSpec {
     IoPriority,
     #[error("invalid scheduler config for process")]
     Scheduler,
+    #[error("cpuset partition requires the cpus of the cpuset to be set")]
+    CpusetPartition,
+    #[error("swap limit requires a memory limit to be set")]
+    SwapLimit,
+    #[error("pids.max override {0} must be positive or -1 for unlimited")]
+    PidsMaxOverride(i64),
+    #[error("hostname or domainname is set while joining an existing uts namespace")]
+    HostnameWithJoinedUts,
+    #[error("a cgroup is created for the container while joining an existing cgroup namespace")]
+    CgroupWithJoinedCgroupNamespace,
+    #[error("invalid container log level annotation {0:?}")]
+    LogLevelAnnotation(String),
+    #[error("uid {0} to run the container as is not mapped in the user namespace")]
+    RunAsUidNotMapped(u32),
+    #[error("gid {0} to run the container as is not mapped in the user namespace")]
+    RunAsGidNotMapped(u32),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -110,6 +500,25 @@ impl CreateContainerError {
     ) -> Self {
         Self(Box::new(run_error), cleanup_error.map(Box::new))
     }
+
+    /// The error the create failed with
+    pub fn run_error(&self) -> &LibcontainerError {
+        &self.0
+    }
+
+    /// The error the cleanup after the failed create failed with, if any
+    pub fn cleanup_error(&self) -> Option<&LibcontainerError> {
+        self.1.as_deref()
+    }
+
+    /// Outcome of each step of the cleanup after the failed create, if the
+    /// cleanup ran and didn't release everything
+    pub fn cleanup_report(&self) -> Option<&crate::container::CleanupReport> {
+        match self.1.as_deref() {
+            Some(LibcontainerError::Cleanup(err)) => Some(&err.0),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for CreateContainerError {
@@ -124,9 +533,21 @@ impl std::fmt::Display for CreateContainerError {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use libcgroups::common::CreateCgroupSetupError;
+    use nix::errno::Errno;
+    use nix::unistd::Pid;
+    use oci_spec::runtime::LinuxNamespaceType;
 
-    use super::{CreateContainerError, ErrInvalidID};
+    use super::{
+        BaseChannelError, CreateContainerError, ErrInvalidID, ErrInvalidSpec, LibcontainerError,
+        ProcessChannelError, ProcessError,
+    };
+    use crate::container::{CleanupError, CleanupReport};
+    use crate::syscall::SyscallError;
+    use crate::tty::{StdIO, TTYError};
+    use crate::user_ns::{MappingError, UserNamespaceError};
 
     #[test]
     fn test_create_container() {
@@ -149,4 +570,111 @@ mod tests {
             msg
         );
     }
+
+    #[test]
+    fn test_create_container_cleanup_report() {
+        let create_container_err =
+            CreateContainerError::new(CreateCgroupSetupError::NonDefault.into(), None);
+        assert!(create_container_err.cleanup_report().is_none());
+
+        let report = CleanupReport::default();
+        let create_container_err = CreateContainerError::new(
+            CreateCgroupSetupError::NonDefault.into(),
+            Some(CleanupError(report.clone()).into()),
+        );
+        assert_eq!(create_container_err.cleanup_report(), Some(&report));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let transient: Vec<LibcontainerError> = vec![
+            // a cgroup that still has processes or a busy mount
+            LibcontainerError::OtherSyscall(Errno::EBUSY),
+            // clone of the intermediate process hit the process limit
+            LibcontainerError::NamespaceCreateFailed {
+                namespace: LinuxNamespaceType::Pid,
+                errno: Errno::EAGAIN,
+            },
+            ProcessError::Channel(ProcessChannelError::NamespaceCreateFailed {
+                namespace: LinuxNamespaceType::User,
+                errno: Errno::EAGAIN,
+            })
+            .into(),
+            // newuidmap didn't write the mappings in time
+            ProcessError::Channel(ProcessChannelError::Timeout(
+                "waiting for mapping request".to_owned(),
+            ))
+            .into(),
+            ProcessError::CreateTimeout {
+                pid: Pid::from_raw(1),
+                timeout: Duration::from_secs(1),
+                diagnostics: Default::default(),
+            }
+            .into(),
+            ProcessError::SyscallOther(SyscallError::Nix(Errno::EINTR)).into(),
+            ProcessError::Channel(ProcessChannelError::BaseChannelError(
+                BaseChannelError::Nix(Errno::EAGAIN),
+            ))
+            .into(),
+            // the binary is being written
+            LibcontainerError::ExecFailed {
+                path: "/bin/sh".into(),
+                errno: Errno::ETXTBSY,
+            },
+            LibcontainerError::OtherIO(std::io::ErrorKind::TimedOut.into()),
+            TTYError::ConnectStdIO {
+                source: Errno::EMFILE,
+                stdio: StdIO::Stdout,
+            }
+            .into(),
+            LibcontainerError::SeccompNotifyTimeout {
+                timeout: Duration::from_secs(1),
+            },
+            CreateContainerError::new(
+                LibcontainerError::OtherSyscall(Errno::EAGAIN),
+                Some(LibcontainerError::Exist),
+            )
+            .into(),
+        ];
+        for err in transient {
+            assert!(err.is_transient(), "{err:?} should be transient");
+        }
+
+        let permanent: Vec<LibcontainerError> = vec![
+            ErrInvalidSpec::UnsupportedVersion.into(),
+            ErrInvalidID::Empty.into(),
+            // newuidmap isn't installed
+            UserNamespaceError::IDMapping(MappingError::BinaryNotFound).into(),
+            LibcontainerError::ExecFailed {
+                path: "/bin/missing".into(),
+                errno: Errno::ENOENT,
+            },
+            LibcontainerError::NamespaceCreateFailed {
+                namespace: LinuxNamespaceType::User,
+                errno: Errno::EPERM,
+            },
+            LibcontainerError::OtherSyscall(Errno::EINVAL),
+            // the memory limit of the cgroup is too low for the init
+            LibcontainerError::OtherSyscall(Errno::ENOMEM),
+            // only the message of the init error crossed the channel
+            ProcessError::Channel(ProcessChannelError::ExecError(
+                "failed to execute the workload".to_owned(),
+            ))
+            .into(),
+            ProcessError::Channel(ProcessChannelError::BaseChannelError(
+                BaseChannelError::BrokenChannel,
+            ))
+            .into(),
+            LibcontainerError::InitExitedEarly { pid: 1 },
+            LibcontainerError::Other("unknown".to_owned()),
+            CreateContainerError::new(
+                CreateCgroupSetupError::NonDefault.into(),
+                Some(LibcontainerError::OtherSyscall(Errno::EBUSY)),
+            )
+            .into(),
+        ];
+        for err in permanent {
+            assert!(!err.is_transient(), "{err:?} should be permanent");
+        }
+    }
 }
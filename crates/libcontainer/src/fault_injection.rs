@@ -0,0 +1,209 @@
+//! Injection of delays and failures into the create of a container
+//!
+//! Shims and other callers of youki test how they handle a slow or failing
+//! runtime, e.g. a hook taking 30s or a cgroup that fails to apply, by
+//! injecting such faults at the named [`FaultPoint`]s of the create. The
+//! names of the points are stable. Faults are only set with the
+//! `fault_injection` feature, with
+//! [`ContainerBuilder::with_fault_injection`](crate::container::builder::ContainerBuilder::with_fault_injection),
+//! or, for end-to-end tests of the binary, parsed from the
+//! [`FAULT_INJECTION_ENV`] env var by [`FaultInjection::from_env`].
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{fmt, thread};
+
+/// Env var the faults are read from, e.g.
+/// `pre-hooks=delay:30000,pre-cgroup-apply=fail`, with delays in
+/// milliseconds
+pub const FAULT_INJECTION_ENV: &str = "YOUKI_FAULT_INJECTION";
+
+#[derive(Debug, thiserror::Error)]
+pub enum FaultInjectionError {
+    #[error("injected failure at {0}")]
+    Injected(FaultPoint),
+    #[error("invalid fault injection {0:?}, expected <point>=fail or <point>=delay:<ms>")]
+    Invalid(String),
+}
+
+type Result<T> = std::result::Result<T, FaultInjectionError>;
+
+/// Point of the create a fault can be injected at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultPoint {
+    /// In the calling process, before the container processes are cloned
+    PreClone,
+    /// In the calling process, once the init process is ready
+    PostClone,
+    /// In the intermediate process, before the cgroups are applied
+    PreCgroupApply,
+    /// In the calling process, before the create runtime hooks run
+    PreHooks,
+    /// In the init process, before it notifies the calling process it is
+    /// ready
+    PreNotify,
+}
+
+impl FaultPoint {
+    /// All the points, in the order the create passes them
+    pub const ALL: [FaultPoint; 5] = [
+        FaultPoint::PreClone,
+        FaultPoint::PreCgroupApply,
+        FaultPoint::PreNotify,
+        FaultPoint::PostClone,
+        FaultPoint::PreHooks,
+    ];
+
+    /// Stable name of the point
+    pub fn name(&self) -> &'static str {
+        match self {
+            FaultPoint::PreClone => "pre-clone",
+            FaultPoint::PostClone => "post-clone",
+            FaultPoint::PreCgroupApply => "pre-cgroup-apply",
+            FaultPoint::PreHooks => "pre-hooks",
+            FaultPoint::PreNotify => "pre-notify",
+        }
+    }
+}
+
+impl fmt::Display for FaultPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for FaultPoint {
+    type Err = FaultInjectionError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        FaultPoint::ALL
+            .into_iter()
+            .find(|point| point.name() == name)
+            .ok_or_else(|| FaultInjectionError::Invalid(name.to_owned()))
+    }
+}
+
+/// What happens at a point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The create sleeps, then goes on
+    Delay(Duration),
+    /// The create fails with [`FaultInjectionError::Injected`]
+    Fail,
+}
+
+/// Faults injected into the create, by point
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultInjection {
+    faults: HashMap<FaultPoint, Fault>,
+}
+
+impl FaultInjection {
+    /// Injects the fault at the point, replacing a fault set before
+    pub fn with_fault(mut self, point: FaultPoint, fault: Fault) -> Self {
+        self.faults.insert(point, fault);
+        self
+    }
+
+    /// Parses the faults from the [`FAULT_INJECTION_ENV`] env var, `None` if
+    /// it isn't set
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(FAULT_INJECTION_ENV) {
+            Ok(value) => value.parse().map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Applies the fault injected at the point, if any
+    pub(crate) fn inject(&self, point: FaultPoint) -> Result<()> {
+        match self.faults.get(&point) {
+            Some(Fault::Delay(delay)) => {
+                tracing::warn!(%point, ?delay, "injecting delay");
+                thread::sleep(*delay);
+                Ok(())
+            }
+            Some(Fault::Fail) => {
+                tracing::warn!(%point, "injecting failure");
+                Err(FaultInjectionError::Injected(point))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for FaultInjection {
+    type Err = FaultInjectionError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut injection = FaultInjection::default();
+        for fault in value.split(',').filter(|fault| !fault.is_empty()) {
+            let invalid = || FaultInjectionError::Invalid(fault.to_owned());
+            let (point, action) = fault.split_once('=').ok_or_else(invalid)?;
+            let point = point.trim().parse()?;
+            let fault = match action.trim().split_once(':') {
+                None if action.trim() == "fail" => Fault::Fail,
+                Some(("delay", ms)) => {
+                    Fault::Delay(Duration::from_millis(ms.parse().map_err(|_| invalid())?))
+                }
+                _ => return Err(invalid()),
+            };
+            injection = injection.with_fault(point, fault);
+        }
+        Ok(injection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_point_names() -> Result<()> {
+        for point in FaultPoint::ALL {
+            assert_eq!(point.name().parse::<FaultPoint>()?, point);
+        }
+        assert!("pre-start".parse::<FaultPoint>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let injection: FaultInjection = "pre-hooks=delay:30000,pre-cgroup-apply=fail".parse()?;
+        assert_eq!(
+            injection,
+            FaultInjection::default()
+                .with_fault(FaultPoint::PreHooks, Fault::Delay(Duration::from_secs(30)))
+                .with_fault(FaultPoint::PreCgroupApply, Fault::Fail)
+        );
+        assert_eq!("".parse::<FaultInjection>()?, FaultInjection::default());
+        for invalid in ["pre-hooks", "pre-hooks=crash", "pre-hooks=delay:soon"] {
+            assert!(
+                invalid.parse::<FaultInjection>().is_err(),
+                "{invalid} was parsed"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_inject() {
+        let injection = FaultInjection::default()
+            .with_fault(FaultPoint::PreClone, Fault::Fail)
+            .with_fault(
+                FaultPoint::PreHooks,
+                Fault::Delay(Duration::from_millis(20)),
+            );
+        assert!(matches!(
+            injection.inject(FaultPoint::PreClone),
+            Err(FaultInjectionError::Injected(FaultPoint::PreClone))
+        ));
+        let start = Instant::now();
+        assert!(injection.inject(FaultPoint::PreHooks).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(injection.inject(FaultPoint::PreNotify).is_ok());
+    }
+}
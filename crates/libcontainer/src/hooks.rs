@@ -1,14 +1,19 @@
 use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
 use std::io::{ErrorKind, Write};
 use std::os::unix::prelude::CommandExt;
-use std::path::Path;
-use std::{process, thread, time};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{process, thread};
 
+use chrono::{DateTime, Utc};
 use nix::sys::signal;
 use nix::unistd::Pid;
 use oci_spec::runtime::Hook;
+use serde::{Deserialize, Serialize};
 
-use crate::container::Container;
+use crate::container::{Container, State};
+use crate::output_capture::{CapturedOutput, OutputCapture, DEFAULT_CAPTURE_LIMIT};
 use crate::utils;
 
 #[derive(Debug, thiserror::Error)]
@@ -27,19 +32,143 @@ pub enum HookError {
     MissingContainerState,
     #[error("failed to write container state to stdin")]
     WriteContainerState(#[source] std::io::Error),
+    #[error("failed to read hook results from {path:?}")]
+    ReadResults {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("failed to decode hook result")]
+    DecodeResult(#[source] serde_json::Error),
 }
 
 type Result<T> = std::result::Result<T, HookError>;
 
+/// Directory in the container root the hook results are recorded in
+const HOOKS_DIR: &str = "hooks";
+/// File in the hooks directory with a line per hook that ran
+const HOOKS_INDEX_FILE: &str = "index.jsonl";
+
+/// The lifecycle stage a hook runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HookStage {
+    Prestart,
+    CreateRuntime,
+    CreateContainer,
+    StartContainer,
+    Poststart,
+    Poststop,
+}
+
+impl HookStage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Prestart => "prestart",
+            Self::CreateRuntime => "createRuntime",
+            Self::CreateContainer => "createContainer",
+            Self::StartContainer => "startContainer",
+            Self::Poststart => "poststart",
+            Self::Poststop => "poststop",
+        }
+    }
+}
+
+/// The result of a hook that ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookResult {
+    pub stage: HookStage,
+    pub path: PathBuf,
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    /// None if the hook was killed by a signal or timed out
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: CapturedOutput,
+    pub stderr: CapturedOutput,
+}
+
+/// The line of a hook result in the index, the output is kept in files next
+/// to it
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HookIndexEntry {
+    stage: HookStage,
+    path: PathBuf,
+    started_at: DateTime<Utc>,
+    duration_ms: u64,
+    exit_code: Option<i32>,
+    timed_out: bool,
+    // Name of the output files, without the .stdout and .stderr extensions
+    output: String,
+    stdout_truncated: bool,
+    stderr_truncated: bool,
+}
+
+/// Env var that tells a create hook how many milliseconds are left until the
+/// create times out, so it can limit itself
+pub const CREATE_DEADLINE_ENV: &str = "YOUKI_CREATE_DEADLINE_MS";
+
+/// Annotation of the state passed to hooks with the absolute path of the
+/// cgroup of the container
+pub const CGROUP_PATH_ANNOTATION: &str = "org.youki.cgroupPath";
+/// Annotation of the state passed to hooks with the name of the systemd unit
+/// of the cgroup of the container
+pub const SYSTEMD_UNIT_ANNOTATION: &str = "org.youki.systemdUnit";
+
+/// Returns the state passed to the hooks. The cgroup location is added as
+/// annotations, the state of the runtime spec has no field for it.
+fn hook_payload(state: &State) -> State {
+    let mut payload = state.clone();
+    if let Some(cgroup_path) = &state.cgroup_path {
+        payload.annotations.get_or_insert_with(HashMap::new).insert(
+            CGROUP_PATH_ANNOTATION.to_owned(),
+            cgroup_path.display().to_string(),
+        );
+    }
+    if let Some(systemd_unit) = &state.systemd_unit {
+        payload
+            .annotations
+            .get_or_insert_with(HashMap::new)
+            .insert(SYSTEMD_UNIT_ANNOTATION.to_owned(), systemd_unit.clone());
+    }
+    payload
+}
+
+/// Runs the hooks of a stage. The stdout and stderr of each hook are captured
+/// and, if the state of the container is saved, recorded in the container
+/// root, see [`Container::hook_results`].
 pub fn run_hooks(
+    stage: HookStage,
     hooks: Option<&Vec<Hook>>,
     container: Option<&Container>,
     cwd: Option<&Path>,
 ) -> Result<()> {
-    let state = &(container.ok_or(HookError::MissingContainerState)?.state);
+    run_hooks_with_deadline(stage, hooks, container, cwd, None)
+}
+
+/// Runs the hooks of a create that times out at `deadline`. The time left is
+/// passed to each hook in [`CREATE_DEADLINE_ENV`], and a hook that is still
+/// running at the deadline is killed, even if its own timeout is longer.
+pub fn run_hooks_with_deadline(
+    stage: HookStage,
+    hooks: Option<&Vec<Hook>>,
+    container: Option<&Container>,
+    cwd: Option<&Path>,
+    deadline: Option<Instant>,
+) -> Result<()> {
+    let container = container.ok_or(HookError::MissingContainerState)?;
+    let state = hook_payload(&container.state);
+    let output_limit = state.hook_output_limit.unwrap_or(DEFAULT_CAPTURE_LIMIT);
 
     if let Some(hooks) = hooks {
         for hook in hooks {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining == Some(Duration::ZERO) {
+                tracing::error!(?hook, "create deadline passed before the hook ran");
+                return Err(HookError::Timeout);
+            }
+
             let mut hook_command = process::Command::new(hook.path());
 
             if let Some(cwd) = cwd {
@@ -59,20 +188,31 @@ pub fn run_hooks(
                 hook_command.arg0(hook.path().display().to_string())
             };
 
-            let envs: HashMap<String, String> = if let Some(env) = hook.env() {
+            let mut envs: HashMap<String, String> = if let Some(env) = hook.env() {
                 utils::parse_env(env)
             } else {
                 HashMap::new()
             };
+            if let Some(remaining) = remaining {
+                envs.insert(
+                    CREATE_DEADLINE_ENV.to_owned(),
+                    remaining.as_millis().to_string(),
+                );
+            }
             tracing::debug!("run_hooks envs: {:?}", envs);
 
+            let started_at = Utc::now();
+            let start = Instant::now();
             let mut hook_process = hook_command
                 .env_clear()
                 .envs(envs)
                 .stdin(process::Stdio::piped())
+                .stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped())
                 .spawn()
                 .map_err(HookError::CommandExecute)?;
             let hook_process_pid = Pid::from_raw(hook_process.id() as i32);
+            let capture = OutputCapture::start(&mut hook_process, output_limit);
             // Based on the OCI spec, we need to pipe the container state into
             // the hook command through stdin.
             if let Some(stdin) = &mut hook_process.stdin {
@@ -85,7 +225,7 @@ pub fn run_hooks(
                 // error, in the case that the hook command is waiting for us to
                 // write to stdin.
                 let encoded_state =
-                    serde_json::to_string(state).map_err(HookError::EncodeContainerState)?;
+                    serde_json::to_string(&state).map_err(HookError::EncodeContainerState)?;
                 if let Err(e) = stdin.write_all(encoded_state.as_bytes()) {
                     if e.kind() != ErrorKind::BrokenPipe {
                         // Not a broken pipe. The hook command may be waiting
@@ -96,7 +236,15 @@ pub fn run_hooks(
                 }
             }
 
-            let res = if let Some(timeout_sec) = hook.timeout() {
+            let timeout = match (
+                hook.timeout()
+                    .map(|timeout_sec| Duration::from_secs(timeout_sec as u64)),
+                remaining,
+            ) {
+                (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+                (timeout, remaining) => timeout.or(remaining),
+            };
+            let res = if let Some(timeout) = timeout {
                 // Rust does not make it easy to handle executing a command and
                 // timeout. Here we decided to wait for the command in a
                 // different thread, so the main thread is not blocked. We use a
@@ -112,29 +260,50 @@ pub fn run_hooks(
                     let res = hook_process.wait();
                     let _ = s.send(res);
                 });
-                match r.recv_timeout(time::Duration::from_secs(timeout_sec as u64)) {
-                    Ok(res) => res,
+                match r.recv_timeout(timeout) {
+                    Ok(res) => res.map(Some),
                     Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
                         // Kill the process. There is no need to further clean
-                        // up because we will be error out.
+                        // up because we will be error out once the result is
+                        // recorded.
                         let _ = signal::kill(hook_process_pid, signal::Signal::SIGKILL);
-                        return Err(HookError::Timeout);
+                        Ok(None)
                     }
                     Err(_) => {
                         unreachable!();
                     }
                 }
             } else {
-                hook_process.wait()
+                hook_process.wait().map(Some)
             };
+            let (stdout, stderr) = capture.finish();
+            let exit_status = res.map_err(HookError::CommandExecute)?;
 
-            match res {
-                Ok(exit_status) => match exit_status.code() {
-                    Some(0) => Ok(()),
-                    Some(exit_code) => Err(HookError::NonZeroExitCode(exit_code)),
-                    None => Err(HookError::Killed),
-                },
-                Err(e) => Err(HookError::CommandExecute(e)),
+            let result = HookResult {
+                stage,
+                path: hook.path().clone(),
+                started_at,
+                duration: start.elapsed(),
+                exit_code: exit_status.and_then(|exit_status| exit_status.code()),
+                timed_out: exit_status.is_none(),
+                stdout,
+                stderr,
+            };
+            record_hook_result(container, &result);
+
+            match (exit_status, result.exit_code) {
+                (None, _) => Err(HookError::Timeout),
+                (Some(_), Some(0)) => Ok(()),
+                (Some(_), Some(exit_code)) => {
+                    tracing::error!(
+                        ?hook,
+                        exit_code,
+                        stderr = result.stderr.lossy(),
+                        "hook failed"
+                    );
+                    Err(HookError::NonZeroExitCode(exit_code))
+                }
+                (Some(_), None) => Err(HookError::Killed),
             }?;
         }
     }
@@ -142,6 +311,94 @@ pub fn run_hooks(
     Ok(())
 }
 
+/// Records the result of a hook in the container root. Only containers whose
+/// state is saved in the root get their hooks recorded. The record is best
+/// effort, a hook doesn't fail because its result couldn't be written.
+fn record_hook_result(container: &Container, result: &HookResult) {
+    if !container.root.join(State::STATE_FILE_PATH).exists() {
+        return;
+    }
+    if let Err(err) = write_hook_result(&container.root.join(HOOKS_DIR), result) {
+        tracing::warn!(?err, path = ?result.path, "failed to record hook result");
+    }
+}
+
+fn write_hook_result(dir: &Path, result: &HookResult) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let output = format!(
+        "{}-{}",
+        result.started_at.format("%Y%m%dT%H%M%S%.6f"),
+        result.stage.as_str()
+    );
+    fs::write(dir.join(format!("{output}.stdout")), &result.stdout.data)?;
+    fs::write(dir.join(format!("{output}.stderr")), &result.stderr.data)?;
+
+    let entry = HookIndexEntry {
+        stage: result.stage,
+        path: result.path.clone(),
+        started_at: result.started_at,
+        duration_ms: result.duration.as_millis() as u64,
+        exit_code: result.exit_code,
+        timed_out: result.timed_out,
+        output,
+        stdout_truncated: result.stdout.truncated,
+        stderr_truncated: result.stderr.truncated,
+    };
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(HOOKS_INDEX_FILE))?
+        .write_all(line.as_bytes())
+}
+
+/// Loads the results of the hooks recorded in the container root, in the
+/// order the hooks ran
+pub fn load_hook_results(container_root: &Path) -> Result<Vec<HookResult>> {
+    let dir = container_root.join(HOOKS_DIR);
+    let index_path = dir.join(HOOKS_INDEX_FILE);
+    let index = match fs::read_to_string(&index_path) {
+        Ok(index) => index,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(HookError::ReadResults {
+                source: err,
+                path: index_path,
+            })
+        }
+    };
+
+    index
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let entry: HookIndexEntry =
+                serde_json::from_str(line).map_err(HookError::DecodeResult)?;
+            let read_output = |extension: &str, truncated: bool| {
+                let path = dir.join(format!("{}.{extension}", entry.output));
+                match fs::read(&path) {
+                    Ok(data) => Ok(CapturedOutput { data, truncated }),
+                    // The output may be gone while the index entry was kept.
+                    Err(err) if err.kind() == ErrorKind::NotFound => Ok(CapturedOutput::default()),
+                    Err(err) => Err(HookError::ReadResults { source: err, path }),
+                }
+            };
+
+            Ok(HookResult {
+                stdout: read_output("stdout", entry.stdout_truncated)?,
+                stderr: read_output("stderr", entry.stderr_truncated)?,
+                stage: entry.stage,
+                path: entry.path,
+                started_at: entry.started_at,
+                duration: Duration::from_millis(entry.duration_ms),
+                exit_code: entry.exit_code,
+                timed_out: entry.timed_out,
+            })
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use std::{env, fs};
@@ -176,7 +433,8 @@ mod test {
     fn test_run_hook() -> Result<()> {
         {
             let default_container: Container = Default::default();
-            run_hooks(None, Some(&default_container), None).context("Failed simple test")?;
+            run_hooks(HookStage::Prestart, None, Some(&default_container), None)
+                .context("Failed simple test")?;
         }
 
         {
@@ -185,7 +443,13 @@ mod test {
 
             let hook = HookBuilder::default().path("true").build()?;
             let hooks = Some(vec![hook]);
-            run_hooks(hooks.as_ref(), Some(&default_container), None).context("Failed true")?;
+            run_hooks(
+                HookStage::Prestart,
+                hooks.as_ref(),
+                Some(&default_container),
+                None,
+            )
+            .context("Failed true")?;
         }
 
         {
@@ -205,8 +469,13 @@ mod test {
                 .env(vec![String::from("key=value")])
                 .build()?;
             let hooks = Some(vec![hook]);
-            run_hooks(hooks.as_ref(), Some(&default_container), None)
-                .context("Failed printenv test")?;
+            run_hooks(
+                HookStage::Prestart,
+                hooks.as_ref(),
+                Some(&default_container),
+                None,
+            )
+            .context("Failed printenv test")?;
         }
 
         {
@@ -224,8 +493,13 @@ mod test {
                 ])
                 .build()?;
             let hooks = Some(vec![hook]);
-            run_hooks(hooks.as_ref(), Some(&default_container), Some(tmp.path()))
-                .context("Failed pwd test")?;
+            run_hooks(
+                HookStage::Prestart,
+                hooks.as_ref(),
+                Some(&default_container),
+                Some(tmp.path()),
+            )
+            .context("Failed pwd test")?;
         }
 
         Ok(())
@@ -248,7 +522,12 @@ mod test {
             .timeout(1)
             .build()?;
         let hooks = Some(vec![hook]);
-        match run_hooks(hooks.as_ref(), Some(&default_container), None) {
+        match run_hooks(
+            HookStage::Prestart,
+            hooks.as_ref(),
+            Some(&default_container),
+            None,
+        ) {
             Ok(_) => {
                 bail!("The test expects the hook to error out with timeout. Should not execute cleanly");
             }
@@ -263,4 +542,147 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    #[serial]
+    fn test_run_hook_deadline() -> Result<()> {
+        let default_container: Container = Default::default();
+        let hook = HookBuilder::default()
+            .path("tail")
+            .args(vec![
+                String::from("tail"),
+                String::from("-f"),
+                String::from("/dev/null"),
+            ])
+            .timeout(60)
+            .build()?;
+        let hooks = Some(vec![hook]);
+
+        // the deadline cuts the longer timeout of the hook short
+        let deadline = Instant::now() + Duration::from_millis(200);
+        assert!(matches!(
+            run_hooks_with_deadline(
+                HookStage::CreateRuntime,
+                hooks.as_ref(),
+                Some(&default_container),
+                None,
+                Some(deadline)
+            ),
+            Err(HookError::Timeout)
+        ));
+        assert!(Instant::now() < deadline + Duration::from_secs(30));
+
+        // a passed deadline doesn't run the hook at all
+        assert!(matches!(
+            run_hooks_with_deadline(
+                HookStage::CreateRuntime,
+                hooks.as_ref(),
+                Some(&default_container),
+                None,
+                Some(Instant::now())
+            ),
+            Err(HookError::Timeout)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_hook_records_result() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut container = Container::new(
+            "test-hook-results",
+            crate::container::ContainerStatus::Created,
+            None,
+            tmp.path(),
+            tmp.path(),
+        )?;
+        container.set_hook_output_limit(Some(4));
+        container.save()?;
+
+        let hook = |script: &str| -> Result<Hook> {
+            Ok(HookBuilder::default()
+                .path("sh")
+                .args(vec![
+                    String::from("sh"),
+                    String::from("-c"),
+                    String::from(script),
+                ])
+                .build()?)
+        };
+        let hooks = Some(vec![hook("echo out; echo err >&2")?]);
+        run_hooks(HookStage::Prestart, hooks.as_ref(), Some(&container), None)?;
+        let hooks = Some(vec![hook("echo truncated; exit 3")?]);
+        assert!(matches!(
+            run_hooks(HookStage::Poststop, hooks.as_ref(), Some(&container), None),
+            Err(HookError::NonZeroExitCode(3))
+        ));
+
+        let results = container.hook_results()?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].stage, HookStage::Prestart);
+        assert_eq!(results[0].path, PathBuf::from("sh"));
+        assert_eq!(results[0].exit_code, Some(0));
+        assert!(!results[0].timed_out);
+        assert_eq!(results[0].stdout.lossy(), "out\n");
+        assert!(!results[0].stdout.truncated);
+        assert_eq!(results[0].stderr.lossy(), "err\n");
+        assert_eq!(results[1].stage, HookStage::Poststop);
+        assert_eq!(results[1].exit_code, Some(3));
+        assert_eq!(results[1].stdout.lossy(), "trun");
+        assert!(results[1].stdout.truncated);
+        assert!(results[1].started_at >= results[0].started_at);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hook_payload() {
+        let mut state = State::new(
+            "web",
+            crate::container::ContainerStatus::Created,
+            Some(42),
+            PathBuf::new(),
+        );
+        assert_eq!(hook_payload(&state).annotations, Some(HashMap::new()));
+        // a state without annotations keeps having none
+        state.annotations = None;
+        assert_eq!(hook_payload(&state).annotations, None);
+
+        state.cgroup_path = Some(PathBuf::from("/sys/fs/cgroup/system.slice/youki-web.scope"));
+        state.systemd_unit = Some("youki-web.scope".to_owned());
+        let annotations = hook_payload(&state).annotations.unwrap();
+        assert_eq!(
+            annotations.get(CGROUP_PATH_ANNOTATION).map(String::as_str),
+            Some("/sys/fs/cgroup/system.slice/youki-web.scope")
+        );
+        assert_eq!(
+            annotations.get(SYSTEMD_UNIT_ANNOTATION).map(String::as_str),
+            Some("youki-web.scope")
+        );
+        // the state of the container itself is left as is
+        assert_eq!(state.annotations, None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_hook_without_saved_state() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut container = Container::default();
+        container.root = tmp.path().to_owned();
+        let hook = HookBuilder::default().path("true").build()?;
+        run_hooks(
+            HookStage::Prestart,
+            Some(&vec![hook]),
+            Some(&container),
+            None,
+        )?;
+
+        // nothing is recorded for a container whose state isn't saved
+        assert!(!tmp.path().join(HOOKS_DIR).exists());
+        assert!(load_hook_results(tmp.path())?.is_empty());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,191 @@
+//! `/etc/hostname` of the container
+//!
+//! Software reading `/etc/hostname` instead of asking the kernel sees the
+//! hostname the image was built with, not the one of the container. With the
+//! fixup, the init process writes the hostname of the uts namespace it is in,
+//! i.e. the effective hostname of the container, into `/etc/hostname` of the
+//! rootfs before it may be remounted read-only. It is enabled with
+//! [`InitContainerBuilder::with_hostname_file`](crate::container::init_builder::InitContainerBuilder::with_hostname_file)
+//! or the [`HOSTNAME_FILE_ANNOTATION`] annotation set to `true`, and skipped if
+//! the spec mounts `/etc/hostname`, as managers that inject a hosts and
+//! hostname file of their own do. The decision is recorded in the
+//! [`HOSTNAME_FILE_RESOLVED_ANNOTATION`] annotation of the resolved spec.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::Spec;
+
+use crate::rootfs::write_accounting::WriteAccounting;
+
+/// Annotation that enables the fixup for a container
+pub const HOSTNAME_FILE_ANNOTATION: &str = "io.youki.hostname-file";
+/// Annotation the decision of the builder is recorded in, one of
+/// [`RESOLVED_WRITE`] or [`RESOLVED_MOUNTED`]
+pub const HOSTNAME_FILE_RESOLVED_ANNOTATION: &str = "io.youki.hostname-file.resolved";
+/// The init process writes `/etc/hostname`
+pub const RESOLVED_WRITE: &str = "write";
+/// The spec mounts `/etc/hostname`, it is left alone
+pub const RESOLVED_MOUNTED: &str = "mounted";
+
+const HOSTNAME_FILE: &str = "etc/hostname";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HostnameFileError {
+    #[error("failed to resolve /etc/hostname in the rootfs")]
+    Resolve(#[source] std::io::Error),
+    #[error("failed to get the hostname")]
+    Hostname(#[source] nix::Error),
+    #[error("failed to write {path:?}")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, HostnameFileError>;
+
+/// Returns if the fixup is enabled by the builder or the annotation
+pub fn enabled(spec: &Spec, builder_flag: bool) -> bool {
+    builder_flag
+        || spec
+            .annotations()
+            .as_ref()
+            .and_then(|annotations| annotations.get(HOSTNAME_FILE_ANNOTATION))
+            .map_or(false, |value| value == "true")
+}
+
+/// Records in the [`HOSTNAME_FILE_RESOLVED_ANNOTATION`] annotation if the
+/// init process writes `/etc/hostname`, which it does unless the spec mounts
+/// it
+pub fn resolve(spec: &mut Spec) {
+    let mounted = spec
+        .mounts()
+        .iter()
+        .flatten()
+        .any(|mount| mount.destination() == Path::new("/etc/hostname"));
+    let resolved = if mounted {
+        tracing::debug!("/etc/hostname is mounted, not writing it");
+        RESOLVED_MOUNTED
+    } else {
+        RESOLVED_WRITE
+    };
+    let mut annotations = spec.annotations().clone().unwrap_or_else(HashMap::new);
+    annotations.insert(
+        HOSTNAME_FILE_RESOLVED_ANNOTATION.to_owned(),
+        resolved.to_owned(),
+    );
+    spec.set_annotations(Some(annotations));
+}
+
+/// Returns if the builder resolved that the init process writes
+/// `/etc/hostname`
+pub fn should_write(spec: &Spec) -> bool {
+    spec.annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(HOSTNAME_FILE_RESOLVED_ANNOTATION))
+        .map_or(false, |value| value == RESOLVED_WRITE)
+}
+
+/// Writes the hostname of the current uts namespace into `/etc/hostname` of
+/// the rootfs. The path is resolved in the rootfs, so a symlink of a hostile
+/// image can't redirect the write outside of it.
+pub fn write(rootfs: &Path, writes: &WriteAccounting) -> Result<()> {
+    let hostname = nix::unistd::gethostname().map_err(HostnameFileError::Hostname)?;
+    write_hostname(rootfs, &hostname.to_string_lossy(), writes)
+}
+
+fn write_hostname(rootfs: &Path, hostname: &str, writes: &WriteAccounting) -> Result<()> {
+    let path = safe_path::scoped_join(rootfs, HOSTNAME_FILE).map_err(|err| {
+        tracing::error!(?err, "failed to resolve /etc/hostname in the rootfs");
+        HostnameFileError::Resolve(err)
+    })?;
+    let write_err = |source| HostnameFileError::Write {
+        path: path.clone(),
+        source,
+    };
+    if let Some(etc) = path.parent() {
+        fs::create_dir_all(etc).map_err(write_err)?;
+    }
+    fs::write(&path, format!("{hostname}\n")).map_err(|err| {
+        tracing::error!(?err, ?path, "failed to write /etc/hostname");
+        write_err(err)
+    })?;
+    writes.record(&path);
+    tracing::debug!(?hostname, "wrote /etc/hostname");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use anyhow::Result;
+    use oci_spec::runtime::MountBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_enabled() {
+        let mut spec = Spec::default();
+        assert!(!enabled(&spec, false));
+        assert!(enabled(&spec, true));
+        spec.set_annotations(Some(HashMap::from([(
+            HOSTNAME_FILE_ANNOTATION.to_owned(),
+            "true".to_owned(),
+        )])));
+        assert!(enabled(&spec, false));
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let mut spec = Spec::default();
+        assert!(!should_write(&spec));
+        resolve(&mut spec);
+        assert!(should_write(&spec));
+
+        let mut mounts = spec.mounts().clone().unwrap_or_default();
+        mounts.push(
+            MountBuilder::default()
+                .destination("/etc/hostname")
+                .typ("bind")
+                .source("/var/lib/manager/hostname")
+                .options(vec!["rbind".to_owned(), "ro".to_owned()])
+                .build()?,
+        );
+        spec.set_mounts(Some(mounts));
+        resolve(&mut spec);
+        assert!(!should_write(&spec));
+        assert_eq!(
+            spec.annotations()
+                .as_ref()
+                .and_then(|annotations| annotations.get(HOSTNAME_FILE_RESOLVED_ANNOTATION))
+                .map(String::as_str),
+            Some(RESOLVED_MOUNTED)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_hostname() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        let writes = WriteAccounting::new();
+        write_hostname(rootfs.path(), "youki", &writes)?;
+        assert_eq!(
+            fs::read_to_string(rootfs.path().join("etc/hostname"))?,
+            "youki\n"
+        );
+        assert_eq!(writes.written(), "hostname".len() as u64 + 6);
+
+        // an /etc symlink of the image is resolved inside the rootfs
+        let rootfs = tempfile::tempdir()?;
+        let outside = tempfile::tempdir()?;
+        symlink(outside.path(), rootfs.path().join("etc"))?;
+        write_hostname(rootfs.path(), "youki", &WriteAccounting::new())?;
+        assert!(!outside.path().join("hostname").exists());
+
+        Ok(())
+    }
+}
@@ -1,20 +1,40 @@
+pub mod annotation_env;
 pub mod apparmor;
 pub mod capabilities;
 pub mod channel;
 pub mod config;
 pub mod container;
+pub mod core_sched;
+pub mod cpuset;
+pub mod create_limit;
+pub mod create_signals;
+pub mod debug;
+pub mod env_file;
 pub mod error;
+pub mod fault_injection;
 pub mod hooks;
+pub mod hostname_file;
 pub mod namespaces;
 pub mod notify_socket;
+pub mod numa;
+pub mod output_capture;
 pub mod process;
+pub mod readiness_probe;
 pub mod rootfs;
+pub mod runtime_root;
 #[cfg(feature = "libseccomp")]
 pub mod seccomp;
+pub mod shared_volume;
 pub mod signal;
+pub mod socket_handoff;
+pub mod spec_diff;
+pub mod spec_limits;
+pub mod stdio_file;
 pub mod syscall;
+pub mod sysctl;
 pub mod test_utils;
 pub mod tty;
+pub mod user;
 pub mod user_ns;
 pub mod utils;
 pub mod workload;
@@ -5,14 +5,17 @@
 //! Interprocess Communication (Control or communication between processes),
 //! Network (which network devices can be seen by the processes in the namespace), User (User configs),
 //! UTS (hostname and domain information, processes will think they're running on servers with different names),
-//! Cgroup (Resource limits, execution priority etc.)
+//! Cgroup (Resource limits, execution priority etc.),
+//! Time (offsets of the monotonic and boot-time clocks)
 
-use std::collections;
+use std::collections::{self, BTreeMap, HashMap};
+use std::fs;
 
+use nix::errno::Errno;
 use nix::sched::CloneFlags;
 use nix::sys::stat;
 use nix::{fcntl, unistd};
-use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType};
+use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType, LinuxTimeOffset};
 
 use crate::syscall::syscall::create_syscall;
 use crate::syscall::Syscall;
@@ -29,8 +32,50 @@ pub enum NamespaceError {
     Syscall(#[from] crate::syscall::SyscallError),
     #[error("Namespace type not supported: {0}")]
     NotSupported(String),
+    #[error("failed to create {namespace:?} namespace")]
+    CreateFailed {
+        namespace: LinuxNamespaceType,
+        #[source]
+        source: crate::syscall::SyscallError,
+    },
+    #[error("failed to access the offsets of the time namespace")]
+    TimeOffsets(#[source] std::io::Error),
+    #[error("joined time namespace has the offsets {actual:?}, not {expected:?}")]
+    TimeOffsetsMismatch {
+        expected: TimeOffsets,
+        actual: TimeOffsets,
+    },
 }
 
+impl NamespaceError {
+    /// Returns the type of the namespace and the errno if a namespace failed
+    /// to be created. The errno is unknown if the failure wasn't a syscall
+    /// error.
+    pub fn create_failure(&self) -> Option<(LinuxNamespaceType, Errno)> {
+        match self {
+            NamespaceError::CreateFailed { namespace, source } => {
+                let errno = match source {
+                    crate::syscall::SyscallError::Nix(errno) => *errno,
+                    _ => Errno::UnknownErrno,
+                };
+                Some((*namespace, errno))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// nix does not expose the time namespace clone flag yet, so it is defined
+/// here from the raw libc value.
+pub const CLONE_NEWTIME: CloneFlags = CloneFlags::from_bits_retain(libc::CLONE_NEWTIME);
+
+const TIMENS_OFFSETS: &str = "/proc/self/timens_offsets";
+const TIME_OFFSET_CLOCKS: &[&str] = &["monotonic", "boottime"];
+
+/// Seconds and nanoseconds the clocks of a time namespace are offset by, by
+/// clock name
+pub type TimeOffsets = BTreeMap<String, (i64, u32)>;
+
 static ORDERED_NAMESPACES: &[CloneFlags] = &[
     CloneFlags::CLONE_NEWUSER,
     CloneFlags::CLONE_NEWPID,
@@ -38,6 +83,11 @@ static ORDERED_NAMESPACES: &[CloneFlags] = &[
     CloneFlags::CLONE_NEWIPC,
     CloneFlags::CLONE_NEWNET,
     CloneFlags::CLONE_NEWCGROUP,
+    // A new time namespace only applies to the children (and exec) of the
+    // calling process, while joining one by path applies immediately. Either
+    // way, the init process ends up in the time namespace before the workload
+    // is executed.
+    CLONE_NEWTIME,
     CloneFlags::CLONE_NEWNS,
 ];
 
@@ -56,7 +106,7 @@ fn get_clone_flag(namespace_type: LinuxNamespaceType) -> Result<CloneFlags> {
         LinuxNamespaceType::Network => CloneFlags::CLONE_NEWNET,
         LinuxNamespaceType::Cgroup => CloneFlags::CLONE_NEWCGROUP,
         LinuxNamespaceType::Mount => CloneFlags::CLONE_NEWNS,
-        LinuxNamespaceType::Time => return Err(NamespaceError::NotSupported("time".to_string())),
+        LinuxNamespaceType::Time => CLONE_NEWTIME,
     };
 
     Ok(flag)
@@ -125,7 +175,10 @@ impl Namespaces {
                     .unshare(get_clone_flag(namespace.typ())?)
                     .map_err(|err| {
                         tracing::error!(?err, ?namespace, "failed to unshare namespace");
-                        err
+                        NamespaceError::CreateFailed {
+                            namespace: namespace.typ(),
+                            source: err,
+                        }
                     })?;
             }
         }
@@ -133,14 +186,98 @@ impl Namespaces {
         Ok(())
     }
 
+    /// Sets the clock offsets of a created time namespace, or checks that a
+    /// joined one has them. The offsets can only be set before a process
+    /// entered the namespace, so the ones a joined namespace was created
+    /// with must match.
+    pub fn apply_time_offsets(
+        &self,
+        offsets: Option<&HashMap<String, LinuxTimeOffset>>,
+    ) -> Result<()> {
+        let (namespace, offsets) = match (self.namespace_map.get(&CLONE_NEWTIME), offsets) {
+            (Some(namespace), Some(offsets)) if !offsets.is_empty() => (namespace, offsets),
+            _ => return Ok(()),
+        };
+        let expected = time_offsets_from_spec(offsets)?;
+
+        match namespace.path() {
+            None => {
+                let content: String = expected
+                    .iter()
+                    .map(|(clock, (secs, nanosecs))| format!("{clock} {secs} {nanosecs}\n"))
+                    .collect();
+                fs::write(TIMENS_OFFSETS, content).map_err(|err| {
+                    tracing::error!(?err, ?expected, "failed to set time namespace offsets");
+                    NamespaceError::TimeOffsets(err)
+                })?;
+            }
+            Some(_) => {
+                let content =
+                    fs::read_to_string(TIMENS_OFFSETS).map_err(NamespaceError::TimeOffsets)?;
+                let actual = parse_time_offsets(&content);
+                if expected
+                    .iter()
+                    .any(|(clock, offset)| actual.get(clock).unwrap_or(&(0, 0)) != offset)
+                {
+                    tracing::error!(?expected, ?actual, "time namespace offsets differ");
+                    return Err(NamespaceError::TimeOffsetsMismatch { expected, actual });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn get(&self, k: LinuxNamespaceType) -> Result<Option<&LinuxNamespace>> {
         Ok(self.namespace_map.get(&get_clone_flag(k)?))
     }
+
+    /// Clone flags of the namespaces that are created, i.e. unshared instead
+    /// of joined by path
+    pub fn new_namespace_flags(&self) -> CloneFlags {
+        self.namespace_map
+            .iter()
+            .filter(|(_, ns)| ns.path().is_none())
+            .fold(CloneFlags::empty(), |flags, (flag, _)| flags | *flag)
+    }
+}
+
+fn time_offsets_from_spec(offsets: &HashMap<String, LinuxTimeOffset>) -> Result<TimeOffsets> {
+    offsets
+        .iter()
+        .map(|(clock, offset)| {
+            if !TIME_OFFSET_CLOCKS.contains(&clock.as_str()) {
+                return Err(NamespaceError::NotSupported(format!(
+                    "time offset of the {clock} clock"
+                )));
+            }
+            let offset = (
+                offset.secs().unwrap_or_default(),
+                offset.nanosecs().unwrap_or_default(),
+            );
+            Ok((clock.to_owned(), offset))
+        })
+        .collect()
+}
+
+// The offsets are listed as `<clock> <secs> <nanosecs>` lines, a line that
+// can't be parsed is skipped.
+fn parse_time_offsets(content: &str) -> TimeOffsets {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let clock = fields.next()?;
+            let secs = fields.next()?.parse().ok()?;
+            let nanosecs = fields.next()?.parse().ok()?;
+            Some((clock.to_owned(), (secs, nanosecs)))
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use oci_spec::runtime::{LinuxNamespaceBuilder, LinuxNamespaceType};
+    use oci_spec::runtime::{LinuxNamespaceBuilder, LinuxNamespaceType, LinuxTimeOffsetBuilder};
     use serial_test::serial;
 
     use super::*;
@@ -173,6 +310,76 @@ mod tests {
         ]
     }
 
+    #[test]
+    fn test_time_offsets() {
+        let offsets = HashMap::from([
+            (
+                "monotonic".to_owned(),
+                LinuxTimeOffsetBuilder::default()
+                    .secs(1000)
+                    .build()
+                    .unwrap(),
+            ),
+            (
+                "boottime".to_owned(),
+                LinuxTimeOffsetBuilder::default()
+                    .secs(-5)
+                    .nanosecs(500u32)
+                    .build()
+                    .unwrap(),
+            ),
+        ]);
+        let expected = TimeOffsets::from([
+            ("boottime".to_owned(), (-5, 500)),
+            ("monotonic".to_owned(), (1000, 0)),
+        ]);
+        assert_eq!(time_offsets_from_spec(&offsets).unwrap(), expected);
+        assert_eq!(
+            parse_time_offsets("monotonic        1000         0\nboottime   -5   500\n"),
+            expected
+        );
+
+        let offsets = HashMap::from([(
+            "realtime".to_owned(),
+            LinuxTimeOffsetBuilder::default().secs(1).build().unwrap(),
+        )]);
+        assert!(matches!(
+            time_offsets_from_spec(&offsets),
+            Err(NamespaceError::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_apply_time_namespace() {
+        let sample_linux_namespaces = vec![
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Time)
+                .path("/dev/null")
+                .build()
+                .unwrap(),
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Mount)
+                .build()
+                .unwrap(),
+        ];
+        let namespaces = Namespaces::try_from(Some(&sample_linux_namespaces))
+            .expect("create namespace struct should be good");
+        let test_command: &TestHelperSyscall = namespaces.command.as_any().downcast_ref().unwrap();
+        assert!(namespaces.apply_namespaces(|_| true).is_ok());
+
+        let setns_args: Vec<_> = test_command
+            .get_setns_args()
+            .into_iter()
+            .map(|(_fd, cf)| cf)
+            .collect();
+        assert_eq!(setns_args, vec![CLONE_NEWTIME]);
+        assert_eq!(
+            test_command.get_unshare_args(),
+            vec![CloneFlags::CLONE_NEWNS]
+        );
+    }
+
     #[test]
     #[serial]
     fn test_apply_namespaces() {
@@ -200,4 +407,54 @@ mod tests {
         expect.sort();
         assert_eq!(unshare_args, expect)
     }
+
+    #[test]
+    #[serial]
+    fn test_create_failure() {
+        let sample_linux_namespaces = vec![
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Mount)
+                .path("/dev/null")
+                .build()
+                .unwrap(),
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Network)
+                .build()
+                .unwrap(),
+        ];
+        let namespaces = Namespaces::try_from(Some(&sample_linux_namespaces))
+            .expect("create namespace struct should be good");
+        let test_command: &TestHelperSyscall = namespaces.command.as_any().downcast_ref().unwrap();
+        test_command.set_ret_err(crate::syscall::test::ArgName::Unshare, || {
+            Err(crate::syscall::SyscallError::Nix(Errno::EPERM))
+        });
+
+        let err = namespaces
+            .apply_namespaces(|_| true)
+            .expect_err("the network namespace fails to be created");
+        assert_eq!(
+            err.create_failure(),
+            Some((LinuxNamespaceType::Network, Errno::EPERM))
+        );
+        // other failures are no create failures
+        assert!(NamespaceError::from(Errno::EPERM)
+            .create_failure()
+            .is_none());
+    }
+
+    #[test]
+    fn test_new_namespace_flags() {
+        let sample_linux_namespaces = gen_sample_linux_namespaces();
+        let namespaces = Namespaces::try_from(Some(&sample_linux_namespaces))
+            .expect("create namespace struct should be good");
+
+        // the mount and network namespaces are joined by path
+        assert_eq!(
+            namespaces.new_namespace_flags(),
+            CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWIPC
+        );
+
+        let namespaces = Namespaces::try_from(None).unwrap();
+        assert!(namespaces.new_namespace_flags().is_empty());
+    }
 }
@@ -1,13 +1,44 @@
-use std::env;
+//! Notify sockets of a container
+//!
+//! The notify listener is the start handshake: the init process of the
+//! container waits on it until `start` connects.
+//!
+//! The readiness socket lets the workload tell that it is ready, not only
+//! started. It is opt-in, see
+//! [`with_readiness_notify`](crate::container::init_builder::InitContainerBuilder::with_readiness_notify).
+//! The wire format is the one of `sd_notify`: the workload sends datagrams to
+//! the unix socket at `$NOTIFY_SOCKET`, each with newline separated
+//! `KEY=VALUE` assignments. `READY=1` marks the workload ready and `STATUS=`
+//! carries a free-form status, other keys are ignored. The socket lives in the
+//! [`READY_DIR`] of the container dir, which is bind mounted into the
+//! container at [`READY_SOCKET_DIR`]. The caller binds it with
+//! [`Container::readiness_listener`](crate::container::Container::readiness_listener)
+//! between the create and the start, so nothing the workload sends is lost.
 use std::io::prelude::*;
 use std::os::fd::FromRawFd;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::AsRawFd;
-use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{env, fs, io};
 
 use nix::unistd::{self, close};
+use oci_spec::runtime::{MountBuilder, Spec};
 
 pub const NOTIFY_FILE: &str = "notify.sock";
+/// Dir of the container dir the readiness socket is in
+pub const READY_DIR: &str = "ready";
+/// Name of the readiness socket in its dir
+pub const READY_FILE: &str = "notify.sock";
+/// Where the dir of the readiness socket is mounted in the container
+pub const READY_SOCKET_DIR: &str = "/run/youki-ready";
+/// How long to wait for the workload to be ready if the caller has no
+/// timeout of its own
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest readiness message that is read, longer ones are truncated
+const MAX_READY_MESSAGE: usize = 4096;
 
 #[derive(Debug, thiserror::Error)]
 pub enum NotifyListenerError {
@@ -35,6 +66,16 @@ pub enum NotifyListenerError {
     Read(#[source] std::io::Error),
     #[error("failed to send start container")]
     SendStartContainer(#[source] std::io::Error),
+    #[error("readiness notify is not enabled for the container")]
+    ReadinessDisabled,
+    #[error("failed to set up readiness socket {path:?}")]
+    ReadinessSocket { path: PathBuf, source: io::Error },
+    #[error("failed to receive readiness message")]
+    ReadinessReceive(#[source] io::Error),
+    #[error("workload was not ready within {0:?}")]
+    ReadinessTimeout(Duration),
+    #[error(transparent)]
+    Spec(#[from] oci_spec::OciSpecError),
 }
 
 type Result<T> = std::result::Result<T, NotifyListenerError>;
@@ -159,6 +200,173 @@ impl NotifySocket {
     }
 }
 
+/// Mounts the dir of the readiness socket into the container and points
+/// `NOTIFY_SOCKET` of the process at the socket. The dir is mounted read-only,
+/// the workload can send to the socket but can't replace it or put anything
+/// else into the container dir on the host.
+pub fn prepare_readiness(spec: &mut Spec, container_dir: &Path) -> Result<()> {
+    let dir = container_dir.join(READY_DIR);
+    let socket_err = |source| NotifyListenerError::ReadinessSocket {
+        path: dir.clone(),
+        source,
+    };
+    fs::create_dir_all(&dir).map_err(socket_err)?;
+    // The workload may run as any user of the container.
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).map_err(socket_err)?;
+
+    let mount = MountBuilder::default()
+        .destination(READY_SOCKET_DIR)
+        .typ("bind")
+        .source(&dir)
+        .options(
+            ["rbind", "ro", "nosuid", "nodev", "noexec"]
+                .map(str::to_owned)
+                .to_vec(),
+        )
+        .build()?;
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    mounts.push(mount);
+    spec.set_mounts(Some(mounts));
+
+    if let Some(process) = spec.process_mut() {
+        let notify_socket = format!("NOTIFY_SOCKET={READY_SOCKET_DIR}/{READY_FILE}");
+        let mut envs: Vec<String> = process
+            .env()
+            .iter()
+            .flatten()
+            .filter(|env| !env.starts_with("NOTIFY_SOCKET="))
+            .cloned()
+            .collect();
+        envs.push(notify_socket);
+        process.set_env(Some(envs));
+    }
+
+    Ok(())
+}
+
+/// What the workload reported when it became ready
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Readiness {
+    /// Last `STATUS=` the workload sent, if any
+    pub status: Option<String>,
+}
+
+/// Receives the readiness messages of the workload of a container
+pub struct ReadinessListener {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl ReadinessListener {
+    /// Binds the readiness socket in the container dir `container_dir`.
+    /// A socket left behind by an earlier listener is replaced.
+    pub fn bind(container_dir: &Path) -> Result<Self> {
+        let dir = container_dir.join(READY_DIR);
+        let path = dir.join(READY_FILE);
+        let socket_err = |source| NotifyListenerError::ReadinessSocket {
+            path: path.clone(),
+            source,
+        };
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(socket_err(err)),
+            _ => {}
+        }
+        // Bound from within its dir, for the same path length limit as the
+        // notify listener.
+        let socket = in_dir(&dir, || UnixDatagram::bind(READY_FILE).map_err(socket_err))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).map_err(socket_err)?;
+        tracing::debug!(?path, "bound readiness socket");
+
+        Ok(Self { socket, path })
+    }
+
+    /// Waits until the workload sends `READY=1`, for at most `timeout`
+    pub fn wait(&self, timeout: Duration) -> Result<Readiness> {
+        let deadline = Instant::now() + timeout;
+        let mut readiness = Readiness::default();
+        let mut buf = [0u8; MAX_READY_MESSAGE];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                tracing::warn!(?timeout, "workload was not ready in time");
+                return Err(NotifyListenerError::ReadinessTimeout(timeout));
+            }
+            self.socket
+                .set_read_timeout(Some(left))
+                .map_err(NotifyListenerError::ReadinessReceive)?;
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(NotifyListenerError::ReadinessReceive(err)),
+            };
+
+            let message = String::from_utf8_lossy(&buf[..len]);
+            tracing::debug!(%message, "received readiness message");
+            if parse_ready_message(&message, &mut readiness) {
+                return Ok(readiness);
+            }
+        }
+    }
+
+    /// Waits for the workload to be ready in a thread, like
+    /// [`ReadinessListener::wait`], and calls `callback` with the outcome
+    pub fn on_ready<F>(self, timeout: Duration, callback: F) -> io::Result<JoinHandle<()>>
+    where
+        F: FnOnce(Result<Readiness>) + Send + 'static,
+    {
+        thread::Builder::new()
+            .name("readiness".to_owned())
+            .spawn(move || callback(self.wait(timeout)))
+    }
+}
+
+impl Drop for ReadinessListener {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            tracing::debug!(path = ?self.path, ?err, "failed to remove readiness socket");
+        }
+    }
+}
+
+/// Applies the assignments of a message to `readiness`, returns if the
+/// message marks the workload ready
+fn parse_ready_message(message: &str, readiness: &mut Readiness) -> bool {
+    let mut ready = false;
+    for (key, value) in message.lines().filter_map(|line| line.split_once('=')) {
+        match key {
+            "READY" => ready |= value == "1",
+            "STATUS" => readiness.status = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    ready
+}
+
+/// Runs `f` from within `dir` and chdirs back, so the sockets `f` binds
+/// aren't bound by the length limit of unix socket paths
+fn in_dir<T>(dir: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let cwd = env::current_dir().map_err(NotifyListenerError::GetCwd)?;
+    unistd::chdir(dir).map_err(|e| NotifyListenerError::Chdir {
+        source: e,
+        path: dir.to_owned(),
+    })?;
+    let result = f();
+    unistd::chdir(&cwd).map_err(|e| NotifyListenerError::Chdir {
+        source: e,
+        path: cwd,
+    })?;
+    result
+}
+
 #[cfg(test)]
 mod test {
     use tempfile::tempdir;
@@ -188,4 +396,96 @@ mod test {
         socket.notify_container_start().unwrap();
         thread_handle.join().unwrap();
     }
+
+    #[test]
+    fn test_parse_ready_message() {
+        let mut readiness = Readiness::default();
+        assert!(!parse_ready_message(
+            "STATUS=warming up\nMAINPID=7",
+            &mut readiness
+        ));
+        assert_eq!(readiness.status.as_deref(), Some("warming up"));
+        assert!(!parse_ready_message("READY=0", &mut readiness));
+        assert!(parse_ready_message(
+            "READY=1\nSTATUS=serving",
+            &mut readiness
+        ));
+        assert_eq!(readiness.status.as_deref(), Some("serving"));
+    }
+
+    #[test]
+    fn test_prepare_readiness() -> anyhow::Result<()> {
+        use oci_spec::runtime::ProcessBuilder;
+
+        let tempdir = tempdir()?;
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default()
+                .env(vec!["NOTIFY_SOCKET=/old".to_owned(), "A=1".to_owned()])
+                .build()?,
+        ));
+        prepare_readiness(&mut spec, tempdir.path())?;
+
+        let mount = spec.mounts().as_ref().unwrap().last().unwrap();
+        assert_eq!(mount.destination(), Path::new(READY_SOCKET_DIR));
+        assert_eq!(
+            mount.source().as_deref(),
+            Some(tempdir.path().join(READY_DIR).as_path())
+        );
+        assert!(mount
+            .options()
+            .iter()
+            .flatten()
+            .any(|option| option == "ro"));
+        assert_eq!(
+            spec.process().as_ref().unwrap().env().as_ref().unwrap(),
+            &vec![
+                "A=1".to_owned(),
+                "NOTIFY_SOCKET=/run/youki-ready/notify.sock".to_owned()
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_readiness_callback_fires() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        fs::create_dir(tempdir.path().join(READY_DIR))?;
+        let listener = ReadinessListener::bind(tempdir.path())?;
+        let socket_path = tempdir.path().join(READY_DIR).join(READY_FILE);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = listener.on_ready(Duration::from_secs(10), move |readiness| {
+            sender.send(readiness).unwrap();
+        })?;
+
+        // the workload, ready after a delay
+        let workload = UnixDatagram::unbound()?;
+        workload.send_to(b"STATUS=warming up", &socket_path)?;
+        thread::sleep(Duration::from_millis(200));
+        assert!(receiver.try_recv().is_err());
+        workload.send_to(b"READY=1", &socket_path)?;
+
+        let readiness = receiver.recv_timeout(Duration::from_secs(10))??;
+        assert_eq!(readiness.status.as_deref(), Some("warming up"));
+        handle.join().unwrap();
+        // the socket is removed with the listener
+        assert!(!socket_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_readiness_timeout() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        fs::create_dir(tempdir.path().join(READY_DIR))?;
+        let listener = ReadinessListener::bind(tempdir.path())?;
+        let socket_path = tempdir.path().join(READY_DIR).join(READY_FILE);
+        UnixDatagram::unbound()?.send_to(b"STATUS=stuck", &socket_path)?;
+
+        assert!(matches!(
+            listener.wait(Duration::from_millis(100)),
+            Err(NotifyListenerError::ReadinessTimeout(_))
+        ));
+        Ok(())
+    }
 }
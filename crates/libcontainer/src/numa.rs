@@ -0,0 +1,313 @@
+//! NUMA aware placement of the memory of a container
+//!
+//! A cpuset that pins the cpus of a container but leaves its memory nodes
+//! unset lets the tasks allocate memory on any node, also on nodes far away
+//! from the cpus they run on. With auto mems, the memory nodes are set to the
+//! nodes of the pinned cpus. It is enabled with
+//! [`InitContainerBuilder::with_numa_auto_mems`](crate::container::init_builder::InitContainerBuilder::with_numa_auto_mems)
+//! or the [`NUMA_AUTO_MEMS_ANNOTATION`] annotation set to `true`.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::{LinuxCpuBuilder, LinuxResourcesBuilder, Spec};
+
+use crate::cpuset::CpusetError;
+pub use crate::cpuset::{format_list, parse_list};
+
+/// Annotation that enables auto mems for a container
+pub const NUMA_AUTO_MEMS_ANNOTATION: &str = "io.youki.numa-auto-mems";
+/// Annotation the mems chosen by auto mems are recorded in
+pub const NUMA_AUTO_MEMS_RESOLVED_ANNOTATION: &str = "io.youki.numa-auto-mems.resolved";
+/// Directory the cpus and their nodes are read from
+pub const SYSFS_CPU_PATH: &str = "/sys/devices/system/cpu";
+
+#[derive(Debug, thiserror::Error)]
+pub enum NumaError {
+    #[error(transparent)]
+    Cpuset(#[from] CpusetError),
+    #[error("cpu {0} is not in the NUMA topology")]
+    UnknownCpu(u32),
+    #[error("failed to read NUMA topology from {path:?}")]
+    ReadTopology {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Spec(#[from] oci_spec::OciSpecError),
+}
+
+type Result<T> = std::result::Result<T, NumaError>;
+
+/// The NUMA node of each cpu
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NumaTopology {
+    cpu_nodes: BTreeMap<u32, u32>,
+}
+
+impl NumaTopology {
+    pub fn new(cpu_nodes: BTreeMap<u32, u32>) -> Self {
+        Self { cpu_nodes }
+    }
+
+    /// Reads the topology from the `cpuN/nodeM` links of a sysfs cpu
+    /// directory, see [`SYSFS_CPU_PATH`]. A kernel without NUMA support has
+    /// no node links and yields an empty topology.
+    pub fn from_sysfs(cpu_path: &Path) -> Result<Self> {
+        let read_err = |err| NumaError::ReadTopology {
+            path: cpu_path.to_owned(),
+            source: err,
+        };
+        let mut cpu_nodes = BTreeMap::new();
+        for entry in fs::read_dir(cpu_path).map_err(read_err)? {
+            let entry = entry.map_err(read_err)?;
+            let cpu = match entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("cpu"))
+                .and_then(|cpu| cpu.parse::<u32>().ok())
+            {
+                Some(cpu) => cpu,
+                None => continue,
+            };
+            let node = fs::read_dir(entry.path())
+                .map_err(read_err)?
+                .filter_map(|entry| entry.ok())
+                .find_map(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| name.strip_prefix("node"))
+                        .and_then(|node| node.parse::<u32>().ok())
+                });
+            if let Some(node) = node {
+                cpu_nodes.insert(cpu, node);
+            }
+        }
+
+        Ok(Self { cpu_nodes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpu_nodes.is_empty()
+    }
+
+    /// Returns the nodes the cpus belong to
+    pub fn nodes_of(&self, cpus: &BTreeSet<u32>) -> Result<BTreeSet<u32>> {
+        cpus.iter()
+            .map(|cpu| {
+                self.cpu_nodes
+                    .get(cpu)
+                    .copied()
+                    .ok_or(NumaError::UnknownCpu(*cpu))
+            })
+            .collect()
+    }
+}
+
+/// Returns the memory nodes for a cpu list: the nodes covering the cpus
+pub fn mems_for_cpus(topology: &NumaTopology, cpus: &str) -> Result<String> {
+    let nodes = topology.nodes_of(&parse_list(cpus)?)?;
+    Ok(format_list(&nodes))
+}
+
+/// If auto mems is enabled for the spec, by the builder or the annotation
+pub fn auto_mems_enabled(spec: &Spec, builder_flag: bool) -> bool {
+    builder_flag
+        || spec
+            .annotations()
+            .as_ref()
+            .and_then(|annotations| annotations.get(NUMA_AUTO_MEMS_ANNOTATION))
+            .map_or(false, |value| value == "true")
+}
+
+/// Sets the memory nodes of the cpuset of the spec to the nodes of its cpus,
+/// if the spec sets cpus but no mems. The mems are also recorded in the
+/// [`NUMA_AUTO_MEMS_RESOLVED_ANNOTATION`] annotation. Returns the mems that
+/// were set.
+pub fn apply_auto_mems(spec: &mut Spec, topology: &NumaTopology) -> Result<Option<String>> {
+    let cpu = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+        .and_then(|resources| resources.cpu().as_ref());
+    let cpus = match cpu {
+        Some(cpu)
+            if cpu
+                .mems()
+                .as_ref()
+                .map_or(true, |mems| mems.trim().is_empty()) =>
+        {
+            match cpu.cpus().as_ref().filter(|cpus| !cpus.trim().is_empty()) {
+                Some(cpus) => cpus.clone(),
+                None => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+    if topology.is_empty() {
+        tracing::debug!("no NUMA topology, leaving the mems of the cpuset unset");
+        return Ok(None);
+    }
+
+    let mems = mems_for_cpus(topology, &cpus)?;
+    tracing::debug!(
+        ?cpus,
+        ?mems,
+        "setting the mems of the cpuset to the nodes of its cpus"
+    );
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    let mut resources = linux
+        .resources()
+        .clone()
+        .unwrap_or(LinuxResourcesBuilder::default().build()?);
+    let mut cpu = resources
+        .cpu()
+        .clone()
+        .unwrap_or(LinuxCpuBuilder::default().build()?);
+    cpu.set_mems(Some(mems.clone()));
+    resources.set_cpu(Some(cpu));
+    linux.set_resources(Some(resources));
+    spec.set_linux(Some(linux));
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    annotations.insert(NUMA_AUTO_MEMS_RESOLVED_ANNOTATION.to_owned(), mems.clone());
+    spec.set_annotations(Some(annotations));
+
+    Ok(Some(mems))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use oci_spec::runtime::LinuxBuilder;
+
+    use super::*;
+
+    /// Two nodes with four cores each and two threads per core. The second
+    /// threads of the cores are numbered after the first threads of all cores,
+    /// as the kernel does on x86.
+    fn smt_topology() -> NumaTopology {
+        let mut cpu_nodes = BTreeMap::new();
+        for cpu in 0..16 {
+            let core = cpu % 8;
+            cpu_nodes.insert(cpu, core / 4);
+        }
+        NumaTopology::new(cpu_nodes)
+    }
+
+    fn multi_node_topology() -> NumaTopology {
+        NumaTopology::new((0..32).map(|cpu| (cpu, cpu / 8)).collect())
+    }
+
+    #[test]
+    fn test_mems_for_cpus_multi_node() -> Result<()> {
+        let topology = multi_node_topology();
+        assert_eq!(mems_for_cpus(&topology, "0-7")?, "0");
+        assert_eq!(mems_for_cpus(&topology, "6-9")?, "0-1");
+        assert_eq!(mems_for_cpus(&topology, "0,31")?, "0,3");
+        assert_eq!(mems_for_cpus(&topology, "8-23")?, "1-2");
+        assert!(matches!(
+            mems_for_cpus(&topology, "32"),
+            Err(NumaError::UnknownCpu(32))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mems_for_cpus_smt() -> Result<()> {
+        let topology = smt_topology();
+        // a core and its sibling thread
+        assert_eq!(mems_for_cpus(&topology, "0,8")?, "0");
+        // the second threads of the cores of node 1
+        assert_eq!(mems_for_cpus(&topology, "12-15")?, "1");
+        assert_eq!(mems_for_cpus(&topology, "3-4")?, "0-1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_auto_mems() -> Result<()> {
+        let topology = multi_node_topology();
+        let spec_with = |cpus: Option<&str>, mems: Option<&str>| -> Result<Spec> {
+            let mut cpu = LinuxCpuBuilder::default();
+            if let Some(cpus) = cpus {
+                cpu = cpu.cpus(cpus);
+            }
+            if let Some(mems) = mems {
+                cpu = cpu.mems(mems);
+            }
+            let mut spec = Spec::default();
+            spec.set_linux(Some(
+                LinuxBuilder::default()
+                    .resources(LinuxResourcesBuilder::default().cpu(cpu.build()?).build()?)
+                    .build()?,
+            ));
+            Ok(spec)
+        };
+        let mems_of = |spec: &Spec| {
+            spec.linux()
+                .as_ref()
+                .and_then(|linux| linux.resources().as_ref())
+                .and_then(|resources| resources.cpu().as_ref())
+                .and_then(|cpu| cpu.mems().clone())
+        };
+
+        let mut spec = spec_with(Some("8-15"), None)?;
+        assert_eq!(apply_auto_mems(&mut spec, &topology)?, Some("1".to_owned()));
+        assert_eq!(mems_of(&spec), Some("1".to_owned()));
+        assert_eq!(
+            spec.annotations().as_ref().unwrap()[NUMA_AUTO_MEMS_RESOLVED_ANNOTATION],
+            "1"
+        );
+
+        // explicit mems are kept
+        let mut spec = spec_with(Some("8-15"), Some("0"))?;
+        assert_eq!(apply_auto_mems(&mut spec, &topology)?, None);
+        assert_eq!(mems_of(&spec), Some("0".to_owned()));
+
+        // without cpus or topology there is nothing to derive the mems from
+        let mut spec = spec_with(None, None)?;
+        assert_eq!(apply_auto_mems(&mut spec, &topology)?, None);
+        let mut spec = spec_with(Some("0"), None)?;
+        assert_eq!(apply_auto_mems(&mut spec, &NumaTopology::default())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_mems_enabled() {
+        let mut spec = Spec::default();
+        assert!(!auto_mems_enabled(&spec, false));
+        assert!(auto_mems_enabled(&spec, true));
+        spec.set_annotations(Some(HashMap::from([(
+            NUMA_AUTO_MEMS_ANNOTATION.to_owned(),
+            "true".to_owned(),
+        )])));
+        assert!(auto_mems_enabled(&spec, false));
+    }
+
+    #[test]
+    fn test_topology_from_sysfs() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for (cpu, node) in [(0, 0), (1, 0), (2, 1), (3, 1)] {
+            let cpu_dir = tmp.path().join(format!("cpu{cpu}"));
+            fs::create_dir_all(&cpu_dir)?;
+            std::os::unix::fs::symlink(
+                format!("../../node/node{node}"),
+                cpu_dir.join(format!("node{node}")),
+            )?;
+        }
+        fs::create_dir_all(tmp.path().join("cpufreq"))?;
+        fs::write(tmp.path().join("online"), "0-3")?;
+
+        let topology = NumaTopology::from_sysfs(tmp.path())?;
+        assert_eq!(
+            topology,
+            NumaTopology::new([(0, 0), (1, 0), (2, 1), (3, 1)].into_iter().collect())
+        );
+        assert_eq!(mems_for_cpus(&topology, "1-2")?, "0-1");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Bounded capture of the output of processes the runtime spawns
+//!
+//! Hooks and helpers like newuidmap would otherwise write to whatever the
+//! runtime inherited, or nowhere. Their stdout and stderr are piped and read
+//! in threads up to a limit, the rest is drained so the process never blocks
+//! on a full pipe. A process may leave a child behind that holds the pipes
+//! open, so collecting the output only waits a short grace period after the
+//! process exited and returns what was read until then.
+use std::io::Read;
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default number of bytes captured of each of stdout and stderr
+pub const DEFAULT_CAPTURE_LIMIT: usize = 32 * 1024;
+/// How long the output is still read after the process exited
+const DRAIN_GRACE: Duration = Duration::from_millis(100);
+
+/// The captured output of a stream
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedOutput {
+    pub data: Vec<u8>,
+    /// If the stream had more output than the limit
+    pub truncated: bool,
+}
+
+impl CapturedOutput {
+    pub fn lossy(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+}
+
+#[derive(Default)]
+struct Stream {
+    output: CapturedOutput,
+    done: bool,
+}
+
+/// Captures the output of a reader in a thread
+struct StreamCapture {
+    stream: Arc<Mutex<Stream>>,
+}
+
+impl StreamCapture {
+    fn start<R: Read + Send + 'static>(mut reader: R, limit: usize) -> Self {
+        let stream = Arc::new(Mutex::new(Stream::default()));
+        let shared = stream.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                let mut stream = shared.lock().unwrap();
+                let room = limit.saturating_sub(stream.output.data.len());
+                if n > room {
+                    stream.output.truncated = true;
+                }
+                stream.output.data.extend_from_slice(&buf[..n.min(room)]);
+            }
+            shared.lock().unwrap().done = true;
+        });
+
+        Self { stream }
+    }
+
+    fn is_done(&self) -> bool {
+        self.stream.lock().unwrap().done
+    }
+
+    fn take(self) -> CapturedOutput {
+        std::mem::take(&mut self.stream.lock().unwrap().output)
+    }
+}
+
+/// Captures stdout and stderr of a child spawned with both piped
+pub struct OutputCapture {
+    stdout: Option<StreamCapture>,
+    stderr: Option<StreamCapture>,
+}
+
+impl OutputCapture {
+    /// Starts reading the piped stdout and stderr of the child, up to `limit`
+    /// bytes each
+    pub fn start(child: &mut Child, limit: usize) -> Self {
+        Self {
+            stdout: child
+                .stdout
+                .take()
+                .map(|stdout| StreamCapture::start(stdout, limit)),
+            stderr: child
+                .stderr
+                .take()
+                .map(|stderr| StreamCapture::start(stderr, limit)),
+        }
+    }
+
+    /// Returns the captured stdout and stderr, once the process exited
+    pub fn finish(self) -> (CapturedOutput, CapturedOutput) {
+        let deadline = Instant::now() + DRAIN_GRACE;
+        let streams = [&self.stdout, &self.stderr];
+        while Instant::now() < deadline && streams.iter().flatten().any(|stream| !stream.is_done())
+        {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        (
+            self.stdout.map(StreamCapture::take).unwrap_or_default(),
+            self.stderr.map(StreamCapture::take).unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Command, Stdio};
+
+    use anyhow::Result;
+
+    use super::*;
+
+    fn capture(script: &str, limit: usize) -> Result<(CapturedOutput, CapturedOutput)> {
+        let mut child = Command::new("sh")
+            .args(["-c", script])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let capture = OutputCapture::start(&mut child, limit);
+        child.wait()?;
+        Ok(capture.finish())
+    }
+
+    #[test]
+    fn test_capture_output() -> Result<()> {
+        let (stdout, stderr) = capture("echo out; echo err >&2", 1024)?;
+        assert_eq!(stdout.lossy(), "out\n");
+        assert!(!stdout.truncated);
+        assert_eq!(stderr.lossy(), "err\n");
+        assert!(!stderr.truncated);
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture_output_truncated() -> Result<()> {
+        // The process writes more than a pipe holds, and must not block.
+        let (stdout, stderr) = capture("head -c 200000 /dev/zero", 16)?;
+        assert_eq!(stdout.data, vec![0u8; 16]);
+        assert!(stdout.truncated);
+        assert_eq!(stderr, CapturedOutput::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture_output_left_open() -> Result<()> {
+        // A child left behind holds the pipes open.
+        let start = Instant::now();
+        let (stdout, _) = capture("echo out; sleep 5 &", 1024)?;
+        assert_eq!(stdout.lossy(), "out\n");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+}
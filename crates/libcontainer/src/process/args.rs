@@ -1,12 +1,17 @@
 use std::os::unix::prelude::RawFd;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
-use libcgroups::common::CgroupConfig;
+use libcgroups::common::{CgroupConfig, CpusetPartition};
 use oci_spec::runtime::Spec;
 
-use crate::container::Container;
+use crate::container::init_builder::HostnamePolicy;
+use crate::container::{Container, State};
+use crate::core_sched::CoreSched;
+use crate::fault_injection::FaultInjection;
 use crate::notify_socket::NotifyListener;
+use crate::rootfs::MountOrder;
 use crate::syscall::syscall::SyscallType;
 use crate::user_ns::UserNamespaceConfig;
 use crate::workload::Executor;
@@ -28,18 +33,62 @@ pub struct ContainerArgs {
     pub rootfs: PathBuf,
     /// Socket to communicate the file descriptor of the ptty
     pub console_socket: Option<RawFd>,
+    /// How long sending the pty master over the console socket is retried
+    pub console_socket_timeout: Duration,
+    /// How long the intermediate process may take to report
+    pub intermediate_timeout: Duration,
     /// The Unix Domain Socket to communicate container start
     pub notify_listener: NotifyListener,
     /// File descriptors preserved/passed to the container init process.
     pub preserve_fds: i32,
+    /// Listening sockets handed off to the container process, with the fd
+    /// each is passed as
+    pub socket_fds: Vec<(RawFd, RawFd)>,
     /// Container state
     pub container: Option<Container>,
+    /// State of the existing container a tenant process joins. It is only
+    /// passed on to the seccomp listener and never saved.
+    pub joined_container_state: Option<State>,
     /// Options for new namespace creation
     pub user_ns_config: Option<UserNamespaceConfig>,
     /// Cgroup Manager Config
     pub cgroup_config: CgroupConfig,
+    /// Partition mode of the container's cpuset
+    pub cpuset_partition: Option<CpusetPartition>,
+    /// If the cgroup filesystem is mounted read-only in the container
+    pub cgroup_mount_readonly: bool,
+    /// If the cgroup of the container is delegated to its root user
+    pub delegate_cgroup: bool,
+    /// Whether the hostname is set when joining an existing uts namespace
+    pub hostname_policy: HostnamePolicy,
+    /// If the hostname is set without a new uts namespace, i.e. on the host
+    pub hostname_without_uts: bool,
+    /// When the spec mounts are applied relative to pivot_root
+    pub mount_order: MountOrder,
+    /// If the loginuid of the container process is reset to unset
+    pub reset_loginuid: bool,
+    /// If /proc/sys is remounted read-only in the container
+    pub proc_sys_readonly: bool,
+    /// If an empty bind mount destination of the wrong type is replaced
+    pub fix_mount_target_type: bool,
+    /// If the /etc/mtab symlink is created when the image lacks it
+    pub mtab_symlink: bool,
+    /// If the default devices missing from the rootfs are created
+    pub ensure_default_devices: bool,
+    /// Bytes the rootfs setup may write into the rootfs
+    pub rootfs_write_limit: Option<u64>,
+    /// Size of the tmpfs the writable layer of the rootfs is backed with
+    pub rootfs_max_size: Option<u64>,
+    /// Uid and gid the container process runs as instead of the spec user
+    pub run_as_user: Option<(u32, u32)>,
+    /// If the username of the spec is resolved even if it gives ids
+    pub resolve_username: bool,
+    /// If the init process starts a new session
+    pub new_session: bool,
     /// If the container is to be run in detached mode
     pub detached: bool,
+    /// File the exit status of a detached init process is written to
+    pub exit_status_file: Option<PathBuf>,
     /// Manage the functions that actually run on the container
     pub executor: Box<dyn Executor>,
     /// If do not use pivot root to jail process inside rootfs
@@ -52,4 +101,22 @@ pub struct ContainerArgs {
     pub stderr: Option<RawFd>,
     // Indicate if the init process should be a sibling of the main process.
     pub as_sibling: bool,
+    /// If EPERM from making the processes non-dumpable is tolerated
+    pub tolerate_dumpable_eperm: bool,
+    /// How long the seccomp listener has to take the seccomp notify fd
+    pub seccomp_notify_timeout: Duration,
+    /// PATH the executable is resolved with if the env has none
+    pub default_path: Option<String>,
+    /// If the default PATH is added to the env of the process
+    pub inject_default_path: bool,
+    /// argv[0] the executable is run with instead of the first arg
+    pub argv0_override: Option<String>,
+    /// Time left for the create hooks when the container processes are
+    /// cloned. The init process may be in a time namespace with another
+    /// monotonic clock, so it gets the time left instead of a deadline.
+    pub create_timeout_left: Option<Duration>,
+    /// Core scheduling cookie the init process gets
+    pub core_sched: Option<CoreSched>,
+    /// Faults injected into the create, for testing
+    pub fault_injection: FaultInjection,
 }
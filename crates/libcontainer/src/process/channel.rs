@@ -1,9 +1,15 @@
+use std::collections::HashMap;
 use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
 
+use nix::errno::Errno;
 use nix::unistd::Pid;
+use oci_spec::runtime::LinuxNamespaceType;
 
 use crate::channel::{channel, Receiver, Sender};
-use crate::process::message::Message;
+use crate::process::message::{CgroupLocation, Message, Phase, PROTOCOL_VERSION};
+use crate::rootfs::FsType;
+use crate::user::ResolvedUser;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ChannelError {
@@ -24,8 +30,17 @@ pub enum ChannelError {
     MissingSeccompFds,
     #[error("exec process failed with error {0}")]
     ExecError(String),
+    #[error("failed to create {namespace:?} namespace: {errno}")]
+    NamespaceCreateFailed {
+        namespace: LinuxNamespaceType,
+        errno: Errno,
+    },
     #[error("intermediate process error {0}")]
     OtherError(String),
+    #[error("timed out {0}")]
+    Timeout(String),
+    #[error("unsupported protocol version {0}, supported up to {PROTOCOL_VERSION}")]
+    UnsupportedVersion(u32),
 }
 
 // Channel Design
@@ -38,10 +53,33 @@ pub enum ChannelError {
 // receiver to receive all message sent to the main process. The other
 // processes will share the main_sender and use it to send message to the main
 // process.
+//
+// The intermediate and init process may additionally report the duration of
+// the setup phases they run to the main process. These reports can arrive in
+// between any of the other messages, so the main receiver records them on the
+// side while waiting for the message it expects. A child which doesn't report
+// them simply leaves the phases without a duration.
+//
+// The reports came with version 1 of the messages. The intermediate process
+// announces the version it speaks before anything else, and the main receiver
+// refuses versions it doesn't know instead of misreading their messages. A
+// child that doesn't announce a version is version 0.
 
 pub fn main_channel() -> Result<(MainSender, MainReceiver), ChannelError> {
     let (sender, receiver) = channel::<Message>()?;
-    Ok((MainSender { sender }, MainReceiver { receiver }))
+    Ok((
+        MainSender { sender },
+        MainReceiver {
+            receiver,
+            phase_timings: HashMap::new(),
+            rootfs_written: None,
+            rootfs_fs_type: None,
+            cgroup_location: None,
+            resolved_user: None,
+            deadline: None,
+            version: 0,
+        },
+    ))
 }
 
 pub struct MainSender {
@@ -49,6 +87,15 @@ pub struct MainSender {
 }
 
 impl MainSender {
+    /// Announces the version of the messages this process sends, see
+    /// [`PROTOCOL_VERSION`]
+    pub fn protocol_version(&mut self) -> Result<(), ChannelError> {
+        tracing::debug!(version = PROTOCOL_VERSION, "sending protocol version");
+        self.sender.send(Message::Version(PROTOCOL_VERSION))?;
+
+        Ok(())
+    }
+
     // requests the Main to write the id mappings for the intermediate process
     // this needs to be done from the parent see https://man7.org/linux/man-pages/man7/user_namespaces.7.html
     pub fn identifier_mapping_request(&mut self) -> Result<(), ChannelError> {
@@ -79,11 +126,58 @@ impl MainSender {
         Ok(())
     }
 
+    pub fn phase_timing(&mut self, phase: Phase, duration: Duration) -> Result<(), ChannelError> {
+        tracing::debug!(?phase, ?duration, "sending phase timing");
+        self.sender.send(Message::PhaseTiming(phase, duration))?;
+
+        Ok(())
+    }
+
+    pub fn rootfs_written(&mut self, bytes: u64) -> Result<(), ChannelError> {
+        tracing::debug!(bytes, "sending rootfs writes");
+        self.sender.send(Message::RootfsWritten(bytes))?;
+
+        Ok(())
+    }
+
+    pub fn rootfs_fs_type(&mut self, fs_type: FsType) -> Result<(), ChannelError> {
+        tracing::debug!(%fs_type, "sending rootfs filesystem type");
+        self.sender.send(Message::RootfsFsType(fs_type))?;
+
+        Ok(())
+    }
+
+    pub fn cgroup_location(&mut self, location: CgroupLocation) -> Result<(), ChannelError> {
+        tracing::debug!(?location, "sending cgroup location");
+        self.sender.send(Message::CgroupLocation(location))?;
+
+        Ok(())
+    }
+
+    pub fn resolved_user(&mut self, user: ResolvedUser) -> Result<(), ChannelError> {
+        tracing::debug!(?user, "sending resolved user");
+        self.sender.send(Message::ResolvedUser(user))?;
+
+        Ok(())
+    }
+
     pub fn exec_failed(&mut self, err: String) -> Result<(), ChannelError> {
         self.sender.send(Message::ExecFailed(err))?;
         Ok(())
     }
 
+    pub fn namespace_create_failed(
+        &mut self,
+        namespace: LinuxNamespaceType,
+        errno: Errno,
+    ) -> Result<(), ChannelError> {
+        self.sender.send(Message::NamespaceCreateFailed {
+            namespace,
+            errno: errno as i32,
+        })?;
+        Ok(())
+    }
+
     pub fn send_error(&mut self, err: String) -> Result<(), ChannelError> {
         self.sender.send(Message::OtherError(err))?;
         Ok(())
@@ -98,23 +192,141 @@ impl MainSender {
 
 pub struct MainReceiver {
     receiver: Receiver<Message>,
+    phase_timings: HashMap<Phase, Duration>,
+    rootfs_written: Option<u64>,
+    rootfs_fs_type: Option<FsType>,
+    cgroup_location: Option<CgroupLocation>,
+    resolved_user: Option<ResolvedUser>,
+    deadline: Option<Instant>,
+    version: u32,
 }
 
 impl MainReceiver {
+    /// Sets the deadline the expected messages must arrive by, a message
+    /// that doesn't fails the wait with [`ChannelError::Timeout`]. `None`
+    /// waits forever.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    fn wait_for_message(&self, waiting_for: &str) -> Result<(), ChannelError> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Ok(()),
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let arrived =
+            self.receiver
+                .wait_readable(remaining)
+                .map_err(|err| ChannelError::ReceiveError {
+                    msg: waiting_for.to_string(),
+                    source: err,
+                })?;
+        if !arrived {
+            return Err(ChannelError::Timeout(waiting_for.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the duration of the phase, if it was reported by one of the
+    /// child processes.
+    pub fn phase_timing(&self, phase: Phase) -> Option<Duration> {
+        self.phase_timings.get(&phase).copied()
+    }
+
+    /// Returns the bytes the rootfs setup wrote into the rootfs, if the init
+    /// process reported them.
+    pub fn rootfs_written(&self) -> Option<u64> {
+        self.rootfs_written
+    }
+
+    /// Returns the type of the filesystem backing the rootfs, if the init
+    /// process detected and reported it.
+    pub fn rootfs_fs_type(&self) -> Option<FsType> {
+        self.rootfs_fs_type
+    }
+
+    /// Returns where the cgroup of the container landed, if the intermediate
+    /// process reported it.
+    pub fn cgroup_location(&self) -> Option<&CgroupLocation> {
+        self.cgroup_location.as_ref()
+    }
+
+    /// Returns the ids the process of the container runs with, if the init
+    /// process reported them.
+    pub fn resolved_user(&self) -> Option<&ResolvedUser> {
+        self.resolved_user.as_ref()
+    }
+
+    /// Returns the version of the messages the child processes announced, 0
+    /// if they didn't.
+    pub fn protocol_version(&self) -> u32 {
+        self.version
+    }
+
+    /// Records the version and the reports, which can arrive in between the
+    /// other messages. Returns any other message.
+    fn record(&mut self, msg: Message) -> Result<Option<Message>, ChannelError> {
+        match msg {
+            Message::Version(version) if version > PROTOCOL_VERSION => {
+                tracing::error!(version, "unsupported protocol version");
+                return Err(ChannelError::UnsupportedVersion(version));
+            }
+            Message::Version(version) => {
+                self.version = version;
+            }
+            Message::PhaseTiming(phase, duration) => {
+                self.phase_timings.insert(phase, duration);
+            }
+            Message::RootfsWritten(bytes) => {
+                self.rootfs_written = Some(bytes);
+            }
+            Message::RootfsFsType(fs_type) => {
+                self.rootfs_fs_type = Some(fs_type);
+            }
+            Message::CgroupLocation(location) => {
+                self.cgroup_location = Some(location);
+            }
+            Message::ResolvedUser(user) => {
+                self.resolved_user = Some(user);
+            }
+            msg => return Ok(Some(msg)),
+        }
+
+        Ok(None)
+    }
+
+    fn recv(&mut self, waiting_for: &str) -> Result<Message, ChannelError> {
+        loop {
+            self.wait_for_message(waiting_for)?;
+            let msg = self
+                .receiver
+                .recv()
+                .map_err(|err| ChannelError::ReceiveError {
+                    msg: waiting_for.to_string(),
+                    source: err,
+                })?;
+            if let Some(msg) = self.record(msg)? {
+                return Ok(msg);
+            }
+        }
+    }
+
     /// Waits for associated intermediate process to send ready message
     /// and return the pid of init process which is forked by intermediate process
     pub fn wait_for_intermediate_ready(&mut self) -> Result<Pid, ChannelError> {
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for intermediate process".to_string(),
-                source: err,
-            })?;
+        let msg = self.recv("waiting for intermediate process")?;
 
         match msg {
             Message::IntermediateReady(pid) => Ok(Pid::from_raw(pid)),
             Message::ExecFailed(err) => Err(ChannelError::ExecError(err)),
+            Message::NamespaceCreateFailed { namespace, errno } => {
+                Err(ChannelError::NamespaceCreateFailed {
+                    namespace,
+                    errno: Errno::from_raw(errno),
+                })
+            }
             Message::OtherError(err) => Err(ChannelError::OtherError(err)),
             msg => Err(ChannelError::UnexpectedMessage {
                 expected: Message::IntermediateReady(0),
@@ -124,15 +336,16 @@ impl MainReceiver {
     }
 
     pub fn wait_for_mapping_request(&mut self) -> Result<(), ChannelError> {
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for mapping request".to_string(),
-                source: err,
-            })?;
+        let msg = self.recv("waiting for mapping request")?;
         match msg {
             Message::WriteMapping => Ok(()),
+            // The user namespace is created before the mapping is requested.
+            Message::NamespaceCreateFailed { namespace, errno } => {
+                Err(ChannelError::NamespaceCreateFailed {
+                    namespace,
+                    errno: Errno::from_raw(errno),
+                })
+            }
             msg => Err(ChannelError::UnexpectedMessage {
                 expected: Message::WriteMapping,
                 received: msg,
@@ -141,12 +354,18 @@ impl MainReceiver {
     }
 
     pub fn wait_for_seccomp_request(&mut self) -> Result<i32, ChannelError> {
-        let (msg, fds) = self.receiver.recv_with_fds::<[RawFd; 1]>().map_err(|err| {
-            ChannelError::ReceiveError {
-                msg: "waiting for seccomp request".to_string(),
-                source: err,
+        let (msg, fds) = loop {
+            self.wait_for_message("waiting for seccomp request")?;
+            let (msg, fds) = self.receiver.recv_with_fds::<[RawFd; 1]>().map_err(|err| {
+                ChannelError::ReceiveError {
+                    msg: "waiting for seccomp request".to_string(),
+                    source: err,
+                }
+            })?;
+            if let Some(msg) = self.record(msg)? {
+                break (msg, fds);
             }
-        })?;
+        };
 
         match msg {
             Message::SeccompNotify => {
@@ -172,19 +391,19 @@ impl MainReceiver {
     /// Waits for associated init process to send ready message
     /// and return the pid of init process which is forked by init process
     pub fn wait_for_init_ready(&mut self) -> Result<(), ChannelError> {
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for init ready".to_string(),
-                source: err,
-            })?;
+        let msg = self.recv("waiting for init ready")?;
         match msg {
             Message::InitReady => Ok(()),
             // this case in unique and known enough to have a special error format
             Message::ExecFailed(err) => Err(ChannelError::ExecError(format!(
                 "error in executing process : {err}"
             ))),
+            Message::NamespaceCreateFailed { namespace, errno } => {
+                Err(ChannelError::NamespaceCreateFailed {
+                    namespace,
+                    errno: Errno::from_raw(errno),
+                })
+            }
             msg => Err(ChannelError::UnexpectedMessage {
                 expected: Message::InitReady,
                 received: msg,
@@ -313,6 +532,7 @@ impl InitReceiver {
 #[cfg(test)]
 mod tests {
     use anyhow::{Context, Result};
+    use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
     use nix::sys::wait;
     use nix::unistd;
     use serial_test::serial;
@@ -414,6 +634,101 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_channel_namespace_create_failed() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                let err = receiver
+                    .wait_for_init_ready()
+                    .expect_err("the init process failed");
+                assert!(matches!(
+                    err,
+                    ChannelError::NamespaceCreateFailed {
+                        namespace: LinuxNamespaceType::Network,
+                        errno: Errno::EPERM,
+                    }
+                ));
+                receiver.close()?;
+            }
+            unistd::ForkResult::Child => {
+                sender
+                    .namespace_create_failed(LinuxNamespaceType::Network, Errno::EPERM)
+                    .with_context(|| "Failed to send namespace create failure")?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_phase_timing() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                receiver.wait_for_init_ready()?;
+                receiver.close()?;
+                assert_eq!(receiver.protocol_version(), PROTOCOL_VERSION);
+                assert_eq!(
+                    receiver.phase_timing(Phase::RootfsPrepare),
+                    Some(Duration::from_millis(42))
+                );
+                assert_eq!(receiver.phase_timing(Phase::CgroupApply), None);
+            }
+            unistd::ForkResult::Child => {
+                sender
+                    .protocol_version()
+                    .with_context(|| "Failed to send protocol version")?;
+                sender
+                    .phase_timing(Phase::RootfsPrepare, Duration::from_millis(42))
+                    .with_context(|| "Failed to send phase timing")?;
+                sender
+                    .init_ready()
+                    .with_context(|| "Failed to send init ready")?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_unsupported_version() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                let err = receiver.wait_for_init_ready().unwrap_err();
+                assert!(
+                    matches!(err, ChannelError::UnsupportedVersion(version) if version == PROTOCOL_VERSION + 1),
+                    "unexpected error: {err:?}"
+                );
+                receiver.close()?;
+            }
+            unistd::ForkResult::Child => {
+                sender
+                    .sender
+                    .send(Message::Version(PROTOCOL_VERSION + 1))
+                    .with_context(|| "Failed to send protocol version")?;
+                sender
+                    .init_ready()
+                    .with_context(|| "Failed to send init ready")?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_main_graceful_exit() -> Result<()> {
@@ -459,4 +774,55 @@ mod tests {
 
         Ok(())
     }
+
+    extern "C" fn ignore(_: libc::c_int) {}
+
+    #[test]
+    #[serial]
+    fn test_channel_wait_resumes_after_signal() -> Result<()> {
+        // The handler of the signal is only installed in the child.
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                assert_eq!(
+                    wait::waitpid(child, None)?,
+                    wait::WaitStatus::Exited(child, 0)
+                );
+            }
+            unistd::ForkResult::Child => {
+                let (mut sender, mut receiver) = main_channel()?;
+                // An embedder's handler, even with SA_RESTART, interrupts poll.
+                let action = SigAction::new(
+                    SigHandler::Handler(ignore),
+                    SaFlags::SA_RESTART,
+                    SigSet::empty(),
+                );
+                unsafe { signal::sigaction(Signal::SIGALRM, &action)? };
+                let sending = std::thread::spawn(move || {
+                    let mut blocked = SigSet::empty();
+                    blocked.add(Signal::SIGALRM);
+                    let _ = blocked.thread_block();
+                    std::thread::sleep(Duration::from_millis(200));
+                    sender.intermediate_ready(unistd::getpid())
+                });
+                let timer = libc::itimerval {
+                    it_interval: libc::timeval {
+                        tv_sec: 0,
+                        tv_usec: 0,
+                    },
+                    it_value: libc::timeval {
+                        tv_sec: 0,
+                        tv_usec: 50_000,
+                    },
+                };
+                unsafe { libc::setitimer(libc::ITIMER_REAL, &timer, std::ptr::null_mut()) };
+
+                receiver.set_deadline(Some(Instant::now() + Duration::from_secs(5)));
+                let received = receiver.wait_for_intermediate_ready();
+                let sent = sending.join().map(|sent| sent.is_ok()).unwrap_or(false);
+                std::process::exit(if received.is_ok() && sent { 0 } else { 1 });
+            }
+        };
+
+        Ok(())
+    }
 }
@@ -1,17 +1,25 @@
 use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use libcgroups::common::CgroupManager;
-use nix::unistd::{close, write, Gid, Pid, Uid};
-use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType, LinuxResources};
+use libcgroups::common::{CgroupManager, CpusetPartition, DEFAULT_CGROUP_ROOT};
+use nix::unistd::{chown, close, write, Gid, Pid, Uid};
+use oci_spec::runtime::{LinuxIdMapping, LinuxNamespace, LinuxNamespaceType, LinuxResources};
 use procfs::process::Process;
 
 use super::args::{ContainerArgs, ContainerType};
 use super::channel::{IntermediateReceiver, MainSender};
 use super::fork::CloneCb;
 use super::init::process as init_process;
+use super::message::{CgroupLocation, Message, Phase};
+use crate::core_sched::{self, CoreSched};
 use crate::error::MissingSpecError;
+use crate::fault_injection::FaultPoint;
 use crate::namespaces::Namespaces;
 use crate::process::{channel, fork};
+use crate::syscall::Syscall;
+use crate::user_ns::UserNamespaceConfig;
+use crate::utils::{self, PathBufExt};
 
 #[derive(Debug, thiserror::Error)]
 pub enum IntermediateProcessError {
@@ -25,18 +33,38 @@ pub enum IntermediateProcessError {
     InitProcess(#[source] fork::CloneError),
     #[error("cgroup error: {0}")]
     Cgroup(String),
+    #[error("failed to delegate cgroup file {path:?}")]
+    CgroupDelegation {
+        path: PathBuf,
+        #[source]
+        source: nix::Error,
+    },
     #[error(transparent)]
     Procfs(#[from] procfs::ProcError),
     #[error("exec notify failed")]
     ExecNotify(#[source] nix::Error),
     #[error(transparent)]
     MissingSpec(#[from] crate::error::MissingSpecError),
+    #[error(transparent)]
+    FaultInjection(#[from] crate::fault_injection::FaultInjectionError),
+    #[error(transparent)]
+    CoreSched(#[from] crate::core_sched::CoreSchedError),
     #[error("other error")]
     Other(String),
 }
 
 type Result<T> = std::result::Result<T, IntermediateProcessError>;
 
+impl IntermediateProcessError {
+    /// Returns the type and errno if a namespace failed to be created.
+    pub fn namespace_failure(&self) -> Option<(LinuxNamespaceType, nix::errno::Errno)> {
+        match self {
+            IntermediateProcessError::Namespace(err) => err.create_failure(),
+            _ => None,
+        }
+    }
+}
+
 pub fn container_intermediate_process(
     args: &ContainerArgs,
     intermediate_chan: &mut (channel::IntermediateSender, channel::IntermediateReceiver),
@@ -45,6 +73,7 @@ pub fn container_intermediate_process(
 ) -> Result<()> {
     let (inter_sender, inter_receiver) = intermediate_chan;
     let (init_sender, init_receiver) = init_chan;
+    main_sender.protocol_version()?;
     let command = args.syscall.create_syscall();
     let spec = &args.spec;
     let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
@@ -52,6 +81,14 @@ pub fn container_intermediate_process(
     let cgroup_manager = libcgroups::common::create_cgroup_manager(args.cgroup_config.to_owned())
         .map_err(|e| IntermediateProcessError::Cgroup(e.to_string()))?;
 
+    // The pid to share the core scheduling cookie from is only valid in the
+    // pid namespace of the runtime, which the init process has left. The
+    // cookie is shared here before any namespace is entered, and the init
+    // process inherits it.
+    if let Some(core_sched @ CoreSched::ShareFrom(_)) = args.core_sched {
+        core_sched::apply(command.as_ref(), core_sched)?;
+    }
+
     // this needs to be done before we create the init process, so that the init
     // process will already be captured by the cgroup. It also needs to be done
     // before we enter the user namespace because if a privileged user starts a
@@ -62,18 +99,44 @@ pub fn container_intermediate_process(
     // In addition this needs to be done before we enter the cgroup namespace as
     // the cgroup of the process will form the root of the cgroup hierarchy in
     // the cgroup namespace.
+    args.fault_injection.inject(FaultPoint::PreCgroupApply)?;
+    let cgroup_apply_start = Instant::now();
     apply_cgroups(
         &cgroup_manager,
         linux.resources().as_ref(),
+        args.cpuset_partition,
         matches!(args.container_type, ContainerType::InitContainer),
     )?;
+    if args.delegate_cgroup {
+        if let Some(user_ns_config) = args
+            .user_ns_config
+            .as_ref()
+            .filter(|c| c.privileged && !c.joins_existing())
+        {
+            delegate_cgroup(user_ns_config)?;
+        }
+    }
+    main_sender.phase_timing(Phase::CgroupApply, cgroup_apply_start.elapsed())?;
+    if let Some(path) = cgroup_manager.cgroup_path() {
+        main_sender.cgroup_location(CgroupLocation {
+            path: path.to_owned(),
+            systemd_unit: cgroup_manager.systemd_unit().map(str::to_owned),
+        })?;
+    }
 
     // if new user is specified in specification, this will be true and new
     // namespace will be created, check
     // https://man7.org/linux/man-pages/man7/user_namespaces.7.html for more
     // information
     if let Some(user_namespace) = namespaces.get(LinuxNamespaceType::User)? {
-        setup_userns(&namespaces, user_namespace, main_sender, inter_receiver)?;
+        setup_userns(
+            &*command,
+            &namespaces,
+            user_namespace,
+            main_sender,
+            inter_receiver,
+            args.tolerate_dumpable_eperm,
+        )?;
 
         // After UID and GID mapping is configured correctly in the Youki main
         // process, We want to make sure continue as the root user inside the
@@ -122,11 +185,28 @@ pub fn container_intermediate_process(
                 Ok(_) => 0,
                 Err(e) => {
                     tracing::error!("failed to initialize container process: {e}");
-                    if let Err(err) = main_sender.exec_failed(e.to_string()) {
+                    let sent = match e.namespace_failure() {
+                        Some((namespace, errno)) => {
+                            main_sender.namespace_create_failed(namespace, errno)
+                        }
+                        None => main_sender.exec_failed(e.to_string()),
+                    };
+                    if let Err(err) = sent {
                         tracing::error!(?err, "failed sending error to main sender");
                     }
                     if let ContainerType::TenantContainer { exec_notify_fd } = args.container_type {
-                        let buf = format!("{e}");
+                        let msg = match (e.exec_failure(), e.namespace_failure()) {
+                            (Some((path, errno)), _) => Message::ExecErrno {
+                                path: path.to_owned(),
+                                errno: errno as i32,
+                            },
+                            (None, Some((namespace, errno))) => Message::NamespaceCreateFailed {
+                                namespace,
+                                errno: errno as i32,
+                            },
+                            (None, None) => Message::OtherError(e.to_string()),
+                        };
+                        let buf = serde_json::to_string(&msg).unwrap_or_else(|_| e.to_string());
                         let exec_notify_fd =
                             unsafe { std::os::fd::OwnedFd::from_raw_fd(exec_notify_fd) };
                         if let Err(err) = write(&exec_notify_fd, buf.as_bytes()) {
@@ -190,10 +270,12 @@ pub fn container_intermediate_process(
 }
 
 fn setup_userns(
+    syscall: &dyn Syscall,
     namespaces: &Namespaces,
     user_namespace: &LinuxNamespace,
     sender: &mut MainSender,
     receiver: &mut IntermediateReceiver,
+    tolerate_dumpable_eperm: bool,
 ) -> Result<()> {
     namespaces.unshare_or_setns(user_namespace)?;
     if user_namespace.path().is_some() {
@@ -203,11 +285,9 @@ fn setup_userns(
     tracing::debug!("creating new user namespace");
     // child needs to be dumpable, otherwise the non root parent is not
     // allowed to write the uid/gid maps
-    prctl::set_dumpable(true).map_err(|e| {
-        IntermediateProcessError::Other(format!(
-            "error in setting dumpable to true : {}",
-            nix::errno::Errno::from_raw(e)
-        ))
+    utils::set_dumpable(syscall, true, tolerate_dumpable_eperm).map_err(|err| {
+        tracing::error!(?err, "failed to set the process dumpable");
+        err
     })?;
     sender.identifier_mapping_request().map_err(|err| {
         tracing::error!("failed to send id mapping request: {}", err);
@@ -217,11 +297,9 @@ fn setup_userns(
         tracing::error!("failed to receive id mapping ack: {}", err);
         err
     })?;
-    prctl::set_dumpable(false).map_err(|e| {
-        IntermediateProcessError::Other(format!(
-            "error in setting dumplable to false : {}",
-            nix::errno::Errno::from_raw(e)
-        ))
+    utils::set_dumpable(syscall, false, tolerate_dumpable_eperm).map_err(|err| {
+        tracing::error!(?err, "failed to set the process non-dumpable");
+        err
     })?;
     Ok(())
 }
@@ -232,6 +310,7 @@ fn apply_cgroups<
 >(
     cmanager: &C,
     resources: Option<&LinuxResources>,
+    cpuset_partition: Option<CpusetPartition>,
     init: bool,
 ) -> Result<()> {
     let pid = Pid::from_raw(Process::myself()?.pid());
@@ -242,12 +321,8 @@ fn apply_cgroups<
 
     if let Some(resources) = resources {
         if init {
-            let controller_opt = libcgroups::common::ControllerOpt {
-                resources,
-                freezer_state: None,
-                oom_score_adj: None,
-                disable_oom_killer: false,
-            };
+            let controller_opt = libcgroups::common::ControllerOpt::new(resources)
+                .with_cpuset_partition(cpuset_partition);
 
             cmanager.apply(&controller_opt).map_err(|err| {
                 tracing::error!(?pid, ?err, ?init, "failed to apply cgroup");
@@ -259,16 +334,109 @@ fn apply_cgroups<
     Ok(())
 }
 
+/// Files of a cgroup its delegatee must own, besides the cgroup directory
+/// itself, see the "Delegation" section of the cgroup v2 kernel docs
+const DELEGATED_CGROUP_FILES: [&str; 3] =
+    ["cgroup.procs", "cgroup.subtree_control", "cgroup.threads"];
+
+/// Hands the cgroup the process was just added to over to the root user of
+/// the new user namespace, so the container can manage its sub-cgroups. A
+/// rootless container is already in a cgroup owned by its user, only a
+/// privileged runtime has to do this, before it enters the user namespace.
+fn delegate_cgroup(user_ns_config: &UserNamespaceConfig) -> Result<()> {
+    let uid = host_id(user_ns_config.uid_mappings.as_deref(), 0).map(Uid::from_raw);
+    let gid = host_id(user_ns_config.gid_mappings.as_deref(), 0).map(Gid::from_raw);
+    if uid.is_none() && gid.is_none() {
+        return Ok(());
+    }
+
+    let own_cgroup = Process::myself()?
+        .cgroups()?
+        .0
+        .into_iter()
+        .find(|cgroup| cgroup.hierarchy == 0)
+        .ok_or_else(|| {
+            IntermediateProcessError::Cgroup("failed to find unified process cgroup".into())
+        })?;
+    let cgroup_path = Path::new(DEFAULT_CGROUP_ROOT)
+        .join_safely(&own_cgroup.pathname)
+        .map_err(|err| IntermediateProcessError::Cgroup(err.to_string()))?;
+
+    let paths = std::iter::once(cgroup_path.clone()).chain(
+        DELEGATED_CGROUP_FILES
+            .iter()
+            .map(|file| cgroup_path.join(file)),
+    );
+    for path in paths {
+        chown(&path, uid, gid).map_err(|err| {
+            tracing::error!(?err, ?path, ?uid, ?gid, "failed to delegate cgroup");
+            IntermediateProcessError::CgroupDelegation { path, source: err }
+        })?;
+    }
+    tracing::debug!(?cgroup_path, ?uid, ?gid, "delegated cgroup");
+
+    Ok(())
+}
+
+/// Id on the host that an id of the user namespace is mapped to, through
+/// the mapping whose range covers it
+fn host_id(mappings: Option<&[LinuxIdMapping]>, id: u32) -> Option<u32> {
+    mappings?.iter().find_map(|mapping| {
+        let offset = id.checked_sub(mapping.container_id())?;
+        if offset >= mapping.size() {
+            return None;
+        }
+        mapping.host_id().checked_add(offset)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
     use libcgroups::test_manager::TestManager;
     use nix::unistd::Pid;
-    use oci_spec::runtime::LinuxResources;
+    use oci_spec::runtime::{LinuxIdMappingBuilder, LinuxResources};
     use procfs::process::Process;
 
     use super::*;
 
+    #[test]
+    fn test_host_id() -> Result<()> {
+        let mapping = |container_id: u32, host_id: u32, size: u32| {
+            LinuxIdMappingBuilder::default()
+                .container_id(container_id)
+                .host_id(host_id)
+                .size(size)
+                .build()
+        };
+
+        assert_eq!(host_id(None, 0), None);
+        assert_eq!(
+            host_id(Some(&[mapping(0, 100000, 65536)?]), 0),
+            Some(100000)
+        );
+        assert_eq!(
+            host_id(Some(&[mapping(1, 100000, 65536)?, mapping(0, 1000, 1)?]), 0),
+            Some(1000)
+        );
+        // ids inside a range are translated by their offset in it
+        assert_eq!(
+            host_id(
+                Some(&[mapping(0, 1000, 1)?, mapping(1, 100000, 65536)?]),
+                1000
+            ),
+            Some(100999)
+        );
+        // root of the container isn't mapped
+        assert_eq!(host_id(Some(&[mapping(1, 100000, 65536)?]), 0), None);
+        assert_eq!(host_id(Some(&[mapping(0, 100000, 0)?]), 0), None);
+        // past the end of the range and of the host ids
+        assert_eq!(host_id(Some(&[mapping(0, 100000, 10)?]), 10), None);
+        assert_eq!(host_id(Some(&[mapping(0, u32::MAX, 2)?]), 1), None);
+
+        Ok(())
+    }
+
     #[test]
     fn apply_cgroup_init() -> Result<()> {
         // arrange
@@ -276,7 +444,7 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), true)?;
+        apply_cgroups(&cmanager, Some(&resources), None, true)?;
 
         // assert
         assert!(cmanager.get_add_task_args().len() == 1);
@@ -295,7 +463,7 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), false)?;
+        apply_cgroups(&cmanager, Some(&resources), None, false)?;
 
         // assert
         assert_eq!(
@@ -312,7 +480,7 @@ mod tests {
         let cmanager = TestManager::default();
 
         // act
-        apply_cgroups(&cmanager, None, true)?;
+        apply_cgroups(&cmanager, None, None, true)?;
         // assert
         assert_eq!(
             cmanager.get_add_task_args()[0],
@@ -1,11 +1,22 @@
+use std::mem::MaybeUninit;
+use std::os::fd::OwnedFd;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitStatus};
 use nix::unistd::Pid;
 
+use crate::container::Rusage;
 use crate::process::args::ContainerArgs;
 use crate::process::fork::{self, CloneCb};
 use crate::process::intel_rdt::setup_intel_rdt;
-use crate::process::{channel, container_intermediate_process};
+use crate::process::message::{CgroupLocation, Phase};
+use crate::process::watchdog::{self, ProcessDiagnostics};
+use crate::process::{channel, container_intermediate_process, exit_waiter};
+use crate::rootfs::FsType;
 use crate::syscall::SyscallError;
+use crate::user::ResolvedUser;
 use crate::user_ns::UserNamespaceConfig;
 
 #[derive(Debug, thiserror::Error)]
@@ -16,10 +27,17 @@ pub enum ProcessError {
     SetGroupsDeny(#[source] std::io::Error),
     #[error(transparent)]
     UserNamespace(#[from] crate::user_ns::UserNamespaceError),
+    #[error("uid mapping of {pid} was written but writing its gid mapping failed, the process was killed")]
+    PartialIdMapping {
+        pid: Pid,
+        source: crate::user_ns::UserNamespaceError,
+    },
     #[error("container state is required")]
     ContainerStateRequired,
     #[error("failed to wait for intermediate process")]
     WaitIntermediateProcess(#[source] nix::Error),
+    #[error("failed to open pidfd of init process {0}")]
+    InitPidfd(Pid, #[source] nix::Error),
     #[error(transparent)]
     IntelRdt(#[from] crate::process::intel_rdt::IntelRdtError),
     #[error("failed to create intermediate process")]
@@ -29,11 +47,50 @@ pub enum ProcessError {
     SeccompListener(#[from] crate::process::seccomp_listener::SeccompListenerError),
     #[error("failed syscall")]
     SyscallOther(#[source] SyscallError),
+    #[error(transparent)]
+    ExitWaiter(#[from] exit_waiter::ExitWaiterError),
+    #[error("intermediate process {pid} didn't respond within {timeout:?}, it was killed")]
+    CreateTimeout {
+        pid: Pid,
+        timeout: Duration,
+        diagnostics: Box<ProcessDiagnostics>,
+    },
 }
 
 type Result<T> = std::result::Result<T, ProcessError>;
 
-pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bool)> {
+/// Outcome of the main process once the container init process is ready
+#[derive(Debug)]
+pub struct MainProcessResult {
+    pub init_pid: Pid,
+    pub need_to_clean_up_intel_rdt_subdirectory: bool,
+    /// Time spent cloning the intermediate process
+    pub clone: Duration,
+    /// Time spent applying cgroups, as reported by the intermediate process
+    pub cgroup_apply: Option<Duration>,
+    /// Time spent preparing the rootfs, as reported by the init process
+    pub rootfs_prepare: Option<Duration>,
+    /// Bytes the rootfs setup wrote into the rootfs, as reported by the init
+    /// process
+    pub rootfs_written: Option<u64>,
+    /// Type of the filesystem backing the rootfs, as reported by the init
+    /// process
+    pub rootfs_fs_type: Option<FsType>,
+    /// Where the cgroup of the container landed, as reported by the
+    /// intermediate process
+    pub cgroup_location: Option<CgroupLocation>,
+    /// Ids the process of the container runs with, as reported by the init
+    /// process
+    pub user: Option<ResolvedUser>,
+    /// Resource usage of the intermediate process, if it was reaped here
+    pub intermediate_rusage: Option<Rusage>,
+    /// Pid of the process waiting for the init process to exit, if any
+    pub exit_waiter_pid: Option<Pid>,
+    /// pidfd of a sibling init process
+    pub init_pidfd: Option<OwnedFd>,
+}
+
+pub fn container_main_process(container_args: &ContainerArgs) -> Result<MainProcessResult> {
     // We use a set of channels to communicate between parent and child process.
     // Each channel is uni-directional. Because we will pass these channel to
     // cloned process, we have to be deligent about closing any unused channel.
@@ -59,7 +116,13 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
                 Ok(_) => 0,
                 Err(err) => {
                     tracing::error!("failed to run intermediate process {}", err);
-                    match main_sender.send_error(err.to_string()) {
+                    let sent = match err.namespace_failure() {
+                        Some((namespace, errno)) => {
+                            main_sender.namespace_create_failed(namespace, errno)
+                        }
+                        None => main_sender.send_error(err.to_string()),
+                    };
+                    match sent {
                         Ok(_) => {}
                         Err(e) => {
                             tracing::error!(
@@ -81,10 +144,27 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
         fork::container_clone
     };
 
-    let intermediate_pid = container_clone_fn(cb).map_err(|err| {
-        tracing::error!("failed to fork intermediate process: {}", err);
-        ProcessError::IntermediateProcessFailed(err)
-    })?;
+    let clone_start = Instant::now();
+    // Nothing is left to reap the init process of a detached container, so
+    // the intermediate process is cloned from a waiter doing it. A sibling
+    // init process is reaped by the parent of the caller instead.
+    let (intermediate_pid, exit_waiter_pid) = match &container_args.exit_status_file {
+        Some(exit_status_file) if container_args.detached && !container_args.as_sibling => {
+            let waiter = exit_waiter::clone_intermediate(cb, exit_status_file).map_err(|err| {
+                tracing::error!("failed to start exit waiter: {}", err);
+                err
+            })?;
+            (waiter.intermediate_pid, Some(waiter.waiter_pid))
+        }
+        _ => {
+            let pid = container_clone_fn(cb).map_err(|err| {
+                tracing::error!("failed to fork intermediate process: {}", err);
+                ProcessError::IntermediateProcessFailed(err)
+            })?;
+            (pid, None)
+        }
+    };
+    let clone = clone_start.elapsed();
 
     // Close down unused fds. The corresponding fds are duplicated to the
     // child process during clone.
@@ -99,11 +179,28 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
     #[cfg(not(feature = "libseccomp"))]
     let (init_sender, init_receiver) = init_chan;
 
+    // The intermediate process may get stuck, e.g. attaching to a frozen
+    // cgroup, so its messages are only waited for until the timeout.
+    let intermediate_timeout = container_args.intermediate_timeout;
+    main_receiver.set_deadline(Some(Instant::now() + intermediate_timeout));
+    let on_timeout = |err: channel::ChannelError| match err {
+        channel::ChannelError::Timeout(_) => {
+            watchdog::stuck_intermediate(intermediate_pid, intermediate_timeout)
+        }
+        err => ProcessError::Channel(err),
+    };
+
     // If creating a container with new user namespace, the intermediate process will ask
     // the main process to set up uid and gid mapping, once the intermediate
-    // process enters into a new user namespace.
-    if let Some(config) = &container_args.user_ns_config {
-        main_receiver.wait_for_mapping_request()?;
+    // process enters into a new user namespace. A joined one is mapped already.
+    if let Some(config) = container_args
+        .user_ns_config
+        .as_ref()
+        .filter(|config| !config.joins_existing())
+    {
+        main_receiver
+            .wait_for_mapping_request()
+            .map_err(on_timeout)?;
         setup_mapping(config, intermediate_pid)?;
         inter_sender.mapping_written()?;
     }
@@ -117,7 +214,21 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
 
     // The intermediate process will send the init pid once it forks the init
     // process.  The intermediate process should exit after this point.
-    let init_pid = main_receiver.wait_for_intermediate_ready()?;
+    let init_pid = main_receiver
+        .wait_for_intermediate_ready()
+        .map_err(on_timeout)?;
+    // The init process runs the create hooks, which have their own timeout.
+    main_receiver.set_deadline(None);
+    // A sibling init process is reaped by the parent of the caller, the
+    // pidfd is the only way for the caller to wait on it without racing the
+    // reuse of its pid. The init process waits for the main process from
+    // here on, so it can't have been reaped yet, and the init ready below
+    // confirms it is still the process the pidfd refers to.
+    let init_pidfd = if container_args.as_sibling {
+        Some(fork::pidfd_open(init_pid).map_err(|err| ProcessError::InitPidfd(init_pid, err))?)
+    } else {
+        None
+    };
     let mut need_to_clean_up_intel_rdt_subdirectory = false;
 
     if let Some(linux) = container_args.spec.linux() {
@@ -132,8 +243,9 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
                 state: container_args
                     .container
                     .as_ref()
+                    .map(|container| &container.state)
+                    .or(container_args.joined_container_state.as_ref())
                     .ok_or(ProcessError::ContainerStateRequired)?
-                    .state
                     .clone(),
             };
             crate::process::seccomp_listener::sync_seccomp(
@@ -141,6 +253,7 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
                 &state,
                 &mut init_sender,
                 &mut main_receiver,
+                container_args.seccomp_notify_timeout,
             )?;
         }
 
@@ -184,29 +297,71 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
         tracing::error!("failed to close main process receiver: {}", err);
         err
     })?;
+    let cgroup_apply = main_receiver.phase_timing(Phase::CgroupApply);
+    let rootfs_prepare = main_receiver.phase_timing(Phase::RootfsPrepare);
+    let rootfs_written = main_receiver.rootfs_written();
+    let rootfs_fs_type = main_receiver.rootfs_fs_type();
+    let cgroup_location = main_receiver.cgroup_location().cloned();
+    let user = main_receiver.resolved_user().cloned();
 
     // Before the main process returns, we want to make sure the intermediate
     // process is exit and reaped. By this point, the intermediate process
     // should already exited successfully. If intermediate process errors out,
-    // the `init_ready` will not be sent.
-    match waitpid(intermediate_pid, None) {
-        Ok(WaitStatus::Exited(_, 0)) => (),
-        Ok(WaitStatus::Exited(_, s)) => {
-            tracing::warn!("intermediate process failed with exit status: {s}");
-        }
-        Ok(WaitStatus::Signaled(_, sig, _)) => {
-            tracing::warn!("intermediate process killed with signal: {sig}")
-        }
-        Ok(_) => (),
-        Err(nix::errno::Errno::ECHILD) => {
-            // This is safe because intermediate_process and main_process check if the process is
-            // finished by piping instead of exit code.
-            tracing::warn!("intermediate process already reaped");
+    // the `init_ready` will not be sent. An intermediate process cloned by
+    // the exit waiter is reaped there. A sibling intermediate process isn't
+    // ours to reap, libcontainer never reaps the processes of a sibling.
+    let intermediate_rusage = if exit_waiter_pid.is_some() || container_args.as_sibling {
+        None
+    } else {
+        match wait_with_rusage(intermediate_pid) {
+            Ok((WaitStatus::Exited(_, 0), rusage)) => Some(rusage),
+            Ok((WaitStatus::Exited(_, s), rusage)) => {
+                tracing::warn!("intermediate process failed with exit status: {s}");
+                Some(rusage)
+            }
+            Ok((WaitStatus::Signaled(_, sig, _), rusage)) => {
+                tracing::warn!("intermediate process killed with signal: {sig}");
+                Some(rusage)
+            }
+            Ok(_) => None,
+            Err(Errno::ECHILD) => {
+                // This is safe because intermediate_process and main_process check if the process is
+                // finished by piping instead of exit code.
+                tracing::warn!("intermediate process already reaped");
+                None
+            }
+            Err(err) => return Err(ProcessError::WaitIntermediateProcess(err)),
         }
-        Err(err) => return Err(ProcessError::WaitIntermediateProcess(err)),
     };
 
-    Ok((init_pid, need_to_clean_up_intel_rdt_subdirectory))
+    Ok(MainProcessResult {
+        init_pid,
+        need_to_clean_up_intel_rdt_subdirectory,
+        clone,
+        cgroup_apply,
+        rootfs_prepare,
+        rootfs_written,
+        rootfs_fs_type,
+        cgroup_location,
+        user,
+        intermediate_rusage,
+        exit_waiter_pid,
+        init_pidfd,
+    })
+}
+
+/// Like `waitpid`, but also returns the resource usage of the reaped child.
+fn wait_with_rusage(pid: Pid) -> nix::Result<(WaitStatus, Rusage)> {
+    let mut status: libc::c_int = 0;
+    let mut usage = MaybeUninit::<libc::rusage>::zeroed();
+    // SAFETY: status and usage are valid for writes for the duration of the call.
+    let res = unsafe { libc::wait4(pid.as_raw(), &mut status, 0, usage.as_mut_ptr()) };
+    let pid = Errno::result(res)?;
+    let status = WaitStatus::from_raw(Pid::from_raw(pid), status)?;
+    // SAFETY: wait4 succeeded, so it filled in the usage, and it was zeroed anyway.
+    let usage = unsafe { usage.assume_init() };
+
+    Ok((status, Rusage::from(usage)))
 }
 
 fn setup_mapping(config: &UserNamespaceConfig, pid: Pid) -> Result<()> {
@@ -222,13 +377,30 @@ fn setup_mapping(config: &UserNamespaceConfig, pid: Pid) -> Result<()> {
         tracing::error!("failed to write uid mapping for pid {:?}: {}", pid, err);
         err
     })?;
-    config.write_gid_mapping(pid).map_err(|err| {
+    if let Err(err) = config.write_gid_mapping(pid) {
         tracing::error!("failed to write gid mapping for pid {:?}: {}", pid, err);
-        err
-    })?;
+        // The process has its uids mapped but all its gids map to the
+        // overflow gid. Kill it instead of letting the creation carry on
+        // with a half configured user namespace.
+        kill_partially_mapped(pid);
+        return Err(ProcessError::PartialIdMapping { pid, source: err });
+    }
     Ok(())
 }
 
+fn kill_partially_mapped(pid: Pid) {
+    if let Err(err) = signal::kill(pid, signal::Signal::SIGKILL) {
+        tracing::warn!(?err, ?pid, "failed to kill partially mapped process");
+        return;
+    }
+    // The process isn't our child if it was cloned by the exit waiter, which
+    // reaps it instead.
+    match waitpid(pid, None) {
+        Ok(_) | Err(Errno::ECHILD) => {}
+        Err(err) => tracing::warn!(?err, ?pid, "failed to reap partially mapped process"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -355,4 +527,49 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    #[serial]
+    fn setup_mapping_kills_process_on_gid_mapping_failure() -> Result<()> {
+        let uid_mapping = LinuxIdMappingBuilder::default()
+            .host_id(getuid())
+            .container_id(0u32)
+            .size(1u32)
+            .build()?;
+        let gid_mapping = LinuxIdMappingBuilder::default()
+            .host_id(getgid())
+            .container_id(0u32)
+            .size(1u32)
+            .build()?;
+        let tmp = tempfile::tempdir()?;
+        let id_mapper = UserNamespaceIDMapper::new_test(tmp.path().to_path_buf());
+        let ns_config = UserNamespaceConfig {
+            uid_mappings: Some(vec![uid_mapping]),
+            gid_mappings: Some(vec![gid_mapping]),
+            privileged: true,
+            id_mapper: id_mapper.clone(),
+            ..Default::default()
+        };
+
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                id_mapper.ensure_uid_path(&child)?;
+                // Writing the gid mapping fails as its path is a directory.
+                fs::create_dir_all(id_mapper.get_gid_path(&child))?;
+
+                let result = setup_mapping(&ns_config, child);
+                assert!(
+                    matches!(result, Err(ProcessError::PartialIdMapping { pid, .. }) if pid == child),
+                    "{result:?}"
+                );
+                assert!(fs::read_to_string(id_mapper.get_uid_path(&child))?.starts_with('0'));
+                // The process was killed and reaped.
+                assert_eq!(signal::kill(child, None), Err(Errno::ESRCH));
+            }
+            unistd::ForkResult::Child => loop {
+                unistd::pause();
+            },
+        }
+        Ok(())
+    }
 }
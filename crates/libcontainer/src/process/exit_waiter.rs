@@ -0,0 +1,230 @@
+//! Waiter reaping the init process of a detached container
+//!
+//! The init process of a detached container outlives the youki process that
+//! created it, so nothing is left to learn its exit status. If an exit status
+//! file is requested, a waiter process is double forked into its own session,
+//! and the intermediate process is cloned from it. The init process is cloned
+//! as a sibling of the intermediate process, which makes the waiter its parent.
+//! The only job of the waiter is to reap it and write the exit status file.
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::Path;
+
+use nix::errno::Errno;
+use nix::sys::wait::waitpid;
+use nix::unistd::{self, Pid};
+
+use super::fork::{self, CloneCb};
+use crate::container::ExitStatus;
+
+/// Process name of the waiter
+pub(crate) const EXIT_WAITER_NAME: &str = "youki:[WAITER]";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExitWaiterError {
+    #[error("failed to create the pipe to the exit waiter")]
+    Pipe(#[source] nix::Error),
+    #[error("failed to clone the exit waiter")]
+    Clone(#[source] fork::CloneError),
+    #[error("failed to wait for the exit waiter to detach")]
+    Wait(#[source] nix::Error),
+    #[error("failed to read the report of the exit waiter")]
+    Read(#[source] std::io::Error),
+    #[error("the exit waiter failed to clone the intermediate process")]
+    IntermediateProcessFailed,
+}
+
+type Result<T> = std::result::Result<T, ExitWaiterError>;
+
+/// Pids reported by a waiter once it cloned the intermediate process
+#[derive(Debug, Clone, Copy)]
+pub struct ExitWaiter {
+    pub waiter_pid: Pid,
+    pub intermediate_pid: Pid,
+}
+
+/// Starts a waiter writing the exit status of the init process to the given
+/// file, and clones the intermediate process from it with the given callback.
+pub fn clone_intermediate(cb: CloneCb, exit_status_file: &Path) -> Result<ExitWaiter> {
+    let (reader, writer) = unistd::pipe().map_err(ExitWaiterError::Pipe)?;
+
+    {
+        let mut cb = Some(cb);
+        let mut waiter_cb: Option<CloneCb> =
+            Some(Box::new(|| run_waiter(&mut cb, &writer, exit_status_file)));
+        // The first child only clones the waiter and exits, so the waiter is
+        // re-parented and doesn't depend on the lifetime of the caller.
+        let detach_cb: CloneCb = Box::new(|| match waiter_cb.take().map(fork::container_clone) {
+            Some(Ok(_)) => 0,
+            Some(Err(err)) => {
+                tracing::error!(?err, "failed to clone exit waiter");
+                -1
+            }
+            None => -1,
+        });
+
+        let detach_pid = fork::container_clone(detach_cb).map_err(ExitWaiterError::Clone)?;
+        waitpid(detach_pid, None).map_err(ExitWaiterError::Wait)?;
+    }
+    drop(writer);
+
+    // The intermediate process inherits the write end of the pipe, so the end
+    // of the report can't be told by EOF. The report is smaller than PIPE_BUF
+    // and written at once, a single read gets all of it. A waiter that failed
+    // before reporting closed its end, so the read doesn't block then.
+    let mut reader = File::from(reader);
+    let mut buf = [0u8; 64];
+    let len = loop {
+        match reader.read(&mut buf) {
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => break result.map_err(ExitWaiterError::Read)?,
+        }
+    };
+    let mut pids = String::from_utf8_lossy(&buf[..len])
+        .split_whitespace()
+        .map(|pid| pid.parse().map(Pid::from_raw));
+    match (pids.next(), pids.next()) {
+        (Some(Ok(waiter_pid)), Some(Ok(intermediate_pid))) => Ok(ExitWaiter {
+            waiter_pid,
+            intermediate_pid,
+        }),
+        _ => Err(ExitWaiterError::IntermediateProcessFailed),
+    }
+}
+
+fn run_waiter(cb: &mut Option<CloneCb>, writer: &OwnedFd, exit_status_file: &Path) -> i32 {
+    if let Err(err) = unistd::setsid() {
+        tracing::error!(?err, "failed to create session for exit waiter");
+        return -1;
+    }
+    if let Err(err) = prctl::set_name(EXIT_WAITER_NAME) {
+        tracing::error!(?err, "failed to set name for exit waiter");
+        return -1;
+    }
+
+    let intermediate_pid = match cb.take().map(fork::container_clone) {
+        Some(Ok(pid)) => pid,
+        Some(Err(err)) => {
+            tracing::error!(?err, "failed to clone intermediate process");
+            return -1;
+        }
+        None => return -1,
+    };
+
+    let report = format!("{} {}", unistd::getpid(), intermediate_pid);
+    if let Err(err) = unistd::write(writer, report.as_bytes()) {
+        tracing::error!(?err, "failed to report pids of exit waiter");
+        // The intermediate process holds the pipe open, it must not be left
+        // waiting for a caller that never learns its pid.
+        let _ = nix::sys::signal::kill(intermediate_pid, nix::sys::signal::Signal::SIGKILL);
+        return -1;
+    }
+
+    close_inherited_fds();
+    reap_init(intermediate_pid, exit_status_file)
+}
+
+/// Closes all file descriptors inherited from the caller and points stdio to
+/// /dev/null. Otherwise the waiter would keep the channels to the container
+/// processes, or the stdio pipes of a shim, open until the container exits.
+fn close_inherited_fds() {
+    // The fds are collected first, the directory is an open fd itself.
+    let fds: Vec<i32> = match fs::read_dir("/proc/self/fd") {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => return,
+    };
+    for fd in fds.into_iter().filter(|fd| *fd > 2) {
+        let _ = unistd::close(fd);
+    }
+
+    if let Ok(null) = OpenOptions::new().read(true).write(true).open("/dev/null") {
+        for fd in 0..=2 {
+            let _ = unistd::dup2(null.as_raw_fd(), fd);
+        }
+    }
+}
+
+/// Reaps the intermediate and the init process, which are the only children of
+/// the waiter. If the create failed before the init process was cloned, no exit
+/// status is written.
+fn reap_init(intermediate_pid: Pid, exit_status_file: &Path) -> i32 {
+    loop {
+        match waitpid(None, None) {
+            Ok(status) => {
+                if status.pid() == Some(intermediate_pid) {
+                    continue;
+                }
+                if let Some(exit_status) = ExitStatus::from_wait_status(status) {
+                    return match exit_status.write(exit_status_file) {
+                        Ok(_) => 0,
+                        Err(_) => -1,
+                    };
+                }
+            }
+            Err(Errno::EINTR) => continue,
+            Err(Errno::ECHILD) => return 0,
+            Err(_) => return -1,
+        }
+    }
+}
+
+/// Kills the waiter of a container, if it is still the parent of the init
+/// process. Checking the parent makes sure a reused pid is never killed.
+pub(crate) fn kill_exit_waiter(waiter_pid: Pid, init_pid: Pid) {
+    let is_parent = procfs::process::Process::new(init_pid.as_raw())
+        .and_then(|init| init.stat())
+        .map_or(false, |stat| stat.ppid == waiter_pid.as_raw());
+    if !is_parent {
+        return;
+    }
+
+    if let Err(err) = nix::sys::signal::kill(waiter_pid, nix::sys::signal::Signal::SIGKILL) {
+        tracing::warn!(?err, ?waiter_pid, "failed to kill exit waiter");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+    use serial_test::serial;
+
+    use super::*;
+
+    #[test]
+    #[serial]
+    fn test_exit_status_written_after_init_exits() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let exit_status_file = tmp.path().join("exit-status");
+        // Like the intermediate process, the callback clones the init process
+        // as its sibling, which makes the waiter the parent of the init.
+        let intermediate: CloneCb =
+            Box::new(|| match fork::container_clone_sibling(Box::new(|| 7)) {
+                Ok(_) => 0,
+                Err(_) => -1,
+            });
+
+        let waiter = clone_intermediate(intermediate, &exit_status_file)?;
+        assert_ne!(waiter.waiter_pid, waiter.intermediate_pid);
+
+        // The file is renamed into place, it exists only once complete.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !exit_status_file.exists() {
+            assert!(
+                Instant::now() < deadline,
+                "exit status file was not written"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        let exit_status = ExitStatus::load(&exit_status_file)?;
+        assert_eq!(exit_status.exit_code, 7);
+        assert_eq!(exit_status.signal, 0);
+
+        Ok(())
+    }
+}
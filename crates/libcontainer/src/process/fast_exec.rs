@@ -0,0 +1,729 @@
+//! Fast path to start a process in an existing container
+//!
+//! Joining a container normally clones an intermediate and an init process,
+//! which set up the process as for a new container before it executes the
+//! payload. A process that only joins the namespaces and the cgroup of the
+//! container doesn't need most of this, so the fast path clones it with
+//! `CLONE_VM | CLONE_VFORK`, like `posix_spawn`, and the child only does the
+//! syscalls to join the container before the exec. The child runs the code of
+//! [`no_alloc`](super::no_alloc), see there for the rules it follows.
+//!
+//! `clone3` isn't used here: with a stack of its own, the child returns from
+//! the raw syscall on an empty stack, which needs an assembly trampoline. The
+//! `clone` wrapper of libc already is one, and is what `posix_spawn` uses.
+//!
+//! Only a process [`unsupported`] doesn't report a reason for can be started
+//! with the fast path.
+
+use std::ffi::{c_char, c_int, c_ulong, c_void, CString, OsStr};
+use std::fs::{self, File, OpenOptions};
+use std::num::NonZeroUsize;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::ptr::{self, NonNull};
+
+use libcgroups::common::{CgroupSetup, DEFAULT_CGROUP_ROOT};
+use nix::errno::Errno;
+use nix::sched::{setns, CloneFlags};
+use nix::sys::signal::{pthread_sigmask, SigSet, SigmaskHow};
+use nix::sys::wait::waitpid;
+use nix::sys::{mman, resource};
+use nix::unistd::Pid;
+use oci_spec::runtime::{Capabilities, LinuxCapabilities, LinuxNamespaceType, Spec};
+#[cfg(feature = "libseccomp")]
+use oci_spec::runtime::{LinuxSeccomp, LinuxSeccompFilterFlag};
+
+use super::no_alloc::{self, ChildCapabilities, ChildPlan, Failure, Step};
+use crate::capabilities::CapabilityExt;
+use crate::error::MissingSpecError;
+
+/// Size of the stack of the child, which only does a few syscalls
+const STACK_SIZE: usize = 256 * 1024;
+const DEFAULT_PAGE_SIZE: usize = 4 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FastExecError {
+    #[error(transparent)]
+    MissingSpec(#[from] MissingSpecError),
+    #[error("{0} of the process contains a nul byte")]
+    Nul(&'static str),
+    #[error("failed to open {path:?}")]
+    Open {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to read {path:?}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("process {0} is not in a cgroup v2")]
+    NoCgroup(Pid),
+    #[cfg(feature = "libseccomp")]
+    #[error("failed to compile seccomp profile")]
+    Seccomp(#[source] crate::seccomp::SeccompError),
+    #[error("failed to get resource limit")]
+    ResourceLimit(#[source] nix::Error),
+    #[error("failed to allocate stack")]
+    StackAllocation(#[source] nix::Error),
+    #[error("failed to create stack guard page")]
+    GuardPage(#[source] nix::Error),
+    #[error("failed to set signal mask")]
+    SignalMask(#[source] nix::Error),
+    #[error("failed to switch pid namespace of the children")]
+    PidNamespace(#[source] nix::Error),
+    #[error("failed to clone process")]
+    Clone(#[source] nix::Error),
+    #[error("failed to {step} in the process: {errno}")]
+    Setup { step: &'static str, errno: Errno },
+    #[error("failed to execute {path:?}: {errno}")]
+    Exec { path: PathBuf, errno: Errno },
+}
+
+type Result<T> = std::result::Result<T, FastExecError>;
+
+/// Returns why the process of the spec can't be started with the fast path,
+/// or `None` if it can. Anything the fast path doesn't set up is a reason,
+/// the full path has to be used for it.
+pub fn unsupported(spec: &Spec) -> Option<&'static str> {
+    let process = match spec.process() {
+        Some(process) => process,
+        None => return Some("missing process"),
+    };
+    if process.terminal() == Some(true) {
+        return Some("terminal");
+    }
+    if process.apparmor_profile().is_some() {
+        return Some("apparmor profile");
+    }
+    if process.selinux_label().is_some() {
+        return Some("selinux label");
+    }
+    if process.io_priority().is_some() {
+        return Some("io priority");
+    }
+    if process.scheduler().is_some() {
+        return Some("scheduler");
+    }
+    if process.oom_score_adj().is_some() {
+        return Some("oom score adj");
+    }
+    if process
+        .rlimits()
+        .as_ref()
+        .map_or(false, |rlimits| !rlimits.is_empty())
+    {
+        return Some("rlimits");
+    }
+    if std::env::var_os("LISTEN_FDS").is_some() {
+        return Some("socket activation");
+    }
+
+    if let Some(linux) = spec.linux() {
+        for namespace in linux.namespaces().iter().flatten() {
+            let path = match namespace.path() {
+                Some(path) => path,
+                None => return Some("new namespace"),
+            };
+            // joining them requires a single threaded process, which the
+            // child isn't as it shares the memory of youki
+            match namespace.typ() {
+                LinuxNamespaceType::User if !is_own_namespace(namespace.typ(), path) => {
+                    return Some("user namespace")
+                }
+                LinuxNamespaceType::Time if !is_own_namespace(namespace.typ(), path) => {
+                    return Some("time namespace")
+                }
+                _ => {}
+            }
+        }
+        if let Some(seccomp) = linux.seccomp() {
+            #[cfg(feature = "libseccomp")]
+            if crate::seccomp::is_notify(seccomp) {
+                return Some("seccomp notify");
+            }
+            #[cfg(not(feature = "libseccomp"))]
+            {
+                let _ = seccomp;
+                return Some("seccomp");
+            }
+        }
+    }
+
+    // cgroup v1 would need one cgroup.procs per hierarchy
+    if !matches!(
+        libcgroups::common::get_cgroup_setup(),
+        Ok(CgroupSetup::Unified)
+    ) {
+        return Some("cgroup v1");
+    }
+
+    None
+}
+
+/// A process prepared to be started with the fast path
+#[derive(Debug)]
+pub struct FastExec {
+    path: CString,
+    args: Vec<CString>,
+    env: Vec<CString>,
+    cwd: Option<CString>,
+    pid_namespace: Option<OwnedFd>,
+    namespaces: Vec<OwnedFd>,
+    cgroup_procs: Option<OwnedFd>,
+    no_new_privileges: Option<bool>,
+    umask: Option<libc::mode_t>,
+    groups: Vec<libc::gid_t>,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    capabilities: Option<ChildCapabilities>,
+    preserve_fds: i32,
+    seccomp: Option<(Vec<libc::sock_filter>, c_ulong)>,
+}
+
+impl FastExec {
+    /// Prepares the process of the spec to join the container of `init_pid`.
+    /// The spec must be one [`unsupported`] has no reason for.
+    pub fn prepare(spec: &Spec, init_pid: Pid, preserve_fds: i32) -> Result<Self> {
+        let process = spec.process().as_ref().ok_or(MissingSpecError::Process)?;
+        let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
+        let args = process
+            .args()
+            .as_ref()
+            .filter(|args| !args.is_empty())
+            .ok_or(MissingSpecError::Args)?;
+        let init_root = PathBuf::from(format!("/proc/{init_pid}/root"));
+        let user = process.user();
+
+        let mut env = process.env().clone().unwrap_or_default();
+        if !env.iter().any(|var| var.starts_with("HOME=")) {
+            if let Some(home) = user_home(&init_root, user.uid()) {
+                env.push(format!("HOME={home}"));
+            }
+        }
+        // as the env of the full path, the last PATH wins
+        let path_var = env
+            .iter()
+            .rev()
+            .find_map(|var| var.strip_prefix("PATH="))
+            .unwrap_or_default();
+        let path = resolve_executable(&init_root, &args[0], path_var);
+
+        let mut pid_namespace = None;
+        let mut namespaces = Vec::new();
+        for namespace in linux.namespaces().iter().flatten() {
+            let ns_path = match namespace.path() {
+                Some(ns_path) if !is_own_namespace(namespace.typ(), ns_path) => ns_path,
+                _ => continue,
+            };
+            let fd = OwnedFd::from(File::open(ns_path).map_err(|err| FastExecError::Open {
+                path: ns_path.clone(),
+                source: err,
+            })?);
+            match namespace.typ() {
+                LinuxNamespaceType::Pid => pid_namespace = Some(fd),
+                _ => namespaces.push(fd),
+            }
+        }
+
+        let cgroup_procs = cgroup_procs_path(init_pid)?;
+        let cgroup_procs = OpenOptions::new()
+            .write(true)
+            .open(&cgroup_procs)
+            .map_err(|err| FastExecError::Open {
+                path: cgroup_procs,
+                source: err,
+            })?;
+
+        let mut groups = user.additional_gids().clone().unwrap_or_default();
+        groups.sort_unstable();
+        groups.dedup();
+
+        let cwd = match process.cwd().as_os_str() {
+            cwd if cwd.is_empty() => None,
+            cwd => Some(cstring(cwd.as_bytes(), "cwd")?),
+        };
+
+        Ok(Self {
+            path: cstring(path.as_os_str().as_bytes(), "path")?,
+            args: args
+                .iter()
+                .map(|arg| cstring(arg.as_bytes(), "args"))
+                .collect::<Result<_>>()?,
+            env: env
+                .iter()
+                .map(|var| cstring(var.as_bytes(), "env"))
+                .collect::<Result<_>>()?,
+            cwd,
+            pid_namespace,
+            namespaces,
+            cgroup_procs: Some(cgroup_procs.into()),
+            no_new_privileges: process.no_new_privileges(),
+            umask: user.umask(),
+            groups,
+            uid: user.uid(),
+            gid: user.gid(),
+            capabilities: process.capabilities().as_ref().map(child_capabilities),
+            preserve_fds,
+            #[cfg(feature = "libseccomp")]
+            seccomp: linux.seccomp().as_ref().map(compile_seccomp).transpose()?,
+            #[cfg(not(feature = "libseccomp"))]
+            seccomp: None,
+        })
+    }
+
+    /// Starts the process, as a sibling of the calling process if
+    /// `as_sibling`. Returns once the process executed the payload, or with
+    /// the error the setup or exec failed with.
+    pub fn spawn(&self, as_sibling: bool) -> Result<Pid> {
+        let argv = null_terminated(&self.args);
+        let envp = null_terminated(&self.env);
+        let namespaces: Vec<c_int> = self.namespaces.iter().map(AsRawFd::as_raw_fd).collect();
+        let seccomp = self.seccomp.as_ref().map(|(filter, _)| libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr() as *mut libc::sock_filter,
+        });
+        let (fd_limit, _) = resource::getrlimit(resource::Resource::RLIMIT_NOFILE)
+            .map_err(FastExecError::ResourceLimit)?;
+        let stack = Stack::new()?;
+        let failure = Failure::default();
+
+        // The handlers of youki must not run in the child, which shares its
+        // memory, so all signals are blocked until the child reset them.
+        let mut sigmask = SigSet::empty();
+        pthread_sigmask(
+            SigmaskHow::SIG_SETMASK,
+            Some(&SigSet::all()),
+            Some(&mut sigmask),
+        )
+        .map_err(FastExecError::SignalMask)?;
+        let plan = ChildPlan {
+            path: &self.path,
+            argv: &argv,
+            envp: &envp,
+            cgroup_procs: self.cgroup_procs.as_ref().map(AsRawFd::as_raw_fd),
+            namespaces: &namespaces,
+            no_new_privileges: self.no_new_privileges,
+            umask: self.umask,
+            cwd: self.cwd.as_deref(),
+            groups: &self.groups,
+            uid: self.uid,
+            gid: self.gid,
+            capabilities: self.capabilities,
+            first_closed_fd: 3 + self.preserve_fds,
+            fd_limit: fd_limit.min(c_int::MAX as u64) as c_int,
+            seccomp: seccomp.as_ref(),
+            seccomp_flags: self.seccomp.as_ref().map_or(0, |(_, flags)| *flags),
+            sigmask: sigmask.as_ref(),
+            failure: &failure,
+        };
+        let cloned = self.clone_child(&plan, &stack, as_sibling);
+        let restored = pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&sigmask), None);
+        let pid = cloned?;
+        restored.map_err(FastExecError::SignalMask)?;
+
+        if let Some((step, errno)) = failure.get() {
+            // The child already exited. A sibling is reaped by the parent of
+            // the calling process.
+            if !as_sibling {
+                let _ = waitpid(pid, None);
+            }
+            let errno = Errno::from_raw(errno);
+            return Err(match step {
+                Step::Exec => FastExecError::Exec {
+                    path: PathBuf::from(OsStr::from_bytes(self.path.as_bytes())),
+                    errno,
+                },
+                step => FastExecError::Setup {
+                    step: step.as_str(),
+                    errno,
+                },
+            });
+        }
+
+        Ok(pid)
+    }
+
+    fn clone_child(&self, plan: &ChildPlan, stack: &Stack, as_sibling: bool) -> Result<Pid> {
+        // The pid namespace of the children of the calling thread is switched
+        // for the clone only, the thread itself stays in its pid namespace.
+        let own_pid_namespace = match &self.pid_namespace {
+            Some(pid_namespace) => {
+                let own_path = Path::new("/proc/thread-self/ns/pid_for_children");
+                let own = File::open(own_path).map_err(|err| FastExecError::Open {
+                    path: own_path.to_owned(),
+                    source: err,
+                })?;
+                setns(pid_namespace, CloneFlags::CLONE_NEWPID)
+                    .map_err(FastExecError::PidNamespace)?;
+                Some(own)
+            }
+            None => None,
+        };
+
+        // As in `container_clone_sibling`, a sibling has no exit signal
+        let parent = if as_sibling {
+            libc::CLONE_PARENT
+        } else {
+            libc::SIGCHLD
+        };
+        // Safety: the child only runs `child_main` on the plan, which outlives
+        // it as the calling thread is suspended until the child executed or
+        // exited.
+        let ret = unsafe {
+            libc::clone(
+                no_alloc::child_main,
+                stack.top(),
+                libc::CLONE_VM | libc::CLONE_VFORK | parent,
+                plan as *const ChildPlan as *mut c_void,
+            )
+        };
+        let clone_errno = Errno::last();
+
+        if let Some(own) = own_pid_namespace {
+            setns(own, CloneFlags::CLONE_NEWPID).map_err(FastExecError::PidNamespace)?;
+        }
+
+        match ret {
+            -1 => Err(FastExecError::Clone(clone_errno)),
+            pid => Ok(Pid::from_raw(pid)),
+        }
+    }
+}
+
+/// The stack of the child, with a guard page as the child shares its memory
+/// with youki
+struct Stack {
+    base: NonNull<c_void>,
+}
+
+impl Stack {
+    fn new() -> Result<Self> {
+        let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+            .ok()
+            .flatten()
+            .map(|size| size as usize)
+            .unwrap_or(DEFAULT_PAGE_SIZE);
+        // Safety: a new anonymous mapping doesn't alias any memory.
+        let base = unsafe {
+            mman::mmap_anonymous(
+                None,
+                NonZeroUsize::new(STACK_SIZE).expect("stack size is not zero"),
+                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+                mman::MapFlags::MAP_PRIVATE | mman::MapFlags::MAP_STACK,
+            )
+            .map_err(FastExecError::StackAllocation)?
+        };
+        let stack = Self { base };
+        // Safety: the guard page is the bottom of the mapping above, the
+        // stack grows downward.
+        unsafe { mman::mprotect(stack.base, page_size, mman::ProtFlags::PROT_NONE) }
+            .map_err(FastExecError::GuardPage)?;
+
+        Ok(stack)
+    }
+
+    fn top(&self) -> *mut c_void {
+        // Safety: the offset is the end of the mapping.
+        unsafe { self.base.as_ptr().add(STACK_SIZE) }
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        // Safety: the child executed or exited, nothing uses the stack anymore.
+        if let Err(err) = unsafe { mman::munmap(self.base, STACK_SIZE) } {
+            tracing::warn!(?err, "failed to unmap the stack of the fast exec child");
+        }
+    }
+}
+
+/// Whether the namespace at `path` is the one the calling thread is in, which
+/// the child is in without joining it
+fn is_own_namespace(typ: LinuxNamespaceType, path: &Path) -> bool {
+    let name = match typ {
+        LinuxNamespaceType::Mount => "mnt",
+        LinuxNamespaceType::Cgroup => "cgroup",
+        LinuxNamespaceType::Uts => "uts",
+        LinuxNamespaceType::Ipc => "ipc",
+        LinuxNamespaceType::User => "user",
+        LinuxNamespaceType::Pid => "pid_for_children",
+        LinuxNamespaceType::Network => "net",
+        LinuxNamespaceType::Time => "time_for_children",
+    };
+    let own = Path::new("/proc/thread-self/ns").join(name);
+    match (fs::metadata(own), fs::metadata(path)) {
+        (Ok(own), Ok(other)) => own.dev() == other.dev() && own.ino() == other.ino(),
+        _ => false,
+    }
+}
+
+fn cstring(bytes: &[u8], what: &'static str) -> Result<CString> {
+    CString::new(bytes).map_err(|_| FastExecError::Nul(what))
+}
+
+fn null_terminated(strings: &[CString]) -> Vec<*const c_char> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr())
+        .chain(std::iter::once(ptr::null()))
+        .collect()
+}
+
+/// Resolves the executable in the rootfs of the container as the default
+/// executor does in the init process. A name that isn't found is executed
+/// as is, so the exec reports why it can't be executed.
+fn resolve_executable(root: &Path, name: &str, path_var: &str) -> PathBuf {
+    if name.contains('/') {
+        return PathBuf::from(name);
+    }
+    for dir in path_var.split(':') {
+        let path = Path::new(dir).join(name);
+        // symlinks are resolved in the container, not here
+        let in_root = root.join(path.strip_prefix("/").unwrap_or(&path));
+        if in_root.symlink_metadata().is_ok() {
+            return path;
+        }
+    }
+    PathBuf::from(name)
+}
+
+/// The home directory of `uid` in the passwd file of the container
+fn user_home(root: &Path, uid: u32) -> Option<String> {
+    let passwd = fs::read_to_string(root.join("etc/passwd")).ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.as_slice() {
+            [_, _, entry_uid, _, _, home, ..] if entry_uid.parse() == Ok(uid) => {
+                Some(home.to_string())
+            }
+            _ => None,
+        }
+    })
+}
+
+/// `cgroup.procs` of the cgroup v2 of `pid`
+fn cgroup_procs_path(pid: Pid) -> Result<PathBuf> {
+    let path = PathBuf::from(format!("/proc/{pid}/cgroup"));
+    let cgroups = fs::read_to_string(&path).map_err(|err| FastExecError::Read {
+        path: path.clone(),
+        source: err,
+    })?;
+    let cgroup = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or(FastExecError::NoCgroup(pid))?;
+
+    Ok(Path::new(DEFAULT_CGROUP_ROOT)
+        .join(cgroup.trim_start_matches('/'))
+        .join("cgroup.procs"))
+}
+
+fn child_capabilities(caps: &LinuxCapabilities) -> ChildCapabilities {
+    let mask = |set: &Option<Capabilities>| {
+        set.as_ref().map(|set| {
+            set.iter()
+                .fold(0u64, |mask, cap| mask | 1 << cap.to_cap().index())
+        })
+    };
+    let last_cap = caps::runtime::thread_all_supported()
+        .iter()
+        .map(|cap| cap.index() as u32)
+        .max()
+        .unwrap_or_default();
+
+    ChildCapabilities {
+        bounding: mask(caps.bounding()),
+        effective: mask(caps.effective()),
+        permitted: mask(caps.permitted()),
+        inheritable: mask(caps.inheritable()),
+        ambient: mask(caps.ambient()),
+        last_cap,
+    }
+}
+
+/// Compiles the profile to the filter the child loads, with the flags to
+/// load it with
+#[cfg(feature = "libseccomp")]
+fn compile_seccomp(seccomp: &LinuxSeccomp) -> Result<(Vec<libc::sock_filter>, c_ulong)> {
+    let bpf = crate::seccomp::compile_to_bpf_bytes(seccomp).map_err(FastExecError::Seccomp)?;
+    let filter = bpf
+        .chunks_exact(8)
+        .map(|insn| libc::sock_filter {
+            code: u16::from_ne_bytes([insn[0], insn[1]]),
+            jt: insn[2],
+            jf: insn[3],
+            k: u32::from_ne_bytes([insn[4], insn[5], insn[6], insn[7]]),
+        })
+        .collect();
+    let flags: c_ulong = seccomp.flags().iter().flatten().fold(0, |flags, flag| {
+        flags
+            | match flag {
+                LinuxSeccompFilterFlag::SeccompFilterFlagLog => libc::SECCOMP_FILTER_FLAG_LOG,
+                LinuxSeccompFilterFlag::SeccompFilterFlagTsync => libc::SECCOMP_FILTER_FLAG_TSYNC,
+                LinuxSeccompFilterFlag::SeccompFilterFlagSpecAllow => {
+                    libc::SECCOMP_FILTER_FLAG_SPEC_ALLOW
+                }
+            }
+    });
+
+    Ok((filter, flags))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use nix::sys::wait::WaitStatus;
+    use nix::unistd::{getegid, geteuid};
+    use oci_spec::runtime::{LinuxBuilder, LinuxNamespaceBuilder, ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec_with(
+        process: ProcessBuilder,
+        namespaces: Vec<oci_spec::runtime::LinuxNamespace>,
+    ) -> Result<Spec> {
+        Ok(SpecBuilder::default()
+            .process(process.args(vec!["true".to_owned()]).build()?)
+            .linux(LinuxBuilder::default().namespaces(namespaces).build()?)
+            .build()?)
+    }
+
+    /// A process on the host, which joins nothing
+    fn host_process(path: &str, args: &[&str], cwd: Option<&str>) -> Result<FastExec> {
+        Ok(FastExec {
+            path: CString::new(path)?,
+            args: args
+                .iter()
+                .map(|arg| CString::new(*arg))
+                .collect::<std::result::Result<_, _>>()?,
+            env: vec![CString::new("PATH=/usr/bin:/bin")?],
+            cwd: cwd.map(CString::new).transpose()?,
+            pid_namespace: None,
+            namespaces: Vec::new(),
+            cgroup_procs: None,
+            no_new_privileges: None,
+            umask: None,
+            groups: Vec::new(),
+            uid: geteuid().as_raw(),
+            gid: getegid().as_raw(),
+            capabilities: None,
+            preserve_fds: 0,
+            seccomp: None,
+        })
+    }
+
+    #[test]
+    fn test_unsupported() -> Result<()> {
+        let joined = |typ| {
+            LinuxNamespaceBuilder::default()
+                .typ(typ)
+                .path("/proc/1/ns/x")
+                .build()
+        };
+
+        let spec = spec_with(ProcessBuilder::default().terminal(true), Vec::new())?;
+        assert_eq!(unsupported(&spec), Some("terminal"));
+
+        let spec = spec_with(
+            ProcessBuilder::default(),
+            vec![joined(LinuxNamespaceType::User)?],
+        )?;
+        assert_eq!(unsupported(&spec), Some("user namespace"));
+
+        let spec = spec_with(
+            ProcessBuilder::default(),
+            vec![joined(LinuxNamespaceType::Time)?],
+        )?;
+        assert_eq!(unsupported(&spec), Some("time namespace"));
+
+        let spec = spec_with(
+            ProcessBuilder::default(),
+            vec![
+                joined(LinuxNamespaceType::Mount)?,
+                LinuxNamespaceBuilder::default()
+                    .typ(LinuxNamespaceType::Network)
+                    .build()?,
+            ],
+        )?;
+        assert_eq!(unsupported(&spec), Some("new namespace"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_executable() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::create_dir_all(root.path().join("usr/bin"))?;
+        fs::write(root.path().join("usr/bin/app"), "")?;
+
+        let path_var = "/bin:/usr/bin";
+        assert_eq!(
+            resolve_executable(root.path(), "app", path_var),
+            PathBuf::from("/usr/bin/app")
+        );
+        assert_eq!(
+            resolve_executable(root.path(), "./app", path_var),
+            PathBuf::from("./app")
+        );
+        assert_eq!(
+            resolve_executable(root.path(), "missing", path_var),
+            PathBuf::from("missing")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_home() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::create_dir_all(root.path().join("etc"))?;
+        fs::write(
+            root.path().join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/sh\napp:x:1000:1000::/home/app:/bin/sh\n",
+        )?;
+
+        assert_eq!(user_home(root.path(), 0).as_deref(), Some("/root"));
+        assert_eq!(user_home(root.path(), 1000).as_deref(), Some("/home/app"));
+        assert_eq!(user_home(root.path(), 1001), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn() -> Result<()> {
+        let process = host_process("/bin/sh", &["sh", "-c", "exit 3"], Some("/"))?;
+        let pid = process.spawn(false)?;
+        assert_eq!(waitpid(pid, None)?, WaitStatus::Exited(pid, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_exec_failure() -> Result<()> {
+        let process = host_process("/some/non/existent/path", &["path"], None)?;
+        assert!(matches!(
+            process.spawn(false),
+            Err(FastExecError::Exec { path, errno: Errno::ENOENT })
+                if path == Path::new("/some/non/existent/path")
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_setup_failure() -> Result<()> {
+        let process = host_process("/bin/sh", &["sh"], Some("/some/non/existent/path"))?;
+        assert!(matches!(
+            process.spawn(false),
+            Err(FastExecError::Setup {
+                step: "change the working directory",
+                errno: Errno::ENOENT,
+            })
+        ));
+
+        Ok(())
+    }
+}
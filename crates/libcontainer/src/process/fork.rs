@@ -1,5 +1,6 @@
 use std::ffi::c_int;
 use std::num::NonZeroUsize;
+use std::os::fd::{FromRawFd, OwnedFd};
 
 use libc::SIGCHLD;
 use nix::sys::{mman, resource};
@@ -55,6 +56,19 @@ pub fn container_clone(cb: CloneCb) -> Result<Pid, CloneError> {
     clone_internal(cb, 0, Some(SIGCHLD as u64))
 }
 
+/// Opens a pidfd of the process `pid`. Unlike the pid, the pidfd keeps
+/// referring to the process after it exited, so it can be polled for the exit
+/// and waited on with `waitid(P_PIDFD)` without racing a reuse of the pid. It
+/// only refers to the right process if the process can't have been reaped
+/// before the call.
+pub fn pidfd_open(pid: Pid) -> nix::Result<OwnedFd> {
+    // SAFETY: pidfd_open takes no pointers.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    let fd = nix::errno::Errno::result(fd)?;
+    // SAFETY: pidfd_open returned a new fd owned by nothing else.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as c_int) })
+}
+
 // An internal wrapper to manage the clone3 vs clone fallback logic.
 fn clone_internal(
     mut cb: CloneCb,
@@ -238,8 +252,10 @@ fn clone(cb: CloneCb, flags: u64, exit_signal: Option<u64>) -> Result<Pid, Clone
 
 #[cfg(test)]
 mod test {
+    use std::os::fd::{AsFd, AsRawFd, RawFd};
+
     use anyhow::{bail, Context, Result};
-    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::sys::wait::{waitid, waitpid, Id, WaitPidFlag, WaitStatus};
     use nix::unistd;
 
     use super::*;
@@ -322,6 +338,40 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_sibling_pidfd_wait() -> Result<()> {
+        // The embedder forks the caller of libcontainer, which creates the
+        // sibling and hands over its pidfd. The sibling is a child of the
+        // embedder, so only the embedder may reap it, through the pidfd.
+        let (sender, receiver) = &mut channel::<i32>()?;
+
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                let (sibling_pid, fds) = receiver.recv_with_fds::<[RawFd; 1]>()?;
+                receiver.close()?;
+                let fds = fds.context("no pidfd received")?;
+                // SAFETY: the fd was just received and is owned by nothing else.
+                let pidfd = unsafe { OwnedFd::from_raw_fd(fds[0]) };
+                assert_eq!(waitpid(child, None)?, WaitStatus::Exited(child, 0));
+
+                let status = waitid(Id::PIDFd(pidfd.as_fd()), WaitPidFlag::WEXITED)?;
+                assert_eq!(status, WaitStatus::Exited(Pid::from_raw(sibling_pid), 42));
+            }
+            unistd::ForkResult::Child => {
+                let pid = container_clone_sibling(Box::new(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    42
+                }))?;
+                let pidfd = pidfd_open(pid)?;
+                sender.send_fds(pid.as_raw(), &[pidfd.as_raw_fd()])?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
     // This test depends on libseccomp to work.
     #[cfg(feature = "libseccomp")]
     #[test]
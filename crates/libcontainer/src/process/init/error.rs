@@ -1,3 +1,5 @@
+use oci_spec::runtime::LinuxNamespaceType;
+
 use crate::namespaces::NamespaceError;
 use crate::process::channel;
 #[cfg(feature = "libseccomp")]
@@ -22,6 +24,8 @@ pub enum InitProcessError {
     SetDomainname(#[source] SyscallError),
     #[error("failed to reopen /dev/null")]
     ReopenDevNull(#[source] std::io::Error),
+    #[error("failed to reset loginuid")]
+    ResetLoginuid(#[source] std::io::Error),
     #[error("failed to unix syscall")]
     NixOther(#[source] nix::Error),
     #[error(transparent)]
@@ -57,6 +61,14 @@ pub enum InitProcessError {
     WorkloadValidation(#[from] ExecutorValidationError),
     #[error(transparent)]
     WorkloadSetEnvs(#[from] ExecutorSetEnvsError),
+    #[error(transparent)]
+    CoreSched(#[from] crate::core_sched::CoreSchedError),
+    #[error(transparent)]
+    FaultInjection(#[from] crate::fault_injection::FaultInjectionError),
+    #[error("failed to hand off sockets")]
+    SocketHandoff(#[from] crate::socket_handoff::SocketHandoffError),
+    #[error(transparent)]
+    User(#[from] crate::user::UserError),
     #[error("invalid io priority class: {0}")]
     IoPriorityClass(String),
     #[error("call exec sched_setattr error: {0}")]
@@ -68,3 +80,23 @@ pub enum InitProcessError {
     #[error("missing process section in spec")]
     NoProcess,
 }
+
+impl InitProcessError {
+    /// Returns the path and errno if the workload failed to be executed.
+    pub fn exec_failure(&self) -> Option<(&std::path::Path, nix::errno::Errno)> {
+        match self {
+            InitProcessError::Workload(workload::ExecutorError::ExecFailed { path, errno }) => {
+                Some((path, *errno))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the type and errno if a namespace failed to be created.
+    pub fn namespace_failure(&self) -> Option<(LinuxNamespaceType, nix::errno::Errno)> {
+        match self {
+            InitProcessError::Namespaces(err) => err.create_failure(),
+            _ => None,
+        }
+    }
+}
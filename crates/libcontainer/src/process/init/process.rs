@@ -1,6 +1,8 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use std::{env, fs, mem};
 
 use nc;
@@ -16,16 +18,26 @@ use oci_spec::runtime::{
 use super::context::InitContext;
 use super::error::InitProcessError;
 use super::Result;
+use crate::container::init_builder::HostnamePolicy;
+use crate::core_sched::CoreSched;
 use crate::error::MissingSpecError;
+use crate::fault_injection::FaultPoint;
+use crate::hooks::HookStage;
 use crate::namespaces::Namespaces;
 use crate::process::args::{ContainerArgs, ContainerType};
 use crate::process::channel;
-use crate::rootfs::RootFS;
+use crate::process::message::Phase;
+use crate::rootfs::{MountOrder, RootFS};
 #[cfg(feature = "libseccomp")]
 use crate::seccomp;
 use crate::syscall::{Syscall, SyscallError};
 use crate::user_ns::UserNamespaceConfig;
-use crate::{apparmor, capabilities, hooks, tty, utils};
+use crate::workload::{ARGV0_ANNOTATION, DEFAULT_PATH_ANNOTATION};
+use crate::{apparmor, capabilities, core_sched, hooks, socket_handoff, tty, user, utils};
+
+const LOGINUID_PATH: &str = "/proc/self/loginuid";
+/// Value of the loginuid when it is not set, `(uid_t)-1`
+const LOGINUID_UNSET: &str = "4294967295";
 
 // Some variables are unused in the case where libseccomp feature is not enabled.
 #[allow(unused_variables)]
@@ -35,22 +47,39 @@ pub fn container_init_process(
     init_receiver: &mut channel::InitReceiver,
 ) -> Result<()> {
     let mut ctx = InitContext::try_from(args)?;
-
-    setsid().map_err(|err| {
-        tracing::error!(?err, "failed to setsid to create a session");
-        InitProcessError::NixOther(err)
-    })?;
+    // The deadline is taken on the clock of the init process, which differs
+    // from the one of the main process in a time namespace.
+    let create_deadline = args
+        .create_timeout_left
+        .map(|timeout_left| Instant::now() + timeout_left);
+
+    // A new session is a new scheduler autogroup, too.
+    if args.new_session {
+        setsid().map_err(|err| {
+            tracing::error!(?err, "failed to setsid to create a session");
+            InitProcessError::NixOther(err)
+        })?;
+    }
 
     set_io_priority(ctx.syscall.as_ref(), ctx.process.io_priority())?;
 
     setup_scheduler(ctx.process.scheduler())?;
 
-    // set up tty if specified
-    if let Some(csocketfd) = args.console_socket {
-        tty::setup_console(csocketfd).map_err(|err| {
-            tracing::error!(?err, "failed to set up tty");
-            InitProcessError::Tty(err)
-        })?;
+    // The cookie is inherited by all processes the container process starts.
+    // A shared cookie is inherited from the intermediate process already.
+    if let Some(core_sched @ CoreSched::Create) = args.core_sched {
+        core_sched::apply(ctx.syscall.as_ref(), core_sched)?;
+    }
+
+    // set up tty if specified, the guard hangs it up if the init process
+    // fails before the workload replaces it
+    let _console = if let Some(csocketfd) = args.console_socket {
+        Some(
+            tty::setup_console(csocketfd, args.console_socket_timeout).map_err(|err| {
+                tracing::error!(?err, "failed to set up tty");
+                InitProcessError::Tty(err)
+            })?,
+        )
     } else {
         if let Some(stdin) = args.stdin {
             dup2(stdin, 0).map_err(InitProcessError::NixOther)?;
@@ -64,45 +93,92 @@ pub fn container_init_process(
             dup2(stderr, 2).map_err(InitProcessError::NixOther)?;
             close(stderr).map_err(InitProcessError::NixOther)?;
         }
-    }
+        None
+    };
 
-    apply_rest_namespaces(&ctx.ns, ctx.spec, ctx.syscall.as_ref())?;
+    apply_rest_namespaces(
+        &ctx.ns,
+        ctx.spec,
+        ctx.syscall.as_ref(),
+        args.hostname_policy,
+        args.hostname_without_uts,
+    )?;
 
     if let Some(true) = ctx.process.no_new_privileges() {
         let _ = prctl::set_no_new_privileges(true);
     }
 
+    if args.reset_loginuid {
+        let in_user_ns = ctx.ns.get(LinuxNamespaceType::User)?.is_some()
+            || utils::is_in_new_userns().map_err(InitProcessError::Io)?;
+        reset_loginuid(Path::new(LOGINUID_PATH), in_user_ns)?;
+    }
+
     if matches!(args.container_type, ContainerType::InitContainer) {
+        // The IPC sysctls only need the IPC namespace, which the process is
+        // in by now. Applied before the rootfs setup, its mounts already see
+        // the limits of the container.
+        let (ipc_sysctls, other_sysctls) = ctx
+            .linux
+            .sysctl()
+            .as_ref()
+            .map(crate::sysctl::partition)
+            .unwrap_or_default();
+        sysctl(&ipc_sysctls)?;
+
         // create_container hook needs to be called after the namespace setup, but
         // before pivot_root is called. This runs in the container namespaces.
         if let Some(hooks) = ctx.hooks {
-            hooks::run_hooks(hooks.create_container().as_ref(), ctx.container, None).map_err(
-                |err| {
-                    tracing::error!(?err, "failed to run create container hooks");
-                    InitProcessError::Hooks(err)
-                },
-            )?;
+            hooks::run_hooks_with_deadline(
+                HookStage::CreateContainer,
+                hooks.create_container().as_ref(),
+                ctx.container,
+                None,
+                create_deadline,
+            )
+            .map_err(|err| {
+                tracing::error!(?err, "failed to run create container hooks");
+                InitProcessError::Hooks(err)
+            })?;
         }
         let in_user_ns = utils::is_in_new_userns().map_err(InitProcessError::Io)?;
         let bind_service = ctx.ns.get(LinuxNamespaceType::User)?.is_some() || in_user_ns;
-        let rootfs = RootFS::new();
-        rootfs
-            .prepare_rootfs(
-                ctx.spec,
-                ctx.rootfs,
-                bind_service,
-                ctx.ns.get(LinuxNamespaceType::Cgroup)?.is_some(),
-            )
+        let rootfs_prepare_start = Instant::now();
+        let rootfs = RootFS::new()
+            .with_fix_mount_target_type(args.fix_mount_target_type)
+            .with_mtab_symlink(args.mtab_symlink)
+            .with_ensure_default_devices(args.ensure_default_devices)
+            .with_write_limit(args.rootfs_write_limit)
+            .with_max_size(args.rootfs_max_size);
+        prepare_and_enter_rootfs(
+            &rootfs,
+            ctx.syscall.as_ref(),
+            &ctx.ns,
+            ctx.spec,
+            ctx.rootfs,
+            args.no_pivot,
+            bind_service,
+            args.cgroup_mount_readonly,
+            args.mount_order,
+        )?;
+        main_sender
+            .phase_timing(Phase::RootfsPrepare, rootfs_prepare_start.elapsed())
             .map_err(|err| {
-                tracing::error!(?err, "failed to prepare rootfs");
-                InitProcessError::RootFS(err)
+                tracing::error!(?err, "failed to report rootfs prepare timing");
+                InitProcessError::Channel(err)
             })?;
-
-        // Entering into the rootfs jail. If mount namespace is specified, then
-        // we use pivot_root, but if we are on the host mount namespace, we will
-        // use simple chroot. Scary things will happen if you try to pivot_root
-        // in the host mount namespace...
-        do_pivot_root(ctx.syscall.as_ref(), &ctx.ns, args.no_pivot, ctx.rootfs)?;
+        main_sender
+            .rootfs_written(rootfs.written())
+            .map_err(|err| {
+                tracing::error!(?err, "failed to report rootfs writes");
+                InitProcessError::Channel(err)
+            })?;
+        if let Some(fs_type) = rootfs.fs_type() {
+            main_sender.rootfs_fs_type(fs_type).map_err(|err| {
+                tracing::error!(?err, "failed to report rootfs filesystem type");
+                InitProcessError::Channel(err)
+            })?;
+        }
 
         // As we have changed the root mount, from here on
         // logs are no longer visible in journalctl
@@ -120,9 +196,7 @@ pub fn container_init_process(
             err
         })?;
 
-        if let Some(kernel_params) = ctx.linux.sysctl() {
-            sysctl(kernel_params)?;
-        }
+        sysctl(&other_sysctls)?;
     }
 
     if let Some(profile) = ctx.process.apparmor_profile() {
@@ -168,6 +242,15 @@ pub fn container_init_process(
         }
     }
 
+    // The sysctls of the spec were applied with the rootfs setup, so /proc/sys
+    // can be made read-only now.
+    if args.proc_sys_readonly {
+        readonly_path(Path::new("/proc/sys"), ctx.syscall.as_ref()).map_err(|err| {
+            tracing::error!(?err, "failed to remount /proc/sys read-only");
+            err
+        })?;
+    }
+
     if let Some(paths) = ctx.linux.masked_paths() {
         // mount masked path
         for path in paths {
@@ -200,24 +283,44 @@ pub fn container_init_process(
         }
     };
 
-    set_supplementary_gids(
-        ctx.process.user(),
-        &args.user_ns_config,
-        ctx.syscall.as_ref(),
-    )
-    .map_err(|err| {
-        tracing::error!(?err, "failed to set supplementary gids");
-        err
-    })?;
+    // The username is looked up once the mounts of the container are set up,
+    // so a passwd file mounted into the container is used.
+    let mut spec_user = ctx.process.user().clone();
+    if matches!(args.container_type, ContainerType::InitContainer) {
+        let resolved = user::resolve_user(&spec_user, Path::new("/"), args.resolve_username)
+            .map_err(|err| {
+                tracing::error!(?err, "failed to resolve the user of the container");
+                err
+            })?;
+        resolved.apply(&mut spec_user);
+        main_sender.resolved_user(resolved).map_err(|err| {
+            tracing::error!(?err, "failed to report the resolved user");
+            InitProcessError::Channel(err)
+        })?;
+    }
+
+    // A user forced by the builder takes precedence over the spec user.
+    let (uid, gid) = match args.run_as_user {
+        Some((uid, gid)) => {
+            set_forced_user_gids(gid, ctx.syscall.as_ref()).map_err(|err| {
+                tracing::error!(?err, "failed to set supplementary gids of forced user");
+                err
+            })?;
+            (uid, gid)
+        }
+        None => {
+            set_supplementary_gids(&spec_user, &args.user_ns_config, ctx.syscall.as_ref())
+                .map_err(|err| {
+                    tracing::error!(?err, "failed to set supplementary gids");
+                    err
+                })?;
+            (spec_user.uid(), spec_user.gid())
+        }
+    };
 
     ctx.syscall
-        .set_id(
-            Uid::from_raw(ctx.process.user().uid()),
-            Gid::from_raw(ctx.process.user().gid()),
-        )
+        .set_id(Uid::from_raw(uid), Gid::from_raw(gid))
         .map_err(|err| {
-            let uid = ctx.process.user().uid();
-            let gid = ctx.process.user().gid();
             tracing::error!(?err, ?uid, ?gid, "failed to set uid and gid");
             InitProcessError::SyscallOther(err)
         })?;
@@ -346,15 +449,23 @@ pub fn container_init_process(
 
     // add HOME into envs if not exists
     if !ctx.envs.contains_key("HOME") {
-        if let Some(dir_home) = utils::get_user_home(ctx.process.user().uid()) {
+        if let Some(dir_home) = utils::get_user_home(uid) {
             ctx.envs
                 .insert("HOME".to_owned(), dir_home.to_string_lossy().to_string());
         }
     }
 
-    args.executor.validate(ctx.spec)?;
+    let spec = apply_default_path(
+        ctx.spec,
+        &mut ctx.envs,
+        args.default_path.as_deref(),
+        args.inject_default_path,
+    );
+    let spec = apply_argv0_override(spec, args.argv0_override.as_deref());
+    args.executor.validate(&spec)?;
     args.executor.setup_envs(ctx.envs)?;
 
+    args.fault_injection.inject(FaultPoint::PreNotify)?;
     // Notify main process that the init process is ready to execute the
     // payload.  Note, because we are already inside the pid namespace, the pid
     // outside the pid namespace should be recorded by the intermediate process
@@ -387,12 +498,16 @@ pub fn container_init_process(
     // before pivot_root is called. This runs in the container namespaces.
     if matches!(args.container_type, ContainerType::InitContainer) {
         if let Some(hooks) = ctx.hooks {
-            hooks::run_hooks(hooks.start_container().as_ref(), ctx.container, None).map_err(
-                |err| {
-                    tracing::error!(?err, "failed to run start container hooks");
-                    err
-                },
-            )?;
+            hooks::run_hooks(
+                HookStage::StartContainer,
+                hooks.start_container().as_ref(),
+                ctx.container,
+                None,
+            )
+            .map_err(|err| {
+                tracing::error!(?err, "failed to run start container hooks");
+                err
+            })?;
         }
     }
 
@@ -401,7 +516,17 @@ pub fn container_init_process(
         Err(MissingSpecError::Args)?;
     }
 
-    args.executor.exec(ctx.spec).map_err(|err| {
+    // The handed off sockets replace whatever is open at their target fds,
+    // nothing but the exec is left to use those.
+    socket_handoff::install_fds(&args.socket_fds).map_err(|err| {
+        tracing::error!(?err, "failed to pass sockets to the container process");
+        err
+    })?;
+
+    #[cfg(feature = "syscall_trace")]
+    crate::syscall::trace::record_exec(ctx.syscall.as_ref(), ctx.process.args().as_ref());
+
+    args.executor.exec(&spec).map_err(|err| {
         tracing::error!(?err, "failed to execute payload");
         err
     })?;
@@ -412,6 +537,68 @@ pub fn container_init_process(
     unreachable!("the executor should not return if it is successful.");
 }
 
+/// Hands the default PATH to the executor if the env of the process has no
+/// PATH, in the [`DEFAULT_PATH_ANNOTATION`] annotation of the spec. It is
+/// added to the env only if `inject` is set. The annotation is only honored
+/// if the builder opted in, one set by the bundle is dropped.
+fn apply_default_path<'a>(
+    spec: &'a Spec,
+    envs: &mut HashMap<String, String>,
+    default_path: Option<&str>,
+    inject: bool,
+) -> Cow<'a, Spec> {
+    let default_path = match default_path {
+        Some(default_path) if !envs.contains_key("PATH") => default_path,
+        _ => return without_annotation(Cow::Borrowed(spec), DEFAULT_PATH_ANNOTATION),
+    };
+
+    tracing::debug!(default_path, inject, "process env has no PATH");
+    if inject {
+        envs.insert("PATH".to_owned(), default_path.to_owned());
+    }
+    let mut spec = spec.clone();
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    annotations.insert(DEFAULT_PATH_ANNOTATION.to_owned(), default_path.to_owned());
+    spec.set_annotations(Some(annotations));
+    Cow::Owned(spec)
+}
+
+/// Hands the argv[0] the executable is run with to the executor, in the
+/// [`ARGV0_ANNOTATION`] annotation of the spec. The annotation is only
+/// honored if the builder opted in, one set by the bundle is dropped.
+fn apply_argv0_override<'a>(spec: Cow<'a, Spec>, argv0: Option<&str>) -> Cow<'a, Spec> {
+    let argv0 = match argv0 {
+        Some(argv0) => argv0,
+        None => return without_annotation(spec, ARGV0_ANNOTATION),
+    };
+
+    tracing::debug!(argv0, "process runs with an overridden argv[0]");
+    let mut spec = spec.into_owned();
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    annotations.insert(ARGV0_ANNOTATION.to_owned(), argv0.to_owned());
+    spec.set_annotations(Some(annotations));
+    Cow::Owned(spec)
+}
+
+/// Drops an annotation of the spec that only the runtime may hand to the
+/// executor
+fn without_annotation<'a>(spec: Cow<'a, Spec>, annotation: &str) -> Cow<'a, Spec> {
+    let present = spec
+        .annotations()
+        .as_ref()
+        .map_or(false, |annotations| annotations.contains_key(annotation));
+    if !present {
+        return spec;
+    }
+
+    tracing::warn!(annotation, "ignoring annotation reserved for the runtime");
+    let mut spec = spec.into_owned();
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    annotations.remove(annotation);
+    spec.set_annotations(Some(annotations));
+    Cow::Owned(spec)
+}
+
 fn sysctl(kernel_params: &HashMap<String, String>) -> Result<()> {
     let sys = PathBuf::from("/proc/sys");
     for (kernel_param, value) in kernel_params {
@@ -529,6 +716,8 @@ fn apply_rest_namespaces(
     namespaces: &Namespaces,
     spec: &Spec,
     syscall: &dyn Syscall,
+    hostname_policy: HostnamePolicy,
+    hostname_without_uts: bool,
 ) -> Result<()> {
     namespaces
         .apply_namespaces(|ns_type| -> bool {
@@ -542,27 +731,67 @@ fn apply_rest_namespaces(
             InitProcessError::Namespaces(err)
         })?;
 
-    // Only set the host name if entering into a new uts namespace
-    if let Some(uts_namespace) = namespaces.get(LinuxNamespaceType::Uts)? {
-        if uts_namespace.path().is_none() {
-            if let Some(hostname) = spec.hostname() {
-                syscall.set_hostname(hostname).map_err(|err| {
-                    tracing::error!(?err, ?hostname, "failed to set hostname");
-                    InitProcessError::SetHostname(err)
-                })?;
-            }
+    // Before anything runs in a created time namespace, its clocks have to
+    // be offset.
+    let time_offsets = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.time_offsets().as_ref());
+    namespaces.apply_time_offsets(time_offsets)?;
+
+    // Only set the host name if entering into a new uts namespace, unless
+    // overwriting the one of a joined namespace or the one of the runtime was
+    // explicitly requested. The builder already rejected the spec otherwise,
+    // if that's the policy.
+    let set_hostname = match namespaces.get(LinuxNamespaceType::Uts)? {
+        Some(uts_namespace) => {
+            uts_namespace.path().is_none() || hostname_policy == HostnamePolicy::Apply
+        }
+        None => hostname_without_uts,
+    };
+    if set_hostname {
+        if let Some(hostname) = spec.hostname() {
+            syscall.set_hostname(hostname).map_err(|err| {
+                tracing::error!(?err, ?hostname, "failed to set hostname");
+                InitProcessError::SetHostname(err)
+            })?;
+        }
 
-            if let Some(domainname) = spec.domainname() {
-                syscall.set_domainname(domainname).map_err(|err| {
-                    tracing::error!(?err, ?domainname, "failed to set domainname");
-                    InitProcessError::SetDomainname(err)
-                })?;
-            }
+        if let Some(domainname) = spec.domainname() {
+            syscall.set_domainname(domainname).map_err(|err| {
+                tracing::error!(?err, ?domainname, "failed to set domainname");
+                InitProcessError::SetDomainname(err)
+            })?;
         }
     }
     Ok(())
 }
 
+/// Resets the audit login uid of the process, which is inherited by the
+/// container payload. Writing it requires `CAP_AUDIT_CONTROL` in the initial
+/// user namespace, so a failure is only logged for a process in a user
+/// namespace.
+fn reset_loginuid(loginuid_path: &Path, in_user_ns: bool) -> Result<()> {
+    match fs::write(loginuid_path, LOGINUID_UNSET) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::debug!("kernel has no audit support, not resetting loginuid");
+            Ok(())
+        }
+        Err(err) if in_user_ns && err.kind() == std::io::ErrorKind::PermissionDenied => {
+            tracing::warn!(
+                ?err,
+                "failed to reset loginuid in a user namespace, skipping"
+            );
+            Ok(())
+        }
+        Err(err) => {
+            tracing::error!(?err, "failed to reset loginuid");
+            Err(InitProcessError::ResetLoginuid(err))
+        }
+    }
+}
+
 fn reopen_dev_null() -> Result<()> {
     // At this point we should be inside of the container and now
     // we can re-open /dev/null if it is in use to the /dev/null
@@ -643,6 +872,54 @@ fn move_root(syscall: &dyn Syscall, rootfs: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Prepares the rootfs and enters it. Depending on the mount order, the mounts
+/// of the spec are applied to the rootfs before entering it, or relative to
+/// the new root afterwards.
+#[allow(clippy::too_many_arguments)]
+fn prepare_and_enter_rootfs(
+    rootfs: &RootFS,
+    syscall: &dyn Syscall,
+    namespaces: &Namespaces,
+    spec: &Spec,
+    rootfs_path: &Path,
+    no_pivot: bool,
+    bind_devices: bool,
+    cgroup_readonly: bool,
+    mount_order: MountOrder,
+) -> Result<()> {
+    let cgroup_ns = namespaces.get(LinuxNamespaceType::Cgroup)?.is_some();
+    rootfs
+        .prepare_rootfs(
+            spec,
+            rootfs_path,
+            bind_devices,
+            cgroup_ns,
+            cgroup_readonly,
+            mount_order,
+        )
+        .map_err(|err| {
+            tracing::error!(?err, "failed to prepare rootfs");
+            InitProcessError::RootFS(err)
+        })?;
+
+    // Entering into the rootfs jail. If mount namespace is specified, then
+    // we use pivot_root, but if we are on the host mount namespace, we will
+    // use simple chroot. Scary things will happen if you try to pivot_root
+    // in the host mount namespace...
+    do_pivot_root(syscall, namespaces, no_pivot, rootfs_path)?;
+
+    if mount_order == MountOrder::AfterPivot {
+        rootfs
+            .mount_spec_mounts_after_pivot(spec, cgroup_ns, cgroup_readonly)
+            .map_err(|err| {
+                tracing::error!(?err, "failed to apply spec mounts after pivot root");
+                InitProcessError::RootFS(err)
+            })?;
+    }
+
+    Ok(())
+}
+
 fn do_pivot_root(
     syscall: &dyn Syscall,
     namespaces: &Namespaces,
@@ -718,7 +995,9 @@ fn set_supplementary_gids(
             .map(|gid| Gid::from_raw(*gid))
             .collect();
 
-        match user_ns_config {
+        // A joined user namespace was set up by someone else, the groups are
+        // set like without a user namespace.
+        match user_ns_config.as_ref().filter(|c| !c.joins_existing()) {
             Some(r) if r.privileged => {
                 syscall.set_groups(&gids).map_err(|err| {
                     tracing::error!(?err, ?gids, "failed to set privileged supplementary gids");
@@ -741,6 +1020,24 @@ fn set_supplementary_gids(
     Ok(())
 }
 
+// The supplementary groups of the spec belong to the spec user, so a user
+// forced by the builder doesn't get them. It must not keep the groups of the
+// runtime either, so they are replaced by its own gid. If setgroups is denied
+// in the user namespace, the groups can't be changed, and there are none but
+// the overflow gid to keep anyway.
+fn set_forced_user_gids(gid: u32, syscall: &dyn Syscall) -> Result<()> {
+    let setgroups = fs::read_to_string("/proc/self/setgroups").unwrap_or_default();
+    if setgroups.trim() == "deny" {
+        return Ok(());
+    }
+
+    let gids = [Gid::from_raw(gid)];
+    syscall.set_groups(&gids).map_err(|err| {
+        tracing::error!(?err, ?gids, "failed to set supplementary gids");
+        InitProcessError::SyscallOther(err)
+    })
+}
+
 /// set_io_priority set io priority
 fn set_io_priority(syscall: &dyn Syscall, io_priority_op: &Option<LinuxIOPriority>) -> Result<()> {
     if let Some(io_priority) = io_priority_op {
@@ -890,13 +1187,79 @@ mod tests {
     use anyhow::Result;
     #[cfg(feature = "libseccomp")]
     use nix::unistd;
-    use oci_spec::runtime::{LinuxNamespaceBuilder, SpecBuilder, UserBuilder};
+    use oci_spec::runtime::{LinuxNamespaceBuilder, MountBuilder, SpecBuilder, UserBuilder};
     #[cfg(feature = "libseccomp")]
     use serial_test::serial;
 
     use super::*;
     use crate::syscall::syscall::create_syscall;
-    use crate::syscall::test::{ArgName, IoPriorityArgs, MountArgs, TestHelperSyscall};
+    use crate::syscall::test::{
+        take_call_order, ArgName, IoPriorityArgs, MountArgs, TestHelperSyscall,
+    };
+
+    #[test]
+    fn test_apply_default_path() {
+        let spec = Spec::default();
+        let mut envs = HashMap::from([("TERM".to_owned(), "xterm".to_owned())]);
+
+        // resolution only, the env is left as is
+        let got = apply_default_path(&spec, &mut envs, Some("/usr/bin:/bin"), false);
+        assert_eq!(crate::workload::default_path(&got), Some("/usr/bin:/bin"));
+        assert!(!envs.contains_key("PATH"));
+
+        let got = apply_default_path(&spec, &mut envs, Some("/usr/bin:/bin"), true);
+        assert_eq!(crate::workload::default_path(&got), Some("/usr/bin:/bin"));
+        assert_eq!(envs.get("PATH").map(String::as_str), Some("/usr/bin:/bin"));
+
+        // the PATH of the env wins
+        envs.insert("PATH".to_owned(), "/opt/bin".to_owned());
+        let got = apply_default_path(&spec, &mut envs, Some("/usr/bin:/bin"), true);
+        assert!(matches!(got, Cow::Borrowed(_)));
+        assert_eq!(envs.get("PATH").map(String::as_str), Some("/opt/bin"));
+        assert!(matches!(
+            apply_default_path(&spec, &mut HashMap::new(), None, true),
+            Cow::Borrowed(_)
+        ));
+
+        // the annotation of a bundle is ignored without the opt in
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(HashMap::from([(
+            DEFAULT_PATH_ANNOTATION.to_owned(),
+            "/tmp".to_owned(),
+        )])));
+        let got = apply_default_path(&spec, &mut HashMap::new(), None, false);
+        assert_eq!(crate::workload::default_path(&got), None);
+        let got = apply_default_path(&spec, &mut envs, Some("/usr/bin:/bin"), false);
+        assert_eq!(crate::workload::default_path(&got), None);
+    }
+
+    #[test]
+    fn test_apply_argv0_override() {
+        let spec = Spec::default();
+        let got = apply_argv0_override(Cow::Borrowed(&spec), None);
+        assert!(matches!(got, Cow::Borrowed(_)));
+        assert_eq!(crate::workload::argv0_override(&got), None);
+
+        let got = apply_argv0_override(Cow::Borrowed(&spec), Some("ls"));
+        assert_eq!(crate::workload::argv0_override(&got), Some("ls"));
+
+        // the annotation of a bundle is ignored without the opt in
+        let got = apply_argv0_override(got, None);
+        assert_eq!(crate::workload::argv0_override(&got), None);
+    }
+
+    #[test]
+    fn test_reset_loginuid() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let loginuid = tmp.path().join("loginuid");
+        fs::write(&loginuid, "1000")?;
+        reset_loginuid(&loginuid, false)?;
+        assert_eq!(fs::read_to_string(&loginuid)?, LOGINUID_UNSET);
+
+        // Kernels without audit support have no loginuid file
+        reset_loginuid(&tmp.path().join("missing").join("loginuid"), false)?;
+        Ok(())
+    }
 
     #[test]
     fn test_readonly_path() -> Result<()> {
@@ -949,7 +1312,13 @@ mod tests {
         ];
         let namespaces = Namespaces::try_from(Some(&linux_spaces))?;
 
-        apply_rest_namespaces(&namespaces, &spec, syscall.as_ref())?;
+        apply_rest_namespaces(
+            &namespaces,
+            &spec,
+            syscall.as_ref(),
+            HostnamePolicy::Skip,
+            false,
+        )?;
 
         let got_hostnames = syscall
             .as_ref()
@@ -970,6 +1339,127 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_apply_rest_namespaces_joined_uts() -> Result<()> {
+        let spec = SpecBuilder::default().build()?;
+        let linux_spaces = vec![LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Uts)
+            .path("/proc/self/ns/uts")
+            .build()?];
+
+        for (policy, want) in [
+            (HostnamePolicy::Skip, vec![]),
+            (HostnamePolicy::Apply, vec!["youki".to_string()]),
+        ] {
+            let syscall = create_syscall();
+            let namespaces = Namespaces::try_from(Some(&linux_spaces))?;
+            apply_rest_namespaces(&namespaces, &spec, syscall.as_ref(), policy, false)?;
+
+            let got_hostnames = syscall
+                .as_ref()
+                .as_any()
+                .downcast_ref::<TestHelperSyscall>()
+                .unwrap()
+                .get_hostname_args();
+            assert_eq!(want, got_hostnames, "policy {policy:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_rest_namespaces_without_uts() -> Result<()> {
+        let spec = SpecBuilder::default().build()?;
+        let linux_spaces = vec![LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Pid)
+            .build()?];
+
+        for (allow, want) in [(false, vec![]), (true, vec!["youki".to_string()])] {
+            let syscall = create_syscall();
+            let namespaces = Namespaces::try_from(Some(&linux_spaces))?;
+            apply_rest_namespaces(
+                &namespaces,
+                &spec,
+                syscall.as_ref(),
+                HostnamePolicy::Skip,
+                allow,
+            )?;
+
+            let got_hostnames = syscall
+                .as_ref()
+                .as_any()
+                .downcast_ref::<TestHelperSyscall>()
+                .unwrap()
+                .get_hostname_args();
+            assert_eq!(want, got_hostnames, "allow {allow}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_and_enter_rootfs_mount_order() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .mounts(vec![
+                MountBuilder::default()
+                    .destination("/dev")
+                    .typ("tmpfs")
+                    .source("tmpfs")
+                    .build()?,
+                MountBuilder::default()
+                    .destination("/tmp")
+                    .typ("tmpfs")
+                    .source("tmpfs")
+                    .build()?,
+            ])
+            .build()?;
+        let linux_spaces = vec![LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Mount)
+            .build()?];
+        let namespaces = Namespaces::try_from(Some(&linux_spaces))?;
+
+        for mount_order in [MountOrder::BeforePivot, MountOrder::AfterPivot] {
+            let rootfs = tempfile::tempdir()?;
+            let syscall = create_syscall();
+            take_call_order();
+
+            prepare_and_enter_rootfs(
+                &RootFS::new(),
+                syscall.as_ref(),
+                &namespaces,
+                &spec,
+                rootfs.path(),
+                false,
+                false,
+                false,
+                mount_order,
+            )?;
+
+            let calls = take_call_order();
+            let pivot_root = calls
+                .iter()
+                .position(|call| *call == ArgName::PivotRoot)
+                .expect("pivot root was called");
+            let last_mount = calls
+                .iter()
+                .rposition(|call| *call == ArgName::Mount)
+                .expect("mount was called");
+            match mount_order {
+                MountOrder::BeforePivot => assert!(last_mount < pivot_root, "{calls:?}"),
+                MountOrder::AfterPivot => assert!(last_mount > pivot_root, "{calls:?}"),
+            }
+            // The devices are created in /dev, which is mounted before
+            // pivot_root in either order.
+            let mounts_after_pivot = calls[pivot_root..]
+                .iter()
+                .filter(|call| **call == ArgName::Mount)
+                .count();
+            match mount_order {
+                MountOrder::BeforePivot => assert_eq!(mounts_after_pivot, 0),
+                MountOrder::AfterPivot => assert_eq!(mounts_after_pivot, 1),
+            }
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_set_supplementary_gids() -> Result<()> {
         // gids additional gids is empty case
@@ -1034,6 +1524,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_set_forced_user_gids() -> Result<()> {
+        let syscall = create_syscall();
+        set_forced_user_gids(1000, syscall.as_ref())?;
+        let got = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_groups_args();
+        match fs::read_to_string("/proc/self/setgroups")?.trim() {
+            "deny" => assert!(got.is_empty()),
+            _ => assert_eq!(got, vec![Gid::from_raw(1000)]),
+        }
+        Ok(())
+    }
+
     #[test]
     #[serial]
     #[cfg(feature = "libseccomp")]
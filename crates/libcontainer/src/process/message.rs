@@ -1,10 +1,25 @@
 use core::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 
+use oci_spec::runtime::LinuxNamespaceType;
 use serde::{Deserialize, Serialize};
 
+use crate::rootfs::FsType;
+use crate::user::ResolvedUser;
+
+/// Version of the messages the child processes send to the main process.
+/// Version 1 added the reports of the setup: the phase timings, the rootfs
+/// writes and filesystem and the cgroup location. Version 2 added the report
+/// of the resolved user. A child announces its version with
+/// [`Message::Version`] before any other message, a child that doesn't is
+/// version 0 and sends no reports.
+pub const PROTOCOL_VERSION: u32 = 2;
+
 /// Used as a wrapper for messages to be sent between child and parent processes
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Message {
+    Version(u32),
     IntermediateReady(i32),
     InitReady,
     WriteMapping,
@@ -12,12 +27,44 @@ pub enum Message {
     SeccompNotify,
     SeccompNotifyDone,
     ExecFailed(String),
+    ExecErrno {
+        path: PathBuf,
+        errno: i32,
+    },
+    NamespaceCreateFailed {
+        namespace: LinuxNamespaceType,
+        errno: i32,
+    },
     OtherError(String),
+    PhaseTiming(Phase, Duration),
+    RootfsWritten(u64),
+    RootfsFsType(FsType),
+    CgroupLocation(CgroupLocation),
+    ResolvedUser(ResolvedUser),
+}
+
+/// Setup phases of a create that run in the intermediate or init process and
+/// are timed there, so the duration has to be reported to the main process.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    CgroupApply,
+    RootfsPrepare,
+}
+
+/// Where the cgroup of the container landed, as the cgroup manager in the
+/// intermediate process placed it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct CgroupLocation {
+    /// Absolute path of the cgroup
+    pub path: PathBuf,
+    /// Name of the unit, if systemd manages the cgroup
+    pub systemd_unit: Option<String>,
 }
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Message::Version(version) => write!(f, "Version({})", version),
             Message::IntermediateReady(pid) => write!(f, "IntermediateReady({})", pid),
             Message::InitReady => write!(f, "InitReady"),
             Message::WriteMapping => write!(f, "WriteMapping"),
@@ -25,7 +72,16 @@ impl fmt::Display for Message {
             Message::SeccompNotify => write!(f, "SeccompNotify"),
             Message::SeccompNotifyDone => write!(f, "SeccompNotifyDone"),
             Message::ExecFailed(s) => write!(f, "ExecFailed({})", s),
+            Message::ExecErrno { path, errno } => write!(f, "ExecErrno({:?}, {})", path, errno),
+            Message::NamespaceCreateFailed { namespace, errno } => {
+                write!(f, "NamespaceCreateFailed({:?}, {})", namespace, errno)
+            }
             Message::OtherError(s) => write!(f, "OtherError({})", s),
+            Message::PhaseTiming(phase, d) => write!(f, "PhaseTiming({:?}, {:?})", phase, d),
+            Message::RootfsWritten(bytes) => write!(f, "RootfsWritten({})", bytes),
+            Message::RootfsFsType(fs_type) => write!(f, "RootfsFsType({})", fs_type),
+            Message::CgroupLocation(location) => write!(f, "CgroupLocation({:?})", location),
+            Message::ResolvedUser(user) => write!(f, "ResolvedUser({:?})", user),
         }
     }
 }
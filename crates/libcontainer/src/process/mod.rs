@@ -5,9 +5,13 @@ pub mod args;
 pub mod channel;
 pub mod container_intermediate_process;
 pub mod container_main_process;
-mod fork;
+pub mod exit_waiter;
+pub mod fast_exec;
+pub(crate) mod fork;
 pub mod init;
 pub mod intel_rdt;
-mod message;
+pub(crate) mod message;
+mod no_alloc;
 #[cfg(feature = "libseccomp")]
-mod seccomp_listener;
+pub(crate) mod seccomp_listener;
+pub mod watchdog;
@@ -0,0 +1,513 @@
+//! The code the child of the fast exec path runs between clone and exec
+//!
+//! The child is cloned with `CLONE_VM | CLONE_VFORK`, the same as
+//! `posix_spawn`: it runs on the memory of youki while the cloning thread is
+//! suspended until the child execs or exits, and all other threads of youki
+//! keep running. Everything the child does must therefore be
+//! async-signal-safe:
+//!
+//! - no allocation or deallocation, the heap is shared with youki and its lock
+//!   may be held by another thread;
+//! - no locks, which includes stdio, `tracing` and the environment of the
+//!   process;
+//! - no panics, unwinding would run the destructors of frames of youki;
+//! - no libc wrappers that synchronize the threads of the process, e.g.
+//!   `setuid` or `setgroups` signal all threads of youki, so the raw syscalls
+//!   are used instead.
+//!
+//! The parent prepares everything the child needs as a [`ChildPlan`] of
+//! already converted data, and the child only passes it to syscalls. To keep
+//! this auditable, the module only depends on `core` and `libc`, and must stay
+//! that way. A failure is reported by storing the failed step and errno in the
+//! plan, which the parent reads once the clone returned.
+
+use core::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void, CStr};
+use core::ptr;
+use core::sync::atomic::{AtomicI32, Ordering};
+
+/// Highest signal number plus one
+const NSIG: c_int = 65;
+/// Version of the capability sets of `capset`, with two 32 bit words per set
+const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+/// Size of the buffer the cwd is read into, `PATH_MAX` of linux
+const CWD_BUFFER_SIZE: usize = 4096;
+/// Unused argument of prctl
+const NO_ARG: c_ulong = 0;
+
+/// The step of the child that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Setsid,
+    Cgroup,
+    Namespace,
+    NoNewPrivileges,
+    Chdir,
+    Bounding,
+    Groups,
+    Gid,
+    Uid,
+    KeepCapabilities,
+    CloseFds,
+    Seccomp,
+    Capabilities,
+    Cwd,
+    SignalMask,
+    Exec,
+}
+
+impl Step {
+    const ALL: [Step; 16] = [
+        Step::Setsid,
+        Step::Cgroup,
+        Step::Namespace,
+        Step::NoNewPrivileges,
+        Step::Chdir,
+        Step::Bounding,
+        Step::Groups,
+        Step::Gid,
+        Step::Uid,
+        Step::KeepCapabilities,
+        Step::CloseFds,
+        Step::Seccomp,
+        Step::Capabilities,
+        Step::Cwd,
+        Step::SignalMask,
+        Step::Exec,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Step::Setsid => "create a session",
+            Step::Cgroup => "join the cgroup",
+            Step::Namespace => "join a namespace",
+            Step::NoNewPrivileges => "set no new privileges",
+            Step::Chdir => "change the working directory",
+            Step::Bounding => "drop bounding capabilities",
+            Step::Groups => "set supplementary gids",
+            Step::Gid => "set gid",
+            Step::Uid => "set uid",
+            Step::KeepCapabilities => "keep capabilities",
+            Step::CloseFds => "close extra fds",
+            Step::Seccomp => "load seccomp filter",
+            Step::Capabilities => "set capabilities",
+            Step::Cwd => "verify the working directory",
+            Step::SignalMask => "restore the signal mask",
+            Step::Exec => "execute the process",
+        }
+    }
+
+    fn to_raw(self) -> i32 {
+        // offset by one, as 0 means no failure
+        self as i32 + 1
+    }
+
+    fn from_raw(raw: i32) -> Option<Step> {
+        Self::ALL.iter().copied().find(|step| step.to_raw() == raw)
+    }
+}
+
+/// The failure of the child, written by the child and read by the parent
+/// after the clone returned
+#[derive(Debug, Default)]
+pub struct Failure {
+    step: AtomicI32,
+    errno: AtomicI32,
+}
+
+impl Failure {
+    /// The failed step and its errno, if the child failed
+    pub fn get(&self) -> Option<(Step, i32)> {
+        Step::from_raw(self.step.load(Ordering::Acquire))
+            .map(|step| (step, self.errno.load(Ordering::Acquire)))
+    }
+
+    fn set(&self, step: Step, errno: i32) {
+        self.errno.store(errno, Ordering::Release);
+        self.step.store(step.to_raw(), Ordering::Release);
+    }
+}
+
+/// Capability sets of the process, as bit masks of the capability numbers.
+/// A set that is `None` is not changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChildCapabilities {
+    pub bounding: Option<u64>,
+    pub effective: Option<u64>,
+    pub permitted: Option<u64>,
+    pub inheritable: Option<u64>,
+    pub ambient: Option<u64>,
+    /// The highest capability number the kernel supports
+    pub last_cap: u32,
+}
+
+/// Everything the child needs, prepared by the parent
+pub struct ChildPlan<'a> {
+    /// Path of the executable, resolved by the parent
+    pub path: &'a CStr,
+    /// Null terminated pointers to the arguments
+    pub argv: &'a [*const c_char],
+    /// Null terminated pointers to the environment
+    pub envp: &'a [*const c_char],
+    /// `cgroup.procs` of the cgroup to join
+    pub cgroup_procs: Option<c_int>,
+    /// Namespaces to join, in order
+    pub namespaces: &'a [c_int],
+    pub no_new_privileges: Option<bool>,
+    pub umask: Option<libc::mode_t>,
+    pub cwd: Option<&'a CStr>,
+    /// Supplementary gids, which are kept if empty
+    pub groups: &'a [libc::gid_t],
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    pub capabilities: Option<ChildCapabilities>,
+    /// First fd that is closed on exec
+    pub first_closed_fd: c_int,
+    /// Limit of the fds, to mark them close on exec one by one on kernels
+    /// without `close_range`
+    pub fd_limit: c_int,
+    pub seccomp: Option<&'a libc::sock_fprog>,
+    pub seccomp_flags: c_ulong,
+    /// Signal mask the process is executed with
+    pub sigmask: &'a libc::sigset_t,
+    pub failure: &'a Failure,
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+type Result<T> = core::result::Result<T, (Step, i32)>;
+
+/// Entry point of the child for `clone`, which is passed a pointer to the
+/// [`ChildPlan`]. It only returns if the child failed.
+pub extern "C" fn child_main(plan: *mut c_void) -> c_int {
+    // Safety: the parent passes a plan that outlives the child, as it is
+    // suspended until the child executed or exited.
+    let plan = unsafe { &*(plan as *const ChildPlan) };
+    if let Err((step, errno)) = run(plan) {
+        plan.failure.set(step, errno);
+    }
+    // Safety: _exit doesn't run any handler of youki.
+    unsafe { libc::_exit(127) }
+}
+
+/// prctl with up to two arguments, the unused ones must be 0
+unsafe fn prctl(option: c_int, arg2: c_ulong, arg3: c_ulong) -> c_long {
+    libc::prctl(option, arg2, arg3, NO_ARG, NO_ARG) as c_long
+}
+
+fn errno() -> i32 {
+    // Safety: errno is thread local, the child runs on the thread local
+    // storage of the suspended thread of the parent.
+    unsafe { *libc::__errno_location() }
+}
+
+fn check(ret: c_long, step: Step) -> Result<c_long> {
+    if ret == -1 {
+        Err((step, errno()))
+    } else {
+        Ok(ret)
+    }
+}
+
+fn run(plan: &ChildPlan) -> Result<()> {
+    // Safety: all syscalls below only take data of the plan or the stack of
+    // the child, which stays valid until the exec.
+    unsafe {
+        reset_signal_handlers();
+
+        check(libc::setsid() as c_long, Step::Setsid)?;
+
+        if let Some(fd) = plan.cgroup_procs {
+            // 0 moves the writing process
+            check(
+                libc::write(fd, b"0".as_ptr() as *const c_void, 1) as c_long,
+                Step::Cgroup,
+            )?;
+        }
+
+        for &fd in plan.namespaces {
+            check(libc::setns(fd, 0) as c_long, Step::Namespace)?;
+        }
+
+        if plan.no_new_privileges == Some(true) {
+            check(
+                prctl(libc::PR_SET_NO_NEW_PRIVS, 1, NO_ARG),
+                Step::NoNewPrivileges,
+            )?;
+        }
+
+        if let Some(umask) = plan.umask {
+            libc::umask(umask);
+        }
+
+        // As in the full path, the chdir is retried after the user is set up
+        // if the user running youki can't access the directory
+        let chdir_later = match plan.cwd {
+            Some(cwd) => match libc::chdir(cwd.as_ptr()) {
+                0 => false,
+                _ if errno() == libc::EPERM => true,
+                _ => return Err((Step::Chdir, errno())),
+            },
+            None => false,
+        };
+
+        // Dropping from the bounding set requires CAP_SETPCAP in the effective
+        // set, which a new uid may clear, so this happens before the user is
+        // set up. The bounding set doesn't depend on the user.
+        if let Some(bounding) = plan.capabilities.and_then(|caps| caps.bounding) {
+            drop_bounding(bounding, plan.capabilities.map_or(0, |caps| caps.last_cap))?;
+        }
+
+        set_user(plan)?;
+        close_fds(plan)?;
+
+        // Without no new privileges, seccomp is a privileged operation, so it
+        // is loaded before the capabilities are dropped
+        if plan.no_new_privileges.is_none() {
+            load_seccomp(plan)?;
+        }
+
+        if let Some(caps) = plan.capabilities {
+            set_capabilities(&caps)?;
+        }
+
+        if chdir_later {
+            if let Some(cwd) = plan.cwd {
+                check(libc::chdir(cwd.as_ptr()) as c_long, Step::Chdir)?;
+            }
+        }
+        verify_cwd()?;
+
+        if plan.no_new_privileges.is_some() {
+            load_seccomp(plan)?;
+        }
+
+        check(
+            libc::sigprocmask(libc::SIG_SETMASK, plan.sigmask, ptr::null_mut()) as c_long,
+            Step::SignalMask,
+        )?;
+
+        libc::execve(plan.path.as_ptr(), plan.argv.as_ptr(), plan.envp.as_ptr());
+        Err((Step::Exec, errno()))
+    }
+}
+
+/// Resets the handlers of youki, which must not run in the child. Ignored
+/// signals stay ignored in the process, as with a normal exec.
+unsafe fn reset_signal_handlers() {
+    let mut action: libc::sigaction = core::mem::zeroed();
+    for signal in 1..NSIG {
+        // fails for the signals that can't be caught and the ones reserved
+        // by libc, which is fine
+        if libc::sigaction(signal, ptr::null(), &mut action) != 0 {
+            continue;
+        }
+        if action.sa_sigaction == libc::SIG_IGN || action.sa_sigaction == libc::SIG_DFL {
+            continue;
+        }
+        action.sa_sigaction = libc::SIG_DFL;
+        action.sa_flags = 0;
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+}
+
+unsafe fn set_user(plan: &ChildPlan) -> Result<()> {
+    if !plan.groups.is_empty() {
+        check(
+            libc::syscall(
+                libc::SYS_setgroups,
+                plan.groups.len() as c_ulong,
+                plan.groups.as_ptr(),
+            ),
+            Step::Groups,
+        )?;
+    }
+
+    // The capabilities are kept for a new uid, they are set up afterwards
+    check(
+        prctl(libc::PR_SET_KEEPCAPS, 1, NO_ARG),
+        Step::KeepCapabilities,
+    )?;
+    check(
+        libc::syscall(libc::SYS_setresgid, plan.gid, plan.gid, plan.gid),
+        Step::Gid,
+    )?;
+    check(
+        libc::syscall(libc::SYS_setresuid, plan.uid, plan.uid, plan.uid),
+        Step::Uid,
+    )?;
+    // A new uid other than root clears the effective set
+    if plan.uid != 0 {
+        let mut data = get_capabilities()?;
+        for set in data.iter_mut() {
+            set.effective = set.permitted;
+        }
+        put_capabilities(&data)?;
+    }
+    check(
+        prctl(libc::PR_SET_KEEPCAPS, 0, NO_ARG),
+        Step::KeepCapabilities,
+    )?;
+
+    Ok(())
+}
+
+unsafe fn close_fds(plan: &ChildPlan) -> Result<()> {
+    let ret = libc::syscall(
+        libc::SYS_close_range,
+        plan.first_closed_fd as c_uint,
+        c_int::MAX as c_uint,
+        libc::CLOSE_RANGE_CLOEXEC,
+    );
+    if ret == 0 {
+        return Ok(());
+    }
+    match errno() {
+        // close_range was added in 5.9 and CLOSE_RANGE_CLOEXEC in 5.11
+        libc::ENOSYS | libc::EINVAL => {
+            for fd in plan.first_closed_fd..plan.fd_limit {
+                // fails with EBADF for an fd that isn't open
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+            Ok(())
+        }
+        errno => Err((Step::CloseFds, errno)),
+    }
+}
+
+unsafe fn load_seccomp(plan: &ChildPlan) -> Result<()> {
+    if let Some(prog) = plan.seccomp {
+        check(
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                plan.seccomp_flags,
+                prog as *const libc::sock_fprog,
+            ),
+            Step::Seccomp,
+        )?;
+    }
+
+    Ok(())
+}
+
+unsafe fn drop_bounding(bounding: u64, last_cap: u32) -> Result<()> {
+    for cap in 0..=last_cap.min(63) {
+        if bounding & (1 << cap) != 0 {
+            continue;
+        }
+        if prctl(libc::PR_CAPBSET_READ, cap as c_ulong, NO_ARG) != 1 {
+            continue;
+        }
+        check(
+            prctl(libc::PR_CAPBSET_DROP, cap as c_ulong, NO_ARG),
+            Step::Bounding,
+        )?;
+    }
+
+    Ok(())
+}
+
+unsafe fn set_capabilities(caps: &ChildCapabilities) -> Result<()> {
+    let mut data = get_capabilities()?;
+    let split = |mask: u64| [mask as u32, (mask >> 32) as u32];
+    if let Some(effective) = caps.effective {
+        for (set, word) in data.iter_mut().zip(split(effective)) {
+            set.effective = word;
+        }
+    }
+    if let Some(permitted) = caps.permitted {
+        for (set, word) in data.iter_mut().zip(split(permitted)) {
+            set.permitted = word;
+        }
+    }
+    if let Some(inheritable) = caps.inheritable {
+        for (set, word) in data.iter_mut().zip(split(inheritable)) {
+            set.inheritable = word;
+        }
+    }
+    put_capabilities(&data)?;
+
+    // As in the full path, ambient capabilities are best effort, as they
+    // aren't supported by every kernel
+    if let Some(ambient) = caps.ambient {
+        prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL as c_ulong,
+            NO_ARG,
+        );
+        for cap in 0..=caps.last_cap.min(63) {
+            if ambient & (1 << cap) != 0 {
+                prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_RAISE as c_ulong,
+                    cap as c_ulong,
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+unsafe fn get_capabilities() -> Result<[CapData; 2]> {
+    let mut header = CapHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    check(
+        libc::syscall(
+            libc::SYS_capget,
+            &mut header as *mut CapHeader,
+            data.as_mut_ptr(),
+        ),
+        Step::Capabilities,
+    )?;
+
+    Ok(data)
+}
+
+unsafe fn put_capabilities(data: &[CapData; 2]) -> Result<()> {
+    let mut header = CapHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    check(
+        libc::syscall(
+            libc::SYS_capset,
+            &mut header as *mut CapHeader,
+            data.as_ptr(),
+        ),
+        Step::Capabilities,
+    )?;
+
+    Ok(())
+}
+
+/// Ensures the working directory is inside the container, the raw getcwd
+/// returns a path that doesn't start with `/` for a directory outside of the
+/// root.
+unsafe fn verify_cwd() -> Result<()> {
+    let mut buf = [0u8; CWD_BUFFER_SIZE];
+    check(
+        libc::syscall(libc::SYS_getcwd, buf.as_mut_ptr(), buf.len()),
+        Step::Cwd,
+    )?;
+    if buf[0] != b'/' {
+        return Err((Step::Cwd, libc::ENOENT));
+    }
+
+    Ok(())
+}
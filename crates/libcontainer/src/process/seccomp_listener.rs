@@ -1,8 +1,11 @@
 use std::io::IoSlice;
 use std::os::fd::AsRawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use nix::sys::socket::{self, UnixAddr};
+use nix::errno::Errno;
+use nix::sys::socket::{self, sockopt, UnixAddr};
+use nix::sys::time::{TimeVal, TimeValLike};
 use nix::unistd;
 use oci_spec::runtime;
 
@@ -20,39 +23,64 @@ pub enum SeccompListenerError {
     ChannelError(#[from] channel::ChannelError),
     #[error("unix syscall fails")]
     UnixOther(#[source] nix::Error),
+    #[error("seccomp listener {listener_path:?} didn't take the notify fd within {timeout:?}")]
+    Timeout {
+        listener_path: PathBuf,
+        timeout: Duration,
+    },
 }
 
 type Result<T> = std::result::Result<T, SeccompListenerError>;
 
+/// Hands the seccomp notify fd of the init process to the seccomp listener.
+/// The listener has `timeout` to take it, so a hanging agent fails the create
+/// instead of blocking it forever.
 pub fn sync_seccomp(
     seccomp: &runtime::LinuxSeccomp,
     state: &ContainerProcessState,
     init_sender: &mut channel::InitSender,
     main_receiver: &mut channel::MainReceiver,
+    timeout: Duration,
 ) -> Result<()> {
     if seccomp::is_notify(seccomp) {
         tracing::debug!("main process waiting for sync seccomp");
         let seccomp_fd = main_receiver.wait_for_seccomp_request()?;
-        let listener_path = seccomp
-            .listener_path()
-            .as_ref()
-            .ok_or(SeccompListenerError::MissingListenerPath)?;
-        let encoded_state = serde_json::to_vec(state).map_err(SeccompListenerError::EncodeState)?;
-        sync_seccomp_send_msg(listener_path, &encoded_state, seccomp_fd).map_err(|err| {
-            tracing::error!("failed to send msg to seccomp listener: {}", err);
-            err
-        })?;
-        init_sender.seccomp_notify_done()?;
+        let sent = send_to_listener(seccomp, state, seccomp_fd, timeout);
         // Once we sent the seccomp notify fd to the seccomp listener, we can
         // safely close the fd. The SCM_RIGHTS msg will duplicate the fd to the
-        // process on the other end of the listener.
+        // process on the other end of the listener. If sending failed, the fd
+        // is of no use anymore either.
         let _ = unistd::close(seccomp_fd);
+        sent?;
+        init_sender.seccomp_notify_done()?;
     }
 
     Ok(())
 }
 
-fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()> {
+fn send_to_listener(
+    seccomp: &runtime::LinuxSeccomp,
+    state: &ContainerProcessState,
+    seccomp_fd: i32,
+    timeout: Duration,
+) -> Result<()> {
+    let listener_path = seccomp
+        .listener_path()
+        .as_ref()
+        .ok_or(SeccompListenerError::MissingListenerPath)?;
+    let encoded_state = serde_json::to_vec(state).map_err(SeccompListenerError::EncodeState)?;
+    sync_seccomp_send_msg(listener_path, &encoded_state, seccomp_fd, timeout).map_err(|err| {
+        tracing::error!("failed to send msg to seccomp listener: {}", err);
+        err
+    })
+}
+
+fn sync_seccomp_send_msg(
+    listener_path: &Path,
+    msg: &[u8],
+    fd: i32,
+    timeout: Duration,
+) -> Result<()> {
     // The seccomp listener has specific instructions on how to transmit the
     // information through seccomp listener.  Therefore, we have to use
     // libc/nix APIs instead of Rust std lib APIs to maintain flexibility.
@@ -69,6 +97,21 @@ fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()
         );
         SeccompListenerError::UnixOther(err)
     })?;
+    // Both the connect and the sendmsg block while the listener doesn't take
+    // the connection, and give up with EAGAIN once the send timeout passed.
+    // A zero timeout would mean no timeout at all.
+    let send_timeout = TimeVal::microseconds((timeout.as_micros() as i64).max(1));
+    socket::setsockopt(&socket, sockopt::SendTimeout, &send_timeout).map_err(|err| {
+        tracing::error!(?err, "failed to set the seccomp listener send timeout");
+        SeccompListenerError::UnixOther(err)
+    })?;
+    let timed_out = || {
+        tracing::error!(?listener_path, ?timeout, "seccomp listener timed out");
+        SeccompListenerError::Timeout {
+            listener_path: listener_path.to_owned(),
+            timeout,
+        }
+    };
     let unix_addr = socket::UnixAddr::new(listener_path).map_err(|err| {
         tracing::error!(
             ?err,
@@ -78,6 +121,9 @@ fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()
         SeccompListenerError::UnixOther(err)
     })?;
     socket::connect(socket.as_raw_fd(), &unix_addr).map_err(|err| {
+        if err == Errno::EAGAIN {
+            return timed_out();
+        }
         tracing::error!(
             ?err,
             ?listener_path,
@@ -100,6 +146,9 @@ fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()
         None,
     )
     .map_err(|err| {
+        if err == Errno::EAGAIN {
+            return timed_out();
+        }
         tracing::error!(?err, "failed to write container state to seccomp listener");
         SeccompListenerError::UnixOther(err)
     })?;
@@ -159,6 +208,7 @@ mod tests {
                 &state,
                 &mut init_sender,
                 &mut main_receiver,
+                Duration::from_secs(10),
             )
             .unwrap();
         });
@@ -177,4 +227,58 @@ mod tests {
         assert!(th.join().is_ok());
         Ok(())
     }
+
+    #[test]
+    #[serial]
+    fn test_sync_seccomp_timeout() -> Result<()> {
+        use std::os::unix::net::UnixStream;
+        use std::time::Instant;
+
+        let tmp_dir = tempfile::tempdir()?;
+        let socket_path = tmp_dir.path().join("socket_file.sock");
+        // An agent that is stuck: its socket is bound, but it never accepts.
+        // With a backlog of 0 the first pending connection fills the queue.
+        let agent = socket::socket(
+            socket::AddressFamily::Unix,
+            socket::SockType::Stream,
+            socket::SockFlag::empty(),
+            None,
+        )?;
+        socket::bind(agent.as_raw_fd(), &UnixAddr::new(&socket_path)?)?;
+        socket::listen(&agent, socket::Backlog::new(0)?)?;
+        let _pending = UnixStream::connect(&socket_path)?;
+
+        let (mut main_sender, mut main_receiver) = channel::main_channel()?;
+        let (mut init_sender, _init_receiver) = channel::init_channel()?;
+        let scmp_file = tempfile::tempfile()?;
+        main_sender.seccomp_notify_request(scmp_file.as_raw_fd())?;
+
+        let seccomp = LinuxSeccompBuilder::default()
+            .listener_path(socket_path.clone())
+            .syscalls(vec![LinuxSyscallBuilder::default()
+                .action(LinuxSeccompAction::ScmpActNotify)
+                .build()?])
+            .build()?;
+        let timeout = Duration::from_millis(100);
+        let start = Instant::now();
+        let result = sync_seccomp(
+            &seccomp,
+            &ContainerProcessState::default(),
+            &mut init_sender,
+            &mut main_receiver,
+            timeout,
+        );
+        match result {
+            Err(SeccompListenerError::Timeout {
+                listener_path,
+                timeout: got,
+            }) => {
+                assert_eq!(listener_path, socket_path);
+                assert_eq!(got, timeout);
+            }
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
 }
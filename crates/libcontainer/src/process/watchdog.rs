@@ -0,0 +1,170 @@
+//! Watchdog of the intermediate process
+//!
+//! The intermediate process can get stuck in the kernel, e.g. attaching to a
+//! cgroup below a frozen one, and the create would wait for it forever. The
+//! main process waits for its messages with a deadline instead, see
+//! [`with_intermediate_timeout`](crate::container::builder::ContainerBuilder::with_intermediate_timeout).
+//! Once it passes, the watchdog reads where the process is stuck from
+//! `/proc/<pid>/stack`, `status` and `wchan`, kills the process and its
+//! descendants and fails the create with
+//! [`ProcessError::CreateTimeout`](crate::process::container_main_process::ProcessError::CreateTimeout).
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::waitpid;
+use nix::unistd::Pid;
+
+use crate::process::container_main_process::ProcessError;
+use crate::utils;
+
+/// How long reading the diagnostics of a stuck process may take
+pub const DIAGNOSTICS_TIMEOUT: Duration = Duration::from_secs(2);
+
+const DIAGNOSTICS_FILES: [&str; 3] = ["stack", "status", "wchan"];
+
+/// Where a stuck process is, from its files in /proc. A file that couldn't
+/// be read in time is `None`, e.g. the stack is only readable with
+/// CAP_SYS_ADMIN.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessDiagnostics {
+    /// Kernel stack of the process
+    pub stack: Option<String>,
+    /// Status of the process, with its state and signal masks
+    pub status: Option<String>,
+    /// Kernel function the process sleeps in
+    pub wchan: Option<String>,
+}
+
+/// Collects the diagnostics of the intermediate process `pid` which didn't
+/// send the expected message within `timeout`, kills it with its
+/// descendants and returns the error the create fails with.
+pub(crate) fn stuck_intermediate(pid: Pid, timeout: Duration) -> ProcessError {
+    let diagnostics = collect(pid, DIAGNOSTICS_TIMEOUT);
+    tracing::error!(
+        ?pid,
+        ?timeout,
+        ?diagnostics,
+        "intermediate process is stuck"
+    );
+    kill_tree(pid);
+    // The process isn't our child if it was cloned by the exit waiter or as
+    // a sibling.
+    match waitpid(pid, None) {
+        Ok(_) | Err(Errno::ECHILD) => {}
+        Err(err) => tracing::warn!(?err, ?pid, "failed to reap stuck intermediate process"),
+    }
+
+    ProcessError::CreateTimeout {
+        pid,
+        timeout,
+        diagnostics: Box::new(diagnostics),
+    }
+}
+
+/// Reads the diagnostics of `pid`, giving up on the files not read within
+/// `timeout`
+pub fn collect(pid: Pid, timeout: Duration) -> ProcessDiagnostics {
+    let (sender, receiver) = mpsc::channel();
+    // Reading the stack takes locks of the process, which a process stuck in
+    // the kernel may hold. The thread is left behind if a read blocks.
+    thread::spawn(move || {
+        for file in DIAGNOSTICS_FILES {
+            let contents = fs::read_to_string(format!("/proc/{pid}/{file}"));
+            if sender.send((file, contents)).is_err() {
+                return;
+            }
+        }
+    });
+
+    let deadline = Instant::now() + timeout;
+    let mut diagnostics = ProcessDiagnostics::default();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let (file, contents) = match receiver.recv_timeout(remaining) {
+            Ok((file, Ok(contents))) => (file, contents),
+            Ok((file, Err(err))) => {
+                tracing::warn!(?err, ?pid, file, "failed to read diagnostics");
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                tracing::warn!(?pid, ?timeout, "reading the diagnostics timed out");
+                break;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        match file {
+            "stack" => diagnostics.stack = Some(contents),
+            "status" => diagnostics.status = Some(contents),
+            _ => diagnostics.wchan = Some(contents),
+        }
+    }
+
+    diagnostics
+}
+
+/// Kills `pid` and its descendants, as far as they can be found
+fn kill_tree(pid: Pid) {
+    for pid in utils::process_tree(pid) {
+        if let Err(err) = signal::kill(pid, Signal::SIGKILL) {
+            tracing::warn!(?err, ?pid, "failed to kill stuck process");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use nix::unistd;
+    use serial_test::serial;
+
+    use super::*;
+    use crate::process::channel::{main_channel, ChannelError};
+
+    #[test]
+    #[serial]
+    fn test_unresponsive_intermediate_times_out() -> Result<()> {
+        let timeout = Duration::from_millis(100);
+        let (_sender, mut receiver) = main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                receiver.set_deadline(Some(Instant::now() + timeout));
+                let err = receiver.wait_for_intermediate_ready().unwrap_err();
+                assert!(matches!(err, ChannelError::Timeout(_)), "{err:?}");
+
+                let err = stuck_intermediate(child, timeout);
+                let diagnostics = match err {
+                    ProcessError::CreateTimeout {
+                        pid,
+                        timeout: err_timeout,
+                        diagnostics,
+                    } => {
+                        assert_eq!(pid, child);
+                        assert_eq!(err_timeout, timeout);
+                        diagnostics
+                    }
+                    err => panic!("unexpected error {err:?}"),
+                };
+                let status = diagnostics.status.unwrap();
+                assert!(status.contains(&format!("Pid:\t{child}")), "{status}");
+                assert!(diagnostics.wchan.is_some());
+                // The process was killed and reaped.
+                assert_eq!(signal::kill(child, None), Err(Errno::ESRCH));
+            }
+            // never sends the expected message
+            unistd::ForkResult::Child => loop {
+                unistd::pause();
+            },
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_gone_process() {
+        // pid_max is at most 2^22
+        let diagnostics = collect(Pid::from_raw(1 << 23), DIAGNOSTICS_TIMEOUT);
+        assert_eq!(diagnostics, ProcessDiagnostics::default());
+    }
+}
@@ -0,0 +1,374 @@
+//! Probe gating the start of a container
+//!
+//! Some workloads are only usable once they set something up, e.g. created
+//! the unix socket they serve on. A container created with
+//! [`with_readiness_probe`](crate::container::init_builder::InitContainerBuilder::with_readiness_probe)
+//! is only reported as running by
+//! [`Container::start`](crate::container::Container::start) once the check of
+//! its probe succeeds. The check is evaluated from the host, through the root
+//! of the init process, after the workload was told to start. It is retried
+//! with a backoff until the timeout of the probe passed, and if it never
+//! succeeds the start fails and the init process is killed.
+//!
+//! The paths of the checks are resolved with `openat2(RESOLVE_IN_ROOT)`
+//! below an fd of `/proc/<pid>/root`, so symlinks of the container, even
+//! with absolute targets or `..`, are resolved in its root and the runtime
+//! never looks at a path of the host. A socket is connected to through the
+//! fd it resolved to. This needs Linux 5.6, on older kernels the checks
+//! never succeed.
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{io, thread};
+
+use nix::sys::signal;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+/// Default time the check of a probe has to succeed in
+pub const DEFAULT_READINESS_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default delay before the second attempt, doubled for every further attempt
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+/// Default upper bound of the delay between two attempts
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadinessProbeError {
+    #[error("path {0:?} of the readiness check is not absolute")]
+    RelativePath(PathBuf),
+    #[error("readiness timeout must be positive")]
+    ZeroTimeout,
+    #[error("readiness check {check} didn't succeed within {timeout:?} after {attempts} attempts")]
+    Timeout {
+        check: ReadinessCheck,
+        timeout: Duration,
+        attempts: u32,
+    },
+    #[error("container init process exited before readiness check {check} succeeded")]
+    InitExited {
+        check: ReadinessCheck,
+        attempts: u32,
+    },
+}
+
+type Result<T> = std::result::Result<T, ReadinessProbeError>;
+
+/// What is checked for the container to be ready, with paths inside the
+/// container
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ReadinessCheck {
+    /// The path exists
+    FileExists { path: PathBuf },
+    /// A unix stream socket at the path accepts connections
+    SocketConnect { path: PathBuf },
+}
+
+impl std::fmt::Display for ReadinessCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FileExists { path } => write!(f, "file-exists {path:?}"),
+            Self::SocketConnect { path } => write!(f, "socket-connect {path:?}"),
+        }
+    }
+}
+
+impl ReadinessCheck {
+    fn path(&self) -> &Path {
+        match self {
+            Self::FileExists { path } | Self::SocketConnect { path } => path,
+        }
+    }
+
+    /// Evaluates the check in the root of `pid`
+    fn evaluate(&self, pid: Pid) -> bool {
+        match open_path(Path::new(&format!("/proc/{pid}/root"))) {
+            Ok(root) => self.evaluate_in(&root),
+            Err(err) => {
+                tracing::debug!(?err, ?pid, "failed to open the root of the init process");
+                false
+            }
+        }
+    }
+
+    /// Evaluates the check with its path resolved in `root`
+    fn evaluate_in(&self, root: &File) -> bool {
+        let fd = match open_in_root(root, self.path()) {
+            Ok(fd) => fd,
+            Err(err) => {
+                tracing::trace!(?err, check = %self, "path of the check not resolved");
+                return false;
+            }
+        };
+        match self {
+            Self::FileExists { .. } => true,
+            // The magic link of the fd reaches the socket the path resolved
+            // to, without resolving the path again.
+            Self::SocketConnect { .. } => {
+                UnixStream::connect(format!("/proc/self/fd/{}", fd.as_raw_fd())).is_ok()
+            }
+        }
+    }
+}
+
+fn open_path(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(path)
+}
+
+/// Opens `path` as an `O_PATH` fd, with every component, symlinks included,
+/// resolved as if `root` was the root directory
+fn open_in_root(root: &File, path: &Path) -> io::Result<OwnedFd> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let how = libc::open_how {
+        flags: (libc::O_PATH | libc::O_CLOEXEC) as u64,
+        mode: 0,
+        resolve: libc::RESOLVE_IN_ROOT | libc::RESOLVE_NO_MAGICLINKS,
+    };
+    // SAFETY: the path and how outlive the call, which returns a new fd or -1.
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            root.as_raw_fd(),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the fd was just opened and is owned by nothing else.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// A check retried with a backoff until it succeeds or the timeout passed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessProbe {
+    pub check: ReadinessCheck,
+    pub timeout: Duration,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl ReadinessProbe {
+    /// Creates a probe of `check` with the default timeout and backoff
+    pub fn new(check: ReadinessCheck) -> Self {
+        Self {
+            check,
+            timeout: DEFAULT_READINESS_TIMEOUT,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+
+    /// Sets the time the check has to succeed in
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the delay before the second attempt, which is doubled for every
+    /// further attempt up to `max`
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if !self.check.path().is_absolute() {
+            return Err(ReadinessProbeError::RelativePath(
+                self.check.path().to_owned(),
+            ));
+        }
+        if self.timeout.is_zero() {
+            return Err(ReadinessProbeError::ZeroTimeout);
+        }
+        Ok(())
+    }
+
+    /// Waits for the check to succeed in the root of the init process `pid`,
+    /// returns the number of attempts it took
+    pub(crate) fn wait(&self, pid: Pid) -> Result<u32> {
+        self.poll(
+            || self.check.evaluate(pid),
+            || signal::kill(pid, None).is_ok(),
+        )
+    }
+
+    /// Runs `check` until it succeeds, the timeout passed, or `alive` tells
+    /// that the init process is gone
+    fn poll(&self, mut check: impl FnMut() -> bool, alive: impl Fn() -> bool) -> Result<u32> {
+        let start = Instant::now();
+        let mut delay = self.initial_backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            if check() {
+                return Ok(attempts);
+            }
+            if !alive() {
+                return Err(ReadinessProbeError::InitExited {
+                    check: self.check.clone(),
+                    attempts,
+                });
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= self.timeout {
+                return Err(ReadinessProbeError::Timeout {
+                    check: self.check.clone(),
+                    timeout: self.timeout,
+                    attempts,
+                });
+            }
+            tracing::debug!(check = %self.check, attempts, ?delay, "container not ready yet");
+            thread::sleep(delay.min(self.timeout - elapsed));
+            delay = (delay * 2).min(self.max_backoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use anyhow::Result;
+
+    use super::*;
+
+    fn probe(timeout: Duration) -> ReadinessProbe {
+        ReadinessProbe::new(ReadinessCheck::FileExists {
+            path: PathBuf::from("/run/ready"),
+        })
+        .with_timeout(timeout)
+        .with_backoff(Duration::from_millis(1), Duration::from_millis(4))
+    }
+
+    #[test]
+    fn test_poll_succeeds_on_third_attempt() -> Result<()> {
+        let calls = Cell::new(0);
+        let attempts = probe(Duration::from_secs(5)).poll(
+            || {
+                calls.set(calls.get() + 1);
+                calls.get() == 3
+            },
+            || true,
+        )?;
+        assert_eq!(attempts, 3);
+        assert_eq!(calls.get(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_poll_times_out() {
+        let start = Instant::now();
+        let err = probe(Duration::from_millis(50))
+            .poll(|| false, || true)
+            .unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(
+            matches!(err, ReadinessProbeError::Timeout { attempts, .. } if attempts > 1),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn test_poll_stops_when_init_exited() {
+        let err = probe(Duration::from_secs(5))
+            .poll(|| false, || false)
+            .unwrap_err();
+        assert!(
+            matches!(err, ReadinessProbeError::InitExited { attempts: 1, .. }),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(probe(Duration::from_secs(1)).validate().is_ok());
+        assert!(matches!(
+            probe(Duration::ZERO).validate(),
+            Err(ReadinessProbeError::ZeroTimeout)
+        ));
+        let relative = ReadinessProbe::new(ReadinessCheck::SocketConnect {
+            path: PathBuf::from("run/app.sock"),
+        });
+        assert!(matches!(
+            relative.validate(),
+            Err(ReadinessProbeError::RelativePath(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_evaluated_in_root_of_pid() -> Result<()> {
+        // The root of the test process is the host root.
+        let dir = tempfile::tempdir()?;
+        let socket_path = dir.path().join("app.sock");
+        let pid = Pid::this();
+
+        let file = ReadinessCheck::FileExists {
+            path: dir.path().join("ready"),
+        };
+        assert!(!file.evaluate(pid));
+        std::fs::write(dir.path().join("ready"), "")?;
+        assert!(file.evaluate(pid));
+
+        let socket = ReadinessCheck::SocketConnect {
+            path: socket_path.clone(),
+        };
+        assert!(!socket.evaluate(pid));
+        let _listener = std::os::unix::net::UnixListener::bind(&socket_path)?;
+        assert!(socket.evaluate(pid));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_confined_to_root() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let host = tempfile::tempdir()?;
+        std::fs::write(host.path().join("ready"), "")?;
+        let _listener = std::os::unix::net::UnixListener::bind(host.path().join("app.sock"))?;
+        // Absolute and relative symlinks of the container pointing at the host
+        std::os::unix::fs::symlink(host.path(), root.path().join("absolute"))?;
+        let mut up = PathBuf::new();
+        for _ in root.path().components().skip(1) {
+            up.push("..");
+        }
+        std::os::unix::fs::symlink(
+            up.join(host.path().strip_prefix("/")?),
+            root.path().join("relative"),
+        )?;
+        let root_fd = open_path(root.path())?;
+
+        for link in ["/absolute", "/relative"] {
+            let file = ReadinessCheck::FileExists {
+                path: Path::new(link).join("ready"),
+            };
+            assert!(!file.evaluate_in(&root_fd), "{file} escaped the root");
+            let socket = ReadinessCheck::SocketConnect {
+                path: Path::new(link).join("app.sock"),
+            };
+            assert!(!socket.evaluate_in(&root_fd), "{socket} escaped the root");
+        }
+
+        // The same paths exist once the links resolve in the root.
+        let inside = root.path().join(host.path().strip_prefix("/")?);
+        std::fs::create_dir_all(&inside)?;
+        std::fs::write(inside.join("ready"), "")?;
+        let file = ReadinessCheck::FileExists {
+            path: PathBuf::from("/absolute/ready"),
+        };
+        assert!(file.evaluate_in(&root_fd));
+
+        Ok(())
+    }
+}
@@ -7,6 +7,7 @@ use nix::unistd::{close, Gid, Uid};
 use oci_spec::runtime::LinuxDevice;
 
 use super::utils::to_sflag;
+use super::write_accounting::WriteAccounting;
 use crate::syscall::syscall::create_syscall;
 use crate::syscall::Syscall;
 use crate::utils::PathBufExt;
@@ -29,6 +30,7 @@ type Result<T> = std::result::Result<T, DeviceError>;
 
 pub struct Device {
     syscall: Box<dyn Syscall>,
+    writes: WriteAccounting,
 }
 
 impl Default for Device {
@@ -39,13 +41,20 @@ impl Default for Device {
 
 impl Device {
     pub fn new() -> Device {
+        Device::new_with_syscall(create_syscall())
+    }
+
+    pub fn new_with_syscall(syscall: Box<dyn Syscall>) -> Device {
         Device {
-            syscall: create_syscall(),
+            syscall,
+            writes: WriteAccounting::default(),
         }
     }
 
-    pub fn new_with_syscall(syscall: Box<dyn Syscall>) -> Device {
-        Device { syscall }
+    /// Sets the accounting the created device nodes are recorded in
+    pub fn with_write_accounting(mut self, writes: WriteAccounting) -> Self {
+        self.writes = writes;
+        self
     }
 
     pub fn create_devices<'a, I>(&self, rootfs: &Path, devices: I, bind: bool) -> Result<()>
@@ -77,7 +86,7 @@ impl Device {
     }
 
     fn bind_dev(&self, rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
-        let full_container_path = create_container_dev_path(rootfs, dev)?;
+        let full_container_path = create_container_dev_path(rootfs, dev, &self.writes)?;
         tracing::debug!(
             "bind_dev with full container path {:?}",
             full_container_path
@@ -93,6 +102,7 @@ impl Device {
             err
         })?;
         close(fd)?;
+        self.writes.record(&full_container_path);
         self.syscall
             .mount(
                 Some(dev.path()),
@@ -121,7 +131,7 @@ impl Device {
                 | ((major & !0xfff) << 32)) as u64
         }
 
-        let full_container_path = create_container_dev_path(rootfs, dev)?;
+        let full_container_path = create_container_dev_path(rootfs, dev, &self.writes)?;
 
         self.syscall
             .mknod(
@@ -141,6 +151,7 @@ impl Device {
 
                 err
             })?;
+        self.writes.record(&full_container_path);
         self.syscall
             .chown(
                 &full_container_path,
@@ -163,7 +174,11 @@ impl Device {
     }
 }
 
-fn create_container_dev_path(rootfs: &Path, dev: &LinuxDevice) -> Result<PathBuf> {
+fn create_container_dev_path(
+    rootfs: &Path,
+    dev: &LinuxDevice,
+    writes: &WriteAccounting,
+) -> Result<PathBuf> {
     let relative_dev_path = dev.path().as_relative().map_err(|err| {
         tracing::error!(
             "failed to convert {:?} to relative path: {}",
@@ -176,19 +191,20 @@ fn create_container_dev_path(rootfs: &Path, dev: &LinuxDevice) -> Result<PathBuf
         tracing::error!("failed to join {rootfs:?} with {:?}: {err}", dev.path());
         DeviceError::Other(err.into())
     })?;
-    std::fs::create_dir_all(
-        full_container_path
-            .parent()
-            .unwrap_or_else(|| Path::new("")),
-    )
-    .map_err(|err| {
-        tracing::error!(
-            "failed to create parent dir of {:?}: {}",
-            full_container_path,
-            err
-        );
-        DeviceError::Other(err.into())
-    })?;
+    writes
+        .create_dir_all(
+            full_container_path
+                .parent()
+                .unwrap_or_else(|| Path::new("")),
+        )
+        .map_err(|err| {
+            tracing::error!(
+                "failed to create parent dir of {:?}: {}",
+                full_container_path,
+                err
+            );
+            DeviceError::Other(err.into())
+        })?;
 
     Ok(full_container_path)
 }
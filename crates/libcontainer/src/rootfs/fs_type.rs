@@ -0,0 +1,104 @@
+//! Filesystem type backing the rootfs
+//!
+//! Some mount issues only show up on a particular backing filesystem, e.g.
+//! overlay refusing some mount options its lower layers accept. The rootfs
+//! setup detects the type with statfs(2) once the rootfs is mounted, and the
+//! type is reported in
+//! [`CreateResult::rootfs_fs_type`](crate::container::CreateResult::rootfs_fs_type).
+use std::fmt;
+use std::path::Path;
+
+use nix::sys::statfs;
+use serde::{Deserialize, Serialize};
+
+/// Filesystem type of a mount, as told by the magic of statfs(2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FsType {
+    Overlay,
+    /// ext2, ext3 and ext4 share the same magic
+    Ext4,
+    Xfs,
+    Btrfs,
+    Tmpfs,
+    Fuse,
+    Nfs,
+    /// Any other filesystem, by its magic
+    Other(u64),
+}
+
+const KNOWN_MAGICS: &[(statfs::FsType, FsType)] = &[
+    (statfs::OVERLAYFS_SUPER_MAGIC, FsType::Overlay),
+    (statfs::EXT4_SUPER_MAGIC, FsType::Ext4),
+    (statfs::XFS_SUPER_MAGIC, FsType::Xfs),
+    (statfs::BTRFS_SUPER_MAGIC, FsType::Btrfs),
+    (statfs::TMPFS_MAGIC, FsType::Tmpfs),
+    (statfs::FUSE_SUPER_MAGIC, FsType::Fuse),
+    (statfs::NFS_SUPER_MAGIC, FsType::Nfs),
+];
+
+impl FsType {
+    /// Detects the type of the filesystem `path` is on
+    pub fn detect(path: &Path) -> nix::Result<Self> {
+        let stat = statfs::statfs(path)?;
+        Ok(Self::from_magic(stat.filesystem_type()))
+    }
+
+    pub fn from_magic(magic: statfs::FsType) -> Self {
+        KNOWN_MAGICS
+            .iter()
+            .find(|(known, _)| *known == magic)
+            .map_or(Self::Other(magic.0 as u64), |(_, fs_type)| *fs_type)
+    }
+}
+
+impl fmt::Display for FsType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overlay => f.write_str("overlay"),
+            Self::Ext4 => f.write_str("ext4"),
+            Self::Xfs => f.write_str("xfs"),
+            Self::Btrfs => f.write_str("btrfs"),
+            Self::Tmpfs => f.write_str("tmpfs"),
+            Self::Fuse => f.write_str("fuse"),
+            Self::Nfs => f.write_str("nfs"),
+            Self::Other(magic) => write!(f, "{magic:#x}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::rootfs::RootFS;
+
+    #[test]
+    fn test_from_magic() {
+        assert_eq!(
+            FsType::from_magic(statfs::OVERLAYFS_SUPER_MAGIC),
+            FsType::Overlay
+        );
+        assert_eq!(FsType::from_magic(statfs::TMPFS_MAGIC), FsType::Tmpfs);
+        assert_eq!(
+            FsType::from_magic(statfs::PROC_SUPER_MAGIC),
+            FsType::Other(0x9fa0)
+        );
+        assert_eq!(FsType::Other(0x9fa0).to_string(), "0x9fa0");
+    }
+
+    #[test]
+    fn test_rootfs_reports_backing_fs_type() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let expected = FsType::from_magic(statfs::statfs(tmp.path())?.filesystem_type());
+
+        let rootfs = RootFS::new();
+        assert_eq!(rootfs.fs_type(), None);
+        rootfs.detect_fs_type(tmp.path());
+        assert_eq!(rootfs.fs_type(), Some(expected));
+        // /proc is never the backing fs of a temp dir
+        assert_ne!(rootfs.fs_type(), Some(FsType::detect(Path::new("/proc"))?));
+
+        Ok(())
+    }
+}
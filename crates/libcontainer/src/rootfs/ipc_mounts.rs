@@ -0,0 +1,197 @@
+//! Mounts of the IPC namespace of the container
+//!
+//! The POSIX message queues of `/dev/mqueue` and the shared memory of
+//! `/dev/shm` belong to the IPC namespace. A container getting a new IPC
+//! namespace gets a fresh `mqueue` at `/dev/mqueue` and a `tmpfs` of
+//! [`DEFAULT_SHM_SIZE`] at `/dev/shm` if its spec doesn't mount them, and a
+//! `tmpfs` of the spec at `/dev/shm` without a size gets the default size,
+//! instead of half of the memory of the host. Both can be turned off with
+//! [`InitContainerBuilder::with_mqueue_mount`](crate::container::init_builder::InitContainerBuilder::with_mqueue_mount)
+//! and
+//! [`InitContainerBuilder::with_shm_size`](crate::container::init_builder::InitContainerBuilder::with_shm_size).
+//! A container joining an existing IPC namespace keeps the mounts of its
+//! spec.
+use std::path::Path;
+
+use oci_spec::runtime::{LinuxNamespaceType, Mount, MountBuilder, Spec};
+use oci_spec::OciSpecError;
+
+/// Size of `/dev/shm` in bytes, the default of Docker
+pub const DEFAULT_SHM_SIZE: u64 = 64 * 1024 * 1024;
+
+const MQUEUE_PATH: &str = "/dev/mqueue";
+const SHM_PATH: &str = "/dev/shm";
+
+/// Adds the `mqueue` mount if `mqueue` is set, and the `/dev/shm` mount of
+/// `shm_size` bytes or its size if it is set, to a spec creating an IPC
+/// namespace
+pub fn apply(spec: &mut Spec, mqueue: bool, shm_size: Option<u64>) -> Result<(), OciSpecError> {
+    if !creates_ipc_namespace(spec) {
+        return Ok(());
+    }
+
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    let mounted = |mounts: &[Mount], path: &str| {
+        mounts
+            .iter()
+            .any(|mount| mount.destination() == Path::new(path))
+    };
+    if mqueue && !mounted(&mounts, MQUEUE_PATH) {
+        tracing::debug!("adding an mqueue mount for the new IPC namespace");
+        mounts.push(
+            MountBuilder::default()
+                .destination(MQUEUE_PATH)
+                .typ("mqueue")
+                .source("mqueue")
+                .options(options(&["nosuid", "noexec", "nodev"]))
+                .build()?,
+        );
+    }
+    if let Some(size) = shm_size {
+        let shm = mounts
+            .iter_mut()
+            .find(|mount| mount.destination() == Path::new(SHM_PATH));
+        match shm {
+            // A bind mount, e.g. of the shm of the host, has no size.
+            Some(shm) if shm.typ().as_deref() != Some("tmpfs") => {}
+            Some(shm) => {
+                let mut shm_options = shm.options().clone().unwrap_or_default();
+                if !shm_options.iter().any(|option| option.starts_with("size=")) {
+                    shm_options.push(format!("size={size}"));
+                    shm.set_options(Some(shm_options));
+                }
+            }
+            None => {
+                tracing::debug!(size, "adding a shm mount for the new IPC namespace");
+                let mut shm_options = options(&["nosuid", "noexec", "nodev", "mode=1777"]);
+                shm_options.push(format!("size={size}"));
+                mounts.push(
+                    MountBuilder::default()
+                        .destination(SHM_PATH)
+                        .typ("tmpfs")
+                        .source("shm")
+                        .options(shm_options)
+                        .build()?,
+                );
+            }
+        }
+    }
+    spec.set_mounts(Some(mounts));
+
+    Ok(())
+}
+
+/// An IPC namespace without a path is created for the container
+fn creates_ipc_namespace(spec: &Spec) -> bool {
+    spec.linux()
+        .as_ref()
+        .and_then(|linux| linux.namespaces().as_ref())
+        .map_or(false, |namespaces| {
+            namespaces
+                .iter()
+                .any(|ns| ns.typ() == LinuxNamespaceType::Ipc && ns.path().is_none())
+        })
+}
+
+fn options(options: &[&str]) -> Vec<String> {
+    options.iter().map(|option| option.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, LinuxNamespaceBuilder};
+
+    use super::*;
+
+    fn ipc_spec(ipc_path: Option<&str>, mounts: Vec<Mount>) -> Result<Spec> {
+        let mut ipc = LinuxNamespaceBuilder::default().typ(LinuxNamespaceType::Ipc);
+        if let Some(path) = ipc_path {
+            ipc = ipc.path(path);
+        }
+        let mut spec = Spec::default();
+        spec.set_linux(Some(
+            LinuxBuilder::default()
+                .namespaces(vec![ipc.build()?])
+                .build()?,
+        ));
+        spec.set_mounts(Some(mounts));
+        Ok(spec)
+    }
+
+    fn mount<'a>(spec: &'a Spec, path: &str) -> Option<&'a Mount> {
+        spec.mounts()
+            .iter()
+            .flatten()
+            .find(|mount| mount.destination() == Path::new(path))
+    }
+
+    #[test]
+    fn test_new_ipc_namespace_gets_mounts() -> Result<()> {
+        let mut spec = ipc_spec(None, Vec::new())?;
+        apply(&mut spec, true, Some(DEFAULT_SHM_SIZE))?;
+
+        let mqueue = mount(&spec, MQUEUE_PATH).unwrap();
+        assert_eq!(mqueue.typ().as_deref(), Some("mqueue"));
+        let shm = mount(&spec, SHM_PATH).unwrap();
+        assert_eq!(shm.typ().as_deref(), Some("tmpfs"));
+        assert!(shm
+            .options()
+            .iter()
+            .flatten()
+            .any(|option| option == "size=67108864"));
+
+        // applying again adds nothing
+        apply(&mut spec, true, Some(DEFAULT_SHM_SIZE))?;
+        assert_eq!(spec.mounts().as_ref().unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disabled_mounts() -> Result<()> {
+        let mut spec = ipc_spec(None, Vec::new())?;
+        apply(&mut spec, false, None)?;
+        assert!(spec.mounts().as_ref().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shm_of_spec_gets_size() -> Result<()> {
+        let shm = |options: &[&str]| -> Result<Mount> {
+            Ok(MountBuilder::default()
+                .destination(SHM_PATH)
+                .typ("tmpfs")
+                .source("shm")
+                .options(super::options(options))
+                .build()?)
+        };
+        let size_options = |spec: &Spec| -> Vec<String> {
+            mount(spec, SHM_PATH)
+                .unwrap()
+                .options()
+                .iter()
+                .flatten()
+                .filter(|option| option.starts_with("size="))
+                .cloned()
+                .collect()
+        };
+
+        let mut spec = ipc_spec(None, vec![shm(&["mode=1777"])?])?;
+        apply(&mut spec, false, Some(1024))?;
+        assert_eq!(size_options(&spec), vec!["size=1024"]);
+
+        // the size of the spec is kept
+        let mut spec = ipc_spec(None, vec![shm(&["size=65536k"])?])?;
+        apply(&mut spec, false, Some(1024))?;
+        assert_eq!(size_options(&spec), vec!["size=65536k"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_joined_ipc_namespace_keeps_mounts() -> Result<()> {
+        let mut spec = ipc_spec(Some("/proc/42/ns/ipc"), Vec::new())?;
+        apply(&mut spec, true, Some(DEFAULT_SHM_SIZE))?;
+        assert!(spec.mounts().as_ref().unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 //! During kernel initialization, a minimal replica of the ramfs filesystem is
 //! loaded, called rootfs.  Most systems mount another filesystem over it
 
+use serde::{Deserialize, Serialize};
+
 #[allow(clippy::module_inception)]
 pub(crate) mod rootfs;
 pub use rootfs::RootFS;
@@ -8,10 +10,37 @@ pub use rootfs::RootFS;
 pub mod device;
 pub use device::Device;
 
+pub mod fs_type;
+pub use fs_type::FsType;
+
 pub(super) mod mount;
+pub use mount::RootfsMountPlan;
 pub(super) mod symlink;
 
+pub mod ipc_mounts;
+pub mod ownership_shift;
+pub mod prewarm;
+pub mod tmpfs_layer;
 pub mod utils;
+pub mod write_accounting;
+
+/// When the mounts of the spec are applied, relative to entering the rootfs
+/// with pivot_root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MountOrder {
+    /// Mount into the rootfs before pivot_root. Mount sources are resolved
+    /// on the host.
+    #[default]
+    BeforePivot,
+    /// Mount relative to the new root after pivot_root. Mount sources are
+    /// resolved inside the container, which allows layered roots to bind
+    /// mount paths of a host `/` that was itself bound into the rootfs. The
+    /// devices and default symlinks are still created before pivot_root, so
+    /// the mount of `/dev` they are created in is applied before pivot_root
+    /// as well, with its source resolved on the host.
+    AfterPivot,
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum RootfsError {
@@ -27,6 +56,12 @@ pub enum RootfsError {
     Mount(#[from] mount::MountError),
     #[error(transparent)]
     Device(#[from] device::DeviceError),
+    #[error(transparent)]
+    HostnameFile(#[from] crate::hostname_file::HostnameFileError),
+    #[error(transparent)]
+    TmpfsLayer(#[from] tmpfs_layer::TmpfsLayerError),
+    #[error("rootfs setup wrote {written} bytes, more than the limit of {limit}")]
+    WriteLimitExceeded { written: u64, limit: u64 },
 }
 
 type Result<T> = std::result::Result<T, RootfsError>;
@@ -1,4 +1,4 @@
-use std::fs::{canonicalize, create_dir_all, OpenOptions};
+use std::fs::{self, canonicalize, create_dir_all, OpenOptions};
 use std::mem;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
@@ -23,6 +23,7 @@ use safe_path;
 use super::symlink::Symlink;
 use super::symlink::SymlinkError;
 use super::utils::{parse_mount, MountOptionConfig};
+use super::write_accounting::{self, WriteAccounting};
 use crate::syscall::syscall::create_syscall;
 use crate::syscall::{linux, Syscall, SyscallError};
 use crate::utils::{retry, PathBufExt};
@@ -58,6 +59,16 @@ pub enum MountError {
     Procfs(#[from] procfs::ProcError),
     #[error("unknown mount option: {0}")]
     UnsupportedMountOption(String),
+    #[error(
+        "bind mount source {src:?} is a {src_type}, but the destination {dest:?} is a {dest_type}: {hint}"
+    )]
+    TargetTypeMismatch {
+        src: PathBuf,
+        src_type: &'static str,
+        dest: PathBuf,
+        dest_type: &'static str,
+        hint: &'static str,
+    },
 }
 
 type Result<T> = std::result::Result<T, MountError>;
@@ -68,10 +79,19 @@ pub struct MountOptions<'a> {
     pub label: Option<&'a str>,
     #[allow(dead_code)]
     pub cgroup_ns: bool,
+    /// Whether the cgroup filesystem is read-only in the container's view.
+    /// The host mount is never affected, so the runtime can still write to
+    /// the cgroup of the container from outside.
+    pub cgroup_readonly: bool,
+    /// Whether an empty bind mount destination of the wrong type, i.e. a
+    /// directory for a file source or the reverse, is replaced by one of the
+    /// right type instead of failing the mount.
+    pub fix_target_type: bool,
 }
 
 pub struct Mount {
     syscall: Box<dyn Syscall>,
+    writes: WriteAccounting,
 }
 
 impl Default for Mount {
@@ -84,9 +104,16 @@ impl Mount {
     pub fn new() -> Mount {
         Mount {
             syscall: create_syscall(),
+            writes: WriteAccounting::default(),
         }
     }
 
+    /// Sets the accounting the created mount targets are recorded in
+    pub fn with_write_accounting(mut self, writes: WriteAccounting) -> Self {
+        self.writes = writes;
+        self
+    }
+
     pub fn setup_mount(&self, mount: &SpecMount, options: &MountOptions) -> Result<()> {
         tracing::debug!("mounting {:?}", mount);
         let mut mount_option_config = parse_mount(mount)?;
@@ -117,6 +144,8 @@ impl Mount {
                                 err
                             })?
                     }
+                    // detected from the host, which always has a hierarchy
+                    setup => unreachable!("the cgroup setup of the host is never {setup}"),
                 }
             }
             _ => {
@@ -127,6 +156,7 @@ impl Mount {
                         options.root,
                         &mount_option_config,
                         options.label,
+                        options.fix_target_type,
                     )
                     .map_err(|err| {
                         tracing::error!("failed to mount /dev: {}", err);
@@ -138,6 +168,7 @@ impl Mount {
                         options.root,
                         &mount_option_config,
                         options.label,
+                        options.fix_target_type,
                     )
                     .map_err(|err| {
                         tracing::error!("failed to mount {:?}: {}", mount, err);
@@ -225,7 +256,7 @@ impl Mount {
             })?;
         tracing::debug!("cgroup root: {:?}", cgroup_root);
 
-        let symlink = Symlink::new();
+        let symlink = Symlink::new().with_write_accounting(self.writes.clone());
 
         // setup cgroup mounts for container
         for host_mount in &host_mounts {
@@ -237,6 +268,12 @@ impl Mount {
                         subsystem_name,
                         subsystem_name == "systemd",
                     )?;
+                    if options.cgroup_readonly {
+                        self.remount_readonly(
+                            &cgroup_root.join(subsystem_name),
+                            MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                        )?;
+                    }
                 } else {
                     self.setup_emulated_subsystem(
                         cgroup_mount,
@@ -254,6 +291,15 @@ impl Mount {
             }
         }
 
+        // The subsystems are mounted into the tmpfs, so it can only be made
+        // read-only after all of them are in place.
+        if options.cgroup_readonly {
+            self.remount_readonly(
+                &cgroup_root,
+                MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            )?;
+        }
+
         Ok(())
     }
 
@@ -355,7 +401,7 @@ impl Mount {
                 )
                 .typ("bind")
                 .options(
-                    ["rw", "rbind"]
+                    [if options.cgroup_readonly { "ro" } else { "rw" }, "rbind"]
                         .iter()
                         .map(|o| o.to_string())
                         .collect::<Vec<String>>(),
@@ -383,6 +429,15 @@ impl Mount {
     ) -> Result<()> {
         tracing::debug!("Mounting cgroup v2 filesystem");
 
+        // A read-only cgroup filesystem is mounted read-write first and then
+        // remounted read-only as a bind mount, which only changes the flags of
+        // the mount in the container's mount namespace. Passing MS_RDONLY to
+        // the mount of the shared cgroup2 superblock must be avoided.
+        let readonly =
+            options.cgroup_readonly || mount_option_config.flags.contains(MsFlags::MS_RDONLY);
+        let mut mount_option_config = (*mount_option_config).clone();
+        mount_option_config.flags.remove(MsFlags::MS_RDONLY);
+
         let cgroup_mount = SpecMountBuilder::default()
             .typ("cgroup2")
             .source("cgroup")
@@ -395,7 +450,7 @@ impl Mount {
             .mount_into_container(
                 &cgroup_mount,
                 options.root,
-                mount_option_config,
+                &mount_option_config,
                 options.label,
             )
             .is_err()
@@ -436,7 +491,7 @@ impl Mount {
                 })?;
             tracing::debug!("{:?}", bind_mount);
 
-            let mut mount_option_config = (*mount_option_config).clone();
+            let mut mount_option_config = mount_option_config.clone();
             mount_option_config.flags |= MsFlags::MS_BIND;
             self.mount_into_container(
                 &bind_mount,
@@ -450,30 +505,54 @@ impl Mount {
             })?;
         }
 
+        if readonly {
+            let dest = options
+                .root
+                .join_safely(cgroup_mount.destination())
+                .map_err(|err| {
+                    tracing::error!(
+                        "could not join rootfs path with cgroup mount destination: {}",
+                        err
+                    );
+                    MountError::Other(err.into())
+                })?;
+            self.remount_readonly(&dest, mount_option_config.flags)?;
+        }
+
         Ok(())
     }
 
+    /// Remounts the mount at `dest` read-only. Because this is a bind remount,
+    /// only the flags of this mount point are changed, and neither the
+    /// superblock nor the mounts of the same filesystem on the host are.
+    #[cfg(any(feature = "v1", feature = "v2"))]
+    fn remount_readonly(&self, dest: &Path, flags: MsFlags) -> Result<()> {
+        self.syscall
+            .mount(
+                Some(dest),
+                dest,
+                None,
+                flags | MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                None,
+            )
+            .map_err(|err| {
+                tracing::error!(?dest, ?err, "failed to remount read-only");
+                err.into()
+            })
+    }
+
     /// Make parent mount of rootfs private if it was shared, which is required by pivot_root.
     /// It also makes sure following bind mount does not propagate in other namespaces.
+    /// If the rootfs already is a mount point, the mount it is mounted on is the parent.
     pub fn make_parent_mount_private(&self, rootfs: &Path) -> Result<Option<MountInfo>> {
-        let mount_infos = Process::myself()
-            .map_err(|err| {
-                tracing::error!("failed to get /proc/self: {}", err);
-                MountError::Other(err.into())
-            })?
-            .mountinfo()
-            .map_err(|err| {
-                tracing::error!("failed to get mount info: {}", err);
-                MountError::Other(err.into())
-            })?;
-        let parent_mount = find_parent_mount(rootfs, mount_infos.0)?;
+        let mount_infos = mount_infos()?
+            .into_iter()
+            .filter(|mi| mi.mount_point != rootfs)
+            .collect();
+        let parent_mount = find_parent_mount(rootfs, mount_infos)?;
 
         // check parent mount has 'shared' propagation type
-        if parent_mount
-            .opt_fields
-            .iter()
-            .any(|field| matches!(field, MountOptFields::Shared(_)))
-        {
+        if is_shared(&parent_mount) {
             self.syscall.mount(
                 None,
                 &parent_mount.mount_point,
@@ -493,6 +572,7 @@ impl Mount {
         rootfs: &Path,
         mount_option_config: &MountOptionConfig,
         label: Option<&str>,
+        fix_target_type: bool,
     ) -> Result<()> {
         let typ = m.typ().as_deref();
         let mut d = mount_option_config.data.to_string();
@@ -517,34 +597,15 @@ impl Mount {
         })?;
 
         let dest = Path::new(&dest_for_host);
+        // The target and the parents created for it count as writes.
+        let created = write_accounting::missing(dest);
         let source = m.source().as_ref().ok_or(MountError::NoSource)?;
         let src = if typ == Some("bind") {
             let src = canonicalize(source).map_err(|err| {
                 tracing::error!("failed to canonicalize {:?}: {}", source, err);
                 err
             })?;
-            let dir = if src.is_file() {
-                Path::new(&dest).parent().unwrap()
-            } else {
-                Path::new(&dest)
-            };
-
-            create_dir_all(dir).map_err(|err| {
-                tracing::error!("failed to create dir for bind mount {:?}: {}", dir, err);
-                err
-            })?;
-
-            if src.is_file() && !dest.exists() {
-                OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .write(true)
-                    .open(dest)
-                    .map_err(|err| {
-                        tracing::error!("failed to create file for bind mount {:?}: {}", src, err);
-                        err
-                    })?;
-            }
+            prepare_bind_target(&src, dest, fix_target_type)?;
 
             src
         } else {
@@ -555,6 +616,9 @@ impl Mount {
 
             PathBuf::from(source)
         };
+        for path in &created {
+            self.writes.record(path);
+        }
 
         if let Err(err) =
             self.syscall
@@ -632,6 +696,164 @@ impl Mount {
     }
 }
 
+fn file_type_name(is_dir: bool) -> &'static str {
+    if is_dir {
+        "directory"
+    } else {
+        "file"
+    }
+}
+
+/// Makes sure the destination of a bind mount exists with the type of the
+/// source. Anything but a directory, e.g. a device, is bound onto a file, like
+/// runc does. `dest` is expected to be securely joined to the rootfs already.
+/// A missing destination is created. A destination of the wrong type fails the
+/// mount with an error naming both paths, unless `fix_target_type` is set and
+/// it is empty, in which case it is replaced.
+fn prepare_bind_target(src: &Path, dest: &Path, fix_target_type: bool) -> Result<()> {
+    let src_is_dir = src.is_dir();
+    let dest_metadata = match fs::symlink_metadata(dest) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return create_bind_target(dest, src_is_dir);
+        }
+        Err(err) => {
+            tracing::error!(?dest, ?err, "failed to stat bind mount destination");
+            return Err(err.into());
+        }
+    };
+    let dest_is_dir = dest_metadata.is_dir();
+    if src_is_dir == dest_is_dir {
+        return Ok(());
+    }
+
+    let mismatch = |hint| {
+        tracing::error!(
+            ?src,
+            ?dest,
+            "bind mount source and destination have different types"
+        );
+        MountError::TargetTypeMismatch {
+            src: src.to_path_buf(),
+            src_type: file_type_name(src_is_dir),
+            dest: dest.to_path_buf(),
+            dest_type: file_type_name(dest_is_dir),
+            hint,
+        }
+    };
+    if !fix_target_type {
+        return Err(mismatch(
+            "change the mount destination or the image, or enable fix_mount_target_type to \
+             replace an empty destination",
+        ));
+    }
+
+    // Only an empty destination is replaced, nothing of the image is lost.
+    let removed = if dest_is_dir {
+        fs::remove_dir(dest)
+    } else if dest_metadata.len() == 0 {
+        fs::remove_file(dest)
+    } else {
+        Err(std::io::Error::from_raw_os_error(libc::ENOTEMPTY))
+    };
+    match removed {
+        Ok(()) => {
+            tracing::warn!(?dest, "replacing bind mount destination of the wrong type");
+            create_bind_target(dest, src_is_dir)
+        }
+        Err(err) if err.raw_os_error() == Some(libc::ENOTEMPTY) => Err(mismatch(
+            "the destination is not empty, so it can't be replaced, change the mount \
+             destination or the image",
+        )),
+        Err(err) => {
+            tracing::error!(?dest, ?err, "failed to remove bind mount destination");
+            Err(err.into())
+        }
+    }
+}
+
+fn create_bind_target(dest: &Path, is_dir: bool) -> Result<()> {
+    let dir = if is_dir {
+        dest
+    } else {
+        dest.parent().unwrap_or(dest)
+    };
+    create_dir_all(dir).map_err(|err| {
+        tracing::error!("failed to create dir for bind mount {:?}: {}", dir, err);
+        err
+    })?;
+
+    if !is_dir {
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(dest)
+            .map_err(|err| {
+                tracing::error!("failed to create file for bind mount {:?}: {}", dest, err);
+                err
+            })?;
+    }
+
+    Ok(())
+}
+
+/// How the rootfs is turned into a mount point, as pivot_root requires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootfsMountPlan {
+    /// If the rootfs is bind mounted onto itself. A rootfs which already is a
+    /// mount point is used as is, a bind would only stack another mount on it.
+    pub self_bind: bool,
+    /// Propagation the rootfs mount is set to before anything else is
+    /// mounted, so none of the following mounts reach the host
+    pub propagation: Option<MsFlags>,
+}
+
+impl RootfsMountPlan {
+    /// Decides how the rootfs is turned into a mount point, given the mounts
+    /// of the current mount namespace
+    pub fn new(rootfs: &Path, mount_infos: &[MountInfo]) -> Self {
+        // The last mount at the path is the one on top.
+        match mount_infos.iter().rev().find(|mi| mi.mount_point == rootfs) {
+            Some(rootfs_mount) => Self {
+                self_bind: false,
+                propagation: is_shared(rootfs_mount).then_some(MsFlags::MS_PRIVATE),
+            },
+            None => Self {
+                self_bind: true,
+                propagation: Some(MsFlags::MS_PRIVATE),
+            },
+        }
+    }
+
+    /// Decides how the rootfs is turned into a mount point, given the mounts
+    /// of the mount namespace of the current process
+    pub fn for_current(rootfs: &Path) -> Result<Self> {
+        Ok(Self::new(rootfs, &mount_infos()?))
+    }
+}
+
+fn mount_infos() -> Result<Vec<MountInfo>> {
+    let mount_infos = Process::myself()
+        .map_err(|err| {
+            tracing::error!("failed to get /proc/self: {}", err);
+            MountError::Other(err.into())
+        })?
+        .mountinfo()
+        .map_err(|err| {
+            tracing::error!("failed to get mount info: {}", err);
+            MountError::Other(err.into())
+        })?;
+    Ok(mount_infos.0)
+}
+
+fn is_shared(mount_info: &MountInfo) -> bool {
+    mount_info
+        .opt_fields
+        .iter()
+        .any(|field| matches!(field, MountOptFields::Shared(_)))
+}
+
 /// Find parent mount of rootfs in given mount infos
 pub fn find_parent_mount(
     rootfs: &Path,
@@ -654,6 +876,7 @@ mod tests {
     use std::fs;
 
     use anyhow::{Context, Ok, Result};
+    use procfs::FromRead;
 
     use super::*;
     use crate::syscall::test::{ArgName, MountArgs, TestHelperSyscall};
@@ -683,7 +906,8 @@ mod tests {
                     mount,
                     tmp_dir.path(),
                     &mount_option_config,
-                    Some("defaults")
+                    Some("defaults"),
+                    false,
                 )
                 .is_ok());
 
@@ -721,7 +945,7 @@ mod tests {
                 .open(tmp_dir.path().join("null"))?;
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)
+                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None, false)
                 .is_ok());
 
             let want = vec![
@@ -770,7 +994,7 @@ mod tests {
             syscall.set_ret_err_times(ArgName::Mount, 1);
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)
+                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None, false)
                 .is_ok());
             assert_eq!(syscall.get_mount_args().len(), 1);
         }
@@ -794,7 +1018,7 @@ mod tests {
             syscall.set_ret_err_times(ArgName::Mount, 2);
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)
+                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None, false)
                 .is_err());
             assert_eq!(syscall.get_mount_args().len(), 0);
         }
@@ -818,7 +1042,7 @@ mod tests {
             syscall.set_ret_err_times(ArgName::Mount, MAX_EBUSY_MOUNT_ATTEMPTS as usize - 1);
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)
+                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None, false)
                 .is_ok());
             assert_eq!(syscall.get_mount_args().len(), 1);
         }
@@ -842,7 +1066,7 @@ mod tests {
             syscall.set_ret_err_times(ArgName::Mount, MAX_EBUSY_MOUNT_ATTEMPTS as usize);
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)
+                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None, false)
                 .is_err());
             assert_eq!(syscall.get_mount_args().len(), 0);
         }
@@ -850,6 +1074,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_prepare_bind_target() -> Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let src_file = tmp_dir.path().join("src-file");
+        let src_dir = tmp_dir.path().join("src-dir");
+        std::fs::write(&src_file, "data")?;
+        std::fs::create_dir(&src_dir)?;
+
+        // missing destinations are created with the type of the source
+        let dest = tmp_dir.path().join("missing/file");
+        prepare_bind_target(&src_file, &dest, false)?;
+        assert!(dest.is_file());
+        let dest = tmp_dir.path().join("missing/dir");
+        prepare_bind_target(&src_dir, &dest, false)?;
+        assert!(dest.is_dir());
+
+        // a file onto a directory fails unless fixing is enabled
+        let dest = tmp_dir.path().join("empty-dir");
+        std::fs::create_dir(&dest)?;
+        let err = prepare_bind_target(&src_file, &dest, false).unwrap_err();
+        assert!(matches!(
+            err,
+            MountError::TargetTypeMismatch {
+                src_type: "file",
+                dest_type: "directory",
+                ..
+            }
+        ));
+        assert!(dest.is_dir());
+        prepare_bind_target(&src_file, &dest, true)?;
+        assert!(dest.is_file());
+
+        // a directory onto an empty file is fixed the same way
+        let dest = tmp_dir.path().join("empty-file");
+        std::fs::write(&dest, "")?;
+        assert!(prepare_bind_target(&src_dir, &dest, false).is_err());
+        prepare_bind_target(&src_dir, &dest, true)?;
+        assert!(dest.is_dir());
+
+        // non empty destinations are never replaced
+        let dest = tmp_dir.path().join("full-dir");
+        std::fs::create_dir(&dest)?;
+        std::fs::write(dest.join("keep"), "data")?;
+        assert!(prepare_bind_target(&src_file, &dest, true).is_err());
+        assert!(dest.join("keep").exists());
+        let dest = tmp_dir.path().join("full-file");
+        std::fs::write(&dest, "data")?;
+        assert!(prepare_bind_target(&src_dir, &dest, true).is_err());
+        assert!(dest.is_file());
+
+        Ok(())
+    }
+
     #[test]
     fn test_make_parent_mount_private() -> Result<()> {
         let tmp_dir = tempfile::tempdir()?;
@@ -900,6 +1177,8 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            cgroup_readonly: false,
+            fix_target_type: false,
         };
 
         let subsystem_name = "cpu";
@@ -955,6 +1234,8 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: false,
+            cgroup_readonly: false,
+            fix_target_type: false,
         };
 
         let subsystem_name = "cpu";
@@ -1016,6 +1297,8 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            cgroup_readonly: false,
+            fix_target_type: false,
         };
 
         let mounter = Mount::new();
@@ -1089,6 +1372,8 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            cgroup_readonly: false,
+            fix_target_type: false,
         };
 
         let mounter = Mount::new();
@@ -1126,6 +1411,73 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[cfg(feature = "v2")]
+    fn test_mount_cgroup_v2_readonly() -> Result<()> {
+        // arrange
+        let tmp = tempfile::tempdir().unwrap();
+        let container_cgroup = PathBuf::from("/sys/fs/cgroup");
+
+        let spec_cgroup_mount = SpecMountBuilder::default()
+            .destination(&container_cgroup)
+            .source("cgroup")
+            .typ("cgroup")
+            .build()
+            .context("failed to build cgroup mount")?;
+
+        let mount_opts = MountOptions {
+            root: tmp.path(),
+            label: None,
+            cgroup_ns: true,
+            cgroup_readonly: true,
+            fix_target_type: false,
+        };
+
+        let mounter = Mount::new();
+        let flags = MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+
+        // act
+        let mount_option_config = MountOptionConfig {
+            flags,
+            data: String::new(),
+            rec_attr: None,
+        };
+        mounter
+            .mount_cgroup_v2(&spec_cgroup_mount, &mount_opts, &mount_option_config)
+            .context("failed to mount cgroup v2")?;
+
+        // assert
+        let target = tmp.path().join_safely(container_cgroup)?;
+        let expected = vec![
+            // the cgroup filesystem itself is never mounted read-only
+            MountArgs {
+                source: Some(PathBuf::from("cgroup".to_owned())),
+                target: target.clone(),
+                fstype: Some("cgroup2".to_owned()),
+                flags,
+                data: Some("".to_owned()),
+            },
+            MountArgs {
+                source: Some(target.clone()),
+                target,
+                fstype: None,
+                flags: flags | MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                data: None,
+            },
+        ];
+
+        let got = mounter
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+
+        assert_eq!(expected, got);
+
+        Ok(())
+    }
+
     #[test]
     fn test_find_parent_mount() -> anyhow::Result<()> {
         let mount_infos = vec![
@@ -1161,6 +1513,63 @@ mod tests {
         Ok(())
     }
 
+    // /proc/self/mountinfo of a host with a shared root, the rootfs of the
+    // container is an overlay mounted at /run/bundle/rootfs
+    const MOUNTINFO_ROOTFS_MOUNTED: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:5 - proc proc rw
+95 22 0:50 / /run/bundle/rootfs rw,relatime shared:40 - overlay overlay rw,lowerdir=/l,upperdir=/u,workdir=/w
+";
+    // the rootfs of the container is a plain directory of the shared root
+    const MOUNTINFO_ROOTFS_DIRECTORY: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:5 - proc proc rw
+";
+
+    fn parse_mountinfo(mountinfo: &str) -> Vec<MountInfo> {
+        procfs::process::MountInfos::from_read(mountinfo.as_bytes())
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_rootfs_mount_plan_mount_point() {
+        let mut mount_infos = parse_mountinfo(MOUNTINFO_ROOTFS_MOUNTED);
+        let rootfs = Path::new("/run/bundle/rootfs");
+        assert_eq!(
+            RootfsMountPlan::new(rootfs, &mount_infos),
+            RootfsMountPlan {
+                self_bind: false,
+                propagation: Some(MsFlags::MS_PRIVATE),
+            }
+        );
+
+        // a private mount point is used as is
+        mount_infos[2].opt_fields.clear();
+        assert_eq!(
+            RootfsMountPlan::new(rootfs, &mount_infos),
+            RootfsMountPlan {
+                self_bind: false,
+                propagation: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rootfs_mount_plan_directory() {
+        let mount_infos = parse_mountinfo(MOUNTINFO_ROOTFS_DIRECTORY);
+        assert_eq!(
+            RootfsMountPlan::new(Path::new("/run/bundle/rootfs"), &mount_infos),
+            RootfsMountPlan {
+                self_bind: true,
+                propagation: Some(MsFlags::MS_PRIVATE),
+            }
+        );
+        // a mount below the rootfs doesn't make it a mount point
+        let mount_infos = parse_mountinfo(MOUNTINFO_ROOTFS_MOUNTED);
+        assert!(RootfsMountPlan::new(Path::new("/run/bundle"), &mount_infos).self_bind);
+    }
+
     #[test]
     fn test_find_parent_mount_with_empty_mount_infos() {
         let mount_infos = vec![];
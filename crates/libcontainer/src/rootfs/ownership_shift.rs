@@ -0,0 +1,604 @@
+//! Shifting of the ownership of the rootfs into a user namespace
+//!
+//! The files of a rootfs unpacked for the host are owned by the ids of the
+//! image, e.g. root, which a container in a user namespace sees as the
+//! overflow ids. Without idmapped mounts, the ownership of every inode is
+//! shifted to the host ids its ids are mapped to instead, like an external
+//! `chown -R` would, while keeping the setuid and setgid bits and the file
+//! capabilities the chown clears, and shifting hardlinked inodes only once.
+//!
+//! The shift needs the container ranges of the mappings not to overlap their
+//! host ranges, so a shifted id can be told from one that still has to be.
+//! This makes an interrupted shift resumable, walking the rootfs again only
+//! shifts the inodes that aren't yet. A marker file next to the rootfs
+//! records the mappings, so a later create of the same rootfs skips the walk
+//! and one with other mappings fails instead of shifting twice.
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs::{self, Metadata, Permissions};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{lchown, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use oci_spec::runtime::{LinuxIdMapping, Spec};
+
+/// Upper bound of the inodes the rootfs may have
+pub const DEFAULT_MAX_FILES: u64 = 1_000_000;
+/// Upper bound of the time a single walk of the rootfs may take. A walk that
+/// runs out of time is resumed by the next create.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+/// Xattr with the file capabilities, cleared by the kernel on a chown
+const CAPABILITY_XATTR: &str = "security.capability";
+/// Size of the file capabilities of revision 3, which name a root uid
+const VFS_CAP_V3_SIZE: usize = 24;
+const VFS_CAP_REVISION_MASK: u32 = 0xff00_0000;
+const VFS_CAP_REVISION_3: u32 = 0x0300_0000;
+const MARKER_DONE: &str = "done";
+const MARKER_IN_PROGRESS: &str = "in-progress";
+
+#[derive(Debug, thiserror::Error)]
+pub enum OwnershipShiftError {
+    #[error("the rootfs ownership can't be shifted for a spec with idmapped mounts")]
+    IdmappedMounts,
+    #[error("container ids {container_id}..+{size} overlap the host ids of the mappings")]
+    OverlappingMappings { container_id: u32, size: u32 },
+    #[error("rootfs {rootfs:?} was shifted for other mappings: {marker:?}")]
+    MappingsChanged { rootfs: PathBuf, marker: String },
+    #[error("rootfs has more than {max_files} files to shift")]
+    TooManyFiles { max_files: u64 },
+    #[error("shifting the rootfs took longer than {timeout:?}, it is resumed by the next create")]
+    Timeout { timeout: Duration },
+    #[error("failed to shift the ownership of {path:?}")]
+    Shift {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("failed to write the marker {path:?}")]
+    Marker {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, OwnershipShiftError>;
+
+/// Bounds of a walk of the rootfs
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub max_files: u64,
+    pub timeout: Duration,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            max_files: DEFAULT_MAX_FILES,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// The uid and gid mappings the ownership is shifted with. Identity mappings
+/// are left out, their ids need no shift.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdShift {
+    uid_mappings: Vec<LinuxIdMapping>,
+    gid_mappings: Vec<LinuxIdMapping>,
+}
+
+impl IdShift {
+    pub fn new(uid_mappings: &[LinuxIdMapping], gid_mappings: &[LinuxIdMapping]) -> Result<Self> {
+        let shifting = |mappings: &[LinuxIdMapping]| -> Result<Vec<LinuxIdMapping>> {
+            let mappings: Vec<_> = mappings
+                .iter()
+                .filter(|m| m.container_id() != m.host_id())
+                .cloned()
+                .collect();
+            for mapping in &mappings {
+                let overlaps = mappings.iter().any(|other| {
+                    ranges_overlap(
+                        mapping.container_id(),
+                        mapping.size(),
+                        other.host_id(),
+                        other.size(),
+                    )
+                });
+                if overlaps {
+                    tracing::error!(?mapping, "container ids of the mapping overlap host ids");
+                    return Err(OwnershipShiftError::OverlappingMappings {
+                        container_id: mapping.container_id(),
+                        size: mapping.size(),
+                    });
+                }
+            }
+            Ok(mappings)
+        };
+
+        Ok(Self {
+            uid_mappings: shifting(uid_mappings)?,
+            gid_mappings: shifting(gid_mappings)?,
+        })
+    }
+
+    /// Returns the shift of the user namespace of the spec, or `None` if the
+    /// spec has no id mappings
+    pub fn from_spec(spec: &Spec) -> Result<Option<Self>> {
+        let linux = match spec.linux() {
+            Some(linux) => linux,
+            None => return Ok(None),
+        };
+        let uid_mappings = linux.uid_mappings().clone().unwrap_or_default();
+        let gid_mappings = linux.gid_mappings().clone().unwrap_or_default();
+        if uid_mappings.is_empty() && gid_mappings.is_empty() {
+            return Ok(None);
+        }
+        Self::new(&uid_mappings, &gid_mappings).map(Some)
+    }
+
+    /// Returns the host id a container uid is shifted to, or `None` if it
+    /// needs no shift
+    pub fn uid(&self, id: u32) -> Option<u32> {
+        shift_id(id, &self.uid_mappings)
+    }
+
+    /// Returns the host id a container gid is shifted to, or `None` if it
+    /// needs no shift
+    pub fn gid(&self, id: u32) -> Option<u32> {
+        shift_id(id, &self.gid_mappings)
+    }
+
+    /// The mappings as they are recorded in the marker
+    fn describe(&self) -> String {
+        let describe = |mappings: &[LinuxIdMapping]| {
+            mappings
+                .iter()
+                .map(|m| format!("{}:{}:{}", m.container_id(), m.host_id(), m.size()))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        format!(
+            "uid={} gid={}",
+            describe(&self.uid_mappings),
+            describe(&self.gid_mappings)
+        )
+    }
+}
+
+fn ranges_overlap(a: u32, a_len: u32, b: u32, b_len: u32) -> bool {
+    let (a, a_len, b, b_len) = (a as u64, a_len as u64, b as u64, b_len as u64);
+    a < b + b_len && b < a + a_len
+}
+
+fn shift_id(id: u32, mappings: &[LinuxIdMapping]) -> Option<u32> {
+    mappings
+        .iter()
+        .find(|m| id >= m.container_id() && (id - m.container_id()) < m.size())
+        .map(|m| m.host_id() + (id - m.container_id()))
+}
+
+/// Returns if a mount of the spec is idmapped
+pub fn uses_idmapped_mounts(spec: &Spec) -> bool {
+    spec.mounts().iter().flatten().any(|mount| {
+        mount.uid_mappings().is_some()
+            || mount.gid_mappings().is_some()
+            || mount
+                .options()
+                .iter()
+                .flatten()
+                .any(|option| option == "idmap" || option == "ridmap")
+    })
+}
+
+/// Path of the marker of a rootfs, next to it so it isn't visible in the
+/// container
+pub fn marker_path(rootfs: &Path) -> PathBuf {
+    let name = rootfs
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    rootfs
+        .parent()
+        .unwrap_or(rootfs)
+        .join(format!(".{name}.youki-shifted"))
+}
+
+/// Shifts the ownership of the rootfs into the user namespace of the spec,
+/// unless the marker says it already is. A spec without id mappings is left
+/// as is, one with idmapped mounts is refused, as their ids would be shifted
+/// twice.
+pub fn apply(spec: &Spec, rootfs: &Path, budget: Budget) -> Result<()> {
+    if uses_idmapped_mounts(spec) {
+        tracing::error!("rootfs ownership shift conflicts with idmapped mounts");
+        return Err(OwnershipShiftError::IdmappedMounts);
+    }
+    let shift = match IdShift::from_spec(spec)? {
+        Some(shift) => shift,
+        None => {
+            tracing::warn!("spec has no id mappings, the rootfs ownership isn't shifted");
+            return Ok(());
+        }
+    };
+
+    let marker = marker_path(rootfs);
+    let mappings = shift.describe();
+    if let Ok(content) = fs::read_to_string(&marker) {
+        let (state, recorded) = content.trim_end().split_once(' ').unwrap_or_default();
+        if recorded != mappings {
+            tracing::error!(
+                ?rootfs,
+                recorded,
+                mappings,
+                "rootfs was shifted for other mappings"
+            );
+            return Err(OwnershipShiftError::MappingsChanged {
+                rootfs: rootfs.to_path_buf(),
+                marker: recorded.to_owned(),
+            });
+        }
+        if state == MARKER_DONE {
+            tracing::debug!(?rootfs, "rootfs ownership is already shifted");
+            return Ok(());
+        }
+        tracing::info!(?rootfs, "resuming the rootfs ownership shift");
+    }
+
+    write_marker(&marker, MARKER_IN_PROGRESS, &mappings)?;
+    let shifted = shift_tree(rootfs, &shift, budget)?;
+    write_marker(&marker, MARKER_DONE, &mappings)?;
+    tracing::info!(?rootfs, shifted, "shifted the rootfs ownership");
+
+    Ok(())
+}
+
+fn write_marker(path: &Path, state: &str, mappings: &str) -> Result<()> {
+    fs::write(path, format!("{state} {mappings}\n")).map_err(|err| {
+        tracing::error!(?path, ?err, "failed to write the ownership shift marker");
+        OwnershipShiftError::Marker {
+            path: path.to_path_buf(),
+            err,
+        }
+    })
+}
+
+/// Shifts the ownership of every inode below `root` that isn't shifted yet,
+/// without following symlinks or crossing into other filesystems. Returns
+/// the number of inodes shifted.
+pub fn shift_tree(root: &Path, shift: &IdShift, budget: Budget) -> Result<u64> {
+    let started = Instant::now();
+    let metadata = symlink_metadata(root)?;
+    let device = metadata.dev();
+    let mut hardlinks = HashSet::new();
+    let mut visited = 0;
+    let mut shifted = 0;
+    let mut pending = vec![(root.to_path_buf(), metadata)];
+    while let Some((path, metadata)) = pending.pop() {
+        visited += 1;
+        if visited > budget.max_files {
+            return Err(OwnershipShiftError::TooManyFiles {
+                max_files: budget.max_files,
+            });
+        }
+        if started.elapsed() > budget.timeout {
+            return Err(OwnershipShiftError::Timeout {
+                timeout: budget.timeout,
+            });
+        }
+
+        // Every link of an inode has the same owner, it is shifted once.
+        if !metadata.is_dir()
+            && metadata.nlink() > 1
+            && !hardlinks.insert((metadata.dev(), metadata.ino()))
+        {
+            continue;
+        }
+        if shift_inode(&path, &metadata, shift)? {
+            shifted += 1;
+        }
+
+        if metadata.is_dir() {
+            let entries = fs::read_dir(&path).map_err(|err| shift_error(&path, err))?;
+            for entry in entries {
+                let entry = entry.map_err(|err| shift_error(&path, err))?;
+                let child = entry.path();
+                let child_metadata = symlink_metadata(&child)?;
+                if child_metadata.dev() != device {
+                    tracing::debug!(path = ?child, "not shifting a mount in the rootfs");
+                    continue;
+                }
+                pending.push((child, child_metadata));
+            }
+        }
+    }
+
+    Ok(shifted)
+}
+
+fn symlink_metadata(path: &Path) -> Result<Metadata> {
+    fs::symlink_metadata(path).map_err(|err| shift_error(path, err))
+}
+
+fn shift_error(path: &Path, err: std::io::Error) -> OwnershipShiftError {
+    tracing::error!(?path, ?err, "failed to shift the ownership");
+    OwnershipShiftError::Shift {
+        path: path.to_path_buf(),
+        err,
+    }
+}
+
+/// Shifts the owner of the inode, returns false if it needed no shift
+fn shift_inode(path: &Path, metadata: &Metadata, shift: &IdShift) -> Result<bool> {
+    let uid = shift.uid(metadata.uid());
+    let gid = shift.gid(metadata.gid());
+    if uid.is_none() && gid.is_none() {
+        return Ok(false);
+    }
+
+    // The chown clears the file capabilities and the setuid and setgid bits.
+    let capabilities = if metadata.is_file() {
+        get_xattr(path, CAPABILITY_XATTR).map_err(|err| shift_error(path, err))?
+    } else {
+        None
+    };
+    lchown(path, uid, gid).map_err(|err| shift_error(path, err))?;
+    let mode = metadata.mode();
+    if !metadata.file_type().is_symlink() && mode & (libc::S_ISUID | libc::S_ISGID) != 0 {
+        fs::set_permissions(path, Permissions::from_mode(mode & 0o7777))
+            .map_err(|err| shift_error(path, err))?;
+    }
+    if let Some(capabilities) = capabilities {
+        let capabilities = shift_capabilities_root(capabilities, shift);
+        set_xattr(path, CAPABILITY_XATTR, &capabilities).map_err(|err| shift_error(path, err))?;
+    }
+
+    Ok(true)
+}
+
+/// File capabilities of revision 3 only apply in the user namespace whose
+/// root has the uid they name, which is shifted like the owner
+fn shift_capabilities_root(mut capabilities: Vec<u8>, shift: &IdShift) -> Vec<u8> {
+    if capabilities.len() != VFS_CAP_V3_SIZE {
+        return capabilities;
+    }
+    let magic = u32::from_le_bytes(capabilities[0..4].try_into().unwrap());
+    if magic & VFS_CAP_REVISION_MASK != VFS_CAP_REVISION_3 {
+        return capabilities;
+    }
+    let root = u32::from_le_bytes(capabilities[20..24].try_into().unwrap());
+    if let Some(shifted) = shift.uid(root) {
+        capabilities[20..24].copy_from_slice(&shifted.to_le_bytes());
+    }
+    capabilities
+}
+
+fn c_path(path: &Path) -> std::io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+}
+
+fn get_xattr(path: &Path, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+    let c_path = c_path(path)?;
+    let c_name = CString::new(name).unwrap();
+    let mut value = vec![0u8; 64];
+    loop {
+        let size = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if size >= 0 {
+            value.truncate(size as usize);
+            return Ok(Some(value));
+        }
+        let err = std::io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => return Ok(None),
+            Some(libc::ERANGE) => value.resize(value.len() * 2, 0),
+            _ => return Err(err),
+        }
+    }
+}
+
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    let c_path = c_path(path)?;
+    let c_name = CString::new(name).unwrap();
+    let ret = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use anyhow::Result;
+    use nix::unistd::geteuid;
+    use oci_spec::runtime::{LinuxIdMappingBuilder, MountBuilder};
+
+    use super::*;
+
+    fn mapping(container_id: u32, host_id: u32, size: u32) -> LinuxIdMapping {
+        LinuxIdMappingBuilder::default()
+            .container_id(container_id)
+            .host_id(host_id)
+            .size(size)
+            .build()
+            .unwrap()
+    }
+
+    fn id_shift() -> IdShift {
+        IdShift::new(&[mapping(0, 100000, 65536)], &[mapping(0, 200000, 65536)]).unwrap()
+    }
+
+    #[test]
+    fn test_shift_id() {
+        let shift = id_shift();
+        assert_eq!(shift.uid(0), Some(100000));
+        assert_eq!(shift.uid(1000), Some(101000));
+        assert_eq!(shift.gid(5), Some(200005));
+        // already shifted or unmapped
+        assert_eq!(shift.uid(100000), None);
+        assert_eq!(shift.uid(65536), None);
+
+        let identity = IdShift::new(&[mapping(0, 0, 65536)], &[]).unwrap();
+        assert_eq!(identity.uid(0), None);
+    }
+
+    #[test]
+    fn test_overlapping_mappings() {
+        assert!(matches!(
+            IdShift::new(&[mapping(0, 1000, 65536)], &[]),
+            Err(OwnershipShiftError::OverlappingMappings { .. })
+        ));
+    }
+
+    #[test]
+    fn test_idmapped_mounts_are_refused() -> Result<()> {
+        let mut spec = Spec::default();
+        let mut mounts = spec.mounts().clone().unwrap_or_default();
+        mounts.push(
+            MountBuilder::default()
+                .destination("/data")
+                .source("/data")
+                .typ("bind")
+                .options(vec!["rbind".to_owned(), "idmap".to_owned()])
+                .build()?,
+        );
+        spec.set_mounts(Some(mounts));
+        assert!(uses_idmapped_mounts(&spec));
+        assert!(matches!(
+            apply(&spec, Path::new("/nonexistent"), Budget::default()),
+            Err(OwnershipShiftError::IdmappedMounts)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shift_capabilities_root() {
+        let mut v3 = Vec::new();
+        v3.extend_from_slice(&(VFS_CAP_REVISION_3 | 1).to_le_bytes());
+        v3.extend_from_slice(&[0xff; 16]);
+        v3.extend_from_slice(&0u32.to_le_bytes());
+        let shifted = shift_capabilities_root(v3.clone(), &id_shift());
+        assert_eq!(&shifted[..20], &v3[..20]);
+        assert_eq!(&shifted[20..], &100000u32.to_le_bytes());
+
+        // revision 2 names no root
+        let v2 = vec![0x01, 0x00, 0x00, 0x02, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0];
+        assert_eq!(shift_capabilities_root(v2.clone(), &id_shift()), v2);
+    }
+
+    #[test]
+    fn test_shift_tree() -> Result<()> {
+        // The ownership can only be changed to other ids by root.
+        if !geteuid().is_root() {
+            return Ok(());
+        }
+        let tmp = tempfile::tempdir()?;
+        let rootfs = tmp.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("usr/bin"))?;
+        fs::write(rootfs.join("usr/bin/ping"), "")?;
+        fs::set_permissions(rootfs.join("usr/bin/ping"), Permissions::from_mode(0o4755))?;
+        fs::hard_link(rootfs.join("usr/bin/ping"), rootfs.join("usr/bin/ping6"))?;
+        symlink("usr/bin", rootfs.join("bin"))?;
+        // A symlink out of the rootfs is shifted, not its target.
+        let outside = tmp.path().join("outside");
+        fs::write(&outside, "")?;
+        symlink(&outside, rootfs.join("escape"))?;
+        for path in ["", "usr", "usr/bin", "usr/bin/ping", "bin", "escape"] {
+            lchown(rootfs.join(path), Some(0), Some(0))?;
+        }
+        lchown(&outside, Some(0), Some(0))?;
+        // revision 2 capabilities, cap_net_raw permitted and effective
+        let capabilities = [
+            0x01, 0x00, 0x00, 0x02, 0x00, 0x20, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        set_xattr(
+            &rootfs.join("usr/bin/ping"),
+            CAPABILITY_XATTR,
+            &capabilities,
+        )?;
+
+        let shifted = shift_tree(&rootfs, &id_shift(), Budget::default())?;
+        // rootfs, usr, usr/bin, ping once for both links, bin and escape
+        assert_eq!(shifted, 6);
+        for path in ["", "usr/bin", "usr/bin/ping6", "bin", "escape"] {
+            let metadata = fs::symlink_metadata(rootfs.join(path))?;
+            assert_eq!((metadata.uid(), metadata.gid()), (100000, 200000), "{path}");
+        }
+        let ping = fs::metadata(rootfs.join("usr/bin/ping"))?;
+        assert_eq!(ping.mode() & 0o7777, 0o4755);
+        assert_eq!(
+            get_xattr(&rootfs.join("usr/bin/ping"), CAPABILITY_XATTR)?.as_deref(),
+            Some(&capabilities[..])
+        );
+        assert_eq!(fs::metadata(&outside)?.uid(), 0);
+
+        // A second walk finds nothing left to shift.
+        assert_eq!(shift_tree(&rootfs, &id_shift(), Budget::default())?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shift_tree_budget() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for name in ["a", "b", "c"] {
+            fs::write(tmp.path().join(name), "")?;
+        }
+        let budget = Budget {
+            max_files: 2,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        // shifts no id, so the walk doesn't need root
+        let shift = IdShift::new(&[mapping(4_000_000_000, 100000, 10)], &[]).unwrap();
+        assert!(matches!(
+            shift_tree(tmp.path(), &shift, budget),
+            Err(OwnershipShiftError::TooManyFiles { max_files: 2 })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_marker() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let rootfs = tmp.path().join("rootfs");
+        fs::create_dir(&rootfs)?;
+        let mut spec = Spec::default();
+        let mut linux = spec.linux().clone().unwrap_or_default();
+        linux
+            .set_uid_mappings(Some(vec![mapping(0, 100000, 65536)]))
+            .set_gid_mappings(Some(vec![mapping(0, 100000, 65536)]));
+        spec.set_linux(Some(linux));
+        let shift = IdShift::from_spec(&spec)?.unwrap();
+
+        // already shifted for the mappings
+        let marker = marker_path(&rootfs);
+        assert_eq!(marker, tmp.path().join(".rootfs.youki-shifted"));
+        fs::write(&marker, format!("{MARKER_DONE} {}\n", shift.describe()))?;
+        apply(&spec, &rootfs, Budget::default())?;
+
+        // shifted for other mappings
+        fs::write(&marker, format!("{MARKER_DONE} uid=0:300000:65536 gid=\n"))?;
+        assert!(matches!(
+            apply(&spec, &rootfs, Budget::default()),
+            Err(OwnershipShiftError::MappingsChanged { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+//! Warming of the page cache for the first start of a container
+//!
+//! A container that starts from a cold page cache faults in its entrypoint
+//! and every shared library page by page. Before the container process is
+//! created, the entrypoint, its ELF interpreter and the libraries it needs are
+//! looked up in the rootfs and the kernel is advised to read them ahead. This
+//! is best effort, a file that can't be found or read is skipped.
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+
+use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+use oci_spec::runtime::Process;
+
+/// Directories the libraries are looked up in, relative to the rootfs
+const LIBRARY_DIRS: &[&str] = &[
+    "/lib64",
+    "/usr/lib64",
+    "/lib",
+    "/usr/lib",
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+    "/usr/local/lib",
+];
+/// Upper bound of the files that are warmed, against dependency explosions
+const MAX_FILES: usize = 256;
+const MAX_SYMLINKS: usize = 40;
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Advises the kernel to read the entrypoint of the process and the shared
+/// libraries it loads into the page cache. Returns the host paths of the
+/// files that were warmed.
+pub fn prewarm_rootfs(rootfs: &Path, process: &Process) -> Vec<PathBuf> {
+    let entrypoint = match process.args().as_ref().and_then(|args| args.first()) {
+        Some(entrypoint) => entrypoint,
+        None => return Vec::new(),
+    };
+    let path_var = process
+        .env()
+        .iter()
+        .flatten()
+        .find_map(|env| env.strip_prefix("PATH="))
+        .unwrap_or(DEFAULT_PATH);
+    let entrypoint = match find_executable(rootfs, entrypoint, path_var) {
+        Some(entrypoint) => entrypoint,
+        None => {
+            tracing::debug!(?entrypoint, "entrypoint to prewarm not found in rootfs");
+            return Vec::new();
+        }
+    };
+
+    let mut warmed = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![entrypoint];
+    while let Some(path) = pending.pop() {
+        if warmed.len() >= MAX_FILES || !seen.insert(path.clone()) {
+            continue;
+        }
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                tracing::debug!(?path, ?err, "failed to open file to prewarm");
+                continue;
+            }
+        };
+        if let Err(err) = posix_fadvise(
+            file.as_raw_fd(),
+            0,
+            0,
+            PosixFadviseAdvice::POSIX_FADV_WILLNEED,
+        ) {
+            tracing::debug!(?path, ?err, "failed to prewarm file");
+            continue;
+        }
+
+        match elf_dependencies(&mut file) {
+            Ok(deps) => {
+                if let Some(interpreter) = deps.interpreter {
+                    pending.extend(resolve_in_rootfs(rootfs, Path::new(&interpreter)));
+                }
+                pending.extend(
+                    deps.needed
+                        .iter()
+                        .filter_map(|library| find_library(rootfs, library)),
+                );
+            }
+            Err(err) => tracing::debug!(?path, ?err, "no dependencies of file to prewarm"),
+        }
+        warmed.push(path);
+    }
+    tracing::debug!(files = warmed.len(), "prewarmed rootfs");
+
+    warmed
+}
+
+fn find_executable(rootfs: &Path, name: &str, path_var: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return resolve_in_rootfs(rootfs, Path::new(name)).filter(|path| path.is_file());
+    }
+    path_var
+        .split(':')
+        .filter_map(|dir| resolve_in_rootfs(rootfs, &Path::new(dir).join(name)))
+        .find(|path| path.is_file())
+}
+
+fn find_library(rootfs: &Path, library: &str) -> Option<PathBuf> {
+    if library.contains('/') {
+        return resolve_in_rootfs(rootfs, Path::new(library)).filter(|path| path.is_file());
+    }
+    LIBRARY_DIRS
+        .iter()
+        .filter_map(|dir| resolve_in_rootfs(rootfs, &Path::new(dir).join(library)))
+        .find(|path| path.is_file())
+}
+
+/// Resolves a path of the container to a path on the host, following
+/// symlinks as if the rootfs was the root, so no link leads out of it
+pub(crate) fn resolve_in_rootfs(rootfs: &Path, path: &Path) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    let mut components: Vec<PathBuf> = path
+        .components()
+        .rev()
+        .map(|c| PathBuf::from(c.as_os_str()))
+        .collect();
+    let mut symlinks = 0;
+    while let Some(component) = components.pop() {
+        match component.components().next()? {
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                let candidate = resolved.join(name);
+                let host_path = rootfs.join(&candidate);
+                match host_path.symlink_metadata() {
+                    Ok(metadata) if metadata.file_type().is_symlink() => {
+                        symlinks += 1;
+                        if symlinks > MAX_SYMLINKS {
+                            return None;
+                        }
+                        let target = host_path.read_link().ok()?;
+                        if target.is_absolute() {
+                            resolved = PathBuf::new();
+                        }
+                        components.extend(
+                            target
+                                .components()
+                                .rev()
+                                .map(|c| PathBuf::from(c.as_os_str())),
+                        );
+                    }
+                    Ok(_) => resolved = candidate,
+                    Err(_) => return None,
+                }
+            }
+            Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(rootfs.join(resolved))
+}
+
+#[derive(Debug, Default)]
+struct ElfDependencies {
+    interpreter: Option<String>,
+    needed: Vec<String>,
+}
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+
+/// Reads the interpreter and the needed libraries of a 64-bit little endian
+/// ELF file, other files have no dependencies
+fn elf_dependencies(file: &mut File) -> io::Result<ElfDependencies> {
+    let mut header = [0u8; 64];
+    file.read_exact(&mut header)?;
+    // 64-bit, little endian
+    if &header[..4] != b"\x7fELF" || header[4] != 2 || header[5] != 1 {
+        return Ok(ElfDependencies::default());
+    }
+    let phoff = u64_at(&header, 0x20);
+    let phentsize = u16_at(&header, 0x36) as u64;
+    let phnum = u16_at(&header, 0x38) as u64;
+    if phentsize < 56 || phnum > 256 {
+        return Ok(ElfDependencies::default());
+    }
+
+    let mut deps = ElfDependencies::default();
+    // (vaddr, offset, filesz) of the loaded segments
+    let mut loads = Vec::new();
+    let mut dynamic = None;
+    for i in 0..phnum {
+        let mut phdr = [0u8; 56];
+        file.seek(SeekFrom::Start(phoff + i * phentsize))?;
+        file.read_exact(&mut phdr)?;
+        let offset = u64_at(&phdr, 0x08);
+        let filesz = u64_at(&phdr, 0x20);
+        match u32_at(&phdr, 0) {
+            PT_LOAD => loads.push((u64_at(&phdr, 0x10), offset, filesz)),
+            PT_DYNAMIC => dynamic = Some((offset, filesz)),
+            PT_INTERP => deps.interpreter = read_str(file, offset, filesz.min(4096))?,
+            _ => {}
+        }
+    }
+
+    let (offset, size) = match dynamic {
+        Some(dynamic) => dynamic,
+        None => return Ok(deps),
+    };
+    let mut entries = vec![0u8; size.min(64 * 1024) as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut entries)?;
+    let mut strtab = None;
+    let mut needed = Vec::new();
+    for entry in entries.chunks_exact(16) {
+        match u64_at(entry, 0) {
+            DT_NULL => break,
+            DT_NEEDED => needed.push(u64_at(entry, 8)),
+            DT_STRTAB => strtab = Some(u64_at(entry, 8)),
+            _ => {}
+        }
+    }
+    // The string table is addressed by its virtual address.
+    let strtab = strtab.and_then(|vaddr| {
+        loads
+            .iter()
+            .find(|(start, _, size)| (*start..start + size).contains(&vaddr))
+            .map(|(start, offset, _)| vaddr - start + offset)
+    });
+    if let Some(strtab) = strtab {
+        for name in needed {
+            if let Some(name) = read_str(file, strtab + name, 4096)? {
+                deps.needed.push(name);
+            }
+        }
+    }
+
+    Ok(deps)
+}
+
+fn read_str(file: &mut File, offset: u64, max_len: u64) -> io::Result<Option<String>> {
+    let mut buf = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(max_len).read_to_end(&mut buf)?;
+    let end = match buf.iter().position(|b| *b == 0) {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    Ok(String::from_utf8(buf[..end].to_vec()).ok())
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use anyhow::Result;
+    use oci_spec::runtime::ProcessBuilder;
+
+    use super::*;
+
+    /// Number of pages of the file that are in the page cache
+    fn resident_pages(path: &Path) -> Result<usize> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        let page_size =
+            nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?.unwrap_or(4096) as usize;
+        let pages = (len + page_size - 1) / page_size;
+        let mut vec = vec![0u8; pages];
+        // SAFETY: the mapping is only passed to mincore and unmapped again.
+        unsafe {
+            let addr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            );
+            assert_ne!(addr, libc::MAP_FAILED);
+            let ret = libc::mincore(addr, len, vec.as_mut_ptr());
+            libc::munmap(addr, len);
+            assert_eq!(ret, 0);
+        }
+        Ok(vec.iter().filter(|page| *page & 1 != 0).count())
+    }
+
+    #[test]
+    fn test_prewarm_rootfs() -> Result<()> {
+        // The host root serves as the rootfs, its shell is a dynamically
+        // linked executable.
+        let process = ProcessBuilder::default()
+            .args(vec!["sh".to_owned()])
+            .env(vec!["PATH=/usr/bin:/bin".to_owned()])
+            .build()?;
+        let warmed = prewarm_rootfs(Path::new("/"), &process);
+        let entrypoint = warmed.first().expect("entrypoint was not warmed");
+        let sh = find_executable(Path::new("/"), "sh", "/usr/bin:/bin").unwrap();
+        assert_eq!(entrypoint, &fs::canonicalize(sh)?);
+
+        let mut entrypoint_file = File::open(entrypoint)?;
+        let deps = elf_dependencies(&mut entrypoint_file)?;
+        if deps.interpreter.is_some() {
+            assert!(warmed.len() > 1, "no libraries were warmed: {warmed:?}");
+        }
+        // Read ahead is asynchronous and the cache may be under pressure, so
+        // residency is only checked best effort.
+        if resident_pages(entrypoint)? == 0 {
+            eprintln!("entrypoint {entrypoint:?} is not resident after prewarming");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_in_rootfs() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        let rootfs = rootfs.path();
+        fs::create_dir_all(rootfs.join("usr/bin"))?;
+        fs::write(rootfs.join("usr/bin/app"), "")?;
+        symlink("usr/bin", rootfs.join("bin"))?;
+        // an absolute link is resolved against the rootfs, not the host
+        symlink("/usr/bin/app", rootfs.join("usr/bin/link"))?;
+        symlink("../../../../../etc", rootfs.join("usr/etc"))?;
+
+        assert_eq!(
+            resolve_in_rootfs(rootfs, Path::new("/bin/link")),
+            Some(rootfs.join("usr/bin/app"))
+        );
+        assert_eq!(
+            find_executable(rootfs, "app", "/nonexistent:/bin"),
+            Some(rootfs.join("usr/bin/app"))
+        );
+        assert_eq!(
+            resolve_in_rootfs(rootfs, Path::new("/usr/etc/passwd")),
+            None
+        );
+        assert_eq!(find_executable(rootfs, "missing", "/bin"), None);
+
+        // only files are libraries, also when named by path
+        fs::create_dir_all(rootfs.join("lib/libdir.so"))?;
+        fs::write(rootfs.join("lib/libapp.so"), "")?;
+        assert_eq!(
+            find_library(rootfs, "/lib/libapp.so"),
+            Some(rootfs.join("lib/libapp.so"))
+        );
+        assert_eq!(find_library(rootfs, "/lib/libdir.so"), None);
+        assert_eq!(find_library(rootfs, "libdir.so"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prewarm_without_entrypoint() -> Result<()> {
+        let process = ProcessBuilder::default()
+            .args(vec!["missing".to_owned()])
+            .build()?;
+        let rootfs = tempfile::tempdir()?;
+        assert!(prewarm_rootfs(rootfs.path(), &process).is_empty());
+
+        Ok(())
+    }
+}
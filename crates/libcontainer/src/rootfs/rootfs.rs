@@ -1,21 +1,31 @@
-use std::collections::HashSet;
+use std::cell::Cell;
 use std::path::Path;
 
 use nix::mount::MsFlags;
-use oci_spec::runtime::{Linux, Spec};
+use oci_spec::runtime::{Linux, Mount as SpecMount, Spec};
 
 use super::device::Device;
-use super::mount::{Mount, MountOptions};
+use super::fs_type::FsType;
+use super::mount::{Mount, MountOptions, RootfsMountPlan};
 use super::symlink::Symlink;
-use super::utils::default_devices;
-use super::{Result, RootfsError};
+use super::utils::missing_default_devices;
+use super::write_accounting::WriteAccounting;
+use super::{tmpfs_layer, MountOrder, Result, RootfsError};
 use crate::error::MissingSpecError;
+use crate::hostname_file;
 use crate::syscall::syscall::create_syscall;
 use crate::syscall::Syscall;
 
 /// Holds information about rootfs
 pub struct RootFS {
     syscall: Box<dyn Syscall>,
+    fix_mount_target_type: bool,
+    mtab_symlink: bool,
+    ensure_default_devices: bool,
+    writes: WriteAccounting,
+    write_limit: Option<u64>,
+    max_size: Option<u64>,
+    fs_type: Cell<Option<FsType>>,
 }
 
 impl Default for RootFS {
@@ -24,10 +34,94 @@ impl Default for RootFS {
     }
 }
 
+/// Whether the mount is the one of `/dev`, which the devices and the `/dev`
+/// symlinks are created in
+fn is_dev_mount(mount: &SpecMount) -> bool {
+    mount.destination() == Path::new("/dev")
+}
+
 impl RootFS {
     pub fn new() -> RootFS {
         RootFS {
             syscall: create_syscall(),
+            fix_mount_target_type: false,
+            mtab_symlink: true,
+            ensure_default_devices: true,
+            writes: WriteAccounting::new(),
+            write_limit: None,
+            max_size: None,
+            fs_type: Cell::new(None),
+        }
+    }
+
+    /// Sets if an empty bind mount destination of the wrong type is replaced
+    /// instead of failing the mount.
+    pub fn with_fix_mount_target_type(mut self, fix: bool) -> Self {
+        self.fix_mount_target_type = fix;
+        self
+    }
+
+    /// Sets if the `/etc/mtab` symlink is created when the image lacks it,
+    /// defaults to true.
+    pub fn with_mtab_symlink(mut self, mtab_symlink: bool) -> Self {
+        self.mtab_symlink = mtab_symlink;
+        self
+    }
+
+    /// Sets if the default devices missing from the rootfs are created
+    /// besides the devices of the spec, defaults to true.
+    pub fn with_ensure_default_devices(mut self, ensure: bool) -> Self {
+        self.ensure_default_devices = ensure;
+        self
+    }
+
+    /// Sets how many bytes the setup may write into the rootfs, see
+    /// [`WriteAccounting`]. The setup fails once it wrote more.
+    pub fn with_write_limit(mut self, limit: Option<u64>) -> Self {
+        self.write_limit = limit;
+        self
+    }
+
+    /// Sets the size of the tmpfs the writable layer of the rootfs is backed
+    /// with, see [`tmpfs_layer`]. By default, the rootfs is used as is.
+    pub fn with_max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Returns the bytes the setup wrote into the rootfs so far
+    pub fn written(&self) -> u64 {
+        self.writes.written()
+    }
+
+    /// Returns the type of the filesystem backing the rootfs, `None` until
+    /// the rootfs is prepared or if it couldn't be detected
+    pub fn fs_type(&self) -> Option<FsType> {
+        self.fs_type.get()
+    }
+
+    /// Records the type of the filesystem backing the rootfs. It's only
+    /// reported, so a failure to detect it doesn't fail the setup.
+    pub(crate) fn detect_fs_type(&self, rootfs: &Path) {
+        match FsType::detect(rootfs) {
+            Ok(fs_type) => {
+                tracing::debug!(?rootfs, %fs_type, "detected rootfs filesystem type");
+                self.fs_type.set(Some(fs_type));
+            }
+            Err(err) => {
+                tracing::warn!(?rootfs, ?err, "failed to detect rootfs filesystem type");
+            }
+        }
+    }
+
+    fn check_write_limit(&self) -> Result<()> {
+        let written = self.writes.written();
+        match self.write_limit {
+            Some(limit) if written > limit => {
+                tracing::error!(written, limit, "rootfs setup exceeded the write limit");
+                Err(RootfsError::WriteLimitExceeded { written, limit })
+            }
+            _ => Ok(()),
         }
     }
 
@@ -37,7 +131,17 @@ impl RootFS {
         spec: &Spec,
         rootfs: &Path,
         cgroup_ns: bool,
+        cgroup_readonly: bool,
     ) -> Result<()> {
+        self.prepare_rootfs_mount(linux, rootfs)?;
+        self.mount_spec_mounts(spec, rootfs, cgroup_ns, cgroup_readonly)
+    }
+
+    /// Sets up the mount propagation of the root and makes the rootfs a
+    /// private mount point, so it can be used with pivot_root. See
+    /// [`RootfsMountPlan`]. With a max size, the rootfs is then overlaid with
+    /// a writable layer on a tmpfs, see [`tmpfs_layer`].
+    fn prepare_rootfs_mount(&self, linux: &Linux, rootfs: &Path) -> Result<()> {
         let mut flags = MsFlags::MS_REC;
         match linux.rootfs_propagation().as_deref() {
             Some("shared") => flags |= MsFlags::MS_SHARED,
@@ -60,33 +164,102 @@ impl RootFS {
                 err
             })?;
 
-        let mounter = Mount::new();
+        let mounter = Mount::new().with_write_accounting(self.writes.clone());
+        let plan = RootfsMountPlan::for_current(rootfs)?;
+        tracing::debug!(?rootfs, ?plan, "prepare the rootfs mount");
 
+        // pivot_root refuses a new root on a shared parent mount, whether the
+        // rootfs is bound onto itself or already is a mount point.
         mounter.make_parent_mount_private(rootfs)?;
 
-        tracing::debug!("mount root fs {:?}", rootfs);
-        self.syscall
-            .mount(
-                Some(rootfs),
-                rootfs,
-                None,
-                MsFlags::MS_BIND | MsFlags::MS_REC,
-                None,
-            )
-            .map_err(|err| {
-                tracing::error!(?rootfs, ?err, "failed to bind mount rootfs");
-                err
-            })?;
+        if plan.self_bind {
+            tracing::debug!("mount root fs {:?}", rootfs);
+            self.syscall
+                .mount(
+                    Some(rootfs),
+                    rootfs,
+                    None,
+                    MsFlags::MS_BIND | MsFlags::MS_REC,
+                    None,
+                )
+                .map_err(|err| {
+                    tracing::error!(?rootfs, ?err, "failed to bind mount rootfs");
+                    err
+                })?;
+        }
+
+        if let Some(flags) = plan.propagation {
+            self.syscall
+                .mount(None, rootfs, None, flags, None)
+                .map_err(|err| {
+                    tracing::error!(
+                        ?rootfs,
+                        ?err,
+                        ?flags,
+                        "failed to change the mount propagation type of the rootfs"
+                    );
+                    err
+                })?;
+        }
 
+        // The layer goes on top of the private rootfs mount, so the mounts of
+        // the spec and pivot_root use the overlay.
+        if let Some(max_size) = self.max_size {
+            tmpfs_layer::mount(self.syscall.as_ref(), rootfs, max_size)?;
+        }
+        self.writes.set_rootfs(rootfs);
+
+        Ok(())
+    }
+
+    /// Applies the mounts of the spec relative to `root`, which is either the
+    /// path of the rootfs or `/` once the container entered it.
+    pub fn mount_spec_mounts(
+        &self,
+        spec: &Spec,
+        root: &Path,
+        cgroup_ns: bool,
+        cgroup_readonly: bool,
+    ) -> Result<()> {
+        self.mount_spec_mounts_matching(spec, root, cgroup_ns, cgroup_readonly, |_| true)
+    }
+
+    /// Applies the mounts of the spec but the one of `/dev` relative to `/`,
+    /// once the container entered the rootfs prepared with
+    /// [`MountOrder::AfterPivot`].
+    pub fn mount_spec_mounts_after_pivot(
+        &self,
+        spec: &Spec,
+        cgroup_ns: bool,
+        cgroup_readonly: bool,
+    ) -> Result<()> {
+        self.mount_spec_mounts_matching(spec, Path::new("/"), cgroup_ns, cgroup_readonly, |m| {
+            !is_dev_mount(m)
+        })
+    }
+
+    fn mount_spec_mounts_matching(
+        &self,
+        spec: &Spec,
+        root: &Path,
+        cgroup_ns: bool,
+        cgroup_readonly: bool,
+        matching: impl Fn(&SpecMount) -> bool,
+    ) -> Result<()> {
+        let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
+        let mounter = Mount::new().with_write_accounting(self.writes.clone());
         let global_options = MountOptions {
-            root: rootfs,
+            root,
             label: linux.mount_label().as_deref(),
             cgroup_ns,
+            cgroup_readonly,
+            fix_target_type: self.fix_mount_target_type,
         };
 
         if let Some(mounts) = spec.mounts() {
-            for mount in mounts {
+            for mount in mounts.iter().filter(|m| matching(m)) {
                 mounter.setup_mount(mount, &global_options)?;
+                self.check_write_limit()?;
             }
         }
         Ok(())
@@ -98,30 +271,59 @@ impl RootFS {
         rootfs: &Path,
         bind_devices: bool,
         cgroup_ns: bool,
+        cgroup_readonly: bool,
+        mount_order: MountOrder,
     ) -> Result<()> {
-        tracing::debug!(?rootfs, "prepare rootfs");
+        tracing::debug!(?rootfs, ?mount_order, "prepare rootfs");
         let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
 
-        self.mount_to_rootfs(linux, spec, rootfs, cgroup_ns)?;
+        match mount_order {
+            MountOrder::BeforePivot => {
+                self.mount_to_rootfs(linux, spec, rootfs, cgroup_ns, cgroup_readonly)?
+            }
+            // The spec mounts are applied by the caller once it entered the
+            // rootfs, but the one of /dev, which would hide the devices and
+            // symlinks created in it below.
+            MountOrder::AfterPivot => {
+                self.prepare_rootfs_mount(linux, rootfs)?;
+                self.mount_spec_mounts_matching(
+                    spec,
+                    rootfs,
+                    cgroup_ns,
+                    cgroup_readonly,
+                    is_dev_mount,
+                )?;
+            }
+        }
+        self.detect_fs_type(rootfs);
 
-        let symlinker = Symlink::new();
+        let symlinker = Symlink::new().with_write_accounting(self.writes.clone());
         symlinker.setup_kcore_symlink(rootfs)?;
         symlinker.setup_default_symlinks(rootfs)?;
 
-        let devicer = Device::new();
-        if let Some(added_devices) = linux.devices() {
-            let mut path_set = HashSet::new();
-            let devices = default_devices();
-            added_devices.iter().for_each(|d| {
-                path_set.insert(d.path());
-            });
-            let default = devices.iter().filter(|d| !path_set.contains(d.path()));
-            devicer.create_devices(rootfs, added_devices.iter().chain(default), bind_devices)
+        let devicer = Device::new().with_write_accounting(self.writes.clone());
+        let spec_devices = linux.devices().as_deref().unwrap_or_default();
+        let default_devices = if self.ensure_default_devices {
+            missing_default_devices(linux, rootfs)
         } else {
-            devicer.create_devices(rootfs, &default_devices(), bind_devices)
-        }?;
+            Vec::new()
+        };
+        devicer.create_devices(
+            rootfs,
+            spec_devices.iter().chain(&default_devices),
+            bind_devices,
+        )?;
+        self.check_write_limit()?;
 
         symlinker.setup_ptmx(rootfs)?;
+        // This runs before the rootfs is remounted read-only.
+        if self.mtab_symlink {
+            symlinker.setup_mtab_symlink(rootfs)?;
+        }
+        if hostname_file::should_write(spec) {
+            hostname_file::write(rootfs, &self.writes)?;
+        }
+        self.check_write_limit()?;
         Ok(())
     }
 
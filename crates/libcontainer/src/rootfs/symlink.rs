@@ -1,6 +1,8 @@
-use std::fs::remove_file;
+use std::fs::{create_dir_all, remove_file};
+use std::io::ErrorKind;
 use std::path::Path;
 
+use super::write_accounting::WriteAccounting;
 use crate::syscall::syscall::create_syscall;
 use crate::syscall::Syscall;
 
@@ -12,12 +14,18 @@ pub enum SymlinkError {
     },
     #[error("failed symlink: {msg}")]
     Other { msg: String },
+    #[error("failed to resolve {path:?} in the rootfs")]
+    Resolve {
+        source: std::io::Error,
+        path: &'static str,
+    },
 }
 
 type Result<T> = std::result::Result<T, SymlinkError>;
 
 pub struct Symlink {
     syscall: Box<dyn Syscall>,
+    writes: WriteAccounting,
 }
 
 impl Default for Symlink {
@@ -32,7 +40,16 @@ impl Symlink {
     }
 
     fn with_syscall(syscall: Box<dyn Syscall>) -> Symlink {
-        Symlink { syscall }
+        Symlink {
+            syscall,
+            writes: WriteAccounting::default(),
+        }
+    }
+
+    /// Sets the accounting the created symlinks are recorded in
+    pub fn with_write_accounting(mut self, writes: WriteAccounting) -> Self {
+        self.writes = writes;
+        self
     }
 
     // Create symlinks for subsystems that have been comounted e.g. cpu -> cpu,cpuacct, cpuacct -> cpu,cpuacct
@@ -50,6 +67,7 @@ impl Symlink {
                     tracing::error!("failed to symlink {link:?} to {subsystem_name:?}");
                     SymlinkError::Syscall { source: err }
                 })?;
+            self.writes.record(&link);
         }
 
         Ok(())
@@ -71,6 +89,7 @@ impl Symlink {
                 tracing::error!("failed to symlink ptmx");
                 SymlinkError::Syscall { source: err }
             })?;
+        self.writes.record(&ptmx);
         Ok(())
     }
 
@@ -78,13 +97,65 @@ impl Symlink {
     // since not every architecture has /proc/kcore file.
     pub fn setup_kcore_symlink(&self, rootfs: &Path) -> Result<()> {
         if Path::new("/proc/kcore").exists() {
+            let kcore = rootfs.join("dev/kcore");
             self.syscall
-                .symlink(Path::new("/proc/kcore"), &rootfs.join("dev/kcore"))
+                .symlink(Path::new("/proc/kcore"), &kcore)
                 .map_err(|err| {
                     tracing::error!("failed to symlink kcore");
                     SymlinkError::Syscall { source: err }
                 })?;
+            self.writes.record(&kcore);
+        }
+        Ok(())
+    }
+
+    /// Creates the `/etc/mtab -> /proc/self/mounts` symlink expected by tools
+    /// like `df`, unless the image already has an `/etc/mtab` of any kind. The
+    /// path is resolved in the rootfs, so a symlink at `/etc` of a hostile
+    /// image can't redirect the creation outside of it.
+    pub fn setup_mtab_symlink(&self, rootfs: &Path) -> Result<()> {
+        const MTAB: &str = "etc/mtab";
+        let mtab = safe_path::scoped_join(rootfs, MTAB).map_err(|err| {
+            tracing::error!(?err, "failed to resolve /etc/mtab in the rootfs");
+            SymlinkError::Resolve {
+                source: err,
+                path: MTAB,
+            }
+        })?;
+
+        match mtab.symlink_metadata() {
+            Ok(_) => {
+                tracing::debug!(?mtab, "/etc/mtab exists, not creating the symlink");
+                return Ok(());
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                tracing::error!(?err, ?mtab, "failed to check /etc/mtab");
+                return Err(SymlinkError::Resolve {
+                    source: err,
+                    path: MTAB,
+                });
+            }
         }
+
+        if let Some(etc) = mtab.parent() {
+            create_dir_all(etc).map_err(|err| {
+                tracing::error!(?err, ?etc, "failed to create /etc for the mtab symlink");
+                SymlinkError::Resolve {
+                    source: err,
+                    path: MTAB,
+                }
+            })?;
+        }
+
+        self.syscall
+            .symlink(Path::new("/proc/self/mounts"), &mtab)
+            .map_err(|err| {
+                tracing::error!("failed to symlink /etc/mtab");
+                SymlinkError::Syscall { source: err }
+            })?;
+        self.writes.record(&mtab);
+
         Ok(())
     }
 
@@ -96,12 +167,12 @@ impl Symlink {
             ("/proc/self/fd/2", "dev/stderr"),
         ];
         for (src, dst) in defaults {
-            self.syscall
-                .symlink(Path::new(src), &rootfs.join(dst))
-                .map_err(|err| {
-                    tracing::error!("failed to symlink defaults");
-                    SymlinkError::Syscall { source: err }
-                })?;
+            let link = rootfs.join(dst);
+            self.syscall.symlink(Path::new(src), &link).map_err(|err| {
+                tracing::error!("failed to symlink defaults");
+                SymlinkError::Syscall { source: err }
+            })?;
+            self.writes.record(&link);
         }
 
         Ok(())
@@ -164,6 +235,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_setup_mtab_symlink() -> anyhow::Result<()> {
+        let symlink_args = |symlink: &Symlink| {
+            symlink
+                .syscall
+                .as_any()
+                .downcast_ref::<TestHelperSyscall>()
+                .unwrap()
+                .get_symlink_args()
+        };
+
+        // an existing file is kept
+        {
+            let tmp_dir = tempfile::tempdir()?;
+            std::fs::create_dir(tmp_dir.path().join("etc"))?;
+            std::fs::write(tmp_dir.path().join("etc/mtab"), "")?;
+            let symlink = Symlink::new();
+            symlink.setup_mtab_symlink(tmp_dir.path())?;
+            assert!(symlink_args(&symlink).is_empty());
+        }
+        // an existing symlink is kept, even if it's dangling
+        {
+            let tmp_dir = tempfile::tempdir()?;
+            std::fs::create_dir(tmp_dir.path().join("etc"))?;
+            std::os::unix::fs::symlink("/nonexistent", tmp_dir.path().join("etc/mtab"))?;
+            let symlink = Symlink::new();
+            symlink.setup_mtab_symlink(tmp_dir.path())?;
+            assert!(symlink_args(&symlink).is_empty());
+        }
+        // a missing /etc is created
+        {
+            let tmp_dir = tempfile::tempdir()?;
+            let symlink = Symlink::new();
+            symlink.setup_mtab_symlink(tmp_dir.path())?;
+            assert!(tmp_dir.path().join("etc").is_dir());
+            assert_eq!(
+                symlink_args(&symlink),
+                vec![(
+                    PathBuf::from("/proc/self/mounts"),
+                    tmp_dir.path().join("etc/mtab")
+                )]
+            );
+        }
+        // a /etc symlink pointing outside is resolved in the rootfs
+        {
+            let tmp_dir = tempfile::tempdir()?;
+            let outside = tempfile::tempdir()?;
+            std::os::unix::fs::symlink(outside.path(), tmp_dir.path().join("etc"))?;
+            let symlink = Symlink::new();
+            symlink.setup_mtab_symlink(tmp_dir.path())?;
+            let args = symlink_args(&symlink);
+            assert_eq!(args.len(), 1);
+            assert!(args[0].1.starts_with(tmp_dir.path()), "{args:?}");
+            assert!(std::fs::read_dir(outside.path())?.next().is_none());
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_setup_default_symlinks() {
         let tmp_dir = tempfile::tempdir().unwrap();
@@ -0,0 +1,201 @@
+//! Writable layer of the rootfs on a tmpfs of a maximum size
+//!
+//! For ephemeral RAM-backed roots, the init process covers the rootfs with a
+//! `tmpfs` of the size set with
+//! [`InitContainerBuilder::with_rootfs_max_size`](crate::container::init_builder::InitContainerBuilder::with_rootfs_max_size)
+//! and mounts an overlay on top of it, with the rootfs as the lower layer and
+//! the upper layer on the `tmpfs`. Everything the container writes to its
+//! root ends up in the `tmpfs`, so writes beyond the size fail with `ENOSPC`
+//! instead of consuming the memory of the host, and the image is left
+//! untouched. Both mounts only exist in the mount namespace of the container
+//! and go away with it.
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::{chown, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use nix::mount::MsFlags;
+
+use crate::syscall::{Syscall, SyscallError};
+use crate::utils;
+
+/// Directory of the `tmpfs` the upper layer of the overlay lives in
+const UPPER_DIR: &str = "upper";
+/// Directory of the `tmpfs` overlayfs uses as its work directory
+const WORK_DIR: &str = "work";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TmpfsLayerError {
+    #[error("failed to open {path:?}")]
+    Open {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to mount the tmpfs of the rootfs")]
+    MountTmpfs(#[source] SyscallError),
+    #[error("failed to prepare {path:?} of the writable layer")]
+    Prepare {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to mount the overlay of the rootfs")]
+    MountOverlay(#[source] SyscallError),
+}
+
+type Result<T> = std::result::Result<T, TmpfsLayerError>;
+
+/// Covers `rootfs` with a `tmpfs` of `max_size` bytes and overlays the
+/// original rootfs with a writable layer on it, see the [module](self) docs
+pub fn mount(syscall: &dyn Syscall, rootfs: &Path, max_size: u64) -> Result<()> {
+    // The tmpfs covers the rootfs, from then on the overlay reaches it
+    // through the fd.
+    let lower = open_path(rootfs)?;
+    let metadata = fs::metadata(rootfs).map_err(|err| TmpfsLayerError::Prepare {
+        path: rootfs.to_owned(),
+        source: err,
+    })?;
+
+    syscall
+        .mount(
+            Some(Path::new("tmpfs")),
+            rootfs,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some(&tmpfs_options(max_size)),
+        )
+        .map_err(|err| {
+            tracing::error!(?err, ?rootfs, max_size, "failed to mount the rootfs tmpfs");
+            TmpfsLayerError::MountTmpfs(err)
+        })?;
+
+    let upper = rootfs.join(UPPER_DIR);
+    let work = rootfs.join(WORK_DIR);
+    for dir in [&upper, &work] {
+        fs::create_dir(dir).map_err(|err| TmpfsLayerError::Prepare {
+            path: dir.to_owned(),
+            source: err,
+        })?;
+    }
+    // The root of the overlay has the owner and mode of the upper layer.
+    fs::set_permissions(&upper, Permissions::from_mode(metadata.mode() & 0o7777)).map_err(
+        |err| TmpfsLayerError::Prepare {
+            path: upper.clone(),
+            source: err,
+        },
+    )?;
+    if let Err(err) = chown(&upper, Some(metadata.uid()), Some(metadata.gid())) {
+        // An owner that isn't mapped into the user namespace can't be kept.
+        tracing::warn!(
+            ?err,
+            uid = metadata.uid(),
+            gid = metadata.gid(),
+            "failed to keep the owner of the rootfs"
+        );
+    }
+    let upper = open_path(&upper)?;
+    let work = open_path(&work)?;
+
+    // Overlayfs only allows the user xattrs in a user namespace.
+    let userxattr = utils::is_in_new_userns().unwrap_or(false);
+    syscall
+        .mount(
+            Some(Path::new("overlay")),
+            rootfs,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(&overlay_options(
+                lower.as_raw_fd(),
+                upper.as_raw_fd(),
+                work.as_raw_fd(),
+                userxattr,
+            )),
+        )
+        .map_err(|err| {
+            tracing::error!(?err, ?rootfs, "failed to mount the rootfs overlay");
+            TmpfsLayerError::MountOverlay(err)
+        })?;
+    tracing::debug!(?rootfs, max_size, "overlaid the rootfs with a tmpfs layer");
+
+    Ok(())
+}
+
+fn tmpfs_options(max_size: u64) -> String {
+    format!("size={max_size},mode=700")
+}
+
+/// The layers are passed as fds, so neither a covered rootfs nor a path with
+/// a comma or colon trips up the option parsing of overlayfs.
+fn overlay_options(lower: RawFd, upper: RawFd, work: RawFd, userxattr: bool) -> String {
+    let mut options =
+        format!("lowerdir=/proc/self/fd/{lower},upperdir=/proc/self/fd/{upper},workdir=/proc/self/fd/{work}");
+    if userxattr {
+        options.push_str(",userxattr");
+    }
+    options
+}
+
+fn open_path(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(path)
+        .map_err(|err| {
+            tracing::error!(?err, ?path, "failed to open the rootfs layer");
+            TmpfsLayerError::Open {
+                path: path.to_owned(),
+                source: err,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::syscall::syscall::create_syscall;
+    use crate::syscall::test::{MountArgs, TestHelperSyscall};
+
+    #[test]
+    fn test_options() {
+        assert_eq!(tmpfs_options(1024), "size=1024,mode=700");
+        assert_eq!(
+            overlay_options(3, 4, 5, false),
+            "lowerdir=/proc/self/fd/3,upperdir=/proc/self/fd/4,workdir=/proc/self/fd/5"
+        );
+        assert!(overlay_options(3, 4, 5, true).ends_with(",userxattr"));
+    }
+
+    #[test]
+    fn test_mount() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        fs::set_permissions(rootfs.path(), Permissions::from_mode(0o755))?;
+        let syscall = create_syscall();
+        mount(syscall.as_ref(), rootfs.path(), 4096)?;
+
+        let mounts = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(
+            mounts[0],
+            MountArgs {
+                source: Some(PathBuf::from("tmpfs")),
+                target: rootfs.path().to_owned(),
+                fstype: Some("tmpfs".to_owned()),
+                flags: MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                data: Some("size=4096,mode=700".to_owned()),
+            }
+        );
+        assert_eq!(mounts[1].fstype.as_deref(), Some("overlay"));
+        assert_eq!(mounts[1].target, rootfs.path());
+        // the mock doesn't mount, so the layers end up in the rootfs itself
+        let upper = fs::metadata(rootfs.path().join(UPPER_DIR))?;
+        assert_eq!(upper.mode() & 0o7777, 0o755);
+        assert!(rootfs.path().join(WORK_DIR).is_dir());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
-use std::path::PathBuf;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use nix::mount::MsFlags;
 use nix::sys::stat::SFlag;
-use oci_spec::runtime::{LinuxDevice, LinuxDeviceBuilder, LinuxDeviceType, Mount};
+use oci_spec::runtime::{
+    Linux, LinuxDevice, LinuxDeviceBuilder, LinuxDeviceCgroup, LinuxDeviceType, Mount,
+};
 
 use super::mount::MountError;
 use crate::syscall::linux::{self, MountOption, MountRecursive};
@@ -73,6 +77,57 @@ pub fn default_devices() -> Vec<LinuxDevice> {
     ]
 }
 
+/// Returns the default devices to create besides the devices of the spec. A
+/// default device is left out if the spec lists its path, if the rootfs
+/// already has a device node there, or if the last device cgroup rule of the
+/// spec naming its major number denies it. Rules without a major number, like
+/// the usual leading deny all, don't count, since the cgroup always allows the
+/// default devices on top of the rules.
+pub fn missing_default_devices(linux: &Linux, rootfs: &Path) -> Vec<LinuxDevice> {
+    let spec_devices = linux.devices().as_deref().unwrap_or_default();
+    let rules = linux
+        .resources()
+        .as_ref()
+        .and_then(|resources| resources.devices().as_deref())
+        .unwrap_or_default();
+
+    default_devices()
+        .into_iter()
+        .filter(|device| {
+            !spec_devices
+                .iter()
+                .any(|spec_device| spec_device.path() == device.path())
+        })
+        .filter(|device| {
+            let path = rootfs.join(device.path().strip_prefix("/").unwrap_or(device.path()));
+            let exists = fs::symlink_metadata(path)
+                .map_or(false, |metadata| metadata.file_type().is_char_device());
+            !exists
+        })
+        .filter(|device| {
+            let denied = rules
+                .iter()
+                .rev()
+                .find(|rule| rule_matches(rule, device))
+                .map_or(false, |rule| !rule.allow());
+            if denied {
+                tracing::debug!(path = ?device.path(), "default device is denied by the spec");
+            }
+            !denied
+        })
+        .collect()
+}
+
+fn rule_matches(rule: &LinuxDeviceCgroup, device: &LinuxDevice) -> bool {
+    let typ_matches = match rule.typ() {
+        None | Some(LinuxDeviceType::A) => true,
+        Some(typ) => typ == device.typ(),
+    };
+    typ_matches
+        && rule.major() == Some(device.major())
+        && rule.minor().map_or(true, |minor| minor == device.minor())
+}
+
 pub fn to_sflag(dev_type: LinuxDeviceType) -> SFlag {
     match dev_type {
         LinuxDeviceType::A => SFlag::S_IFBLK | SFlag::S_IFCHR | SFlag::S_IFIFO,
@@ -192,10 +247,75 @@ pub fn parse_mount(m: &Mount) -> std::result::Result<MountOptionConfig, MountErr
     })
 }
 
+/// Options that set and clear the same mount flag
+const OPPOSITE_OPTIONS: &[(&str, &str)] = &[
+    ("ro", "rw"),
+    ("suid", "nosuid"),
+    ("dev", "nodev"),
+    ("exec", "noexec"),
+    ("sync", "async"),
+    ("mand", "nomand"),
+    ("atime", "noatime"),
+    ("diratime", "nodiratime"),
+    ("relatime", "norelatime"),
+    ("strictatime", "nostrictatime"),
+    ("rro", "rrw"),
+    ("rsuid", "rnosuid"),
+    ("rdev", "rnodev"),
+    ("rexec", "rnoexec"),
+    ("ratime", "rnoatime"),
+    ("rdiratime", "rnodiratime"),
+    ("rrelatime", "rnorelatime"),
+    ("rstrictatime", "rnostrictatime"),
+];
+
+/// Groups of options of which a mount can only have one, e.g. a mount can't
+/// be both shared and private
+const EXCLUSIVE_OPTIONS: &[&[&str]] = &[
+    &[
+        "private",
+        "shared",
+        "slave",
+        "unbindable",
+        "rprivate",
+        "rshared",
+        "rslave",
+        "runbindable",
+    ],
+    &["noatime", "relatime", "strictatime"],
+    &["rnoatime", "rrelatime", "rstrictatime"],
+];
+
+/// Returns the first two options of a mount that contradict each other.
+/// [`parse_mount`] lets the last option win, which usually hides a mistake in
+/// a hand written spec, or fails later at `mount(2)` with a generic errno.
+pub fn find_conflicting_options(options: &[String]) -> Option<(String, String)> {
+    for (i, option) in options.iter().enumerate() {
+        for other in &options[i + 1..] {
+            if option == other {
+                continue;
+            }
+            let opposite = OPPOSITE_OPTIONS.iter().any(|(set, clear)| {
+                (option == set && other == clear) || (option == clear && other == set)
+            });
+            let exclusive = EXCLUSIVE_OPTIONS
+                .iter()
+                .any(|group| group.contains(&option.as_str()) && group.contains(&other.as_str()));
+            if opposite || exclusive {
+                return Some((option.clone(), other.clone()));
+            }
+        }
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
-    use oci_spec::runtime::MountBuilder;
+    use oci_spec::runtime::{
+        LinuxBuilder, LinuxDeviceCgroupBuilder, LinuxResourcesBuilder, MountBuilder,
+    };
 
     use super::*;
     use crate::syscall::linux::MountAttr;
@@ -467,4 +587,113 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_find_conflicting_options() {
+        let options = |options: &[&str]| options.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            find_conflicting_options(&options(&["nosuid", "ro", "nodev", "rw"])),
+            Some(("ro".to_owned(), "rw".to_owned()))
+        );
+        assert_eq!(
+            find_conflicting_options(&options(&["rbind", "rprivate", "shared"])),
+            Some(("rprivate".to_owned(), "shared".to_owned()))
+        );
+        assert_eq!(
+            find_conflicting_options(&options(&["noatime", "strictatime"])),
+            Some(("noatime".to_owned(), "strictatime".to_owned()))
+        );
+        assert_eq!(
+            find_conflicting_options(&options(&["rro", "rrw"])),
+            Some(("rro".to_owned(), "rrw".to_owned()))
+        );
+
+        assert_eq!(
+            find_conflicting_options(&options(&[
+                "rbind",
+                "ro",
+                "ro",
+                "nosuid",
+                "nodev",
+                "noexec",
+                "rprivate",
+                "relatime",
+                "mode=755",
+                "size=65536k",
+            ])),
+            None
+        );
+        assert_eq!(find_conflicting_options(&[]), None);
+    }
+
+    fn default_paths(linux: &Linux, rootfs: &Path) -> Vec<PathBuf> {
+        missing_default_devices(linux, rootfs)
+            .iter()
+            .map(|device| device.path().to_owned())
+            .collect()
+    }
+
+    fn device_rule(allow: bool, major: Option<i64>, minor: Option<i64>) -> LinuxDeviceCgroup {
+        let mut builder = LinuxDeviceCgroupBuilder::default()
+            .allow(allow)
+            .access("rwm");
+        if let Some(major) = major {
+            builder = builder.major(major);
+        }
+        if let Some(minor) = minor {
+            builder = builder.minor(minor);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_missing_default_devices() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        let all: Vec<PathBuf> = default_devices()
+            .iter()
+            .map(|device| device.path().to_owned())
+            .collect();
+
+        // a spec without devices gets all of them, e.g. /dev/null
+        let mut linux = LinuxBuilder::default().build()?;
+        assert_eq!(default_paths(&linux, rootfs.path()), all);
+        assert!(all.contains(&PathBuf::from("/dev/null")));
+
+        // the spec's own device takes precedence
+        linux.set_devices(Some(vec![default_devices().remove(0)]));
+        assert_eq!(default_paths(&linux, rootfs.path()), all[1..].to_vec());
+        linux.set_devices(None);
+
+        // a file that isn't a device node is replaced
+        std::fs::create_dir(rootfs.path().join("dev"))?;
+        std::fs::write(rootfs.path().join("dev/null"), "")?;
+        assert_eq!(default_paths(&linux, rootfs.path()), all);
+
+        // deny all doesn't count, an explicit deny of 1:3 does
+        let deny_null = vec![
+            device_rule(false, None, None),
+            device_rule(false, Some(1), Some(3)),
+        ];
+        linux.set_resources(Some(
+            LinuxResourcesBuilder::default()
+                .devices(deny_null.clone())
+                .build()?,
+        ));
+        let paths = default_paths(&linux, rootfs.path());
+        assert!(!paths.contains(&PathBuf::from("/dev/null")));
+        assert_eq!(paths.len(), all.len() - 1);
+
+        // a later rule wins
+        let mut allow_again = deny_null;
+        allow_again.push(device_rule(true, Some(1), None));
+        linux.set_resources(Some(
+            LinuxResourcesBuilder::default()
+                .devices(allow_again)
+                .build()?,
+        ));
+        assert_eq!(default_paths(&linux, rootfs.path()), all);
+
+        Ok(())
+    }
 }
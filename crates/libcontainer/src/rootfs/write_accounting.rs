@@ -0,0 +1,148 @@
+//! Accounting of what the rootfs setup writes into the rootfs
+//!
+//! On quota-sensitive hosts the rootfs is often a scratch layer that is
+//! charged for everything written into it. The setup creates device nodes,
+//! symlinks and mount targets, each taking a directory entry in its parent
+//! directory and, for symlinks and files, data. The accounting counts the
+//! bytes of the names and the data of the entries the setup created, so the
+//! total can be reported and capped. Once the rootfs is known, entries on
+//! other filesystems, e.g. the tmpfs mounted on `/dev`, are not counted, they
+//! aren't charged to the rootfs.
+use std::cell::Cell;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{fs, io};
+
+/// Counts the bytes the setup wrote into the rootfs. Clones share the count,
+/// so the device, symlink and mount setup can each hold one.
+#[derive(Debug, Clone, Default)]
+pub struct WriteAccounting {
+    written: Rc<Cell<u64>>,
+    /// Device of the filesystem of the rootfs, entries on other devices
+    /// don't count
+    rootfs_dev: Rc<Cell<Option<u64>>>,
+}
+
+impl WriteAccounting {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the bytes written so far
+    pub fn written(&self) -> u64 {
+        self.written.get()
+    }
+
+    /// Only counts the entries on the filesystem of `rootfs` from here on.
+    /// Until then, or if it can't be stat'ed, every entry counts.
+    pub fn set_rootfs(&self, rootfs: &Path) {
+        match fs::metadata(rootfs) {
+            Ok(metadata) => self.rootfs_dev.set(Some(metadata.dev())),
+            Err(err) => tracing::warn!(?rootfs, ?err, "failed to stat rootfs for accounting"),
+        }
+    }
+
+    /// Records the entry the setup just created at `path`. Directories only
+    /// count their name, their size is up to the filesystem. An entry that
+    /// doesn't exist, e.g. because the syscalls are mocked, counts nothing.
+    pub fn record(&self, path: &Path) {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return,
+        };
+        if let Some(rootfs_dev) = self.rootfs_dev.get() {
+            if metadata.dev() != rootfs_dev {
+                tracing::trace!(?path, "rootfs write on another filesystem");
+                return;
+            }
+        }
+        let name = path.file_name().map_or(0, |name| name.len() as u64);
+        let data = if metadata.is_dir() { 0 } else { metadata.len() };
+        tracing::trace!(?path, name, data, "rootfs write");
+        self.written
+            .set(self.written.get().saturating_add(name + data));
+    }
+
+    /// Creates the directory `path` with its missing parents and records
+    /// every directory that was created
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let created = missing(path);
+        fs::create_dir_all(path)?;
+        for path in &created {
+            self.record(path);
+        }
+        Ok(())
+    }
+}
+
+/// Returns `path` and its ancestors that don't exist yet, outermost first,
+/// i.e. the entries creating `path` with its parents adds
+pub fn missing(path: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|ancestor| {
+            !ancestor.as_os_str().is_empty() && fs::symlink_metadata(ancestor).is_err()
+        })
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_write_accounting() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let writes = WriteAccounting::new();
+        let shared = writes.clone();
+
+        std::os::unix::fs::symlink("/proc/self/fd", tmp.path().join("fd"))?;
+        writes.record(&tmp.path().join("fd"));
+        assert_eq!(writes.written(), 2 + 13);
+
+        fs::write(tmp.path().join("file"), "data")?;
+        shared.record(&tmp.path().join("file"));
+        fs::create_dir(tmp.path().join("dir"))?;
+        shared.record(&tmp.path().join("dir"));
+        assert_eq!(writes.written(), 15 + 4 + 4 + 3);
+
+        // nothing was created
+        writes.record(&tmp.path().join("missing"));
+        assert_eq!(writes.written(), 26);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_dir_all_records_every_component() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let writes = WriteAccounting::new();
+        fs::create_dir(tmp.path().join("a"))?;
+        writes.create_dir_all(&tmp.path().join("a/bb/ccc"))?;
+        assert_eq!(writes.written(), 2 + 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_filesystems_dont_count() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let writes = WriteAccounting::new();
+        writes.set_rootfs(tmp.path());
+        fs::write(tmp.path().join("file"), "data")?;
+        writes.record(&tmp.path().join("file"));
+        assert_eq!(writes.written(), 8);
+
+        // /proc is never on the filesystem of a temporary dir
+        writes.record(Path::new("/proc/self"));
+        assert_eq!(writes.written(), 8);
+
+        Ok(())
+    }
+}
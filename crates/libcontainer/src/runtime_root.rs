@@ -0,0 +1,401 @@
+//! Default runtime root of the user running youki
+//!
+//! The runtime root keeps the directories of the containers. Without an
+//! explicit root, root uses [`ROOT_RUNTIME_ROOT`] and every other user
+//! `$XDG_RUNTIME_DIR/youki`, falling back to `/run/user/<uid>/youki` and then
+//! to `$HOME/.youki/run` when a location can't be created, so users of a host
+//! never share one. The root must be a directory owned by the user that only
+//! the user can write to, anything else is refused, as the state of the
+//! containers in it is trusted.
+//!
+//! The first use lays out the subdirectories of [`LAYOUT_DIRS`] under a lock,
+//! so concurrent first invocations don't race. The containers keep their
+//! directories in the root itself, as roots of older versions do.
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use nix::fcntl::{Flock, FlockArg};
+
+use crate::syscall::Syscall;
+use crate::utils::rootless_required;
+
+/// Runtime root of root
+pub const ROOT_RUNTIME_ROOT: &str = "/run/youki";
+/// Subdirectories laid out in a new runtime root, for checkpoint images and
+/// caches
+pub const LAYOUT_DIRS: [&str; 2] = ["checkpoints", "cache"];
+/// Mode of the runtime root and its subdirectories
+const ROOT_MODE: u32 = 0o700;
+/// File the layout is locked with
+const LAYOUT_LOCK_FILE: &str = ".layout.lock";
+/// File that marks the layout as done
+const LAYOUT_MARKER_FILE: &str = ".layout";
+
+#[derive(Debug, thiserror::Error)]
+pub enum RuntimeRootError {
+    #[error("failed to check if rootless mode is required")]
+    Rootless(#[source] std::io::Error),
+    #[error("failed to create runtime root {path:?}")]
+    Create {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("runtime root {path:?} is not a directory")]
+    NotADirectory { path: PathBuf },
+    #[error("runtime root {path:?} is owned by uid {found}, expected uid {expected}")]
+    WrongOwner {
+        path: PathBuf,
+        expected: u32,
+        found: u32,
+    },
+    #[error(
+        "runtime root {path:?} has mode {found:#o}, expected {ROOT_MODE:#o} without group or other write permission"
+    )]
+    WrongMode { path: PathBuf, found: u32 },
+    #[error("no location for the runtime root")]
+    NoLocation,
+    #[error("failed to lock the layout of runtime root {path:?}")]
+    Lock { path: PathBuf, source: nix::Error },
+    #[error("failed to lay out runtime root {path:?}")]
+    Layout {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, RuntimeRootError>;
+
+/// Returns the runtime root of the user, created and laid out if needed, see
+/// the [module](self) docs
+pub fn default_for_user(syscall: &dyn Syscall) -> Result<PathBuf> {
+    let rootless = rootless_required(syscall).map_err(RuntimeRootError::Rootless)?;
+    prepare_default(rootless, syscall.get_euid().as_raw())
+}
+
+/// Returns the default runtime root of `uid`, in rootless mode or not,
+/// created and laid out if needed
+pub fn prepare_default(rootless: bool, uid: u32) -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").and_then(|home| fs::canonicalize(home).ok());
+    let paths = default_paths(rootless, uid, std::env::var_os("XDG_RUNTIME_DIR"), home);
+    prepare_first(&paths, uid)
+}
+
+/// Returns the default runtime roots in the order they are tried, without
+/// touching them
+pub fn default_paths(
+    rootless: bool,
+    uid: u32,
+    xdg_runtime_dir: Option<OsString>,
+    home: Option<PathBuf>,
+) -> Vec<PathBuf> {
+    if !rootless {
+        return vec![PathBuf::from(ROOT_RUNTIME_ROOT)];
+    }
+    let mut paths = Vec::new();
+    // see https://specifications.freedesktop.org/basedir-spec/basedir-spec-latest.html
+    if let Some(dir) = xdg_runtime_dir.filter(|dir| !dir.is_empty()) {
+        paths.push(Path::new(&dir).join("youki"));
+    }
+    paths.push(PathBuf::from(format!("/run/user/{uid}/youki")));
+    if let Some(home) = home {
+        paths.push(home.join(".youki/run"));
+    }
+    paths
+}
+
+/// Prepares the first of `paths` that can be created. A root that exists but
+/// fails the checks is refused rather than skipped, the containers would
+/// otherwise move to another root unnoticed.
+fn prepare_first(paths: &[PathBuf], owner: u32) -> Result<PathBuf> {
+    let mut last_err = None;
+    for path in paths {
+        match prepare(path, owner) {
+            Ok(()) => return Ok(path.to_owned()),
+            Err(err @ RuntimeRootError::Create { .. }) => {
+                tracing::debug!(
+                    ?err,
+                    ?path,
+                    "runtime root can't be created, trying the next one"
+                );
+                last_err = Some(err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(last_err.unwrap_or(RuntimeRootError::NoLocation))
+}
+
+/// Creates the runtime root at `path` if it doesn't exist, checks that it is
+/// safe to use for `owner` and lays it out once
+pub fn prepare(path: &Path, owner: u32) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(ROOT_MODE)
+        .create(path)
+        .map_err(|err| {
+            tracing::error!(?err, ?path, "failed to create runtime root");
+            RuntimeRootError::Create {
+                path: path.to_owned(),
+                source: err,
+            }
+        })?;
+    check(path, owner)?;
+    lay_out(path)
+}
+
+/// Refuses a root that isn't a directory owned by `owner` that the owner has
+/// full access to and only the owner can write to. A symlink is refused too, whoever can replace it
+/// controls the root.
+fn check(path: &Path, owner: u32) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).map_err(|err| RuntimeRootError::Create {
+        path: path.to_owned(),
+        source: err,
+    })?;
+    if !metadata.is_dir() {
+        tracing::error!(?path, "runtime root is not a directory");
+        return Err(RuntimeRootError::NotADirectory {
+            path: path.to_owned(),
+        });
+    }
+    if metadata.uid() != owner {
+        tracing::error!(
+            ?path,
+            owner,
+            found = metadata.uid(),
+            "runtime root has the wrong owner"
+        );
+        return Err(RuntimeRootError::WrongOwner {
+            path: path.to_owned(),
+            expected: owner,
+            found: metadata.uid(),
+        });
+    }
+    let mode = metadata.mode() & 0o7777;
+    if mode & 0o700 != 0o700 || mode & 0o022 != 0 {
+        tracing::error!(
+            ?path,
+            mode = format!("{mode:o}"),
+            "runtime root is not usable by the owner alone"
+        );
+        return Err(RuntimeRootError::WrongMode {
+            path: path.to_owned(),
+            found: mode,
+        });
+    }
+
+    Ok(())
+}
+
+fn lay_out(path: &Path) -> Result<()> {
+    let layout_err = |err| RuntimeRootError::Layout {
+        path: path.to_owned(),
+        source: err,
+    };
+    if path.join(LAYOUT_MARKER_FILE).exists() {
+        return Ok(());
+    }
+
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.join(LAYOUT_LOCK_FILE))
+        .map_err(layout_err)?;
+    let _lock = Flock::lock(lock_file, FlockArg::LockExclusive).map_err(|(_, err)| {
+        tracing::error!(?path, %err, "failed to lock the runtime root layout");
+        RuntimeRootError::Lock {
+            path: path.to_owned(),
+            source: err,
+        }
+    })?;
+    // Another invocation may have laid it out while this one waited.
+    if path.join(LAYOUT_MARKER_FILE).exists() {
+        return Ok(());
+    }
+
+    for dir in LAYOUT_DIRS {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(ROOT_MODE)
+            .create(path.join(dir))
+            .map_err(layout_err)?;
+    }
+    fs::write(path.join(LAYOUT_MARKER_FILE), "1\n").map_err(layout_err)?;
+    tracing::debug!(?path, "laid out runtime root");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::Permissions;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::thread;
+
+    use anyhow::Result;
+    use nix::unistd::geteuid;
+
+    use super::*;
+
+    #[test]
+    fn test_default_paths() {
+        let home = Some(PathBuf::from("/home/user"));
+        assert_eq!(
+            default_paths(false, 0, Some("/run/user/0".into()), home.clone()),
+            vec![PathBuf::from(ROOT_RUNTIME_ROOT)]
+        );
+        assert_eq!(
+            default_paths(true, 1000, Some("/tmp/xdg".into()), home.clone()),
+            vec![
+                PathBuf::from("/tmp/xdg/youki"),
+                PathBuf::from("/run/user/1000/youki"),
+                PathBuf::from("/home/user/.youki/run"),
+            ]
+        );
+        for unset in [None, Some("".into())] {
+            assert_eq!(
+                default_paths(true, 1000, unset, None),
+                vec![PathBuf::from("/run/user/1000/youki")]
+            );
+        }
+    }
+
+    #[test]
+    fn test_prepare_first_falls_back() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let uid = geteuid().as_raw();
+        // a path below a file can't be created
+        let file = tmp.path().join("file");
+        fs::write(&file, "")?;
+        let home = tmp.path().join("home/.youki/run");
+        let root = prepare_first(&[file.join("youki"), home.clone()], uid)?;
+        assert_eq!(root, home);
+        assert_eq!(fs::metadata(&home)?.mode() & 0o777, ROOT_MODE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_first_refuses_unsafe_root() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let uid = geteuid().as_raw();
+        let unsafe_root = tmp.path().join("youki");
+        fs::create_dir(&unsafe_root)?;
+        fs::set_permissions(&unsafe_root, Permissions::from_mode(0o777))?;
+        assert!(matches!(
+            prepare_first(&[unsafe_root, tmp.path().join("home")], uid),
+            Err(RuntimeRootError::WrongMode { .. })
+        ));
+        assert!(!tmp.path().join("home").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("youki");
+        let uid = geteuid().as_raw();
+        prepare(&root, uid)?;
+
+        let metadata = fs::metadata(&root)?;
+        assert_eq!(metadata.mode() & 0o777, ROOT_MODE);
+        for dir in LAYOUT_DIRS {
+            assert!(root.join(dir).is_dir());
+        }
+        // a prepared root is used as is
+        prepare(&root, uid)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_concurrently() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("youki");
+        let uid = geteuid().as_raw();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let root = root.clone();
+                thread::spawn(move || prepare(&root, uid))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        for dir in LAYOUT_DIRS {
+            assert!(root.join(dir).is_dir());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_wrong_mode() -> Result<()> {
+        let uid = geteuid().as_raw();
+        for mode in [0o770, 0o702, 0o777, 0o400] {
+            let tmp = tempfile::tempdir()?;
+            fs::set_permissions(tmp.path(), Permissions::from_mode(mode))?;
+            match prepare(tmp.path(), uid) {
+                Err(RuntimeRootError::WrongMode { found, .. }) => assert_eq!(found, mode),
+                other => panic!("root with mode {mode:o} wasn't refused: {other:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_other_owner() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        fs::set_permissions(tmp.path(), Permissions::from_mode(ROOT_MODE))?;
+        let uid = geteuid().as_raw();
+        match prepare(tmp.path(), uid + 1) {
+            Err(RuntimeRootError::WrongOwner {
+                expected, found, ..
+            }) => {
+                assert_eq!(expected, uid + 1);
+                assert_eq!(found, uid);
+            }
+            other => panic!("root of another user wasn't refused: {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_symlink_and_file() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let uid = geteuid().as_raw();
+        let target = tmp.path().join("target");
+        fs::create_dir(&target)?;
+        fs::set_permissions(&target, Permissions::from_mode(ROOT_MODE))?;
+        let link = tmp.path().join("link");
+        symlink(&target, &link)?;
+        assert!(matches!(
+            prepare(&link, uid),
+            Err(RuntimeRootError::NotADirectory { .. })
+        ));
+
+        let file = tmp.path().join("file");
+        fs::write(&file, "")?;
+        assert!(prepare(&file, uid).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_states_expectation() {
+        let err = RuntimeRootError::WrongMode {
+            path: PathBuf::from("/run/youki"),
+            found: 0o777,
+        };
+        assert_eq!(
+            err.to_string(),
+            "runtime root \"/run/youki\" has mode 0o777, expected 0o700 without group or other write permission"
+        );
+    }
+}
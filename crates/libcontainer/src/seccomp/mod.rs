@@ -1,4 +1,8 @@
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
 use std::num::TryFromIntError;
+use std::os::fd::FromRawFd;
 use std::os::unix::io;
 
 use libseccomp::{
@@ -47,6 +51,14 @@ pub enum SeccompError {
     SetCtlNnp {
         source: libseccomp::error::SeccompError,
     },
+    #[error("failed to export seccomp filter")]
+    Export {
+        source: libseccomp::error::SeccompError,
+    },
+    #[error("failed to read exported seccomp filter")]
+    ReadExport { source: std::io::Error },
+    #[error("exported seccomp filter is not valid utf-8")]
+    ExportUtf8 { source: std::string::FromUtf8Error },
 }
 
 type Result<T> = std::result::Result<T, SeccompError>;
@@ -139,8 +151,10 @@ fn check_seccomp(seccomp: &LinuxSeccomp) -> Result<()> {
     Ok(())
 }
 
-#[tracing::instrument(level = "trace", skip(seccomp))]
-pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
+/// Translates a seccomp profile into an unloaded filter context. This is the
+/// only place a profile is translated, so the filter loaded into a container
+/// and an exported one can't differ.
+fn build_filter(seccomp: &LinuxSeccomp) -> Result<ScmpFilterContext> {
     check_seccomp(seccomp)?;
 
     tracing::trace!(default_action = ?seccomp.default_action(), errno = ?seccomp.default_errno_ret(), "initializing seccomp");
@@ -256,6 +270,13 @@ pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
         }
     }
 
+    Ok(ctx)
+}
+
+#[tracing::instrument(level = "trace", skip(seccomp))]
+pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
+    let ctx = build_filter(seccomp)?;
+
     // In order to use the SECCOMP_SET_MODE_FILTER operation, either the calling
     // thread must have the CAP_SYS_ADMIN capability in its user namespace, or
     // the thread must already have the no_new_privs bit set.
@@ -275,6 +296,47 @@ pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
     Ok(fd)
 }
 
+/// Compiles a seccomp profile to the pseudo filter code (PFC) of libseccomp,
+/// a human readable form of the filter, without loading it.
+pub fn compile_to_pfc(seccomp: &LinuxSeccomp) -> Result<String> {
+    let ctx = build_filter(seccomp)?;
+    let pfc = export(|file| ctx.export_pfc(file))?;
+    String::from_utf8(pfc).map_err(|err| SeccompError::ExportUtf8 { source: err })
+}
+
+/// Compiles a seccomp profile to the BPF program that is loaded into the
+/// kernel, without loading it.
+pub fn compile_to_bpf_bytes(seccomp: &LinuxSeccomp) -> Result<Vec<u8>> {
+    let ctx = build_filter(seccomp)?;
+    export(|file| ctx.export_bpf(file))
+}
+
+/// libseccomp exports to a file descriptor, so the export goes through an
+/// anonymous in-memory file.
+fn export<F>(export_to: F) -> Result<Vec<u8>>
+where
+    F: FnOnce(&mut File) -> std::result::Result<(), libseccomp::error::SeccompError>,
+{
+    let name = CStr::from_bytes_with_nul(b"youki-seccomp-export\0").unwrap();
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(SeccompError::ReadExport {
+            source: std::io::Error::last_os_error(),
+        });
+    }
+    // Safety: the fd was just created and is owned by nothing else.
+    let mut file = unsafe { File::from_raw_fd(fd) };
+
+    export_to(&mut file).map_err(|err| SeccompError::Export { source: err })?;
+
+    let mut exported = Vec::new();
+    file.seek(SeekFrom::Start(0))
+        .and_then(|_| file.read_to_end(&mut exported))
+        .map_err(|err| SeccompError::ReadExport { source: err })?;
+
+    Ok(exported)
+}
+
 pub fn is_notify(seccomp: &LinuxSeccomp) -> bool {
     seccomp
         .syscalls()
@@ -365,6 +427,82 @@ mod tests {
         Ok(())
     }
 
+    /// The golden file was exported with libseccomp 2.5.4 on x86_64, the PFC
+    /// depends on both, so it is only compared there. Run with
+    /// `YOUKI_UPDATE_SECCOMP_GOLDEN=1` to regenerate it after an intended
+    /// change of the translation, and update the version if it changed.
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_moby_pfc_golden() -> Result<()> {
+        const GOLDEN_LIBSECCOMP_VERSION: (u32, u32, u32) = (2, 5, 4);
+
+        let fixture_dir =
+            path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/seccomp/fixture");
+        let spec = oci_spec::runtime::Spec::load(fixture_dir.join("config.json"))
+            .context("Failed to load test spec for seccomp")?;
+        let seccomp_profile = spec.linux().as_ref().unwrap().seccomp().as_ref().unwrap();
+
+        let pfc = compile_to_pfc(seccomp_profile)?;
+        let golden_path = fixture_dir.join("config.pfc");
+        if matches!(
+            std::env::var("YOUKI_UPDATE_SECCOMP_GOLDEN").as_deref(),
+            Ok("1")
+        ) {
+            std::fs::write(&golden_path, &pfc)?;
+            return Ok(());
+        }
+
+        let version = libseccomp::ScmpVersion::current()?;
+        if (version.major, version.minor, version.micro) != GOLDEN_LIBSECCOMP_VERSION {
+            // The PFC of another libseccomp differs without a change of the
+            // translation.
+            return Ok(());
+        }
+        let golden = std::fs::read_to_string(&golden_path)?;
+        assert!(
+            pfc == golden,
+            "seccomp translation changed, compare with {golden_path:?}:\n{pfc}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_to_bpf_bytes() -> Result<()> {
+        let syscall = LinuxSyscallBuilder::default()
+            .names(vec![String::from("getcwd")])
+            .action(LinuxSeccompAction::ScmpActErrno)
+            .build()?;
+        let seccomp_profile = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .architectures(vec![Arch::ScmpArchNative])
+            .syscalls(vec![syscall])
+            .build()?;
+
+        // The program is made of 8 byte sock_filter instructions.
+        let bpf = compile_to_bpf_bytes(&seccomp_profile)?;
+        assert!(!bpf.is_empty());
+        assert_eq!(bpf.len() % 8, 0);
+        // Compiling doesn't load the filter, getcwd still works.
+        assert!(nix::unistd::getcwd().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_rejects_invalid_profile() -> Result<()> {
+        let seccomp_profile = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActNotify)
+            .build()?;
+
+        assert!(matches!(
+            compile_to_pfc(&seccomp_profile),
+            Err(SeccompError::NotifyAsDefaultAction)
+        ));
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_seccomp_notify() -> Result<()> {
@@ -0,0 +1,551 @@
+//! Ephemeral volumes shared between the containers of a group
+//!
+//! Pod-like groups of containers often need a scratch volume that all of them
+//! can see, similar to an `emptyDir` in kubernetes. A shared volume is a tmpfs
+//! created by the runtime under its root path and bind mounted into every
+//! container that attaches to it. It lives as long as at least one of the
+//! attached containers exists.
+//!
+//! The attachments are tracked in two places: the state of each container
+//! lists the groups it is attached to, and every volume has a reference file
+//! per attached container. The reference files are only a cache of the
+//! container states. When a container releases a volume, the remaining
+//! references are checked against the states of the containers they point to,
+//! so a reference left behind by a create that crashed can't keep a volume
+//! alive forever.
+//!
+//! ```text
+//! <root>/.shared-volumes/
+//! ├── .lock
+//! └── <group id>/
+//!     ├── data/            tmpfs mounted into the containers
+//!     └── refs/<container id>
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use nix::fcntl::{Flock, FlockArg};
+use nix::mount::{MntFlags, MsFlags};
+use oci_spec::runtime::{Mount, MountBuilder, Spec};
+
+use crate::container::{default_state_store, State, StateStore};
+use crate::syscall::syscall::create_syscall;
+use crate::syscall::{Syscall, SyscallError};
+
+const SHARED_VOLUMES_DIR: &str = ".shared-volumes";
+const LOCK_FILE: &str = ".lock";
+const DATA_DIR: &str = "data";
+const REFS_DIR: &str = "refs";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SharedVolumeError {
+    #[error("invalid shared volume group id {0:?}")]
+    InvalidGroupId(String),
+    #[error("failed to lock shared volumes in {path:?}")]
+    Lock { path: PathBuf, source: nix::Error },
+    #[error("io error on shared volume path {path:?}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to mount shared volume {0:?}")]
+    Mount(PathBuf, #[source] SyscallError),
+    #[error("failed to unmount shared volume {0:?}")]
+    Unmount(PathBuf, #[source] SyscallError),
+    #[error(transparent)]
+    Spec(#[from] oci_spec::OciSpecError),
+}
+
+type Result<T> = std::result::Result<T, SharedVolumeError>;
+
+/// Options of a shared volume. They are only applied by the first attach
+/// that creates the volume.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SharedVolumeOptions {
+    /// Maximum size of the volume in bytes, defaults to the tmpfs default
+    pub size: Option<u64>,
+    /// Permissions of the root directory of the volume
+    pub mode: Option<u32>,
+    /// Mount the volume read only in the container
+    pub readonly: bool,
+}
+
+/// A request to attach a container to a shared volume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedVolume {
+    pub group_id: String,
+    pub destination: PathBuf,
+    pub options: SharedVolumeOptions,
+}
+
+/// Creates, attaches and removes the shared volumes of a runtime root.
+pub struct SharedVolumeManager {
+    root_path: PathBuf,
+    syscall: Box<dyn Syscall>,
+    store: Arc<dyn StateStore>,
+}
+
+impl SharedVolumeManager {
+    /// Manages the shared volumes of the containers stored in `root_path`.
+    pub fn new<P: Into<PathBuf>>(root_path: P) -> Self {
+        Self {
+            root_path: root_path.into(),
+            syscall: create_syscall(),
+            store: default_state_store(),
+        }
+    }
+
+    /// Sets the store the states of the containers are read from when the
+    /// references of a volume are checked, the
+    /// [default store](crate::container::default_state_store) otherwise.
+    pub fn with_state_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Path of the data directory of the volume of `group_id`, as seen from
+    /// the runtime.
+    pub fn volume_path(&self, group_id: &str) -> PathBuf {
+        self.volumes_dir().join(group_id).join(DATA_DIR)
+    }
+
+    /// Attaches the container to the volume of `group_id`, creating the
+    /// volume if it doesn't exist yet, and adds a bind mount of the volume at
+    /// `dest` to the spec.
+    ///
+    /// The group must already be recorded in the state of the container when
+    /// this is called, otherwise a concurrent release may consider the
+    /// attachment stale.
+    pub fn attach<P: Into<PathBuf>>(
+        &self,
+        spec: &mut Spec,
+        container_id: &str,
+        group_id: &str,
+        dest: P,
+        opts: &SharedVolumeOptions,
+    ) -> Result<()> {
+        validate_group_id(group_id)?;
+        let _lock = self.lock()?;
+
+        let group_dir = self.volumes_dir().join(group_id);
+        let data_dir = group_dir.join(DATA_DIR);
+        let refs_dir = group_dir.join(REFS_DIR);
+        if !data_dir.exists() {
+            tracing::debug!(?group_id, "creating shared volume");
+            create_dir_all(&data_dir)?;
+            create_dir_all(&refs_dir)?;
+            self.syscall
+                .mount(
+                    Some(Path::new("tmpfs")),
+                    &data_dir,
+                    Some("tmpfs"),
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                    Some(&tmpfs_data(opts)),
+                )
+                .map_err(|err| {
+                    tracing::error!(?err, ?data_dir, "failed to mount shared volume");
+                    // Don't leave an unmounted volume behind for the next
+                    // attach to pick up.
+                    let _ = fs::remove_dir_all(&group_dir);
+                    SharedVolumeError::Mount(data_dir.clone(), err)
+                })?;
+        }
+
+        let ref_path = refs_dir.join(container_id);
+        fs::write(&ref_path, b"").map_err(|err| SharedVolumeError::Io {
+            path: ref_path,
+            source: err,
+        })?;
+
+        let mut mounts = spec.mounts().clone().unwrap_or_default();
+        mounts.push(bind_mount(&data_dir, dest.into(), opts.readonly)?);
+        spec.set_mounts(Some(mounts));
+
+        Ok(())
+    }
+
+    /// Attaches the container to all of `volumes`. If one of them can't be
+    /// attached, the references taken so far are dropped again, so a failed
+    /// create doesn't keep the volumes alive.
+    pub fn attach_all(
+        &self,
+        spec: &mut Spec,
+        container_id: &str,
+        volumes: &[SharedVolume],
+    ) -> Result<()> {
+        for (i, volume) in volumes.iter().enumerate() {
+            let attached = self.attach(
+                spec,
+                container_id,
+                &volume.group_id,
+                volume.destination.clone(),
+                &volume.options,
+            );
+            if let Err(err) = attached {
+                tracing::error!(?err, group_id = ?volume.group_id, "failed to attach shared volume");
+                // The failed attach may have created the volume or taken the
+                // reference before it failed.
+                for volume in &volumes[..=i] {
+                    if let Err(err) = self.release(container_id, &volume.group_id) {
+                        tracing::warn!(?err, group_id = ?volume.group_id, "failed to release shared volume");
+                    }
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the reference of the container to the volume of `group_id`.
+    /// The volume is unmounted and removed if no live container references
+    /// it anymore. Returns whether the volume was removed.
+    pub fn release(&self, container_id: &str, group_id: &str) -> Result<bool> {
+        validate_group_id(group_id)?;
+        let _lock = self.lock()?;
+
+        let group_dir = self.volumes_dir().join(group_id);
+        if !group_dir.exists() {
+            return Ok(false);
+        }
+
+        let refs_dir = group_dir.join(REFS_DIR);
+        remove_ref(&refs_dir.join(container_id))?;
+        if self.reconcile_refs(&refs_dir, group_id)? > 0 {
+            return Ok(false);
+        }
+
+        tracing::debug!(?group_id, "removing shared volume");
+        let data_dir = group_dir.join(DATA_DIR);
+        match self.syscall.umount2(&data_dir, MntFlags::MNT_DETACH) {
+            Ok(()) => {}
+            // The volume was never mounted, e.g. the attach failed to mount it.
+            Err(SyscallError::Nix(nix::Error::EINVAL)) => {}
+            Err(err) => {
+                tracing::error!(?err, ?data_dir, "failed to unmount shared volume");
+                return Err(SharedVolumeError::Unmount(data_dir, err));
+            }
+        }
+        fs::remove_dir_all(&group_dir).map_err(|err| SharedVolumeError::Io {
+            path: group_dir,
+            source: err,
+        })?;
+
+        Ok(true)
+    }
+
+    /// Removes the references of containers that no longer exist or whose
+    /// state doesn't list the group, and returns the number of references
+    /// left.
+    fn reconcile_refs(&self, refs_dir: &Path, group_id: &str) -> Result<usize> {
+        let entries = match fs::read_dir(refs_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => {
+                return Err(SharedVolumeError::Io {
+                    path: refs_dir.to_owned(),
+                    source: err,
+                })
+            }
+        };
+
+        let mut live = 0;
+        for entry in entries {
+            let entry = entry.map_err(|err| SharedVolumeError::Io {
+                path: refs_dir.to_owned(),
+                source: err,
+            })?;
+            let container_id = entry.file_name();
+            let container_root = self.root_path.join(&container_id);
+            let attached = container_root.exists()
+                && match State::load_from(&*self.store, &container_root) {
+                    Ok(state) => state.shared_volumes.iter().any(|g| g == group_id),
+                    // Keep the volume if the state can't be read, removing it
+                    // from under a live container is worse than leaking it.
+                    Err(_) => true,
+                };
+            if attached {
+                live += 1;
+            } else {
+                tracing::warn!(
+                    ?container_id,
+                    ?group_id,
+                    "removing stale shared volume reference"
+                );
+                remove_ref(&entry.path())?;
+            }
+        }
+
+        Ok(live)
+    }
+
+    fn volumes_dir(&self) -> PathBuf {
+        self.root_path.join(SHARED_VOLUMES_DIR)
+    }
+
+    // Serializes the attach and release of all volumes of the root, so a
+    // volume can't be removed while another container is attaching to it.
+    fn lock(&self) -> Result<Flock<fs::File>> {
+        let volumes_dir = self.volumes_dir();
+        create_dir_all(&volumes_dir)?;
+        let lock_path = volumes_dir.join(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|err| SharedVolumeError::Io {
+                path: lock_path.clone(),
+                source: err,
+            })?;
+        Flock::lock(file, FlockArg::LockExclusive).map_err(|(_, err)| SharedVolumeError::Lock {
+            path: lock_path,
+            source: err,
+        })
+    }
+}
+
+fn validate_group_id(group_id: &str) -> Result<()> {
+    if group_id.is_empty()
+        || group_id == "."
+        || group_id == ".."
+        || group_id.starts_with('.')
+        || group_id.contains('/')
+    {
+        tracing::error!(?group_id, "invalid shared volume group id");
+        return Err(SharedVolumeError::InvalidGroupId(group_id.to_owned()));
+    }
+
+    Ok(())
+}
+
+fn tmpfs_data(opts: &SharedVolumeOptions) -> String {
+    let mut data = vec![format!("mode={:o}", opts.mode.unwrap_or(0o755))];
+    if let Some(size) = opts.size {
+        data.push(format!("size={size}"));
+    }
+    data.join(",")
+}
+
+fn bind_mount(source: &Path, destination: PathBuf, readonly: bool) -> Result<Mount> {
+    let access = if readonly { "ro" } else { "rw" };
+    Ok(MountBuilder::default()
+        .destination(destination)
+        .typ("bind")
+        .source(source)
+        .options(vec!["rbind".to_owned(), access.to_owned()])
+        .build()?)
+}
+
+fn create_dir_all(path: &Path) -> Result<()> {
+    fs::create_dir_all(path).map_err(|err| SharedVolumeError::Io {
+        path: path.to_owned(),
+        source: err,
+    })
+}
+
+fn remove_ref(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(SharedVolumeError::Io {
+            path: path.to_owned(),
+            source: err,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+    use crate::container::{ContainerStatus, InMemoryStateStore};
+    use crate::syscall::test::TestHelperSyscall;
+
+    fn save_state(root: &Path, container_id: &str, groups: &[&str]) -> Result<()> {
+        let container_root = root.join(container_id);
+        fs::create_dir_all(&container_root)?;
+        let mut state = State::new(
+            container_id,
+            ContainerStatus::Created,
+            None,
+            PathBuf::from("/bundle"),
+        );
+        state.shared_volumes = groups.iter().map(|g| g.to_string()).collect();
+        state.save(&container_root)?;
+        Ok(())
+    }
+
+    fn umounts(manager: &SharedVolumeManager) -> Vec<PathBuf> {
+        manager
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_umount_args()
+            .into_iter()
+            .map(|args| args.target)
+            .collect()
+    }
+
+    #[test]
+    fn test_attach_and_release() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let manager = SharedVolumeManager::new(root.path());
+        let opts = SharedVolumeOptions {
+            size: Some(1024 * 1024),
+            readonly: true,
+            ..Default::default()
+        };
+
+        let mut specs = Vec::new();
+        for id in ["c1", "c2"] {
+            save_state(root.path(), id, &["pod"])?;
+            let mut spec = SpecBuilder::default().mounts(vec![]).build()?;
+            manager.attach(&mut spec, id, "pod", "/shared", &opts)?;
+            specs.push(spec);
+        }
+
+        let volume = manager.volume_path("pod");
+        let mounts = manager
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(mounts.len(), 1, "the volume is only mounted once");
+        assert_eq!(mounts[0].target, volume);
+        assert_eq!(mounts[0].data.as_deref(), Some("mode=755,size=1048576"));
+        for spec in &specs {
+            let mount = &spec.mounts().as_ref().unwrap()[0];
+            assert_eq!(mount.destination(), Path::new("/shared"));
+            assert_eq!(mount.source().as_deref(), Some(volume.as_path()));
+            assert_eq!(
+                mount.options().as_ref().unwrap(),
+                &vec!["rbind".to_string(), "ro".to_string()]
+            );
+        }
+
+        fs::remove_dir_all(root.path().join("c1"))?;
+        assert!(!manager.release("c1", "pod")?);
+        assert!(volume.exists());
+        assert!(umounts(&manager).is_empty());
+
+        fs::remove_dir_all(root.path().join("c2"))?;
+        assert!(manager.release("c2", "pod")?);
+        assert!(!volume.exists());
+        assert_eq!(umounts(&manager), vec![volume]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_reconciles_stale_refs() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let manager = SharedVolumeManager::new(root.path());
+        let opts = SharedVolumeOptions::default();
+
+        for id in ["live", "crashed", "detached"] {
+            save_state(root.path(), id, &["pod"])?;
+            let mut spec = SpecBuilder::default().build()?;
+            manager.attach(&mut spec, id, "pod", "/shared", &opts)?;
+        }
+        // A create that crashed and lost its state, and a container whose
+        // state doesn't reference the group anymore.
+        fs::remove_dir_all(root.path().join("crashed"))?;
+        save_state(root.path(), "detached", &[])?;
+
+        let refs_dir = root
+            .path()
+            .join(SHARED_VOLUMES_DIR)
+            .join("pod")
+            .join(REFS_DIR);
+        assert!(!manager.release("unknown", "pod")?);
+        assert!(refs_dir.join("live").exists());
+        assert!(!refs_dir.join("crashed").exists());
+        assert!(!refs_dir.join("detached").exists());
+
+        fs::remove_dir_all(root.path().join("live"))?;
+        assert!(manager.release("live", "pod")?);
+        assert!(!manager.volume_path("pod").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_reads_states_from_store() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let store = Arc::new(InMemoryStateStore::new());
+        let manager = SharedVolumeManager::new(root.path()).with_state_store(store.clone());
+
+        // The state is only in the store, the container directory is empty. A
+        // state that can't be read would keep the volume alive.
+        let container_root = root.path().join("detached");
+        fs::create_dir_all(&container_root)?;
+        let mut spec = SpecBuilder::default().build()?;
+        manager.attach(&mut spec, "detached", "pod", "/shared", &Default::default())?;
+        State::new(
+            "detached",
+            ContainerStatus::Created,
+            None,
+            PathBuf::from("/bundle"),
+        )
+        .save_to(&*store, &container_root)?;
+
+        assert!(manager.release("unknown", "pod")?);
+        assert!(!manager.volume_path("pod").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_attach_all_rolls_back() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let manager = SharedVolumeManager::new(root.path());
+        let volume = |group_id: &str| SharedVolume {
+            group_id: group_id.to_owned(),
+            destination: PathBuf::from("/shared").join(group_id),
+            options: SharedVolumeOptions::default(),
+        };
+
+        // Another container keeps the volume of the first group alive.
+        save_state(root.path(), "other", &["pod"])?;
+        let mut spec = SpecBuilder::default().build()?;
+        manager.attach_all(&mut spec, "other", &[volume("pod")])?;
+
+        save_state(root.path(), "c1", &["pod", "scratch", ".invalid"])?;
+        let mut spec = SpecBuilder::default().build()?;
+        let volumes = [volume("pod"), volume("scratch"), volume(".invalid")];
+        assert!(matches!(
+            manager.attach_all(&mut spec, "c1", &volumes),
+            Err(SharedVolumeError::InvalidGroupId(_))
+        ));
+
+        let refs_dir = root
+            .path()
+            .join(SHARED_VOLUMES_DIR)
+            .join("pod")
+            .join(REFS_DIR);
+        assert!(refs_dir.join("other").exists());
+        assert!(!refs_dir.join("c1").exists());
+        assert!(!manager.volume_path("scratch").exists());
+        assert_eq!(umounts(&manager), vec![manager.volume_path("scratch")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_group_id() {
+        let manager = SharedVolumeManager::new("/run/youki");
+        for group_id in ["", ".", "..", ".lock", "a/b"] {
+            assert!(matches!(
+                manager.release("c1", group_id),
+                Err(SharedVolumeError::InvalidGroupId(_))
+            ));
+        }
+    }
+}
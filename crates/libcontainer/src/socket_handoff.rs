@@ -0,0 +1,546 @@
+//! Handoff of listening unix sockets to the container
+//!
+//! A socket bind mounted into a container can be connected to, but nothing in
+//! the container can accept the connections unless it has the listening
+//! socket. A mount of the pseudo type `socket` makes the runtime bind and
+//! listen on the socket at the source path on the host:
+//!
+//! ```json
+//! {
+//!     "destination": "/run/app.sock",
+//!     "type": "socket",
+//!     "source": "/run/apps/app.sock",
+//!     "options": ["backlog=64", "uid=1000", "gid=1000", "mode=0660", "fd=3"]
+//! }
+//! ```
+//!
+//! The mount is replaced by a bind mount of the socket to its destination, so
+//! processes in the container can connect to it, and with the `fd` option the
+//! listening socket is passed to the container process as that fd, e.g. for
+//! socket activation. The socket path is bound once, a socket that already
+//! exists fails the creation of the container. The handoffs are recorded in
+//! the [`SOCKET_HANDOFF_ANNOTATION`] annotation of the spec, and the socket
+//! paths are removed again when the container is deleted.
+//!
+//! The socket is bind mounted by path rather than through `/proc/self/fd`, as
+//! a connect through the fd would look up the inode of the socket itself, not
+//! the inode the socket is bound to.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::socket::{self, AddressFamily, Backlog, SockFlag, SockType, UnixAddr};
+use nix::unistd::{self, Gid, Uid};
+use oci_spec::runtime::{Mount, MountBuilder, Spec};
+use serde::{Deserialize, Serialize};
+
+/// Type of the mounts of the spec that are socket handoffs
+pub const SOCKET_MOUNT_TYPE: &str = "socket";
+/// Annotation the handoffs are recorded in, as a JSON list of
+/// [`SocketHandoff`]
+pub const SOCKET_HANDOFF_ANNOTATION: &str = "io.youki.socket-handoff";
+/// Backlog of the listening socket if the mount doesn't set one
+pub const DEFAULT_BACKLOG: i32 = 128;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SocketHandoffError {
+    #[error("socket mount to {destination:?} has no source")]
+    MissingSource { destination: PathBuf },
+    #[error("invalid option {option:?} of socket mount to {destination:?}")]
+    InvalidOption {
+        destination: PathBuf,
+        option: String,
+    },
+    #[error("fd {0} is the target of several socket mounts")]
+    DuplicateFd(RawFd),
+    #[error("failed to create socket {path:?}")]
+    Socket { path: PathBuf, source: nix::Error },
+    #[error("failed to bind socket {path:?}")]
+    Bind { path: PathBuf, source: nix::Error },
+    #[error("failed to listen on socket {path:?}")]
+    Listen { path: PathBuf, source: nix::Error },
+    #[error("failed to change the owner of socket {path:?}")]
+    Chown { path: PathBuf, source: nix::Error },
+    #[error("failed to change the mode of socket {path:?}")]
+    Chmod {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to pass socket to fd {target}")]
+    InstallFd { target: RawFd, source: nix::Error },
+    #[error(transparent)]
+    Spec(#[from] oci_spec::OciSpecError),
+    #[error("failed to record socket handoffs")]
+    Serialize(#[source] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, SocketHandoffError>;
+
+/// A socket mount of the spec
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SocketHandoff {
+    /// Path of the socket on the host
+    pub source: PathBuf,
+    /// Path of the socket in the container
+    pub destination: PathBuf,
+    pub backlog: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Fd the listening socket is passed to the container process as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fd: Option<RawFd>,
+    /// Options passed on to the bind mount
+    #[serde(skip)]
+    pub mount_options: Vec<String>,
+}
+
+impl SocketHandoff {
+    fn from_mount(mount: &Mount) -> Result<Self> {
+        let destination = mount.destination().clone();
+        let source = mount
+            .source()
+            .clone()
+            .ok_or_else(|| SocketHandoffError::MissingSource {
+                destination: destination.clone(),
+            })?;
+        let mut handoff = Self {
+            source,
+            destination,
+            backlog: DEFAULT_BACKLOG,
+            uid: None,
+            gid: None,
+            mode: None,
+            fd: None,
+            mount_options: Vec::new(),
+        };
+
+        for option in mount.options().iter().flatten() {
+            let invalid = || SocketHandoffError::InvalidOption {
+                destination: handoff.destination.clone(),
+                option: option.clone(),
+            };
+            let (key, value) = match option.split_once('=') {
+                Some(kv) => kv,
+                None => {
+                    handoff.mount_options.push(option.clone());
+                    continue;
+                }
+            };
+            match key {
+                "backlog" => handoff.backlog = value.parse().map_err(|_| invalid())?,
+                "uid" => handoff.uid = Some(value.parse().map_err(|_| invalid())?),
+                "gid" => handoff.gid = Some(value.parse().map_err(|_| invalid())?),
+                "mode" => {
+                    handoff.mode = Some(u32::from_str_radix(value, 8).map_err(|_| invalid())?)
+                }
+                "fd" => {
+                    let fd: RawFd = value.parse().map_err(|_| invalid())?;
+                    // stdio stays what the container was given
+                    if fd < 3 {
+                        return Err(invalid());
+                    }
+                    handoff.fd = Some(fd);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(handoff)
+    }
+
+    fn bind_mount(&self) -> Result<Mount> {
+        let mut options = vec!["bind".to_owned()];
+        options.extend(
+            self.mount_options
+                .iter()
+                .filter(|o| *o != "bind" && *o != "rbind")
+                .cloned(),
+        );
+        Ok(MountBuilder::default()
+            .destination(self.destination.clone())
+            .typ("bind")
+            .source(self.source.clone())
+            .options(options)
+            .build()?)
+    }
+}
+
+/// A socket bound and listening on the host
+#[derive(Debug)]
+pub struct ListeningSocket {
+    pub handoff: SocketHandoff,
+    pub fd: OwnedFd,
+}
+
+/// The listening sockets of a container. The socket paths are removed when
+/// this is dropped, unless the container was created and [`keep`] was called.
+///
+/// [`keep`]: ListeningSockets::keep
+#[derive(Debug, Default)]
+pub struct ListeningSockets {
+    sockets: Vec<ListeningSocket>,
+    keep: bool,
+}
+
+impl ListeningSockets {
+    /// Pairs of the fd of a listening socket and the fd it is passed to the
+    /// container process as
+    pub fn fds(&self) -> Vec<(RawFd, RawFd)> {
+        self.sockets
+            .iter()
+            .filter_map(|s| s.handoff.fd.map(|target| (s.fd.as_raw_fd(), target)))
+            .collect()
+    }
+
+    pub fn sockets(&self) -> &[ListeningSocket] {
+        &self.sockets
+    }
+
+    /// Leaves the socket paths in place, once the container holds the sockets
+    pub fn keep(mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for ListeningSockets {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        for socket in &self.sockets {
+            if let Err(err) = fs::remove_file(&socket.handoff.source) {
+                tracing::warn!(?err, path = ?socket.handoff.source, "failed to remove socket");
+            }
+        }
+    }
+}
+
+/// Binds and listens on the sockets of the socket mounts of the spec, and
+/// replaces the mounts by bind mounts of the sockets
+pub fn prepare(spec: &mut Spec) -> Result<ListeningSockets> {
+    let mut mounts = match spec.mounts() {
+        Some(mounts)
+            if mounts
+                .iter()
+                .any(|m| m.typ().as_deref() == Some(SOCKET_MOUNT_TYPE)) =>
+        {
+            mounts.clone()
+        }
+        _ => return Ok(ListeningSockets::default()),
+    };
+
+    let mut sockets = ListeningSockets::default();
+    for mount in mounts.iter_mut() {
+        if mount.typ().as_deref() != Some(SOCKET_MOUNT_TYPE) {
+            continue;
+        }
+        let handoff = SocketHandoff::from_mount(mount)?;
+        if let Some(fd) = handoff.fd {
+            if sockets.sockets.iter().any(|s| s.handoff.fd == Some(fd)) {
+                return Err(SocketHandoffError::DuplicateFd(fd));
+            }
+        }
+        *mount = handoff.bind_mount()?;
+        let fd = listen(&handoff)?;
+        sockets.sockets.push(ListeningSocket { handoff, fd });
+    }
+    spec.set_mounts(Some(mounts));
+
+    let handoffs: Vec<&SocketHandoff> = sockets.sockets.iter().map(|s| &s.handoff).collect();
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    annotations.insert(
+        SOCKET_HANDOFF_ANNOTATION.to_owned(),
+        serde_json::to_string(&handoffs).map_err(SocketHandoffError::Serialize)?,
+    );
+    spec.set_annotations(Some(annotations));
+
+    Ok(sockets)
+}
+
+fn listen(handoff: &SocketHandoff) -> Result<OwnedFd> {
+    let path = &handoff.source;
+    tracing::debug!(
+        ?path,
+        backlog = handoff.backlog,
+        "listening on handed off socket"
+    );
+    // The socket is inherited by the init process, which only passes it on to
+    // the container process at its target fd.
+    let fd = socket::socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .map_err(|err| SocketHandoffError::Socket {
+        path: path.clone(),
+        source: err,
+    })?;
+    let addr = UnixAddr::new(path).map_err(|err| SocketHandoffError::Socket {
+        path: path.clone(),
+        source: err,
+    })?;
+    socket::bind(fd.as_raw_fd(), &addr).map_err(|err| {
+        tracing::error!(?err, ?path, "failed to bind handed off socket");
+        SocketHandoffError::Bind {
+            path: path.clone(),
+            source: err,
+        }
+    })?;
+
+    // From here on the socket path exists and is removed again on failure.
+    let cleanup = |err| {
+        let _ = fs::remove_file(path);
+        err
+    };
+    let backlog = Backlog::new(handoff.backlog).map_err(|err| {
+        cleanup(SocketHandoffError::Listen {
+            path: path.clone(),
+            source: err,
+        })
+    })?;
+    socket::listen(&fd, backlog).map_err(|err| {
+        cleanup(SocketHandoffError::Listen {
+            path: path.clone(),
+            source: err,
+        })
+    })?;
+    if handoff.uid.is_some() || handoff.gid.is_some() {
+        unistd::chown(
+            path,
+            handoff.uid.map(Uid::from_raw),
+            handoff.gid.map(Gid::from_raw),
+        )
+        .map_err(|err| {
+            cleanup(SocketHandoffError::Chown {
+                path: path.clone(),
+                source: err,
+            })
+        })?;
+    }
+    if let Some(mode) = handoff.mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|err| {
+            cleanup(SocketHandoffError::Chmod {
+                path: path.clone(),
+                source: err,
+            })
+        })?;
+    }
+
+    Ok(fd)
+}
+
+/// Returns the handoffs recorded in the annotations of a container
+pub fn recorded(annotations: Option<&HashMap<String, String>>) -> Vec<SocketHandoff> {
+    annotations
+        .and_then(|annotations| annotations.get(SOCKET_HANDOFF_ANNOTATION))
+        .and_then(|handoffs| serde_json::from_str(handoffs).ok())
+        .unwrap_or_default()
+}
+
+/// Removes the socket paths of the recorded handoffs, so the paths can be
+/// bound again. Paths that are already gone are skipped.
+pub fn remove_sockets(handoffs: &[SocketHandoff]) -> std::io::Result<bool> {
+    let mut removed = false;
+    for handoff in handoffs {
+        match remove_socket(&handoff.source) {
+            Ok(true) => removed = true,
+            Ok(false) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                tracing::error!(?err, path = ?handoff.source, "failed to remove socket");
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+fn remove_socket(path: &Path) -> std::io::Result<bool> {
+    // Something else may have taken the path since, which is left alone.
+    if !fs::symlink_metadata(path)?.file_type().is_socket() {
+        tracing::warn!(?path, "handed off socket path is not a socket anymore");
+        return Ok(false);
+    }
+    fs::remove_file(path)?;
+    Ok(true)
+}
+
+/// Passes the listening sockets to the container process at their target
+/// fds. This replaces whatever is open at the targets, so it's done right
+/// before the exec, when the fds of the init process are not used anymore.
+pub fn install_fds(fds: &[(RawFd, RawFd)]) -> Result<()> {
+    let above_targets = fds.iter().map(|(_, target)| *target).max().unwrap_or(0) + 1;
+    // Move the sockets out of the way first, so installing one socket doesn't
+    // replace another one that sits at its target.
+    let mut moved = Vec::with_capacity(fds.len());
+    for (fd, target) in fds {
+        let dup = fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(above_targets)).map_err(|err| {
+            SocketHandoffError::InstallFd {
+                target: *target,
+                source: err,
+            }
+        })?;
+        moved.push((dup, *target));
+    }
+    for (fd, target) in moved {
+        // dup2 clears the close-on-exec flag of the target.
+        unistd::dup2(fd, target).map_err(|err| SocketHandoffError::InstallFd {
+            target,
+            source: err,
+        })?;
+        let _ = unistd::close(fd);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::path::Path;
+
+    use anyhow::Result;
+    use nix::fcntl::FdFlag;
+
+    use super::*;
+
+    fn spec_with_socket(source: &Path, options: &[&str]) -> Result<Spec> {
+        let mut spec = Spec::default();
+        let mut mounts = spec.mounts().clone().unwrap_or_default();
+        mounts.push(
+            MountBuilder::default()
+                .destination("/run/app.sock")
+                .typ(SOCKET_MOUNT_TYPE)
+                .source(source)
+                .options(options.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+                .build()?,
+        );
+        spec.set_mounts(Some(mounts));
+        Ok(spec)
+    }
+
+    #[test]
+    fn test_prepare_socket_mount() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("app.sock");
+        let mut spec = spec_with_socket(&path, &["backlog=4", "mode=0600", "fd=3", "ro"])?;
+
+        let sockets = prepare(&mut spec)?;
+        assert!(fs::metadata(&path)?.file_type().is_socket());
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        UnixStream::connect(&path)?;
+        assert_eq!(sockets.fds().len(), 1);
+        assert_eq!(sockets.fds()[0].1, 3);
+
+        let mount = spec.mounts().as_ref().unwrap().last().unwrap().clone();
+        assert_eq!(mount.typ().as_deref(), Some("bind"));
+        assert_eq!(mount.source().as_deref(), Some(path.as_path()));
+        assert_eq!(
+            mount.options().clone().unwrap(),
+            vec!["bind".to_owned(), "ro".to_owned()]
+        );
+        let recorded: Vec<SocketHandoff> =
+            serde_json::from_str(&spec.annotations().as_ref().unwrap()[SOCKET_HANDOFF_ANNOTATION])?;
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].source, path);
+        assert_eq!(recorded[0].fd, Some(3));
+
+        // the socket is removed unless the container was created
+        drop(sockets);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_address_in_use() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("app.sock");
+        let mut spec = spec_with_socket(&path, &[])?;
+        let sockets = prepare(&mut spec)?;
+        sockets.keep();
+
+        let mut spec = spec_with_socket(&path, &[])?;
+        let err = prepare(&mut spec).unwrap_err();
+        assert!(matches!(
+            &err,
+            SocketHandoffError::Bind { path: p, source } if p == &path && *source == nix::Error::EADDRINUSE
+        ));
+        assert!(err.to_string().contains(&path.display().to_string()));
+        // the socket of the other container is left alone
+        assert!(path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_recorded_sockets() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("app.sock");
+        let mut spec = spec_with_socket(&path, &[])?;
+        prepare(&mut spec)?.keep();
+
+        let handoffs = recorded(spec.annotations().as_ref());
+        assert_eq!(handoffs.len(), 1);
+        assert!(remove_sockets(&handoffs)?);
+        assert!(!path.exists());
+        // removing again is a no-op
+        assert!(!remove_sockets(&handoffs)?);
+
+        // the path can be bound again, e.g. by a container with the same id
+        let mut spec = spec_with_socket(&path, &[])?;
+        prepare(&mut spec)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_socket_options() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        for option in ["fd=1", "fd=x", "backlog=-", "mode=999", "owner=1"] {
+            let mut spec = spec_with_socket(&tmp.path().join("app.sock"), &[option])?;
+            assert!(
+                matches!(
+                    prepare(&mut spec),
+                    Err(SocketHandoffError::InvalidOption { .. })
+                ),
+                "{option}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_install_fds() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let first = fs::File::open(tmp.path())?;
+        let second = fs::File::open(tmp.path())?;
+        // far above the fds of the test process
+        let targets = (900, 901);
+
+        install_fds(&[
+            (first.as_raw_fd(), targets.0),
+            (second.as_raw_fd(), targets.1),
+        ])?;
+        for target in [targets.0, targets.1] {
+            let flags = FdFlag::from_bits_truncate(fcntl(target, FcntlArg::F_GETFD)?);
+            assert!(!flags.contains(FdFlag::FD_CLOEXEC));
+            unistd::close(target)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! Differences between the spec of a bundle and the spec a container was
+//! created with
+//!
+//! The builder adjusts the spec of the bundle before the container is
+//! created: it resolves paths against the bundle, enforces policies, infers
+//! values from annotations and rewrites mounts. [`spec_diff`] compares two
+//! specs structurally and returns the differences with the JSON pointers of
+//! the values. Arrays, like the env or the seccomp rules, are compared
+//! element by element, so an added env var shows up as one added element
+//! instead of the whole list.
+//!
+//! The adjustment sites tag the changes they make with their
+//! [`subsystem`] in a [`SpecProvenance`], which is saved with the spec of the
+//! bundle in the container directory of an init container created with
+//! [`with_spec_provenance`](crate::container::init_builder::InitContainerBuilder::with_spec_provenance).
+//! [`Container::spec_diff`](crate::container::Container::spec_diff) returns
+//! the differences to the resolved spec with the subsystem that made each
+//! change, if known.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::Spec;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Name of the file the provenance is saved as in the container directory
+const SPEC_PROVENANCE_NAME: &str = "spec_provenance.json";
+/// Arrays whose elements would need more comparisons than this to find the
+/// common elements are compared index by index instead
+const MAX_ARRAY_COMPARISONS: usize = 1 << 20;
+
+/// Subsystems of the builder that adjust the spec
+pub mod subsystem {
+    /// Resolves the rootfs and hook paths against the bundle
+    pub const BUNDLE: &str = "bundle";
+    /// Applies the resource overrides of the builder
+    pub const RESOURCES: &str = "resources";
+    /// Restricts the cpuset to online cpus and infers the NUMA nodes
+    pub const CPUSET: &str = "cpuset";
+    /// Layers env files, the explicit env and annotations on the env
+    pub const ENV: &str = "env";
+    /// Drops the hostname the container doesn't set
+    pub const HOSTNAME: &str = "hostname";
+    /// Enforces the security policies of the builder
+    pub const POLICY: &str = "policy";
+    /// Validates the cgroups of a rootless container against the subtree
+    /// delegated to the user
+    pub const ROOTLESS: &str = "rootless";
+    /// Adds, drops and rewrites mounts
+    pub const MOUNTS: &str = "mounts";
+    /// Records if `/etc/hostname` is written
+    pub const HOSTNAME_FILE: &str = "hostname_file";
+    /// Hands listening sockets to the container
+    pub const SOCKET_HANDOFF: &str = "socket_handoff";
+    /// Mounts the shared volumes
+    pub const SHARED_VOLUMES: &str = "shared_volumes";
+    /// Passes the readiness socket to the container
+    pub const READINESS: &str = "readiness";
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpecDiffError {
+    #[error("failed to save spec provenance")]
+    SaveIO {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("failed to save spec provenance")]
+    SaveEncode {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+    #[error("failed to load spec provenance")]
+    LoadIO {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("failed to parse spec provenance")]
+    LoadParse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+}
+
+type Result<T> = std::result::Result<T, SpecDiffError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A difference between two specs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffEntry {
+    /// JSON pointer of the value. A removed array element is addressed by
+    /// its index in the original spec, any other by its index in the
+    /// resolved spec.
+    pub path: String,
+    pub op: DiffOp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<Value>,
+    /// Subsystem that made the change, see [`subsystem`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsystem: Option<String>,
+}
+
+/// Returns the differences between the `original` and the `resolved` spec,
+/// ordered by their path, see the [module](self) docs
+pub fn spec_diff(original: &Spec, resolved: &Spec) -> Vec<DiffEntry> {
+    let mut entries = Vec::new();
+    diff_value("", &to_value(original), &to_value(resolved), &mut entries);
+    entries
+}
+
+/// The spec of a bundle and the subsystems that changed it, see the
+/// [module](self) docs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpecProvenance {
+    /// Spec of the bundle, before any adjustment
+    pub original: Spec,
+    /// Subsystem that last changed the value at a JSON pointer. The array
+    /// an element was changed in is recorded, too, as the index of the
+    /// element may shift with later changes.
+    pub changes: BTreeMap<String, String>,
+    /// Spec as of the last tag
+    #[serde(skip)]
+    current: Value,
+}
+
+impl SpecProvenance {
+    pub fn new(original: &Spec) -> Self {
+        Self {
+            original: original.clone(),
+            changes: BTreeMap::new(),
+            current: to_value(original),
+        }
+    }
+
+    /// Attributes the changes of `spec` since the last tag to `subsystem`
+    pub fn tag(&mut self, subsystem: &str, spec: &Spec) {
+        let current = to_value(spec);
+        let mut entries = Vec::new();
+        diff_value("", &self.current, &current, &mut entries);
+        for entry in entries {
+            if let Some((parent, index)) = entry.path.rsplit_once('/') {
+                if !parent.is_empty() && index.bytes().all(|byte| byte.is_ascii_digit()) {
+                    self.changes.insert(parent.to_owned(), subsystem.to_owned());
+                }
+            }
+            tracing::trace!(path = entry.path, subsystem, "spec adjusted");
+            self.changes.insert(entry.path, subsystem.to_owned());
+        }
+        self.current = current;
+    }
+
+    /// Returns the subsystem that changed the value at `path`, or the
+    /// closest value containing it
+    pub fn subsystem(&self, path: &str) -> Option<&str> {
+        let mut path = path;
+        while !path.is_empty() {
+            if let Some(subsystem) = self.changes.get(path) {
+                return Some(subsystem);
+            }
+            path = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        }
+        None
+    }
+
+    /// Returns the differences between the spec of the bundle and
+    /// `resolved`, with the subsystems that made them
+    pub fn diff(&self, resolved: &Spec) -> Vec<DiffEntry> {
+        let mut entries = spec_diff(&self.original, resolved);
+        for entry in &mut entries {
+            entry.subsystem = self.subsystem(&entry.path).map(str::to_owned);
+        }
+        entries
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = fs::File::create(path.join(SPEC_PROVENANCE_NAME)).map_err(|err| {
+            SpecDiffError::SaveIO {
+                source: err,
+                path: path.to_owned(),
+            }
+        })?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self).map_err(|err| SpecDiffError::SaveEncode {
+            source: err,
+            path: path.to_owned(),
+        })?;
+        writer.flush().map_err(|err| SpecDiffError::SaveIO {
+            source: err,
+            path: path.to_owned(),
+        })?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = fs::File::open(path.join(SPEC_PROVENANCE_NAME)).map_err(|err| {
+            SpecDiffError::LoadIO {
+                source: err,
+                path: path.to_owned(),
+            }
+        })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| SpecDiffError::LoadParse {
+            source: err,
+            path: path.to_owned(),
+        })
+    }
+}
+
+fn to_value(spec: &Spec) -> Value {
+    serde_json::to_value(spec).unwrap_or_else(|err| {
+        // The spec has string keys only, so this doesn't happen in practice.
+        tracing::warn!(?err, "failed to serialize spec for the diff");
+        Value::Null
+    })
+}
+
+fn diff_value(path: &str, old: &Value, new: &Value, entries: &mut Vec<DiffEntry>) {
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                let path = format!("{path}/{}", escape(key));
+                match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) => diff_value(&path, old, new, entries),
+                    (Some(old), None) => entries.push(removed(path, old)),
+                    (None, Some(new)) => entries.push(added(path, new)),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => diff_array(path, old, new, entries),
+        _ => entries.push(DiffEntry {
+            path: path.to_owned(),
+            op: DiffOp::Changed,
+            old: Some(old.clone()),
+            new: Some(new.clone()),
+            subsystem: None,
+        }),
+    }
+}
+
+/// Keeps the longest common subsequence of the elements, the others are
+/// removed or added. A run of removed elements followed by added ones at the
+/// same place is a change of the elements, which is compared element-wise.
+fn diff_array(path: &str, old: &[Value], new: &[Value], entries: &mut Vec<DiffEntry>) {
+    if old.len().saturating_mul(new.len()) > MAX_ARRAY_COMPARISONS {
+        let removed_run: Vec<_> = (0..old.len()).collect();
+        let added_run: Vec<_> = (0..new.len()).collect();
+        diff_run(path, old, new, &removed_run, &added_run, entries);
+        return;
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..]
+    // and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut removed_run = Vec::new();
+    let mut added_run = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff_run(path, old, new, &removed_run, &added_run, entries);
+            removed_run.clear();
+            added_run.clear();
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            removed_run.push(i);
+            i += 1;
+        } else {
+            added_run.push(j);
+            j += 1;
+        }
+    }
+    diff_run(path, old, new, &removed_run, &added_run, entries);
+}
+
+fn diff_run(
+    path: &str,
+    old: &[Value],
+    new: &[Value],
+    removed_run: &[usize],
+    added_run: &[usize],
+    entries: &mut Vec<DiffEntry>,
+) {
+    let paired = removed_run.len().min(added_run.len());
+    for (&i, &j) in removed_run.iter().zip(added_run) {
+        diff_value(&format!("{path}/{j}"), &old[i], &new[j], entries);
+    }
+    for &i in &removed_run[paired..] {
+        entries.push(removed(format!("{path}/{i}"), &old[i]));
+    }
+    for &j in &added_run[paired..] {
+        entries.push(added(format!("{path}/{j}"), &new[j]));
+    }
+}
+
+fn added(path: String, new: &Value) -> DiffEntry {
+    DiffEntry {
+        path,
+        op: DiffOp::Added,
+        old: None,
+        new: Some(new.clone()),
+        subsystem: None,
+    }
+}
+
+fn removed(path: String, old: &Value) -> DiffEntry {
+    DiffEntry {
+        path,
+        op: DiffOp::Removed,
+        old: Some(old.clone()),
+        new: None,
+        subsystem: None,
+    }
+}
+
+/// Escapes a key for a JSON pointer, see RFC 6901
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use oci_spec::runtime::ProcessBuilder;
+    use serde_json::json;
+
+    use super::*;
+
+    fn with_env(env: &[&str]) -> Result<Spec> {
+        let mut spec = Spec::default();
+        let process = ProcessBuilder::default()
+            .env(env.iter().map(|var| var.to_string()).collect::<Vec<_>>())
+            .build()?;
+        spec.set_process(Some(process));
+        Ok(spec)
+    }
+
+    #[test]
+    fn test_spec_diff() -> Result<()> {
+        let mut spec = Spec::default();
+        spec.set_hostname(Some("bundle".to_owned()));
+        spec.set_annotations(Some(HashMap::new()));
+        assert!(spec_diff(&spec, &spec).is_empty());
+
+        let mut resolved = spec.clone();
+        resolved.set_hostname(None);
+        resolved.set_annotations(Some([("a/b~c".to_owned(), "d".to_owned())].into()));
+        assert_eq!(
+            spec_diff(&spec, &resolved),
+            vec![
+                added("/annotations/a~1b~0c".to_owned(), &json!("d")),
+                removed("/hostname".to_owned(), &json!("bundle")),
+            ]
+        );
+
+        let mut original = resolved.clone();
+        original.set_annotations(Some([("a/b~c".to_owned(), "e".to_owned())].into()));
+        assert_eq!(
+            spec_diff(&original, &resolved),
+            vec![DiffEntry {
+                path: "/annotations/a~1b~0c".to_owned(),
+                op: DiffOp::Changed,
+                old: Some(json!("e")),
+                new: Some(json!("d")),
+                subsystem: None,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_diff_arrays() -> Result<()> {
+        let original = with_env(&["A=1", "B=2", "C=3"])?;
+        let resolved = with_env(&["A=1", "X=0", "B=2", "C=4"])?;
+        assert_eq!(
+            spec_diff(&original, &resolved),
+            vec![
+                added("/process/env/1".to_owned(), &json!("X=0")),
+                DiffEntry {
+                    path: "/process/env/3".to_owned(),
+                    op: DiffOp::Changed,
+                    old: Some(json!("C=3")),
+                    new: Some(json!("C=4")),
+                    subsystem: None,
+                },
+            ]
+        );
+
+        let resolved = with_env(&["B=2"])?;
+        assert_eq!(
+            spec_diff(&original, &resolved),
+            vec![
+                removed("/process/env/0".to_owned(), &json!("A=1")),
+                removed("/process/env/2".to_owned(), &json!("C=3")),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_provenance() -> Result<()> {
+        let original = with_env(&["A=1"])?;
+        let mut provenance = SpecProvenance::new(&original);
+
+        let mut spec = with_env(&["A=1", "B=2"])?;
+        provenance.tag(subsystem::ENV, &spec);
+        spec.set_hostname(Some("resolved".to_owned()));
+        provenance.tag(subsystem::HOSTNAME, &spec);
+        // nothing changed since the last tag
+        provenance.tag(subsystem::READINESS, &spec);
+        // a later change of an element takes it over
+        spec.set_hostname(Some("policy".to_owned()));
+        provenance.tag(subsystem::POLICY, &spec);
+
+        let diff = provenance.diff(&spec);
+        let subsystems: Vec<_> = diff
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.subsystem.as_deref()))
+            .collect();
+        assert_eq!(
+            subsystems,
+            vec![
+                ("/hostname", Some(subsystem::POLICY)),
+                ("/process/env/1", Some(subsystem::ENV)),
+            ]
+        );
+        // the array of a changed element is attributed, too
+        assert_eq!(provenance.subsystem("/process/env/7"), Some(subsystem::ENV));
+        assert_eq!(provenance.subsystem("/process/args"), None);
+        assert_eq!(provenance.subsystem(""), None);
+
+        let dir = tempfile::tempdir()?;
+        provenance.save(dir.path())?;
+        let loaded = SpecProvenance::load(dir.path())?;
+        assert_eq!(loaded.changes, provenance.changes);
+        assert_eq!(loaded.diff(&spec), diff);
+        assert!(matches!(
+            SpecProvenance::load(dir.path().join("missing")),
+            Err(SpecDiffError::LoadIO { .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,434 @@
+//! Guarded loading of the spec of a bundle
+//!
+//! The spec of a bundle is untrusted input. A multi-gigabyte config.json or
+//! a deeply nested document can exhaust the memory or the stack of the
+//! runtime before any validation runs. The spec is therefore only read if its
+//! file is within a size limit, its nesting is checked before it is
+//! deserialized, and the lists that the runtime iterates over during the
+//! setup are capped. The defaults are generous, [`Limits`] can be raised for
+//! legitimate giant specs.
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::Spec;
+
+use crate::capabilities::{self, CapabilityError, CAP_LAST_CAP_PATH};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpecValidationError {
+    #[error("failed to read spec {path:?}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("spec is {size} bytes, the limit is {limit}")]
+    TooLarge { size: u64, limit: u64 },
+    #[error("spec is nested deeper than the limit of {limit}")]
+    TooDeep { limit: usize },
+    #[error("failed to parse spec")]
+    Parse(#[source] serde_json::Error),
+    #[error(transparent)]
+    Capability(#[from] CapabilityError),
+    #[error("spec has {count} {what}, the limit is {limit}")]
+    TooMany {
+        what: &'static str,
+        count: usize,
+        limit: usize,
+    },
+}
+
+type Result<T> = std::result::Result<T, SpecValidationError>;
+
+/// Limits of a spec loaded from a bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Size of the config.json in bytes
+    pub max_bytes: u64,
+    /// Nesting of the objects and arrays of the config.json
+    pub max_depth: usize,
+    pub max_mounts: usize,
+    pub max_devices: usize,
+    /// Syscall rules of the seccomp profile
+    pub max_seccomp_rules: usize,
+    /// Env entries of the process
+    pub max_env: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 16 * 1024 * 1024,
+            max_depth: 64,
+            max_mounts: 4096,
+            max_devices: 4096,
+            max_seccomp_rules: 16384,
+            max_env: 16384,
+        }
+    }
+}
+
+impl Limits {
+    /// No limits, for a spec that was already checked when the container
+    /// was created
+    pub fn unlimited() -> Self {
+        Self {
+            max_bytes: u64::MAX,
+            max_depth: usize::MAX,
+            max_mounts: usize::MAX,
+            max_devices: usize::MAX,
+            max_seccomp_rules: usize::MAX,
+            max_env: usize::MAX,
+        }
+    }
+
+    /// Loads the spec at `path`, refusing a spec beyond the limits
+    pub fn load_spec<P: AsRef<Path>>(&self, path: P) -> Result<Spec> {
+        let path = path.as_ref();
+        let read_err = |source| SpecValidationError::Read {
+            path: path.to_owned(),
+            source,
+        };
+
+        let file = File::open(path).map_err(read_err)?;
+        let size = file.metadata().map_err(read_err)?.len();
+        self.check_size(size)?;
+        // The file may grow between the stat and the read.
+        let mut json = Vec::with_capacity(size as usize);
+        file.take(self.max_bytes.saturating_add(1))
+            .read_to_end(&mut json)
+            .map_err(read_err)?;
+        self.check_size(json.len() as u64)?;
+
+        self.parse_spec(&json)
+    }
+
+    /// Parses the spec from `json`, refusing a spec beyond the limits. The
+    /// capability names are normalized, see [`capabilities`].
+    pub fn parse_spec(&self, json: &[u8]) -> Result<Spec> {
+        self.check_size(json.len() as u64)?;
+        check_depth(json, self.max_depth)?;
+        let mut value: serde_json::Value =
+            serde_json::from_slice(json).map_err(SpecValidationError::Parse)?;
+        let last_cap = capabilities::last_cap(Path::new(CAP_LAST_CAP_PATH));
+        capabilities::normalize_spec_json(&mut value, last_cap)?;
+        let spec: Spec = serde_json::from_value(value).map_err(SpecValidationError::Parse)?;
+        self.check_counts(&spec)?;
+        Ok(spec)
+    }
+
+    fn check_size(&self, size: u64) -> Result<()> {
+        if size > self.max_bytes {
+            tracing::error!(size, limit = self.max_bytes, "spec is too large");
+            return Err(SpecValidationError::TooLarge {
+                size,
+                limit: self.max_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the lists of the spec against the limits
+    pub fn check_counts(&self, spec: &Spec) -> Result<()> {
+        let linux = spec.linux().as_ref();
+        let counts = [
+            (
+                "mounts",
+                spec.mounts().as_ref().map_or(0, Vec::len),
+                self.max_mounts,
+            ),
+            (
+                "devices",
+                linux
+                    .and_then(|linux| linux.devices().as_ref())
+                    .map_or(0, Vec::len),
+                self.max_devices,
+            ),
+            (
+                "seccomp rules",
+                linux
+                    .and_then(|linux| linux.seccomp().as_ref())
+                    .and_then(|seccomp| seccomp.syscalls().as_ref())
+                    .map_or(0, Vec::len),
+                self.max_seccomp_rules,
+            ),
+            (
+                "env entries",
+                spec.process()
+                    .as_ref()
+                    .and_then(|process| process.env().as_ref())
+                    .map_or(0, Vec::len),
+                self.max_env,
+            ),
+        ];
+
+        for (what, count, limit) in counts {
+            if count > limit {
+                tracing::error!(what, count, limit, "spec exceeds a limit");
+                return Err(SpecValidationError::TooMany { what, count, limit });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the nesting of the objects and arrays of the json without parsing
+/// it, so serde never recurses into a hostile document
+fn check_depth(json: &[u8], limit: usize) -> Result<()> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > limit {
+                    tracing::error!(limit, "spec is nested too deep");
+                    return Err(SpecValidationError::TooDeep { limit });
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{
+        Capabilities, Capability, LinuxBuilder, LinuxDeviceBuilder, LinuxSeccompAction,
+        LinuxSeccompBuilder, LinuxSyscallBuilder, MountBuilder, ProcessBuilder,
+    };
+
+    use super::*;
+
+    fn unlimited() -> Limits {
+        Limits::unlimited()
+    }
+
+    fn assert_too_many(result: super::Result<()>, count: usize, limit: usize) {
+        match result {
+            Err(SpecValidationError::TooMany {
+                count: got_count,
+                limit: got_limit,
+                ..
+            }) => assert_eq!((got_count, got_limit), (count, limit)),
+            other => panic!("expected too many, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_default_spec_within_default_limits() -> Result<()> {
+        let json = serde_json::to_vec(&Spec::default())?;
+        Limits::default().parse_spec(&json)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_capabilities_normalized() -> Result<()> {
+        let json = br#"{
+            "ociVersion": "1.0.2",
+            "root": {"path": "rootfs"},
+            "process": {
+                "cwd": "/",
+                "user": {"uid": 0, "gid": 0},
+                "capabilities": {
+                    "bounding": ["net_admin", "CAP_CHOWN", "CAP_NET_ADMIN"],
+                    "effective": ["Cap_Kill"]
+                }
+            }
+        }"#;
+        let spec = unlimited().parse_spec(json)?;
+        let caps = spec
+            .process()
+            .as_ref()
+            .unwrap()
+            .capabilities()
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            caps.bounding(),
+            &Some(Capabilities::from([
+                Capability::Chown,
+                Capability::NetAdmin
+            ]))
+        );
+        assert_eq!(
+            caps.effective(),
+            &Some(Capabilities::from([Capability::Kill]))
+        );
+
+        let json =
+            br#"{"ociVersion": "1.0.2", "process": {"cwd": "/", "user": {"uid": 0, "gid": 0},
+            "capabilities": {"bounding": ["CAP_MIND_CONTROL"]}}}"#;
+        assert!(matches!(
+            unlimited().parse_spec(json),
+            Err(SpecValidationError::Capability(CapabilityError::Unknown { name, .. }))
+                if name == "CAP_MIND_CONTROL"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_bytes() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("config.json");
+        Spec::default().save(&path)?;
+        let size = std::fs::metadata(&path)?.len();
+
+        let limits = Limits {
+            max_bytes: size,
+            ..unlimited()
+        };
+        limits.load_spec(&path)?;
+
+        let limits = Limits {
+            max_bytes: size - 1,
+            ..unlimited()
+        };
+        match limits.load_spec(&path) {
+            Err(SpecValidationError::TooLarge { size: got, limit }) => {
+                assert_eq!((got, limit), (size, size - 1))
+            }
+            other => panic!("expected too large, got {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_depth() {
+        // depth 3, with brackets in strings and escaped quotes not counting
+        let json = br#"{"a": [{"b": "[[{\"[["}]}"#;
+        assert!(check_depth(json, 3).is_ok());
+        assert!(matches!(
+            check_depth(json, 2),
+            Err(SpecValidationError::TooDeep { limit: 2 })
+        ));
+
+        let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(matches!(
+            Limits::default().parse_spec(nested.as_bytes()),
+            Err(SpecValidationError::TooDeep { .. })
+        ));
+    }
+
+    #[test]
+    fn test_max_mounts() -> Result<()> {
+        let mut spec = Spec::default();
+        let mount = MountBuilder::default()
+            .destination(PathBuf::from("/mnt"))
+            .build()?;
+        spec.set_mounts(Some(vec![mount; 3]));
+
+        let limits = Limits {
+            max_mounts: 3,
+            ..unlimited()
+        };
+        limits.check_counts(&spec)?;
+        let limits = Limits {
+            max_mounts: 2,
+            ..unlimited()
+        };
+        assert_too_many(limits.check_counts(&spec), 3, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_devices() -> Result<()> {
+        let device = LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/fuse"))
+            .major(10)
+            .minor(229)
+            .build()?;
+        let mut spec = Spec::default();
+        spec.set_linux(Some(
+            LinuxBuilder::default().devices(vec![device; 3]).build()?,
+        ));
+
+        let limits = Limits {
+            max_devices: 3,
+            ..unlimited()
+        };
+        limits.check_counts(&spec)?;
+        let limits = Limits {
+            max_devices: 2,
+            ..unlimited()
+        };
+        assert_too_many(limits.check_counts(&spec), 3, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_seccomp_rules() -> Result<()> {
+        let rule = LinuxSyscallBuilder::default()
+            .names(vec!["mount".to_owned()])
+            .action(LinuxSeccompAction::ScmpActErrno)
+            .build()?;
+        let mut spec = Spec::default();
+        spec.set_linux(Some(
+            LinuxBuilder::default()
+                .seccomp(
+                    LinuxSeccompBuilder::default()
+                        .default_action(LinuxSeccompAction::ScmpActAllow)
+                        .syscalls(vec![rule; 3])
+                        .build()?,
+                )
+                .build()?,
+        ));
+
+        let limits = Limits {
+            max_seccomp_rules: 3,
+            ..unlimited()
+        };
+        limits.check_counts(&spec)?;
+        let limits = Limits {
+            max_seccomp_rules: 2,
+            ..unlimited()
+        };
+        assert_too_many(limits.check_counts(&spec), 3, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_env() -> Result<()> {
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default()
+                .env(vec!["A=1".to_owned(), "B=2".to_owned(), "C=3".to_owned()])
+                .build()?,
+        ));
+
+        let limits = Limits {
+            max_env: 3,
+            ..unlimited()
+        };
+        limits.check_counts(&spec)?;
+        let limits = Limits {
+            max_env: 2,
+            ..unlimited()
+        };
+        assert_too_many(limits.check_counts(&spec), 3, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! Stdout and stderr of a container written to files rotated by the runtime
+//!
+//! Embedders without a log collector can have the output of the container
+//! written to plain files. The container gets the file itself, opened with
+//! O_APPEND, so its writes never depend on the runtime. A watcher thread in
+//! the runtime rotates a file once it grew beyond its size cap by copying it
+//! to the first of the old generations and truncating it in place. As the
+//! container appends, its next write lands at the start of the truncated
+//! file, the container never has to reopen it. Output written between the
+//! copy and the truncate is lost, like with the copytruncate of logrotate.
+//!
+//! The watcher stops once the container process is gone. If it fails, only
+//! the rotation stops, the container keeps writing to the file.
+use std::fs::{self, File, OpenOptions};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{io, thread};
+
+use nix::errno::Errno;
+use nix::unistd::Pid;
+
+use crate::process::fork::pidfd_open;
+
+/// When and how a file is rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Size in bytes beyond which the file is rotated
+    pub max_bytes: u64,
+    /// Number of old generations kept as `<path>.1` (newest) to `<path>.N`.
+    /// With none, the output beyond the size cap is discarded.
+    pub keep: usize,
+    /// How often the size of the file is checked
+    pub interval: Duration,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: 10 * 1024 * 1024,
+            keep: 3,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A file the stdout or stderr of a container is written to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StdioFile {
+    pub path: PathBuf,
+    pub rotation: RotationPolicy,
+}
+
+impl StdioFile {
+    pub fn new<P: Into<PathBuf>>(path: P, rotation: RotationPolicy) -> Self {
+        Self {
+            path: path.into(),
+            rotation,
+        }
+    }
+
+    /// Opens the file for the container to append to, creating it if needed
+    pub fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o640)
+            .custom_flags(libc::O_CLOEXEC)
+            .open(&self.path)
+    }
+}
+
+/// Rotates a file according to its policy, driven by the time passed to
+/// [`Rotator::tick`]
+#[derive(Debug)]
+pub struct Rotator {
+    file: StdioFile,
+    next_check: Option<Instant>,
+}
+
+impl Rotator {
+    pub fn new(file: StdioFile) -> Self {
+        Self {
+            file,
+            next_check: None,
+        }
+    }
+
+    /// Rotates the file if a check is due at `now` and the file is beyond its
+    /// size cap. Returns if the file was rotated.
+    pub fn tick(&mut self, now: Instant) -> io::Result<bool> {
+        if self.next_check.map_or(false, |next_check| now < next_check) {
+            return Ok(false);
+        }
+        self.next_check = Some(now + self.file.rotation.interval);
+
+        let size = match fs::metadata(&self.file.path) {
+            Ok(metadata) => metadata.len(),
+            // Nothing to rotate until the file is back.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        if size <= self.file.rotation.max_bytes {
+            return Ok(false);
+        }
+
+        self.rotate()?;
+        Ok(true)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        let path = &self.file.path;
+        let keep = self.file.rotation.keep;
+        tracing::debug!(?path, keep, "rotate stdio file");
+        if keep > 0 {
+            remove_if_exists(&generation(path, keep))?;
+            for n in (1..keep).rev() {
+                let from = generation(path, n);
+                if from.exists() {
+                    fs::rename(&from, generation(path, n + 1))?;
+                }
+            }
+            fs::copy(path, generation(path, 1))?;
+        }
+
+        // The container holds the file, so it is truncated rather than
+        // replaced.
+        OpenOptions::new().write(true).open(path)?.set_len(0)
+    }
+}
+
+/// Returns the path of the `n`th old generation of the file
+pub fn generation(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Rotates the files in a thread for as long as the process `pid` exists.
+/// The process is watched through a pidfd, so a reuse of its pid doesn't
+/// keep the rotation going.
+pub fn watch(files: Vec<StdioFile>, pid: Pid) {
+    let interval = match files.iter().map(|file| file.rotation.interval).min() {
+        Some(interval) => interval,
+        None => return,
+    };
+    let mut rotators: Vec<Rotator> = files.into_iter().map(Rotator::new).collect();
+    let pidfd = match pidfd_open(pid) {
+        Ok(pidfd) => pidfd,
+        Err(err) => {
+            tracing::warn!(?pid, %err, "failed to watch the process, stdio files aren't rotated");
+            return;
+        }
+    };
+
+    let spawned = thread::Builder::new()
+        .name("stdio-rotate".to_owned())
+        .spawn(move || {
+            while !rotators.is_empty() && !wait_exit(&pidfd, interval) {
+                let now = Instant::now();
+                rotators.retain_mut(|rotator| match rotator.tick(now) {
+                    Ok(_) => true,
+                    Err(err) => {
+                        tracing::warn!(
+                            path = ?rotator.file.path,
+                            %err,
+                            "failed to rotate stdio file, rotation stops"
+                        );
+                        false
+                    }
+                });
+            }
+        });
+    if let Err(err) = spawned {
+        tracing::warn!(%err, "failed to start the stdio file rotation");
+    }
+}
+
+/// Waits up to `timeout` for the exit of the process of `pidfd`, returns
+/// whether it exited. A failed wait counts as exited, so the watcher stops.
+fn wait_exit(pidfd: &OwnedFd, timeout: Duration) -> bool {
+    let mut fds = [libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    // SAFETY: fds is valid for the duration of the call.
+    match Errno::result(unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) }) {
+        Ok(ready) => ready > 0,
+        // An early rotation check is harmless, the wait isn't resumed.
+        Err(Errno::EINTR) => false,
+        Err(err) => {
+            tracing::warn!(%err, "failed to wait for the process");
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use anyhow::Result;
+
+    use super::*;
+
+    fn policy(max_bytes: u64, keep: usize) -> RotationPolicy {
+        RotationPolicy {
+            max_bytes,
+            keep,
+            interval: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_generation() {
+        assert_eq!(
+            generation(Path::new("/var/log/web.out"), 2),
+            PathBuf::from("/var/log/web.out.2")
+        );
+    }
+
+    #[test]
+    fn test_rotate_over_fake_clock() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = StdioFile::new(tmp.path().join("out"), policy(4, 2));
+        // the container side, appending
+        let mut out = file.open()?;
+        let mut rotator = Rotator::new(file.clone());
+        let start = Instant::now();
+
+        out.write_all(b"abc")?;
+        assert!(!rotator.tick(start)?);
+        out.write_all(b"de")?;
+        // beyond the cap, but the next check isn't due yet
+        assert!(!rotator.tick(start + Duration::from_secs(9))?);
+        assert!(rotator.tick(start + Duration::from_secs(10))?);
+        assert_eq!(fs::read_to_string(&file.path)?, "");
+        assert_eq!(fs::read_to_string(generation(&file.path, 1))?, "abcde");
+
+        // the appends of the container continue at the start
+        out.write_all(b"fghij")?;
+        assert_eq!(fs::read_to_string(&file.path)?, "fghij");
+        assert!(rotator.tick(start + Duration::from_secs(20))?);
+        out.write_all(b"klmno")?;
+        assert!(rotator.tick(start + Duration::from_secs(30))?);
+        assert_eq!(fs::read_to_string(generation(&file.path, 1))?, "klmno");
+        assert_eq!(fs::read_to_string(generation(&file.path, 2))?, "fghij");
+        // only two generations are kept
+        assert!(!generation(&file.path, 3).exists());
+
+        // within the cap
+        out.write_all(b"pq")?;
+        assert!(!rotator.tick(start + Duration::from_secs(40))?);
+        assert_eq!(fs::read_to_string(&file.path)?, "pq");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_without_generations() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = StdioFile::new(tmp.path().join("out"), policy(2, 0));
+        let mut out = file.open()?;
+        let mut rotator = Rotator::new(file.clone());
+
+        out.write_all(b"abc")?;
+        assert!(rotator.tick(Instant::now())?);
+        assert_eq!(fs::metadata(&file.path)?.len(), 0);
+        assert!(!generation(&file.path, 1).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_missing_file() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = StdioFile::new(tmp.path().join("out"), policy(0, 1));
+        let mut rotator = Rotator::new(file);
+        assert!(!rotator.tick(Instant::now())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_exit() -> Result<()> {
+        let mut child = std::process::Command::new("sleep").arg("10").spawn()?;
+        let pidfd = pidfd_open(Pid::from_raw(child.id() as i32))?;
+        assert!(!wait_exit(&pidfd, Duration::from_millis(10)));
+
+        child.kill()?;
+        child.wait()?;
+        // the pidfd keeps referring to the reaped process, not to a process
+        // that reuses the pid
+        assert!(wait_exit(&pidfd, Duration::from_millis(10)));
+        Ok(())
+    }
+}
@@ -38,6 +38,14 @@ const MOUNT_ATTR_STRICTATIME: u64 = 0x00000020;
 const MOUNT_ATTR_NODIRATIME: u64 = 0x00000080;
 const MOUNT_ATTR_NOSYMFOLLOW: u64 = 0x00200000;
 
+// Commands and pid types of prctl(PR_SCHED_CORE).
+// see https://docs.kernel.org/admin-guide/hw-vuln/core-scheduling.html.
+const PR_SCHED_CORE: libc::c_int = 62;
+pub const PR_SCHED_CORE_CREATE: u64 = 1;
+pub const PR_SCHED_CORE_SHARE_FROM: u64 = 3;
+pub const PIDTYPE_PID: u64 = 0;
+pub const PIDTYPE_TGID: u64 = 1;
+
 /// Constants used by mount(2).
 pub enum MountOption {
     Defaults(bool, MsFlags),
@@ -723,6 +731,41 @@ impl Syscall for LinuxSyscall {
         Ok(())
     }
 
+    fn get_oom_score_adj(&self) -> Result<i32> {
+        let score = fs::read_to_string("/proc/self/oom_score_adj")?;
+        score.trim().parse().map_err(|err| {
+            SyscallError::IO(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid oom_score_adj {score:?}: {err}"),
+            ))
+        })
+    }
+
+    fn set_oom_score_adj(&self, score: i32) -> Result<()> {
+        fs::write("/proc/self/oom_score_adj", score.to_string())?;
+        Ok(())
+    }
+
+    fn set_dumpable(&self, dumpable: bool) -> Result<()> {
+        prctl::set_dumpable(dumpable)
+            .map_err(|errno| SyscallError::Nix(nix::errno::Errno::from_raw(errno)))
+    }
+
+    fn sched_core(&self, cmd: u64, pid: i32, pid_type: u64) -> Result<()> {
+        match unsafe {
+            libc::prctl(
+                PR_SCHED_CORE,
+                cmd as libc::c_ulong,
+                pid as libc::c_ulong,
+                pid_type as libc::c_ulong,
+                0 as libc::c_ulong,
+            )
+        } {
+            0 => Ok(()),
+            _ => Err(nix::Error::last().into()),
+        }
+    }
+
     fn umount2(&self, target: &Path, flags: MntFlags) -> Result<()> {
         umount2(target, flags)?;
         Ok(())
@@ -6,6 +6,8 @@ pub mod linux;
 #[allow(clippy::module_inception)]
 pub mod syscall;
 pub mod test;
+#[cfg(feature = "syscall_trace")]
+pub mod trace;
 
 pub use syscall::Syscall;
 #[derive(Debug, thiserror::Error)]
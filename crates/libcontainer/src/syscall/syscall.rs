@@ -3,6 +3,8 @@
 //! implementation details
 use std::any::Any;
 use std::ffi::OsStr;
+#[cfg(feature = "syscall_trace")]
+use std::os::unix::io::RawFd;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -16,6 +18,8 @@ use oci_spec::runtime::PosixRlimit;
 
 use crate::syscall::linux::{LinuxSyscall, MountAttr};
 use crate::syscall::test::TestHelperSyscall;
+#[cfg(feature = "syscall_trace")]
+use crate::syscall::trace::TracingSyscall;
 use crate::syscall::Result;
 
 /// This specifies various kernel/other functionalities required for
@@ -54,6 +58,11 @@ pub trait Syscall {
         size: libc::size_t,
     ) -> Result<()>;
     fn set_io_priority(&self, class: i64, priority: i64) -> Result<()>;
+    fn get_oom_score_adj(&self) -> Result<i32>;
+    fn set_oom_score_adj(&self, score: i32) -> Result<()>;
+    fn set_dumpable(&self, dumpable: bool) -> Result<()>;
+    /// Runs `prctl(PR_SCHED_CORE, cmd, pid, pid_type, 0)`
+    fn sched_core(&self, cmd: u64, pid: i32, pid_type: u64) -> Result<()>;
     fn umount2(&self, target: &Path, flags: MntFlags) -> Result<()>;
     fn get_uid(&self) -> Uid;
     fn get_gid(&self) -> Gid;
@@ -65,6 +74,10 @@ pub trait Syscall {
 pub enum SyscallType {
     Linux,
     Test,
+    /// Linux syscalls, each traced to the given fd with its arguments and
+    /// result. The fd is not closed and must stay open during the create.
+    #[cfg(feature = "syscall_trace")]
+    Trace(RawFd),
 }
 
 impl Default for SyscallType {
@@ -82,6 +95,8 @@ impl SyscallType {
         match self {
             SyscallType::Linux => Box::new(LinuxSyscall),
             SyscallType::Test => Box::<TestHelperSyscall>::default(),
+            #[cfg(feature = "syscall_trace")]
+            SyscallType::Trace(fd) => Box::new(TracingSyscall::new(*fd)),
         }
     }
 }
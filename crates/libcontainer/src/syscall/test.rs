@@ -44,6 +44,13 @@ pub struct IoPriorityArgs {
     pub priority: i64,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SchedCoreArgs {
+    pub cmd: u64,
+    pub pid: i32,
+    pub pid_type: u64,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct UMount2Args {
     pub target: PathBuf,
@@ -57,7 +64,7 @@ struct Mock {
     ret_err_times: usize,
 }
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub enum ArgName {
     Namespace,
     Unshare,
@@ -71,6 +78,10 @@ pub enum ArgName {
     Capability,
     IoPriority,
     UMount2,
+    PivotRoot,
+    OomScoreAdj,
+    Dumpable,
+    SchedCore,
 }
 
 impl ArgName {
@@ -87,12 +98,28 @@ impl ArgName {
             ArgName::Groups,
             ArgName::Capability,
             ArgName::IoPriority,
+            ArgName::PivotRoot,
+            ArgName::OomScoreAdj,
+            ArgName::Dumpable,
+            ArgName::SchedCore,
         ]
         .iter()
         .copied()
     }
 }
 
+thread_local! {
+    // The order of the calls across all test syscall instances of the thread,
+    // as helpers like the rootfs and mount setup create their own instance.
+    static CALL_ORDER: RefCell<Vec<ArgName>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the calls recorded by all test syscall instances of the current
+/// thread in the order they were made, and clears the record.
+pub fn take_call_order() -> Vec<ArgName> {
+    CALL_ORDER.with(|calls| calls.take())
+}
+
 struct MockCalls {
     args: HashMap<ArgName, RefCell<Mock>>,
 }
@@ -126,6 +153,7 @@ impl MockCalls {
             .borrow_mut()
             .values
             .push(value);
+        CALL_ORDER.with(|calls| calls.borrow_mut().push(name));
         Ok(())
     }
 
@@ -141,6 +169,7 @@ impl MockCalls {
 #[derive(Default)]
 pub struct TestHelperSyscall {
     mock_id: RefCell<MockId>,
+    oom_score_adj: RefCell<i32>,
     mocks: MockCalls,
 }
 
@@ -167,8 +196,9 @@ impl Syscall for TestHelperSyscall {
         self
     }
 
-    fn pivot_rootfs(&self, _path: &Path) -> Result<()> {
-        unimplemented!()
+    fn pivot_rootfs(&self, path: &Path) -> Result<()> {
+        self.mocks
+            .act(ArgName::PivotRoot, Box::new(path.to_owned()))
     }
 
     fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> Result<()> {
@@ -290,6 +320,27 @@ impl Syscall for TestHelperSyscall {
         )
     }
 
+    fn get_oom_score_adj(&self) -> Result<i32> {
+        Ok(*self.oom_score_adj.borrow())
+    }
+
+    fn set_oom_score_adj(&self, score: i32) -> Result<()> {
+        self.mocks.act(ArgName::OomScoreAdj, Box::new(score))?;
+        *self.oom_score_adj.borrow_mut() = score;
+        Ok(())
+    }
+
+    fn set_dumpable(&self, dumpable: bool) -> Result<()> {
+        self.mocks.act(ArgName::Dumpable, Box::new(dumpable))
+    }
+
+    fn sched_core(&self, cmd: u64, pid: i32, pid_type: u64) -> Result<()> {
+        self.mocks.act(
+            ArgName::SchedCore,
+            Box::new(SchedCoreArgs { cmd, pid, pid_type }),
+        )
+    }
+
     fn umount2(&self, target: &Path, flags: MntFlags) -> Result<()> {
         self.mocks.act(
             ArgName::UMount2,
@@ -426,6 +477,38 @@ impl TestHelperSyscall {
             .collect::<Vec<IoPriorityArgs>>()
     }
 
+    /// Sets the oom_score_adj the process has before any is set
+    pub fn set_initial_oom_score_adj(&self, score: i32) {
+        *self.oom_score_adj.borrow_mut() = score;
+    }
+
+    pub fn get_oom_score_adj_args(&self) -> Vec<i32> {
+        self.mocks
+            .fetch(ArgName::OomScoreAdj)
+            .values
+            .iter()
+            .map(|x| *x.downcast_ref::<i32>().unwrap())
+            .collect::<Vec<i32>>()
+    }
+
+    pub fn get_dumpable_args(&self) -> Vec<bool> {
+        self.mocks
+            .fetch(ArgName::Dumpable)
+            .values
+            .iter()
+            .map(|x| *x.downcast_ref::<bool>().unwrap())
+            .collect::<Vec<bool>>()
+    }
+
+    pub fn get_sched_core_args(&self) -> Vec<SchedCoreArgs> {
+        self.mocks
+            .fetch(ArgName::SchedCore)
+            .values
+            .iter()
+            .map(|x| x.downcast_ref::<SchedCoreArgs>().unwrap().clone())
+            .collect::<Vec<SchedCoreArgs>>()
+    }
+
     pub fn get_umount_args(&self) -> Vec<UMount2Args> {
         self.mocks
             .fetch(ArgName::UMount2)
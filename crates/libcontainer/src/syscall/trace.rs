@@ -0,0 +1,316 @@
+//! Syscall tracing for debugging the container setup
+//!
+//! [`TracingSyscall`] wraps the Linux implementation of [`Syscall`] and writes
+//! a line per call with its arguments and result to a file descriptor, like a
+//! very limited strace. It's selected with [`SyscallType::Trace`], so only the
+//! calls the intermediate and init process make through the syscall of the
+//! container are traced.
+//!
+//! [`SyscallType::Trace`]: super::syscall::SyscallType::Trace
+use std::any::Any;
+use std::ffi::OsStr;
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::Write;
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::Path;
+use std::sync::Arc;
+
+use caps::{CapSet, CapsHashSet};
+use nix::mount::{MntFlags, MsFlags};
+use nix::sched::CloneFlags;
+use nix::sys::stat::{Mode, SFlag};
+use nix::unistd::{Gid, Uid};
+use oci_spec::runtime::PosixRlimit;
+
+use super::linux::{LinuxSyscall, MountAttr};
+use super::{Result, Syscall};
+
+pub struct TracingSyscall {
+    inner: LinuxSyscall,
+    fd: RawFd,
+}
+
+impl TracingSyscall {
+    /// Creates a tracing syscall writing to `fd`. The fd is borrowed, it must
+    /// stay open as long as the syscall is used and is not closed by it.
+    pub fn new(fd: RawFd) -> Self {
+        Self {
+            inner: LinuxSyscall,
+            fd,
+        }
+    }
+
+    fn trace<T: Debug>(&self, name: &str, args: fmt::Arguments, result: T) -> T {
+        self.write_line(format_args!("{name}({args}) = {result:?}"));
+        result
+    }
+
+    fn write_line(&self, line: fmt::Arguments) {
+        // Safety: the fd is only borrowed, ManuallyDrop keeps it open.
+        let mut out = ManuallyDrop::new(unsafe { File::from_raw_fd(self.fd) });
+        // A line is written at once, so the lines of the intermediate and init
+        // process don't interleave. Tracing is best effort, errors are ignored.
+        let line = format!("[{}] {line}\n", std::process::id());
+        let _ = out.write_all(line.as_bytes());
+    }
+}
+
+/// Records the exec of the container process if `syscall` traces. The result
+/// is not known, as the exec doesn't return if it succeeds.
+pub fn record_exec(syscall: &dyn Syscall, args: Option<&Vec<String>>) {
+    if let Some(tracing) = syscall.as_any().downcast_ref::<TracingSyscall>() {
+        tracing.write_line(format_args!("execve({args:?})"));
+    }
+}
+
+impl Syscall for TracingSyscall {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn pivot_rootfs(&self, path: &Path) -> Result<()> {
+        self.trace(
+            "pivot_root",
+            format_args!("{path:?}"),
+            self.inner.pivot_rootfs(path),
+        )
+    }
+
+    fn chroot(&self, path: &Path) -> Result<()> {
+        self.trace("chroot", format_args!("{path:?}"), self.inner.chroot(path))
+    }
+
+    fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> Result<()> {
+        self.trace(
+            "setns",
+            format_args!("{rawfd}, {nstype:?}"),
+            self.inner.set_ns(rawfd, nstype),
+        )
+    }
+
+    fn set_id(&self, uid: Uid, gid: Gid) -> Result<()> {
+        self.trace(
+            "setresuid/setresgid",
+            format_args!("{uid}, {gid}"),
+            self.inner.set_id(uid, gid),
+        )
+    }
+
+    fn unshare(&self, flags: CloneFlags) -> Result<()> {
+        self.trace(
+            "unshare",
+            format_args!("{flags:?}"),
+            self.inner.unshare(flags),
+        )
+    }
+
+    fn set_capability(&self, cset: CapSet, value: &CapsHashSet) -> Result<()> {
+        self.trace(
+            "capset",
+            format_args!("{cset:?}, {value:?}"),
+            self.inner.set_capability(cset, value),
+        )
+    }
+
+    fn set_hostname(&self, hostname: &str) -> Result<()> {
+        self.trace(
+            "sethostname",
+            format_args!("{hostname:?}"),
+            self.inner.set_hostname(hostname),
+        )
+    }
+
+    fn set_domainname(&self, domainname: &str) -> Result<()> {
+        self.trace(
+            "setdomainname",
+            format_args!("{domainname:?}"),
+            self.inner.set_domainname(domainname),
+        )
+    }
+
+    fn set_rlimit(&self, rlimit: &PosixRlimit) -> Result<()> {
+        self.trace(
+            "setrlimit",
+            format_args!("{rlimit:?}"),
+            self.inner.set_rlimit(rlimit),
+        )
+    }
+
+    fn get_pwuid(&self, uid: u32) -> Option<Arc<OsStr>> {
+        self.trace("getpwuid", format_args!("{uid}"), self.inner.get_pwuid(uid))
+    }
+
+    fn mount(
+        &self,
+        source: Option<&Path>,
+        target: &Path,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+    ) -> Result<()> {
+        self.trace(
+            "mount",
+            format_args!("{source:?}, {target:?}, {fstype:?}, {flags:?}, {data:?}"),
+            self.inner.mount(source, target, fstype, flags, data),
+        )
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> Result<()> {
+        self.trace(
+            "symlink",
+            format_args!("{original:?}, {link:?}"),
+            self.inner.symlink(original, link),
+        )
+    }
+
+    fn mknod(&self, path: &Path, kind: SFlag, perm: Mode, dev: u64) -> Result<()> {
+        self.trace(
+            "mknod",
+            format_args!("{path:?}, {kind:?}, {perm:?}, {dev}"),
+            self.inner.mknod(path, kind, perm, dev),
+        )
+    }
+
+    fn chown(&self, path: &Path, owner: Option<Uid>, group: Option<Gid>) -> Result<()> {
+        self.trace(
+            "chown",
+            format_args!("{path:?}, {owner:?}, {group:?}"),
+            self.inner.chown(path, owner, group),
+        )
+    }
+
+    fn set_groups(&self, groups: &[Gid]) -> Result<()> {
+        self.trace(
+            "setgroups",
+            format_args!("{groups:?}"),
+            self.inner.set_groups(groups),
+        )
+    }
+
+    fn close_range(&self, preserve_fds: i32) -> Result<()> {
+        self.trace(
+            "close_range",
+            format_args!("{preserve_fds}"),
+            self.inner.close_range(preserve_fds),
+        )
+    }
+
+    fn mount_setattr(
+        &self,
+        dirfd: i32,
+        pathname: &Path,
+        flags: u32,
+        mount_attr: &MountAttr,
+        size: libc::size_t,
+    ) -> Result<()> {
+        self.trace(
+            "mount_setattr",
+            format_args!("{dirfd}, {pathname:?}, {flags:#x}, {mount_attr:?}, {size}"),
+            self.inner
+                .mount_setattr(dirfd, pathname, flags, mount_attr, size),
+        )
+    }
+
+    fn set_io_priority(&self, class: i64, priority: i64) -> Result<()> {
+        self.trace(
+            "ioprio_set",
+            format_args!("{class}, {priority}"),
+            self.inner.set_io_priority(class, priority),
+        )
+    }
+
+    fn get_oom_score_adj(&self) -> Result<i32> {
+        self.trace(
+            "get_oom_score_adj",
+            format_args!(""),
+            self.inner.get_oom_score_adj(),
+        )
+    }
+
+    fn set_oom_score_adj(&self, score: i32) -> Result<()> {
+        self.trace(
+            "set_oom_score_adj",
+            format_args!("{score}"),
+            self.inner.set_oom_score_adj(score),
+        )
+    }
+
+    fn set_dumpable(&self, dumpable: bool) -> Result<()> {
+        self.trace(
+            "prctl",
+            format_args!("PR_SET_DUMPABLE, {}", u8::from(dumpable)),
+            self.inner.set_dumpable(dumpable),
+        )
+    }
+
+    fn sched_core(&self, cmd: u64, pid: i32, pid_type: u64) -> Result<()> {
+        self.trace(
+            "prctl",
+            format_args!("PR_SCHED_CORE, {cmd}, {pid}, {pid_type}, 0"),
+            self.inner.sched_core(cmd, pid, pid_type),
+        )
+    }
+
+    fn umount2(&self, target: &Path, flags: MntFlags) -> Result<()> {
+        self.trace(
+            "umount2",
+            format_args!("{target:?}, {flags:?}"),
+            self.inner.umount2(target, flags),
+        )
+    }
+
+    fn get_uid(&self) -> Uid {
+        self.trace("getuid", format_args!(""), self.inner.get_uid())
+    }
+
+    fn get_gid(&self) -> Gid {
+        self.trace("getgid", format_args!(""), self.inner.get_gid())
+    }
+
+    fn get_euid(&self) -> Uid {
+        self.trace("geteuid", format_args!(""), self.inner.get_euid())
+    }
+
+    fn get_egid(&self) -> Gid {
+        self.trace("getegid", format_args!(""), self.inner.get_egid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+    use std::os::unix::io::AsRawFd;
+
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_trace_lines() -> Result<()> {
+        let mut out = tempfile::tempfile()?;
+        let syscall = TracingSyscall::new(out.as_raw_fd());
+
+        let uid = syscall.get_uid();
+        assert!(syscall.chroot(Path::new("/nonexistent")).is_err());
+        record_exec(&syscall, Some(&vec!["sh".to_owned()]));
+
+        let mut trace = String::new();
+        out.seek(SeekFrom::Start(0))?;
+        out.read_to_string(&mut trace)?;
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 3, "{trace}");
+        assert!(
+            lines[0].ends_with(&format!("getuid() = {uid:?}")),
+            "{trace}"
+        );
+        assert!(
+            lines[1].contains("chroot(\"/nonexistent\") = Err("),
+            "{trace}"
+        );
+        assert!(lines[2].ends_with("execve(Some([\"sh\"]))"), "{trace}");
+
+        Ok(())
+    }
+}
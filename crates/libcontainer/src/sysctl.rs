@@ -0,0 +1,240 @@
+//! Scoping of the sysctls of the spec
+//!
+//! Most sysctls are global, a few are scoped to a namespace of the process
+//! writing them. A container can only set the scoped ones without touching
+//! the host if it has its own namespace of that type. The SysV IPC and POSIX
+//! message queue limits, e.g. `kernel.shmmax`, are scoped to the IPC
+//! namespace. A spec setting them without an IPC namespace would change the
+//! limits of the host, so it is refused.
+//!
+//! The IPC sysctls are applied as soon as the init process is in the IPC
+//! namespace, before the rootfs is set up, so the mounts of the rootfs, e.g.
+//! an mqueue mount, already see the limits of the container. The other
+//! sysctls are applied once the rootfs is set up.
+use std::collections::{BTreeMap, HashMap};
+
+use oci_spec::runtime::{LinuxNamespaceType, Spec};
+
+/// The SysV IPC sysctls, all scoped to the IPC namespace
+const IPC_SYSCTLS: &[&str] = &[
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.msg_next_id",
+    "kernel.sem",
+    "kernel.sem_next_id",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_next_id",
+    "kernel.shm_rmid_forced",
+];
+/// Prefix of the POSIX message queue sysctls, scoped to the IPC namespace
+const MQUEUE_PREFIX: &str = "fs.mqueue.";
+/// The sysctls scoped to the UTS namespace
+const UTS_SYSCTLS: &[&str] = &["kernel.hostname", "kernel.domainname"];
+/// Prefix of the sysctls scoped to the network namespace
+const NET_PREFIX: &str = "net.";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SysctlError {
+    #[error("sysctl {key} is scoped to the IPC namespace, but the container has no IPC namespace of its own")]
+    RequiresIpcNamespace { key: String },
+}
+
+type Result<T> = std::result::Result<T, SysctlError>;
+
+/// What a sysctl is scoped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysctlScope {
+    Ipc,
+    Net,
+    Uts,
+    /// Not scoped to a namespace, writing it changes the host
+    Host,
+}
+
+/// Returns what the sysctl `key` is scoped to. The key may be separated with
+/// dots or slashes, e.g. `kernel.shmmax` or `kernel/shmmax`.
+pub fn scope(key: &str) -> SysctlScope {
+    let key = key.replace('/', ".");
+    if IPC_SYSCTLS.contains(&key.as_str()) || key.starts_with(MQUEUE_PREFIX) {
+        SysctlScope::Ipc
+    } else if key.starts_with(NET_PREFIX) {
+        SysctlScope::Net
+    } else if UTS_SYSCTLS.contains(&key.as_str()) {
+        SysctlScope::Uts
+    } else {
+        SysctlScope::Host
+    }
+}
+
+/// Refuses a spec setting IPC sysctls without an IPC namespace. A namespace
+/// joined by path counts as the container's own.
+pub fn validate(spec: &Spec) -> Result<()> {
+    let linux = match spec.linux() {
+        Some(linux) => linux,
+        None => return Ok(()),
+    };
+    let has_ipc_namespace = linux.namespaces().as_ref().map_or(false, |namespaces| {
+        namespaces
+            .iter()
+            .any(|namespace| namespace.typ() == LinuxNamespaceType::Ipc)
+    });
+    if has_ipc_namespace {
+        return Ok(());
+    }
+
+    // Sorted, so the same spec always fails on the same key.
+    let mut keys: Vec<&String> = linux
+        .sysctl()
+        .iter()
+        .flatten()
+        .map(|(key, _)| key)
+        .collect();
+    keys.sort_unstable();
+    match keys.into_iter().find(|key| scope(key) == SysctlScope::Ipc) {
+        Some(key) => {
+            tracing::error!(%key, "IPC sysctl without an IPC namespace");
+            Err(SysctlError::RequiresIpcNamespace {
+                key: key.to_string(),
+            })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Splits the sysctls into the IPC ones and the rest
+pub fn partition(
+    sysctls: &HashMap<String, String>,
+) -> (HashMap<String, String>, HashMap<String, String>) {
+    sysctls
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .partition(|(key, _)| scope(key) == SysctlScope::Ipc)
+}
+
+/// Returns the IPC sysctls of the spec, recorded in the state of the
+/// container
+pub fn ipc_sysctls(spec: &Spec) -> BTreeMap<String, String> {
+    spec.linux()
+        .as_ref()
+        .and_then(|linux| linux.sysctl().as_ref())
+        .map(|sysctls| {
+            sysctls
+                .iter()
+                .filter(|(key, _)| scope(key) == SysctlScope::Ipc)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, LinuxNamespaceBuilder};
+
+    use super::*;
+
+    fn spec(namespaces: &[LinuxNamespaceType], sysctls: &[(&str, &str)]) -> Result<Spec> {
+        let namespaces = namespaces
+            .iter()
+            .map(|typ| LinuxNamespaceBuilder::default().typ(*typ).build())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let sysctls = sysctls
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut spec = Spec::default();
+        spec.set_linux(Some(
+            LinuxBuilder::default()
+                .namespaces(namespaces)
+                .sysctl(sysctls)
+                .build()?,
+        ));
+        Ok(spec)
+    }
+
+    #[test]
+    fn test_scope() {
+        let tests = [
+            ("kernel.msgmax", SysctlScope::Ipc),
+            ("kernel.msgmnb", SysctlScope::Ipc),
+            ("kernel.msgmni", SysctlScope::Ipc),
+            ("kernel.msg_next_id", SysctlScope::Ipc),
+            ("kernel.sem", SysctlScope::Ipc),
+            ("kernel.sem_next_id", SysctlScope::Ipc),
+            ("kernel.shmall", SysctlScope::Ipc),
+            ("kernel.shmmax", SysctlScope::Ipc),
+            ("kernel.shmmni", SysctlScope::Ipc),
+            ("kernel.shm_next_id", SysctlScope::Ipc),
+            ("kernel.shm_rmid_forced", SysctlScope::Ipc),
+            ("kernel/shmmax", SysctlScope::Ipc),
+            ("fs.mqueue.msg_max", SysctlScope::Ipc),
+            ("fs.mqueue.queues_max", SysctlScope::Ipc),
+            ("net.ipv4.ip_forward", SysctlScope::Net),
+            ("net/core/somaxconn", SysctlScope::Net),
+            ("kernel.hostname", SysctlScope::Uts),
+            ("kernel.domainname", SysctlScope::Uts),
+            // look alike, but global
+            ("kernel.shmmax_extra", SysctlScope::Host),
+            ("kernel.semaphores", SysctlScope::Host),
+            ("kernel.pid_max", SysctlScope::Host),
+            ("fs.file-max", SysctlScope::Host),
+            ("vm.max_map_count", SysctlScope::Host),
+        ];
+        for (key, expected) in tests {
+            assert_eq!(scope(key), expected, "{key}");
+        }
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let ipc = [("kernel.shmmax", "68719476736")];
+        validate(&spec(&[LinuxNamespaceType::Ipc], &ipc)?)?;
+        // no IPC sysctl, no IPC namespace needed
+        validate(&spec(
+            &[LinuxNamespaceType::Network],
+            &[("net.ipv4.ip_forward", "1")],
+        )?)?;
+
+        let err = validate(&spec(
+            &[LinuxNamespaceType::Mount],
+            &[("kernel.shmall", "1"), ("kernel.sem", "250 32000 32 128")],
+        )?)
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            SysctlError::RequiresIpcNamespace { key } if key == "kernel.sem"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition() {
+        let sysctls = HashMap::from([
+            ("kernel.shmmax".to_owned(), "1".to_owned()),
+            ("fs.mqueue.msg_max".to_owned(), "2".to_owned()),
+            ("net.ipv4.ip_forward".to_owned(), "1".to_owned()),
+        ]);
+        let (ipc, rest) = partition(&sysctls);
+        assert_eq!(ipc.len(), 2);
+        assert!(ipc.contains_key("kernel.shmmax") && ipc.contains_key("fs.mqueue.msg_max"));
+        assert_eq!(rest.len(), 1);
+        assert!(rest.contains_key("net.ipv4.ip_forward"));
+    }
+
+    #[test]
+    fn test_ipc_sysctls() -> Result<()> {
+        let spec = spec(
+            &[LinuxNamespaceType::Ipc],
+            &[("kernel.msgmax", "8192"), ("vm.swappiness", "10")],
+        )?;
+        assert_eq!(
+            ipc_sysctls(&spec),
+            BTreeMap::from([("kernel.msgmax".to_owned(), "8192".to_owned())])
+        );
+        Ok(())
+    }
+}
@@ -1,16 +1,25 @@
 //! tty (teletype) for user-system interaction
 
-use std::env;
-use std::io::IoSlice;
-use std::os::fd::OwnedFd;
+use std::io::{IoSlice, IoSliceMut};
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
 use std::os::unix::fs::symlink;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::RawFd;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{env, thread};
 
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
 use nix::sys::socket::{self, UnixAddr};
 use nix::unistd::{close, dup2};
 
+/// First delay between the attempts to connect to the console socket or to
+/// send the pty master, doubled after each attempt up to
+/// [`MAX_RETRY_DELAY`].
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_millis(200);
+
 #[derive(Debug)]
 pub enum StdIO {
     Stdin = 0,
@@ -47,6 +56,13 @@ pub enum TTYError {
         source: nix::Error,
         socket_name: String,
     },
+    #[error("console socket {console_socket_path:?} doesn't exist")]
+    ConsoleSocketNotFound { console_socket_path: PathBuf },
+    #[error("nothing listened on console socket {console_socket_path:?} within {timeout:?}")]
+    ConsoleSocketNotListening {
+        console_socket_path: PathBuf,
+        timeout: Duration,
+    },
     #[error("failed to symlink console socket into container_dir")]
     Symlink {
         source: std::io::Error,
@@ -64,17 +80,50 @@ pub enum TTYError {
     CreatePseudoTerminal { source: nix::Error },
     #[error("failed to send pty master")]
     SendPtyMaster { source: nix::Error },
+    #[error("console socket peer closed the connection before the pty master was sent")]
+    ConsoleSocketPeerClosed { source: nix::Error },
     #[error("could not close console socket")]
     CloseConsoleSocket { source: nix::Error },
+    #[error("failed to create pty master socket pair")]
+    CreatePtyMasterSocket { source: nix::Error },
+    #[error("failed to receive pty master")]
+    ReceivePtyMaster { source: nix::Error },
+    #[error("no pty master was received")]
+    MissingPtyMaster,
 }
 
 type Result<T> = std::result::Result<T, TTYError>;
 
-// TODO: Handling when there isn't console-socket.
+/// Retries `f` while it fails with an error `retryable` accepts, with a
+/// backoff between the attempts, until `timeout` passed. Returns the last
+/// error once it did, a zero timeout makes a single attempt.
+fn retry_with_backoff<T, E: std::fmt::Debug>(
+    timeout: Duration,
+    retryable: impl Fn(&E) -> bool,
+    mut f: impl FnMut() -> std::result::Result<T, E>,
+) -> std::result::Result<T, E> {
+    let start = Instant::now();
+    let mut delay = INITIAL_RETRY_DELAY;
+    loop {
+        match f() {
+            Err(err) if retryable(&err) && start.elapsed() + delay <= timeout => {
+                tracing::debug!(?err, ?delay, "console socket is busy, retrying");
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Connects to the console socket the pty master of the container is sent
+/// to. A receiver that doesn't listen yet, or is too slow to accept, is
+/// retried with a backoff for up to `timeout`.
 pub fn setup_console_socket(
     container_dir: &Path,
     console_socket_path: &Path,
     socket_name: &str,
+    timeout: Duration,
 ) -> Result<OwnedFd> {
     struct CurrentDirGuard {
         path: PathBuf,
@@ -98,54 +147,174 @@ pub fn setup_console_socket(
         linked: linked.to_path_buf().into(),
         console_socket_path: console_socket_path.to_path_buf().into(),
     })?;
-    let csocketfd = socket::socket(
-        socket::AddressFamily::Unix,
-        socket::SockType::Stream,
-        socket::SockFlag::empty(),
-        None,
-    )
-    .map_err(|err| TTYError::CreateConsoleSocketFd { source: err })?;
-    socket::connect(
-        csocketfd.as_raw_fd(),
-        &socket::UnixAddr::new(linked.as_path()).map_err(|err| TTYError::InvalidSocketName {
+    let addr =
+        socket::UnixAddr::new(linked.as_path()).map_err(|err| TTYError::InvalidSocketName {
             source: err,
             socket_name: socket_name.to_string(),
-        })?,
+        })?;
+    // A socket whose connect failed is dropped, and so closed, before the
+    // next attempt with a fresh one.
+    let csocketfd = retry_with_backoff(
+        timeout,
+        |err| matches!(err, TTYError::ConsoleSocketNotListening { .. }),
+        || {
+            let csocketfd = socket::socket(
+                socket::AddressFamily::Unix,
+                socket::SockType::Stream,
+                socket::SockFlag::empty(),
+                None,
+            )
+            .map_err(|err| TTYError::CreateConsoleSocketFd { source: err })?;
+            socket::connect(csocketfd.as_raw_fd(), &addr).map_err(|err| match err {
+                Errno::ENOENT => TTYError::ConsoleSocketNotFound {
+                    console_socket_path: console_socket_path.to_path_buf(),
+                },
+                // Nothing listens, or the backlog of the listener is full.
+                Errno::ECONNREFUSED | Errno::EAGAIN => TTYError::ConsoleSocketNotListening {
+                    console_socket_path: console_socket_path.to_path_buf(),
+                    timeout,
+                },
+                _ => TTYError::CreateConsoleSocket {
+                    source: err,
+                    socket_name: socket_name.to_string(),
+                },
+            })?;
+            Ok(csocketfd)
+        },
     )
-    .map_err(|e| TTYError::CreateConsoleSocket {
-        source: e,
-        socket_name: socket_name.to_string(),
+    .map_err(|err| {
+        tracing::error!(?err, "failed to connect to console socket");
+        err
     })?;
 
     Ok(csocketfd)
 }
 
-pub fn setup_console(console_fd: RawFd) -> Result<()> {
+/// Creates a connected socket pair to deliver the pty master in-process
+/// instead of over an external console socket. The first socket takes the
+/// place of the console socket in the init process, the pty master is received
+/// from the second one with [`receive_pty_master`].
+pub fn create_pty_master_socket() -> Result<(OwnedFd, OwnedFd)> {
+    // The init process inherits both sockets, close on exec keeps them out of
+    // the container workload.
+    socket::socketpair(
+        socket::AddressFamily::Unix,
+        socket::SockType::Stream,
+        None,
+        socket::SockFlag::SOCK_CLOEXEC,
+    )
+    .map_err(|err| TTYError::CreatePtyMasterSocket { source: err })
+}
+
+/// Receives the pty master sent by [`setup_console`]. The caller owns the
+/// returned fd, the pty is hung up for the container once it is closed.
+pub fn receive_pty_master(socket: &OwnedFd) -> Result<OwnedFd> {
+    let mut buf = [0u8; 64];
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let mut cmsgspace = nix::cmsg_space!([RawFd; 1]);
+    let msg = socket::recvmsg::<UnixAddr>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsgspace),
+        socket::MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .map_err(|err| TTYError::ReceivePtyMaster { source: err })?;
+
+    let fd = msg
+        .cmsgs()
+        .map_err(|err| TTYError::ReceivePtyMaster { source: err })?
+        .find_map(|cmsg| match cmsg {
+            socket::ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+            _ => None,
+        })
+        .ok_or(TTYError::MissingPtyMaster)?;
+
+    // Safety: the fd was just received and is owned by nothing else.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Hangs up the terminal of the container when dropped, see
+/// [`setup_console`]. The init process holds it until the workload replaces
+/// it, so it is only dropped if the init process fails.
+#[must_use]
+#[derive(Debug)]
+pub struct ConsoleGuard {}
+
+impl Drop for ConsoleGuard {
+    fn drop(&mut self) {
+        // Only possible with CAP_SYS_ADMIN, without it the receiver still
+        // sees the hangup once the failed init process closed its stdio.
+        if unsafe { libc::ioctl(StdIO::Stdin.into(), libc::TIOCVHANGUP) } < 0 {
+            tracing::debug!(err = ?Errno::last(), "could not hang up the terminal");
+        }
+    }
+}
+
+/// Sends the pty master over the console socket `console_fd` and connects
+/// the stdio to the pty. A receiver that is slow to read is retried with a
+/// backoff for up to `timeout`. The console socket is closed, and so are
+/// the pty fds besides the stdio: the master belongs to the receiver and
+/// must not leak into the workload, which would keep the terminal open
+/// after the receiver closed it. On failure, the pty is closed as a whole.
+///
+/// The returned guard hangs up the terminal when dropped, so a create
+/// failing later, e.g. on a mount, doesn't leave processes behind on a
+/// terminal the receiver may reuse.
+pub fn setup_console(console_fd: RawFd, timeout: Duration) -> Result<ConsoleGuard> {
+    // Safety: the console socket is handed over to be closed here.
+    let console = unsafe { OwnedFd::from_raw_fd(console_fd) };
     // You can also access pty master, but it is better to use the API.
     // ref. https://github.com/containerd/containerd/blob/261c107ffc4ff681bc73988f64e3f60c32233b37/vendor/github.com/containerd/go-runc/console.go#L139-L154
     let openpty_result = nix::pty::openpty(None, None)
         .map_err(|err| TTYError::CreatePseudoTerminal { source: err })?;
+    let [master, slave] = [openpty_result.master, openpty_result.slave];
+    // openpty has no flag for it, the stdio is connected with dup2 which
+    // doesn't keep the flag.
+    for fd in [&master, &slave] {
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .map_err(|err| TTYError::CreatePseudoTerminal { source: err })?;
+    }
     let pty_name: &[u8] = b"/dev/ptmx";
     let iov = [IoSlice::new(pty_name)];
 
-    let [master, slave] = [openpty_result.master, openpty_result.slave];
-    // Use ManuallyDrop to keep FDs open.
-    let master = std::mem::ManuallyDrop::new(master);
-    let slave = std::mem::ManuallyDrop::new(slave);
-
     let fds = [master.as_raw_fd()];
     let cmsg = socket::ControlMessage::ScmRights(&fds);
-    socket::sendmsg::<UnixAddr>(console_fd, &iov, &[cmsg], socket::MsgFlags::empty(), None)
-        .map_err(|err| TTYError::SendPtyMaster { source: err })?;
+    // MSG_NOSIGNAL, a peer that closed the connection is an error instead of
+    // a SIGPIPE killing the init process.
+    let sent = retry_with_backoff(
+        timeout,
+        |err| matches!(err, Errno::EAGAIN | Errno::EINTR | Errno::ENOBUFS),
+        || {
+            socket::sendmsg::<UnixAddr>(
+                console.as_raw_fd(),
+                &iov,
+                &[cmsg],
+                socket::MsgFlags::MSG_NOSIGNAL,
+                None,
+            )
+        },
+    );
+    if let Err(err) = sent {
+        tracing::error!(?err, "failed to send pty master");
+        // The master, the slave and the console socket are dropped, and so
+        // closed, with the error.
+        return Err(match err {
+            Errno::EPIPE | Errno::ECONNRESET => TTYError::ConsoleSocketPeerClosed { source: err },
+            _ => TTYError::SendPtyMaster { source: err },
+        });
+    }
+    drop(master);
 
     if unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSCTTY) } < 0 {
         tracing::warn!("could not TIOCSCTTY");
     };
-    let slave = slave.as_raw_fd();
-    connect_stdio(&slave, &slave, &slave)?;
-    close(console_fd).map_err(|err| TTYError::CloseConsoleSocket { source: err })?;
+    let slave_fd = slave.as_raw_fd();
+    connect_stdio(&slave_fd, &slave_fd, &slave_fd)?;
+    drop(slave);
+    let guard = ConsoleGuard {};
+    close(console.into_raw_fd()).map_err(|err| TTYError::CloseConsoleSocket { source: err })?;
 
-    Ok(())
+    Ok(guard)
 }
 
 fn connect_stdio(stdin: &RawFd, stdout: &RawFd, stderr: &RawFd) -> Result<()> {
@@ -173,12 +342,37 @@ mod tests {
     use std::os::fd::IntoRawFd;
     use std::os::unix::net::UnixListener;
 
-    use anyhow::{Ok, Result};
+    use anyhow::Result;
     use serial_test::serial;
 
     use super::*;
+    use crate::test_utils::{test_in_child_process, TestCallbackError};
 
     const CONSOLE_SOCKET: &str = "console-socket";
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn open_fds() -> std::result::Result<usize, TestCallbackError> {
+        Ok(std::fs::read_dir("/proc/self/fd")
+            .map_err(|err| err.to_string())?
+            .count())
+    }
+
+    /// A receiver that is bound but only listens after `delay`, so connects
+    /// are refused until then
+    fn slow_receiver(socket_path: &Path, delay: Duration) -> Result<thread::JoinHandle<OwnedFd>> {
+        let socket = socket::socket(
+            socket::AddressFamily::Unix,
+            socket::SockType::Stream,
+            socket::SockFlag::empty(),
+            None,
+        )?;
+        socket::bind(socket.as_raw_fd(), &UnixAddr::new(socket_path)?)?;
+        Ok(thread::spawn(move || {
+            thread::sleep(delay);
+            socket::listen(&socket, socket::Backlog::new(1).unwrap()).unwrap();
+            socket
+        }))
+    }
 
     #[test]
     #[serial]
@@ -187,7 +381,7 @@ mod tests {
         let socket_path = Path::join(testdir.path(), "test-socket");
         let lis = UnixListener::bind(&socket_path);
         assert!(lis.is_ok());
-        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET)?;
+        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, TIMEOUT)?;
         assert_ne!(fd.as_raw_fd(), -1);
         Ok(())
     }
@@ -197,8 +391,67 @@ mod tests {
     fn test_setup_console_socket_empty() -> Result<()> {
         let testdir = tempfile::tempdir()?;
         let socket_path = Path::join(testdir.path(), "test-socket");
-        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET);
-        assert!(fd.is_err());
+        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, TIMEOUT);
+        assert!(matches!(
+            fd,
+            Err(TTYError::ConsoleSocketNotFound { console_socket_path }) if console_socket_path == socket_path
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console_socket_slow_receiver() -> Result<()> {
+        let testdir = tempfile::tempdir()?;
+        let socket_path = Path::join(testdir.path(), "test-socket");
+        let receiver = slow_receiver(&socket_path, Duration::from_millis(100))?;
+
+        let start = Instant::now();
+        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, TIMEOUT)?;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        let listener = receiver.join().unwrap();
+        let conn = socket::accept(listener.as_raw_fd())?;
+        // SAFETY: the fd was just accepted and is owned by nothing else.
+        drop(unsafe { OwnedFd::from_raw_fd(conn) });
+        drop(fd);
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console_socket_not_listening() -> Result<()> {
+        let testdir = tempfile::tempdir()?;
+        let socket_path = Path::join(testdir.path(), "test-socket");
+        let receiver = slow_receiver(&socket_path, Duration::from_millis(500))?;
+
+        let timeout = Duration::from_millis(50);
+        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, timeout);
+        assert!(matches!(
+            fd,
+            Err(TTYError::ConsoleSocketNotListening { timeout: got, .. }) if got == timeout
+        ));
+        receiver.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console_peer_closed() -> Result<()> {
+        let testdir = tempfile::tempdir()?;
+        let socket_path = Path::join(testdir.path(), "test-socket");
+        let lis = UnixListener::bind(&socket_path)?;
+        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, TIMEOUT)?;
+        // The receiver accepts and closes without reading the pty master.
+        drop(lis.accept()?);
+
+        let status = setup_console(fd.into_raw_fd(), TIMEOUT);
+        assert!(matches!(
+            status,
+            Err(TTYError::ConsoleSocketPeerClosed { .. })
+        ));
+
         Ok(())
     }
 
@@ -209,7 +462,7 @@ mod tests {
         let socket_path = Path::join(testdir.path(), "test-socket");
         let _socket = File::create(Path::join(testdir.path(), "console-socket"));
         assert!(_socket.is_ok());
-        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET);
+        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, TIMEOUT);
         assert!(fd.is_err());
 
         Ok(())
@@ -229,8 +482,9 @@ mod tests {
 
         let lis = UnixListener::bind(&socket_path);
         assert!(lis.is_ok());
-        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET)?;
-        let status = setup_console(fd.into_raw_fd());
+        let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, TIMEOUT)?;
+        // The guard hangs up the pty, not the restored stdin.
+        let status = setup_console(fd.into_raw_fd(), TIMEOUT).map(drop);
 
         // restore the original std* before doing final assert
         dup2(old_stdin, StdIO::Stdin.into())?;
@@ -241,4 +495,75 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    #[serial]
+    fn test_receive_pty_master() -> Result<()> {
+        let old_stdin: RawFd = nix::unistd::dup(StdIO::Stdin.into())?;
+        let old_stdout: RawFd = nix::unistd::dup(StdIO::Stdout.into())?;
+        let old_stderr: RawFd = nix::unistd::dup(StdIO::Stderr.into())?;
+
+        let (init_socket, socket) = create_pty_master_socket()?;
+        let status = setup_console(init_socket.into_raw_fd(), TIMEOUT).map(drop);
+        let master = receive_pty_master(&socket);
+
+        dup2(old_stdin, StdIO::Stdin.into())?;
+        dup2(old_stdout, StdIO::Stdout.into())?;
+        dup2(old_stderr, StdIO::Stderr.into())?;
+
+        assert!(status.is_ok());
+        assert!(nix::unistd::isatty(master?.as_raw_fd())?);
+
+        Ok(())
+    }
+
+    // The fds are counted in a child process, the threads of other tests
+    // open fds concurrently.
+    #[test]
+    #[serial]
+    fn test_setup_console_failure_closes_pty() -> Result<()> {
+        let testdir = tempfile::tempdir()?;
+        let socket_path = Path::join(testdir.path(), "test-socket");
+        let lis = UnixListener::bind(&socket_path)?;
+        test_in_child_process(|| {
+            let fd = setup_console_socket(testdir.path(), &socket_path, CONSOLE_SOCKET, TIMEOUT)
+                .map_err(|err| err.to_string())?;
+            drop(lis.accept().map_err(|err| err.to_string())?);
+
+            let before = open_fds()?;
+            let status = setup_console(fd.into_raw_fd(), TIMEOUT);
+            if !matches!(status, Err(TTYError::ConsoleSocketPeerClosed { .. })) {
+                return Err(format!("unexpected status {status:?}").into());
+            }
+            // the console socket is closed along with the pty
+            let after = open_fds()?;
+            if after != before - 1 {
+                return Err(format!("{before} fds before the failure, {after} after").into());
+            }
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console_keeps_only_stdio() -> Result<()> {
+        let (init_socket, socket) = create_pty_master_socket()?;
+        test_in_child_process(move || {
+            let before = open_fds()?;
+            let guard =
+                setup_console(init_socket.into_raw_fd(), TIMEOUT).map_err(|err| err.to_string())?;
+            // the master went to the receiver, the slave is only the stdio
+            let after = open_fds()?;
+            if after != before - 1 {
+                return Err(format!("{before} fds before the setup, {after} after").into());
+            }
+            drop(guard);
+            drop(socket);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
 }
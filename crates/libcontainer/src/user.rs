@@ -0,0 +1,268 @@
+//! Resolution of the user of the spec by name
+//!
+//! The spec defines the username of the process for Windows only, a Linux
+//! process runs with the uid and gid of the spec. A username is still looked
+//! up if the spec leaves both ids at 0, or if the builder asks for it with
+//! [`with_resolve_username`](crate::container::init_builder::InitContainerBuilder::with_resolve_username).
+//! The name is looked up in the `/etc/passwd` of the container for the uid
+//! and the primary gid, and the groups listing the user in the `/etc/group`
+//! of the container are added to the supplementary groups. The init process
+//! reads the files once the mounts of the container are set up, so a passwd
+//! file mounted into the container is used. The outcome is reported in
+//! [`CreateResult::user`](crate::container::CreateResult::user).
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::User;
+use serde::{Deserialize, Serialize};
+
+use crate::rootfs::prewarm;
+
+const PASSWD_PATH: &str = "/etc/passwd";
+const GROUP_PATH: &str = "/etc/group";
+
+#[derive(Debug, thiserror::Error)]
+pub enum UserError {
+    #[error("user {username:?} not found in /etc/passwd of the container")]
+    UnknownUser { username: String },
+    #[error("invalid /etc/passwd entry of user {username:?} in the container: {line:?}")]
+    InvalidEntry { username: String, line: String },
+    #[error("failed to read {path:?}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, UserError>;
+
+/// The ids the process of the container runs with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedUser {
+    /// Name the ids were resolved from, `None` if the ids of the spec are
+    /// used
+    pub username: Option<String>,
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, sorted and without duplicates
+    pub additional_gids: Vec<u32>,
+}
+
+impl ResolvedUser {
+    /// Sets the resolved ids in `user`
+    pub fn apply(&self, user: &mut User) {
+        user.set_uid(self.uid);
+        user.set_gid(self.gid);
+        if !self.additional_gids.is_empty() {
+            user.set_additional_gids(Some(self.additional_gids.clone()));
+        }
+    }
+}
+
+/// Returns if the username of `user` is looked up instead of using its ids,
+/// see the [module](self) docs
+pub fn resolves_username(user: &User, resolve_username: bool) -> bool {
+    user.username().is_some() && (resolve_username || (user.uid() == 0 && user.gid() == 0))
+}
+
+/// Resolves the user of the process in the filesystem at `root`, which is
+/// `/` for the init process once it entered the rootfs. A user whose
+/// username isn't resolved, see [`resolves_username`], is reported with the
+/// ids of the spec.
+pub fn resolve_user(user: &User, root: &Path, resolve_username: bool) -> Result<ResolvedUser> {
+    let mut resolved = ResolvedUser {
+        username: None,
+        uid: user.uid(),
+        gid: user.gid(),
+        additional_gids: user.additional_gids().clone().unwrap_or_default(),
+    };
+    match user.username() {
+        Some(username) if resolves_username(user, resolve_username) => {
+            let (uid, gid) = lookup_passwd(root, username)?;
+            resolved
+                .additional_gids
+                .extend(lookup_groups(root, username)?);
+            resolved.username = Some(username.clone());
+            resolved.uid = uid;
+            resolved.gid = gid;
+            tracing::debug!(?resolved, "resolved the user of the container");
+        }
+        Some(username) => {
+            tracing::debug!(
+                username,
+                uid = user.uid(),
+                gid = user.gid(),
+                "the username is ignored in favor of the ids of the spec"
+            );
+        }
+        None => {}
+    }
+    resolved.additional_gids.sort_unstable();
+    resolved.additional_gids.dedup();
+
+    Ok(resolved)
+}
+
+/// Reads a file of the container, `None` if it doesn't exist
+fn read_in_rootfs(rootfs: &Path, path: &str) -> Result<Option<String>> {
+    let host_path = match prewarm::resolve_in_rootfs(rootfs, Path::new(path)) {
+        Some(host_path) => host_path,
+        None => return Ok(None),
+    };
+    match fs::read_to_string(&host_path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(UserError::Read {
+            path: host_path,
+            source: err,
+        }),
+    }
+}
+
+/// The uid and gid of `username` in the passwd file of the container
+fn lookup_passwd(rootfs: &Path, username: &str) -> Result<(u32, u32)> {
+    let passwd = read_in_rootfs(rootfs, PASSWD_PATH)?.unwrap_or_default();
+    let line = passwd
+        .lines()
+        .find(|line| line.split(':').next() == Some(username))
+        .ok_or_else(|| UserError::UnknownUser {
+            username: username.to_owned(),
+        })?;
+    let fields: Vec<&str> = line.split(':').collect();
+    match fields.as_slice() {
+        [_, _, uid, gid, ..] => match (uid.parse(), gid.parse()) {
+            (Ok(uid), Ok(gid)) => Ok((uid, gid)),
+            _ => Err(UserError::InvalidEntry {
+                username: username.to_owned(),
+                line: line.to_owned(),
+            }),
+        },
+        _ => Err(UserError::InvalidEntry {
+            username: username.to_owned(),
+            line: line.to_owned(),
+        }),
+    }
+}
+
+/// The gids of the groups listing `username` as a member in the group file
+/// of the container. Malformed lines are skipped.
+fn lookup_groups(rootfs: &Path, username: &str) -> Result<Vec<u32>> {
+    let group = read_in_rootfs(rootfs, GROUP_PATH)?.unwrap_or_default();
+    Ok(group
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.as_slice() {
+                [_, _, gid, members] if members.split(',').any(|member| member == username) => {
+                    gid.parse().ok()
+                }
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::UserBuilder;
+
+    use super::*;
+
+    fn prepare_rootfs() -> Result<tempfile::TempDir> {
+        let rootfs = tempfile::tempdir()?;
+        fs::create_dir(rootfs.path().join("etc"))?;
+        fs::write(
+            rootfs.path().join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/sh\nweb:x:1001:1002::/home/web:/bin/sh\n",
+        )?;
+        fs::write(
+            rootfs.path().join("etc/group"),
+            "root:x:0:\nweb:x:1002:\naudio:x:29:web,other\nvideo:x:44:other\nbroken\n",
+        )?;
+        Ok(rootfs)
+    }
+
+    #[test]
+    fn test_resolve_username_from_rootfs() -> Result<()> {
+        let rootfs = prepare_rootfs()?;
+        let mut user = UserBuilder::default()
+            .username("web")
+            .additional_gids(vec![44, 29])
+            .build()?;
+        let resolved = resolve_user(&user, rootfs.path(), false)?;
+        assert_eq!(
+            resolved,
+            ResolvedUser {
+                username: Some("web".to_owned()),
+                uid: 1001,
+                gid: 1002,
+                additional_gids: vec![29, 44],
+            }
+        );
+        resolved.apply(&mut user);
+        assert_eq!((user.uid(), user.gid()), (1001, 1002));
+        assert_eq!(user.additional_gids(), &Some(vec![29, 44]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_username_with_ids() -> Result<()> {
+        let rootfs = prepare_rootfs()?;
+        let user = UserBuilder::default()
+            .username("web")
+            .uid(5u32)
+            .gid(6u32)
+            .build()?;
+        // the ids of the spec win unless resolving is asked for
+        assert!(!resolves_username(&user, false));
+        assert_eq!(
+            resolve_user(&user, rootfs.path(), false)?,
+            ResolvedUser {
+                username: None,
+                uid: 5,
+                gid: 6,
+                additional_gids: vec![],
+            }
+        );
+        assert!(resolves_username(&user, true));
+        assert_eq!(resolve_user(&user, rootfs.path(), true)?.uid, 1001);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_unknown_username() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        // no passwd at all
+        let user = UserBuilder::default().username("web").build()?;
+        let err = resolve_user(&user, rootfs.path(), false).unwrap_err();
+        assert!(matches!(err, UserError::UnknownUser { username } if username == "web"));
+
+        fs::create_dir(rootfs.path().join("etc"))?;
+        fs::write(
+            rootfs.path().join("etc/passwd"),
+            "web:x:web:1002::/:/bin/sh\n",
+        )?;
+        let err = resolve_user(&user, rootfs.path(), false).unwrap_err();
+        assert!(matches!(err, UserError::InvalidEntry { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_ids_as_is() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        let user = UserBuilder::default().uid(5u32).gid(6u32).build()?;
+        let resolved = resolve_user(&user, rootfs.path(), true)?;
+        assert_eq!(
+            resolved,
+            ResolvedUser {
+                username: None,
+                uid: 5,
+                gid: 6,
+                additional_gids: vec![],
+            }
+        );
+        Ok(())
+    }
+}
@@ -1,12 +1,14 @@
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::{env, fs};
 
+use nix::sched::CloneFlags;
 use nix::unistd::Pid;
 use oci_spec::runtime::{Linux, LinuxIdMapping, LinuxNamespace, LinuxNamespaceType, Mount, Spec};
 
 use crate::error::MissingSpecError;
 use crate::namespaces::{NamespaceError, Namespaces};
+use crate::output_capture::{OutputCapture, DEFAULT_CAPTURE_LIMIT};
 use crate::syscall::syscall::{create_syscall, Syscall};
 use crate::utils;
 // Wrap the uid/gid path function into a struct for dependency injection. This
@@ -123,6 +125,12 @@ pub enum MappingError {
     NoPathEnv,
     #[error("failed to execute newuidmap/newgidmap")]
     Execute(#[source] std::io::Error),
+    #[error("{binary:?} failed with {status}: {stderr}")]
+    Failed {
+        binary: PathBuf,
+        status: std::process::ExitStatus,
+        stderr: String,
+    },
     #[error("at least one id mapping needs to be defined")]
     NoIDMapping,
     #[error("failed to write id mapping")]
@@ -148,6 +156,20 @@ pub struct UserNamespaceConfig {
 }
 
 impl UserNamespaceConfig {
+    /// Returns the user namespace setup of the container, by the user
+    /// namespace in the spec:
+    ///
+    /// | user namespace | config | container |
+    /// |---|---|---|
+    /// | none | `None` | stays in the host user namespace |
+    /// | with a path | `Some`, [`Self::joins_existing`] | joins it, no mappings are written |
+    /// | without a path | `Some` | creates a new one and writes its mappings |
+    ///
+    /// In the host user namespace, the other namespaces of the spec can
+    /// only be created with CAP_SYS_ADMIN. A rootful runtime has it, as has
+    /// a rootless runtime that was started as root in a user namespace of
+    /// its own (e.g. by podman). A rootless runtime without it fails to
+    /// create them, so a rootless container needs a user namespace.
     pub fn new(spec: &Spec) -> Result<Option<Self>> {
         let syscall = create_syscall();
         let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
@@ -157,26 +179,55 @@ impl UserNamespaceConfig {
             .get(LinuxNamespaceType::User)
             .map_err(ValidateSpecError::Namespaces)?;
 
-        if user_namespace.is_some() && user_namespace.unwrap().path().is_none() {
-            tracing::debug!("container with new user namespace should be created");
-
-            validate_spec_for_new_user_ns(spec, &*syscall).map_err(|err| {
-                tracing::error!("failed to validate spec for new user namespace: {}", err);
-                err
-            })?;
-            let mut user_ns_config = UserNamespaceConfig::try_from(linux)?;
-            if let Some((uid_binary, gid_binary)) = lookup_map_binaries(linux)? {
-                user_ns_config.newuidmap = Some(uid_binary);
-                user_ns_config.newgidmap = Some(gid_binary);
+        match user_namespace {
+            None => {
+                tracing::debug!("container stays in the host user namespace");
+                Ok(None)
             }
+            Some(user_namespace) if user_namespace.path().is_some() => {
+                tracing::debug!(
+                    path = ?user_namespace.path(),
+                    "container joins an existing user namespace"
+                );
+                // The joined namespace is mapped already, the mappings of
+                // the spec don't apply to it.
+                let mut user_ns_config = UserNamespaceConfig::try_from(linux)?;
+                user_ns_config.uid_mappings = None;
+                user_ns_config.gid_mappings = None;
+                Ok(Some(user_ns_config))
+            }
+            Some(_) => {
+                tracing::debug!("container with new user namespace should be created");
+
+                validate_spec_for_new_user_ns(spec, &*syscall).map_err(|err| {
+                    tracing::error!("failed to validate spec for new user namespace: {}", err);
+                    err
+                })?;
+                let mut user_ns_config = UserNamespaceConfig::try_from(linux)?;
+                if let Some((uid_binary, gid_binary)) = lookup_map_binaries(linux)? {
+                    user_ns_config.newuidmap = Some(uid_binary);
+                    user_ns_config.newgidmap = Some(gid_binary);
+                }
 
-            Ok(Some(user_ns_config))
-        } else {
-            tracing::debug!("this container does NOT create a new user namespace");
-            Ok(None)
+                Ok(Some(user_ns_config))
+            }
         }
     }
 
+    /// Returns if the container joins an existing user namespace by path,
+    /// instead of creating a new one
+    pub fn joins_existing(&self) -> bool {
+        self.user_namespace
+            .as_ref()
+            .map_or(false, |user_namespace| user_namespace.path().is_some())
+    }
+
+    /// Returns if the container is rootless, i.e. runs in a user namespace
+    /// set up by a runtime that isn't real root
+    pub fn is_rootless(&self) -> bool {
+        !self.privileged
+    }
+
     pub fn write_uid_mapping(&self, target_pid: Pid) -> Result<()> {
         tracing::debug!("write UID mapping for {:?}", target_pid);
         if let Some(uid_mappings) = self.uid_mappings.as_ref() {
@@ -230,6 +281,23 @@ impl TryFrom<&Linux> for UserNamespaceConfig {
     }
 }
 
+/// Returns the namespaces the spec creates while the container stays in the
+/// host user namespace, none if the spec has a user namespace
+pub fn host_user_ns_namespaces(spec: &Spec) -> Result<CloneFlags> {
+    let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
+    let namespaces =
+        Namespaces::try_from(linux.namespaces().as_ref()).map_err(ValidateSpecError::Namespaces)?;
+    if namespaces
+        .get(LinuxNamespaceType::User)
+        .map_err(ValidateSpecError::Namespaces)?
+        .is_some()
+    {
+        return Ok(CloneFlags::empty());
+    }
+
+    Ok(namespaces.new_namespace_flags())
+}
+
 pub fn unprivileged_user_ns_enabled() -> Result<bool> {
     let user_ns_sysctl = Path::new("/proc/sys/kernel/unprivileged_userns_clone");
     if !user_ns_sysctl.exists() {
@@ -363,7 +431,7 @@ fn validate_mounts_for_new_user_ns(
     Ok(())
 }
 
-fn is_id_mapped(id: u32, mappings: &[LinuxIdMapping]) -> bool {
+pub(crate) fn is_id_mapped(id: u32, mappings: &[LinuxIdMapping]) -> bool {
     mappings
         .iter()
         .any(|m| id >= m.container_id() && id <= m.container_id() + m.size())
@@ -434,14 +502,36 @@ fn write_id_mapping(
             // we can be certain here that map_binary will not be None,
             // as in the lookup_map_binaries function, we return error
             // if there are mappings.len() > 1 and binaries are not present
-            Command::new(map_binary.unwrap())
+            let map_binary = map_binary.unwrap();
+            let execute_err = |err: std::io::Error| {
+                tracing::error!(?err, ?map_binary, "failed to execute newuidmap/newgidmap");
+                MappingError::Execute(err)
+            };
+            let mut child = Command::new(map_binary)
                 .arg(pid.to_string())
                 .args(args)
-                .output()
-                .map_err(|err| {
-                    tracing::error!(?err, ?map_binary, "failed to execute newuidmap/newgidmap");
-                    MappingError::Execute(err)
-                })?;
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(execute_err)?;
+            let capture = OutputCapture::start(&mut child, DEFAULT_CAPTURE_LIMIT);
+            let status = child.wait().map_err(execute_err)?;
+            let (stdout, stderr) = capture.finish();
+            tracing::debug!(
+                ?map_binary,
+                ?status,
+                stdout = stdout.lossy(),
+                stderr = stderr.lossy(),
+                "newuidmap/newgidmap finished"
+            );
+            if !status.success() {
+                tracing::error!(?map_binary, ?status, "newuidmap/newgidmap failed");
+                return Err(MappingError::Failed {
+                    binary: map_binary.to_path_buf(),
+                    status,
+                    stderr: stderr.lossy(),
+                });
+            }
         }
     }
 
@@ -492,6 +582,50 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_host_user_ns() -> Result<()> {
+        let mntns = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Mount)
+            .build()?;
+        // a new mount namespace, but the host user namespace
+        let spec = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .namespaces(vec![mntns.clone()])
+                    .build()?,
+            )
+            .build()?;
+        assert!(UserNamespaceConfig::new(&spec)?.is_none());
+        assert_eq!(host_user_ns_namespaces(&spec)?, CloneFlags::CLONE_NEWNS);
+
+        // a joined user namespace is no host user namespace, and its mappings
+        // are not written
+        let userns = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::User)
+            .path("/proc/1/ns/user")
+            .build()?;
+        let uid_mappings = vec![LinuxIdMappingBuilder::default()
+            .host_id(gen_u32())
+            .container_id(0_u32)
+            .size(10_u32)
+            .build()?];
+        let spec = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .namespaces(vec![mntns, userns])
+                    .uid_mappings(uid_mappings)
+                    .build()?,
+            )
+            .build()?;
+        let config = UserNamespaceConfig::new(&spec)?.unwrap();
+        assert!(config.joins_existing());
+        assert!(config.uid_mappings.is_none());
+        assert_eq!(host_user_ns_namespaces(&spec)?, CloneFlags::empty());
+
+        assert!(!UserNamespaceConfig::default().joins_existing());
+        Ok(())
+    }
+
     #[test]
     fn test_validate_err() -> Result<()> {
         let syscall = create_syscall();
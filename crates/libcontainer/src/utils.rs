@@ -7,14 +7,16 @@ use std::os::unix::fs::DirBuilderExt;
 use std::path::{Component, Path, PathBuf};
 use std::time::Duration;
 
+use caps::{CapSet, Capability};
 use nix::sys::stat::Mode;
 use nix::sys::statfs;
-use nix::unistd::{Uid, User};
+use nix::unistd::{Pid, Uid, User};
 use oci_spec::runtime::Spec;
 
 use crate::error::LibcontainerError;
 use crate::syscall::syscall::{create_syscall, Syscall};
-use crate::user_ns::UserNamespaceConfig;
+use crate::syscall::SyscallError;
+use crate::user_ns::{self, UserNamespaceConfig};
 
 #[derive(Debug, thiserror::Error)]
 pub enum PathBufExtError {
@@ -267,6 +269,27 @@ pub fn rootless_required(syscall: &dyn Syscall) -> Result<bool, std::io::Error>
     is_in_new_userns()
 }
 
+/// Sets if the process is dumpable. Making the process non-dumpable is
+/// hardening, so with `tolerate_eperm` a host that doesn't permit the prctl,
+/// e.g. because its seccomp policy blocks it, only gets a warning and the
+/// process stays as it is.
+pub fn set_dumpable(
+    syscall: &dyn Syscall,
+    dumpable: bool,
+    tolerate_eperm: bool,
+) -> Result<(), SyscallError> {
+    match syscall.set_dumpable(dumpable) {
+        Err(SyscallError::Nix(nix::errno::Errno::EPERM)) if tolerate_eperm => {
+            tracing::warn!(
+                dumpable,
+                "setting the dumpable flag is not permitted, keeping it"
+            );
+            Ok(())
+        }
+        result => result,
+    }
+}
+
 /// checks if given spec is valid for current user namespace setup
 pub fn validate_spec_for_new_user_ns(spec: &Spec) -> Result<(), LibcontainerError> {
     let syscall = create_syscall();
@@ -282,6 +305,20 @@ pub fn validate_spec_for_new_user_ns(spec: &Spec) -> Result<(), LibcontainerErro
     if is_rootless_required && !in_user_ns && config.is_none() {
         return Err(LibcontainerError::NoUserNamespace);
     }
+    // Staying in the host user namespace is fine for the runtime that holds
+    // CAP_SYS_ADMIN in it, creating the other namespaces fails without.
+    if is_rootless_required && config.is_none() {
+        let flags = user_ns::host_user_ns_namespaces(spec)?;
+        let sys_admin =
+            caps::has_cap(None, CapSet::Effective, Capability::CAP_SYS_ADMIN).unwrap_or(true);
+        if !flags.is_empty() && !sys_admin {
+            tracing::warn!(
+                ?flags,
+                "rootless container creates namespaces without a user namespace, \
+                    which fails without CAP_SYS_ADMIN"
+            );
+        }
+    }
     Ok(())
 }
 
@@ -312,6 +349,37 @@ where
     unreachable!("retry loop completed without returning a result.");
 }
 
+/// Returns `pid` followed by its descendants, as far as they can be found in
+/// /proc. Processes exiting or forking during the walk may be missed.
+pub fn process_tree(pid: Pid) -> Vec<Pid> {
+    let mut pids = vec![pid];
+    let mut next = 0;
+    while let Some(pid) = pids.get(next).copied() {
+        pids.extend(children(pid));
+        next += 1;
+    }
+    pids
+}
+
+/// The children of all the threads of `pid`
+fn children(pid: Pid) -> Vec<Pid> {
+    let tasks = match fs::read_dir(format!("/proc/{pid}/task")) {
+        Ok(tasks) => tasks,
+        Err(_) => return Vec::new(),
+    };
+    tasks
+        .flatten()
+        .filter_map(|task| fs::read_to_string(task.path().join("children")).ok())
+        .flat_map(|children| {
+            children
+                .split_whitespace()
+                .filter_map(|child| child.parse().ok())
+                .map(Pid::from_raw)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::{bail, Result};
@@ -454,4 +522,20 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_process_tree() -> Result<()> {
+        let mut child = std::process::Command::new("sleep").arg("10").spawn()?;
+        let child_pid = Pid::from_raw(child.id() as i32);
+        let tree = process_tree(Pid::this());
+        child.kill()?;
+        child.wait()?;
+
+        assert_eq!(tree[0], Pid::this());
+        assert!(tree.contains(&child_pid), "{tree:?}");
+        // pid_max is at most 2^22
+        let gone = Pid::from_raw(1 << 23);
+        assert_eq!(process_tree(gone), vec![gone]);
+        Ok(())
+    }
 }
@@ -1,13 +1,33 @@
+use std::env;
 use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 
+use nix::errno::Errno;
 use nix::unistd;
 use oci_spec::runtime::Spec;
 
-use super::{Executor, ExecutorError, ExecutorValidationError};
+use super::{argv0_override, default_path, Executor, ExecutorError, ExecutorValidationError};
 
-#[derive(Clone)]
-pub struct DefaultExecutor {}
+/// Shell a payload the kernel can't execute is run with, in the rootfs of the
+/// container
+const SHELL: &str = "/bin/sh";
+
+#[derive(Clone, Default)]
+pub struct DefaultExecutor {
+    /// Runs a payload the kernel can't execute, e.g. a script without a
+    /// shebang, with `/bin/sh` as shells do. Off by default, so such a
+    /// payload fails with ENOEXEC.
+    shell_fallback: bool,
+}
+
+impl DefaultExecutor {
+    /// Runs payloads the kernel can't execute with `/bin/sh`.
+    pub fn with_shell_fallback(mut self, shell_fallback: bool) -> Self {
+        self.shell_fallback = shell_fallback;
+        self
+    }
+}
 
 impl Executor for DefaultExecutor {
     fn exec(&self, spec: &Spec) -> Result<(), ExecutorError> {
@@ -22,27 +42,37 @@ impl Executor for DefaultExecutor {
             })?;
 
         let executable = args[0].as_str();
-        let cstring_path = CString::new(executable.as_bytes()).map_err(|err| {
-            tracing::error!("failed to convert path {executable:?} to cstring: {}", err,);
+        // Resolve the executable ourselves instead of using execvp, so the
+        // path that failed to execute can be reported, and a file that isn't
+        // a valid executable fails with ENOEXEC instead of being run by the
+        // shell, unless the shell fallback is on.
+        let path_var = env::var("PATH")
+            .ok()
+            .or_else(|| default_path(spec).map(str::to_owned))
+            .unwrap_or_default();
+        let path =
+            get_executable_path(executable, &path_var).unwrap_or_else(|| PathBuf::from(executable));
+        let cstring_path = CString::new(path.as_os_str().as_bytes()).map_err(|err| {
+            tracing::error!("failed to convert path {path:?} to cstring: {}", err,);
             ExecutorError::InvalidArg
         })?;
-        let a: Vec<CString> = args
-            .iter()
+        // The executable is still resolved from the first arg, only the
+        // argv[0] it sees is replaced, e.g. for the applets of busybox.
+        let argv0 = argv0_override(spec).unwrap_or(executable);
+        let a: Vec<CString> = std::iter::once(argv0)
+            .chain(args.iter().skip(1).map(String::as_str))
             .map(|s| CString::new(s.as_bytes()).unwrap_or_default())
             .collect();
-        unistd::execvp(&cstring_path, &a).map_err(|err| {
-            tracing::error!(?err, filename = ?cstring_path, args = ?a, "failed to execvp");
-            ExecutorError::Execution(
-                format!(
-                    "error '{}' executing '{:?}' with args '{:?}'",
-                    err, cstring_path, a
-                )
-                .into(),
-            )
-        })?;
+        if let Err(errno) = unistd::execv(&cstring_path, &a) {
+            if errno == Errno::ENOEXEC && self.shell_fallback {
+                return Err(exec_with_shell(path, args));
+            }
+            tracing::error!(?errno, filename = ?cstring_path, args = ?a, "failed to execv");
+            Err(ExecutorError::ExecFailed { path, errno })?;
+        }
 
-        // After execvp is called, the process is replaced with the container
-        // payload through execvp, so it should never reach here.
+        // After execv is called, the process is replaced with the container
+        // payload through execv, so it should never reach here.
         unreachable!();
     }
 
@@ -56,14 +86,18 @@ impl Executor for DefaultExecutor {
 
         if let Some(args) = proc.args() {
             let envs: Vec<String> = proc.env().as_ref().unwrap_or(&vec![]).clone();
-            let path_vars: Vec<&String> = envs.iter().filter(|&e| e.starts_with("PATH=")).collect();
-            if path_vars.is_empty() {
-                tracing::error!("PATH environment variable is not set");
-                Err(ExecutorValidationError::ArgValidationError(
-                    "PATH environment variable is not set".into(),
-                ))?;
-            }
-            let path_var = path_vars[0].trim_start_matches("PATH=");
+            let path_var = match envs.iter().find(|e| e.starts_with("PATH=")) {
+                Some(path_var) => path_var.trim_start_matches("PATH="),
+                None => match default_path(spec) {
+                    Some(path_var) => path_var,
+                    None => {
+                        tracing::error!("PATH environment variable is not set");
+                        return Err(ExecutorValidationError::ArgValidationError(
+                            "PATH environment variable is not set".into(),
+                        ));
+                    }
+                },
+            };
             match get_executable_path(&args[0], path_var) {
                 None => {
                     tracing::error!(
@@ -109,7 +143,61 @@ impl Executor for DefaultExecutor {
 }
 
 pub fn get_executor() -> Box<dyn Executor> {
-    Box::new(DefaultExecutor {})
+    Box::new(DefaultExecutor::default())
+}
+
+/// Runs the payload at `path`, which the kernel can't execute, with the shell
+/// and returns why that failed. The shell gets the payload as a command, which
+/// it runs as a script when its own exec fails with ENOEXEC. The init process
+/// already entered the rootfs, so the shell is the one of the container.
+fn exec_with_shell(path: PathBuf, args: &[String]) -> ExecutorError {
+    let command = shell_command(&path, &args[1..]);
+    let argv = [SHELL, "-c", command.as_str()];
+    tracing::warn!(
+        ?path,
+        ?argv,
+        "payload is not executable by the kernel, running it with the shell"
+    );
+
+    let errno = match argv
+        .iter()
+        .map(|arg| CString::new(*arg))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(cstring_argv) => match unistd::execv(&cstring_argv[0], &cstring_argv) {
+            Err(errno) => errno,
+            Ok(never) => match never {},
+        },
+        Err(_) => Errno::EINVAL,
+    };
+    tracing::error!(
+        ?errno,
+        ?path,
+        ?argv,
+        "failed to run the payload with the shell"
+    );
+
+    ExecutorError::ShellFallbackFailed {
+        path,
+        shell: PathBuf::from(SHELL),
+        errno,
+    }
+}
+
+/// The command line the shell runs the payload with, with all words quoted
+fn shell_command(path: &Path, args: &[String]) -> String {
+    std::iter::once(path.to_string_lossy().as_ref())
+        .chain(args.iter().map(String::as_str))
+        .map(shell_quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes a word for the shell. Nothing is special in single quotes, so only
+/// a single quote itself has to be written as `'\''`, closing the quotes
+/// around an escaped quote.
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
 }
 
 fn get_executable_path(name: &str, path_var: &str) -> Option<PathBuf> {
@@ -144,6 +232,7 @@ mod tests {
     use serial_test::serial;
 
     use super::*;
+    use crate::workload::{ARGV0_ANNOTATION, DEFAULT_PATH_ANNOTATION};
 
     #[test]
     fn test_get_executable_path() {
@@ -167,6 +256,99 @@ mod tests {
         assert_eq!(get_executable_path(non_existing_binary, path_value), None);
     }
 
+    #[test]
+    fn test_validate_with_default_path() -> anyhow::Result<()> {
+        use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+        let mut spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .args(vec!["sh".to_owned()])
+                    .env(vec!["TERM=xterm".to_owned()])
+                    .build()?,
+            )
+            .build()?;
+        let executor = DefaultExecutor::default();
+        assert!(executor.validate(&spec).is_err());
+
+        spec.set_annotations(Some(HashMap::from([(
+            DEFAULT_PATH_ANNOTATION.to_owned(),
+            "/usr/bin:/bin".to_owned(),
+        )])));
+        executor.validate(&spec)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_with_default_path() -> anyhow::Result<()> {
+        use nix::sys::wait::{self, WaitStatus};
+        use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+        let mut spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .args(vec!["sh".to_owned(), "-c".to_owned(), "exit 7".to_owned()])
+                    .build()?,
+            )
+            .build()?;
+        spec.set_annotations(Some(HashMap::from([(
+            DEFAULT_PATH_ANNOTATION.to_owned(),
+            "/usr/bin:/bin".to_owned(),
+        )])));
+
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                let status = wait::waitpid(child, None)?;
+                assert_eq!(status, WaitStatus::Exited(child, 7));
+            }
+            unistd::ForkResult::Child => {
+                // the env of the process has no PATH
+                env::remove_var("PATH");
+                let _ = DefaultExecutor::default().exec(&spec);
+                std::process::exit(1);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_with_argv0_override() -> anyhow::Result<()> {
+        use nix::sys::wait::{self, WaitStatus};
+        use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+        // without further args, $0 of `sh -c` is the argv[0] of the shell
+        let mut spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .args(vec![
+                        "sh".to_owned(),
+                        "-c".to_owned(),
+                        r#"[ "$0" = applet ] && exit 7; exit 3"#.to_owned(),
+                    ])
+                    .build()?,
+            )
+            .build()?;
+        spec.set_annotations(Some(HashMap::from([(
+            ARGV0_ANNOTATION.to_owned(),
+            "applet".to_owned(),
+        )])));
+
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                let status = wait::waitpid(child, None)?;
+                assert_eq!(status, WaitStatus::Exited(child, 7));
+            }
+            unistd::ForkResult::Child => {
+                let _ = DefaultExecutor::default().exec(&spec);
+                std::process::exit(1);
+            }
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_is_executable() {
         let tmp = tempfile::tempdir().expect("create temp directory for test");
@@ -183,6 +365,130 @@ mod tests {
         assert!(!is_executable(directory_path).unwrap());
     }
 
+    #[test]
+    fn test_exec_non_binary_reports_errno_and_path() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+        let tmp = tempfile::tempdir()?;
+        let script = tmp.path().join("not-a-binary");
+        std::fs::write(&script, "this is not an executable\n")?;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        let spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .args(vec![script.to_string_lossy().to_string()])
+                    .build()?,
+            )
+            .build()?;
+
+        match get_executor().exec(&spec) {
+            Err(ExecutorError::ExecFailed { path, errno }) => {
+                assert_eq!(path, script);
+                assert_eq!(errno, nix::errno::Errno::ENOEXEC);
+            }
+            other => panic!("expected exec to fail with ENOEXEC, got {other:?}"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell_command() -> anyhow::Result<()> {
+        let args: Vec<String> = [
+            "with space",
+            "it's",
+            r#""double""#,
+            "$HOME",
+            "",
+            r"back\slash",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        assert_eq!(
+            shell_command(Path::new("/bin/my script"), &args),
+            r#"'/bin/my script' 'with space' 'it'\''s' '"double"' '$HOME' '' 'back\slash'"#
+        );
+
+        // the shell splits the command back into the very same words
+        let output = std::process::Command::new(SHELL)
+            .arg("-c")
+            .arg(format!(
+                "printf '%s\\n' {}",
+                shell_command(Path::new("printf"), &args)
+            ))
+            .output()?;
+        let words: Vec<&str> = std::str::from_utf8(&output.stdout)?.lines().collect();
+        assert_eq!(words[0], "printf");
+        assert_eq!(
+            words[1..],
+            args.iter().map(String::as_str).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_non_binary_with_shell_fallback() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        use nix::sys::wait::{self, WaitStatus};
+        use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+        let tmp = tempfile::tempdir()?;
+        let out = tmp.path().join("out");
+        let script = tmp.path().join("no-shebang");
+        std::fs::write(
+            &script,
+            format!(
+                "printf '%s|' \"$@\" > {}\n",
+                shell_quote(&out.to_string_lossy())
+            ),
+        )?;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        let spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .args(vec![
+                        script.to_string_lossy().to_string(),
+                        "a b".to_owned(),
+                        "it's".to_owned(),
+                        "$HOME".to_owned(),
+                    ])
+                    .build()?,
+            )
+            .build()?;
+
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                let status = wait::waitpid(child, None)?;
+                assert_eq!(status, WaitStatus::Exited(child, 0));
+            }
+            unistd::ForkResult::Child => {
+                let executor = DefaultExecutor::default().with_shell_fallback(true);
+                let _ = executor.exec(&spec);
+                std::process::exit(1);
+            }
+        }
+        assert_eq!(std::fs::read_to_string(&out)?, "a b|it's|$HOME|");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell_fallback_failed_error() {
+        let err = ExecutorError::ShellFallbackFailed {
+            path: PathBuf::from("/app/run"),
+            shell: PathBuf::from(SHELL),
+            errno: Errno::ENOENT,
+        };
+        let message = err.to_string();
+        assert!(message.contains("\"/app/run\""), "{message}");
+        assert!(message.contains("ENOEXEC"), "{message}");
+        assert!(message.contains("\"/bin/sh\""), "{message}");
+    }
+
     #[test]
     #[serial]
     fn test_executor_set_envs() {
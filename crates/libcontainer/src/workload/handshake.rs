@@ -0,0 +1,22 @@
+use oci_spec::runtime::Spec;
+
+use super::{Executor, ExecutorError, ExecutorValidationError};
+
+/// Built-in minimal init, which exits successfully as soon as the container is
+/// started instead of executing the workload of the spec. It allows testing
+/// the create and start handshake end to end without a real image.
+#[derive(Clone)]
+pub struct HandshakeOnlyExecutor {}
+
+impl Executor for HandshakeOnlyExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        tracing::debug!("start handshake completed, exiting without executing the workload");
+        // The executor must not return on success, so exit the init process
+        // the same way a workload that did nothing would.
+        std::process::exit(0)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
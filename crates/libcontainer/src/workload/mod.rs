@@ -1,12 +1,42 @@
 use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
 
 use oci_spec::runtime::Spec;
 
 pub mod default;
+pub mod handshake;
+pub mod registry;
 
 pub static EMPTY: Vec<String> = Vec::new();
 
+/// Annotation of the spec handed to the executor with the PATH the
+/// executable of the process is resolved with when its env has no PATH, see
+/// [`with_default_path`](crate::container::builder::ContainerBuilder::with_default_path).
+/// It isn't part of the env of the process.
+pub const DEFAULT_PATH_ANNOTATION: &str = "io.youki.default-path";
+
+/// Returns the PATH of the [`DEFAULT_PATH_ANNOTATION`] annotation
+pub fn default_path(spec: &Spec) -> Option<&str> {
+    spec.annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(DEFAULT_PATH_ANNOTATION))
+        .map(String::as_str)
+}
+
+/// Annotation of the spec handed to the executor with the `argv[0]` the
+/// executable of the process gets instead of the first arg, see
+/// [`with_argv0_override`](crate::container::builder::ContainerBuilder::with_argv0_override).
+pub const ARGV0_ANNOTATION: &str = "io.youki.argv0";
+
+/// Returns the `argv[0]` of the [`ARGV0_ANNOTATION`] annotation
+pub fn argv0_override(spec: &Spec) -> Option<&str> {
+    spec.annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(ARGV0_ANNOTATION))
+        .map(String::as_str)
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ExecutorError {
     #[error("invalid argument")]
@@ -17,6 +47,17 @@ pub enum ExecutorError {
     Other(String),
     #[error("{0} executor can't handle spec")]
     CantHandle(&'static str),
+    #[error("failed to execute {path:?}: {errno}")]
+    ExecFailed {
+        path: PathBuf,
+        errno: nix::errno::Errno,
+    },
+    #[error("failed to execute {path:?}: ENOEXEC, and running it with {shell:?} failed: {errno}")]
+    ShellFallbackFailed {
+        path: PathBuf,
+        shell: PathBuf,
+        errno: nix::errno::Errno,
+    },
 }
 
 #[derive(Debug, thiserror::Error)]
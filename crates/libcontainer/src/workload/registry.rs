@@ -0,0 +1,288 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use oci_spec::runtime::Spec;
+
+use super::{Executor, ExecutorError, ExecutorSetEnvsError, ExecutorValidationError};
+
+/// Annotation used by higher level runtimes (e.g. crun, podman) to request a
+/// specific handler for the container workload.
+pub const OCI_HANDLER_ANNOTATION: &str = "run.oci.handler";
+/// Annotation set on wasm images to request a specific wasm variant.
+pub const WASM_VARIANT_ANNOTATION: &str = "module.wasm.image/variant";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExecutorRegistryError {
+    #[error("unknown executor '{name}', registered executors: [{}]", .registered.join(", "))]
+    UnknownExecutor {
+        name: String,
+        registered: Vec<String>,
+    },
+}
+
+/// A registry of named executors, which allows a single binary to pick the
+/// executor of the container workload at runtime.
+///
+/// The executor is selected in the following order:
+/// 1. The name set with [`ExecutorRegistry::with_selected`].
+/// 2. The `run.oci.handler` annotation of the spec.
+/// 3. The `module.wasm.image/variant` annotation of the spec.
+/// 4. The default executor.
+///
+/// The registry implements [`Executor`] itself, so it can be passed to
+/// `ContainerBuilder::with_executor` like any other executor.
+///
+/// # Example
+///
+/// ```no_run
+/// use libcontainer::workload::default::DefaultExecutor;
+/// use libcontainer::workload::registry::ExecutorRegistry;
+///
+/// let registry = ExecutorRegistry::new(Box::new(DefaultExecutor::default()))
+///     .register("runc-compat", Box::new(DefaultExecutor::default()));
+/// ```
+#[derive(Clone)]
+pub struct ExecutorRegistry {
+    executors: BTreeMap<String, Box<dyn Executor>>,
+    default: Box<dyn Executor>,
+    selected: Option<String>,
+    /// Name of the executor picked for the spec by the last `validate`, so
+    /// that `setup_envs`, which isn't given the spec, forwards to the same
+    /// executor. `None` stands for the default executor.
+    validated: RefCell<Option<String>>,
+}
+
+impl ExecutorRegistry {
+    /// Creates a new registry, falling back to the `default` executor when
+    /// neither the builder nor the spec annotations select one.
+    pub fn new(default: Box<dyn Executor>) -> Self {
+        Self {
+            executors: BTreeMap::new(),
+            default,
+            selected: None,
+            validated: RefCell::new(None),
+        }
+    }
+
+    /// Registers an executor under the given name. Registering the same name
+    /// twice replaces the previously registered executor.
+    pub fn register<S: Into<String>>(mut self, name: S, executor: Box<dyn Executor>) -> Self {
+        self.executors.insert(name.into(), executor);
+        self
+    }
+
+    /// Selects the executor by name, taking precedence over the spec
+    /// annotations.
+    pub fn with_selected<S: Into<String>>(mut self, name: S) -> Self {
+        self.selected = Some(name.into());
+        self
+    }
+
+    /// Names of all registered executors, in sorted order.
+    pub fn names(&self) -> Vec<String> {
+        self.executors.keys().cloned().collect()
+    }
+
+    /// Returns the executor that should run the workload described by `spec`.
+    pub fn select(&self, spec: &Spec) -> Result<&dyn Executor, ExecutorRegistryError> {
+        self.get(self.selected_name(spec))
+    }
+
+    fn selected_name<'a>(&'a self, spec: &'a Spec) -> Option<&'a str> {
+        self.selected.as_deref().or_else(|| {
+            spec.annotations().as_ref().and_then(|annotations| {
+                annotations
+                    .get(OCI_HANDLER_ANNOTATION)
+                    .or_else(|| annotations.get(WASM_VARIANT_ANNOTATION))
+                    .map(String::as_str)
+            })
+        })
+    }
+
+    fn get(&self, name: Option<&str>) -> Result<&dyn Executor, ExecutorRegistryError> {
+        match name {
+            Some(name) => self
+                .executors
+                .get(name)
+                .map(|executor| &**executor)
+                .ok_or_else(|| {
+                    tracing::error!(?name, registered = ?self.names(), "unknown executor");
+                    ExecutorRegistryError::UnknownExecutor {
+                        name: name.to_owned(),
+                        registered: self.names(),
+                    }
+                }),
+            None => Ok(&*self.default),
+        }
+    }
+}
+
+impl Executor for ExecutorRegistry {
+    fn exec(&self, spec: &Spec) -> Result<(), ExecutorError> {
+        self.select(spec)
+            .map_err(|err| ExecutorError::Other(err.to_string()))?
+            .exec(spec)
+    }
+
+    fn validate(&self, spec: &Spec) -> Result<(), ExecutorValidationError> {
+        let name = self.selected_name(spec);
+        *self.validated.borrow_mut() = name.map(str::to_owned);
+        self.get(name)
+            .map_err(|err| ExecutorValidationError::ArgValidationError(err.to_string()))?
+            .validate(spec)
+    }
+
+    fn setup_envs(&self, envs: HashMap<String, String>) -> Result<(), ExecutorSetEnvsError> {
+        // The init process validates the spec before it sets up the envs, so
+        // forward to the executor picked there. Without it, only the name
+        // set on the registry is known.
+        let validated = self.validated.borrow();
+        let name = validated.as_deref().or(self.selected.as_deref());
+        self.get(name)
+            .map_err(|err| ExecutorSetEnvsError::Other(err.to_string()))?
+            .setup_envs(envs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use anyhow::Result;
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct FakeExecutor {
+        name: &'static str,
+    }
+
+    impl Executor for FakeExecutor {
+        fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+            Err(ExecutorError::CantHandle(self.name))
+        }
+
+        fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+            Err(ExecutorValidationError::CantHandle(self.name))
+        }
+    }
+
+    fn registry() -> ExecutorRegistry {
+        ExecutorRegistry::new(Box::new(FakeExecutor { name: "default" }))
+            .register("foo", Box::new(FakeExecutor { name: "foo" }))
+            .register("bar", Box::new(FakeExecutor { name: "bar" }))
+    }
+
+    fn spec_with_annotations(annotations: &[(&str, &str)]) -> Result<Spec> {
+        let annotations: HashMap<String, String> = annotations
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Ok(SpecBuilder::default().annotations(annotations).build()?)
+    }
+
+    fn selected_name(registry: &ExecutorRegistry, spec: &Spec) -> String {
+        match registry.validate(spec) {
+            Err(ExecutorValidationError::CantHandle(name)) => name.to_owned(),
+            Err(err) => err.to_string(),
+            Ok(_) => unreachable!("fake executors never validate successfully"),
+        }
+    }
+
+    #[test]
+    fn test_select_default() -> Result<()> {
+        let spec = spec_with_annotations(&[])?;
+        assert_eq!(selected_name(&registry(), &spec), "default");
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_by_annotation() -> Result<()> {
+        let spec = spec_with_annotations(&[(OCI_HANDLER_ANNOTATION, "foo")])?;
+        assert_eq!(selected_name(&registry(), &spec), "foo");
+
+        let spec = spec_with_annotations(&[(WASM_VARIANT_ANNOTATION, "bar")])?;
+        assert_eq!(selected_name(&registry(), &spec), "bar");
+
+        // run.oci.handler takes precedence over the wasm variant
+        let spec = spec_with_annotations(&[
+            (OCI_HANDLER_ANNOTATION, "foo"),
+            (WASM_VARIANT_ANNOTATION, "bar"),
+        ])?;
+        assert_eq!(selected_name(&registry(), &spec), "foo");
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_by_name() -> Result<()> {
+        let spec = spec_with_annotations(&[(OCI_HANDLER_ANNOTATION, "foo")])?;
+        let registry = registry().with_selected("bar");
+        assert_eq!(selected_name(&registry, &spec), "bar");
+        Ok(())
+    }
+
+    #[derive(Clone)]
+    struct EnvsExecutor {
+        envs: Rc<Cell<usize>>,
+    }
+
+    impl Executor for EnvsExecutor {
+        fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+            Ok(())
+        }
+
+        fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+            Ok(())
+        }
+
+        fn setup_envs(&self, envs: HashMap<String, String>) -> Result<(), ExecutorSetEnvsError> {
+            self.envs.set(envs.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_setup_envs_forwards_to_selected() -> Result<()> {
+        let default_envs = Rc::new(Cell::new(0));
+        let foo_envs = Rc::new(Cell::new(0));
+        let registry = ExecutorRegistry::new(Box::new(EnvsExecutor {
+            envs: default_envs.clone(),
+        }))
+        .register(
+            "foo",
+            Box::new(EnvsExecutor {
+                envs: foo_envs.clone(),
+            }),
+        );
+        let envs = HashMap::from([("FOO".to_owned(), "bar".to_owned())]);
+
+        let spec = spec_with_annotations(&[(OCI_HANDLER_ANNOTATION, "foo")])?;
+        registry.validate(&spec)?;
+        registry.setup_envs(envs.clone())?;
+        assert_eq!(foo_envs.get(), 1);
+        assert_eq!(default_envs.get(), 0);
+
+        let spec = spec_with_annotations(&[])?;
+        registry.validate(&spec)?;
+        registry.setup_envs(envs)?;
+        assert_eq!(default_envs.get(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_unknown() -> Result<()> {
+        let spec = spec_with_annotations(&[(OCI_HANDLER_ANNOTATION, "baz")])?;
+        let err = registry().select(&spec).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unknown executor 'baz', registered executors: [bar, foo]"
+        );
+        assert!(matches!(
+            registry().validate(&spec),
+            Err(ExecutorValidationError::ArgValidationError(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::capabilities::AmbientCapabilities;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+use oci_spec::runtime::{Capability, Spec};
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Number of `CAP_NET_BIND_SERVICE`
+const NET_BIND_SERVICE_BIT: u32 = 10;
+
+/// Returns if `CAP_NET_BIND_SERVICE` is in the ambient set of the calling
+/// process
+fn has_ambient_net_bind_service() -> bool {
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapAmb:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .map_or(false, |mask| mask & (1 << NET_BIND_SERVICE_BIT) != 0)
+}
+
+/// Exits successfully if a child process spawned by the container process
+/// has `CAP_NET_BIND_SERVICE` in its ambient set
+#[derive(Clone)]
+struct AmbientExecutor {}
+
+impl Executor for AmbientExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let code = match unsafe { fork() } {
+            Ok(ForkResult::Child) => {
+                std::process::exit(if has_ambient_net_bind_service() { 0 } else { 1 })
+            }
+            Ok(ForkResult::Parent { child }) => match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, code)) => code,
+                _ => 2,
+            },
+            Err(_) => 2,
+        };
+        std::process::exit(code)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a rootless spec without ambient and inheritable capabilities
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let mut spec = rootless_spec();
+    let process = spec.process_mut().as_mut().unwrap();
+    let mut caps = process.capabilities().clone().unwrap_or_default();
+    caps.set_ambient(None).set_inheritable(None);
+    process.set_capabilities(Some(caps));
+    prepare_container_root_with_spec(root, spec)
+}
+
+fn run(root: &Path, id: &str, ambient: AmbientCapabilities) -> Result<WaitStatus> {
+    let container = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root)?
+        .with_executor(AmbientExecutor {})
+        .as_init(root)
+        .with_ambient_capabilities(ambient)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    Ok(waitpid(init_pid, None)?)
+}
+
+#[test]
+#[serial]
+fn ambient_capability_retained_by_child() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let ambient = AmbientCapabilities::Set([Capability::NetBindService].into_iter().collect());
+    let status = run(root.as_ref(), "test-ambient-caps", ambient)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "child process lacks the ambient capability: {status:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn no_ambient_capability_by_default() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let status = run(
+        root.as_ref(),
+        "test-no-ambient-caps",
+        AmbientCapabilities::default(),
+    )?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 1)),
+        "child process has an ambient capability: {status:?}"
+    );
+
+    Ok(())
+}
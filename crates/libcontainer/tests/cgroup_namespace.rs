@@ -0,0 +1,152 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::init_builder::CgroupNamespacePolicy;
+use libcontainer::container::Container;
+use libcontainer::error::{ErrInvalidSpec, LibcontainerError};
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+use oci_spec::runtime::{LinuxNamespaceBuilder, LinuxNamespaceType, Spec};
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if the container process is in the cgroup namespace
+/// `expected` links to
+#[derive(Clone)]
+struct CgroupNamespaceExecutor {
+    expected: Option<PathBuf>,
+}
+
+impl Executor for CgroupNamespaceExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let joined = fs::read_link("/proc/self/ns/cgroup").ok() == self.expected;
+        std::process::exit(if joined { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a rootless spec with a cgroup namespace, joining the user and
+/// cgroup namespaces of `joined` if set
+fn prepare_container_root(root: impl AsRef<Path>, joined: Option<Pid>) -> Result<()> {
+    let mut spec = rootless_spec();
+    let linux = spec.linux_mut().as_mut().unwrap();
+    let mut namespaces: Vec<_> = linux
+        .namespaces()
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|ns| ns.typ() != LinuxNamespaceType::Cgroup)
+        .collect();
+    namespaces.push(
+        LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Cgroup)
+            .build()?,
+    );
+    if let Some(pid) = joined {
+        namespaces = namespaces
+            .into_iter()
+            .map(|ns| match ns.typ() {
+                LinuxNamespaceType::User | LinuxNamespaceType::Cgroup => {
+                    let name = if ns.typ() == LinuxNamespaceType::User {
+                        "user"
+                    } else {
+                        "cgroup"
+                    };
+                    LinuxNamespaceBuilder::default()
+                        .typ(ns.typ())
+                        .path(format!("/proc/{pid}/ns/{name}"))
+                        .build()
+                }
+                _ => Ok(ns),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    linux.set_namespaces(Some(namespaces));
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn joined_cgroup_namespace_policies() -> Result<()> {
+    // The init process of a created container holds its cgroup namespace
+    // until the container is started or deleted.
+    let holder_root = tempdir()?;
+    prepare_container_root(&holder_root, None)?;
+    let holder = ContainerBuilder::new("test-cgroupns-holder".to_owned(), SyscallType::Linux)
+        .with_root_path(holder_root.as_ref())?
+        .with_executor(CgroupNamespaceExecutor { expected: None })
+        .as_init(holder_root.as_ref())
+        .build()?;
+    let holder = scopeguard::guard(holder, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let holder_pid = holder.pid().unwrap();
+    let holder_cgroupns = fs::read_link(format!("/proc/{holder_pid}/ns/cgroup"))?;
+
+    // A cgroup of its own contradicts the rejecting policy.
+    let root = tempdir()?;
+    prepare_container_root(&root, Some(holder_pid))?;
+    let err = ContainerBuilder::new("test-cgroupns-reject".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_cgroup_namespace_policy(CgroupNamespacePolicy::Reject)
+        .build()
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            LibcontainerError::InvalidSpec(ErrInvalidSpec::CgroupWithJoinedCgroupNamespace)
+        ),
+        "{err:?}"
+    );
+    assert!(!root.path().join("test-cgroupns-reject").exists());
+
+    // So does mounting cgroup2 inside, whatever the policy.
+    let err = ContainerBuilder::new("test-cgroupns-mount".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_mount_cgroup2_inside(true)
+        .build()
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            LibcontainerError::Cgroup2MountWithJoinedCgroupNamespace
+        ),
+        "{err:?}"
+    );
+
+    let container = ContainerBuilder::new("test-cgroupns-inherit".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(CgroupNamespaceExecutor {
+            expected: Some(holder_cgroupns),
+        })
+        .as_init(root.as_ref())
+        .with_cgroup_namespace_policy(CgroupNamespacePolicy::Inherit)
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    assert!(container.unmanaged_cgroups());
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+
+    let status = waitpid(init_pid, None)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "the container isn't in the joined cgroup namespace: {status:?}"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! Helpers shared by the integration tests. Every test file compiles its own
+//! copy of this module and only uses some of it.
+#![allow(dead_code)]
+
+use std::fs::create_dir;
+use std::path::Path;
+
+use anyhow::Result;
+use nix::unistd::{getegid, geteuid};
+use oci_spec::runtime::{RootBuilder, Spec};
+
+/// Spec of a rootless container of the user running the tests
+pub fn rootless_spec() -> Spec {
+    Spec::rootless(geteuid().as_raw(), getegid().as_raw())
+}
+
+/// Prepares the bundle of a rootless container with an empty rootfs in
+/// `root`
+pub fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    prepare_container_root_with_spec(root, rootless_spec())
+}
+
+/// Prepares the bundle of a container with an empty rootfs in `root`. The
+/// root of `spec` is set to the rootfs before it is saved.
+pub fn prepare_container_root_with_spec(root: impl AsRef<Path>, mut spec: Spec) -> Result<()> {
+    let root = root.as_ref();
+    create_dir(root.join("rootfs"))?;
+
+    spec.set_root(
+        RootBuilder::default()
+            .path("rootfs")
+            .readonly(false)
+            .build()
+            .ok(),
+    );
+
+    spec.save(root.join("config.json"))?;
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::{Container, ContainerStatus};
+use libcontainer::error::LibcontainerError;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits the init process shortly after it was validated, which happens right
+/// before it signals readiness.
+#[derive(Clone)]
+struct ExitAfterReadyExecutor {}
+
+impl Executor for ExitAfterReadyExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        std::process::exit(0)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        thread::spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            std::process::exit(1);
+        });
+        Ok(())
+    }
+}
+
+#[test]
+#[serial]
+fn create_fails_if_init_exits_after_readiness() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let result = ContainerBuilder::new("test-confirm-liveness".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(ExitAfterReadyExecutor {})
+        .as_init(root.as_ref())
+        .with_confirm_liveness(true)
+        .with_liveness_delay(Duration::from_secs(1))
+        .build();
+
+    let mut container = Container::load(root.path().join("test-confirm-liveness"))?;
+    assert!(matches!(
+        result,
+        Err(LibcontainerError::InitExitedEarly { .. })
+    ));
+    // The container is left behind as stopped, so it can be deleted.
+    assert_eq!(container.status(), ContainerStatus::Stopped);
+    container.delete(false)?;
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::core_sched::{CORE_SCHED_ANNOTATION, CORE_SCHED_GROUP_ANNOTATION};
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+use oci_spec::runtime::{LinuxNamespaceType, Spec};
+use serial_test::serial;
+use tempfile::tempdir;
+
+const PR_SCHED_CORE: libc::c_int = 62;
+const PR_SCHED_CORE_GET: libc::c_ulong = 0;
+const PIDTYPE_PID: libc::c_ulong = 0;
+
+/// Returns the core scheduling cookie of the process, 0 for none, or None
+/// without core scheduling support
+fn cookie(pid: Pid) -> Option<u64> {
+    let mut cookie: u64 = 0;
+    let ret = unsafe {
+        libc::prctl(
+            PR_SCHED_CORE,
+            PR_SCHED_CORE_GET,
+            pid.as_raw() as libc::c_ulong,
+            PIDTYPE_PID,
+            &mut cookie as *mut u64 as libc::c_ulong,
+        )
+    };
+    (ret == 0).then_some(cookie)
+}
+
+/// Exits successfully if the container process has the `expected` cookie
+#[derive(Clone)]
+struct CookieExecutor {
+    expected: Option<u64>,
+}
+
+impl Executor for CookieExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let shared = match self.expected {
+            Some(expected) => cookie(Pid::from_raw(0)) == Some(expected),
+            None => true,
+        };
+        std::process::exit(if shared { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+fn prepare_container_root(root: impl AsRef<Path>, annotation: (&str, &str)) -> Result<()> {
+    let mut spec = rootless_spec();
+    // The pid of the group init differs in the pid namespace of the container.
+    let namespaces = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.namespaces().clone())
+        .unwrap_or_default();
+    assert!(namespaces
+        .iter()
+        .any(|ns| ns.typ() == LinuxNamespaceType::Pid && ns.path().is_none()));
+    spec.set_annotations(Some(HashMap::from([(
+        annotation.0.to_owned(),
+        annotation.1.to_owned(),
+    )])));
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn core_sched_group_with_pid_namespace() -> Result<()> {
+    // EINVAL without CONFIG_SCHED_CORE, ENODEV without SMT
+    if cookie(Pid::from_raw(0)).is_none() {
+        eprintln!("skipping, no core scheduling support");
+        return Ok(());
+    }
+
+    let group_root = tempdir()?;
+    prepare_container_root(&group_root, (CORE_SCHED_ANNOTATION, "true"))?;
+    let group = ContainerBuilder::new("test-core-sched-group".to_owned(), SyscallType::Linux)
+        .with_root_path(group_root.as_ref())?
+        .with_executor(CookieExecutor { expected: None })
+        .as_init(group_root.as_ref())
+        .build()?;
+    let group = scopeguard::guard(group, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let group_cookie = cookie(group.pid().unwrap()).unwrap();
+    assert_ne!(group_cookie, 0, "the group init has no cookie");
+
+    // The member is created with the root path of the group, so it finds it.
+    let root = tempdir()?;
+    prepare_container_root(
+        &root,
+        (CORE_SCHED_GROUP_ANNOTATION, "test-core-sched-group"),
+    )?;
+    let container = ContainerBuilder::new("test-core-sched-member".to_owned(), SyscallType::Linux)
+        .with_root_path(group_root.as_ref())?
+        .with_executor(CookieExecutor {
+            expected: Some(group_cookie),
+        })
+        .as_init(root.as_ref())
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    assert_eq!(cookie(init_pid), Some(group_cookie));
+    container.start()?;
+
+    let status = waitpid(init_pid, None)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "the container process doesn't share the cookie of the group: {status:?}"
+    );
+
+    Ok(())
+}
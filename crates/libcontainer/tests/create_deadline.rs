@@ -0,0 +1,72 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::hooks::CREATE_DEADLINE_ENV;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::{HookBuilder, HooksBuilder};
+use serial_test::serial;
+use tempfile::tempdir;
+
+const CREATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Prepares a container whose create_runtime hook writes the deadline it got
+/// to `deadline` in the root
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let root = root.as_ref();
+    let mut spec = rootless_spec();
+    let hook = HookBuilder::default()
+        .path("/bin/sh")
+        .args(vec![
+            "sh".to_owned(),
+            "-c".to_owned(),
+            format!(
+                "echo \"${CREATE_DEADLINE_ENV}\" > {}",
+                root.join("deadline").display()
+            ),
+        ])
+        .build()?;
+    spec.set_hooks(Some(
+        HooksBuilder::default().create_runtime(vec![hook]).build()?,
+    ));
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn create_runtime_hook_gets_deadline() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-create-deadline".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_handshake_only(true)
+        .with_create_timeout(CREATE_TIMEOUT)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+
+    // The hook runs after the container processes started, so some of the
+    // timeout is already used up.
+    let deadline_ms: u128 = fs::read_to_string(root.path().join("deadline"))?
+        .trim()
+        .parse()?;
+    assert!(deadline_ms > 0);
+    assert!(deadline_ms <= CREATE_TIMEOUT.as_millis());
+
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "fault_injection")]
+
+mod common;
+
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{fs, thread};
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::create_limit::set_create_concurrency_limit;
+use libcontainer::fault_injection::{Fault, FaultInjection, FaultPoint};
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Directory of the markers of the creates in the limited section, seen
+/// from the containers. All containers share the rootfs of the bundle.
+const INSIDE_DIR: &str = "/inside";
+/// Directory the number of creates each create saw in the section is
+/// written to
+const SEEN_DIR: &str = "/seen";
+
+/// Marks the create as inside the limited section while its init process
+/// validates the spec, which the calling process waits for with the permit
+/// held, and records how many creates were inside at the same time.
+#[derive(Clone)]
+struct InsideExecutor {
+    id: String,
+}
+
+impl Executor for InsideExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        std::process::exit(0)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        let err =
+            |err: std::io::Error| ExecutorValidationError::ArgValidationError(err.to_string());
+        let marker = Path::new(INSIDE_DIR).join(&self.id);
+        fs::write(&marker, "").map_err(err)?;
+        let inside = fs::read_dir(INSIDE_DIR).map_err(err)?.count();
+        thread::sleep(Duration::from_millis(200));
+        fs::remove_file(&marker).map_err(err)?;
+        fs::write(Path::new(SEEN_DIR).join(&self.id), inside.to_string()).map_err(err)?;
+        Ok(())
+    }
+}
+
+/// Creates and deletes a container. The fault is injected at the start of
+/// the section of the create the limit applies to, with the permit held.
+fn build(root: &Path, id: &str, fault: Fault) -> Result<()> {
+    let mut container = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root)?
+        .with_executor(InsideExecutor { id: id.to_owned() })
+        .with_fault_injection(FaultInjection::default().with_fault(FaultPoint::PreClone, fault))
+        .as_init(root)
+        .build()?;
+    let _ = container.delete(true);
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn parallel_creates_are_limited() -> Result<()> {
+    const LIMIT: usize = 2;
+    const CREATES: usize = 6;
+
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let rootfs = root.path().join("rootfs");
+    fs::create_dir(rootfs.join(INSIDE_DIR.trim_start_matches('/')))?;
+    fs::create_dir(rootfs.join(SEEN_DIR.trim_start_matches('/')))?;
+    set_create_concurrency_limit(Some(LIMIT));
+    scopeguard::defer!(set_create_concurrency_limit(None));
+
+    let handles: Vec<_> = (0..CREATES)
+        .map(|i| {
+            let root = root.path().to_owned();
+            thread::spawn(move || {
+                build(
+                    &root,
+                    &format!("test-limit-{i}"),
+                    Fault::Delay(Duration::ZERO),
+                )
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+
+    // The last create to enter the section sees the markers of all creates
+    // inside with it, so the largest count is the most creates that were
+    // inside at once.
+    let mut seen = Vec::new();
+    for entry in fs::read_dir(rootfs.join(SEEN_DIR.trim_start_matches('/')))? {
+        seen.push(fs::read_to_string(entry?.path())?.parse::<usize>()?);
+    }
+    assert_eq!(seen.len(), CREATES, "not every create reached the section");
+    let max_inside = seen.into_iter().max().unwrap();
+    assert!(
+        max_inside <= LIMIT,
+        "{max_inside} creates were inside the section at once, the limit is {LIMIT}"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn failed_create_releases_its_permit() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    set_create_concurrency_limit(Some(1));
+    scopeguard::defer!(set_create_concurrency_limit(None));
+
+    let result = build(root.as_ref(), "test-limit-failed", Fault::Fail);
+    assert!(
+        result.is_err(),
+        "the injected failure didn't fail the create"
+    );
+
+    // The next create only gets the single permit if the failed one gave it
+    // back, otherwise it waits forever.
+    let (sender, receiver) = mpsc::channel();
+    let next_root = root.path().to_owned();
+    thread::spawn(move || {
+        let result = build(&next_root, "test-limit-next", Fault::Delay(Duration::ZERO));
+        let _ = sender.send(result);
+    });
+    match receiver.recv_timeout(Duration::from_secs(30)) {
+        Ok(result) => result?,
+        Err(_) => panic!("the create after a failed one didn't get the permit"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if /dev/null and /dev/zero work as expected
+#[derive(Clone)]
+struct DevNullExecutor {}
+
+fn devices_work() -> std::io::Result<bool> {
+    OpenOptions::new()
+        .write(true)
+        .open("/dev/null")?
+        .write_all(b"discarded")?;
+    let mut zeros = [1u8; 16];
+    File::open("/dev/zero")?.read_exact(&mut zeros)?;
+    Ok(zeros == [0u8; 16])
+}
+
+impl Executor for DevNullExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let code = if devices_work().unwrap_or(false) {
+            0
+        } else {
+            1
+        };
+        std::process::exit(code)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a container whose spec lists no devices
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let mut spec = rootless_spec();
+    if let Some(linux) = spec.linux_mut() {
+        linux.set_devices(None);
+    }
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn default_devices_without_spec_devices() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-default-devices".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(DevNullExecutor {})
+        .as_init(root.as_ref())
+        .with_ensure_default_devices(true)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{dup, dup2, pipe, read};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if reading stdin returns EOF right away. An empty pipe
+/// would block, so stdin is read non-blocking.
+#[derive(Clone)]
+struct StdinEofExecutor {}
+
+impl Executor for StdinEofExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let _ = fcntl(0, FcntlArg::F_SETFL(OFlag::O_NONBLOCK));
+        let mut buf = [0u8; 1];
+        let eof = matches!(read(0, &mut buf), Ok(0));
+        std::process::exit(if eof { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Creates a detached container while the stdin of the test is an empty pipe
+/// that is never closed, like a terminal nobody types in, and returns how the
+/// container exited.
+fn run_with_pipe_stdin(root: &Path, id: &str, null_stdin: bool) -> Result<WaitStatus> {
+    let (read_end, _write_end) = pipe()?;
+    let saved_stdin = dup(0)?;
+    dup2(read_end.as_raw_fd(), 0)?;
+    let container = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root)?
+        .with_executor(StdinEofExecutor {})
+        .as_init(root)
+        .with_detach(true)
+        .with_detached_null_stdin(null_stdin)
+        .build();
+    dup2(saved_stdin, 0)?;
+    nix::unistd::close(saved_stdin)?;
+
+    let mut container = scopeguard::guard(container?, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    Ok(waitpid(init_pid, None)?)
+}
+
+#[test]
+#[serial]
+fn detached_stdin_reads_eof_by_default() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let id = "test-detached-null-stdin";
+    let status = run_with_pipe_stdin(root.as_ref(), id, true)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "stdin of the container didn't read EOF: {status:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn detached_stdin_inherited_when_disabled() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let id = "test-detached-inherited-stdin";
+    let status = run_with_pipe_stdin(root.as_ref(), id, false)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 1)),
+        "stdin of the container wasn't inherited: {status:?}"
+    );
+
+    Ok(())
+}
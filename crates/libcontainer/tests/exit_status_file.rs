@@ -0,0 +1,90 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::ExitStatus;
+use libcontainer::error::LibcontainerError;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+const EXIT_CODE: i32 = 3;
+
+#[derive(Clone)]
+struct ExitCodeExecutor {}
+
+impl Executor for ExitCodeExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        std::process::exit(EXIT_CODE)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+#[test]
+#[serial]
+fn detached_container_writes_exit_status_file() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let exit_status_file = root.path().join("exit-status");
+
+    let container = ContainerBuilder::new("test-exit-status-file".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(ExitCodeExecutor {})
+        .as_init(root.as_ref())
+        .with_detach(true)
+        .with_exit_status_file(Some(&exit_status_file))?
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    // The init process isn't a child of the test, the waiter reaps it.
+    assert!(container.exit_waiter_pid().is_some());
+    assert!(!exit_status_file.exists());
+
+    container.start()?;
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !exit_status_file.exists() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+    let exit_status = ExitStatus::load(&exit_status_file)?;
+    assert_eq!(exit_status.exit_code, EXIT_CODE);
+    assert_eq!(exit_status.signal, 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn attached_container_rejects_exit_status_file() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let exit_status_file = root.path().join("exit-status");
+
+    // Nothing would reap the init process and write the file.
+    let result = ContainerBuilder::new("test-exit-status-attached".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(ExitCodeExecutor {})
+        .as_init(root.as_ref())
+        .with_detach(false)
+        .with_exit_status_file(Some(&exit_status_file))?
+        .build();
+    assert!(
+        matches!(result, Err(LibcontainerError::InvalidInput(_))),
+        "unexpected result: {:?}",
+        result.map(|container| container.id().to_owned())
+    );
+    assert!(!root.path().join("test-exit-status-attached").exists());
+
+    Ok(())
+}
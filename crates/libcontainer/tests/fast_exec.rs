@@ -0,0 +1,106 @@
+mod common;
+
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::{MountBuilder, Spec};
+use serial_test::serial;
+use tempfile::tempdir;
+
+const EXECS: usize = 100;
+
+/// Keeps the init process of the container running until it is killed
+#[derive(Clone)]
+struct IdleExecutor {}
+
+impl Executor for IdleExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        loop {
+            thread::sleep(Duration::from_secs(3600));
+        }
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a container with the binaries and libraries of the host mounted
+/// read only, so a tenant can run `/bin/true`
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let mut spec = rootless_spec();
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    for dir in ["/bin", "/lib", "/lib64", "/usr"] {
+        if !Path::new(dir).exists() {
+            continue;
+        }
+        mounts.push(
+            MountBuilder::default()
+                .destination(dir)
+                .typ("bind")
+                .source(dir)
+                .options(vec!["rbind".to_owned(), "ro".to_owned()])
+                .build()?,
+        );
+    }
+    spec.set_mounts(Some(mounts));
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+/// Runs `/bin/true` in the container `EXECS` times and returns the mean time
+/// from the build of the tenant to the exit of its process
+fn mean_exec_time(root: &Path, id: &str, fast_exec: bool) -> Result<Duration> {
+    let start = Instant::now();
+    for _ in 0..EXECS {
+        let pid = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+            .with_root_path(root)?
+            .as_tenant()
+            .with_container_args(vec!["/bin/true".to_owned()])
+            .fast_exec(fast_exec)
+            .build()?;
+        assert_eq!(waitpid(pid, None)?, WaitStatus::Exited(pid, 0));
+    }
+
+    Ok(start.elapsed() / EXECS as u32)
+}
+
+/// Compares the latency of the fast exec path with the full path. Timing
+/// depends on the host, so this only runs on request with
+/// `cargo test --test fast_exec -- --ignored --nocapture`.
+#[test]
+#[ignore]
+#[serial]
+fn fast_exec_latency() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let id = "test-fast-exec";
+    let container = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(IdleExecutor {})
+        .as_init(root.as_ref())
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    container.start()?;
+
+    // Warm up the page cache and the dentries of the container first.
+    mean_exec_time(root.as_ref(), id, true)?;
+    let full = mean_exec_time(root.as_ref(), id, false)?;
+    let fast = mean_exec_time(root.as_ref(), id, true)?;
+    println!("mean exec latency over {EXECS} execs: full path {full:?}, fast path {fast:?}");
+    assert!(
+        fast < full,
+        "the fast path ({fast:?}) isn't faster than the full path ({full:?})"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,236 @@
+#![cfg(feature = "fault_injection")]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::error::LibcontainerError;
+use libcontainer::fault_injection::{Fault, FaultInjection, FaultInjectionError, FaultPoint};
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+#[derive(Clone)]
+struct ExitExecutor {}
+
+impl Executor for ExitExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        std::process::exit(0)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    prepare_container_root_with_terminal(root, false)
+}
+
+fn prepare_container_root_with_terminal(root: impl AsRef<Path>, terminal: bool) -> Result<()> {
+    let mut spec = rootless_spec();
+    if let Some(process) = spec.process_mut() {
+        process.set_terminal(Some(terminal));
+    }
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+fn build(root: &Path, id: &str, fault_injection: FaultInjection) -> Result<(), LibcontainerError> {
+    let mut container = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root)?
+        .with_executor(ExitExecutor {})
+        .with_fault_injection(fault_injection)
+        .as_init(root)
+        .build()?;
+    let _ = container.delete(true);
+    Ok(())
+}
+
+/// Makes the test process the reaper of the processes of the containers
+/// whose parent died, so they can't escape [`live_children`].
+fn set_child_subreaper(subreaper: bool) -> Result<()> {
+    // SAFETY: PR_SET_CHILD_SUBREAPER takes no pointers.
+    nix::errno::Errno::result(unsafe {
+        libc::prctl(
+            libc::PR_SET_CHILD_SUBREAPER,
+            subreaper as libc::c_ulong,
+            0,
+            0,
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+/// Pids of the children of the test process that didn't exit. The children
+/// that did are reaped.
+fn live_children() -> Result<Vec<i32>> {
+    let own_pid = std::process::id().to_string();
+    let mut children = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // The process may be gone by now.
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // The state and the parent pid follow the command, which may contain
+        // spaces.
+        let mut fields = stat[stat.rfind(')').map_or(0, |end| end + 1)..].split_whitespace();
+        let (state, ppid) = (fields.next(), fields.next());
+        if ppid == Some(own_pid.as_str()) && state != Some("Z") {
+            children.push(pid);
+        }
+    }
+    while let Ok(status) = waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+        if status == WaitStatus::StillAlive {
+            break;
+        }
+    }
+
+    Ok(children)
+}
+
+/// Cgroups named after the container in any of the cgroup hierarchies
+fn cgroups_of(id: &str) -> Vec<PathBuf> {
+    let mut cgroups = Vec::new();
+    let mut dirs = vec![PathBuf::from("/sys/fs/cgroup")];
+    while let Some(dir) = dirs.pop() {
+        // The cgroups of other processes may be removed during the walk.
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        // Only directories are cgroups, the symlinks of co-mounted cgroup v1
+        // controllers aren't followed.
+        for entry in entries.flatten() {
+            if !entry.file_type().map_or(false, |ty| ty.is_dir()) {
+                continue;
+            }
+            if entry.file_name().to_string_lossy().contains(id) {
+                cgroups.push(entry.path());
+            }
+            dirs.push(entry.path());
+        }
+    }
+    cgroups
+}
+
+#[test]
+#[serial]
+fn failure_at_each_point_is_cleaned_up() -> Result<()> {
+    set_child_subreaper(true)?;
+    scopeguard::defer!(set_child_subreaper(false).unwrap());
+    for point in FaultPoint::ALL {
+        let root = tempdir()?;
+        prepare_container_root(&root)?;
+        let id = format!("test-fault-{point}");
+
+        let result = build(
+            root.as_ref(),
+            &id,
+            FaultInjection::default().with_fault(point, Fault::Fail),
+        );
+        assert!(result.is_err(), "create didn't fail at {point}");
+        // A failure in the calling process is reported as is, one in the
+        // container processes through the main process.
+        if matches!(
+            point,
+            FaultPoint::PreClone | FaultPoint::PostClone | FaultPoint::PreHooks
+        ) {
+            assert!(
+                matches!(
+                    result,
+                    Err(LibcontainerError::FaultInjection(
+                        FaultInjectionError::Injected(p)
+                    )) if p == point
+                ),
+                "unexpected error at {point}: {result:?}"
+            );
+        }
+        assert!(
+            !root.path().join(&id).exists(),
+            "container state left after a failure at {point}"
+        );
+        let children = live_children()?;
+        assert!(
+            children.is_empty(),
+            "processes {children:?} left after a failure at {point}"
+        );
+        let cgroups = cgroups_of(&id);
+        assert!(
+            cgroups.is_empty(),
+            "cgroups {cgroups:?} left after a failure at {point}"
+        );
+    }
+
+    Ok(())
+}
+
+fn open_fds() -> Result<usize> {
+    Ok(fs::read_dir("/proc/self/fd")?.count())
+}
+
+#[test]
+#[serial]
+fn failure_at_each_point_leaks_no_fds() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root_with_terminal(&root, true)?;
+    let failed_create = |id: &str, point: FaultPoint| -> Result<()> {
+        let result = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+            .with_root_path(root.as_ref())?
+            .with_executor(ExitExecutor {})
+            .with_fault_injection(FaultInjection::default().with_fault(point, Fault::Fail))
+            .as_init(root.as_ref())
+            .build_with_pty_master();
+        assert!(result.is_err(), "create didn't fail at {point}");
+        Ok(())
+    };
+
+    // Anything the process opens once and keeps is opened by the first one.
+    failed_create("test-fault-fds-first", FaultPoint::PreClone)?;
+    for point in FaultPoint::ALL {
+        let before = open_fds()?;
+        failed_create(&format!("test-fault-fds-{point}"), point)?;
+        // The pty master socket pair, the channels and the notify socket of
+        // the create are all closed.
+        assert_eq!(open_fds()?, before, "fds leaked by a failure at {point}");
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn delay_slows_down_the_create() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let delay = Duration::from_millis(500);
+
+    let start = Instant::now();
+    build(
+        root.as_ref(),
+        "test-fault-delay",
+        FaultInjection::default().with_fault(FaultPoint::PreHooks, Fault::Delay(delay)),
+    )?;
+    assert!(start.elapsed() >= delay);
+
+    Ok(())
+}
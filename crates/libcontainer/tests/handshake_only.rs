@@ -0,0 +1,39 @@
+mod common;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::ContainerStatus;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::sys::wait::{waitpid, WaitStatus};
+use serial_test::serial;
+use tempfile::tempdir;
+
+#[test]
+#[serial]
+fn create_and_start_handshake_only() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-handshake-only".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_handshake_only(true)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    assert_eq!(container.status(), ContainerStatus::Created);
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+
+    // The init process only exits successfully after it received the start
+    // signal over the notify socket, which completes the handshake.
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+    container.refresh_status()?;
+    assert_eq!(container.status(), ContainerStatus::Stopped);
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::fs::{self, create_dir};
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::hostname_file::{HOSTNAME_FILE_RESOLVED_ANNOTATION, RESOLVED_WRITE};
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+const HOSTNAME: &str = "youki-hostname-file";
+
+/// Exits successfully if /etc/hostname has the hostname of the container
+#[derive(Clone)]
+struct HostnameExecutor {}
+
+impl Executor for HostnameExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let written = fs::read_to_string("/etc/hostname").unwrap_or_default();
+        std::process::exit(if written == format!("{HOSTNAME}\n") {
+            0
+        } else {
+            1
+        })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a rootless spec with a uts namespace of its own, whose image has
+/// an /etc/hostname of the hostname it was built with
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let root = root.as_ref();
+    let mut spec = rootless_spec();
+    spec.set_hostname(Some(HOSTNAME.to_owned()));
+    prepare_container_root_with_spec(root, spec)?;
+
+    create_dir(root.join("rootfs/etc"))?;
+    fs::write(root.join("rootfs/etc/hostname"), "image-builder\n")?;
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn hostname_file_has_the_effective_hostname() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-hostname-file".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(HostnameExecutor {})
+        .as_init(root.as_ref())
+        .with_hostname_file(true)
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+
+    let resolved = container.create_descriptor()?.spec;
+    assert_eq!(
+        resolved
+            .annotations()
+            .as_ref()
+            .and_then(|annotations| annotations.get(HOSTNAME_FILE_RESOLVED_ANNOTATION))
+            .map(String::as_str),
+        Some(RESOLVED_WRITE)
+    );
+
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    let status = waitpid(init_pid, None)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "/etc/hostname doesn't have the hostname of the container: {status:?}"
+    );
+
+    Ok(())
+}
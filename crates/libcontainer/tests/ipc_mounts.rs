@@ -0,0 +1,106 @@
+mod common;
+
+use std::ffi::CString;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+const QUEUE_NAME: &str = "/youki-test";
+
+/// Exits successfully if a POSIX message queue can be created and shows up
+/// in `/dev/mqueue`.
+#[derive(Clone)]
+struct MessageQueueExecutor {}
+
+impl Executor for MessageQueueExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let name = CString::new(QUEUE_NAME).unwrap();
+        let mqd = unsafe {
+            libc::mq_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_RDWR,
+                0o600 as libc::mode_t,
+                std::ptr::null::<libc::mq_attr>(),
+            )
+        };
+        let created = mqd != -1;
+        let visible = Path::new("/dev/mqueue")
+            .join(QUEUE_NAME.trim_start_matches('/'))
+            .exists();
+        std::process::exit(if created && visible { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a rootless spec, which has a new IPC namespace, without the
+/// `/dev/mqueue` mount, so it only exists if the builder adds it.
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let mut spec = rootless_spec();
+    let mounts = spec.mounts().clone().map(|mounts| {
+        mounts
+            .into_iter()
+            .filter(|mount| mount.destination() != Path::new("/dev/mqueue"))
+            .collect()
+    });
+    spec.set_mounts(mounts);
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+fn run(root: &Path, id: &str, mqueue_mount: bool) -> Result<WaitStatus> {
+    let container = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root)?
+        .with_executor(MessageQueueExecutor {})
+        .as_init(root)
+        .with_mqueue_mount(mqueue_mount)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    Ok(waitpid(init_pid, None)?)
+}
+
+#[test]
+#[serial]
+fn mqueue_mounted_by_default() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let status = run(root.as_ref(), "test-mqueue-mount", true)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "message queue wasn't created in /dev/mqueue: {status:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn mqueue_not_mounted_when_disabled() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let status = run(root.as_ref(), "test-no-mqueue-mount", false)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 1)),
+        "/dev/mqueue was mounted: {status:?}"
+    );
+
+    Ok(())
+}
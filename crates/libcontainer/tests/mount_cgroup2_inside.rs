@@ -0,0 +1,81 @@
+mod common;
+
+use std::fs::{self, create_dir};
+use std::path::Path;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcgroups::common::{CgroupSetup, DEFAULT_CGROUP_ROOT};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::geteuid;
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if the container sees its own cgroup as the root of
+/// `/sys/fs/cgroup` and can create a sub-cgroup in it.
+#[derive(Clone)]
+struct NestedCgroupExecutor {}
+
+impl Executor for NestedCgroupExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let cgroup_root = Path::new(DEFAULT_CGROUP_ROOT);
+        // In the cgroup namespace the own cgroup is the root, and the init
+        // process is the only process in it.
+        let own_cgroup = fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+        let procs = fs::read_to_string(cgroup_root.join("cgroup.procs")).unwrap_or_default();
+        let sub_cgroup = cgroup_root.join("nested");
+        let nested = fs::create_dir(&sub_cgroup).is_ok() && fs::remove_dir(&sub_cgroup).is_ok();
+
+        let ok = own_cgroup.trim() == "0::/" && procs.trim() == "1" && nested;
+        std::process::exit(if ok { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// A rootless container can only get a cgroup of its own from the systemd
+/// user instance, on the unified hierarchy.
+fn rootless_delegation_available() -> bool {
+    !geteuid().is_root()
+        && std::env::var_os("XDG_RUNTIME_DIR").is_some()
+        && matches!(
+            libcgroups::common::get_cgroup_setup(),
+            Ok(CgroupSetup::Unified)
+        )
+}
+
+#[test]
+#[serial]
+fn rootless_container_manages_own_cgroup() -> Result<()> {
+    if !rootless_delegation_available() {
+        eprintln!("skipping, no delegated cgroup for a rootless container");
+        return Ok(());
+    }
+
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container =
+        ContainerBuilder::new("test-mount-cgroup2-inside".to_owned(), SyscallType::Linux)
+            .with_root_path(root.as_ref())?
+            .with_executor(NestedCgroupExecutor {})
+            .as_init(root.as_ref())
+            .with_mount_cgroup2_inside(true)
+            .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
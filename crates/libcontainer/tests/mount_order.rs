@@ -0,0 +1,59 @@
+mod common;
+
+use std::path::Path;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::rootfs::MountOrder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if the devices and /dev symlinks are visible
+#[derive(Clone)]
+struct DevExecutor {}
+
+impl Executor for DevExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let visible = Path::new("/dev/null").exists()
+            && Path::new("/dev/ptmx").symlink_metadata().is_ok()
+            && Path::new("/dev/fd").symlink_metadata().is_ok();
+        std::process::exit(if visible { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+#[test]
+#[serial]
+fn devices_visible_with_mounts_after_pivot() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-mount-after-pivot".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(DevExecutor {})
+        .as_init(root.as_ref())
+        .with_mount_order(MountOrder::AfterPivot)
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+
+    let status = waitpid(init_pid, None)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "the /dev mount hides the devices: {status:?}"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::sched::CloneFlags;
+use nix::sys::wait::{waitpid, WaitStatus};
+use serial_test::serial;
+use tempfile::tempdir;
+
+#[test]
+#[serial]
+fn create_reports_new_namespace_flags() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let (container, result) =
+        ContainerBuilder::new("test-namespace-flags".to_owned(), SyscallType::Linux)
+            .with_root_path(root.as_ref())?
+            .as_init(root.as_ref())
+            .with_handshake_only(true)
+            .build_with_result()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+
+    // the rootless spec requests new mount and pid namespaces
+    assert!(result
+        .namespace_flags
+        .contains(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID));
+
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{getpid, getsid};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if the container process leads its session as
+/// `expected`
+#[derive(Clone)]
+struct SessionExecutor {
+    expected: bool,
+}
+
+impl Executor for SessionExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let leader = getsid(None).ok() == Some(getpid());
+        std::process::exit(if leader == self.expected { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+fn run(id: &str, new_session: bool) -> Result<WaitStatus> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(SessionExecutor {
+            expected: new_session,
+        })
+        .as_init(root.as_ref())
+        .with_new_session(new_session)
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+
+    Ok(waitpid(init_pid, None)?)
+}
+
+#[test]
+#[serial]
+fn init_leads_a_new_session() -> Result<()> {
+    let status = run("test-new-session", true)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "the init process isn't a session leader: {status:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn init_stays_in_the_session_of_the_caller() -> Result<()> {
+    let status = run("test-no-new-session", false)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "the init process started a new session: {status:?}"
+    );
+
+    Ok(())
+}
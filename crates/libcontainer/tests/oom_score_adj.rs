@@ -0,0 +1,61 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use serial_test::serial;
+use tempfile::tempdir;
+
+fn oom_score_adj() -> Result<i32> {
+    Ok(fs::read_to_string("/proc/self/oom_score_adj")?
+        .trim()
+        .parse()?)
+}
+
+fn prepare_container_root(root: impl AsRef<Path>, score: i32) -> Result<()> {
+    let mut spec = rootless_spec();
+    spec.process_mut()
+        .as_mut()
+        .unwrap()
+        .set_oom_score_adj(Some(score));
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn create_restores_oom_score_adj() -> Result<()> {
+    let previous = oom_score_adj()?;
+    // Raising the score is permitted without CAP_SYS_RESOURCE, and so is
+    // lowering it back.
+    let score = (previous + 100).min(1000);
+    if score == previous {
+        eprintln!("oom_score_adj is already at its maximum, skipping");
+        return Ok(());
+    }
+
+    let root = tempdir()?;
+    prepare_container_root(&root, score)?;
+
+    let container = ContainerBuilder::new("test-oom-score-adj".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .build()?;
+    let container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+
+    // The container got the score of the spec, the runtime kept its own.
+    let init_pid = container.pid().unwrap();
+    let container_score: i32 = fs::read_to_string(format!("/proc/{init_pid}/oom_score_adj"))?
+        .trim()
+        .parse()?;
+    assert_eq!(container_score, score);
+    assert_eq!(oom_score_adj()?, previous);
+
+    Ok(())
+}
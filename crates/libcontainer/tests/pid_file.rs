@@ -0,0 +1,55 @@
+mod common;
+
+use std::fs;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use serial_test::serial;
+use tempfile::tempdir;
+
+#[test]
+#[serial]
+fn create_reports_pid_file_contents() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let pid_file = root.path().join("container.pid");
+
+    let (container, result) = ContainerBuilder::new("test-pid-file".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_pid_file(Some(&pid_file))?
+        .as_init(root.as_ref())
+        .build_with_result()?;
+    let _container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+
+    // The reported contents are what is on disk, and name the init process.
+    assert!(result.pid_file_written);
+    assert_eq!(result.pid_file, Some(fs::read_to_string(&pid_file)?));
+    assert_eq!(result.pid_file, Some(result.pid.to_string()));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn create_without_pid_file_reports_none() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let (container, result) =
+        ContainerBuilder::new("test-no-pid-file".to_owned(), SyscallType::Linux)
+            .with_root_path(root.as_ref())?
+            .as_init(root.as_ref())
+            .build_with_result()?;
+    let _container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+
+    assert!(!result.pid_file_written);
+    assert_eq!(result.pid_file, None);
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::fs;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcgroups::common::CgroupSetup;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, geteuid, ForkResult};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if the init process, the only process of the
+/// container, can't fork.
+#[derive(Clone)]
+struct ForkLimitedExecutor {}
+
+impl Executor for ForkLimitedExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let limited = match unsafe { fork() } {
+            Ok(ForkResult::Child) => std::process::exit(0),
+            Ok(ForkResult::Parent { child }) => {
+                let _ = waitpid(child, None);
+                false
+            }
+            Err(errno) => errno == Errno::EAGAIN,
+        };
+        std::process::exit(if limited { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// A rootless container can only get a cgroup of its own from the systemd
+/// user instance, on the unified hierarchy.
+fn rootless_delegation_available() -> bool {
+    !geteuid().is_root()
+        && std::env::var_os("XDG_RUNTIME_DIR").is_some()
+        && matches!(
+            libcgroups::common::get_cgroup_setup(),
+            Ok(CgroupSetup::Unified)
+        )
+}
+
+#[test]
+#[serial]
+fn pids_max_override_limits_forks() -> Result<()> {
+    if !rootless_delegation_available() {
+        eprintln!("skipping, no delegated cgroup for a rootless container");
+        return Ok(());
+    }
+
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-pids-max-override".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(ForkLimitedExecutor {})
+        .as_init(root.as_ref())
+        .with_pids_max_override(Some(1))
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    // The limit is in place before the workload runs.
+    let cgroup_path = container.cgroup_path().unwrap().to_owned();
+    assert_eq!(
+        fs::read_to_string(cgroup_path.join("pids.max"))?.trim(),
+        "1"
+    );
+
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
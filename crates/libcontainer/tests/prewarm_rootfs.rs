@@ -0,0 +1,97 @@
+mod common;
+
+use std::fs::{create_dir, File, Permissions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+use serial_test::serial;
+use tempfile::tempdir;
+
+const ENTRYPOINT: &str = "/bin/entrypoint";
+
+/// Prepares a container whose entrypoint is a file in the rootfs that is not
+/// in the page cache
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let root = root.as_ref();
+    let mut spec = rootless_spec();
+    spec.process_mut()
+        .as_mut()
+        .unwrap()
+        .set_args(Some(vec![ENTRYPOINT.to_owned()]));
+    prepare_container_root_with_spec(root, spec)?;
+
+    create_dir(root.join("rootfs/bin"))?;
+    let mut entrypoint = File::create(root.join("rootfs").join(&ENTRYPOINT[1..]))?;
+    entrypoint.write_all(&vec![0x5a; 1 << 20])?;
+    // The default executor only accepts an executable entrypoint.
+    entrypoint.set_permissions(Permissions::from_mode(0o755))?;
+    // Only clean pages are dropped from the page cache.
+    entrypoint.sync_all()?;
+    posix_fadvise(
+        entrypoint.as_raw_fd(),
+        0,
+        0,
+        PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+    )?;
+
+    Ok(())
+}
+
+/// Number of pages of the file that are in the page cache
+fn resident_pages(path: &Path) -> Result<usize> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    let page_size =
+        nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?.unwrap_or(4096) as usize;
+    let mut vec = vec![0u8; (len + page_size - 1) / page_size];
+    // SAFETY: the mapping is only passed to mincore and unmapped again.
+    unsafe {
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED);
+        let ret = libc::mincore(addr, len, vec.as_mut_ptr());
+        libc::munmap(addr, len);
+        assert_eq!(ret, 0);
+    }
+    Ok(vec.iter().filter(|page| *page & 1 != 0).count())
+}
+
+#[test]
+#[serial]
+fn create_prewarms_entrypoint() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let entrypoint = root.path().join("rootfs").join(&ENTRYPOINT[1..]);
+    let cold = resident_pages(&entrypoint)?;
+
+    let container = ContainerBuilder::new("test-prewarm-rootfs".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_prewarm_rootfs(true)
+        .build()?;
+    let _container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+
+    // Read ahead is asynchronous and the cache may be under pressure, so
+    // residency is only checked best effort.
+    let warm = resident_pages(&entrypoint)?;
+    if warm <= cold {
+        eprintln!("entrypoint is not more resident after the create: {cold} -> {warm} pages");
+    }
+
+    Ok(())
+}
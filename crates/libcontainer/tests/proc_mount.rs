@@ -0,0 +1,111 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::init_builder::ProcMountPolicy;
+use libcontainer::container::Container;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{getpid, Pid};
+use oci_spec::runtime::{LinuxNamespaceBuilder, LinuxNamespaceType, Spec};
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if /proc of the container shows the pids of the pid
+/// namespace it joined: its own pid through /proc/self, and the init
+/// process of the namespace as pid 1
+#[derive(Clone)]
+struct JoinedProcExecutor {}
+
+impl Executor for JoinedProcExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let pid = getpid();
+        let self_pid = fs::read_link("/proc/self")
+            .ok()
+            .and_then(|link| link.to_str()?.parse::<i32>().ok());
+        let joined =
+            pid.as_raw() != 1 && self_pid == Some(pid.as_raw()) && Path::new("/proc/1").exists();
+        std::process::exit(if joined { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a rootless spec, joining the user and pid namespaces of
+/// `joined` if set
+fn prepare_container_root(root: impl AsRef<Path>, joined: Option<Pid>) -> Result<()> {
+    let mut spec = rootless_spec();
+    if let Some(pid) = joined {
+        let linux = spec.linux_mut().as_mut().unwrap();
+        let namespaces = linux
+            .namespaces()
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|ns| match ns.typ() {
+                LinuxNamespaceType::User | LinuxNamespaceType::Pid => {
+                    let name = if ns.typ() == LinuxNamespaceType::User {
+                        "user"
+                    } else {
+                        "pid"
+                    };
+                    LinuxNamespaceBuilder::default()
+                        .typ(ns.typ())
+                        .path(format!("/proc/{pid}/ns/{name}"))
+                        .build()
+                }
+                _ => Ok(ns),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        linux.set_namespaces(Some(namespaces));
+    }
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn proc_shows_the_joined_pid_namespace() -> Result<()> {
+    // The init process of a created container holds its pid namespace until
+    // the container is started or deleted.
+    let holder_root = tempdir()?;
+    prepare_container_root(&holder_root, None)?;
+    let holder = ContainerBuilder::new("test-proc-holder".to_owned(), SyscallType::Linux)
+        .with_root_path(holder_root.as_ref())?
+        .with_executor(JoinedProcExecutor {})
+        .as_init(holder_root.as_ref())
+        .build()?;
+    let holder = scopeguard::guard(holder, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let holder_pid = holder.pid().unwrap();
+
+    let root = tempdir()?;
+    prepare_container_root(&root, Some(holder_pid))?;
+    let container = ContainerBuilder::new("test-proc-joined".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(JoinedProcExecutor {})
+        .as_init(root.as_ref())
+        .with_proc_mount_policy(ProcMountPolicy::BindShared)
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+
+    let status = waitpid(init_pid, None)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "/proc doesn't show the joined pid namespace: {status:?}"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if writing to /proc/sys fails because it is mounted
+/// read-only.
+#[derive(Clone)]
+struct ProcSysWriteExecutor {}
+
+impl Executor for ProcSysWriteExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        // The domainname belongs to the uts namespace of the container, so
+        // only the read-only mount can prevent writing it.
+        let result = OpenOptions::new()
+            .write(true)
+            .open("/proc/sys/kernel/domainname");
+        let code = match result {
+            Err(err) if err.raw_os_error() == Some(libc::EROFS) => 0,
+            _ => 1,
+        };
+        std::process::exit(code)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let mut spec = rootless_spec();
+    // Make sure only the builder option makes /proc/sys read-only
+    if let Some(linux) = spec.linux_mut() {
+        linux.set_readonly_paths(None);
+    }
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn proc_sys_is_readonly() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-proc-sys-readonly".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(ProcSysWriteExecutor {})
+        .as_init(root.as_ref())
+        .with_proc_sys_readonly(true)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
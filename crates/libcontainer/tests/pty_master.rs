@@ -0,0 +1,146 @@
+mod common;
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::{MountBuilder, Spec};
+use serial_test::serial;
+use tempfile::tempdir;
+
+const GREETING: &str = "hello from the container";
+
+/// Writes a greeting to the terminal of the container.
+#[derive(Clone)]
+struct GreetingExecutor {}
+
+impl Executor for GreetingExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let written =
+            writeln!(std::io::stdout(), "{GREETING}").and_then(|_| std::io::stdout().flush());
+        std::process::exit(if written.is_ok() { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let mut spec = rootless_spec();
+    if let Some(process) = spec.process_mut() {
+        process.set_terminal(Some(true));
+    }
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn init_writes_to_returned_pty_master() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let (container, _, pty_master) =
+        ContainerBuilder::new("test-pty-master".to_owned(), SyscallType::Linux)
+            .with_root_path(root.as_ref())?
+            .with_executor(GreetingExecutor {})
+            .as_init(root.as_ref())
+            .build_with_pty_master()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+
+    // Reading fails with EIO once the container closed the terminal.
+    let mut master = File::from(pty_master);
+    let mut output = Vec::new();
+    let mut buf = [0u8; 256];
+    while !String::from_utf8_lossy(&output).contains(GREETING) {
+        match master.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    assert!(
+        String::from_utf8_lossy(&output).contains(GREETING),
+        "unexpected terminal output {:?}",
+        String::from_utf8_lossy(&output)
+    );
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn pty_master_conflicts_with_console_socket() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let result = ContainerBuilder::new("test-pty-master-socket".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_console_socket(Some(root.path().join("console.sock")))
+        .with_executor(GreetingExecutor {})
+        .as_init(root.as_ref())
+        .build_with_pty_master();
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+fn open_fds() -> Result<usize> {
+    Ok(fs::read_dir("/proc/self/fd")?.count())
+}
+
+#[test]
+#[serial]
+fn failed_create_leaks_no_fds() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    // The mount fails in the init process, after the pty was set up.
+    let config = root.path().join("config.json");
+    let mut spec = Spec::load(&config)?;
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    mounts.push(
+        MountBuilder::default()
+            .destination("/missing")
+            .typ("bind")
+            .source(root.path().join("missing"))
+            .options(vec!["bind".to_owned()])
+            .build()?,
+    );
+    spec.set_mounts(Some(mounts));
+    spec.save(&config)?;
+
+    let failed_create = |id: &str| -> Result<()> {
+        let result = ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+            .with_root_path(root.as_ref())?
+            .with_executor(GreetingExecutor {})
+            .as_init(root.as_ref())
+            .build_with_pty_master();
+        assert!(result.is_err());
+        Ok(())
+    };
+    // Anything the process opens once and keeps is opened by the first one.
+    failed_create("test-pty-master-failed-first")?;
+    let before = open_fds()?;
+    failed_create("test-pty-master-failed")?;
+
+    // The pty master socket pair, the channels and the notify socket of the
+    // create are all closed.
+    assert_eq!(open_fds()?, before);
+
+    Ok(())
+}
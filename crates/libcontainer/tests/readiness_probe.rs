@@ -0,0 +1,128 @@
+mod common;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::{Container, ContainerStatus};
+use libcontainer::error::LibcontainerError;
+use libcontainer::readiness_probe::{ReadinessCheck, ReadinessProbe, ReadinessProbeError};
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+const READY_FILE: &str = "/ready";
+
+/// Creates the ready file after a while if `ready` is set, and then waits to
+/// be killed.
+#[derive(Clone)]
+struct SlowStartExecutor {
+    ready: bool,
+}
+
+impl Executor for SlowStartExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        std::thread::sleep(Duration::from_millis(100));
+        if self.ready {
+            let _ = std::fs::write(READY_FILE, "");
+        }
+        loop {
+            nix::unistd::pause();
+        }
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+fn create(root: &Path, id: &str, ready: bool, timeout: Duration) -> Result<Container> {
+    let probe = ReadinessProbe::new(ReadinessCheck::FileExists {
+        path: READY_FILE.into(),
+    })
+    .with_timeout(timeout)
+    .with_backoff(Duration::from_millis(10), Duration::from_millis(50));
+    Ok(ContainerBuilder::new(id.to_owned(), SyscallType::Linux)
+        .with_root_path(root)?
+        .with_executor(SlowStartExecutor { ready })
+        .as_init(root)
+        .with_readiness_probe(Some(probe))
+        .build()?)
+}
+
+#[test]
+#[serial]
+fn start_waits_for_readiness_probe() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = create(
+        root.as_ref(),
+        "test-readiness-probe",
+        true,
+        Duration::from_secs(10),
+    )?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    let start = Instant::now();
+    container.start()?;
+    // The workload takes a while to get ready, and start waited for it.
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(root.path().join("rootfs/ready").exists());
+    assert_eq!(container.status(), ContainerStatus::Running);
+
+    container.kill(Signal::SIGKILL, true)?;
+    assert_eq!(
+        waitpid(init_pid, None)?,
+        WaitStatus::Signaled(init_pid, Signal::SIGKILL, false)
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn start_fails_when_readiness_probe_times_out() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = create(
+        root.as_ref(),
+        "test-readiness-probe-timeout",
+        false,
+        Duration::from_millis(300),
+    )?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    let err = container.start().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            LibcontainerError::ReadinessProbe(ReadinessProbeError::Timeout { attempts, .. })
+                if attempts > 1
+        ),
+        "{err:?}"
+    );
+
+    // The init process was killed and the container never ran.
+    assert_eq!(
+        waitpid(init_pid, None)?,
+        WaitStatus::Signaled(init_pid, Signal::SIGKILL, false)
+    );
+    container.refresh_status()?;
+    assert_eq!(container.status(), ContainerStatus::Stopped);
+
+    Ok(())
+}
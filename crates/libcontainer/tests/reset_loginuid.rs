@@ -0,0 +1,76 @@
+mod common;
+
+use std::fs;
+
+use anyhow::Result;
+use common::prepare_container_root_with_spec;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::geteuid;
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+const LOGINUID_PATH: &str = "/proc/self/loginuid";
+const LOGINUID_UNSET: &str = "4294967295";
+
+/// Exits successfully if the loginuid of the container process is unset.
+#[derive(Clone)]
+struct LoginuidExecutor {}
+
+impl Executor for LoginuidExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let loginuid = fs::read_to_string(LOGINUID_PATH).unwrap_or_default();
+        std::process::exit(if loginuid.trim() == LOGINUID_UNSET {
+            0
+        } else {
+            1
+        })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Resetting the loginuid needs root and a kernel with audit support that
+/// doesn't have the loginuid locked. Writing back the current value checks all
+/// of them without changing it.
+fn audit_capable() -> bool {
+    geteuid().is_root()
+        && fs::read_to_string(LOGINUID_PATH)
+            .and_then(|loginuid| fs::write(LOGINUID_PATH, loginuid))
+            .is_ok()
+}
+
+#[test]
+#[serial]
+fn create_with_reset_loginuid() -> Result<()> {
+    if !audit_capable() {
+        eprintln!("skipping, resetting the loginuid is not supported");
+        return Ok(());
+    }
+
+    let root = tempdir()?;
+    prepare_container_root_with_spec(&root, Spec::default())?;
+
+    let container = ContainerBuilder::new("test-reset-loginuid".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(LoginuidExecutor {})
+        .as_init(root.as_ref())
+        .with_systemd(false)
+        .with_reset_loginuid(true)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
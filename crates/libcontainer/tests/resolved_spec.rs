@@ -0,0 +1,61 @@
+mod common;
+
+use std::fs;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::debug::ResolvedSpec;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Rejects every spec, so the create fails in the init process once the
+/// spec is resolved
+#[derive(Clone)]
+struct RejectingExecutor {}
+
+impl Executor for RejectingExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        std::process::exit(1)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Err(ExecutorValidationError::ArgValidationError(
+            "rejected".to_owned(),
+        ))
+    }
+}
+
+#[test]
+#[serial]
+fn failed_create_records_resolved_spec() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let env_file = root.path().join("job.env");
+    fs::write(&env_file, "FROM_ENV_FILE=1\n")?;
+
+    let resolved_spec = ResolvedSpec::default();
+    let result = ContainerBuilder::new("test-resolved-spec".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(RejectingExecutor {})
+        .with_env_file(&env_file)
+        .as_init(root.as_ref())
+        .with_resolved_spec(Some(resolved_spec.clone()))
+        .build();
+    assert!(result.is_err(), "create with a rejected spec succeeded");
+
+    // The env of the env files is merged into the recorded spec.
+    let spec = resolved_spec.get().expect("no resolved spec was recorded");
+    let env = spec.process().as_ref().unwrap().env().clone().unwrap();
+    assert!(env.contains(&"FROM_ENV_FILE=1".to_owned()), "{env:?}");
+    // The rootfs is resolved against the bundle.
+    assert_eq!(
+        spec.root().as_ref().unwrap().path(),
+        &fs::canonicalize(root.path().join("rootfs"))?
+    );
+
+    Ok(())
+}
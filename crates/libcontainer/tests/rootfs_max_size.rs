@@ -0,0 +1,115 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// Exits successfully if a small write to the root succeeds and a write
+/// beyond the maximum size fails with ENOSPC
+#[derive(Clone)]
+struct MaxSizeExecutor {}
+
+impl Executor for MaxSizeExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let small = fs::write("/small", "youki").is_ok();
+        let chunk = vec![0u8; 64 * 1024];
+        let mut large = fs::File::create("/large")
+            .map_err(|err| ExecutorError::Other(format!("failed to create /large: {err}")))?;
+        let mut limited = false;
+        for _ in 0..(2 * MAX_SIZE / chunk.len() as u64) {
+            if let Err(err) = large.write_all(&chunk).and_then(|_| large.sync_all()) {
+                limited = err.raw_os_error() == Some(libc::ENOSPC);
+                break;
+            }
+        }
+        std::process::exit(if small && limited { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Rootless overlay mounts on tmpfs need Linux 5.11
+fn tmpfs_layer_capable() -> bool {
+    let filesystems = fs::read_to_string("/proc/filesystems").unwrap_or_default();
+    let supported = |fs_type: &str| {
+        filesystems
+            .lines()
+            .any(|line| line.split_whitespace().last() == Some(fs_type))
+    };
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    let mut version = release
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse::<u32>().ok());
+    let version = (version.next().unwrap_or(0), version.next().unwrap_or(0));
+
+    supported("tmpfs") && supported("overlay") && version >= (5, 11)
+}
+
+#[test]
+#[serial]
+fn rootfs_max_size_bounds_writes() -> Result<()> {
+    if !tmpfs_layer_capable() {
+        eprintln!("skipping, no overlay on tmpfs for a rootless container");
+        return Ok(());
+    }
+
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-rootfs-max-size".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(MaxSizeExecutor {})
+        .as_init(root.as_ref())
+        .with_rootfs_max_size(Some(MAX_SIZE))
+        .build()?;
+    let mut container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+
+    let status = waitpid(init_pid, None)?;
+    assert!(
+        matches!(status, WaitStatus::Exited(_, 0)),
+        "writes beyond the rootfs max size didn't fail: {status:?}"
+    );
+    // the writes went to the tmpfs, not the image
+    assert!(!root.path().join("rootfs/small").exists());
+    assert!(!root.path().join("rootfs/large").exists());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn rootfs_max_size_of_zero_is_rejected() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let result = ContainerBuilder::new("test-rootfs-max-size-zero".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_rootfs_max_size(Some(0))
+        .build();
+
+    if let Ok(mut container) = result {
+        let _ = container.delete(true);
+        anyhow::bail!("a tmpfs of size 0 is unlimited and should be rejected");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::sys::wait::{waitpid, WaitStatus};
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Returns the bytes of the names and data of the entries below `dir`, as
+/// the accounting counts them
+fn entry_bytes(dir: &Path) -> Result<u64> {
+    let mut bytes = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+        bytes += entry.file_name().len() as u64;
+        if metadata.is_dir() {
+            bytes += entry_bytes(&entry.path())?;
+        } else {
+            bytes += metadata.len();
+        }
+    }
+    Ok(bytes)
+}
+
+#[test]
+#[serial]
+fn rootfs_writes_are_reported() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let (container, result) =
+        ContainerBuilder::new("test-rootfs-writes".to_owned(), SyscallType::Linux)
+            .with_root_path(root.as_ref())?
+            .as_init(root.as_ref())
+            .with_handshake_only(true)
+            .build_with_result()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+
+    // The mount targets created in the empty rootfs count, the devices and
+    // symlinks in the tmpfs on /dev don't. The mounts of the container are
+    // not visible from here, so everything counted must be in the rootfs.
+    let written = result.rootfs_written.unwrap_or_default();
+    let on_rootfs = entry_bytes(&root.path().join("rootfs"))?;
+    assert!(written > 0, "no write was counted");
+    assert!(
+        written <= on_rootfs,
+        "{written} bytes were counted, but only {on_rootfs} are in the rootfs"
+    );
+
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn rootfs_write_limit_fails_create() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let result = ContainerBuilder::new("test-rootfs-write-limit".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_handshake_only(true)
+        .with_rootfs_write_limit(Some(1))
+        .build();
+
+    if let Ok(mut container) = result {
+        let _ = container.delete(true);
+        anyhow::bail!("the create should fail on the write limit");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::utils::is_in_new_userns;
+use nix::unistd::geteuid;
+use oci_spec::runtime::LinuxNamespaceType;
+use serial_test::serial;
+use tempfile::tempdir;
+
+fn prepare_container_root(root: impl AsRef<Path>, user_ns: bool) -> Result<()> {
+    let mut spec = rootless_spec();
+    if !user_ns {
+        let linux = spec.linux_mut().as_mut().unwrap();
+        let namespaces = linux.namespaces().clone().map(|namespaces| {
+            namespaces
+                .into_iter()
+                .filter(|ns| ns.typ() != LinuxNamespaceType::User)
+                .collect()
+        });
+        linux.set_namespaces(namespaces);
+    }
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+/// Real root outside of a user namespace of its own
+fn real_root() -> Result<bool> {
+    Ok(geteuid().is_root() && !is_in_new_userns()?)
+}
+
+#[test]
+#[serial]
+fn rootless_with_user_namespace() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root, true)?;
+    let expected = !real_root()?;
+
+    let builder = ContainerBuilder::new("test-rootless".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref());
+    assert_eq!(builder.is_rootless()?, expected);
+
+    let container = builder.build()?;
+    let container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    assert_eq!(container.is_rootless(), expected);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn not_rootless_without_user_namespace() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root, false)?;
+
+    // Only the decision is checked, a runtime that isn't root can't create
+    // the container.
+    let builder = ContainerBuilder::new("test-not-rootless".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref());
+    assert!(!builder.is_rootless()?);
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{getgid, getuid};
+use oci_spec::runtime::{Spec, UserBuilder};
+use serial_test::serial;
+use tempfile::tempdir;
+
+/// Exits successfully if the container process runs as root.
+#[derive(Clone)]
+struct RootUserExecutor {}
+
+impl Executor for RootUserExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let is_root = getuid().is_root() && getgid().as_raw() == 0;
+        std::process::exit(if is_root { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let mut spec = rootless_spec();
+    // Only root is mapped in the rootless user namespace, so the container
+    // can only start if the forced user replaces this one.
+    if let Some(process) = spec.process_mut() {
+        process.set_user(UserBuilder::default().uid(1000_u32).gid(1000_u32).build()?);
+    }
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn init_runs_as_forced_user() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-run-as-user".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(RootUserExecutor {})
+        .as_init(root.as_ref())
+        .with_run_as_user(Some((0, 0)))
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
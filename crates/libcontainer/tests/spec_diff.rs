@@ -0,0 +1,76 @@
+mod common;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::spec_diff::{subsystem, DiffOp};
+use libcontainer::syscall::syscall::SyscallType;
+use oci_spec::runtime::Spec;
+use serde_json::json;
+use serial_test::serial;
+use tempfile::tempdir;
+
+#[test]
+#[serial]
+fn spec_diff_attributes_adjustments() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-spec-diff".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_env(HashMap::from([("SPEC_DIFF".to_owned(), "1".to_owned())]))
+        .with_spec_provenance(true)
+        .build()?;
+    let container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+
+    let diff = container.spec_diff()?;
+    let rootfs = diff
+        .iter()
+        .find(|entry| entry.path == "/root/path")
+        .expect("the rootfs isn't resolved against the bundle");
+    assert_eq!(rootfs.op, DiffOp::Changed);
+    assert_eq!(rootfs.old, Some(json!("rootfs")));
+    assert_eq!(rootfs.subsystem.as_deref(), Some(subsystem::BUNDLE));
+
+    let env = diff
+        .iter()
+        .find(|entry| entry.new == Some(json!("SPEC_DIFF=1")))
+        .expect("the explicit env isn't in the diff");
+    assert!(env.path.starts_with("/process/env/"), "{env:?}");
+    assert_eq!(env.op, DiffOp::Added);
+    assert_eq!(env.subsystem.as_deref(), Some(subsystem::ENV));
+
+    // the spec of the bundle isn't touched
+    let bundle = Spec::load(root.path().join("config.json"))?;
+    assert_eq!(bundle.root().as_ref().unwrap().path(), Path::new("rootfs"));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn spec_diff_requires_provenance() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-spec-diff-off".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .build()?;
+    let container = scopeguard::guard(container, |mut container: Container| {
+        let _ = container.delete(true);
+    });
+
+    // the provenance isn't recorded by default
+    assert!(!container.root.join("spec_provenance.json").exists());
+    assert!(container.spec_diff().is_err());
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use common::{prepare_container_root_with_spec, rootless_spec};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::{HookBuilder, HooksBuilder, Spec};
+use serial_test::serial;
+use tempfile::tempdir;
+
+const MARKER_ENV: &str = "YOUKI_SPEC_MARKER";
+
+/// Exits successfully if the process got the env of the original spec
+#[derive(Clone)]
+struct MarkerExecutor {}
+
+impl Executor for MarkerExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        let original = std::env::var(MARKER_ENV).as_deref() == Ok("original");
+        std::process::exit(if original { 0 } else { 1 })
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+/// Prepares a container whose create_runtime hook rewrites the marker env
+/// in the config.json of the bundle while the container is created
+fn prepare_container_root(root: impl AsRef<Path>) -> Result<()> {
+    let root = root.as_ref();
+    let mut spec = rootless_spec();
+    let process = spec.process_mut().as_mut().unwrap();
+    let mut env = process.env().clone().unwrap_or_default();
+    env.push(format!("{MARKER_ENV}=original"));
+    process.set_env(Some(env));
+    let hook = HookBuilder::default()
+        .path("/bin/sh")
+        .args(vec![
+            "sh".to_owned(),
+            "-c".to_owned(),
+            format!(
+                "sed -i s/{MARKER_ENV}=original/{MARKER_ENV}=rewritten/ {}",
+                root.join("config.json").display()
+            ),
+        ])
+        .build()?;
+    spec.set_hooks(Some(
+        HooksBuilder::default().create_runtime(vec![hook]).build()?,
+    ));
+
+    prepare_container_root_with_spec(root, spec)
+}
+
+#[test]
+#[serial]
+fn rewritten_config_is_not_applied() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-spec-snapshot".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .with_executor(MarkerExecutor {})
+        .as_init(root.as_ref())
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    assert!(fs::read_to_string(root.path().join("config.json"))?
+        .contains(&format!("{MARKER_ENV}=rewritten")));
+
+    // The spec was read once, before the bundle was rewritten.
+    let init_pid = container.pid().unwrap();
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "syscall_trace")]
+
+mod common;
+
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::io::AsRawFd;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
+use nix::sys::wait::{waitpid, WaitStatus};
+use oci_spec::runtime::Spec;
+use serial_test::serial;
+use tempfile::tempdir;
+
+#[derive(Clone)]
+struct ExitExecutor {}
+
+impl Executor for ExitExecutor {
+    fn exec(&self, _spec: &Spec) -> Result<(), ExecutorError> {
+        std::process::exit(0)
+    }
+
+    fn validate(&self, _spec: &Spec) -> Result<(), ExecutorValidationError> {
+        Ok(())
+    }
+}
+
+#[test]
+#[serial]
+fn trace_contains_pivot_root_before_execve() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+    let mut trace_file = tempfile::tempfile()?;
+
+    let container = ContainerBuilder::new(
+        "test-syscall-trace".to_owned(),
+        SyscallType::Trace(trace_file.as_raw_fd()),
+    )
+    .with_root_path(root.as_ref())?
+    .with_executor(ExitExecutor {})
+    .as_init(root.as_ref())
+    .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    let init_pid = container.pid().unwrap();
+
+    container.start()?;
+    assert_eq!(waitpid(init_pid, None)?, WaitStatus::Exited(init_pid, 0));
+
+    let mut trace = String::new();
+    trace_file.seek(SeekFrom::Start(0))?;
+    trace_file.read_to_string(&mut trace)?;
+    let position = |call: &str| {
+        trace
+            .lines()
+            .position(|line| line.contains(&format!("] {call}(")))
+    };
+    let pivot_root = position("pivot_root").expect("pivot_root was traced");
+    let execve = position("execve").expect("execve was traced");
+    assert!(pivot_root < execve, "{trace}");
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::fs;
+
+use anyhow::Result;
+use common::prepare_container_root;
+use libcgroups::common::CgroupSetup;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::ContainerStatus;
+use libcontainer::error::LibcontainerError;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitStatus};
+use serial_test::serial;
+use tempfile::tempdir;
+
+#[test]
+#[serial]
+fn unmanaged_cgroups_lifecycle() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let container = ContainerBuilder::new("test-unmanaged-cgroups".to_owned(), SyscallType::Linux)
+        .with_root_path(root.as_ref())?
+        .as_init(root.as_ref())
+        .with_cgroup_setup(CgroupSetup::Unmanaged)
+        .build()?;
+
+    let mut container = scopeguard::guard(container, |mut container| {
+        let _ = container.delete(true);
+    });
+    assert_eq!(container.status(), ContainerStatus::Created);
+    assert!(container.unmanaged_cgroups());
+    let init_pid = container.pid().unwrap();
+
+    // The init process stays in the cgroup of the caller.
+    assert_eq!(
+        fs::read_to_string(format!("/proc/{init_pid}/cgroup"))?,
+        fs::read_to_string("/proc/self/cgroup")?
+    );
+
+    let err = container.pause().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            LibcontainerError::UnsupportedWithoutCgroups { operation: "pause" }
+        ),
+        "{err:?}"
+    );
+    let err = container.resume().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            LibcontainerError::UnsupportedWithoutCgroups {
+                operation: "resume"
+            }
+        ),
+        "{err:?}"
+    );
+
+    // Without a cgroup to list the processes, the init process is found by
+    // its pid.
+    container.kill(Signal::SIGKILL, true)?;
+    assert_eq!(
+        waitpid(init_pid, None)?,
+        WaitStatus::Signaled(init_pid, Signal::SIGKILL, false)
+    );
+    container.refresh_status()?;
+    assert_eq!(container.status(), ContainerStatus::Stopped);
+
+    let mut container = scopeguard::ScopeGuard::into_inner(container);
+    container.delete(false)?;
+    assert!(!root.path().join("test-unmanaged-cgroups").exists());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn unmanaged_cgroups_reject_cgroup_options() -> Result<()> {
+    let root = tempdir()?;
+    prepare_container_root(&root)?;
+
+    let err = ContainerBuilder::new(
+        "test-unmanaged-cgroups-options".to_owned(),
+        SyscallType::Linux,
+    )
+    .with_root_path(root.as_ref())?
+    .as_init(root.as_ref())
+    .with_cgroup_setup(CgroupSetup::Unmanaged)
+    .with_nested_cgroup_delegation(true)
+    .build()
+    .unwrap_err();
+    assert!(matches!(err, LibcontainerError::InvalidInput(_)), "{err:?}");
+    assert!(!root.path().join("test-unmanaged-cgroups-options").exists());
+
+    Ok(())
+}
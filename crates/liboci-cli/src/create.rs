@@ -26,6 +26,23 @@ pub struct Create {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     pub preserve_fds: i32,
+    /// Print the time spent in each phase of the create
+    #[clap(long)]
+    pub timing: bool,
+    /// File to write the exit status of the container process to, once it exits
+    #[clap(long)]
+    pub exit_status_file: Option<PathBuf>,
+    /// Env file to layer the env of the process on, can be specified multiple
+    /// times, later files override earlier ones
+    #[clap(long, number_of_values = 1)]
+    pub env_file: Vec<PathBuf>,
+    /// Directory to write a debug bundle to if the create fails
+    #[clap(long)]
+    pub debug_bundle_on_failure: Option<PathBuf>,
+    /// Record which part of the runtime changed which value of the spec, for
+    /// `state --diff`
+    #[clap(long)]
+    pub spec_provenance: bool,
 
     /// Name of the container instance to be started
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
@@ -16,6 +16,10 @@ pub struct Exec {
     /// Environment variables that should be set in the container
     #[clap(short, long, value_parser = parse_env::<String, String>, number_of_values = 1)]
     pub env: Vec<(String, String)>,
+    /// Env file to layer the environment variables on, can be specified multiple
+    /// times, later files override earlier ones
+    #[clap(long, number_of_values = 1)]
+    pub env_file: Vec<PathBuf>,
     #[clap(short, long)]
     pub tty: bool,
     /// Run the command as a user
@@ -54,6 +58,14 @@ pub struct Exec {
     /// Execute a process in a sub-cgroup
     #[clap(long)]
     pub cgroup: Option<String>,
+    /// Record the process under this id, so it can be signaled with
+    /// `kill --exec-id`
+    #[clap(long)]
+    pub exec_id: Option<String>,
+    /// Start the process without the full setup of the runtime if the
+    /// container allows it
+    #[clap(long)]
+    pub fast_exec: bool,
 
     /// Identifier of the container
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
@@ -1,4 +1,6 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
 
 /// Return the features list for a container
 /// This subcommand was introduced in runc by
@@ -6,4 +8,26 @@ use clap::Parser;
 /// It is documented here:
 /// https://github.com/opencontainers/runtime-spec/blob/main/features-linux.md
 #[derive(Parser, Debug)]
-pub struct Features {}
+pub struct Features {
+    /// Seccomp profile (the `linux.seccomp` object of a spec) to print the
+    /// compiled filter of instead of the features list
+    #[clap(long)]
+    pub seccomp_profile: Option<PathBuf>,
+    /// format to dump the compiled seccomp filter in
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = SeccompDumpFormat::Pfc,
+        requires = "seccomp_profile"
+    )]
+    pub dump: SeccompDumpFormat,
+}
+
+/// Formats a compiled seccomp filter can be dumped in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeccompDumpFormat {
+    /// human readable pseudo filter code
+    Pfc,
+    /// raw BPF program as loaded into the kernel
+    Bpf,
+}
@@ -8,4 +8,7 @@ pub struct Kill {
     pub signal: String,
     #[clap(short, long)]
     pub all: bool,
+    /// Signal only the process exec'd with this id instead of the init process
+    #[clap(long, conflicts_with = "all")]
+    pub exec_id: Option<String>,
 }
@@ -33,7 +33,7 @@ mod update;
 pub use checkpoint::Checkpoint;
 pub use events::Events;
 pub use exec::Exec;
-pub use features::Features;
+pub use features::{Features, SeccompDumpFormat};
 pub use list::List;
 pub use pause::Pause;
 pub use ps::Ps;
@@ -2,7 +2,22 @@ use clap::Parser;
 
 /// Show the container state
 #[derive(Parser, Debug)]
+#[non_exhaustive]
 pub struct State {
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
     pub container_id: String,
+    /// Show the differences between the spec of the bundle and the spec the
+    /// container was created with instead, requires a container created with
+    /// `--spec-provenance`
+    #[clap(long)]
+    pub diff: bool,
+}
+
+impl State {
+    pub fn new(container_id: String) -> Self {
+        Self {
+            container_id,
+            diff: false,
+        }
+    }
 }
@@ -1,11 +1,17 @@
 //! Handles the creation of a new container
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Result;
 use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::{Container, CreateResult};
+use libcontainer::create_signals::CreateSignalPolicy;
+use libcontainer::debug::{DebugContext, RecentEvents, ResolvedSpec};
+use libcontainer::error::LibcontainerError;
 use libcontainer::syscall::syscall::SyscallType;
 use liboci_cli::Create;
 
+use super::{with_env_fault_injection, write_debug_bundle};
 use crate::workload::executor::default_executor;
 
 // One thing to note is that in the end, container is just another process in Linux
@@ -13,19 +19,101 @@ use crate::workload::executor::default_executor;
 // can be given impression that is is running on a complete system, but on the system which
 // it is running, it is just another process, and has attributes such as pid, file descriptors, etc.
 // associated with it like any other process.
-pub fn create(args: Create, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
-    ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
-        .with_executor(default_executor())
-        .with_pid_file(args.pid_file.as_ref())?
-        .with_console_socket(args.console_socket.as_ref())
-        .with_root_path(root_path)?
-        .with_preserved_fds(args.preserve_fds)
-        .validate_id()?
-        .as_init(&args.bundle)
-        .with_systemd(systemd_cgroup)
-        .with_detach(true)
-        .with_no_pivot(args.no_pivot)
-        .build()?;
+pub fn create(
+    args: Create,
+    root_path: PathBuf,
+    systemd_cgroup: bool,
+    recent_events: Option<RecentEvents>,
+) -> Result<()> {
+    // The resolved spec is only recorded for the debug bundle.
+    let resolved_spec = args
+        .debug_bundle_on_failure
+        .is_some()
+        .then(ResolvedSpec::default);
+    let (_, result) =
+        build(&args, root_path, systemd_cgroup, resolved_spec.clone()).map_err(|err| {
+            let ctx = DebugContext {
+                container_id: args.container_id.clone(),
+                bundle: Some(args.bundle.clone()),
+                resolved_spec: resolved_spec.and_then(|spec| spec.get()),
+                recent_events,
+            };
+            write_debug_bundle(args.debug_bundle_on_failure.as_deref(), &ctx, &err);
+            err
+        })?;
+
+    if args.timing {
+        print_timings(&result);
+    }
 
     Ok(())
 }
+
+fn build(
+    args: &Create,
+    root_path: PathBuf,
+    systemd_cgroup: bool,
+    resolved_spec: Option<ResolvedSpec>,
+) -> Result<(Container, CreateResult), LibcontainerError> {
+    with_env_fault_injection(args.env_file.iter().fold(
+        ContainerBuilder::new(args.container_id.clone(), SyscallType::default()),
+        ContainerBuilder::with_env_file,
+    ))?
+    .with_executor(default_executor())
+    .with_pid_file(args.pid_file.as_ref())?
+    .with_console_socket(args.console_socket.as_ref())
+    .with_root_path(root_path)?
+    .with_preserved_fds(args.preserve_fds)
+    .validate_id()?
+    .as_init(&args.bundle)
+    .with_systemd(systemd_cgroup)
+    .with_detach(true)
+    // Like runc, the container gets the stdin youki was started with,
+    // which the caller sets up for it.
+    .with_detached_null_stdin(false)
+    .with_exit_status_file(args.exit_status_file.as_ref())?
+    .with_spec_provenance(args.spec_provenance)
+    .with_no_pivot(args.no_pivot)
+    .with_create_signal_policy(CreateSignalPolicy::Cleanup)
+    .with_resolved_spec(resolved_spec)
+    .build_with_result()
+}
+
+fn print_timings(result: &CreateResult) {
+    let timings = &result.timings;
+    let phases = [
+        ("Notify socket", Some(timings.notify_socket_setup)),
+        ("Clone", Some(timings.clone)),
+        ("Cgroup apply", timings.cgroup_apply),
+        ("Rootfs prepare", timings.rootfs_prepare),
+        ("Hooks", Some(timings.hooks)),
+        ("Total", Some(timings.total)),
+    ];
+    for (phase, duration) in phases {
+        match duration {
+            Some(duration) => println!("{:<18}{}", phase, format_duration(duration)),
+            None => println!("{:<18}-", phase),
+        }
+    }
+
+    if let Some(rusage) = &result.child_rusage {
+        println!(
+            "{:<18}{}",
+            "Child user time",
+            format_duration(rusage.user_time)
+        );
+        println!(
+            "{:<18}{}",
+            "Child system time",
+            format_duration(rusage.system_time)
+        );
+        println!("{:<18}{} kB", "Child max RSS", rusage.max_rss_kb);
+    }
+    if let Some(rootfs_written) = result.rootfs_written {
+        println!("{:<18}{} B", "Rootfs written", rootfs_written);
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
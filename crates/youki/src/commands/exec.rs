@@ -6,7 +6,7 @@ use libcontainer::syscall::syscall::SyscallType;
 use liboci_cli::Exec;
 use nix::sys::wait::{waitpid, WaitStatus};
 
-use crate::workload::executor::default_executor;
+use crate::commands::{load_container, with_env_fault_injection};
 
 pub fn exec(args: Exec, root_path: PathBuf) -> Result<i32> {
     // TODO: not all values from exec are used here. We need to support
@@ -14,9 +14,21 @@ pub fn exec(args: Exec, root_path: PathBuf) -> Result<i32> {
     let user = args.user.map(|(u, _)| u);
     let group = args.user.and_then(|(_, g)| g);
 
-    let pid = ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
-        .with_executor(default_executor())
-        .with_root_path(root_path)?
+    let builder = with_env_fault_injection(args.env_file.iter().fold(
+        ContainerBuilder::new(args.container_id.clone(), SyscallType::default()),
+        ContainerBuilder::with_env_file,
+    ))?;
+    // Without a wasm executor the executor of youki is the default one, which
+    // leaves the fast exec path to the process.
+    #[cfg(any(
+        feature = "wasm-wasmer",
+        feature = "wasm-wasmedge",
+        feature = "wasm-wasmtime"
+    ))]
+    let builder = builder.with_executor(crate::workload::executor::default_executor());
+
+    let pid = builder
+        .with_root_path(root_path.clone())?
         .with_console_socket(args.console_socket.as_ref())
         .with_pid_file(args.pid_file.as_ref())?
         .validate_id()?
@@ -30,6 +42,8 @@ pub fn exec(args: Exec, root_path: PathBuf) -> Result<i32> {
         .with_additional_gids(args.additional_gids)
         .with_user(user)
         .with_group(group)
+        .with_exec_id(args.exec_id.clone())
+        .fast_exec(args.fast_exec)
         .build()?;
 
     // See https://github.com/containers/youki/pull/1252 for a detailed explanation
@@ -40,9 +54,17 @@ pub fn exec(args: Exec, root_path: PathBuf) -> Result<i32> {
         return Ok(0);
     }
 
-    match waitpid(pid, None)? {
-        WaitStatus::Exited(_, status) => Ok(status),
-        WaitStatus::Signaled(_, sig, _) => Ok(sig as i32),
-        _ => Ok(0),
+    let status = match waitpid(pid, None)? {
+        WaitStatus::Exited(_, status) => status,
+        WaitStatus::Signaled(_, sig, _) => sig as i32,
+        _ => 0,
+    };
+    if let Some(exec_id) = &args.exec_id {
+        let container = load_container(root_path, &args.container_id)?;
+        if let Err(err) = container.remove_exec(exec_id) {
+            tracing::warn!(?err, ?exec_id, "failed to remove the exec session");
+        }
     }
+
+    Ok(status)
 }
@@ -1,11 +1,13 @@
 //! Contains Functionality of `features` container command
+use std::path::Path;
+
 use anyhow::Result;
 use libcontainer::oci_spec::runtime::{
     ApparmorBuilder, CgroupBuilder, FeaturesBuilder, IDMapBuilder, IntelRdtBuilder,
     LinuxFeatureBuilder, LinuxNamespaceType, MountExtensionsBuilder, SelinuxBuilder, VERSION,
 };
 use libcontainer::syscall::linux::MountOption;
-use liboci_cli::Features;
+use liboci_cli::{Features, SeccompDumpFormat};
 
 // Function to query and return capabilities
 fn query_caps() -> Result<Vec<String>> {
@@ -41,8 +43,42 @@ fn known_hooks() -> Vec<String> {
     .collect()
 }
 
+/// Prints the seccomp filter a profile compiles to, for auditing it without
+/// running a container
+#[cfg(feature = "seccomp")]
+fn dump_seccomp(profile: &Path, format: SeccompDumpFormat) -> Result<()> {
+    use std::io::Write;
+
+    use anyhow::Context;
+    use libcontainer::oci_spec::runtime::LinuxSeccomp;
+    use libcontainer::seccomp;
+
+    let file = std::fs::File::open(profile)
+        .with_context(|| format!("failed to open seccomp profile {profile:?}"))?;
+    let profile: LinuxSeccomp = serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("failed to parse seccomp profile {profile:?}"))?;
+
+    match format {
+        SeccompDumpFormat::Pfc => print!("{}", seccomp::compile_to_pfc(&profile)?),
+        SeccompDumpFormat::Bpf => {
+            std::io::stdout().write_all(&seccomp::compile_to_bpf_bytes(&profile)?)?
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "seccomp"))]
+fn dump_seccomp(_profile: &Path, _format: SeccompDumpFormat) -> Result<()> {
+    anyhow::bail!("youki was built without seccomp support")
+}
+
 /// lists all existing containers
-pub fn features(_: Features) -> Result<()> {
+pub fn features(args: Features) -> Result<()> {
+    if let Some(profile) = &args.seccomp_profile {
+        return dump_seccomp(profile, args.dump);
+    }
+
     // Query supported namespaces
     let namespaces = match query_supported_namespaces() {
         Ok(ns) => ns,
@@ -112,7 +148,36 @@ mod tests {
 
     #[test]
     fn test_features() {
-        let features = Features {};
+        let features = Features {
+            seccomp_profile: None,
+            dump: SeccompDumpFormat::Pfc,
+        };
+        assert!(crate::commands::features::features(features).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "seccomp")]
+    fn test_features_seccomp_dump() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let profile = tmp.path().join("seccomp.json");
+        std::fs::write(
+            &profile,
+            r#"{"defaultAction": "SCMP_ACT_ALLOW", "syscalls": [{"names": ["getcwd"], "action": "SCMP_ACT_ERRNO"}]}"#,
+        )?;
+
+        let features = Features {
+            seccomp_profile: Some(profile.clone()),
+            dump: SeccompDumpFormat::Pfc,
+        };
         assert!(crate::commands::features::features(features).is_ok());
+        // a bad profile fails instead of dumping nothing
+        std::fs::write(&profile, r#"{"defaultAction": "SCMP_ACT_NOPE"}"#)?;
+        let features = Features {
+            seccomp_profile: Some(profile),
+            dump: SeccompDumpFormat::Pfc,
+        };
+        assert!(crate::commands::features::features(features).is_err());
+
+        Ok(())
     }
 }
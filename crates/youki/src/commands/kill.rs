@@ -12,6 +12,11 @@ use crate::commands::load_container;
 pub fn kill(args: Kill, root_path: PathBuf) -> Result<()> {
     let mut container = load_container(root_path, &args.container_id)?;
     let signal: Signal = args.signal.as_str().try_into()?;
+    if let Some(exec_id) = &args.exec_id {
+        return container
+            .kill_exec(exec_id, signal)
+            .map_err(|e| anyhow!(e).context("failed to kill exec process"));
+    }
     match container.kill(signal, args.all) {
         Ok(_) => Ok(()),
         Err(e) => {
@@ -6,8 +6,7 @@ use std::{fs, io};
 
 use anyhow::Result;
 use chrono::{DateTime, Local};
-use libcontainer::container::state::State;
-use libcontainer::container::Container;
+use libcontainer::container::{default_state_store, Container};
 use liboci_cli::List;
 use tabwriter::TabWriter;
 
@@ -17,13 +16,7 @@ pub fn list(_: List, root_path: PathBuf) -> Result<()> {
     let mut content = String::new();
     // all containers' data is stored in their respective dir in root directory
     // so we iterate through each and print the various info
-    for container_dir in fs::read_dir(root_path)? {
-        let container_dir = container_dir?.path();
-        let state_file = State::file_path(&container_dir);
-        if !state_file.exists() {
-            continue;
-        }
-
+    for container_dir in default_state_store().list(&root_path)? {
         let container = Container::load(container_dir)?;
         let pid = if let Some(pid) = container.pid() {
             pid.to_string()
@@ -42,18 +35,22 @@ pub fn list(_: List, root_path: PathBuf) -> Result<()> {
 
         let _ = writeln!(
             content,
-            "{}\t{}\t{}\t{}\t{}\t{}",
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
             container.id(),
             pid,
             container.status(),
             container.bundle().display(),
             created,
-            user_name.to_string_lossy()
+            user_name.to_string_lossy(),
+            container.kept()
         );
     }
 
     let mut tab_writer = TabWriter::new(io::stdout());
-    writeln!(&mut tab_writer, "ID\tPID\tSTATUS\tBUNDLE\tCREATED\tCREATOR")?;
+    writeln!(
+        &mut tab_writer,
+        "ID\tPID\tSTATUS\tBUNDLE\tCREATED\tCREATOR\tKEPT"
+    )?;
     write!(&mut tab_writer, "{content}")?;
     tab_writer.flush()?;
 
@@ -3,7 +3,10 @@ use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
 use libcgroups::common::AnyCgroupManager;
+use libcontainer::container::builder::ContainerBuilder;
 use libcontainer::container::Container;
+use libcontainer::debug::{self, DebugContext};
+use libcontainer::error::LibcontainerError;
 
 pub mod checkpoint;
 pub mod completion;
@@ -24,6 +27,29 @@ pub mod start;
 pub mod state;
 pub mod update;
 
+/// Injects the faults of the hidden
+/// [`FAULT_INJECTION_ENV`](libcontainer::fault_injection::FAULT_INJECTION_ENV)
+/// env var into the create, for end-to-end tests of callers against a slow
+/// or failing runtime
+#[cfg(feature = "fault_injection")]
+fn with_env_fault_injection(
+    builder: ContainerBuilder,
+) -> Result<ContainerBuilder, LibcontainerError> {
+    use libcontainer::fault_injection::FaultInjection;
+
+    Ok(match FaultInjection::from_env()? {
+        Some(fault_injection) => builder.with_fault_injection(fault_injection),
+        None => builder,
+    })
+}
+
+#[cfg(not(feature = "fault_injection"))]
+fn with_env_fault_injection(
+    builder: ContainerBuilder,
+) -> Result<ContainerBuilder, LibcontainerError> {
+    Ok(builder)
+}
+
 fn construct_container_root<P: AsRef<Path>>(root_path: P, container_id: &str) -> Result<PathBuf> {
     // resolves relative paths, symbolic links etc. and get complete path
     let root_path = fs::canonicalize(&root_path).with_context(|| {
@@ -57,11 +83,31 @@ fn create_cgroup_manager<P: AsRef<Path>>(
     container_id: &str,
 ) -> Result<AnyCgroupManager> {
     let container = load_container(root_path, container_id)?;
+    let config = container.spec()?;
     Ok(libcgroups::common::create_cgroup_manager(
-        libcgroups::common::CgroupConfig {
-            cgroup_path: container.spec()?.cgroup_path,
-            systemd_cgroup: container.systemd(),
-            container_name: container.id().to_string(),
-        },
+        libcgroups::common::CgroupConfig::new(
+            config.cgroup_path,
+            container.systemd(),
+            container.id().to_string(),
+        )
+        .with_nested_delegation(config.nested_cgroup_delegation)
+        .with_setup(container.cgroup_setup()),
     )?)
 }
+
+/// Writes a debug bundle of a failed create to `dir`, if the user asked for
+/// one. A bundle that can't be written is only reported, as the error of the
+/// create is what the user has to see.
+fn write_debug_bundle(dir: Option<&Path>, ctx: &DebugContext, err: &LibcontainerError) {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return,
+    };
+    match debug::collect_bundle(dir, ctx, err) {
+        Ok(path) => eprintln!("debug bundle written to {}", path.display()),
+        Err(bundle_err) => {
+            tracing::warn!(?bundle_err, "failed to write debug bundle");
+            eprintln!("failed to write debug bundle: {bundle_err}");
+        }
+    }
+}
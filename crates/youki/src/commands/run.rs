@@ -2,6 +2,10 @@ use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::{Container, ExitStatus};
+use libcontainer::create_signals::CreateSignalPolicy;
+use libcontainer::debug::{DebugContext, RecentEvents, ResolvedSpec};
+use libcontainer::error::LibcontainerError;
 use libcontainer::syscall::syscall::SyscallType;
 use liboci_cli::Run;
 use nix::sys::signal::{self, kill};
@@ -9,21 +13,31 @@ use nix::sys::signalfd::SigSet;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 
+use super::{with_env_fault_injection, write_debug_bundle};
 use crate::workload::executor::default_executor;
 
-pub fn run(args: Run, root_path: PathBuf, systemd_cgroup: bool) -> Result<i32> {
-    let mut container = ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
-        .with_executor(default_executor())
-        .with_pid_file(args.pid_file.as_ref())?
-        .with_console_socket(args.console_socket.as_ref())
-        .with_root_path(root_path)?
-        .with_preserved_fds(args.preserve_fds)
-        .validate_id()?
-        .as_init(&args.bundle)
-        .with_systemd(systemd_cgroup)
-        .with_detach(args.detach)
-        .with_no_pivot(args.no_pivot)
-        .build()?;
+pub fn run(
+    args: Run,
+    root_path: PathBuf,
+    systemd_cgroup: bool,
+    recent_events: Option<RecentEvents>,
+) -> Result<i32> {
+    // The resolved spec is only recorded for the debug bundle.
+    let resolved_spec = args
+        .debug_bundle_on_failure
+        .is_some()
+        .then(ResolvedSpec::default);
+    let mut container =
+        build(&args, root_path, systemd_cgroup, resolved_spec.clone()).map_err(|err| {
+            let ctx = DebugContext {
+                container_id: args.container_id.clone(),
+                bundle: Some(args.bundle.clone()),
+                resolved_spec: resolved_spec.and_then(|spec| spec.get()),
+                recent_events,
+            };
+            write_debug_bundle(args.debug_bundle_on_failure.as_deref(), &ctx, &err);
+            err
+        })?;
 
     container
         .start()
@@ -41,10 +55,67 @@ pub fn run(args: Run, root_path: PathBuf, systemd_cgroup: bool) -> Result<i32> {
         "expects a container init pid in the container state"
     );
     let foreground_result = handle_foreground(container.pid().unwrap());
-    // execute the destruction action after the container finishes running
-    container.delete(true)?;
-    // return result
-    foreground_result
+    let exit_status = foreground_result
+        .as_ref()
+        .ok()
+        .and_then(|status| ExitStatus::from_wait_status(*status));
+    if args.keep {
+        // The stopped container stays with its state and cgroup until it is
+        // deleted or pruned.
+        container.refresh_status()?;
+        container
+            .set_kept(true)
+            .set_exit_status(exit_status.clone())
+            .save()?;
+    } else {
+        // execute the destruction action after the container finishes running
+        container.delete(true)?;
+    }
+    let status = foreground_result?;
+
+    // A detached container has its exit status file written by the exit
+    // waiter, in the foreground it is up to us.
+    if let (Some(exit_status_file), Some(exit_status)) = (&args.exit_status_file, &exit_status) {
+        exit_status.write(exit_status_file)?;
+    }
+
+    match status {
+        WaitStatus::Signaled(_, signal, _) => Ok(signal as i32),
+        WaitStatus::Exited(_, status) => Ok(status),
+        _ => unreachable!("handle_foreground only returns terminated processes"),
+    }
+}
+
+fn build(
+    args: &Run,
+    root_path: PathBuf,
+    systemd_cgroup: bool,
+    resolved_spec: Option<ResolvedSpec>,
+) -> Result<Container, LibcontainerError> {
+    with_env_fault_injection(args.env_file.iter().fold(
+        ContainerBuilder::new(args.container_id.clone(), SyscallType::default()),
+        ContainerBuilder::with_env_file,
+    ))?
+    .with_executor(default_executor())
+    .with_pid_file(args.pid_file.as_ref())?
+    .with_console_socket(args.console_socket.as_ref())
+    .with_root_path(root_path)?
+    .with_preserved_fds(args.preserve_fds)
+    .validate_id()?
+    .as_init(&args.bundle)
+    .with_systemd(systemd_cgroup)
+    .with_detach(args.detach)
+    // Like runc, the container gets the stdin youki was started with,
+    // which the caller sets up for it.
+    .with_detached_null_stdin(false)
+    // Only the exit waiter of a detached container writes the file, in the
+    // foreground it is written once the container exits.
+    .with_exit_status_file(args.exit_status_file.as_ref().filter(|_| args.detach))?
+    .with_spec_provenance(args.spec_provenance)
+    .with_no_pivot(args.no_pivot)
+    .with_create_signal_policy(CreateSignalPolicy::Cleanup)
+    .with_resolved_spec(resolved_spec)
+    .build()
 }
 
 // handle_foreground will match the `runc` behavior running the foreground mode.
@@ -52,7 +123,7 @@ pub fn run(args: Run, root_path: PathBuf, systemd_cgroup: bool) -> Result<i32> {
 // youki main process also forwards most of the signals to the container init
 // process.
 #[tracing::instrument(level = "trace")]
-fn handle_foreground(init_pid: Pid) -> Result<i32> {
+fn handle_foreground(init_pid: Pid) -> Result<WaitStatus> {
     tracing::trace!("waiting for container init process to exit");
     // We mask all signals here and forward most of the signals to the container
     // init process.
@@ -72,16 +143,16 @@ fn handle_foreground(init_pid: Pid) -> Result<i32> {
                 tracing::trace!("reaping child processes");
                 loop {
                     match waitpid(None, Some(WaitPidFlag::WNOHANG))? {
-                        WaitStatus::Exited(pid, status) => {
+                        status @ WaitStatus::Exited(pid, _) => {
                             if pid.eq(&init_pid) {
                                 return Ok(status);
                             }
 
                             // Else, some random child process exited, ignoring...
                         }
-                        WaitStatus::Signaled(pid, signal, _) => {
+                        status @ WaitStatus::Signaled(pid, _, _) => {
                             if pid.eq(&init_pid) {
-                                return Ok(signal as i32);
+                                return Ok(status);
                             }
 
                             // Else, some random child process exited, ignoring...
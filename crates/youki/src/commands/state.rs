@@ -7,6 +7,10 @@ use crate::commands::load_container;
 
 pub fn state(args: State, root_path: PathBuf) -> Result<()> {
     let container = load_container(root_path, &args.container_id)?;
-    println!("{}", serde_json::to_string_pretty(&container.state)?);
+    if args.diff {
+        println!("{}", serde_json::to_string_pretty(&container.spec_diff()?)?);
+    } else {
+        println!("{}", serde_json::to_string_pretty(&container.state)?);
+    }
     std::process::exit(0);
 }
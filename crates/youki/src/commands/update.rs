@@ -29,11 +29,6 @@ pub fn update(args: Update, root_path: PathBuf) -> Result<()> {
         linux_res = builder.build()?;
     }
 
-    cmanager.apply(&ControllerOpt {
-        resources: &linux_res,
-        disable_oom_killer: false,
-        oom_score_adj: None,
-        freezer_state: None,
-    })?;
+    cmanager.apply(&ControllerOpt::new(&linux_res))?;
     Ok(())
 }
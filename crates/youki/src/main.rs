@@ -6,12 +6,17 @@ mod observability;
 mod rootpath;
 mod workload;
 
+use std::sync::Arc;
+
 use anyhow::{Context, Result};
 use clap::{crate_version, CommandFactory, Parser};
+use libcontainer::container::{set_default_state_store, FileStateStore};
+use libcontainer::debug::RecentEvents;
 use libcontainer::syscall::syscall::create_syscall;
 use liboci_cli::{CommonCmd, GlobalOpts, StandardCmd};
 
 use crate::commands::info;
+use crate::observability::ObservabilityConfig;
 
 // Additional options that are not defined in OCI runtime-spec, but are used by Youki.
 #[derive(Parser, Debug)]
@@ -72,6 +77,20 @@ enum SubCommand {
     Completion(commands::completion::Completion),
 }
 
+fn wants_debug_bundle(subcmd: &SubCommand) -> bool {
+    match subcmd {
+        SubCommand::Standard(cmd) => matches!(
+            cmd.as_ref(),
+            StandardCmd::Create(create) if create.debug_bundle_on_failure.is_some()
+        ),
+        SubCommand::Common(cmd) => matches!(
+            cmd.as_ref(),
+            CommonCmd::Run(run) if run.debug_bundle_on_failure.is_some()
+        ),
+        _ => false,
+    }
+}
+
 /// This is the entry point in the container runtime. The binary is run by a high-level container runtime,
 /// with various flags passed. This parses the flags, creates and manages appropriate resources.
 fn main() -> Result<()> {
@@ -91,7 +110,11 @@ fn main() -> Result<()> {
     let mut app = Opts::command();
     let syscall = create_syscall();
 
-    observability::init(&opts).map_err(|err| {
+    // The recent events are only kept for the debug bundle of a failed create.
+    let recent_events = wants_debug_bundle(&opts.subcmd).then(RecentEvents::default);
+    let mut observability_config = ObservabilityConfig::from(&opts);
+    observability_config.recent_events = recent_events.clone();
+    observability::init(observability_config).map_err(|err| {
         eprintln!("failed to initialize observability: {}", err);
         err
     })?;
@@ -103,12 +126,15 @@ fn main() -> Result<()> {
     );
 
     let root_path = rootpath::determine(opts.global.root, &*syscall)?;
+    // The state is kept in files in the root path, which other tools and
+    // older versions of youki read.
+    set_default_state_store(Arc::new(FileStateStore));
     let systemd_cgroup = opts.global.systemd_cgroup;
 
     let cmd_result = match opts.subcmd {
         SubCommand::Standard(cmd) => match *cmd {
             StandardCmd::Create(create) => {
-                commands::create::create(create, root_path, systemd_cgroup)
+                commands::create::create(create, root_path, systemd_cgroup, recent_events)
             }
             StandardCmd::Start(start) => commands::start::start(start, root_path),
             StandardCmd::Kill(kill) => commands::kill::kill(kill, root_path),
@@ -133,14 +159,16 @@ fn main() -> Result<()> {
             CommonCmd::Pause(pause) => commands::pause::pause(pause, root_path),
             CommonCmd::Ps(ps) => commands::ps::ps(ps, root_path),
             CommonCmd::Resume(resume) => commands::resume::resume(resume, root_path),
-            CommonCmd::Run(run) => match commands::run::run(run, root_path, systemd_cgroup) {
-                Ok(exit_code) => std::process::exit(exit_code),
-                Err(e) => {
-                    tracing::error!("error in executing command: {:?}", e);
-                    eprintln!("run failed : {e}");
-                    std::process::exit(-1);
+            CommonCmd::Run(run) => {
+                match commands::run::run(run, root_path, systemd_cgroup, recent_events) {
+                    Ok(exit_code) => std::process::exit(exit_code),
+                    Err(e) => {
+                        tracing::error!("error in executing command: {:?}", e);
+                        eprintln!("run failed : {e}");
+                        std::process::exit(-1);
+                    }
                 }
-            },
+            }
             CommonCmd::Spec(spec) => commands::spec_json::spec(spec, &*syscall),
             CommonCmd::Update(update) => commands::update::update(update, root_path),
         },
@@ -4,8 +4,16 @@ use std::path::PathBuf;
 use std::str::FromStr;
 
 use anyhow::{bail, Context, Result};
-use tracing::Level;
+use libcontainer::container::log_level::LOG_LEVEL_SPAN_FIELD;
+use libcontainer::debug::RecentEvents;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{self, Layer};
 use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
 
 const LOG_FORMAT_TEXT: &str = "text";
 const LOG_FORMAT_JSON: &str = "json";
@@ -43,6 +51,96 @@ fn detect_log_level(input: Option<String>, is_debug: bool) -> Result<Level> {
     Ok(Level::from_str(log_level.as_ref())?)
 }
 
+/// Filters by the global log level, except inside the span of a container
+/// that has its own log level set with the `io.youki.log-level` annotation.
+/// Inside such a span, the more verbose of both levels applies.
+struct ContainerLevelFilter {
+    global: LevelFilter,
+}
+
+/// Log level of a container span, stored in the span extensions
+struct ContainerLevel(LevelFilter);
+
+/// Extracts the log level from the fields of a container span
+struct ContainerLevelVisitor(Option<LevelFilter>);
+
+impl Visit for ContainerLevelVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == LOG_LEVEL_SPAN_FIELD {
+            self.0 = LevelFilter::from_str(value).ok();
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+impl<S> Layer<S> for ContainerLevelFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        // Callsites above the global level depend on the span they are in.
+        if self.global >= *metadata.level() {
+            Interest::always()
+        } else {
+            Interest::sometimes()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: layer::Context<'_, S>) -> bool {
+        if self.global >= *metadata.level() {
+            return true;
+        }
+
+        ctx.lookup_current().map_or(false, |span| {
+            span.scope().any(|span| {
+                span.extensions()
+                    .get::<ContainerLevel>()
+                    .map_or(false, |level| level.0 >= *metadata.level())
+            })
+        })
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let mut visitor = ContainerLevelVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(level), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(ContainerLevel(level));
+        }
+    }
+}
+
+/// Keeps the events that pass the log level for the debug bundle of a failed
+/// create
+struct RecentEventsLayer(RecentEvents);
+
+/// Formats the fields of an event as `message key=value...`
+struct EventVisitor(String);
+
+impl Visit for EventVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0.push_str(&format!(" {value:?}"));
+        } else {
+            self.0.push_str(&format!(" {}={value:?}", field.name()));
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RecentEventsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = EventVisitor(format!(
+            "{} {} {}:",
+            chrono::Utc::now().to_rfc3339(),
+            metadata.level(),
+            metadata.target()
+        ));
+        event.record(&mut visitor);
+        self.0.push(visitor.0);
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct ObservabilityConfig {
     pub log_debug_flag: bool,
@@ -51,6 +149,8 @@ pub struct ObservabilityConfig {
     pub log_format: Option<String>,
     #[allow(dead_code)]
     pub systemd_log: bool,
+    /// Buffer the most recent events are kept in, if any
+    pub recent_events: Option<RecentEvents>,
 }
 
 impl From<&crate::Opts> for ObservabilityConfig {
@@ -61,6 +161,7 @@ impl From<&crate::Opts> for ObservabilityConfig {
             log_file: opts.global.log.to_owned(),
             log_format: opts.global.log_format.to_owned(),
             systemd_log: opts.youki_extend.systemd_log,
+            recent_events: None,
         }
     }
 }
@@ -72,7 +173,9 @@ where
     let config = config.into();
     let level = detect_log_level(config.log_level, config.log_debug_flag)
         .with_context(|| "failed to parse log level")?;
-    let log_level_filter = tracing_subscriber::filter::LevelFilter::from(level);
+    let log_level_filter = ContainerLevelFilter {
+        global: LevelFilter::from(level),
+    };
     let log_format = detect_log_format(config.log_format.as_deref())
         .with_context(|| "failed to detect log format")?;
 
@@ -96,7 +199,8 @@ where
     };
     let subscriber = tracing_subscriber::registry()
         .with(log_level_filter)
-        .with(systemd_journald);
+        .with(systemd_journald)
+        .with(config.recent_events.map(RecentEventsLayer));
 
     // I really dislike how we have to specify individual branch for each
     // combination, but I can't find any better way to do this. The tracing
@@ -274,6 +378,104 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_container_log_level() -> Result<()> {
+        libcontainer::test_utils::test_in_child_process(|| {
+            use libcontainer::container::log_level::container_span;
+            use libcontainer::container::{Container, ContainerLogLevel};
+
+            let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+            let log_file = Path::join(temp_dir.path(), "test.log");
+            let config = ObservabilityConfig {
+                log_file: Some(log_file.clone()),
+                log_level: Some("error".to_string()),
+                ..Default::default()
+            };
+            init(config).map_err(|err| TestCallbackError::Other(err.into()))?;
+
+            tracing::debug!("debug outside of a container");
+            {
+                let _span = container_span("verbose", Some(ContainerLogLevel::Debug)).entered();
+                tracing::debug!("debug in the verbose container");
+                tracing::trace!("trace in the verbose container");
+                tracing::debug_span!("nested").in_scope(|| {
+                    tracing::debug!("debug nested in the verbose container");
+                });
+            }
+            {
+                let _span = container_span("quiet", None).entered();
+                tracing::debug!("debug in the quiet container");
+            }
+            tracing::debug!("debug after the verbose container");
+
+            // Later operations use the level persisted in the container state.
+            let mut container = Container::default();
+            container.set_log_level(Some(ContainerLogLevel::Trace));
+            container.span().in_scope(|| {
+                tracing::trace!("trace in the loaded container");
+            });
+
+            let data = std::fs::read_to_string(&log_file)
+                .map_err(|err| format!("failed to read the logfile: {err:?}"))?;
+            for logged in [
+                "debug in the verbose container",
+                "debug nested in the verbose container",
+                "trace in the loaded container",
+            ] {
+                if !data.contains(logged) {
+                    Err(format!("{logged:?} should be logged, but got: {data}"))?;
+                }
+            }
+            for filtered in [
+                "debug outside of a container",
+                "trace in the verbose container",
+                "debug in the quiet container",
+                "debug after the verbose container",
+            ] {
+                if data.contains(filtered) {
+                    Err(format!(
+                        "{filtered:?} should not be logged, but got: {data}"
+                    ))?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent_events() -> Result<()> {
+        libcontainer::test_utils::test_in_child_process(|| {
+            let recent_events = RecentEvents::new(2);
+            let config = ObservabilityConfig {
+                log_level: Some("info".to_string()),
+                recent_events: Some(recent_events.clone()),
+                ..Default::default()
+            };
+            init(config).map_err(|err| TestCallbackError::Other(err.into()))?;
+
+            tracing::debug!("below the log level");
+            tracing::info!("first");
+            tracing::warn!(pid = 42, "second");
+            tracing::error!("third");
+
+            let events = recent_events.events();
+            if events.len() != 2
+                || !events[0].contains("WARN")
+                || !events[0].contains("second pid=42")
+                || !events[1].contains("third")
+            {
+                Err(format!("unexpected recent events: {events:?}"))?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_json_logfile() -> Result<()> {
         libcontainer::test_utils::test_in_child_process(|| {
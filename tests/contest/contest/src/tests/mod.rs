@@ -28,4 +28,5 @@ pub mod scheduler;
 pub mod seccomp;
 pub mod seccomp_notify;
 pub mod sysctl;
+pub mod time_ns;
 pub mod tlb;
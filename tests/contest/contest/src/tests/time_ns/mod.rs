@@ -0,0 +1,2 @@
+mod time_ns_test;
+pub use time_ns_test::get_time_ns_test;
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use oci_spec::runtime::{LinuxNamespaceBuilder, LinuxNamespaceType, LinuxTimeOffsetBuilder, Spec};
+use tempfile::TempDir;
+use test_framework::{Test, TestGroup, TestResult};
+
+use crate::utils::test_utils::CreateOptions;
+use crate::utils::{
+    create_container, delete_container, generate_uuid, get_state, kill_container, prepare_bundle,
+    set_config, State,
+};
+
+// non-zero monotonic and boottime offsets in seconds of the created time
+// namespace
+const OFFSETS: (i64, i64) = (1000, 2000);
+
+// get spec with the default namespaces plus a time namespace with the given
+// monotonic and boottime offsets in seconds, which is joined by path if one
+// is given
+fn get_spec(time_ns_path: Option<PathBuf>, offsets: (i64, i64)) -> Result<Spec> {
+    let mut spec = Spec::default();
+    let mut linux = spec.linux().clone().context("default spec has no linux")?;
+    let mut namespaces = linux.namespaces().clone().unwrap_or_default();
+    let mut time_ns = LinuxNamespaceBuilder::default().typ(LinuxNamespaceType::Time);
+    if let Some(path) = time_ns_path {
+        time_ns = time_ns.path(path);
+    }
+    namespaces.push(time_ns.build()?);
+    linux.set_namespaces(Some(namespaces));
+    let (monotonic, boottime) = offsets;
+    linux.set_time_offsets(Some(HashMap::from([
+        (
+            "monotonic".to_owned(),
+            LinuxTimeOffsetBuilder::default().secs(monotonic).build()?,
+        ),
+        (
+            "boottime".to_owned(),
+            LinuxTimeOffsetBuilder::default().secs(boottime).build()?,
+        ),
+    ])));
+    spec.set_linux(Some(linux));
+    Ok(spec)
+}
+
+fn create(id: &str, spec: &Spec) -> Result<(TempDir, i32)> {
+    let bundle = prepare_bundle()?;
+    set_config(&bundle, spec)?;
+    let output = create_container(id, &bundle, &CreateOptions::default())?.wait_with_output()?;
+    if !output.status.success() {
+        // The runtime may have left a container behind.
+        let _ = delete_container(id, &bundle).map(|mut child| child.wait());
+        bail!(
+            "failed to create container: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let (out, err) = get_state(id, &bundle)?;
+    if !err.is_empty() {
+        return Err(anyhow!("error in state : {}", err));
+    }
+    let state: State = serde_json::from_str(&out)?;
+    let pid = state.pid.context("container has no pid")?;
+    Ok((bundle, pid))
+}
+
+fn cleanup(id: &str, bundle: &TempDir) {
+    kill_container(id, bundle).unwrap().wait().unwrap();
+    delete_container(id, bundle).unwrap().wait().unwrap();
+}
+
+// Until the workload is executed, a newly created time namespace is only
+// visible as time_for_children of the init process.
+fn time_ns_of(pid: i32) -> Result<(PathBuf, String)> {
+    let ns = fs::read_link(format!("/proc/{pid}/ns/time_for_children"))?;
+    let offsets = fs::read_to_string(format!("/proc/{pid}/timens_offsets"))?;
+    Ok((ns, offsets))
+}
+
+// offsets in seconds of a clock, as listed in timens_offsets
+fn offset_of(offsets: &str, clock: &str) -> Option<i64> {
+    offsets.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        if fields.next()? != clock {
+            return None;
+        }
+        fields.next()?.parse().ok()
+    })
+}
+
+fn check_shared_time_ns(first_pid: i32) -> Result<()> {
+    let (_, offsets) = time_ns_of(first_pid)?;
+    if offset_of(&offsets, "monotonic") != Some(OFFSETS.0)
+        || offset_of(&offsets, "boottime") != Some(OFFSETS.1)
+    {
+        bail!("offsets {OFFSETS:?} were not applied to the time namespace: {offsets}");
+    }
+    let path = PathBuf::from(format!("/proc/{first_pid}/ns/time_for_children"));
+
+    // A joined time namespace keeps the offsets it was created with, so
+    // joining it with others fails.
+    let mismatch_id = generate_uuid().to_string();
+    let spec = get_spec(Some(path.clone()), (OFFSETS.0 + 1, OFFSETS.1))?;
+    match create(&mismatch_id, &spec) {
+        Ok((bundle, _)) => {
+            cleanup(&mismatch_id, &bundle);
+            bail!("joining the time namespace with other offsets succeeded");
+        }
+        Err(err) if err.to_string().contains("offsets") => {}
+        Err(err) => bail!("joining the time namespace with other offsets failed with: {err:?}"),
+    }
+
+    // Joining it with the same offsets succeeds.
+    let second_id = generate_uuid().to_string();
+    let spec = get_spec(Some(path), OFFSETS)?;
+    let (second_bundle, second_pid) = create(&second_id, &spec)?;
+    let result = match (time_ns_of(first_pid), time_ns_of(second_pid)) {
+        (Ok(first), Ok(second)) if first == second => Ok(()),
+        (Ok(first), Ok(second)) => Err(anyhow!(
+            "containers do not share the time namespace: {:?} != {:?}",
+            first,
+            second
+        )),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+    cleanup(&second_id, &second_bundle);
+    result
+}
+
+fn test_shared_time_ns() -> TestResult {
+    let first_id = generate_uuid().to_string();
+    let (first_bundle, first_pid) =
+        match get_spec(None, OFFSETS).and_then(|spec| create(&first_id, &spec)) {
+            Ok(v) => v,
+            Err(e) => return TestResult::Failed(e),
+        };
+
+    let result = check_shared_time_ns(first_pid);
+
+    cleanup(&first_id, &first_bundle);
+    match result {
+        Ok(()) => TestResult::Passed,
+        Err(e) => TestResult::Failed(e),
+    }
+}
+
+pub fn get_time_ns_test() -> TestGroup {
+    let shared_time_ns = Test::new("shared_time_ns", Box::new(test_shared_time_ns));
+    let mut tg = TestGroup::new("time_ns");
+    tg.add(vec![Box::new(shared_time_ns)]);
+    tg
+}